@@ -5,7 +5,7 @@ use crate::{
         connectors::{
             Connectors, HttpPeerConfig, RouteMatcher, UpstreamConfig, UpstreamContextConfig,
         },
-        listeners::{ListenerConfig, ListenerKind, Listeners},
+        listeners::{H2Limits, ListenerConfig, ListenerKind, Listeners},
         simple_response_type::SimpleResponseConfig,
     },
     internal::ProxyConfig,
@@ -72,7 +72,13 @@ impl CliConfigBuilder {
                 addr: format!("0.0.0.0:{}", port),
                 tls: None,
                 offer_h2: false,
+                h2c: false,
             },
+            unmatched_host: None,
+            redirect_to_https: false,
+            max_request_body_bytes: None,
+            h2_limits: H2Limits::default(),
+            tls_fingerprint: false,
         };
 
         let mut upstreams = Vec::new();
@@ -108,10 +114,15 @@ impl CliConfigBuilder {
                         peer_address: socket_addr,
                         alpn: ALPN::H1,
                         sni: String::new(),
+                        host_sni: None,
                         tls: false,
                         prefix_path,
                         target_path: uri.path().parse().into_diagnostic()?,
                         matcher: route.route_match.match_type,
+                        allow_websocket: true,
+                        grpc: false,
+                        pool_min_idle: 0,
+                        pool_warm_path: None,
                     })
                 }
             };
@@ -120,6 +131,17 @@ impl CliConfigBuilder {
                 upstream,
                 chains: vec![],
                 lb_options: None,
+                shaping: None,
+                timeouts: None,
+                retry: None,
+                compression: None,
+                max_request_body_bytes: None,
+                buffering: None,
+                record_har: None,
+                cache: None,
+                methods: None,
+                flag: None,
+                active: None,
             });
         }
 
@@ -128,10 +150,15 @@ impl CliConfigBuilder {
             listeners: Listeners {
                 list_cfgs: vec![listener],
             },
+            hosts: Vec::new(),
             connectors: Connectors {
                 upstreams,
                 anonymous_definitions: Default::default(),
             },
+            warm_up: None,
+            error_pages: None,
+            labels: Default::default(),
+            maintenance: Default::default(),
         };
 
         Ok(Config {
@@ -143,6 +170,7 @@ impl CliConfigBuilder {
             upgrade: false,
             basic_proxies: vec![proxy_config],
             file_servers: vec![],
+            ..Config::default()
         })
     }
 
@@ -1,6 +1,6 @@
 //! Configuration sourced from the CLI
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -33,6 +33,10 @@ pub struct Cli {
     #[arg(long)]
     pub pidfile: Option<PathBuf>,
 
+    /// Start the services that initialize, logging and skipping the others
+    #[arg(long)]
+    pub allow_partial_start: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -57,6 +61,86 @@ pub enum Commands {
         #[arg(short, long)]
         map: Vec<String>,
     },
+
+    /// Inspect the filters built into the binary
+    Filters {
+        #[command(subcommand)]
+        command: FiltersCommand,
+    },
+
+    /// Drive synthetic traffic through every proxy service of the
+    /// configuration, against a built-in echo upstream
+    #[command(hide = true)]
+    SelfTest {
+        #[arg(long, value_enum, default_value_t = TrafficPattern::Ramp)]
+        pattern: TrafficPattern,
+
+        /// Length of the run for each service, in seconds
+        #[arg(long, default_value_t = 30)]
+        duration_secs: u64,
+
+        /// Highest number of concurrent clients
+        #[arg(long, default_value_t = 64)]
+        concurrency: usize,
+
+        /// Path requested on every service
+        #[arg(long, default_value = "/")]
+        path: String,
+    },
+
+    /// Send the requests of HAR recordings to another server, e.g. a staging
+    /// deployment, and report how it answered
+    Replay {
+        /// HAR file, or directory of them, as written by `record-har`
+        #[arg(long, required = true)]
+        har: Vec<PathBuf>,
+
+        /// Base URL the requests are sent to, e.g. "http://staging:8080"
+        #[arg(long)]
+        target: String,
+
+        /// Send up to this many requests per second instead of keeping the
+        /// recorded timing
+        #[arg(long)]
+        max_rate: Option<f64>,
+    },
+
+    /// Load the configuration with its includes, report any error and exit
+    Validate,
+
+    /// Print the configuration with its includes merged into one document
+    PrintConfig {
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Kdl)]
+        format: ConfigFormat,
+    },
+
+    /// Print a JSON description of the configuration nodes, for editors and validators
+    Schema,
+}
+
+/// How `print-config` prints the configuration.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Kdl,
+    /// Every node as an object with its name, arguments, properties and children
+    Json,
+}
+
+/// How the number of clients of `self-test` changes over the run.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficPattern {
+    /// Clients are added steadily up to `concurrency`
+    Ramp,
+    /// A tenth of the clients, and all of them during the middle third
+    Spike,
+    /// `concurrency` clients for the whole run
+    Soak,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FiltersCommand {
+    /// Print every builtin filter with its phase and arguments
+    List,
 }
 
 pub const BANNER: &str = r#"
@@ -0,0 +1,139 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The `active-between` of a route or filter, active from `start` up to but
+/// not including `end`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveWindow {
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl ActiveWindow {
+    pub fn contains(&self, at: SystemTime) -> bool {
+        self.start <= at && at < self.end
+    }
+}
+
+/// Parses an RFC 3339 timestamp with an optional seconds part, e.g.
+/// `2024-12-01T00:00Z` or `2024-12-01T09:30:00+01:00`.
+pub fn parse_timestamp(value: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("'{value}' is not a timestamp like '2024-12-01T00:00Z'");
+
+    let (date, time) = value.split_once(['T', 't']).ok_or_else(invalid)?;
+
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let at = time.rfind(['+', '-']).ok_or_else(invalid)?;
+        let (time, offset) = time.split_at(at);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let [hours, minutes] = fields(&offset[1..]).ok_or_else(invalid)?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        (time, sign * (hours * 3600 + minutes * 60))
+    };
+
+    let [year, month, day] = fields(date).ok_or_else(invalid)?;
+    if date.len() != 10 || !(1..=12).contains(&month) || day < 1 || day > days_in(year, month) {
+        return Err(invalid());
+    }
+
+    let [hour, minute, second] = match fields::<2>(time) {
+        Some([hour, minute]) => [hour, minute, 0],
+        None => fields::<3>(time).ok_or_else(invalid)?,
+    };
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(invalid());
+    }
+
+    let secs =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+
+    u64::try_from(secs)
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .map_err(|_| format!("'{value}' is before 1970"))
+}
+
+/// `N` two or more digit numbers separated by `-` or `:`.
+fn fields<const N: usize>(value: &str) -> Option<[i64; N]> {
+    let mut parts = value.split(['-', ':']);
+    let mut fields = [0; N];
+
+    for field in &mut fields {
+        let part = parts.next()?;
+        if part.len() < 2 || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        *field = part.parse().ok()?;
+    }
+
+    parts.next().is_none().then_some(fields)
+}
+
+fn days_in(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix(value: &str) -> u64 {
+        parse_timestamp(value)
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(unix("1970-01-01T00:00Z"), 0);
+        assert_eq!(unix("2024-12-01T00:00Z"), 1733011200);
+        assert_eq!(unix("2024-02-29T12:34:56Z"), 1709210096);
+        assert_eq!(unix("2024-12-01T01:00+01:00"), 1733011200);
+        assert_eq!(unix("2024-11-30T19:00:00-05:00"), 1733011200);
+
+        for invalid in [
+            "2024-12-01",
+            "2024-12-01T00:00",
+            "2023-02-29T00:00Z",
+            "2024-13-01T00:00Z",
+            "2024-12-01T24:00Z",
+            "2024-12-1T00:00Z",
+            "1969-12-31T23:59Z",
+        ] {
+            assert!(parse_timestamp(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_contains() {
+        let window = ActiveWindow {
+            start: parse_timestamp("2024-12-01T00:00Z").unwrap(),
+            end: parse_timestamp("2024-12-26T00:00Z").unwrap(),
+        };
+
+        assert!(window.contains(window.start));
+        assert!(window.contains(parse_timestamp("2024-12-24T18:00Z").unwrap()));
+        assert!(!window.contains(window.end));
+        assert!(!window.contains(parse_timestamp("2024-11-30T23:59Z").unwrap()));
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use http::Uri;
+
+/// Webhooks notified when one of the alert rules starts or stops firing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertsConfig {
+    pub webhooks: Vec<WebhookConfig>,
+    pub rules: Vec<AlertRule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookConfig {
+    pub url: Uri,
+    pub format: WebhookFormat,
+}
+
+/// Shape of the JSON body posted to a webhook.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookFormat {
+    /// `{"alert": .., "status": .., "summary": ..}`
+    Generic,
+    /// Slack incoming webhook, `{"text": ..}`.
+    Slack,
+    /// PagerDuty Events API v2.
+    PagerDuty { routing_key: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertRule {
+    /// Fires while less than `percent` of a service's backends are healthy.
+    PoolHealthyBelow { percent: u8 },
+    /// Fires while a certificate expires within `within`.
+    CertExpiring { within: Duration },
+    /// Fires when more than `percent` of the responses in a `window` are 5xx,
+    /// once at least `min_requests` were served in it.
+    ErrorRateAbove {
+        percent: u8,
+        window: Duration,
+        min_requests: u64,
+    },
+}
+
+impl AlertRule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertRule::PoolHealthyBelow { .. } => "pool-healthy-below",
+            AlertRule::CertExpiring { .. } => "cert-expiring",
+            AlertRule::ErrorRateAbove { .. } => "error-rate-above",
+        }
+    }
+}
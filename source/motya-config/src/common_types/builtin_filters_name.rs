@@ -2,8 +2,19 @@
 macro_rules! define_builtin_filters {
     ($callback:ident) => {
         $callback! {
+            pre_routing: {
+                "motya.pre-routing.rewrite-path" => RewritePathRegex,
+            }
+
             actions: {
                 "motya.filters.block-cidr-range" => CidrRangeFilter,
+                "motya.filters.block-tls-fingerprint" => BlockTlsFingerprint,
+                "motya.filters.ext-auth" => ExtAuth,
+                "motya.filters.redirect" => Redirect,
+                "motya.filters.require-flag" => RequireFlag,
+                "motya.filters.require-header" => RequireHeader,
+                "motya.filters.deny-header" => DenyHeader,
+                "motya.filters.geoip" => GeoIpFilter,
             }
 
             requests: {
@@ -11,11 +22,38 @@ macro_rules! define_builtin_filters {
                 "motya.request.remove-header" => RequestRemoveHeaderKeyRegex,
                 "motya.request.strip-prefix" => StripPrefix,
                 "motya.request.rewrite-path" => RewritePathRegex,
+                "motya.request.forwarded-for" => ForwardedFor,
+                "motya.request.geoip" => GeoIpHeaders,
+            }
+
+            request_bodies: {
+                "motya.request-body.gunzip" => GunzipBody,
+                "motya.request-body.strip-json-fields" => StripJsonFields,
             }
 
             responses: {
                 "motya.response.upsert-header" => ResponseUpsertHeader,
                 "motya.response.remove-header" => ResponseRemoveHeaderKeyRegex,
+                "motya.response.config-fingerprint" => ConfigFingerprint,
+            }
+
+            response_bodies: {
+                "motya.response-body.gzip" => GzipBody,
+                "motya.response-body.replace" => ReplaceBody,
+                "motya.response-body.regex-replace" => RegexReplaceBody,
+                "motya.response-body.map-status" => MapStatus,
+                "motya.response-body.validate" => ValidateResponse,
+            }
+
+            upgrades: {
+                "motya.upgrade.check-origin" => CheckOrigin,
+            }
+
+            post_select: {
+                "motya.post-select.backend-header" => BackendHeader,
+                "motya.post-select.sni-from-header" => SniFromHeader,
+                "motya.post-select.alpn" => Alpn,
+                "motya.post-select.address-from-header" => AddressFromHeader,
             }
         }
     };
@@ -41,6 +79,17 @@ macro_rules! impl_definitions_table {
 
             definitions
         }
+
+        /// Whether `name` is a builtin filter of the `pre-routing` phase.
+        pub fn is_pre_routing(name: &fqdn::FQDN) -> bool {
+            $($(
+                if stringify!($section) == "pre_routing" && *name == fqdn::fqdn!($key) {
+                    return true;
+                }
+            )*)*
+
+            false
+        }
     };
 }
 
@@ -0,0 +1,44 @@
+use std::{path::PathBuf, time::Duration};
+
+use http::HeaderName;
+
+/// `cache` of a route: `GET` responses kept and served to later requests
+/// without asking the upstream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheConfig {
+    pub storage: CacheStorage,
+    /// How long responses without `s-maxage` or `max-age` are kept.
+    pub ttl: Duration,
+    /// How the request headers named by `Vary` split the cached responses.
+    pub vary: VaryConfig,
+}
+
+/// Where a route's cached responses are held.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheStorage {
+    /// In memory, up to `max_bytes` of bodies.
+    Memory { max_bytes: usize },
+    /// In files under `path`, up to `max_bytes` of them, kept across restarts.
+    Disk { path: PathBuf, max_bytes: u64 },
+}
+
+/// Normalization rules for the request headers named by a cached response's `Vary`.
+///
+/// Without rules every distinct header value produces its own cache variant,
+/// which quickly fragments the cache for headers like `User-Agent`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VaryConfig {
+    /// Headers that never split the cache, even when listed in `Vary`.
+    pub ignore: Vec<HeaderName>,
+    pub buckets: Vec<VaryBucket>,
+}
+
+/// Collapses the values of `header` into a fixed set of `values`.
+///
+/// The first value (in configuration order) found among the request's
+/// comma-separated tokens wins; the last value is the fallback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VaryBucket {
+    pub header: HeaderName,
+    pub values: Vec<String>,
+}
@@ -1,11 +1,16 @@
 use std::fmt::Debug;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use http::uri::PathAndQuery;
+use cidr::IpCidr;
+use http::{uri::PathAndQuery, Method};
+use regex::Regex;
 
 use crate::common_types::{
-    definitions::Modificator, definitions_table::DefinitionsTable,
-    simple_response_type::SimpleResponseConfig,
+    active_window::ActiveWindow, cache::CacheConfig, definitions::Modificator,
+    definitions_table::DefinitionsTable, simple_response_type::SimpleResponseConfig,
 };
 use crate::internal::UpstreamOptions;
 
@@ -16,11 +21,45 @@ pub enum ALPN {
     H2H1,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum RouteMatcher {
     #[default]
     Exact,
     Prefix,
+    /// Matches the whole request path, checked after exact routes and before
+    /// prefix routes, in the order the sections are declared.
+    Regex(RoutePattern),
+}
+
+/// Compiled pattern of a regex `section`, compared by its source.
+#[derive(Debug, Clone)]
+pub struct RoutePattern(Arc<Regex>);
+
+impl RoutePattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(|regex| Self(Arc::new(regex)))
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.0
+    }
+}
+
+impl PartialEq for RoutePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for RoutePattern {}
+
+/// A `tls-sni` holding `{host}`, the SNI follows the host of each request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostSniConfig {
+    /// The `tls-sni` value, every `{host}` is replaced by the request host.
+    pub template: String,
+    /// Hosts the SNI may be derived from, `*.example.com` matches one label.
+    pub allow: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -29,9 +68,18 @@ pub struct HttpPeerConfig {
     pub alpn: ALPN,
     pub tls: bool,
     pub sni: String,
+    pub host_sni: Option<HostSniConfig>,
     pub prefix_path: PathAndQuery,
     pub target_path: PathAndQuery,
     pub matcher: RouteMatcher,
+    /// Proxies `Upgrade: websocket` requests, otherwise they are refused.
+    pub allow_websocket: bool,
+    /// Set by `proto="grpc"`, the route speaks gRPC over HTTP/2.
+    pub grpc: bool,
+    /// Idle connections kept open to the backend ahead of traffic.
+    pub pool_min_idle: usize,
+    /// Path of the `HEAD` requests opening them, set with `pool_min_idle`.
+    pub pool_warm_path: Option<PathAndQuery>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -46,16 +94,78 @@ pub enum UpstreamConfig {
 pub struct UpstreamServer {
     pub address: std::net::SocketAddr,
     pub weight: usize,
+    /// Explicit number of Ketama ring points, overrides the one derived from `weight`.
+    pub vnodes: Option<usize>,
+}
+
+/// Read-replica splitting: writes go to the primary servers, and so do the
+/// client's reads for `ttl` afterwards, so it can read its own writes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadYourWritesConfig {
+    pub primary: Vec<SocketAddr>,
+    pub ttl: Duration,
+    /// Cookie set on write responses, pins the client while it is present.
+    pub cookie: String,
+    /// Request header that asks for the primary explicitly.
+    pub header: Option<String>,
+}
+
+/// Sends `GET` and `HEAD` to the replicas (the `server`s) and every other
+/// method to the primary servers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodSplitConfig {
+    pub primary: Vec<SocketAddr>,
+}
+
+/// Shares the requests of a route between groups of servers by percentage,
+/// for canary and blue/green rollouts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrafficSplitConfig {
+    /// Weights add up to 100.
+    pub groups: Vec<SplitGroupConfig>,
+    /// Keeps each client on one group, by a hash of its address.
+    pub sticky: bool,
+    /// Request header naming the group to use, regardless of the weights.
+    pub override_header: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitGroupConfig {
+    pub name: String,
+    /// Percentage of the requests, zero drains the group.
+    pub weight: usize,
+    pub servers: Vec<SocketAddr>,
+}
+
+/// Lets trusted clients pick the backend of a request, for debugging a
+/// specific instance behind the balancer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendOverrideConfig {
+    /// Request header holding the address of the backend.
+    pub header: String,
+    /// Client ranges allowed to use the header, it is ignored for others.
+    pub trusted: Vec<IpCidr>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct MultiServerUpstreamConfig {
     pub servers: Vec<UpstreamServer>,
+    pub read_your_writes: Option<ReadYourWritesConfig>,
+    pub split_by_method: Option<MethodSplitConfig>,
+    pub split: Option<TrafficSplitConfig>,
+    pub backend_override: Option<BackendOverrideConfig>,
     pub tls_sni: Option<String>,
+    pub host_sni: Option<HostSniConfig>,
     pub alpn: ALPN,
     pub prefix_path: PathAndQuery,
     pub target_path: PathAndQuery,
     pub matcher: RouteMatcher,
+    pub allow_websocket: bool,
+    pub grpc: bool,
+    /// Idle connections kept open to each backend ahead of traffic.
+    pub pool_min_idle: usize,
+    /// Path of the `HEAD` requests opening them, set with `pool_min_idle`.
+    pub pool_warm_path: Option<PathAndQuery>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -63,6 +173,18 @@ pub enum ConnectorsLeaf {
     Upstream(UpstreamConfig),
     Modificator(Modificator),
     LoadBalance(UpstreamOptions),
+    Shaping(ShapingConfig),
+    Timeouts(TimeoutsConfig),
+    Retry(RetryConfig),
+    Compression(CompressionConfig),
+    MaxRequestBodyBytes(usize),
+    /// `None` for `buffering #false`, turning off an inherited one.
+    Buffering(Option<BufferingConfig>),
+    RecordHar(HarRecordingConfig),
+    Cache(CacheConfig),
+    Methods(Vec<Method>),
+    Flag(FlagCondition),
+    ActiveBetween(ActiveWindow),
     Section(Vec<ConnectorsLeaf>),
 }
 
@@ -77,4 +199,130 @@ pub struct UpstreamContextConfig {
     pub upstream: UpstreamConfig,
     pub chains: Vec<Modificator>,
     pub lb_options: Option<UpstreamOptions>,
+    pub shaping: Option<ShapingConfig>,
+    pub timeouts: Option<TimeoutsConfig>,
+    pub retry: Option<RetryConfig>,
+    pub compression: Option<CompressionConfig>,
+    /// Largest request body the route accepts, the listener's limit when `None`.
+    pub max_request_body_bytes: Option<usize>,
+    /// Upstream responses are streamed through as they arrive when `None`.
+    pub buffering: Option<BufferingConfig>,
+    /// Sampled requests of the route are written to HAR files when set.
+    pub record_har: Option<HarRecordingConfig>,
+    /// `GET` responses of the route are cached when set.
+    pub cache: Option<CacheConfig>,
+    /// Methods the route accepts, any when `None`.
+    pub methods: Option<Vec<Method>>,
+    /// Feature flag the route is gated on, always on when `None`.
+    pub flag: Option<FlagCondition>,
+    /// Time window the route is active in, always when `None`.
+    pub active: Option<ActiveWindow>,
+}
+
+/// A route only matches while the flag `name` is set to `enabled`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlagCondition {
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// Retries of failed upstream attempts on a route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// Tries in total, the first one included.
+    pub attempts: usize,
+    /// Pause before the first retry, doubled for every further one.
+    pub backoff: Duration,
+    /// Retry when the upstream answers with a `5xx`.
+    pub on_server_error: bool,
+    /// Retry when no connection to the upstream could be made.
+    pub on_connect_error: bool,
+    pub methods: Vec<Method>,
+    /// Retries allowed on top of the route's requests, in percent.
+    pub budget_percent: usize,
+}
+
+/// Per-route limits on talking to the upstream, `None` keeps the default.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TimeoutsConfig {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+    pub write: Option<Duration>,
+    /// Budget for the whole request, from its arrival to the last response byte.
+    pub total: Option<Duration>,
+    /// Body of the `504` sent when a timeout is hit.
+    pub body: String,
+}
+
+/// Artificial network conditions for a route, meant for staging environments.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ShapingConfig {
+    /// Downstream response throughput cap.
+    pub bytes_per_sec: Option<u64>,
+    /// Fixed delay added before a request is handled.
+    pub latency: Duration,
+    /// Upper bound of a random delay added on top of `latency`.
+    pub jitter: Duration,
+}
+
+/// On-the-fly compression of the upstream responses of a route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionConfig {
+    /// Encodings in order of preference, the first one the client accepts is used.
+    pub algorithms: Vec<CompressionAlgorithm>,
+    /// Responses with a smaller `Content-Length` are sent as they are.
+    pub min_size: usize,
+    /// Lowercased media types worth compressing, `type/*` matches a whole type.
+    pub content_types: Vec<String>,
+    /// zstd dictionary of the state directory, offered ahead of `algorithms`
+    /// as the `zstd-dict-NAME` encoding.
+    pub dictionary: Option<String>,
+}
+
+/// Upstream responses of a route held back before they are sent downstream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferingConfig {
+    /// Bytes of the body held at most, the high watermark.
+    pub max_bytes: usize,
+    /// Bytes kept held once `max_bytes` is reached, the rest streams through
+    /// when `None`.
+    pub low_watermark: Option<usize>,
+    /// Receive buffer of upstream sockets, the system default when `None`.
+    pub upstream_recv_bytes: Option<usize>,
+}
+
+/// Requests of a route written with their responses to HAR files, to replay
+/// the traffic elsewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HarRecordingConfig {
+    /// Directory the files are written to, one per request.
+    pub dir: PathBuf,
+    /// One request in this many is recorded, picked at random.
+    pub one_in: usize,
+    /// Bytes kept of each body, the rest is left out.
+    pub max_body_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    /// The `Content-Encoding` token of the algorithm.
+    pub fn token(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_token(token: &str) -> Option<Self> {
+        [Self::Gzip, Self::Brotli, Self::Zstd]
+            .into_iter()
+            .find(|algorithm| algorithm.token() == token)
+    }
 }
@@ -1,5 +1,7 @@
 use fqdn::FQDN;
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use crate::common_types::active_window::ActiveWindow;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FilterChain {
@@ -10,12 +12,70 @@ pub struct FilterChain {
 pub struct ConfiguredFilter {
     pub name: FQDN,
     pub args: HashMap<String, String>,
+    /// Time window the filter runs in, always when `None`.
+    pub active: Option<ActiveWindow>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct PluginDefinition {
     pub name: FQDN,
     pub source: PluginSource,
+    pub capabilities: WasiCapabilities,
+    pub limits: PluginLimits,
+}
+
+/// Bounds of every call into a plugin, none unless set.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PluginLimits {
+    /// Linear memory of an instance, in bytes.
+    pub max_memory: Option<usize>,
+    /// Wall time of a single call.
+    pub max_time: Option<Duration>,
+    pub on_failure: FailurePolicy,
+}
+
+/// What happens to a request when a plugin call traps, e.g. by exceeding its
+/// limits.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FailurePolicy {
+    /// The request goes on as if the filter passed it.
+    Open,
+    /// The request fails.
+    #[default]
+    Closed,
+}
+
+/// IO a plugin may perform, none unless granted.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WasiCapabilities {
+    pub dirs: Vec<PreopenedDir>,
+    pub env: Vec<(String, String)>,
+    /// `host:port` pairs the plugin may open sockets to.
+    pub allowed_hosts: Vec<String>,
+    /// Most bytes of the request body the plugin may read, it sees no body
+    /// when `None`.
+    pub request_body: Option<usize>,
+    /// Outbound HTTP calls to `allowed_hosts`, none when `None`.
+    pub http_client: Option<HttpClientLimits>,
+}
+
+/// Bounds of the HTTP calls a plugin makes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpClientLimits {
+    /// Of a whole call, waiting for a free slot included.
+    pub timeout: Duration,
+    /// Calls in flight at once across all requests.
+    pub max_concurrent: usize,
+    pub max_response_bytes: usize,
+}
+
+/// A host directory made visible to a plugin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreopenedDir {
+    pub host: PathBuf,
+    /// Path the plugin sees the directory at.
+    pub guest: String,
+    pub read_only: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -24,6 +84,16 @@ pub enum PluginSource {
     Url(String),
 }
 
+/// MaxMind databases the geoip filters look client addresses up in, reopened
+/// when the files change.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GeoIpDatabases {
+    /// A GeoIP2 or GeoLite2 Country (or City) database.
+    pub country: Option<PathBuf>,
+    /// A GeoLite2 ASN database.
+    pub asn: Option<PathBuf>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct KeyTemplateConfig {
     pub source: String,
@@ -4,7 +4,8 @@ use fqdn::FQDN;
 
 use crate::common_types::{
     builtin_filters_name::load_definitions_table,
-    definitions::{FilterChain, KeyTemplateConfig, PluginDefinition},
+    definitions::{FilterChain, GeoIpDatabases, KeyTemplateConfig, PluginDefinition},
+    filter_schema::FilterArg,
 };
 
 /// Definitions Table (Intermediate Representation).
@@ -60,6 +61,15 @@ pub struct DefinitionsTable {
     /// Anonymous profiles are automatically generated for inline key specifications
     /// in connectors and stored with auto-generated names like `__anon_key_0`.
     key_templates: HashMap<String, KeyTemplateConfig>,
+
+    /// Arguments accepted by the builtin filters, registered with the filter registry.
+    ///
+    /// Chains are checked against these while parsing, filters without a schema
+    /// (plugins, `def`) are checked when they are built.
+    filter_schemas: HashMap<FQDN, &'static [FilterArg]>,
+
+    /// Databases of the geoip filters, defined once across all files.
+    geoip: Option<GeoIpDatabases>,
 }
 
 impl DefinitionsTable {
@@ -74,6 +84,8 @@ impl DefinitionsTable {
             chains,
             plugins,
             key_templates: key_profiles,
+            filter_schemas: HashMap::new(),
+            geoip: None,
         }
     }
 
@@ -97,6 +109,14 @@ impl DefinitionsTable {
         self.available_filters.insert(filter_name)
     }
 
+    pub fn insert_filter_schema(&mut self, filter_name: FQDN, args: &'static [FilterArg]) {
+        self.filter_schemas.insert(filter_name, args);
+    }
+
+    pub fn get_filter_schema(&self, filter_name: &FQDN) -> Option<&'static [FilterArg]> {
+        self.filter_schemas.get(filter_name).copied()
+    }
+
     pub fn insert_plugin(
         &mut self,
         name: FQDN,
@@ -129,12 +149,20 @@ impl DefinitionsTable {
     pub fn get_key_templates(&self) -> &HashMap<String, KeyTemplateConfig> {
         &self.key_templates
     }
+    pub fn set_geoip(&mut self, geoip: GeoIpDatabases) {
+        self.geoip = Some(geoip);
+    }
+    pub fn get_geoip(&self) -> Option<&GeoIpDatabases> {
+        self.geoip.as_ref()
+    }
 
     pub fn merge(&mut self, other: DefinitionsTable) -> miette::Result<()> {
         for filter in other.available_filters {
             self.available_filters.insert(filter);
         }
 
+        self.filter_schemas.extend(other.filter_schemas);
+
         for (name, chain) in other.chains {
             if self.chains.contains_key(&name) {
                 return Err(miette::miette!(
@@ -155,6 +183,13 @@ impl DefinitionsTable {
             self.plugins.insert(name, plugin);
         }
 
+        if let Some(geoip) = other.geoip {
+            if self.geoip.is_some() {
+                return Err(miette::miette!("Duplicate geoip definition across files"));
+            }
+            self.geoip = Some(geoip);
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,11 @@
+use std::path::PathBuf;
+
+/// Pages a proxy service answers its own errors with, picked by the
+/// client's `Accept-Language`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPagesConfig {
+    /// Holds `<code>.<lang>.html` pages and language-neutral `<code>.html` ones.
+    pub dir: PathBuf,
+    /// Language sent when none of the accepted ones has a page.
+    pub default_language: String,
+}
@@ -0,0 +1,64 @@
+use regex::Regex;
+
+/// What an argument value of a builtin filter must look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    String,
+    Integer,
+    Bool,
+    Regex,
+}
+
+impl ArgKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ArgKind::String => "string",
+            ArgKind::Integer => "integer",
+            ArgKind::Bool => "bool",
+            ArgKind::Regex => "regex",
+        }
+    }
+
+    /// Checks `value`, returning why it does not fit.
+    pub fn check(self, value: &str) -> Result<(), String> {
+        match self {
+            ArgKind::String => Ok(()),
+            ArgKind::Integer => value
+                .parse::<i64>()
+                .map(drop)
+                .map_err(|_| format!("expected an integer, found '{value}'")),
+            ArgKind::Bool => match value {
+                "true" | "false" => Ok(()),
+                _ => Err(format!("expected 'true' or 'false', found '{value}'")),
+            },
+            ArgKind::Regex => Regex::new(value)
+                .map(drop)
+                .map_err(|err| format!("invalid regex: {err}")),
+        }
+    }
+}
+
+/// An argument a builtin filter accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterArg {
+    pub name: &'static str,
+    pub required: bool,
+    pub kind: ArgKind,
+    pub description: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert!(ArgKind::String.check("anything").is_ok());
+        assert!(ArgKind::Integer.check("-42").is_ok());
+        assert!(ArgKind::Integer.check("4x").is_err());
+        assert!(ArgKind::Bool.check("true").is_ok());
+        assert!(ArgKind::Bool.check("yes").is_err());
+        assert!(ArgKind::Regex.check(".*(secret|SECRET).*").is_ok());
+        assert!(ArgKind::Regex.check("(unclosed").is_err());
+    }
+}
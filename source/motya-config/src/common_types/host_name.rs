@@ -0,0 +1,41 @@
+//! Host names as they are compared at runtime: ASCII, with internationalized
+//! labels in their punycode form.
+
+/// Lowercased ASCII form of `host` without a trailing dot, Unicode labels
+/// converted to punycode. `None` when it is not a host name.
+pub fn ascii_host(host: &str) -> Option<String> {
+    let host = idna::domain_to_ascii(host.trim().trim_end_matches('.')).ok()?;
+
+    let valid = host.split('.').all(|label| {
+        !label.is_empty()
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    });
+
+    valid.then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_host() {
+        assert_eq!(ascii_host("Example.COM.").as_deref(), Some("example.com"));
+        assert_eq!(
+            ascii_host("Bücher.example").as_deref(),
+            Some("xn--bcher-kva.example")
+        );
+        assert_eq!(
+            ascii_host("xn--bcher-kva.example").as_deref(),
+            Some("xn--bcher-kva.example")
+        );
+        assert_eq!(ascii_host("127.0.0.1").as_deref(), Some("127.0.0.1"));
+
+        assert_eq!(ascii_host(""), None);
+        assert_eq!(ascii_host("a..b"), None);
+        assert_eq!(ascii_host("under_score.example"), None);
+        assert_eq!(ascii_host("[::1]"), None);
+    }
+}
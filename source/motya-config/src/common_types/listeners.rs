@@ -1,9 +1,26 @@
 use std::path::PathBuf;
 
+/// Let's Encrypt production directory, used when `acme` sets no `directory`.
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
 #[derive(Debug, PartialEq, Clone)]
-pub struct TlsConfig {
-    pub cert_path: PathBuf,
-    pub key_path: PathBuf,
+pub enum TlsConfig {
+    Files {
+        cert_path: PathBuf,
+        key_path: PathBuf,
+    },
+    Acme(AcmeConfig),
+}
+
+/// Certificates obtained and renewed automatically over ACME (RFC 8555).
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct AcmeConfig {
+    /// The first domain names the certificate files, the rest become SANs.
+    pub domains: Vec<String>,
+    pub email: String,
+    /// Holds the account key and the issued certificates across restarts.
+    pub cache_dir: PathBuf,
+    pub directory: String,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -12,13 +29,70 @@ pub enum ListenerKind {
         addr: String,
         tls: Option<TlsConfig>,
         offer_h2: bool,
+        /// Accepts HTTP/2 with prior knowledge on a cleartext listener.
+        h2c: bool,
     },
     Uds(PathBuf),
 }
 
+impl ListenerKind {
+    /// The address or socket path, listeners with the same one are shared.
+    pub fn address(&self) -> String {
+        match self {
+            ListenerKind::Tcp { addr, .. } => addr.clone(),
+            ListenerKind::Uds(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// What a listener does with requests for a host no service on it claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmatchedHost {
+    /// The service without `hosts` serves them.
+    DefaultService,
+    /// Answered with `421 Misdirected Request`.
+    Misdirected,
+    /// The connection is closed without a response.
+    Close,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct ListenerConfig {
     pub source: ListenerKind,
+    /// `None` falls back to the service without `hosts` when one shares the
+    /// listener, and to `421` otherwise.
+    pub unmatched_host: Option<UnmatchedHost>,
+    /// Answers every request with a `301` to the same host and path over HTTPS.
+    pub redirect_to_https: bool,
+    /// Largest request body accepted, answered with `413` when exceeded.
+    pub max_request_body_bytes: Option<usize>,
+    pub h2_limits: H2Limits,
+    /// Computes the JA3 and JA4 fingerprints of TLS client hellos.
+    pub tls_fingerprint: bool,
+}
+
+/// Bounds HTTP/2 clients of a listener are held to, so abusive ones are
+/// turned away by the HTTP/2 layer before a request is handled.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct H2Limits {
+    /// Size of the header list of a request, counted as in RFC 9113 with 32
+    /// bytes per field. It also caps the CONTINUATION frames of a request.
+    pub max_header_list_bytes: u32,
+    /// Streams a client may have open at once.
+    pub max_concurrent_streams: u32,
+    /// Streams reset by the client before they were accepted, the pattern of
+    /// rapid reset floods.
+    pub max_pending_reset_streams: usize,
+}
+
+impl Default for H2Limits {
+    fn default() -> Self {
+        Self {
+            max_header_list_bytes: 64 * 1024,
+            max_concurrent_streams: 256,
+            max_pending_reset_streams: 20,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
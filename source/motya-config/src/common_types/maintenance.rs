@@ -0,0 +1,26 @@
+use std::{path::PathBuf, time::Duration};
+
+use http::StatusCode;
+
+/// What a proxy service answers in maintenance mode, instead of contacting
+/// its upstreams.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceConfig {
+    /// Whether the service starts in maintenance, the admin API toggles it.
+    pub enabled: bool,
+    pub status: StatusCode,
+    /// HTML page of the answer, a short plain text one when `None`.
+    pub page: Option<PathBuf>,
+    pub retry_after: Option<Duration>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            page: None,
+            retry_after: Some(Duration::from_secs(60)),
+        }
+    }
+}
@@ -1,13 +1,21 @@
+pub mod active_window;
+pub mod alerts;
 pub mod bad;
 pub mod builtin_filters_name;
+pub mod cache;
 pub mod connectors;
 pub mod definitions;
 pub mod definitions_table;
+pub mod error_pages;
 pub mod file_server;
+pub mod filter_schema;
+pub mod host_name;
 pub mod listeners;
+pub mod maintenance;
 pub mod rate_limiter;
 pub mod section_parser;
 pub mod service;
 pub mod services;
 pub mod simple_response_type;
 pub mod system_data;
+pub mod warm_up;
@@ -35,7 +35,12 @@ where
         Ok(ProxyConfig {
             name: self.name.to_string(),
             listeners,
+            hosts: Vec::new(),
             connectors,
+            warm_up: None,
+            error_pages: None,
+            labels: Default::default(),
+            maintenance: Default::default(),
         })
     }
 }
@@ -1,7 +1,11 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use cidr::IpCidr;
 
 use http::uri::PathAndQuery;
 
+use crate::common_types::alerts::AlertsConfig;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ConfigProvider {
     Files(FilesProviderConfig),
@@ -28,6 +32,94 @@ pub struct HttpProviderConfig {
     pub address: SocketAddr,
     pub path: PathAndQuery,
     pub persist: bool,
+    /// Bearer token pushes must carry, optional only on a loopback address.
+    pub token: Option<String>,
+}
+
+/// Admin endpoint streaming runtime events as Server-Sent Events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventsConfig {
+    pub address: SocketAddr,
+    pub path: PathAndQuery,
+}
+
+/// Admin API listener exposing runtime state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminConfig {
+    pub address: SocketAddr,
+}
+
+/// Signing keys for sticky-session cookies, kept in the state directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AffinityKeysConfig {
+    pub rotate: Duration,
+}
+
+impl Default for AffinityKeysConfig {
+    fn default() -> Self {
+        Self {
+            rotate: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// How client addresses are anonymized before they reach balancing keys and
+/// forwarded headers. Access controls still see the real address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientIpPrivacy {
+    /// Keeps the first bits of the address, zeroing the rest.
+    Truncate { ipv4_prefix: u8, ipv6_prefix: u8 },
+    /// Replaces the address with a keyed hash of it, stable for the life of
+    /// the process.
+    Hash,
+}
+
+/// Proxies in front of Motya, such as load balancers or CDNs, whose forwarded
+/// header tells the address of the client they connected for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrustedProxies {
+    pub ranges: Vec<IpCidr>,
+    /// Lowercased name of the header, a comma separated list of addresses
+    /// with the nearest hop last, like `X-Forwarded-For`.
+    pub header: String,
+}
+
+/// When the Wasm plugins are compiled to native code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasmCompilation {
+    /// At startup, a broken plugin fails the start.
+    Eager,
+    /// On the first request that runs the plugin.
+    Lazy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WasmConfig {
+    pub compilation: WasmCompilation,
+    /// Enables the wasmtime compilation cache.
+    pub cache: bool,
+    /// Directory keeping precompiled components across restarts.
+    pub precompiled_dir: Option<PathBuf>,
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        Self {
+            compilation: WasmCompilation::Eager,
+            cache: true,
+            precompiled_dir: None,
+        }
+    }
+}
+
+/// How long an old process keeps serving after being told to stop, `None`
+/// keeps the Pingora defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShutdownConfig {
+    /// Time the old process keeps accepting after handing its listeners over.
+    pub grace_period: Option<Duration>,
+    /// Time in-flight requests get to finish before the process exits.
+    pub timeout: Option<Duration>,
 }
 
 #[derive(Debug)]
@@ -37,6 +129,15 @@ pub struct SystemData {
     pub upgrade_socket: Option<PathBuf>,
     pub pid_file: Option<PathBuf>,
     pub provider: Option<ConfigProvider>,
+    pub events: Option<EventsConfig>,
+    pub admin: Option<AdminConfig>,
+    pub alerts: Option<AlertsConfig>,
+    pub state_dir: Option<PathBuf>,
+    pub affinity_keys: AffinityKeysConfig,
+    pub wasm: WasmConfig,
+    pub shutdown: ShutdownConfig,
+    pub client_ip_privacy: Option<ClientIpPrivacy>,
+    pub trusted_proxies: Option<TrustedProxies>,
 }
 
 impl Default for SystemData {
@@ -47,6 +148,15 @@ impl Default for SystemData {
             upgrade_socket: None,
             pid_file: None,
             provider: None,
+            events: None,
+            admin: None,
+            alerts: None,
+            state_dir: None,
+            affinity_keys: AffinityKeysConfig::default(),
+            wasm: WasmConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            client_ip_privacy: None,
+            trusted_proxies: None,
         }
     }
 }
@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use http::Method;
+
+/// Synthetic requests a proxy service sends through its own listener at
+/// startup, before it is reported ready.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarmUpConfig {
+    pub requests: Vec<WarmUpRequest>,
+    /// The service turns ready once this elapses, even if requests are pending.
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarmUpRequest {
+    pub method: Method,
+    /// Path and query, e.g. `/api/search?q=warm`.
+    pub path: String,
+    /// How many times the request is sent.
+    pub repeat: usize,
+}
@@ -5,4 +5,9 @@ use std::path::PathBuf;
 #[allow(async_fn_in_trait)]
 pub trait ConfigSource: Send + Sync + Default + Clone {
     async fn collect(&self, entry_path: PathBuf) -> Result<Vec<(KdlDocument, String)>>;
+
+    /// Paths of every file that makes up the configuration rooted at `entry_path`.
+    async fn sources(&self, entry_path: PathBuf) -> Result<Vec<PathBuf>> {
+        Ok(vec![entry_path])
+    }
 }
@@ -1,8 +1,20 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{collections::BTreeMap, path::PathBuf, str::FromStr, time::Duration};
+
+use http::StatusCode;
 
 use crate::common_types::{
-    connectors::Connectors, definitions::KeyTemplateConfig, file_server::FileServerConfig,
+    alerts::AlertsConfig,
+    connectors::Connectors,
+    definitions::KeyTemplateConfig,
+    error_pages::ErrorPagesConfig,
+    file_server::FileServerConfig,
     listeners::Listeners,
+    maintenance::MaintenanceConfig,
+    system_data::{
+        AdminConfig, AffinityKeysConfig, ClientIpPrivacy, ConfigProvider, EventsConfig,
+        ShutdownConfig, TrustedProxies, WasmConfig,
+    },
+    warm_up::WarmUpConfig,
 };
 
 use tracing::warn;
@@ -16,8 +28,26 @@ pub struct Config {
     pub pid_file: Option<PathBuf>,
     pub upgrade_socket: Option<PathBuf>,
     pub upgrade: bool,
+    /// Services and plugins that fail to initialize are skipped.
+    pub allow_partial_start: bool,
     pub basic_proxies: Vec<ProxyConfig>,
     pub file_servers: Vec<FileServerConfig>,
+    pub provider: Option<ConfigProvider>,
+    pub events: Option<EventsConfig>,
+    pub admin: Option<AdminConfig>,
+    pub alerts: Option<AlertsConfig>,
+    /// Directory for state that has to survive restarts.
+    pub state_dir: Option<PathBuf>,
+    pub affinity_keys: AffinityKeysConfig,
+    pub wasm: WasmConfig,
+    pub shutdown: ShutdownConfig,
+    pub client_ip_privacy: Option<ClientIpPrivacy>,
+    /// Clients are taken from the forwarded header of these proxies.
+    pub trusted_proxies: Option<TrustedProxies>,
+    /// Hash of the configuration documents, `None` when built from CLI flags.
+    pub fingerprint: Option<String>,
+    /// Initial state of the feature flags of the `flags` section.
+    pub flags: BTreeMap<String, bool>,
 }
 
 impl Config {
@@ -65,7 +95,15 @@ impl Config {
 pub struct ProxyConfig {
     pub name: String,
     pub listeners: Listeners,
+    /// Hosts the service answers on a shared listener, any host when empty.
+    pub hosts: Vec<String>,
     pub connectors: Connectors,
+    pub warm_up: Option<WarmUpConfig>,
+    pub error_pages: Option<ErrorPagesConfig>,
+    /// Metadata of the service, e.g. the team owning it, shown in the metrics
+    /// and the admin API.
+    pub labels: BTreeMap<String, String>,
+    pub maintenance: MaintenanceConfig,
     // pub rate_limiting: RateLimitingConfig,
 }
 
@@ -74,7 +112,40 @@ pub struct UpstreamOptions {
     pub selection: SelectionKind,
     pub template: Option<KeyTemplateConfig>,
     pub health_checks: HealthCheckKind,
-    pub discovery: DiscoveryKind,
+    pub discovery: DiscoveryConfig,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Set by `selection "CookieSticky"`, `selection` is then its fallback.
+    pub sticky: Option<StickyCookieConfig>,
+    pub when_down: Option<WhenDownConfig>,
+}
+
+/// Pins a client to the backend named in a signed affinity cookie.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StickyCookieConfig {
+    pub cookie: String,
+    pub ttl: Duration,
+}
+
+/// Stops sending traffic to a backend that keeps failing.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Trips after this many failures in a row.
+    pub consecutive_failures: usize,
+    /// Trips when this share of the requests in `window` failed, in percent.
+    pub error_rate: Option<usize>,
+    pub window: Duration,
+    /// Requests `window` needs before `error_rate` applies.
+    pub min_requests: usize,
+    /// Time an open breaker waits before letting a trial request through.
+    pub cool_down: Duration,
+}
+
+/// Response of a route whose backends are all unavailable.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WhenDownConfig {
+    pub status: StatusCode,
+    pub body: String,
+    pub retry_after: Option<Duration>,
 }
 
 impl Default for UpstreamOptions {
@@ -83,7 +154,10 @@ impl Default for UpstreamOptions {
             selection: SelectionKind::RoundRobin,
             template: None,
             health_checks: HealthCheckKind::None,
-            discovery: DiscoveryKind::Static,
+            discovery: DiscoveryConfig::default(),
+            circuit_breaker: None,
+            sticky: None,
+            when_down: None,
         }
     }
 }
@@ -113,11 +187,39 @@ impl FromStr for SelectionKind {
 #[derive(Debug, PartialEq, Clone)]
 pub enum HealthCheckKind {
     None,
+    /// The standard `grpc.health.v1.Health/Check`, a backend is evicted while
+    /// it does not answer `SERVING` for `service`, empty for the whole server.
+    Grpc {
+        service: String,
+        interval: Duration,
+    },
 }
 
+/// The `discovery` of a balanced route, dispatched by name to the discovery
+/// backends registered in the binary.
 #[derive(Debug, PartialEq, Clone)]
-pub enum DiscoveryKind {
-    Static,
+pub struct DiscoveryConfig {
+    pub name: String,
+    /// Properties of the `discovery` node, handed to the backend as they are.
+    pub settings: BTreeMap<String, String>,
+}
+
+impl DiscoveryConfig {
+    /// The builtin backend using the `server` entries of the route.
+    pub const STATIC: &'static str = "Static";
+
+    pub fn is_static(&self) -> bool {
+        self.name == Self::STATIC
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            name: Self::STATIC.to_string(),
+            settings: BTreeMap::new(),
+        }
+    }
 }
 
 //
@@ -135,6 +237,19 @@ impl Default for Config {
             pid_file: None,
             upgrade_socket: Some(PathBuf::from("/tmp/motya-upgrade.sock")),
             upgrade: false,
+            allow_partial_start: false,
+            provider: None,
+            events: None,
+            admin: None,
+            alerts: None,
+            state_dir: None,
+            affinity_keys: AffinityKeysConfig::default(),
+            wasm: WasmConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            client_ip_privacy: None,
+            trusted_proxies: None,
+            fingerprint: None,
+            flags: BTreeMap::new(),
         }
     }
 }
@@ -0,0 +1,25 @@
+use crate::{
+    common_types::active_window::{parse_timestamp, ActiveWindow},
+    kdl::parser::{ctx::ParseContext, ensures::Rule},
+};
+
+/// Parses `active-between "START" "END"`, shared by sections and filters:
+///
+/// ```kdl
+/// active-between "2024-12-01T00:00Z" "2024-12-26T00:00Z"
+/// ```
+pub fn parse_active_between(ctx: &ParseContext<'_>) -> miette::Result<ActiveWindow> {
+    ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(2), Rule::OnlyKeys(&[])])?;
+
+    let timestamp = |index| -> miette::Result<_> {
+        let value = ctx.arg(index)?.as_str()?;
+        parse_timestamp(&value).map_err(|err| ctx.error(err))
+    };
+    let (start, end) = (timestamp(0)?, timestamp(1)?);
+
+    if end <= start {
+        return Err(ctx.error("'active-between' must end after it starts"));
+    }
+
+    Ok(ActiveWindow { start, end })
+}
@@ -0,0 +1,302 @@
+use std::time::Duration;
+
+use http::Uri;
+use motya_macro::validate;
+
+use crate::{
+    block_parser,
+    common_types::{
+        alerts::{AlertRule, AlertsConfig, WebhookConfig, WebhookFormat},
+        section_parser::SectionParser,
+    },
+    kdl::parser::{
+        ctx::ParseContext,
+        ensures::Rule,
+        utils::{OptionTypedValueExt, PrimitiveType},
+    },
+};
+
+/// Parses the `alerts` block of the `system` section:
+///
+/// ```kdl
+/// alerts {
+///     webhook "https://hooks.slack.com/services/T0/B0/X" format="slack"
+///     pool-healthy-below percent=50
+///     cert-expiring days=14
+///     error-rate-above percent=5 window-secs=60 min-requests=20
+/// }
+/// ```
+pub struct AlertsSection;
+
+impl SectionParser<ParseContext<'_>, AlertsConfig> for AlertsSection {
+    #[validate(ensure_node_name = "alerts")]
+    fn parse_node(&self, ctx: ParseContext) -> miette::Result<AlertsConfig> {
+        ctx.validate(&[Rule::NoArgs, Rule::ReqChildren])?;
+
+        block_parser!(
+            ctx.clone(),
+            webhooks: repeated("webhook") => |ctx| self.parse_webhook(ctx),
+            pool: optional("pool-healthy-below") => |ctx| self.parse_pool_healthy_below(ctx),
+            cert: optional("cert-expiring") => |ctx| self.parse_cert_expiring(ctx),
+            errors: optional("error-rate-above") => |ctx| self.parse_error_rate_above(ctx)
+        );
+
+        if webhooks.is_empty() {
+            return Err(ctx.error("'alerts' requires at least one 'webhook'"));
+        }
+
+        let rules: Vec<AlertRule> = [pool, cert, errors].into_iter().flatten().collect();
+
+        if rules.is_empty() {
+            return Err(ctx.error(
+                "'alerts' requires at least one of 'pool-healthy-below', 'cert-expiring' or 'error-rate-above'",
+            ));
+        }
+
+        Ok(AlertsConfig { webhooks, rules })
+    }
+}
+
+impl AlertsSection {
+    fn parse_webhook(&self, ctx: ParseContext<'_>) -> miette::Result<WebhookConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::ExactArgs(1),
+            Rule::OnlyKeysTyped(&[
+                ("format", PrimitiveType::String),
+                ("routing-key", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let url = ctx.first()?.parse_as::<Uri>()?;
+
+        if !matches!(url.scheme_str(), Some("http" | "https")) {
+            return Err(ctx.error("Webhook URL must be http or https"));
+        }
+
+        let [format, routing_key] = ctx.props(["format", "routing-key"])?;
+        let format = format.as_str()?.unwrap_or_else(|| "generic".to_string());
+        let routing_key = routing_key.as_str()?;
+
+        let format = match (format.as_str(), routing_key) {
+            ("pagerduty", Some(routing_key)) => WebhookFormat::PagerDuty { routing_key },
+            ("pagerduty", None) => {
+                return Err(ctx.error("'pagerduty' webhooks require a 'routing-key'"))
+            }
+            (_, Some(_)) => {
+                return Err(ctx.error("'routing-key' is only valid for 'pagerduty' webhooks"))
+            }
+            ("generic", None) => WebhookFormat::Generic,
+            ("slack", None) => WebhookFormat::Slack,
+            (other, None) => {
+                return Err(ctx.error(format!(
+                    "Unknown webhook format '{other}', expected 'generic', 'slack' or 'pagerduty'"
+                )))
+            }
+        };
+
+        Ok(WebhookConfig { url, format })
+    }
+
+    fn parse_pool_healthy_below(&self, ctx: ParseContext<'_>) -> miette::Result<AlertRule> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[("percent", PrimitiveType::Integer)]),
+        ])?;
+
+        let percent = self.parse_percent(&ctx)?;
+
+        Ok(AlertRule::PoolHealthyBelow { percent })
+    }
+
+    fn parse_cert_expiring(&self, ctx: ParseContext<'_>) -> miette::Result<AlertRule> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[("days", PrimitiveType::Integer)]),
+        ])?;
+
+        let days = ctx.prop("days")?.as_usize()? as u64;
+
+        if days == 0 {
+            return Err(ctx.error("'days' must be greater than zero"));
+        }
+
+        Ok(AlertRule::CertExpiring {
+            within: Duration::from_secs(days * 24 * 60 * 60),
+        })
+    }
+
+    fn parse_error_rate_above(&self, ctx: ParseContext<'_>) -> miette::Result<AlertRule> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("percent", PrimitiveType::Integer),
+                ("window-secs", PrimitiveType::Integer),
+                ("min-requests", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let percent = self.parse_percent(&ctx)?;
+
+        let [window, min_requests] = ctx.props(["window-secs", "min-requests"])?;
+        let window = window.as_usize()?.unwrap_or(60) as u64;
+        let min_requests = min_requests.as_usize()?.unwrap_or(1) as u64;
+
+        if window == 0 {
+            return Err(ctx.error("'window-secs' must be greater than zero"));
+        }
+
+        Ok(AlertRule::ErrorRateAbove {
+            percent,
+            window: Duration::from_secs(window),
+            min_requests,
+        })
+    }
+
+    fn parse_percent(&self, ctx: &ParseContext<'_>) -> miette::Result<u8> {
+        let percent = ctx.prop("percent")?.as_usize()?;
+
+        if percent == 0 || percent > 100 {
+            return Err(ctx.error("'percent' must be between 1 and 100"));
+        }
+
+        Ok(percent as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_err_contains;
+    use crate::kdl::parser::block::BlockParser;
+    use crate::kdl::parser::ctx::Current;
+    use kdl::KdlDocument;
+
+    fn parse_alerts(input: &str) -> miette::Result<AlertsConfig> {
+        let doc: KdlDocument = input.parse().unwrap();
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let mut block = BlockParser::new(ctx)?;
+
+        block.required("alerts", |ctx| AlertsSection.parse_node(ctx))
+    }
+
+    #[test]
+    fn test_alerts_full() {
+        let config = parse_alerts(
+            r#"
+            alerts {
+                webhook "https://hooks.slack.com/services/T0/B0/X" format="slack"
+                webhook "https://events.pagerduty.com/v2/enqueue" format="pagerduty" routing-key="abc"
+                webhook "http://127.0.0.1:9000/alerts"
+                pool-healthy-below percent=50
+                cert-expiring days=14
+                error-rate-above percent=5 window-secs=30 min-requests=20
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.webhooks.len(), 3);
+        assert_eq!(config.webhooks[0].format, WebhookFormat::Slack);
+        assert_eq!(
+            config.webhooks[1].format,
+            WebhookFormat::PagerDuty {
+                routing_key: "abc".into()
+            }
+        );
+        assert_eq!(config.webhooks[2].format, WebhookFormat::Generic);
+
+        assert_eq!(
+            config.rules,
+            vec![
+                AlertRule::PoolHealthyBelow { percent: 50 },
+                AlertRule::CertExpiring {
+                    within: Duration::from_secs(14 * 24 * 60 * 60)
+                },
+                AlertRule::ErrorRateAbove {
+                    percent: 5,
+                    window: Duration::from_secs(30),
+                    min_requests: 20
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alerts_require_webhook_and_rule() {
+        let no_webhook = parse_alerts(
+            r#"
+            alerts {
+                cert-expiring days=7
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            no_webhook.help().unwrap().to_string(),
+            "at least one 'webhook'"
+        );
+
+        let no_rule = parse_alerts(
+            r#"
+            alerts {
+                webhook "https://example.com/hook"
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            no_rule.help().unwrap().to_string(),
+            "requires at least one of"
+        );
+    }
+
+    #[test]
+    fn test_alerts_invalid_webhook() {
+        let missing_key = parse_alerts(
+            r#"
+            alerts {
+                webhook "https://events.pagerduty.com/v2/enqueue" format="pagerduty"
+                cert-expiring days=7
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            missing_key.help().unwrap().to_string(),
+            "require a 'routing-key'"
+        );
+
+        let unknown = parse_alerts(
+            r#"
+            alerts {
+                webhook "https://example.com/hook" format="teams"
+                cert-expiring days=7
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            unknown.help().unwrap().to_string(),
+            "Unknown webhook format 'teams'"
+        );
+    }
+
+    #[test]
+    fn test_alerts_percent_bounds() {
+        let err = parse_alerts(
+            r#"
+            alerts {
+                webhook "https://example.com/hook"
+                pool-healthy-below percent=120
+            }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_err_contains!(err.help().unwrap().to_string(), "must be between 1 and 100");
+    }
+}
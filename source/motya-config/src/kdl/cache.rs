@@ -0,0 +1,166 @@
+use http::HeaderName;
+use motya_macro::validate;
+
+use crate::{
+    block_parser,
+    common_types::{
+        cache::{VaryBucket, VaryConfig},
+        section_parser::SectionParser,
+    },
+    kdl::parser::{ctx::ParseContext, ensures::Rule, typed_value::TypedValue},
+};
+
+/// Parses the `vary` block of a cache:
+///
+/// ```kdl
+/// vary {
+///     ignore "user-agent"
+///     bucket "accept-encoding" "br" "gzip" "identity"
+/// }
+/// ```
+pub struct VarySection;
+
+impl SectionParser<ParseContext<'_>, VaryConfig> for VarySection {
+    #[validate(ensure_node_name = "vary")]
+    fn parse_node(&self, ctx: ParseContext) -> miette::Result<VaryConfig> {
+        ctx.validate(&[Rule::NoArgs, Rule::ReqChildren])?;
+
+        block_parser!(
+            ctx.clone(),
+            ignore: repeated("ignore") => |ctx| self.parse_ignore(ctx),
+            buckets: repeated("bucket") => |ctx| self.parse_bucket(ctx)
+        );
+
+        let config = VaryConfig { ignore, buckets };
+
+        for bucket in &config.buckets {
+            if config.ignore.contains(&bucket.header) {
+                return Err(ctx.error(format!(
+                    "Header '{}' is both ignored and bucketed",
+                    bucket.header
+                )));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl VarySection {
+    fn parse_ignore(&self, ctx: ParseContext<'_>) -> miette::Result<HeaderName> {
+        ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1)])?;
+
+        ctx.first()?.parse_as::<HeaderName>()
+    }
+
+    fn parse_bucket(&self, ctx: ParseContext<'_>) -> miette::Result<VaryBucket> {
+        ctx.validate(&[Rule::NoChildren])?;
+
+        let header = ctx.first()?.parse_as::<HeaderName>()?;
+
+        let values = ctx
+            .args()?
+            .iter()
+            .skip(1)
+            .map(|entry| {
+                if entry.name().is_some() {
+                    return Err(ctx.error("Bucket values must be positional arguments"));
+                }
+                TypedValue::new(&ctx, entry)
+                    .as_str()
+                    .map(|v| v.to_ascii_lowercase())
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+
+        if values.is_empty() {
+            return Err(ctx.error(format!("Bucket for '{header}' requires at least one value")));
+        }
+
+        Ok(VaryBucket { header, values })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_err_contains;
+    use crate::kdl::parser::block::BlockParser;
+    use crate::kdl::parser::ctx::Current;
+    use kdl::KdlDocument;
+
+    fn parse_vary(input: &str) -> miette::Result<VaryConfig> {
+        let doc: KdlDocument = input.parse().unwrap();
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let mut block = BlockParser::new(ctx)?;
+
+        block.required("vary", |ctx| VarySection.parse_node(ctx))
+    }
+
+    #[test]
+    fn test_vary_rules() {
+        let config = parse_vary(
+            r#"
+            vary {
+                ignore "User-Agent"
+                bucket "accept-encoding" "br" "GZIP" "identity"
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.ignore, vec![HeaderName::from_static("user-agent")]);
+        assert_eq!(
+            config.buckets,
+            vec![VaryBucket {
+                header: HeaderName::from_static("accept-encoding"),
+                values: vec!["br".into(), "gzip".into(), "identity".into()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_vary_bucket_without_values() {
+        let err = parse_vary(
+            r#"
+            vary {
+                bucket "accept-encoding"
+            }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "requires at least one value"
+        );
+    }
+
+    #[test]
+    fn test_vary_ignored_and_bucketed() {
+        let err = parse_vary(
+            r#"
+            vary {
+                ignore "accept-encoding"
+                bucket "accept-encoding" "gzip" "identity"
+            }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_err_contains!(err.help().unwrap().to_string(), "both ignored and bucketed");
+    }
+
+    #[test]
+    fn test_vary_invalid_header() {
+        let err = parse_vary(
+            r#"
+            vary {
+                ignore "not a header"
+            }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_err_contains!(err.help().unwrap().to_string(), "Invalid");
+    }
+}
@@ -1,19 +1,36 @@
 use crate::{
-    common_types::definitions::{ConfiguredFilter, FilterChain},
-    kdl::parser::{block::BlockParser, ctx::ParseContext, ensures::Rule},
+    common_types::{
+        definitions::{ConfiguredFilter, FilterChain},
+        definitions_table::DefinitionsTable,
+        filter_schema::FilterArg,
+    },
+    kdl::{
+        active_window::parse_active_between,
+        parser::{block::BlockParser, ctx::ParseContext, ensures::Rule, typed_value::TypedValue},
+    },
 };
 use std::collections::HashMap;
 
-pub struct ChainParser;
+pub struct ChainParser<'a> {
+    table: &'a DefinitionsTable,
+}
+
+impl<'a> ChainParser<'a> {
+    pub fn new(table: &'a DefinitionsTable) -> Self {
+        Self { table }
+    }
 
-impl ChainParser {
     pub fn parse(&self, ctx: ParseContext<'_>) -> miette::Result<FilterChain> {
         let mut block = BlockParser::new(ctx)?;
         let filters = block.repeated("filter", |filter_ctx| {
-            filter_ctx.validate(&[Rule::NoChildren, Rule::NoPositionalArgs])?;
+            filter_ctx.validate(&[Rule::NoPositionalArgs])?;
 
             let name = filter_ctx.prop("name")?.parse_as::<fqdn::FQDN>()?;
 
+            if let Some(schema) = self.table.get_filter_schema(&name) {
+                Self::check_args(&filter_ctx, &name, schema)?;
+            }
+
             let all_args = filter_ctx.args_map(1..)?;
 
             let args = all_args
@@ -21,18 +38,78 @@ impl ChainParser {
                 .map(|(k, v)| (k.to_string(), v.to_string()))
                 .collect::<HashMap<_, _>>();
 
-            Ok(ConfiguredFilter { name, args })
+            let active = if filter_ctx.has_children_block()? {
+                let mut block = BlockParser::new(filter_ctx.enter_block()?)?;
+                let active = block.required("active-between", |ctx| parse_active_between(&ctx))?;
+                block.exhaust()?;
+                Some(active)
+            } else {
+                None
+            };
+
+            Ok(ConfiguredFilter { name, args, active })
         })?;
 
         block.exhaust()?;
 
         Ok(FilterChain { filters })
     }
+
+    /// Checks the arguments of a builtin filter against its schema.
+    fn check_args(
+        ctx: &ParseContext<'_>,
+        name: &fqdn::FQDN,
+        schema: &[FilterArg],
+    ) -> miette::Result<()> {
+        for entry in ctx.args()? {
+            let Some(key) = entry.name().map(|key| key.value()) else {
+                continue;
+            };
+
+            if key == "name" {
+                continue;
+            }
+
+            let Some(arg) = schema.iter().find(|arg| arg.name == key) else {
+                let expected = schema.iter().map(|arg| arg.name).collect::<Vec<_>>();
+                return Err(ctx.error_with_span(
+                    format!(
+                        "Unknown argument '{key}' for filter '{name}'. Expected: {}",
+                        expected.join(", ")
+                    ),
+                    entry.span(),
+                ));
+            };
+
+            let value = TypedValue::new(ctx, entry).as_str()?;
+
+            arg.kind.check(&value).map_err(|reason| {
+                ctx.error_with_span(
+                    format!("Invalid argument '{key}' for filter '{name}': {reason}"),
+                    entry.span(),
+                )
+            })?;
+        }
+
+        for arg in schema.iter().filter(|arg| arg.required) {
+            if ctx.opt_prop(arg.name)?.is_none() {
+                return Err(ctx.error(format!(
+                    "Missing required argument '{}' for filter '{name}'",
+                    arg.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::kdl::parser::ctx::Current;
+    use crate::{
+        common_types::{active_window::parse_timestamp, filter_schema::ArgKind},
+        kdl::parser::ctx::Current,
+    };
 
     use super::*;
     use kdl::KdlDocument;
@@ -46,7 +123,9 @@ mod tests {
         let doc: KdlDocument = kdl_input.parse().unwrap();
 
         let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
-        let chain = ChainParser.parse(ctx).expect("Should parse valid chain");
+        let chain = ChainParser::new(&DefinitionsTable::default())
+            .parse(ctx)
+            .expect("Should parse valid chain");
 
         assert_eq!(chain.filters.len(), 2);
 
@@ -66,7 +145,9 @@ mod tests {
         let doc: KdlDocument = kdl_input.parse().unwrap();
 
         let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
-        let chain = ChainParser.parse(ctx).expect("Should parse valid chain");
+        let chain = ChainParser::new(&DefinitionsTable::default())
+            .parse(ctx)
+            .expect("Should parse valid chain");
         assert!(chain.filters.is_empty());
     }
 
@@ -79,7 +160,7 @@ mod tests {
         let doc: KdlDocument = kdl_input.parse().unwrap();
 
         let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
-        let result = ChainParser.parse(ctx);
+        let result = ChainParser::new(&DefinitionsTable::default()).parse(ctx);
         let msg_err = result.unwrap_err().help().unwrap().to_string();
 
         crate::assert_err_contains!(msg_err, "Unknown directive: 'not-filter'");
@@ -93,7 +174,7 @@ mod tests {
         let doc: KdlDocument = kdl_input.parse().unwrap();
 
         let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
-        let result = ChainParser.parse(ctx);
+        let result = ChainParser::new(&DefinitionsTable::default()).parse(ctx);
         let msg_err = result.unwrap_err().help().unwrap().to_string();
         crate::assert_err_contains!(msg_err, "Missing required property 'name'");
     }
@@ -106,7 +187,7 @@ mod tests {
         let doc: KdlDocument = kdl_input.parse().unwrap();
 
         let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
-        let result = ChainParser.parse(ctx);
+        let result = ChainParser::new(&DefinitionsTable::default()).parse(ctx);
         let msg_err = result.unwrap_err().help().unwrap().to_string();
 
         crate::assert_err_contains!(
@@ -114,4 +195,98 @@ mod tests {
             "Invalid FQDN 'invalid name with spaces'. Reason: invalid char found in FQDN"
         );
     }
+
+    fn parse_with_schema(kdl_input: &str) -> miette::Result<FilterChain> {
+        const ARGS: &[FilterArg] = &[
+            FilterArg {
+                name: "pattern",
+                required: true,
+                kind: ArgKind::Regex,
+                description: "",
+            },
+            FilterArg {
+                name: "limit",
+                required: false,
+                kind: ArgKind::Integer,
+                description: "",
+            },
+        ];
+
+        let mut table = DefinitionsTable::default();
+        table.insert_filter_schema(fqdn::fqdn!("motya.request.rewrite"), ARGS);
+
+        let doc: KdlDocument = kdl_input.parse().unwrap();
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        ChainParser::new(&table).parse(ctx)
+    }
+
+    #[test]
+    fn test_chain_parser_schema() {
+        let chain = parse_with_schema(
+            r#"
+            filter name="motya.request.rewrite" pattern="^/api/(.*)" limit="10"
+            filter name="com.example.unknown" anything="goes"
+        "#,
+        )
+        .expect("Should parse valid chain");
+        assert_eq!(chain.filters.len(), 2);
+
+        let cases = [
+            (
+                r#"filter name="motya.request.rewrite""#,
+                "Missing required argument 'pattern' for filter 'motya.request.rewrite'",
+            ),
+            (
+                r#"filter name="motya.request.rewrite" pattern="(unclosed""#,
+                "Invalid argument 'pattern' for filter 'motya.request.rewrite': invalid regex",
+            ),
+            (
+                r#"filter name="motya.request.rewrite" pattern=".*" limit="ten""#,
+                "Invalid argument 'limit' for filter 'motya.request.rewrite': expected an integer, found 'ten'",
+            ),
+            (
+                r#"filter name="motya.request.rewrite" pattern=".*" replace="x""#,
+                "Unknown argument 'replace' for filter 'motya.request.rewrite'. Expected: pattern, limit",
+            ),
+            (
+                r#"filter name="motya.request.rewrite" pattern=".*" limit=10"#,
+                "Expected a string value",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let err = parse_with_schema(input).unwrap_err();
+            crate::assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_chain_parser_active_between() {
+        let kdl_input = r#"
+            filter name="motya.filters.redirect" location="/holidays" {
+                active-between "2024-12-01T00:00Z" "2024-12-26T00:00Z"
+            }
+            filter name="com.example.logger"
+        "#;
+        let doc: KdlDocument = kdl_input.parse().unwrap();
+
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let chain = ChainParser::new(&DefinitionsTable::default())
+            .parse(ctx)
+            .expect("Should parse valid chain");
+
+        let active = chain.filters[0].active.expect("window should be set");
+        assert_eq!(active.start, parse_timestamp("2024-12-01T00:00Z").unwrap());
+        assert_eq!(chain.filters[0].args.len(), 1);
+        assert_eq!(chain.filters[1].active, None);
+
+        let kdl_input = r#"
+            filter name="com.example.logger" { level "debug"; }
+        "#;
+        let doc: KdlDocument = kdl_input.parse().unwrap();
+
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let result = ChainParser::new(&DefinitionsTable::default()).parse(ctx);
+        assert!(result.is_err());
+    }
 }
@@ -1,16 +1,19 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 use crate::common_types::bad::Bad;
 use crate::common_types::definitions_table::DefinitionsTable;
+use crate::common_types::listeners::UnmatchedHost;
 use crate::common_types::section_parser::SectionParser;
-use crate::internal::Config;
+use crate::internal::{Config, ProxyConfig};
 use crate::kdl::parser::block::BlockParser;
 use crate::kdl::parser::ctx::{Current, ParseContext};
 use crate::kdl::{
-    definitions::DefinitionsSection, services::ServicesSection, system_data::SystemDataSection,
+    definitions::DefinitionsSection, flags::FlagsSection, lints, services::ServicesSection,
+    system_data::SystemDataSection,
 };
 use kdl::KdlDocument;
 use miette::{miette, Result};
+use sha2::{Digest, Sha256};
 
 /// Orchestrates the loading and composition of the configuration from multiple KDL files.
 ///
@@ -57,10 +60,11 @@ impl ConfigCompiler {
             ));
         }
 
-        let allowed_names: HashSet<&str> = ["services", "definitions", "includes", "system"]
-            .iter()
-            .cloned()
-            .collect();
+        let allowed_names: HashSet<&str> =
+            ["services", "definitions", "includes", "system", "flags"]
+                .iter()
+                .cloned()
+                .collect();
 
         for (doc, source_name) in &self.documents {
             let present_names: HashSet<&str> =
@@ -77,7 +81,7 @@ impl ConfigCompiler {
                 {
                     let unknown = node.name().value();
                     return Err(Bad::docspan(
-                        format!("Unknown top-level section '{}' in '{}'. Allowed: services, definitions, includes, system.", unknown, source_name),
+                        format!("Unknown top-level section '{}' in '{}'. Allowed: services, definitions, includes, system, flags.", unknown, source_name),
                         doc,
                         &node.span(),
                         source_name
@@ -86,7 +90,10 @@ impl ConfigCompiler {
             }
         }
 
-        let mut final_config = Config::default();
+        let mut final_config = Config {
+            fingerprint: Some(fingerprint(&self.documents)),
+            ..Config::default()
+        };
 
         let sys_data = self
             .documents
@@ -114,12 +121,39 @@ impl ConfigCompiler {
         final_config.daemonize = sys_data.daemonize;
         final_config.upgrade_socket = sys_data.upgrade_socket;
         final_config.pid_file = sys_data.pid_file;
+        final_config.provider = sys_data.provider;
+        final_config.events = sys_data.events;
+        final_config.admin = sys_data.admin;
+        final_config.alerts = sys_data.alerts;
+        final_config.state_dir = sys_data.state_dir;
+        final_config.affinity_keys = sys_data.affinity_keys;
+        final_config.wasm = sys_data.wasm;
+        final_config.shutdown = sys_data.shutdown;
+        final_config.client_ip_privacy = sys_data.client_ip_privacy;
+        final_config.trusted_proxies = sys_data.trusted_proxies;
 
         for (doc, name) in &self.documents {
             let ctx = ParseContext::new(doc, Current::Document(doc), name);
             let mut block = BlockParser::new(ctx)?;
 
-            let defs = block.optional("definitions", |ctx| DefinitionsSection.parse_node(ctx))?;
+            let flags = block.optional("flags", |ctx| FlagsSection.parse_node(ctx))?;
+
+            for (flag, enabled) in flags.into_iter().flatten() {
+                if final_config.flags.insert(flag.clone(), enabled).is_some() {
+                    return Err(miette!(
+                        "Flag '{flag}' is declared in several 'flags' sections"
+                    ));
+                }
+            }
+        }
+
+        for (doc, name) in &self.documents {
+            let ctx = ParseContext::new(doc, Current::Document(doc), name);
+            let mut block = BlockParser::new(ctx)?;
+
+            let defs = block.optional("definitions", |ctx| {
+                DefinitionsSection::new(global_definitions).parse_node(ctx)
+            })?;
 
             if let Some(defs) = defs {
                 global_definitions.merge(defs)?;
@@ -140,14 +174,127 @@ impl ConfigCompiler {
             }
         }
 
+        let sticky = final_config
+            .basic_proxies
+            .iter()
+            .flat_map(|proxy| &proxy.connectors.upstreams)
+            .any(|upstream| {
+                upstream
+                    .lb_options
+                    .as_ref()
+                    .is_some_and(|lb| lb.sticky.is_some())
+            });
+
+        if sticky && final_config.state_dir.is_none() {
+            return Err(miette!(
+                "'CookieSticky' selection signs its cookies with the affinity keys, set 'system.state-dir' to keep them"
+            ));
+        }
+
+        check_shared_listeners(&final_config.basic_proxies)?;
+        check_flags(&final_config)?;
+
+        for lint in lints::lint(&final_config, &self.documents) {
+            tracing::warn!("{:?}", miette::Report::new(lint));
+        }
+
         Ok(final_config)
     }
 }
 
+/// The first 8 bytes of a SHA-256 over the documents in loading order, with
+/// whitespace and comments stripped so that only edits to nodes change it.
+fn fingerprint(documents: &[(KdlDocument, String)]) -> String {
+    let mut hasher = Sha256::new();
+
+    for (doc, _) in documents {
+        let mut doc = doc.clone();
+        doc.clear_format_recursive();
+        hasher.update(doc.to_string());
+        hasher.update([0]);
+    }
+
+    hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Routes can only be gated on flags of the `flags` section.
+fn check_flags(config: &Config) -> Result<()> {
+    for proxy in &config.basic_proxies {
+        let gates = proxy
+            .connectors
+            .upstreams
+            .iter()
+            .filter_map(|upstream| upstream.flag.as_ref());
+
+        for gate in gates {
+            if !config.flags.contains_key(&gate.name) {
+                return Err(miette!(
+                    "Service '{}' uses the flag '{}' that is not declared in a 'flags' section",
+                    proxy.name,
+                    gate.name
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Services may share a listener when they configure it the same way and
+/// their `hosts` tell them apart.
+fn check_shared_listeners(proxies: &[ProxyConfig]) -> Result<()> {
+    let mut listeners: BTreeMap<String, Vec<_>> = BTreeMap::new();
+
+    for proxy in proxies {
+        for listener in &proxy.listeners.list_cfgs {
+            listeners
+                .entry(listener.source.address())
+                .or_default()
+                .push((proxy, listener));
+        }
+    }
+
+    for (address, services) in listeners {
+        let (first, listener) = services[0];
+
+        if let Some((other, _)) = services.iter().find(|(_, other)| *other != listener) {
+            return Err(miette!(
+                "Services '{}' and '{}' share the listener '{address}' with different settings",
+                first.name,
+                other.name
+            ));
+        }
+
+        let fallbacks: Vec<_> = services
+            .iter()
+            .filter(|(proxy, _)| proxy.hosts.is_empty())
+            .map(|(proxy, _)| proxy.name.as_str())
+            .collect();
+
+        if let [one, two, ..] = fallbacks[..] {
+            return Err(miette!(
+                "Services '{one}' and '{two}' share the listener '{address}', set 'hosts' on one of them"
+            ));
+        }
+
+        if listener.unmatched_host == Some(UnmatchedHost::DefaultService) && fallbacks.is_empty() {
+            return Err(miette!(
+                "'unmatched-host=\"default\"' on '{address}' needs a service without 'hosts'"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::common_types::system_data::{ConfigProvider, FilesProviderConfig};
     use fqdn::fqdn;
     #[tokio::test]
     async fn test_namespace_merge_across_files() {
@@ -380,4 +527,215 @@ mod tests {
         assert_eq!(config.threads_per_service, 2);
         assert_eq!(config.basic_proxies.len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_provider_is_carried_into_config() {
+        const MAIN_FILE: &str = r#"
+            system {
+                providers {
+                    files watch=#true
+                }
+            }
+
+            services {
+                TestService {
+                    listeners { "127.0.0.1:8080" }
+                    connectors {
+                        return code=200 response="OK"
+                    }
+                }
+            }
+        "#;
+
+        let main: KdlDocument = MAIN_FILE.parse().unwrap();
+        let compiler = ConfigCompiler::new(vec![(main, "main.kdl".to_string())]);
+
+        let mut def_table = DefinitionsTable::new_with_global();
+
+        let config = compiler
+            .compile(&mut def_table)
+            .expect("Config should load successfully");
+
+        assert_eq!(
+            config.provider,
+            Some(ConfigProvider::Files(FilesProviderConfig { watch: true }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cookie_sticky_needs_state_dir() {
+        const MAIN_FILE: &str = r#"
+            system {
+                threads-per-service 1
+            }
+
+            services {
+                TestService {
+                    listeners { "127.0.0.1:8080" }
+                    connectors {
+                        load-balance {
+                            selection "CookieSticky"
+                        }
+                        proxy {
+                            server "10.0.0.1:80"
+                            server "10.0.0.2:80"
+                        }
+                    }
+                }
+            }
+        "#;
+
+        let main: KdlDocument = MAIN_FILE.parse().unwrap();
+        let compiler = ConfigCompiler::new(vec![(main, "main.kdl".to_string())]);
+
+        let err = compiler
+            .compile(&mut DefinitionsTable::new_with_global())
+            .unwrap_err();
+        assert!(err.to_string().contains("'system.state-dir'"));
+
+        let with_state_dir = MAIN_FILE.replace(
+            "threads-per-service 1",
+            "threads-per-service 1\n                state-dir \"/var/lib/motya\"",
+        );
+        let main: KdlDocument = with_state_dir.parse().unwrap();
+        let compiler = ConfigCompiler::new(vec![(main, "main.kdl".to_string())]);
+
+        assert!(compiler
+            .compile(&mut DefinitionsTable::new_with_global())
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_shared_listeners() {
+        let compile = |services: &str| {
+            let main: KdlDocument =
+                format!("system {{ threads-per-service 1 }}\nservices {{ {services} }}")
+                    .parse()
+                    .unwrap();
+            ConfigCompiler::new(vec![(main, "main.kdl".to_string())])
+                .compile(&mut DefinitionsTable::new_with_global())
+        };
+
+        let config = compile(
+            r#"
+            Shop {
+                listeners { "0.0.0.0:8080" unmatched-host="misdirected" }
+                hosts "shop.example.com" "*.shop.example.com"
+                connectors { return code=200 response="shop" }
+            }
+            Blog {
+                listeners { "0.0.0.0:8080" unmatched-host="misdirected" }
+                hosts "blog.example.com"
+                connectors { return code=200 response="blog" }
+            }
+            "#,
+        )
+        .expect("Services with distinct hosts should share a listener");
+        assert_eq!(
+            config.basic_proxies[0].hosts,
+            vec!["shop.example.com", "*.shop.example.com"]
+        );
+
+        let cases = [
+            (
+                r#"
+                A { listeners { "0.0.0.0:8080" }; connectors { return code=200; } }
+                B { listeners { "0.0.0.0:8080" }; connectors { return code=200; } }
+                "#,
+                "set 'hosts' on one of them",
+            ),
+            (
+                r#"
+                A { listeners { "0.0.0.0:8080" h2c=#true }; hosts "a.com"; connectors { return code=200; } }
+                B { listeners { "0.0.0.0:8080" }; connectors { return code=200; } }
+                "#,
+                "with different settings",
+            ),
+            (
+                r#"
+                A { listeners { "0.0.0.0:8080" unmatched-host="default" }; hosts "a.com"; connectors { return code=200; } }
+                "#,
+                "needs a service without 'hosts'",
+            ),
+        ];
+
+        for (services, expected) in cases {
+            let err = compile(services).unwrap_err();
+            assert!(
+                err.to_string().contains(expected),
+                "expected: {expected}, got: {err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flags() {
+        let compile = |documents: &[&str]| {
+            let documents = documents
+                .iter()
+                .enumerate()
+                .map(|(idx, source)| (source.parse().unwrap(), format!("{idx}.kdl")))
+                .collect();
+            ConfigCompiler::new(documents).compile(&mut DefinitionsTable::new_with_global())
+        };
+
+        const SERVICE: &str = r#"
+            system { threads-per-service 1; }
+            services {
+                Api {
+                    listeners { "0.0.0.0:8080"; }
+                    connectors {
+                        section "/" flag="!new-router" { return code=200 response="old"; }
+                    }
+                }
+            }
+        "#;
+
+        let config = compile(&[SERVICE, "flags { new-router #true; beta #false; }"]).unwrap();
+        assert_eq!(
+            config.flags,
+            BTreeMap::from([
+                ("beta".to_string(), false),
+                ("new-router".to_string(), true)
+            ])
+        );
+
+        let err = compile(&[SERVICE]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("'new-router' that is not declared"));
+
+        let err = compile(&[
+            SERVICE,
+            "flags { new-router #true; }",
+            "flags { new-router #false; }",
+        ])
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("declared in several 'flags' sections"));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let fingerprint = |source: &str| {
+            let doc: KdlDocument = source.parse().unwrap();
+            fingerprint(&[(doc, "main.kdl".to_string())])
+        };
+
+        let base = fingerprint("system { threads-per-service 1; }");
+        assert_eq!(base.len(), 16);
+
+        let reformatted = fingerprint(
+            r#"
+            // Comments and layout are not part of the configuration.
+            system {
+                threads-per-service   1
+            }
+            "#,
+        );
+        assert_eq!(base, reformatted);
+
+        assert_ne!(base, fingerprint("system { threads-per-service 2; }"));
+    }
 }
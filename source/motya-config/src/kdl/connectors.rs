@@ -1,27 +1,46 @@
 use std::{
+    collections::{BTreeMap, HashSet},
     net::SocketAddr,
     sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
 };
 
-use http::{uri::PathAndQuery, StatusCode, Uri};
+use cidr::IpCidr;
+use fqdn::fqdn;
+use http::{uri::PathAndQuery, Method, StatusCode, Uri};
 use motya_macro::validate;
 
 use crate::{
     block_parser,
     common_types::{
+        active_window::ActiveWindow,
+        builtin_filters_name::is_pre_routing,
+        cache::{CacheConfig, CacheStorage, VaryConfig},
         connectors::{
-            Connectors, ConnectorsLeaf, HttpPeerConfig, MultiServerUpstreamConfig, RouteMatcher,
-            UpstreamConfig, UpstreamContextConfig, UpstreamServer, ALPN,
+            BackendOverrideConfig, BufferingConfig, CompressionAlgorithm, CompressionConfig,
+            Connectors, ConnectorsLeaf, FlagCondition, HarRecordingConfig, HostSniConfig,
+            HttpPeerConfig, MethodSplitConfig, MultiServerUpstreamConfig, ReadYourWritesConfig,
+            RetryConfig, RouteMatcher, RoutePattern, ShapingConfig, SplitGroupConfig,
+            TimeoutsConfig, TrafficSplitConfig, UpstreamConfig, UpstreamContextConfig,
+            UpstreamServer, ALPN,
+        },
+        definitions::{
+            ConfiguredFilter, FilterChain, KeyTemplateConfig, Modificator, NamedFilterChain,
         },
-        definitions::{KeyTemplateConfig, Modificator, NamedFilterChain},
         definitions_table::DefinitionsTable,
+        host_name::ascii_host,
         section_parser::SectionParser,
         simple_response_type::SimpleResponseConfig,
     },
-    internal::{DiscoveryKind, HealthCheckKind, SelectionKind, UpstreamOptions},
+    internal::{
+        CircuitBreakerConfig, DiscoveryConfig, HealthCheckKind, SelectionKind, StickyCookieConfig,
+        UpstreamOptions, WhenDownConfig,
+    },
     kdl::{
+        active_window::parse_active_between,
+        cache::VarySection,
         chain_parser::ChainParser,
-        key_profile_parser::KeyProfileParser,
+        key_profile_parser::{selector_template, KeyProfileParser},
         parser::{
             block::BlockParser,
             ctx::ParseContext,
@@ -31,6 +50,25 @@ use crate::{
     },
 };
 
+/// Media types compressed when `compression` does not list its own.
+const DEFAULT_COMPRESSIBLE_TYPES: &str =
+    "text/*,application/json,application/javascript,application/xml,image/svg+xml";
+
+/// Response bytes held by `buffering` when it sets no `max-bytes`.
+const DEFAULT_BUFFERING_MAX_BYTES: usize = 1024 * 1024;
+
+/// Bytes of each body `record-har` keeps when it sets no `max-body-bytes`.
+const DEFAULT_HAR_MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Megabytes of bodies a `cache` holds when it sets no `max-size-mb`.
+const DEFAULT_CACHE_MAX_SIZE_MB: usize = 64;
+
+/// Gigabytes a `cache storage="disk"` holds when it sets no `max-size-gb`.
+const DEFAULT_CACHE_MAX_SIZE_GB: usize = 1;
+
+/// Seconds a `cache` keeps responses without `max-age` when it sets no `ttl-secs`.
+const DEFAULT_CACHE_TTL_SECS: usize = 60;
+
 pub struct ConnectorsSection<'a> {
     table: &'a DefinitionsTable,
     anon_counter: AtomicUsize,
@@ -43,7 +81,21 @@ impl SectionParser<ParseContext<'_>, Connectors> for ConnectorsSection<'_> {
 
         let root_nodes = self.parse_connections_node(ctx, &mut anonymous_definitions)?;
 
-        let upstreams = flatten_nodes(root_nodes, &[])?;
+        let upstreams = flatten_nodes(
+            root_nodes,
+            &[],
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?;
 
         Ok(Connectors {
             upstreams,
@@ -70,6 +122,7 @@ impl<'a> ConnectorsSection<'a> {
             anonymous_definitions,
             "/".parse().unwrap(),
             RouteMatcher::Exact,
+            false,
         )
     }
 
@@ -79,20 +132,38 @@ impl<'a> ConnectorsSection<'a> {
         anon_definitions: &mut DefinitionsTable,
         base_path: PathAndQuery,
         matcher: RouteMatcher,
+        in_section: bool,
     ) -> miette::Result<Vec<ConnectorsLeaf>> {
         block_parser!(
             ctx,
             leaf: optional_any(&["proxy", "return"]) => |ctx, name| match name {
-                "return" => self.extract_static_response(ctx, base_path.clone()),
-                "proxy" => self.extract_connector(ctx, base_path.clone(), matcher),
+                "return" if matches!(matcher, RouteMatcher::Regex(_)) => {
+                    Err(ctx.error("'return' is not supported in a 'regex' section"))
+                }
+                "return" => Ok((self.extract_static_response(ctx, base_path.clone())?, None)),
+                "proxy" => {
+                    let connector = self.extract_connector(ctx.clone(), base_path.clone(), matcher.clone())?;
+                    let inline = self.extract_inline_filters(&ctx, anon_definitions, &base_path, &matcher)?;
+                    Ok((connector, inline))
+                }
                 _ => unreachable!("Guaranteed by BlockParser"),
             },
             lb: optional("load-balance") => |ctx| self.extract_load_balance(ctx, anon_definitions),
-            chains: repeated("use-chain") => |ctx| self.extract_chain_usage(ctx, anon_definitions, base_path.clone()),
-            sections: repeated("section") => |ctx| self.extract_section(ctx, anon_definitions, base_path.clone(), matcher)
+            shaping: optional("shaping") => |ctx| self.extract_shaping(ctx),
+            timeouts: optional("timeouts") => |ctx| self.extract_timeouts(ctx),
+            retry: optional("retry") => |ctx| self.extract_retry(ctx),
+            compression: optional("compression") => |ctx| self.extract_compression(ctx),
+            body_limit: optional("max-request-body-bytes") => |ctx| self.extract_max_request_body(ctx),
+            buffering: optional("buffering") => |ctx| self.extract_buffering(ctx),
+            record_har: optional("record-har") => |ctx| self.extract_record_har(ctx),
+            cache: optional("cache") => |ctx| self.extract_cache(ctx),
+            active: optional("active-between") => |ctx| parse_active_between(&ctx).map(ConnectorsLeaf::ActiveBetween),
+            chains: repeated("use-chain") => |ctx| self.extract_chain_usage(ctx, anon_definitions, base_path.clone(), in_section),
+            sections: repeated("section") => |ctx| self.extract_section(ctx, anon_definitions, base_path.clone(), matcher.clone())
         );
 
         let mut result = Vec::new();
+        let (leaf, inline_chain) = leaf.unzip();
 
         if let Some(l) = leaf {
             result.push(l);
@@ -100,23 +171,67 @@ impl<'a> ConnectorsSection<'a> {
         if let Some(l) = lb {
             result.push(l);
         }
+        if let Some(l) = shaping {
+            result.push(l);
+        }
+        if let Some(l) = timeouts {
+            result.push(l);
+        }
+        if let Some(l) = retry {
+            result.push(l);
+        }
+        if let Some(l) = compression {
+            result.push(l);
+        }
+        if let Some(l) = body_limit {
+            result.push(l);
+        }
+        if let Some(l) = buffering {
+            result.push(l);
+        }
+        if let Some(l) = record_har {
+            result.push(l);
+        }
+        if let Some(l) = cache {
+            result.push(l);
+        }
+        if let Some(l) = active {
+            result.push(l);
+        }
 
         result.extend(chains);
+        result.extend(inline_chain.flatten());
         result.extend(sections);
 
         Ok(result)
     }
 
+    /// A `use-chain` of the block. Pre-routing filters run before a route is
+    /// picked, so a `section` cannot use a chain holding them.
     fn extract_chain_usage(
         &self,
         ctx: ParseContext<'_>,
         anonymous_definitions: &mut DefinitionsTable,
         path: PathAndQuery,
+        in_section: bool,
     ) -> miette::Result<ConnectorsLeaf> {
+        let check_phases = |chain: &FilterChain| {
+            let pre_routing = chain.filters.iter().find(|f| is_pre_routing(&f.name));
+
+            match pre_routing {
+                Some(filter) if in_section => Err(ctx.error(format!(
+                    "'{}' runs before the route is matched, use its chain at the top of 'connectors' instead of in a 'section'",
+                    filter.name
+                ))),
+                _ => Ok(()),
+            }
+        };
+
         if ctx.has_children_block()? {
             ctx.validate(&[Rule::NoArgs])?;
 
-            let chain = ChainParser.parse(ctx.enter_block()?)?;
+            let chain = ChainParser::new(self.table).parse(ctx.enter_block()?)?;
+            check_phases(&chain)?;
 
             let id = self.anon_counter.fetch_add(1, Ordering::Relaxed);
             let path_slug = path.path().replace('/', "_");
@@ -139,6 +254,7 @@ impl<'a> ConnectorsSection<'a> {
                 .table
                 .get_chain_by_name(&name)
                 .ok_or_else(|| ctx.error(format!("Chain '{}' not found in definitions", name)))?;
+            check_phases(&chain)?;
 
             Ok(ConnectorsLeaf::Modificator(Modificator::Chain(
                 NamedFilterChain {
@@ -149,6 +265,113 @@ impl<'a> ConnectorsSection<'a> {
         }
     }
 
+    /// Filters set as properties of a single line `proxy`, desugared into an
+    /// anonymous chain that runs after the `use-chain`s of its block.
+    fn extract_inline_filters(
+        &self,
+        ctx: &ParseContext<'_>,
+        anonymous_definitions: &mut DefinitionsTable,
+        path: &PathAndQuery,
+        matcher: &RouteMatcher,
+    ) -> miette::Result<Option<ConnectorsLeaf>> {
+        if ctx.has_children_block()? {
+            return Ok(None);
+        }
+
+        let [set_header, remove_header, strip_prefix, forwarded_for] = ctx.props([
+            "set-header",
+            "remove-header",
+            "strip-prefix",
+            "forwarded-for",
+        ])?;
+
+        let mut filters = Vec::new();
+
+        if let Some(header) = set_header.as_str()? {
+            let Some((key, value)) = header.split_once(':') else {
+                return Err(ctx.error("'set-header' must be in the form \"Name: value\""));
+            };
+            filters.push((
+                fqdn!("motya.request.upsert-header"),
+                vec![
+                    ("key", key.trim().to_string()),
+                    ("value", value.trim().to_string()),
+                ],
+            ));
+        }
+
+        if let Some(pattern) = remove_header.as_str()? {
+            filters.push((
+                fqdn!("motya.request.remove-header"),
+                vec![("pattern", pattern)],
+            ));
+        }
+
+        if strip_prefix.as_bool()?.unwrap_or(false) {
+            if matches!(matcher, RouteMatcher::Regex(_)) {
+                return Err(ctx.error("'strip-prefix' is not supported in a 'regex' section"));
+            }
+
+            let prefix = path.path().trim_end_matches('/');
+            if prefix.is_empty() {
+                return Err(ctx.error("'strip-prefix' needs the path of a section to strip"));
+            }
+            filters.push((
+                fqdn!("motya.request.strip-prefix"),
+                vec![("prefix", prefix.to_string())],
+            ));
+        }
+
+        if forwarded_for.as_bool()?.unwrap_or(false) {
+            filters.push((fqdn!("motya.request.forwarded-for"), vec![]));
+        }
+
+        if filters.is_empty() {
+            return Ok(None);
+        }
+
+        let filters = filters
+            .into_iter()
+            .map(|(name, args)| {
+                let schema = self.table.get_filter_schema(&name).unwrap_or_default();
+
+                for (key, value) in &args {
+                    if let Some(arg) = schema.iter().find(|arg| arg.name == *key) {
+                        arg.kind.check(value).map_err(|reason| {
+                            ctx.error(format!(
+                                "Invalid argument '{key}' for filter '{name}': {reason}"
+                            ))
+                        })?;
+                    }
+                }
+
+                Ok(ConfiguredFilter {
+                    name,
+                    args: args
+                        .into_iter()
+                        .map(|(key, value)| (key.to_string(), value))
+                        .collect(),
+                    active: None,
+                })
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+
+        let chain = FilterChain { filters };
+
+        let id = self.anon_counter.fetch_add(1, Ordering::Relaxed);
+        let path_slug = path.path().replace('/', "_");
+        let generated_name = format!("__anon_{id}_{path_slug}");
+
+        anonymous_definitions.insert_chain(generated_name.clone(), chain.clone());
+
+        Ok(Some(ConnectorsLeaf::Modificator(Modificator::Chain(
+            NamedFilterChain {
+                chain,
+                name: generated_name,
+            },
+        ))))
+    }
+
     fn extract_section(
         &self,
         ctx: ParseContext<'_>,
@@ -159,59 +382,105 @@ impl<'a> ConnectorsSection<'a> {
         ctx.validate(&[
             Rule::ReqChildren,
             Rule::ExactArgs(1),
-            Rule::OnlyKeysTyped(&[("as", PrimitiveType::String)]),
+            Rule::OnlyKeysTyped(&[
+                ("as", PrimitiveType::String),
+                ("methods", PrimitiveType::String),
+                ("flag", PrimitiveType::String),
+            ]),
         ])?;
 
         let path_segment = ctx.arg(0)?.as_str()?;
         let mode_arg = ctx.opt_prop("as")?.as_str()?;
+        let methods = ctx
+            .opt_prop("methods")?
+            .as_str()?
+            .map(|methods| parse_methods(&ctx, &methods))
+            .transpose()?;
+        let flag = ctx
+            .opt_prop("flag")?
+            .as_str()?
+            .map(|flag| parse_flag(&ctx, &flag))
+            .transpose()?;
+
+        let regex = || {
+            RoutePattern::new(&path_segment)
+                .map(RouteMatcher::Regex)
+                .map_err(|err| ctx.error(format!("Invalid route regex: {err}")))
+        };
 
         let next_matcher = match mode_arg.as_deref() {
             Some("prefix") => RouteMatcher::Prefix,
             Some("exact") => RouteMatcher::Exact,
+            Some("regex") => regex()?,
             Some(other) => {
                 return Err(ctx.error(format!(
-                    "Unknown routing mode '{other}'. Use 'prefix' or 'exact'"
+                    "Unknown routing mode '{other}'. Use 'prefix', 'exact' or 'regex'"
                 )))
             }
+            // Paths start with '/', so a leading '^' can only be a pattern.
+            None if path_segment.starts_with('^') => regex()?,
             None => parent_matcher,
         };
 
         let children_nodes = ctx.req_nodes()?;
 
-        if next_matcher == RouteMatcher::Exact {
+        let leaf_mode = match next_matcher {
+            RouteMatcher::Exact => Some("exact"),
+            RouteMatcher::Regex(_) => Some("regex"),
+            RouteMatcher::Prefix => None,
+        };
+
+        if let Some(mode) = leaf_mode {
             for child in &children_nodes {
                 if child.name()? == "section" {
-                    return Err(child.error(
-                        "A section with 'exact' routing mode cannot contain nested sections.",
-                    ));
+                    return Err(child.error(format!(
+                        "A section with '{mode}' routing mode cannot contain nested sections."
+                    )));
                 }
             }
         }
 
-        let full_path = if base_path.path() == "/" && !path_segment.starts_with('/') {
-            format!("/{}", path_segment)
-        } else if base_path.path() == "/" {
-            path_segment.to_string()
+        let path = if let RouteMatcher::Regex(_) = next_matcher {
+            // The pattern is matched against the whole path, the route keeps
+            // the path of the enclosing section.
+            base_path
         } else {
-            format!(
-                "{}/{}",
-                base_path.path().trim_end_matches('/'),
-                path_segment.trim_start_matches('/')
-            )
+            let full_path = if base_path.path() == "/" && !path_segment.starts_with('/') {
+                format!("/{}", path_segment)
+            } else if base_path.path() == "/" {
+                path_segment.to_string()
+            } else {
+                format!(
+                    "{}/{}",
+                    base_path.path().trim_end_matches('/'),
+                    path_segment.trim_start_matches('/')
+                )
+            };
+
+            full_path
+                .parse()
+                .map_err(|err| ctx.error(format!("Bad path: {full_path}, error: {err}")))?
         };
 
-        let path = full_path
-            .parse()
-            .map_err(|err| ctx.error(format!("Bad path: {full_path}, error: {err}")))?;
-
         let block_ctx = ctx.enter_block()?;
 
-        Ok(ConnectorsLeaf::Section(self.process_nodes_recursive(
+        let mut children = self.process_nodes_recursive(
             block_ctx,
             anonymous_definitions,
             path,
             next_matcher,
-        )?))
+            true,
+        )?;
+
+        if let Some(methods) = methods {
+            children.push(ConnectorsLeaf::Methods(methods));
+        }
+
+        if let Some(flag) = flag {
+            children.push(ConnectorsLeaf::Flag(flag));
+        }
+
+        Ok(ConnectorsLeaf::Section(children))
     }
 
     fn extract_static_response(
@@ -250,50 +519,151 @@ impl<'a> ConnectorsSection<'a> {
         parent_matcher: RouteMatcher,
     ) -> miette::Result<ConnectorsLeaf> {
         if ctx.has_children_block()? {
-            ctx.validate(&[Rule::NoArgs])?;
+            // A target URI next to the block is the most common mistake here.
+            if ctx.args()?.iter().any(|arg| arg.name().is_none()) {
+                ctx.validate(&[Rule::NoArgs])?;
+            }
+            ctx.validate(&[Rule::OnlyKeysTyped(&[
+                ("allow-websocket", PrimitiveType::Bool),
+                ("pool-min-idle", PrimitiveType::Integer),
+                ("pool-warm-path", PrimitiveType::String),
+            ])])?;
+
+            let allow_websocket = ctx.opt_prop("allow-websocket")?.as_bool()?.unwrap_or(true);
+            let (pool_min_idle, pool_warm_path) = parse_pool_warm(&ctx, &parent_matcher)?;
 
             let block_ctx = ctx.enter_block()?;
             let mut block = BlockParser::new(block_ctx)?;
 
-            let servers = block.required_repeated("server", |ctx| {
+            let servers = block.repeated("server", |ctx| {
                 ctx.validate(&[
                     Rule::NoChildren,
                     Rule::ExactArgs(1),
-                    Rule::OnlyKeysTyped(&[("weight", PrimitiveType::Integer)]),
+                    Rule::OnlyKeysTyped(&[
+                        ("weight", PrimitiveType::Integer),
+                        ("vnodes", PrimitiveType::Integer),
+                    ]),
                 ])?;
 
                 let address = ctx.first()?.parse_as::<SocketAddr>()?;
 
                 let weight = ctx.opt_prop("weight")?.as_usize()?.unwrap_or(1);
+                if weight == 0 {
+                    return Err(ctx.error("'weight' must be greater than zero"));
+                }
+
+                let vnodes = ctx.opt_prop("vnodes")?.as_usize()?;
+                if vnodes == Some(0) {
+                    return Err(ctx.error("'vnodes' must be greater than zero"));
+                }
 
-                Ok(UpstreamServer { address, weight })
+                Ok(UpstreamServer {
+                    address,
+                    weight,
+                    vnodes,
+                })
             })?;
 
             let tls_sni = block.optional("tls-sni", |ctx| {
-                ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
-                ctx.first()?.as_str()
+                ctx.validate(&[
+                    Rule::NoChildren,
+                    Rule::ExactArgs(1),
+                    Rule::OnlyKeysTyped(&[("allow", PrimitiveType::String)]),
+                ])?;
+
+                let sni = ascii_sni(ctx.first()?.as_str()?);
+                let allow = ctx.opt_prop("allow")?.as_str()?;
+                let host_sni = parse_host_sni(&ctx, &sni, allow, "allow")?;
+
+                Ok((sni, host_sni))
             })?;
+            let (tls_sni, host_sni) = tls_sni.unzip();
 
             let proto_str = block.optional("proto", |ctx| {
                 ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
                 ctx.first()?.as_str()
             })?;
 
+            let h2c = block.optional("h2c", |ctx| {
+                ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
+                ctx.first()?.as_bool()
+            })?;
+
+            let read_your_writes =
+                block.optional("read-your-writes", |ctx| self.parse_read_your_writes(ctx))?;
+
+            let split_by_method = block.optional("split-by-method", |ctx| {
+                ctx.validate(&[Rule::ReqChildren, Rule::NoArgs])?;
+
+                if read_your_writes.is_some() {
+                    return Err(ctx.error(
+                        "'split-by-method' cannot be combined with 'read-your-writes', which already sends writes to the primary",
+                    ));
+                }
+
+                let mut block = BlockParser::new(ctx.enter_block()?)?;
+                let primary = block.required_repeated("primary", |ctx| self.parse_primary(ctx))?;
+                block.exhaust()?;
+
+                Ok(MethodSplitConfig { primary })
+            })?;
+
+            let backend_override =
+                block.optional("backend-override", |ctx| self.parse_backend_override(ctx))?;
+
+            let split = block.optional("split", |ctx| self.parse_traffic_split(ctx))?;
+
             block.exhaust()?;
 
-            let (tls, sni, alpn) =
-                self.resolve_proto_settings(&ctx, proto_str.as_deref(), tls_sni.as_deref())?;
+            let servers = match &split {
+                None if servers.is_empty() => {
+                    return Err(
+                        ctx.error("Missing required directive 'server' (at least one expected)")
+                    )
+                }
+                None => servers,
+                Some(_) if !servers.is_empty() => return Err(ctx.error(
+                    "'split' takes the servers of its groups, 'server' cannot be used next to it",
+                )),
+                // The groups pick their servers, the other features see all of them.
+                Some(split) => split
+                    .groups
+                    .iter()
+                    .flat_map(|group| &group.servers)
+                    .map(|address| UpstreamServer {
+                        address: *address,
+                        weight: 1,
+                        vnodes: None,
+                    })
+                    .collect(),
+            };
+
+            let (_tls, sni, alpn) = self.resolve_proto_settings(
+                &ctx,
+                proto_str.as_deref(),
+                tls_sni.as_deref(),
+                h2c.unwrap_or(false),
+            )?;
 
             let final_sni = if sni.is_empty() { None } else { Some(sni) };
 
             Ok(ConnectorsLeaf::Upstream(UpstreamConfig::MultiServer(
                 MultiServerUpstreamConfig {
                     servers,
+                    read_your_writes,
+                    split_by_method,
+                    split,
+                    backend_override,
                     tls_sni: final_sni,
+                    host_sni: host_sni.flatten(),
                     alpn,
                     prefix_path: base_path,
                     target_path: PathAndQuery::from_static("/"),
                     matcher: parent_matcher,
+                    allow_websocket,
+                    grpc: proto_str.as_deref() == Some("grpc"),
+                    pool_min_idle,
+                    pool_warm_path,
                 },
             )))
         } else {
@@ -301,7 +671,16 @@ impl<'a> ConnectorsSection<'a> {
                 Rule::ExactArgs(1),
                 Rule::OnlyKeysTyped(&[
                     ("tls-sni", PrimitiveType::String),
+                    ("sni-allow", PrimitiveType::String),
                     ("proto", PrimitiveType::String),
+                    ("h2c", PrimitiveType::Bool),
+                    ("allow-websocket", PrimitiveType::Bool),
+                    ("pool-min-idle", PrimitiveType::Integer),
+                    ("pool-warm-path", PrimitiveType::String),
+                    ("set-header", PrimitiveType::String),
+                    ("remove-header", PrimitiveType::String),
+                    ("strip-prefix", PrimitiveType::Bool),
+                    ("forwarded-for", PrimitiveType::Bool),
                 ]),
             ])?;
 
@@ -312,23 +691,44 @@ impl<'a> ConnectorsSection<'a> {
                 .and_then(|host| host.as_str().parse::<SocketAddr>().ok())
                 .ok_or(ctx.error("Not a valid socket address"))?;
 
-            let [sni_opt, proto_opt] = ctx.props(["tls-sni", "proto"])?;
+            let [sni_opt, sni_allow_opt, proto_opt, h2c_opt, websocket_opt] =
+                ctx.props(["tls-sni", "sni-allow", "proto", "h2c", "allow-websocket"])?;
+
+            let proto = proto_opt.as_str()?;
+
+            let tls_sni = sni_opt.as_str()?.map(ascii_sni);
+
+            let host_sni = match &tls_sni {
+                Some(sni) => parse_host_sni(&ctx, sni, sni_allow_opt.as_str()?, "sni-allow")?,
+                None if sni_allow_opt.as_str()?.is_some() => {
+                    return Err(ctx.error("'sni-allow' requires a 'tls-sni' with '{host}'"))
+                }
+                None => None,
+            };
 
             let (tls, sni, alpn) = self.resolve_proto_settings(
                 &ctx,
-                proto_opt.as_str()?.as_deref(),
-                sni_opt.as_str()?.as_deref(),
+                proto.as_deref(),
+                tls_sni.as_deref(),
+                h2c_opt.as_bool()?.unwrap_or(false),
             )?;
 
+            let (pool_min_idle, pool_warm_path) = parse_pool_warm(&ctx, &parent_matcher)?;
+
             Ok(ConnectorsLeaf::Upstream(UpstreamConfig::Service(
                 HttpPeerConfig {
                     peer_address: host_addr,
                     alpn,
                     sni,
+                    host_sni,
                     tls,
                     prefix_path: base_path,
                     target_path: uri.path().parse().unwrap_or(PathAndQuery::from_static("/")),
                     matcher: parent_matcher,
+                    allow_websocket: websocket_opt.as_bool()?.unwrap_or(true),
+                    grpc: proto.as_deref() == Some("grpc"),
+                    pool_min_idle,
+                    pool_warm_path,
                 },
             )))
         }
@@ -346,931 +746,3527 @@ impl<'a> ConnectorsSection<'a> {
         block_parser!(block_ctx,
             selection_data: optional("selection") => |ctx| self.parse_selection(ctx, anonymous_definitions),
 
-            health_opt: optional("health-check") => |ctx| {
-                ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
+            health_opt: optional("health-check") => |ctx| self.parse_health_check(ctx),
 
-                match ctx.arg(0)?.as_str()?.as_str() {
-                    "None" => Ok(HealthCheckKind::None),
-                    val => Err(ctx.error(format!("Unknown health-check kind: '{val}'"))),
-                }
-            },
+            discovery_opt: optional("discovery") => |ctx| self.parse_discovery(ctx),
 
-            discovery_opt: optional("discovery") => |ctx| {
-                ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
+            circuit_breaker: optional("circuit-breaker") => |ctx| self.parse_circuit_breaker(ctx),
 
-                match ctx.arg(0)?.as_str()?.as_str() {
-                    "Static" => Ok(DiscoveryKind::Static),
-                    val => Err(ctx.error(format!("Unknown discovery kind: '{val}'"))),
-                }
-            }
+            when_down: optional("when-down") => |ctx| self.parse_when_down(ctx)
         );
 
-        let (selection, template) = selection_data.unwrap_or((SelectionKind::RoundRobin, None));
+        let (selection, template, sticky) =
+            selection_data.unwrap_or((SelectionKind::RoundRobin, None, None));
         let health_checks = health_opt.unwrap_or(HealthCheckKind::None);
-        let discovery = discovery_opt.unwrap_or(DiscoveryKind::Static);
+
+        if selection == SelectionKind::KetamaHashing && health_checks != HealthCheckKind::None {
+            return Err(ctx.error("'Ketama' selection does not support health checks"));
+        }
+        let discovery = discovery_opt.unwrap_or_default();
+
+        if selection == SelectionKind::KetamaHashing && !discovery.is_static() {
+            return Err(ctx.error("'Ketama' selection only supports 'Static' discovery"));
+        }
 
         Ok(ConnectorsLeaf::LoadBalance(UpstreamOptions {
             selection,
             template,
             health_checks,
             discovery,
+            circuit_breaker,
+            sticky,
+            when_down,
         }))
     }
 
-    fn parse_selection(
-        &self,
-        ctx: ParseContext<'_>,
-        anonymous_definitions: &mut DefinitionsTable,
-    ) -> miette::Result<(SelectionKind, Option<KeyTemplateConfig>)> {
+    /// `discovery "NAME" key="value"...`, the name is checked against the
+    /// registered backends once the route is built.
+    fn parse_discovery(&self, ctx: ParseContext<'_>) -> miette::Result<DiscoveryConfig> {
+        ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1)])?;
+
+        let name = ctx.arg(0)?.as_str()?;
+        let mut settings = BTreeMap::new();
+
+        for entry in ctx.args()? {
+            let Some(key) = entry.name().map(|key| key.value()) else {
+                continue;
+            };
+            let Some(value) = entry.value().as_string() else {
+                return Err(ctx.error_with_span(
+                    format!("discovery property '{key}' must be a string"),
+                    entry.span(),
+                ));
+            };
+            settings.insert(key.to_string(), value.to_string());
+        }
+
+        if name == DiscoveryConfig::STATIC && !settings.is_empty() {
+            return Err(ctx.error("'Static' discovery takes no properties"));
+        }
+
+        Ok(DiscoveryConfig { name, settings })
+    }
+
+    fn parse_when_down(&self, ctx: ParseContext<'_>) -> miette::Result<WhenDownConfig> {
         ctx.validate(&[
-            Rule::ExactArgs(1),
-            Rule::OnlyKeysTyped(&[("use-key-profile", PrimitiveType::String)]),
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("code", PrimitiveType::Integer),
+                ("response", PrimitiveType::String),
+                ("retry-after-secs", PrimitiveType::Integer),
+            ]),
         ])?;
 
-        let selection_kind = ctx.first()?.parse_as::<SelectionKind>()?;
+        let [code, response, retry_after] = ctx.props(["code", "response", "retry-after-secs"])?;
 
-        let profile_ref = ctx.opt_prop("use-key-profile")?.as_str()?;
+        Ok(WhenDownConfig {
+            status: code
+                .parse_as::<StatusCode>()?
+                .unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
+            body: response.as_str()?.unwrap_or_default(),
+            retry_after: retry_after
+                .as_usize()?
+                .map(|secs| Duration::from_secs(secs as u64)),
+        })
+    }
 
-        let has_block = ctx.has_children_block()?;
+    fn parse_circuit_breaker(&self, ctx: ParseContext<'_>) -> miette::Result<CircuitBreakerConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("consecutive-failures", PrimitiveType::Integer),
+                ("error-rate-percent", PrimitiveType::Integer),
+                ("window-secs", PrimitiveType::Integer),
+                ("min-requests", PrimitiveType::Integer),
+                ("cool-down-secs", PrimitiveType::Integer),
+            ]),
+        ])?;
 
-        self.validate_selection(
-            ctx,
-            anonymous_definitions,
-            selection_kind,
-            profile_ref,
-            has_block,
-        )
-    }
+        let [consecutive, error_rate, window, min_requests, cool_down] = ctx.props([
+            "consecutive-failures",
+            "error-rate-percent",
+            "window-secs",
+            "min-requests",
+            "cool-down-secs",
+        ])?;
 
-    fn resolve_proto_settings(
-        &self,
-        ctx: &ParseContext<'_>,
-        proto: Option<&str>,
-        tls_sni: Option<&str>,
-    ) -> miette::Result<(bool, String, ALPN)> {
-        let alpn = match proto {
-            Some(p) => parse_proto_value(p).map_err(|msg| ctx.error(msg))?,
-            None => None,
-        };
+        let consecutive_failures = consecutive.as_usize()?.unwrap_or(5);
+        let error_rate = error_rate.as_usize()?;
+        let window = window.as_usize()?.unwrap_or(10);
+        let cool_down = cool_down.as_usize()?.unwrap_or(30);
 
-        match (alpn, tls_sni) {
-            (None, None) | (Some(ALPN::H1), None) => Ok((false, String::new(), ALPN::H1)),
-            (None, Some(sni)) => Ok((true, sni.to_string(), ALPN::H2H1)),
-            (Some(_), None) => Err(ctx.error("'tls-sni' is required for HTTP2 support")),
-            (Some(p), Some(sni)) => Ok((true, sni.to_string(), p)),
+        if consecutive_failures == 0 {
+            return Err(ctx.error("'consecutive-failures' must be greater than zero"));
+        }
+        if error_rate.is_some_and(|rate| rate == 0 || rate > 100) {
+            return Err(ctx.error("'error-rate-percent' must be between 1 and 100"));
         }
+        if window == 0 || cool_down == 0 {
+            return Err(ctx.error("'window-secs' and 'cool-down-secs' must be greater than zero"));
+        }
+
+        Ok(CircuitBreakerConfig {
+            consecutive_failures,
+            error_rate,
+            window: Duration::from_secs(window as u64),
+            min_requests: min_requests.as_usize()?.unwrap_or(20),
+            cool_down: Duration::from_secs(cool_down as u64),
+        })
     }
 
-    fn validate_selection(
-        &self,
-        ctx: ParseContext<'_>,
-        anonymous_definitions: &mut DefinitionsTable,
-        selection_kind: SelectionKind,
-        profile_ref: Option<String>,
-        has_block: bool,
-    ) -> Result<(SelectionKind, Option<KeyTemplateConfig>), miette::Error> {
-        let key_source = match (profile_ref, has_block) {
-            (Some(_), true) => {
-                return Err(ctx.error(
-                    "Cannot use 'use-key-profile' and define an inline block simultaneously",
-                ));
-            }
-            (Some(name), false) => {
-                if let Some(template) = self.table.get_key_templates().get(&name) {
-                    Some(template.clone())
-                } else {
-                    return Err(ctx.error(format!("Key profile '{}' not found", name)));
-                }
-            }
-            (None, true) => {
-                let template = KeyProfileParser.parse(ctx.enter_block()?)?;
+    fn parse_health_check(&self, ctx: ParseContext<'_>) -> miette::Result<HealthCheckKind> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::ExactArgs(1),
+            Rule::OnlyKeysTyped(&[
+                ("service", PrimitiveType::String),
+                ("interval-secs", PrimitiveType::Integer),
+            ]),
+        ])?;
 
-                let id = self.anon_counter.fetch_add(1, Ordering::Relaxed);
-                let generated_name = format!("__anon_key_{id}");
+        let [service, interval] = ctx.props(["service", "interval-secs"])?;
 
-                anonymous_definitions.insert_key_profile(generated_name, template.clone());
-                Some(template)
+        match ctx.arg(0)?.as_str()?.as_str() {
+            "None" if service.is_some() || interval.is_some() => {
+                Err(ctx.error("'None' health checks take no options"))
             }
-            (None, false) => None,
-        };
+            "None" => Ok(HealthCheckKind::None),
+            "Grpc" => {
+                let interval = interval.as_usize()?.unwrap_or(5) as u64;
 
-        match selection_kind {
-            SelectionKind::KetamaHashing | SelectionKind::FvnHash => {
-                if key_source.is_none() {
-                    return Err(ctx.error(format!(
-                        "Selection kind '{kind}' requires a key source. Use 'use-key-profile' or provide an inline key configuration block.",
-                        kind = ctx.first()?.as_str()?
-                    )));
+                if interval == 0 {
+                    return Err(ctx.error("'interval-secs' must be greater than zero"));
                 }
+
+                Ok(HealthCheckKind::Grpc {
+                    service: service.as_str()?.unwrap_or_default(),
+                    interval: Duration::from_secs(interval),
+                })
             }
-            _ => {}
+            val => Err(ctx.error(format!("Unknown health-check kind: '{val}'"))),
         }
-
-        Ok((selection_kind, key_source))
     }
-}
 
-/// Recursive function to flatten the node tree
-fn flatten_nodes(
-    nodes: Vec<ConnectorsLeaf>,
-    parent_chains: &[Modificator], // Chains inherited from parents
-) -> miette::Result<Vec<UpstreamContextConfig>> {
-    let mut results = Vec::new();
+    fn extract_shaping(&self, ctx: ParseContext<'_>) -> miette::Result<ConnectorsLeaf> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("bytes-per-sec", PrimitiveType::Integer),
+                ("latency-ms", PrimitiveType::Integer),
+                ("jitter-ms", PrimitiveType::Integer),
+            ]),
+        ])?;
 
-    // 1. Build context for the current level
-    let mut current_chains = parent_chains.to_vec();
-    let mut local_lb_options: Option<UpstreamOptions> = None;
+        let [rate, latency, jitter] = ctx.props(["bytes-per-sec", "latency-ms", "jitter-ms"])?;
 
-    // Separate configuration (chains, lb) from structure (upstreams, sections)
-    let mut structure = Vec::new();
+        let bytes_per_sec = rate.as_usize()?.map(|rate| rate as u64);
+        let latency = latency.as_usize()?;
+        let jitter = jitter.as_usize()?;
 
-    for node in nodes {
-        match node {
-            ConnectorsLeaf::Modificator(m) => current_chains.push(m),
-            ConnectorsLeaf::LoadBalance(lb) => local_lb_options = Some(lb),
-            s => structure.push(s),
+        if bytes_per_sec.is_none() && latency.is_none() && jitter.is_none() {
+            return Err(ctx.error(
+                "'shaping' requires at least one of 'bytes-per-sec', 'latency-ms' or 'jitter-ms'",
+            ));
         }
-    }
 
-    // 2. Traverse structural elements
-    for node in structure {
-        match node {
-            ConnectorsLeaf::Upstream(up) => {
-                // VALIDATION: Check compatibility if LoadBalance is present
-                if local_lb_options.is_some() && !matches!(up, UpstreamConfig::MultiServer(_)) {
-                    return Err(miette::miette!(
-                        "The 'load-balance' directive can only be applied to 'proxy' blocks with multiple servers (MultiServer). Found incompatible upstream (Static or Single Service) in the same section."
-                    ));
-                }
+        if bytes_per_sec == Some(0) {
+            return Err(ctx.error("'bytes-per-sec' must be greater than zero"));
+        }
 
-                results.push(UpstreamContextConfig {
-                    upstream: up,
-                    chains: current_chains.clone(),
-                    lb_options: local_lb_options.clone(),
-                });
+        Ok(ConnectorsLeaf::Shaping(ShapingConfig {
+            bytes_per_sec,
+            latency: Duration::from_millis(latency.unwrap_or(0) as u64),
+            jitter: Duration::from_millis(jitter.unwrap_or(0) as u64),
+        }))
+    }
+
+    fn extract_timeouts(&self, ctx: ParseContext<'_>) -> miette::Result<ConnectorsLeaf> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("connect-ms", PrimitiveType::Integer),
+                ("read-ms", PrimitiveType::Integer),
+                ("write-ms", PrimitiveType::Integer),
+                ("total-ms", PrimitiveType::Integer),
+                ("body", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let keys = ["connect-ms", "read-ms", "write-ms", "total-ms"];
+        let values = ctx.props(keys)?;
+        let mut durations = [None; 4];
+
+        for ((key, value), duration) in keys.iter().zip(values).zip(&mut durations) {
+            *duration = match value.as_usize()? {
+                Some(0) => return Err(ctx.error(format!("'{key}' must be greater than zero"))),
+                ms => ms.map(|ms| Duration::from_millis(ms as u64)),
+            };
+        }
+
+        if durations.iter().all(Option::is_none) {
+            return Err(ctx.error(
+                "'timeouts' requires at least one of 'connect-ms', 'read-ms', 'write-ms' or 'total-ms'",
+            ));
+        }
+
+        let [connect, read, write, total] = durations;
+
+        let body = ctx
+            .opt_prop("body")?
+            .as_str()?
+            .unwrap_or_else(|| "Gateway Timeout".to_string());
+
+        Ok(ConnectorsLeaf::Timeouts(TimeoutsConfig {
+            connect,
+            read,
+            write,
+            total,
+            body,
+        }))
+    }
+
+    fn extract_retry(&self, ctx: ParseContext<'_>) -> miette::Result<ConnectorsLeaf> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("attempts", PrimitiveType::Integer),
+                ("backoff-ms", PrimitiveType::Integer),
+                ("on", PrimitiveType::String),
+                ("methods", PrimitiveType::String),
+                ("budget-percent", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let [attempts, backoff, on, methods, budget] =
+            ctx.props(["attempts", "backoff-ms", "on", "methods", "budget-percent"])?;
+
+        let attempts = attempts.as_usize()?.unwrap_or(3);
+        if attempts < 2 {
+            return Err(ctx.error("'attempts' must be at least 2, the first try included"));
+        }
+
+        let budget_percent = budget.as_usize()?.unwrap_or(20);
+        if budget_percent == 0 {
+            return Err(ctx.error("'budget-percent' must be greater than zero"));
+        }
+
+        let mut on_server_error = false;
+        let mut on_connect_error = false;
+
+        for condition in on
+            .as_str()?
+            .unwrap_or_else(|| "connect-error".to_string())
+            .split(',')
+            .map(str::trim)
+        {
+            match condition {
+                "5xx" => on_server_error = true,
+                "connect-error" => on_connect_error = true,
+                other => {
+                    return Err(ctx.error(format!(
+                        "unknown retry condition '{other}', expected '5xx' or 'connect-error'"
+                    )))
+                }
+            }
+        }
+
+        let methods = parse_methods(
+            &ctx,
+            &methods
+                .as_str()?
+                .unwrap_or_else(|| "GET,HEAD,OPTIONS".to_string()),
+        )?;
+
+        Ok(ConnectorsLeaf::Retry(RetryConfig {
+            attempts,
+            backoff: Duration::from_millis(backoff.as_usize()?.unwrap_or(50) as u64),
+            on_server_error,
+            on_connect_error,
+            methods,
+            budget_percent,
+        }))
+    }
+
+    fn extract_max_request_body(&self, ctx: ParseContext<'_>) -> miette::Result<ConnectorsLeaf> {
+        ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
+
+        let max = ctx.first()?.as_usize()?;
+        if max == 0 {
+            return Err(ctx.error("'max-request-body-bytes' must be greater than 0"));
+        }
+
+        Ok(ConnectorsLeaf::MaxRequestBodyBytes(max))
+    }
+
+    fn extract_buffering(&self, ctx: ParseContext<'_>) -> miette::Result<ConnectorsLeaf> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::ExactArgs(1),
+            Rule::OnlyKeysTyped(&[
+                ("max-bytes", PrimitiveType::Integer),
+                ("low-watermark", PrimitiveType::Integer),
+                ("upstream-recv-bytes", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let enabled = ctx.first()?.as_bool()?;
+        let max_bytes = ctx.opt_prop("max-bytes")?.as_usize()?;
+        let low_watermark = ctx.opt_prop("low-watermark")?.as_usize()?;
+        let upstream_recv_bytes = ctx.opt_prop("upstream-recv-bytes")?.as_usize()?;
+
+        if !enabled {
+            if max_bytes.is_some() || low_watermark.is_some() || upstream_recv_bytes.is_some() {
+                return Err(ctx.error("options only apply to 'buffering #true'"));
+            }
+            return Ok(ConnectorsLeaf::Buffering(None));
+        }
+
+        let max_bytes = max_bytes.unwrap_or(DEFAULT_BUFFERING_MAX_BYTES);
+        if max_bytes == 0 {
+            return Err(ctx.error("'max-bytes' must be greater than 0"));
+        }
+
+        if low_watermark.is_some_and(|low| low >= max_bytes) {
+            return Err(ctx.error("'low-watermark' must be below 'max-bytes'"));
+        }
+
+        if upstream_recv_bytes == Some(0) {
+            return Err(ctx.error("'upstream-recv-bytes' must be greater than 0"));
+        }
+
+        Ok(ConnectorsLeaf::Buffering(Some(BufferingConfig {
+            max_bytes,
+            low_watermark,
+            upstream_recv_bytes,
+        })))
+    }
+
+    fn extract_record_har(&self, ctx: ParseContext<'_>) -> miette::Result<ConnectorsLeaf> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("dir", PrimitiveType::String),
+                ("one-in", PrimitiveType::Integer),
+                ("max-body-bytes", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let [dir, one_in, max_body_bytes] = ctx.props(["dir", "one-in", "max-body-bytes"])?;
+
+        let Some(dir) = dir.as_str()?.filter(|dir| !dir.is_empty()) else {
+            return Err(ctx.error("'record-har' needs the 'dir' to write to"));
+        };
+
+        let one_in = one_in.as_usize()?.unwrap_or(1);
+        if one_in == 0 {
+            return Err(ctx.error("'one-in' must be greater than 0"));
+        }
+
+        Ok(ConnectorsLeaf::RecordHar(HarRecordingConfig {
+            dir: dir.into(),
+            one_in,
+            max_body_bytes: max_body_bytes
+                .as_usize()?
+                .unwrap_or(DEFAULT_HAR_MAX_BODY_BYTES),
+        }))
+    }
+
+    fn extract_cache(&self, ctx: ParseContext<'_>) -> miette::Result<ConnectorsLeaf> {
+        ctx.validate(&[
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("storage", PrimitiveType::String),
+                ("max-size-mb", PrimitiveType::Integer),
+                ("path", PrimitiveType::String),
+                ("max-size-gb", PrimitiveType::Integer),
+                ("ttl-secs", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let [storage, max_size_mb, path, max_size_gb, ttl_secs] =
+            ctx.props(["storage", "max-size-mb", "path", "max-size-gb", "ttl-secs"])?;
+
+        let storage = match storage.as_str()?.as_deref().unwrap_or("memory") {
+            "memory" => {
+                if path.is_some() || max_size_gb.is_some() {
+                    return Err(ctx.error("'path' and 'max-size-gb' only apply to a disk cache"));
+                }
+
+                let max_size_mb = max_size_mb.as_usize()?.unwrap_or(DEFAULT_CACHE_MAX_SIZE_MB);
+                if max_size_mb == 0 {
+                    return Err(ctx.error("'max-size-mb' must be greater than 0"));
+                }
+
+                CacheStorage::Memory {
+                    max_bytes: max_size_mb * 1024 * 1024,
+                }
+            }
+            "disk" => {
+                if max_size_mb.is_some() {
+                    return Err(ctx.error("a disk cache is sized with 'max-size-gb'"));
+                }
+
+                let Some(path) = path.as_str()?.filter(|path| !path.is_empty()) else {
+                    return Err(ctx.error("a disk cache needs the 'path' to keep its files in"));
+                };
+
+                let max_size_gb = max_size_gb.as_usize()?.unwrap_or(DEFAULT_CACHE_MAX_SIZE_GB);
+                if max_size_gb == 0 {
+                    return Err(ctx.error("'max-size-gb' must be greater than 0"));
+                }
+
+                CacheStorage::Disk {
+                    path: path.into(),
+                    max_bytes: max_size_gb as u64 * 1024 * 1024 * 1024,
+                }
+            }
+            other => {
+                return Err(ctx.error(format!(
+                    "unknown cache storage '{other}', expected 'memory' or 'disk'"
+                )))
+            }
+        };
+
+        let ttl_secs = ttl_secs.as_usize()?.unwrap_or(DEFAULT_CACHE_TTL_SECS);
+        if ttl_secs == 0 {
+            return Err(ctx.error("'ttl-secs' must be greater than 0"));
+        }
+
+        let vary = if ctx.has_children_block()? {
+            block_parser!(
+                ctx.enter_block()?,
+                vary: optional("vary") => |ctx| VarySection.parse_node(ctx)
+            );
+            vary.unwrap_or_default()
+        } else {
+            VaryConfig::default()
+        };
+
+        Ok(ConnectorsLeaf::Cache(CacheConfig {
+            storage,
+            ttl: Duration::from_secs(ttl_secs as u64),
+            vary,
+        }))
+    }
+
+    fn extract_compression(&self, ctx: ParseContext<'_>) -> miette::Result<ConnectorsLeaf> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("algorithms", PrimitiveType::String),
+                ("min-size", PrimitiveType::Integer),
+                ("content-types", PrimitiveType::String),
+                ("dictionary", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let [algorithms, min_size, content_types, dictionary] =
+            ctx.props(["algorithms", "min-size", "content-types", "dictionary"])?;
+
+        let algorithms = algorithms
+            .as_str()?
+            .unwrap_or_else(|| "zstd,br,gzip".to_string())
+            .split(',')
+            .map(str::trim)
+            .map(|token| {
+                CompressionAlgorithm::from_token(&token.to_ascii_lowercase()).ok_or_else(|| {
+                    ctx.error(format!(
+                        "unknown compression algorithm '{token}', expected 'gzip', 'br' or 'zstd'"
+                    ))
+                })
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+
+        let content_types: Vec<String> = content_types
+            .as_str()?
+            .unwrap_or_else(|| DEFAULT_COMPRESSIBLE_TYPES.to_string())
+            .split(',')
+            .map(|media_type| media_type.trim().to_ascii_lowercase())
+            .filter(|media_type| !media_type.is_empty())
+            .collect();
+
+        if content_types.is_empty() {
+            return Err(ctx.error("'content-types' must list at least one media type"));
+        }
+
+        // The name ends up in a file name and a `Content-Encoding` token.
+        let dictionary = dictionary.as_str()?;
+        if dictionary.as_deref().is_some_and(|name| {
+            name.is_empty()
+                || !name
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+        }) {
+            return Err(ctx.error("'dictionary' must be lowercase letters, digits, '-' or '_'"));
+        }
+
+        Ok(ConnectorsLeaf::Compression(CompressionConfig {
+            algorithms,
+            min_size: min_size.as_usize()?.unwrap_or(1024),
+            content_types,
+            dictionary,
+        }))
+    }
+
+    fn parse_read_your_writes(
+        &self,
+        ctx: ParseContext<'_>,
+    ) -> miette::Result<ReadYourWritesConfig> {
+        ctx.validate(&[
+            Rule::ReqChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("ttl-secs", PrimitiveType::Integer),
+                ("cookie", PrimitiveType::String),
+                ("header", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let [ttl, cookie, header] = ctx.props(["ttl-secs", "cookie", "header"])?;
+
+        let ttl = ttl.as_usize()?.unwrap_or(5);
+        if ttl == 0 {
+            return Err(ctx.error("'ttl-secs' must be greater than zero"));
+        }
+
+        let cookie = cookie
+            .as_str()?
+            .unwrap_or_else(|| "motya-primary".to_string());
+        if cookie.is_empty()
+            || !cookie
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+        {
+            return Err(ctx.error(format!("Invalid cookie name '{cookie}'")));
+        }
+
+        let header = header.as_str()?.map(|h| h.to_lowercase());
+
+        let mut block = BlockParser::new(ctx.enter_block()?)?;
+        let primary = block.required_repeated("primary", |ctx| self.parse_primary(ctx))?;
+        block.exhaust()?;
+
+        Ok(ReadYourWritesConfig {
+            primary,
+            ttl: Duration::from_secs(ttl as u64),
+            cookie,
+            header,
+        })
+    }
+
+    fn parse_backend_override(
+        &self,
+        ctx: ParseContext<'_>,
+    ) -> miette::Result<BackendOverrideConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("header", PrimitiveType::String),
+                ("trusted", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let header = ctx
+            .opt_prop("header")?
+            .as_str()?
+            .map(|h| h.to_lowercase())
+            .unwrap_or_else(|| "x-motya-backend".to_string());
+
+        let trusted = ctx
+            .prop("trusted")?
+            .as_str()?
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                range
+                    .parse::<IpCidr>()
+                    .map_err(|_| ctx.error(format!("'{range}' is not a valid CIDR range")))
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+
+        if trusted.is_empty() {
+            return Err(ctx.error("'trusted' must list at least one CIDR range"));
+        }
+
+        Ok(BackendOverrideConfig { header, trusted })
+    }
+
+    /// `split sticky=#true override-header="X-Canary" { group "NAME" weight=N {
+    /// server "ADDR"; }; }`, the weights are percentages of the requests.
+    fn parse_traffic_split(&self, ctx: ParseContext<'_>) -> miette::Result<TrafficSplitConfig> {
+        ctx.validate(&[
+            Rule::ReqChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("sticky", PrimitiveType::Bool),
+                ("override-header", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let sticky = ctx.opt_prop("sticky")?.as_bool()?.unwrap_or(false);
+        let override_header = ctx
+            .opt_prop("override-header")?
+            .as_str()?
+            .map(|header| header.to_lowercase());
+
+        let mut names = HashSet::new();
+        let mut block = BlockParser::new(ctx.enter_block()?)?;
+        let groups = block.required_repeated("group", |ctx| {
+            ctx.validate(&[
+                Rule::ReqChildren,
+                Rule::ExactArgs(1),
+                Rule::OnlyKeysTyped(&[("weight", PrimitiveType::Integer)]),
+            ])?;
+
+            let name = ctx.first()?.as_str()?;
+            if !names.insert(name.clone()) {
+                return Err(ctx.error(format!("Duplicate split group '{name}'")));
+            }
+
+            let weight = ctx.prop("weight")?.as_usize()?;
+
+            let mut block = BlockParser::new(ctx.enter_block()?)?;
+            let servers = block.required_repeated("server", |ctx| {
+                ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
+                ctx.first()?.parse_as::<SocketAddr>()
+            })?;
+            block.exhaust()?;
+
+            Ok(SplitGroupConfig {
+                name,
+                weight,
+                servers,
+            })
+        })?;
+        block.exhaust()?;
+
+        let total: usize = groups.iter().map(|group| group.weight).sum();
+        if total != 100 {
+            return Err(ctx.error(format!(
+                "The weights of the 'split' groups must add up to 100, found {total}"
+            )));
+        }
+
+        Ok(TrafficSplitConfig {
+            groups,
+            sticky,
+            override_header,
+        })
+    }
+
+    fn parse_primary(&self, ctx: ParseContext<'_>) -> miette::Result<SocketAddr> {
+        ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1)])?;
+        ctx.first()?.parse_as::<SocketAddr>()
+    }
+
+    fn parse_selection(
+        &self,
+        ctx: ParseContext<'_>,
+        anonymous_definitions: &mut DefinitionsTable,
+    ) -> miette::Result<(
+        SelectionKind,
+        Option<KeyTemplateConfig>,
+        Option<StickyCookieConfig>,
+    )> {
+        ctx.validate(&[
+            Rule::ExactArgs(1),
+            Rule::OnlyKeysTyped(&[
+                ("use-key-profile", PrimitiveType::String),
+                ("cookie", PrimitiveType::String),
+                ("ttl-secs", PrimitiveType::Integer),
+                ("fallback", PrimitiveType::String),
+                ("key", PrimitiveType::String),
+                ("header-name", PrimitiveType::String),
+                ("param", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let [cookie, ttl, fallback] = ctx.props(["cookie", "ttl-secs", "fallback"])?;
+
+        // `CookieSticky` picks the backend of the affinity cookie and leaves
+        // everything else to its `fallback` selection.
+        let (selection_kind, sticky) = if ctx.first()?.as_str()? == "CookieSticky" {
+            let sticky = StickyCookieConfig {
+                cookie: cookie.as_str()?.unwrap_or_else(|| "motya-affinity".into()),
+                ttl: Duration::from_secs(ttl.as_usize()?.unwrap_or(60 * 60) as u64),
+            };
+
+            if sticky.cookie.is_empty() {
+                return Err(ctx.error("'cookie' must not be empty"));
+            }
+            if sticky.ttl.is_zero() {
+                return Err(ctx.error("'ttl-secs' must be greater than zero"));
+            }
+
+            let fallback = fallback
+                .parse_as::<SelectionKind>()?
+                .unwrap_or(SelectionKind::RoundRobin);
+
+            (fallback, Some(sticky))
+        } else {
+            if cookie.is_some() || ttl.is_some() || fallback.is_some() {
+                return Err(ctx.error(
+                    "'cookie', 'ttl-secs' and 'fallback' only apply to 'CookieSticky' selection",
+                ));
+            }
+
+            (ctx.first()?.parse_as::<SelectionKind>()?, None)
+        };
+
+        let [key, header_name, param] = ctx.props(["key", "header-name", "param"])?;
+        let (header_name, param) = (header_name.as_str()?, param.as_str()?);
+
+        let inline_key = match key.as_str()? {
+            Some(key) => Some(
+                selector_template(&key, header_name.as_deref(), param.as_deref())
+                    .map_err(|e| ctx.error(e))?,
+            ),
+            None if header_name.is_some() => {
+                return Err(ctx.error("'header-name' requires 'key=\"Header\"'"))
+            }
+            None if param.is_some() => {
+                return Err(ctx.error("'param' requires 'key=\"Cookie\"' or 'key=\"QueryParam\"'"))
+            }
+            None => None,
+        };
+
+        let profile_ref = ctx.opt_prop("use-key-profile")?.as_str()?;
+
+        let has_block = ctx.has_children_block()?;
+
+        let (selection_kind, template) = self.validate_selection(
+            ctx,
+            anonymous_definitions,
+            selection_kind,
+            inline_key,
+            profile_ref,
+            has_block,
+        )?;
+
+        Ok((selection_kind, template, sticky))
+    }
+
+    fn resolve_proto_settings(
+        &self,
+        ctx: &ParseContext<'_>,
+        proto: Option<&str>,
+        tls_sni: Option<&str>,
+        h2c: bool,
+    ) -> miette::Result<(bool, String, ALPN)> {
+        // gRPC needs HTTP/2, over TLS when `tls-sni` is set and h2c otherwise.
+        if proto == Some("grpc") {
+            return match (tls_sni, h2c) {
+                (Some(_), true) => Err(ctx.error("'h2c' is cleartext HTTP/2, remove 'tls-sni'")),
+                (Some(sni), false) => Ok((true, sni.to_string(), ALPN::H2)),
+                (None, _) => Ok((false, String::new(), ALPN::H2)),
+            };
+        }
+
+        let alpn = match proto {
+            Some(p) => parse_proto_value(p).map_err(|msg| ctx.error(msg))?,
+            None => None,
+        };
+
+        if h2c {
+            return match (alpn, tls_sni) {
+                (_, Some(_)) => Err(ctx.error("'h2c' is cleartext HTTP/2, remove 'tls-sni'")),
+                (None | Some(ALPN::H2), None) => Ok((false, String::new(), ALPN::H2)),
+                (Some(_), None) => Err(ctx.error("'h2c' can only be combined with 'h2-only'")),
+            };
+        }
+
+        match (alpn, tls_sni) {
+            (None, None) | (Some(ALPN::H1), None) => Ok((false, String::new(), ALPN::H1)),
+            (None, Some(sni)) => Ok((true, sni.to_string(), ALPN::H2H1)),
+            (Some(_), None) => Err(ctx.error("'tls-sni' is required for HTTP2 support")),
+            (Some(p), Some(sni)) => Ok((true, sni.to_string(), p)),
+        }
+    }
+
+    fn validate_selection(
+        &self,
+        ctx: ParseContext<'_>,
+        anonymous_definitions: &mut DefinitionsTable,
+        selection_kind: SelectionKind,
+        inline_key: Option<KeyTemplateConfig>,
+        profile_ref: Option<String>,
+        has_block: bool,
+    ) -> Result<(SelectionKind, Option<KeyTemplateConfig>), miette::Error> {
+        if inline_key.is_some() {
+            if profile_ref.is_some() || has_block {
+                return Err(ctx.error("Cannot combine 'key' with 'use-key-profile' or a key block"));
+            }
+
+            return Ok((selection_kind, inline_key));
+        }
+
+        let key_source = match (profile_ref, has_block) {
+            (Some(_), true) => {
+                return Err(ctx.error(
+                    "Cannot use 'use-key-profile' and define an inline block simultaneously",
+                ));
+            }
+            (Some(name), false) => {
+                if let Some(template) = self.table.get_key_templates().get(&name) {
+                    Some(template.clone())
+                } else {
+                    return Err(ctx.error(format!("Key profile '{}' not found", name)));
+                }
+            }
+            (None, true) => {
+                let template = KeyProfileParser.parse(ctx.enter_block()?)?;
+
+                let id = self.anon_counter.fetch_add(1, Ordering::Relaxed);
+                let generated_name = format!("__anon_key_{id}");
+
+                anonymous_definitions.insert_key_profile(generated_name, template.clone());
+                Some(template)
+            }
+            (None, false) => None,
+        };
+
+        match selection_kind {
+            SelectionKind::KetamaHashing | SelectionKind::FvnHash => {
+                if key_source.is_none() {
+                    return Err(ctx.error(format!(
+                        "Selection kind '{kind}' requires a key source. Use 'key', 'use-key-profile' or provide an inline key configuration block.",
+                        kind = ctx.first()?.as_str()?
+                    )));
+                }
+            }
+            _ => {}
+        }
+
+        Ok((selection_kind, key_source))
+    }
+}
+
+/// Recursive function to flatten the node tree
+#[allow(clippy::too_many_arguments)]
+fn flatten_nodes(
+    nodes: Vec<ConnectorsLeaf>,
+    parent_chains: &[Modificator], // Chains inherited from parents
+    parent_shaping: Option<&ShapingConfig>, // Shaping inherited from parents
+    parent_timeouts: Option<&TimeoutsConfig>, // Timeouts inherited from parents
+    parent_retry: Option<&RetryConfig>, // Retry policy inherited from parents
+    parent_compression: Option<&CompressionConfig>, // Compression inherited from parents
+    parent_body_limit: Option<usize>, // Request body limit inherited from parents
+    parent_buffering: Option<&BufferingConfig>, // Response buffering inherited from parents
+    parent_record_har: Option<&HarRecordingConfig>, // HAR recording inherited from parents
+    parent_cache: Option<&CacheConfig>, // Response cache inherited from parents
+    parent_methods: Option<&[Method]>, // Method constraint inherited from parents
+    parent_flag: Option<&FlagCondition>, // Feature flag gate inherited from parents
+    parent_active: Option<ActiveWindow>, // Activation window inherited from parents
+) -> miette::Result<Vec<UpstreamContextConfig>> {
+    let mut results = Vec::new();
+
+    // 1. Build context for the current level
+    let mut current_chains = parent_chains.to_vec();
+    let mut local_lb_options: Option<UpstreamOptions> = None;
+    let mut current_shaping = parent_shaping.cloned();
+    let mut current_timeouts = parent_timeouts.cloned();
+    let mut current_retry = parent_retry.cloned();
+    let mut current_compression = parent_compression.cloned();
+    let mut current_body_limit = parent_body_limit;
+    let mut current_buffering = parent_buffering.cloned();
+    let mut current_record_har = parent_record_har.cloned();
+    let mut current_cache = parent_cache.cloned();
+    let mut current_methods = parent_methods.map(<[Method]>::to_vec);
+    let mut current_flag = parent_flag.cloned();
+    let mut current_active = parent_active;
+
+    // Separate configuration (chains, lb) from structure (upstreams, sections)
+    let mut structure = Vec::new();
+
+    for node in nodes {
+        match node {
+            ConnectorsLeaf::Modificator(m) => current_chains.push(m),
+            ConnectorsLeaf::LoadBalance(lb) => local_lb_options = Some(lb),
+            ConnectorsLeaf::Shaping(shaping) => current_shaping = Some(shaping),
+            ConnectorsLeaf::Timeouts(timeouts) => current_timeouts = Some(timeouts),
+            ConnectorsLeaf::Retry(retry) => current_retry = Some(retry),
+            ConnectorsLeaf::Compression(compression) => current_compression = Some(compression),
+            ConnectorsLeaf::MaxRequestBodyBytes(max) => current_body_limit = Some(max),
+            ConnectorsLeaf::Buffering(buffering) => current_buffering = buffering,
+            ConnectorsLeaf::RecordHar(record_har) => current_record_har = Some(record_har),
+            ConnectorsLeaf::Cache(cache) => current_cache = Some(cache),
+            ConnectorsLeaf::Methods(methods) => current_methods = Some(methods),
+            ConnectorsLeaf::Flag(flag) => current_flag = Some(flag),
+            ConnectorsLeaf::ActiveBetween(window) => current_active = Some(window),
+            s => structure.push(s),
+        }
+    }
+
+    // 2. Traverse structural elements
+    for node in structure {
+        match node {
+            ConnectorsLeaf::Upstream(up) => {
+                // VALIDATION: Check compatibility if LoadBalance is present
+                if local_lb_options.is_some() && !matches!(up, UpstreamConfig::MultiServer(_)) {
+                    return Err(miette::miette!(
+                        "The 'load-balance' directive can only be applied to 'proxy' blocks with multiple servers (MultiServer). Found incompatible upstream (Static or Single Service) in the same section."
+                    ));
+                }
+
+                if let UpstreamConfig::MultiServer(m) = &up {
+                    if m.split.is_some() && local_lb_options.is_some() {
+                        return Err(miette::miette!(
+                            "'load-balance' cannot be applied to a 'proxy' with a 'split', its groups pick their servers"
+                        ));
+                    }
+
+                    let ketama = local_lb_options
+                        .as_ref()
+                        .is_some_and(|lb| lb.selection == SelectionKind::KetamaHashing);
+
+                    if !ketama && m.servers.iter().any(|s| s.vnodes.is_some()) {
+                        return Err(miette::miette!(
+                            "'vnodes' only applies to servers balanced with 'Ketama' selection"
+                        ));
+                    }
+                }
+
+                results.push(UpstreamContextConfig {
+                    upstream: up,
+                    chains: current_chains.clone(),
+                    lb_options: local_lb_options.clone(),
+                    shaping: current_shaping.clone(),
+                    timeouts: current_timeouts.clone(),
+                    retry: current_retry.clone(),
+                    compression: current_compression.clone(),
+                    max_request_body_bytes: current_body_limit,
+                    buffering: current_buffering.clone(),
+                    record_har: current_record_har.clone(),
+                    cache: current_cache.clone(),
+                    methods: current_methods.clone(),
+                    flag: current_flag.clone(),
+                    active: current_active,
+                });
+            }
+            ConnectorsLeaf::Section(children) => {
+                let children_flat = flatten_nodes(
+                    children,
+                    &current_chains,
+                    current_shaping.as_ref(),
+                    current_timeouts.as_ref(),
+                    current_retry.as_ref(),
+                    current_compression.as_ref(),
+                    current_body_limit,
+                    current_buffering.as_ref(),
+                    current_record_har.as_ref(),
+                    current_cache.as_ref(),
+                    current_methods.as_deref(),
+                    current_flag.as_ref(),
+                    current_active,
+                )?;
+                results.extend(children_flat);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(results)
+}
+
+/// The `pool-min-idle` of a `proxy`, `0` when it is not set, and the
+/// `pool-warm-path` it requires. The connections are opened with requests
+/// routed by the route path, which a regex route lacks, and the backends
+/// receive them for `pool-warm-path`.
+fn parse_pool_warm(
+    ctx: &ParseContext<'_>,
+    matcher: &RouteMatcher,
+) -> miette::Result<(usize, Option<PathAndQuery>)> {
+    let pool_min_idle = ctx.opt_prop("pool-min-idle")?.as_usize()?.unwrap_or(0);
+    let pool_warm_path = ctx.opt_prop("pool-warm-path")?.as_str()?;
+
+    if pool_min_idle == 0 {
+        if pool_warm_path.is_some() {
+            return Err(ctx.error("'pool-warm-path' requires 'pool-min-idle'"));
+        }
+        return Ok((0, None));
+    }
+
+    if matches!(matcher, RouteMatcher::Regex(_)) {
+        return Err(ctx.error("'pool-min-idle' is not supported in a 'regex' section"));
+    }
+
+    let Some(path) = pool_warm_path else {
+        return Err(ctx.error(
+            "'pool-min-idle' requires a 'pool-warm-path', the backends receive HEAD requests for it",
+        ));
+    };
+
+    match path.parse::<PathAndQuery>() {
+        Ok(path) if path.as_str().starts_with('/') => Ok((pool_min_idle, Some(path))),
+        _ => Err(ctx.error("'pool-warm-path' must be an absolute path")),
+    }
+}
+
+/// Parses the `flag` of a section, `"NAME"` or `"!NAME"` for its negation.
+fn parse_flag(ctx: &ParseContext<'_>, flag: &str) -> miette::Result<FlagCondition> {
+    let (name, enabled) = match flag.strip_prefix('!') {
+        Some(name) => (name, false),
+        None => (flag, true),
+    };
+
+    if name.is_empty() {
+        return Err(ctx.error("'flag' needs a flag name, e.g. \"new-router\" or \"!new-router\""));
+    }
+
+    Ok(FlagCondition {
+        name: name.to_string(),
+        enabled,
+    })
+}
+
+/// Parses a comma separated list of HTTP methods, e.g. `"GET,HEAD"`.
+fn parse_methods(ctx: &ParseContext<'_>, list: &str) -> miette::Result<Vec<Method>> {
+    list.split(',')
+        .map(|method| {
+            method
+                .trim()
+                .parse::<Method>()
+                .map_err(|_| ctx.error(format!("invalid HTTP method '{}'", method.trim())))
+        })
+        .collect()
+}
+
+/// Lowercases a host name, or a `*.` wildcard matching a single label.
+pub(crate) fn parse_host_pattern(ctx: &ParseContext<'_>, host: &str) -> miette::Result<String> {
+    let host = host.trim();
+    let (wildcard, name) = match host.strip_prefix("*.") {
+        Some(name) => ("*.", name),
+        None => ("", host),
+    };
+
+    match ascii_host(name) {
+        Some(name) => Ok(format!("{wildcard}{name}")),
+        None => Err(ctx.error(format!("'{host}' is not a host name or a '*.' wildcard"))),
+    }
+}
+
+/// A fixed `tls-sni` in its punycode form, templates are left alone.
+fn ascii_sni(sni: String) -> String {
+    if sni.contains("{host}") {
+        return sni;
+    }
+
+    ascii_host(&sni).unwrap_or(sni)
+}
+
+/// Checks a `tls-sni` value, returning its template when it holds `{host}`.
+/// Such a template needs the hosts it may take, comma separated in the
+/// `allow_key` property.
+fn parse_host_sni(
+    ctx: &ParseContext<'_>,
+    sni: &str,
+    allow: Option<String>,
+    allow_key: &str,
+) -> miette::Result<Option<HostSniConfig>> {
+    if sni.replace("{host}", "").contains(['{', '}']) {
+        return Err(ctx.error("'tls-sni' only supports the '{host}' placeholder"));
+    }
+
+    if !sni.contains("{host}") {
+        return match allow {
+            Some(_) => Err(ctx.error(format!(
+                "'{allow_key}' requires a 'tls-sni' with '{{host}}'"
+            ))),
+            None => Ok(None),
+        };
+    }
+
+    let Some(allow) = allow else {
+        return Err(ctx.error(format!(
+            "A 'tls-sni' with '{{host}}' needs the hosts it may take, e.g. {allow_key}=\"*.example.com\""
+        )));
+    };
+
+    let allow = allow
+        .split(',')
+        .map(|host| parse_host_pattern(ctx, host))
+        .collect::<miette::Result<Vec<_>>>()?;
+
+    Ok(Some(HostSniConfig {
+        template: sni.to_string(),
+        allow,
+    }))
+}
+
+fn parse_proto_value(value: &str) -> Result<Option<ALPN>, String> {
+    match value {
+        "h1-only" => Ok(Some(ALPN::H1)),
+        "h2-only" => Ok(Some(ALPN::H2)),
+        "h1-or-h2" => {
+            tracing::warn!("accepting 'h1-or-h2' as meaning 'h2-or-h1'");
+            Ok(Some(ALPN::H2H1))
+        }
+        "h2-or-h1" => Ok(Some(ALPN::H2H1)),
+        other => Err(format!(
+            "'proto' should be one of 'h1-only', 'h2-only', 'h2-or-h1' or 'grpc', found '{other}'"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kdl::KdlDocument;
+    use crate::kdl::parser::block::BlockParser;
+    use crate::kdl::definitions::DefinitionsSection;
+    use crate::assert_err_contains;
+    use crate::kdl::parser::ctx::Current;
+    use crate::common_types::active_window::parse_timestamp;
+
+    /// Helper to parse config when no external definitions are needed
+    fn parse_config(input: &str) -> miette::Result<Connectors> {
+        let doc: KdlDocument = input.parse().unwrap();
+        let table = DefinitionsTable::default();
+        
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let mut block = BlockParser::new(ctx)?;
+
+        block.required("connectors", |ctx| {
+            ConnectorsSection::new(&table).parse_node(ctx)
+        })
+    }
+
+    /// Helper to parse config with definitions
+    fn parse_config_with_defs(defs_input: &str, conn_input: &str) -> miette::Result<Connectors> {
+        // 1. Parse definitions
+        let defs_doc: KdlDocument = defs_input.parse().unwrap();
+        let defs_ctx = ParseContext::new(&defs_doc, Current::Document(&defs_doc), "test");
+        let mut defs_block = BlockParser::new(defs_ctx)?;
+        
+        let table = defs_block.required("definitions", |ctx| {
+            DefinitionsSection::new(&DefinitionsTable::default()).parse_node(ctx)
+        })?;
+
+        // 2. Parse connectors
+        let conn_doc: KdlDocument = conn_input.parse().unwrap();
+        let conn_ctx = ParseContext::new(&conn_doc, Current::Document(&conn_doc), "test");
+        let mut conn_block = BlockParser::new(conn_ctx)?;
+        
+        conn_block.required("connectors", |ctx| {
+            ConnectorsSection::new(&table).parse_node(ctx)
+        })
+    }
+
+    const LOAD_BALANCE_BASIC: &str = r#"
+    connectors {
+        load-balance {
+            selection "RoundRobin"
+            health-check "None"
+            discovery "Static"
+        }
+        proxy {
+            server "127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_load_balance_basic() {
+        let connectors = parse_config(LOAD_BALANCE_BASIC).expect("Parsing failed");
+
+        assert_eq!(connectors.upstreams.len(), 1);
+        let upstream = &connectors.upstreams[0];
+        let lb_options = upstream.lb_options.clone().unwrap();
+        assert_eq!(lb_options.selection, SelectionKind::RoundRobin);
+        assert_eq!(lb_options.health_checks, HealthCheckKind::None);
+        assert_eq!(lb_options.discovery, DiscoveryConfig::default());
+        assert!(lb_options.template.is_none());
+    }
+
+    #[test]
+    fn test_registered_discovery() {
+        let discovery = |load_balance: &str| {
+            parse_config(&format!(
+                r#"
+                connectors {{
+                    load-balance {{
+                        {load_balance}
+                    }}
+                    proxy {{
+                        server "10.0.0.1:80"
+                    }}
+                }}
+                "#
+            ))
+            .map(|connectors| {
+                connectors.upstreams[0]
+                    .lb_options
+                    .clone()
+                    .unwrap()
+                    .discovery
+            })
+        };
+
+        assert_eq!(
+            discovery(r#"discovery "acme.dns" name="api.internal" port="8080""#).unwrap(),
+            DiscoveryConfig {
+                name: "acme.dns".into(),
+                settings: BTreeMap::from([
+                    ("name".to_string(), "api.internal".to_string()),
+                    ("port".to_string(), "8080".to_string()),
+                ]),
+            }
+        );
+
+        assert!(discovery(r#"discovery "acme.dns" port=8080"#).is_err());
+        assert!(discovery(r#"discovery "Static" name="api.internal""#).is_err());
+        assert!(discovery(
+            r#"selection "Ketama" key="UriPath"
+               discovery "acme.dns""#
+        )
+        .is_err());
+    }
+
+    const LOAD_BALANCE_ALL_SELECTION_TYPES: &str = r#"
+    connectors {
+        load-balance {
+            selection "Random"
+        }
+        proxy {
+            server "127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_load_balance_random_selection() {
+        let connectors = parse_config(LOAD_BALANCE_ALL_SELECTION_TYPES).expect("Parsing failed");
+
+        let upstream = &connectors.upstreams[0];
+        let lb_options = upstream.lb_options.clone().unwrap();
+        assert_eq!(lb_options.selection, SelectionKind::Random);
+    }
+
+    const LOAD_BALANCE_FNV_HASH: &str = r#"
+    connectors {
+        load-balance {
+            selection "FNV" use-key-profile="ip-profile"
+        }
+        proxy { 
+            server "127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_load_balance_fnv_hash_with_key_profile() {
+        // Should fail because "ip-profile" is not defined in the empty table
+        let result = parse_config(LOAD_BALANCE_FNV_HASH);
+        assert!(result.is_err());
+    }
+
+    const DEFS_KEY_PROFILE: &str = r#"
+    definitions {
+        key-profiles {
+            template "ip-profile" {
+                key "amogus"
+            }
+        }
+    }
+    "#;
+
+    const LOAD_BALANCE_WITH_KEY_PROFILE: &str = r#"
+    connectors {
+        load-balance {
+            selection "FNV" use-key-profile="ip-profile"
+        }
+        proxy {
+            server "127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_load_balance_with_defined_key_profile() {
+        let connectors = parse_config_with_defs(DEFS_KEY_PROFILE, LOAD_BALANCE_WITH_KEY_PROFILE)
+            .expect("Parsing failed");
+
+        let upstream = &connectors.upstreams[0];
+        let lb_options = upstream.lb_options.clone().unwrap();
+        assert_eq!(lb_options.selection, SelectionKind::FvnHash);
+        assert!(lb_options.template.is_some());
+
+        let template = lb_options.template.as_ref().unwrap();
+        assert_eq!(template.source, "amogus".to_string());
+    }
+
+    const LOAD_BALANCE_HASH_WITHOUT_KEY_SOURCE: &str = r#"
+    connectors {
+        load-balance {
+            selection "Ketama"
+        }
+        proxy {
+            server "127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_error_hash_selection_without_key_source() {
+        let result = parse_config(LOAD_BALANCE_HASH_WITHOUT_KEY_SOURCE);
+        assert!(result.is_err());
+
+        let err_msg = result.unwrap_err().help().unwrap().to_string();
+        assert!(err_msg.contains("requires a key source"));
+    }
+
+    const LOAD_BALANCE_DUPLICATE: &str = r#"
+    connectors {
+        load-balance {
+            selection "RoundRobin"
+        }
+        load-balance {
+            selection "Random"
+        }
+        proxy {
+            server "127.0.0.1:8081"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_error_duplicate_load_balance() {
+        let result = parse_config(LOAD_BALANCE_DUPLICATE);
+        assert!(result.is_err());
+
+        let err_msg = result.unwrap_err().help().unwrap().to_string();
+        assert_err_contains!(err_msg, "Directive 'load-balance' cannot be repeated");
+    }
+
+    const MULTI_SERVER_PROXY_CONFIG: &str = r#"
+    connectors {
+        proxy { 
+            tls-sni "onevariable.com" 
+            proto "h2-or-h1"
+            server "91.107.223.4:443" 
+            server "91.107.223.5:443" 
+            server "91.107.223.6:443"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_multi_server_proxy_parsing() {
+        let connectors = parse_config(MULTI_SERVER_PROXY_CONFIG).expect("Parsing failed");
+
+        assert_eq!(
+            connectors.upstreams.len(),
+            1,
+            "Should have one UpstreamConfig"
+        );
+
+        let upstream_config = &connectors.upstreams[0];
+
+        let UpstreamConfig::MultiServer(upstream) = &upstream_config.upstream else {
+            panic!(
+                "Expected MultiServer upstream, got {:?}",
+                upstream_config.upstream
+            );
+        };
+
+        assert_eq!(
+            upstream.servers.len(),
+            3,
+            "Should parse 3 server directives"
+        );
+        assert_eq!(
+            upstream.tls_sni.as_deref(),
+            Some("onevariable.com"),
+            "Should parse tls-sni"
+        );
+        assert_eq!(upstream.alpn, ALPN::H2H1, "Should parse proto h2-or-h1");
+        assert_eq!(upstream.prefix_path, "/", "Should have default prefix path");
+        assert_eq!(
+            upstream.matcher,
+            RouteMatcher::Exact,
+            "Should have default matcher"
+        );
+
+        assert_eq!(
+            upstream.servers[0].address,
+            "91.107.223.4:443".parse().unwrap()
+        );
+        assert_eq!(
+            upstream.servers[2].address,
+            "91.107.223.6:443".parse().unwrap()
+        );
+    }
+
+    const SINGLE_SERVER_BLOCK_PROXY_CONFIG: &str = r#"
+    connectors {
+        proxy {
+            server "127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_single_server_block_proxy_parsing() {
+        let connectors = parse_config(SINGLE_SERVER_BLOCK_PROXY_CONFIG).expect("Parsing failed");
+
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream (even for a single server in block)");
+        };
+
+        assert_eq!(upstream.servers.len(), 1, "Should parse 1 server directive");
+        assert!(
+            upstream.tls_sni.is_none(),
+            "tls-sni should be None by default"
+        );
+        assert_eq!(
+            upstream.alpn,
+            ALPN::H1,
+            "ALPN should be H1 by default (no sni/proto)"
+        );
+        assert_eq!(
+            upstream.servers[0].address,
+            "127.0.0.1:8080".parse().unwrap()
+        );
+    }
+
+    const ERROR_DUPLICATE_PROTO: &str = r#"
+    connectors {
+        proxy {
+            proto "h2-or-h1"
+            proto "h1-only"
+            server "127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_error_on_duplicate_options() {
+        let result = parse_config(ERROR_DUPLICATE_PROTO);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().help().unwrap().to_string();
+        crate::assert_err_contains!(err_msg, "Directive 'proto' cannot be repeated");
+    }
+
+    const ERROR_NO_SERVERS: &str = r#"
+    connectors {
+        proxy {
+            tls-sni "a.com"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_error_on_no_servers() {
+        let result = parse_config(ERROR_NO_SERVERS);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().help().unwrap().to_string();
+        crate::assert_err_contains!(
+            err_msg,
+            "Missing required directive 'server' (at least one expected)"
+        );
+    }
+
+    const ERROR_BLOCK_WITH_ARG: &str = r#"
+    connectors {
+        proxy "http://invalid.com" {
+            server "127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_error_block_with_top_arg() {
+        let result = parse_config(ERROR_BLOCK_WITH_ARG);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().help().unwrap().to_string();
+        crate::assert_err_contains!(err_msg, "Directive 'proxy' cannot have arguments");
+    }
+
+    const INVALID_STRICT_NESTING: &str = r#"
+    connectors {
+        section "/api" as="exact" {
+            // This should fail because parent is exact
+            section "/v1" {
+                return code=200 response="fail"
+            }
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_strict_section_cannot_have_children() {
+        let result = parse_config(INVALID_STRICT_NESTING);
+        let err_msg = result.err().unwrap().help().unwrap().to_string();
+        crate::assert_err_contains!(
+            err_msg,
+            "A section with 'exact' routing mode cannot contain nested sections"
+        );
+    }
+
+    #[test]
+    fn test_regex_section() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                section "/api" as="prefix" {
+                    section "^/api/v[0-9]+/users/(?<id>[0-9]+)$" {
+                        proxy "http://127.0.0.1:8000"
+                    }
+                    section "/v[0-9]+/" as="regex" {
+                        proxy "http://127.0.0.1:8001"
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let UpstreamConfig::Service(users) = &connectors.upstreams[0].upstream else {
+            unreachable!()
+        };
+        assert_eq!(users.prefix_path, "/api");
+        let RouteMatcher::Regex(pattern) = &users.matcher else {
+            panic!("expected a regex route, got {:?}", users.matcher)
+        };
+        assert!(pattern.regex().is_match("/api/v2/users/42"));
+
+        let UpstreamConfig::Service(versioned) = &connectors.upstreams[1].upstream else {
+            unreachable!()
+        };
+        assert_eq!(
+            versioned.matcher,
+            RouteMatcher::Regex(RoutePattern::new("/v[0-9]+/").unwrap())
+        );
+
+        let cases = [
+            (
+                r#"section "^/(unclosed" { proxy "http://127.0.0.1:8000"; }"#,
+                "Invalid route regex",
+            ),
+            (
+                r#"section "^/api" { section "/v1" { proxy "http://127.0.0.1:8000"; }; }"#,
+                "A section with 'regex' routing mode cannot contain nested sections",
+            ),
+            (
+                r#"section "^/health$" { return code=200 response="OK"; }"#,
+                "'return' is not supported in a 'regex' section",
+            ),
+        ];
+
+        for (section, expected) in cases {
+            let err = parse_config(&format!("connectors {{ {section} }}")).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_method_section() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                section "/api" as="prefix" methods="POST,PUT,DELETE" {
+                    proxy "http://10.0.0.1:80"
+                    section "/reports" {
+                        proxy "http://10.0.0.2:80"
+                    }
+                }
+                section "/api" {
+                    proxy "http://10.0.0.3:80"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let writes = Some(vec![Method::POST, Method::PUT, Method::DELETE]);
+        assert_eq!(connectors.upstreams[0].methods, writes);
+        // Nested sections inherit the constraint.
+        assert_eq!(connectors.upstreams[1].methods, writes);
+        assert_eq!(connectors.upstreams[2].methods, None);
+
+        let err = parse_config(
+            r#"connectors { section "/api" methods="GET,," { proxy "http://10.0.0.1:80"; } }"#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "invalid HTTP method ''");
+    }
+
+    #[test]
+    fn test_flag_section() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                section "/api" as="prefix" flag="new-router" {
+                    proxy "http://10.0.0.1:80"
+                    section "/reports" {
+                        proxy "http://10.0.0.2:80"
+                    }
+                }
+                section "/api" flag="!new-router" {
+                    proxy "http://10.0.0.3:80"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let flag = |enabled| {
+            Some(FlagCondition {
+                name: "new-router".to_string(),
+                enabled,
+            })
+        };
+        assert_eq!(connectors.upstreams[0].flag, flag(true));
+        // Nested sections inherit the gate.
+        assert_eq!(connectors.upstreams[1].flag, flag(true));
+        assert_eq!(connectors.upstreams[2].flag, flag(false));
+
+        let err = parse_config(
+            r#"connectors { section "/api" flag="!" { proxy "http://10.0.0.1:80"; } }"#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "'flag' needs a flag name");
+    }
+
+    #[test]
+    fn test_active_between() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                section "/" as="prefix" {
+                    active-between "2024-12-01T00:00Z" "2024-12-26T00:00Z"
+                    return code=503 response="Down for maintenance"
+                    section "/shop" {
+                        proxy "http://10.0.0.1:80"
+                    }
+                }
+                section "/" {
+                    proxy "http://10.0.0.2:80"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let window = Some(ActiveWindow {
+            start: parse_timestamp("2024-12-01T00:00Z").unwrap(),
+            end: parse_timestamp("2024-12-26T00:00Z").unwrap(),
+        });
+        assert_eq!(connectors.upstreams[0].active, window);
+        // Nested sections inherit the window.
+        assert_eq!(connectors.upstreams[1].active, window);
+        assert_eq!(connectors.upstreams[2].active, None);
+
+        let cases = [
+            (
+                r#"active-between "2024-12-26T00:00Z" "2024-12-01T00:00Z""#,
+                "must end after it starts",
+            ),
+            (
+                r#"active-between "2024-12-01" "2024-12-26T00:00Z""#,
+                "is not a timestamp",
+            ),
+        ];
+
+        for (active, expected) in cases {
+            let err = parse_config(&format!(
+                r#"connectors {{ section "/" {{ {active}; proxy "http://10.0.0.1:80"; }} }}"#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_host_sni() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                section "/a" {
+                    proxy "https://10.0.0.1:443" tls-sni="{host}" sni-allow="*.example.com, Example.com"
+                }
+                section "/b" {
+                    proxy {
+                        server "10.0.0.2:443"
+                        tls-sni "origin-{host}" allow="*.tenants.example.com"
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let UpstreamConfig::Service(single) = &connectors.upstreams[0].upstream else {
+            unreachable!()
+        };
+        assert!(single.tls);
+        assert_eq!(
+            single.host_sni,
+            Some(HostSniConfig {
+                template: "{host}".into(),
+                allow: vec!["*.example.com".into(), "example.com".into()],
+            })
+        );
+
+        let UpstreamConfig::MultiServer(multi) = &connectors.upstreams[1].upstream else {
+            unreachable!()
+        };
+        assert_eq!(
+            multi.host_sni.as_ref().map(|sni| sni.template.as_str()),
+            Some("origin-{host}")
+        );
+
+        let cases = [
+            (
+                r#"proxy "https://10.0.0.1:443" tls-sni="{host}""#,
+                "needs the hosts it may take",
+            ),
+            (
+                r#"proxy "https://10.0.0.1:443" tls-sni="{tenant}" sni-allow="example.com""#,
+                "only supports the '{host}' placeholder",
+            ),
+            (
+                r#"proxy "https://10.0.0.1:443" tls-sni="example.com" sni-allow="example.com""#,
+                "'sni-allow' requires a 'tls-sni' with '{host}'",
+            ),
+            (
+                r#"proxy "https://10.0.0.1:443" tls-sni="{host}" sni-allow="*.ex ample.com""#,
+                "is not a host name",
+            ),
+        ];
+
+        for (proxy, expected) in cases {
+            let err = parse_config(&format!("connectors {{ {proxy}; }}")).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_idn_hosts() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                section "/a" {
+                    proxy "https://10.0.0.1:443" tls-sni="Bücher.example"
+                }
+                section "/b" {
+                    proxy "https://10.0.0.2:443" tls-sni="{host}" sni-allow="*.Bücher.example"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let UpstreamConfig::Service(fixed) = &connectors.upstreams[0].upstream else {
+            unreachable!()
+        };
+        assert_eq!(fixed.sni, "xn--bcher-kva.example");
+
+        let UpstreamConfig::Service(templated) = &connectors.upstreams[1].upstream else {
+            unreachable!()
+        };
+        assert_eq!(
+            templated.host_sni.as_ref().map(|sni| sni.allow.clone()),
+            Some(vec!["*.xn--bcher-kva.example".to_string()])
+        );
+    }
+
+    const VALID_STRICT_CONFIG: &str = r#"
+    connectors {
+        section "/api" as="exact" {
+            // Non-section children are allowed
+            return code=200 response="OK"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_strict_section_allowed_directives() {
+        let result = parse_config(VALID_STRICT_CONFIG);
+        assert!(result.is_ok());
+    }
+
+    const DEFS_ARGS: &str = r#"
+    definitions {
+        modifiers {
+            chain-filters "defined_with_args" {
+                filter name="set-header" key="X-Region" value="EU"
+                filter name="log-request"
+            }
+        }
+    }
+    "#;
+
+    const ARGS_PARSING_CONN: &str = r#"
+    connectors {
+        use-chain {
+            filter name="rate-limit" rps="100" burst="20"
+        }
+        use-chain "defined_with_args"
+        proxy "http://127.0.0.1:8080"
+    }
+    "#;
+
+    #[test]
+    fn test_filter_args_parsing() {
+        let connectors =
+            parse_config_with_defs(DEFS_ARGS, ARGS_PARSING_CONN).expect("Parsing failed");
+        let upstream = &connectors.upstreams[0];
+
+        assert_eq!(upstream.chains.len(), 2);
+
+        match &upstream.chains[0] {
+            Modificator::Chain(named_chain) => {
+                assert!(named_chain.name.contains("__anon_"), "Should be anonymous");
+                let filter = &named_chain.chain.filters[0];
+
+                assert_eq!(filter.name, "rate-limit");
+                assert_eq!(filter.args.len(), 2);
+                assert_eq!(filter.args.get("rps").map(|s| s.as_str()), Some("100"));
+                assert_eq!(filter.args.get("burst").map(|s| s.as_str()), Some("20"));
+            }
+        }
+
+        match &upstream.chains[1] {
+            Modificator::Chain(named_chain) => {
+                assert_eq!(named_chain.name, "defined_with_args");
+                assert_eq!(named_chain.chain.filters.len(), 2);
+
+                let filter1 = &named_chain.chain.filters[0];
+                assert_eq!(filter1.name, "set-header");
+                assert_eq!(filter1.args.len(), 2);
+                assert_eq!(
+                    filter1.args.get("key").map(|s| s.as_str()),
+                    Some("X-Region")
+                );
+                assert_eq!(filter1.args.get("value").map(|s| s.as_str()), Some("EU"));
+
+                let filter2 = &named_chain.chain.filters[1];
+                assert_eq!(filter2.name, "log-request");
+                assert!(filter2.args.is_empty(), "Args should be empty");
+            }
+        }
+    }
+
+    const ANONYMOUS_CHAIN_TEST: &str = r#"
+    connectors {
+        section "/anon" {
+            use-chain {
+                filter name="logger" level="debug"
+            }
+            proxy "http://127.0.0.1:8080"
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_anonymous_chain() {
+        let connectors = parse_config(ANONYMOUS_CHAIN_TEST).expect("Parsing failed");
+        let upstream = &connectors.upstreams[0];
+
+        match &upstream.chains[0] {
+            Modificator::Chain(named_chain) => {
+                assert_eq!(named_chain.chain.filters.len(), 1);
+                let filter = &named_chain.chain.filters[0];
+                assert_eq!(filter.name, "logger");
+                let level_arg = filter.args.get("level").expect("Argument 'level' missing");
+                assert_eq!(level_arg, "debug");
+                assert_eq!(filter.args.len(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inline_filters() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                use-chain {
+                    filter name="logger"
+                }
+                section "/api/" {
+                    proxy "http://127.0.0.1:8080" set-header="X-A: B" strip-prefix=#true forwarded-for=#true
+                }
+                section "/plain" {
+                    proxy "http://127.0.0.1:8081" strip-prefix=#false
+                }
             }
-            ConnectorsLeaf::Section(children) => {
-                let children_flat = flatten_nodes(children, &current_chains)?;
-                results.extend(children_flat);
+            "#,
+        )
+        .expect("Parsing failed");
+
+        let Modificator::Chain(inline) = &connectors.upstreams[0].chains[1];
+        assert!(inline.name.starts_with("__anon_"));
+        assert!(connectors
+            .anonymous_definitions
+            .get_chain_by_name(&inline.name)
+            .is_some());
+
+        let filters = &inline.chain.filters;
+        assert_eq!(filters.len(), 3);
+        assert_eq!(filters[0].name, "motya.request.upsert-header");
+        assert_eq!(filters[0].args["key"], "X-A");
+        assert_eq!(filters[0].args["value"], "B");
+        assert_eq!(filters[1].name, "motya.request.strip-prefix");
+        assert_eq!(filters[1].args["prefix"], "/api");
+        assert_eq!(filters[2].name, "motya.request.forwarded-for");
+        assert!(filters[2].args.is_empty());
+
+        assert_eq!(connectors.upstreams[1].chains.len(), 1);
+    }
+
+    #[test]
+    fn test_pre_routing_chains_outside_sections() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                use-chain {
+                    filter name="motya.pre-routing.rewrite-path" pattern="^/v1/(.*)" replace="/api/$1"
+                }
+                section "/api" {
+                    proxy "http://127.0.0.1:8080"
+                }
             }
-            _ => unreachable!(),
+            "#,
+        )
+        .expect("Parsing failed");
+        assert_eq!(connectors.upstreams[0].chains.len(), 1);
+
+        // A route cannot have filters that run before routes are matched.
+        let err = parse_config(
+            r#"
+            connectors {
+                section "/api" {
+                    use-chain {
+                        filter name="motya.pre-routing.rewrite-path" pattern="^/v1/(.*)" replace="/api/$1"
+                    }
+                    proxy "http://127.0.0.1:8080"
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "'motya.pre-routing.rewrite-path' runs before the route is matched"
+        );
+    }
+
+    #[test]
+    fn test_inline_filters_errors() {
+        let cases = [
+            (
+                r#"proxy "http://127.0.0.1:8080" set-header="X-A""#,
+                "must be in the form",
+            ),
+            (
+                r#"proxy "http://127.0.0.1:8080" strip-prefix=#true"#,
+                "needs the path of a section",
+            ),
+            (
+                r#"section "/a" { proxy "http://127.0.0.1:8080" strip-prefix="/a"; }"#,
+                "Invalid type for key 'strip-prefix'",
+            ),
+            (
+                r#"proxy strip-prefix=#true { server "127.0.0.1:8080"; }"#,
+                "Unknown configuration key: 'strip-prefix'",
+            ),
+        ];
+
+        for (proxy, message) in cases {
+            let err = parse_config(&format!("connectors {{ {proxy}; }}")).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), message);
         }
     }
 
-    Ok(results)
-}
+    const DEFS_SIMPLE: &str = r#"
+    definitions {
+        modifiers {
+            chain-filters "security" {
+                filter name="block-ip"
+                filter name="auth-check"
+            }
+        }
+    }
+    "#;
 
-fn parse_proto_value(value: &str) -> Result<Option<ALPN>, String> {
-    match value {
-        "h1-only" => Ok(Some(ALPN::H1)),
-        "h2-only" => Ok(Some(ALPN::H2)),
-        "h1-or-h2" => {
-            tracing::warn!("accepting 'h1-or-h2' as meaning 'h2-or-h1'");
-            Ok(Some(ALPN::H2H1))
+    const SIMPLE_CHAIN_CONN: &str = r#"
+    connectors {
+        use-chain "security"
+        proxy "http://127.0.0.1:8080"
+    }
+    "#;
+
+    #[test]
+    fn test_use_chain_simple() {
+        let connectors =
+            parse_config_with_defs(DEFS_SIMPLE, SIMPLE_CHAIN_CONN).expect("Parsing failed");
+        let upstream = &connectors.upstreams[0];
+
+        assert_eq!(upstream.chains.len(), 1, "Should have 1 rule (the chain)");
+
+        match &upstream.chains[0] {
+            Modificator::Chain(named_chain) => {
+                assert_eq!(named_chain.chain.filters.len(), 2);
+                assert_eq!(named_chain.chain.filters[0].name, "block-ip");
+                assert_eq!(named_chain.chain.filters[1].name, "auth-check");
+            }
         }
-        "h2-or-h1" => Ok(Some(ALPN::H2H1)),
-        other => Err(format!(
-            "'proto' should be one of 'h1-only', 'h2-only', or 'h2-or-h1', found '{other}'"
-        )),
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use kdl::KdlDocument;
-    use crate::kdl::parser::block::BlockParser;
-    use crate::kdl::definitions::DefinitionsSection;
-    use crate::assert_err_contains;
-    use crate::kdl::parser::ctx::Current;
+    const DEFS_NESTED: &str = r#"
+    definitions {
+        modifiers {
+            chain-filters "global-log" {
+                filter name="logger"
+            }
+            chain-filters "api-protection" {
+                filter name="rate-limit"
+            }
+        }
+    }
+    "#;
 
-    /// Helper to parse config when no external definitions are needed
-    fn parse_config(input: &str) -> miette::Result<Connectors> {
-        let doc: KdlDocument = input.parse().unwrap();
-        let table = DefinitionsTable::default();
-        
-        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
-        let mut block = BlockParser::new(ctx)?;
+    const NESTED_INHERITANCE_CONN: &str = r#"
+    connectors {
+        use-chain "global-log"
 
-        block.required("connectors", |ctx| {
-            ConnectorsSection::new(&table).parse_node(ctx)
-        })
+        section "/api" {
+            use-chain "api-protection"
+            proxy "http://127.0.0.1:8081"
+        }
+
+        section "/public" {
+            proxy "http://127.0.0.1:8082"
+        }
     }
+    "#;
 
-    /// Helper to parse config with definitions
-    fn parse_config_with_defs(defs_input: &str, conn_input: &str) -> miette::Result<Connectors> {
-        // 1. Parse definitions
-        let defs_doc: KdlDocument = defs_input.parse().unwrap();
-        let defs_ctx = ParseContext::new(&defs_doc, Current::Document(&defs_doc), "test");
-        let mut defs_block = BlockParser::new(defs_ctx)?;
-        
-        let table = defs_block.required("definitions", |ctx| {
-            DefinitionsSection.parse_node(ctx)
-        })?;
+    #[test]
+    fn test_use_chain_inheritance() {
+        let connectors =
+            parse_config_with_defs(DEFS_NESTED, NESTED_INHERITANCE_CONN).expect("Parsing failed");
 
-        // 2. Parse connectors
-        let conn_doc: KdlDocument = conn_input.parse().unwrap();
-        let conn_ctx = ParseContext::new(&conn_doc, Current::Document(&conn_doc), "test");
-        let mut conn_block = BlockParser::new(conn_ctx)?;
-        
-        conn_block.required("connectors", |ctx| {
-            ConnectorsSection::new(&table).parse_node(ctx)
-        })
+        let api_upstream = connectors.upstreams.iter()
+            .find(|u| match &u.upstream {
+                UpstreamConfig::Service(s) => &s.prefix_path,
+                _ => panic!("not for this test")
+            } == "/api")
+            .expect("API upstream not found");
+
+        assert_eq!(api_upstream.chains.len(), 2);
+        let Modificator::Chain(r1) = &api_upstream.chains[0];
+        assert_eq!(r1.chain.filters[0].name, "logger");
+        let Modificator::Chain(r2) = &api_upstream.chains[1];
+        assert_eq!(r2.chain.filters[0].name, "rate-limit");
+
+        let public_upstream = connectors.upstreams.iter()
+            .find(|u| match &u.upstream {
+                UpstreamConfig::Service(s) => &s.prefix_path,
+                _ => panic!("not for this test")
+            } == "/public")
+            .expect("Public upstream not found");
+
+        assert_eq!(public_upstream.chains.len(), 1);
+        let Modificator::Chain(r1) = &public_upstream.chains[0];
+        assert_eq!(r1.chain.filters[0].name, "logger");
     }
 
-    const LOAD_BALANCE_BASIC: &str = r#"
+    const DEFS_MULTI: &str = r#"
+    definitions {
+        modifiers {
+            chain-filters "a" { filter name="A" }
+            chain-filters "b" { filter name="B" }
+        }
+    }
+    "#;
+
+    const MULTIPLE_CHAINS_CONN: &str = r#"
     connectors {
-        load-balance {
-            selection "RoundRobin"
-            health-check "None"
-            discovery "Static"
+        section "/combo" {
+            use-chain "a"
+            use-chain "b"
+            proxy "http://127.0.0.1:9000"
         }
-        proxy {
-            server "127.0.0.1:8080"
+    }
+    "#;
+
+    #[test]
+    fn test_multiple_chains_same_level() {
+        let connectors =
+            parse_config_with_defs(DEFS_MULTI, MULTIPLE_CHAINS_CONN).expect("Parsing failed");
+        let upstream = &connectors.upstreams[0];
+
+        assert_eq!(upstream.chains.len(), 2);
+
+        let Modificator::Chain(r) = &upstream.chains[0];
+        assert_eq!(r.chain.filters[0].name, "A");
+        let Modificator::Chain(r) = &upstream.chains[1];
+        assert_eq!(r.chain.filters[0].name, "B");
+    }
+
+    const DEFS_MISSING: &str = r#"
+    definitions {
+        modifiers {
+            chain-filters "exists" { filter name="ok" }
         }
     }
     "#;
 
+    const MISSING_CHAIN_CONN: &str = r#"
+    connectors {
+        use-chain "GHOST"
+        proxy "http://127.0.0.1:8080"
+    }
+    "#;
+
     #[test]
-    fn test_load_balance_basic() {
-        let connectors = parse_config(LOAD_BALANCE_BASIC).expect("Parsing failed");
+    fn test_missing_chain_error() {
+        let result = parse_config_with_defs(DEFS_MISSING, MISSING_CHAIN_CONN);
 
-        assert_eq!(connectors.upstreams.len(), 1);
-        let upstream = &connectors.upstreams[0];
-        let lb_options = upstream.lb_options.clone().unwrap();
-        assert_eq!(lb_options.selection, SelectionKind::RoundRobin);
-        assert_eq!(lb_options.health_checks, HealthCheckKind::None);
-        assert_eq!(lb_options.discovery, DiscoveryKind::Static);
-        assert!(lb_options.template.is_none());
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().help().unwrap().to_string();
+        assert!(err_msg.contains("Chain 'GHOST' not found in definitions"));
     }
 
-    const LOAD_BALANCE_ALL_SELECTION_TYPES: &str = r#"
+    const CONNECTORS_NESTED_SECTIONS: &str = r#"
     connectors {
-        load-balance {
-            selection "Random"
-        }
-        proxy {
-            server "127.0.0.1:8080"
+        proxy "http://0.0.0.0:8000"
+        section "/first" as="prefix" {
+            proxy "http://0.0.0.0:8000"
+            section "/second" {
+                proxy "http://0.0.0.0:8001/something"
+            }
         }
     }
     "#;
 
     #[test]
-    fn test_load_balance_random_selection() {
-        let connectors = parse_config(LOAD_BALANCE_ALL_SELECTION_TYPES).expect("Parsing failed");
+    fn connectors_with_nested_sections() {
+        let doc: KdlDocument = CONNECTORS_NESTED_SECTIONS.parse().unwrap();
+        let table = DefinitionsTable::default();
+        let mut anon = DefinitionsTable::default();
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let mut block = BlockParser::new(ctx).unwrap();
 
-        let upstream = &connectors.upstreams[0];
-        let lb_options = upstream.lb_options.clone().unwrap();
-        assert_eq!(lb_options.selection, SelectionKind::Random);
+        let nodes = block.required("connectors", |ctx| {
+             ConnectorsSection::new(&table).parse_connections_node(ctx, &mut anon)
+        }).unwrap();
+
+        let ConnectorsLeaf::Upstream(UpstreamConfig::Service(first)) = &nodes[0] else {
+            unreachable!()
+        };
+        let ConnectorsLeaf::Section(second_section) = &nodes[1] else {
+            unreachable!()
+        };
+        let ConnectorsLeaf::Section(third_section) = &second_section[1] else {
+            unreachable!()
+        };
+
+        assert_eq!(first.prefix_path, "/");
+        assert_eq!(first.target_path, "/");
+
+        let ConnectorsLeaf::Upstream(UpstreamConfig::Service(second)) =
+            second_section.first().unwrap()
+        else {
+            unreachable!()
+        };
+        assert_eq!(second.prefix_path, "/first");
+        assert_eq!(second.target_path, "/");
+
+        let ConnectorsLeaf::Upstream(UpstreamConfig::Service(third)) =
+            third_section.first().unwrap()
+        else {
+            unreachable!()
+        };
+        assert_eq!(third.prefix_path, "/first/second");
+        assert_eq!(third.target_path, "/something");
     }
 
-    const LOAD_BALANCE_FNV_HASH: &str = r#"
+    const CONNECTORS_SECTION_WITH_PATH: &str = r#"
     connectors {
-        load-balance {
-            selection "FNV" use-key-profile="ip-profile"
-        }
-        proxy { 
-            server "127.0.0.1:8080"
+        section "/old-path" {
+            proxy "http://0.0.0.0:8000/new-path"
         }
     }
     "#;
 
     #[test]
-    fn test_load_balance_fnv_hash_with_key_profile() {
-        // Should fail because "ip-profile" is not defined in the empty table
-        let result = parse_config(LOAD_BALANCE_FNV_HASH);
-        assert!(result.is_err());
-    }
-
-    const DEFS_KEY_PROFILE: &str = r#"
-    definitions {
-        key-profiles {
-            template "ip-profile" {
-                key "amogus"
-            }
+    fn service_section_with_path() {
+        let connectors = parse_config(CONNECTORS_SECTION_WITH_PATH).unwrap();
+        let upstream = &connectors.upstreams[0];
+        if let UpstreamConfig::Service(s) = &upstream.upstream {
+            assert_eq!(s.prefix_path, "/old-path");
+            assert_eq!(s.target_path, "/new-path");
+        } else {
+            panic!("Expected Service upstream");
         }
     }
-    "#;
 
-    const LOAD_BALANCE_WITH_KEY_PROFILE: &str = r#"
+    const CONNECTORS_SECTION: &str = r#"
     connectors {
-        load-balance {
-            selection "FNV" use-key-profile="ip-profile"
-        }
-        proxy {
-            server "127.0.0.1:8080"
+        section "/" {
+            proxy "http://0.0.0.0:8000"
         }
     }
     "#;
 
     #[test]
-    fn test_load_balance_with_defined_key_profile() {
-        let connectors = parse_config_with_defs(DEFS_KEY_PROFILE, LOAD_BALANCE_WITH_KEY_PROFILE)
-            .expect("Parsing failed");
-
+    fn service_section() {
+        let connectors = parse_config(CONNECTORS_SECTION).unwrap();
         let upstream = &connectors.upstreams[0];
-        let lb_options = upstream.lb_options.clone().unwrap();
-        assert_eq!(lb_options.selection, SelectionKind::FvnHash);
-        assert!(lb_options.template.is_some());
-
-        let template = lb_options.template.as_ref().unwrap();
-        assert_eq!(template.source, "amogus".to_string());
+        if let UpstreamConfig::Service(s) = &upstream.upstream {
+            assert_eq!(
+                s.peer_address,
+                SocketAddr::V4("0.0.0.0:8000".parse().unwrap())
+            );
+        } else {
+            panic!("Expected Service upstream");
+        }
     }
 
-    const LOAD_BALANCE_HASH_WITHOUT_KEY_SOURCE: &str = r#"
+    const CONNECTORS_PROXY: &str = r#"
     connectors {
-        load-balance {
-            selection "Ketama"
-        }
-        proxy {
-            server "127.0.0.1:8080"
-        }
+        proxy "http://0.0.0.0:8000"
     }
     "#;
 
     #[test]
-    fn test_error_hash_selection_without_key_source() {
-        let result = parse_config(LOAD_BALANCE_HASH_WITHOUT_KEY_SOURCE);
-        assert!(result.is_err());
-
-        let err_msg = result.unwrap_err().help().unwrap().to_string();
-        assert!(err_msg.contains("requires a key source"));
+    fn service_proxy() {
+        let connectors = parse_config(CONNECTORS_PROXY).unwrap();
+        let upstream = &connectors.upstreams[0];
+        if let UpstreamConfig::Service(s) = &upstream.upstream {
+            assert_eq!(
+                s.peer_address,
+                SocketAddr::V4("0.0.0.0:8000".parse().unwrap())
+            );
+        } else {
+            panic!("Expected Service upstream");
+        }
     }
 
-    const LOAD_BALANCE_DUPLICATE: &str = r#"
+    const CONNECTORS_RETURN_SIMPLE_RESPONSE: &str = r#"
     connectors {
-        load-balance {
-            selection "RoundRobin"
-        }
-        load-balance {
-            selection "Random"
-        }
-        proxy {
-            server "127.0.0.1:8081"
-        }
+        return code=200 response="OK"
     }
     "#;
 
     #[test]
-    fn test_error_duplicate_load_balance() {
-        let result = parse_config(LOAD_BALANCE_DUPLICATE);
-        assert!(result.is_err());
-
-        let err_msg = result.unwrap_err().help().unwrap().to_string();
-        assert_err_contains!(err_msg, "Directive 'load-balance' cannot be repeated");
+    fn service_return_simple_response() {
+        let connectors = parse_config(CONNECTORS_RETURN_SIMPLE_RESPONSE).unwrap();
+        let upstream = &connectors.upstreams[0];
+        if let UpstreamConfig::Static(response) = &upstream.upstream {
+            assert_eq!(response.http_code, http::StatusCode::OK);
+            assert_eq!(response.response_body, "OK");
+        } else {
+            panic!("Expected Static upstream");
+        }
     }
 
-    const MULTI_SERVER_PROXY_CONFIG: &str = r#"
+    const CONNECTORS_SHAPING: &str = r#"
     connectors {
-        proxy { 
-            tls-sni "onevariable.com" 
-            proto "h2-or-h1"
-            server "91.107.223.4:443" 
-            server "91.107.223.5:443" 
-            server "91.107.223.6:443"
+        shaping bytes-per-sec=65536 latency-ms=200
+        proxy "http://0.0.0.0:8000"
+        section "/fast" {
+            proxy "http://0.0.0.0:8001"
+        }
+        section "/jittery" {
+            shaping latency-ms=50 jitter-ms=100
+            proxy "http://0.0.0.0:8002"
         }
     }
     "#;
 
     #[test]
-    fn test_multi_server_proxy_parsing() {
-        let connectors = parse_config(MULTI_SERVER_PROXY_CONFIG).expect("Parsing failed");
-
-        assert_eq!(
-            connectors.upstreams.len(),
-            1,
-            "Should have one UpstreamConfig"
-        );
-
-        let upstream_config = &connectors.upstreams[0];
+    fn test_shaping_inheritance() {
+        let connectors = parse_config(CONNECTORS_SHAPING).unwrap();
 
-        let UpstreamConfig::MultiServer(upstream) = &upstream_config.upstream else {
-            panic!(
-                "Expected MultiServer upstream, got {:?}",
-                upstream_config.upstream
-            );
+        let root = ShapingConfig {
+            bytes_per_sec: Some(65536),
+            latency: Duration::from_millis(200),
+            jitter: Duration::ZERO,
         };
 
+        assert_eq!(connectors.upstreams[0].shaping, Some(root.clone()));
+        assert_eq!(connectors.upstreams[1].shaping, Some(root));
         assert_eq!(
-            upstream.servers.len(),
-            3,
-            "Should parse 3 server directives"
-        );
-        assert_eq!(
-            upstream.tls_sni.as_deref(),
-            Some("onevariable.com"),
-            "Should parse tls-sni"
-        );
-        assert_eq!(upstream.alpn, ALPN::H2H1, "Should parse proto h2-or-h1");
-        assert_eq!(upstream.prefix_path, "/", "Should have default prefix path");
-        assert_eq!(
-            upstream.matcher,
-            RouteMatcher::Exact,
-            "Should have default matcher"
+            connectors.upstreams[2].shaping,
+            Some(ShapingConfig {
+                bytes_per_sec: None,
+                latency: Duration::from_millis(50),
+                jitter: Duration::from_millis(100),
+            })
         );
+    }
 
-        assert_eq!(
-            upstream.servers[0].address,
-            "91.107.223.4:443".parse().unwrap()
+    #[test]
+    fn test_shaping_errors() {
+        let empty = parse_config(
+            r#"
+            connectors {
+                shaping
+                proxy "http://0.0.0.0:8000"
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            empty.help().unwrap().to_string(),
+            "requires at least one of"
         );
-        assert_eq!(
-            upstream.servers[2].address,
-            "91.107.223.6:443".parse().unwrap()
+
+        let zero = parse_config(
+            r#"
+            connectors {
+                shaping bytes-per-sec=0
+                proxy "http://0.0.0.0:8000"
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            zero.help().unwrap().to_string(),
+            "must be greater than zero"
         );
     }
 
-    const SINGLE_SERVER_BLOCK_PROXY_CONFIG: &str = r#"
+    const CONNECTORS_TIMEOUTS: &str = r#"
     connectors {
-        proxy {
-            server "127.0.0.1:8080"
+        timeouts connect-ms=500 total-ms=30000
+        proxy "http://0.0.0.0:8000"
+        section "/reports" {
+            timeouts read-ms=60000 body="report took too long"
+            proxy "http://0.0.0.0:8001"
         }
     }
     "#;
 
     #[test]
-    fn test_single_server_block_proxy_parsing() {
-        let connectors = parse_config(SINGLE_SERVER_BLOCK_PROXY_CONFIG).expect("Parsing failed");
-
-        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
-            panic!("Expected MultiServer upstream (even for a single server in block)");
-        };
+    fn test_timeouts_inheritance() {
+        let connectors = parse_config(CONNECTORS_TIMEOUTS).unwrap();
 
-        assert_eq!(upstream.servers.len(), 1, "Should parse 1 server directive");
-        assert!(
-            upstream.tls_sni.is_none(),
-            "tls-sni should be None by default"
-        );
         assert_eq!(
-            upstream.alpn,
-            ALPN::H1,
-            "ALPN should be H1 by default (no sni/proto)"
+            connectors.upstreams[0].timeouts,
+            Some(TimeoutsConfig {
+                connect: Some(Duration::from_millis(500)),
+                read: None,
+                write: None,
+                total: Some(Duration::from_secs(30)),
+                body: "Gateway Timeout".into(),
+            })
         );
         assert_eq!(
-            upstream.servers[0].address,
-            "127.0.0.1:8080".parse().unwrap()
-        );
-    }
-
-    const ERROR_DUPLICATE_PROTO: &str = r#"
-    connectors {
-        proxy {
-            proto "h2-or-h1"
-            proto "h1-only"
-            server "127.0.0.1:8080"
-        }
-    }
-    "#;
-
-    #[test]
-    fn test_error_on_duplicate_options() {
-        let result = parse_config(ERROR_DUPLICATE_PROTO);
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err().help().unwrap().to_string();
-        crate::assert_err_contains!(err_msg, "Directive 'proto' cannot be repeated");
-    }
-
-    const ERROR_NO_SERVERS: &str = r#"
-    connectors {
-        proxy {
-            tls-sni "a.com"
-        }
-    }
-    "#;
-
-    #[test]
-    fn test_error_on_no_servers() {
-        let result = parse_config(ERROR_NO_SERVERS);
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err().help().unwrap().to_string();
-        crate::assert_err_contains!(
-            err_msg,
-            "Missing required directive 'server' (at least one expected)"
+            connectors.upstreams[1].timeouts,
+            Some(TimeoutsConfig {
+                connect: None,
+                read: Some(Duration::from_secs(60)),
+                write: None,
+                total: None,
+                body: "report took too long".into(),
+            })
         );
     }
 
-    const ERROR_BLOCK_WITH_ARG: &str = r#"
-    connectors {
-        proxy "http://invalid.com" {
-            server "127.0.0.1:8080"
-        }
-    }
-    "#;
-
-    #[test]
-    fn test_error_block_with_top_arg() {
-        let result = parse_config(ERROR_BLOCK_WITH_ARG);
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err().help().unwrap().to_string();
-        crate::assert_err_contains!(err_msg, "Directive 'proxy' cannot have arguments");
-    }
-
-    const INVALID_STRICT_NESTING: &str = r#"
-    connectors {
-        section "/api" as="exact" {
-            // This should fail because parent is exact
-            section "/v1" {
-                return code=200 response="fail"
-            }
-        }
-    }
-    "#;
-
     #[test]
-    fn test_strict_section_cannot_have_children() {
-        let result = parse_config(INVALID_STRICT_NESTING);
-        let err_msg = result.err().unwrap().help().unwrap().to_string();
-        crate::assert_err_contains!(
-            err_msg,
-            "A section with 'exact' routing mode cannot contain nested sections"
+    fn test_timeouts_errors() {
+        let empty = parse_config(
+            r#"
+            connectors {
+                timeouts body="slow"
+                proxy "http://0.0.0.0:8000"
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            empty.help().unwrap().to_string(),
+            "requires at least one of"
+        );
+
+        let zero = parse_config(
+            r#"
+            connectors {
+                timeouts read-ms=0
+                proxy "http://0.0.0.0:8000"
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            zero.help().unwrap().to_string(),
+            "'read-ms' must be greater than zero"
         );
     }
 
-    const VALID_STRICT_CONFIG: &str = r#"
+    const CONNECTORS_RETRY: &str = r#"
     connectors {
-        section "/api" as="exact" {
-            // Non-section children are allowed
-            return code=200 response="OK"
+        retry attempts=4 backoff-ms=100 on="5xx,connect-error" methods="GET,HEAD"
+        proxy "http://0.0.0.0:8000"
+        section "/defaults" {
+            retry
+            proxy "http://0.0.0.0:8001"
         }
     }
     "#;
 
     #[test]
-    fn test_strict_section_allowed_directives() {
-        let result = parse_config(VALID_STRICT_CONFIG);
-        assert!(result.is_ok());
-    }
+    fn test_retry() {
+        let connectors = parse_config(CONNECTORS_RETRY).unwrap();
 
-    const DEFS_ARGS: &str = r#"
-    definitions {
-        modifiers {
-            chain-filters "defined_with_args" {
-                filter name="set-header" key="X-Region" value="EU"
-                filter name="log-request"
-            }
-        }
+        assert_eq!(
+            connectors.upstreams[0].retry,
+            Some(RetryConfig {
+                attempts: 4,
+                backoff: Duration::from_millis(100),
+                on_server_error: true,
+                on_connect_error: true,
+                methods: vec![Method::GET, Method::HEAD],
+                budget_percent: 20,
+            })
+        );
+        assert_eq!(
+            connectors.upstreams[1].retry,
+            Some(RetryConfig {
+                attempts: 3,
+                backoff: Duration::from_millis(50),
+                on_server_error: false,
+                on_connect_error: true,
+                methods: vec![Method::GET, Method::HEAD, Method::OPTIONS],
+                budget_percent: 20,
+            })
+        );
     }
-    "#;
 
-    const ARGS_PARSING_CONN: &str = r#"
+    const CONNECTORS_COMPRESSION: &str = r#"
     connectors {
-        use-chain {
-            filter name="rate-limit" rps="100" burst="20"
+        compression
+        proxy "http://0.0.0.0:8000"
+        section "/api" {
+            compression algorithms="br, GZIP" min-size=256 content-types="application/json, text/*" \
+                dictionary="api-v1"
+            proxy "http://0.0.0.0:8001"
         }
-        use-chain "defined_with_args"
-        proxy "http://127.0.0.1:8080"
     }
     "#;
 
     #[test]
-    fn test_filter_args_parsing() {
-        let connectors =
-            parse_config_with_defs(DEFS_ARGS, ARGS_PARSING_CONN).expect("Parsing failed");
-        let upstream = &connectors.upstreams[0];
-
-        assert_eq!(upstream.chains.len(), 2);
+    fn test_compression() {
+        let connectors = parse_config(CONNECTORS_COMPRESSION).unwrap();
 
-        match &upstream.chains[0] {
-            Modificator::Chain(named_chain) => {
-                assert!(named_chain.name.contains("__anon_"), "Should be anonymous");
-                let filter = &named_chain.chain.filters[0];
+        assert_eq!(
+            connectors.upstreams[0].compression,
+            Some(CompressionConfig {
+                algorithms: vec![
+                    CompressionAlgorithm::Zstd,
+                    CompressionAlgorithm::Brotli,
+                    CompressionAlgorithm::Gzip,
+                ],
+                min_size: 1024,
+                content_types: DEFAULT_COMPRESSIBLE_TYPES
+                    .split(',')
+                    .map(String::from)
+                    .collect(),
+                dictionary: None,
+            })
+        );
+        assert_eq!(
+            connectors.upstreams[1].compression,
+            Some(CompressionConfig {
+                algorithms: vec![CompressionAlgorithm::Brotli, CompressionAlgorithm::Gzip],
+                min_size: 256,
+                content_types: vec!["application/json".into(), "text/*".into()],
+                dictionary: Some("api-v1".into()),
+            })
+        );
+    }
 
-                assert_eq!(filter.name, "rate-limit");
-                assert_eq!(filter.args.len(), 2);
-                assert_eq!(filter.args.get("rps").map(|s| s.as_str()), Some("100"));
-                assert_eq!(filter.args.get("burst").map(|s| s.as_str()), Some("20"));
-            }
+    #[test]
+    fn test_compression_errors() {
+        let cases = [
+            (
+                "compression algorithms=\"deflate\"",
+                "unknown compression algorithm 'deflate'",
+            ),
+            (
+                "compression content-types=\" , \"",
+                "at least one media type",
+            ),
+            (
+                "compression dictionary=\"../api\"",
+                "'dictionary' must be lowercase",
+            ),
+        ];
+
+        for (compression, expected) in cases {
+            let err = parse_config(&format!(
+                r#"
+                connectors {{
+                    {compression}
+                    proxy "http://0.0.0.0:8000"
+                }}
+                "#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
         }
+    }
 
-        match &upstream.chains[1] {
-            Modificator::Chain(named_chain) => {
-                assert_eq!(named_chain.name, "defined_with_args");
-                assert_eq!(named_chain.chain.filters.len(), 2);
+    #[test]
+    fn test_record_har() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                record-har dir="/var/lib/motya/har" one-in=100
+                proxy "http://0.0.0.0:8000"
+                section "/upload" {
+                    record-har dir="/var/lib/motya/uploads" max-body-bytes=0
+                    proxy "http://0.0.0.0:8001"
+                }
+            }
+            "#,
+        )
+        .unwrap();
 
-                let filter1 = &named_chain.chain.filters[0];
-                assert_eq!(filter1.name, "set-header");
-                assert_eq!(filter1.args.len(), 2);
-                assert_eq!(
-                    filter1.args.get("key").map(|s| s.as_str()),
-                    Some("X-Region")
-                );
-                assert_eq!(filter1.args.get("value").map(|s| s.as_str()), Some("EU"));
+        assert_eq!(
+            connectors.upstreams[0].record_har,
+            Some(HarRecordingConfig {
+                dir: "/var/lib/motya/har".into(),
+                one_in: 100,
+                max_body_bytes: DEFAULT_HAR_MAX_BODY_BYTES,
+            })
+        );
+        assert_eq!(
+            connectors.upstreams[1].record_har,
+            Some(HarRecordingConfig {
+                dir: "/var/lib/motya/uploads".into(),
+                one_in: 1,
+                max_body_bytes: 0,
+            })
+        );
 
-                let filter2 = &named_chain.chain.filters[1];
-                assert_eq!(filter2.name, "log-request");
-                assert!(filter2.args.is_empty(), "Args should be empty");
-            }
+        let cases = [
+            ("record-har one-in=10", "needs the 'dir'"),
+            (
+                r#"record-har dir="har" one-in=0"#,
+                "'one-in' must be greater than 0",
+            ),
+        ];
+
+        for (record_har, expected) in cases {
+            let err = parse_config(&format!(
+                r#"
+                connectors {{
+                    {record_har}
+                    proxy "http://0.0.0.0:8000"
+                }}
+                "#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
         }
     }
 
-    const ANONYMOUS_CHAIN_TEST: &str = r#"
-    connectors {
-        section "/anon" {
-            use-chain {
-                filter name="logger" level="debug"
+    #[test]
+    fn test_cache() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                cache
+                proxy "http://0.0.0.0:8000"
+                section "/assets" {
+                    cache max-size-mb=256 ttl-secs=3600 {
+                        vary {
+                            ignore "user-agent"
+                        }
+                    }
+                    proxy "http://0.0.0.0:8001"
+                }
+                section "/downloads" {
+                    cache storage="disk" path="/var/cache/motya" max-size-gb=10
+                    proxy "http://0.0.0.0:8002"
+                }
             }
-            proxy "http://127.0.0.1:8080"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            connectors.upstreams[0].cache,
+            Some(CacheConfig {
+                storage: CacheStorage::Memory {
+                    max_bytes: DEFAULT_CACHE_MAX_SIZE_MB * 1024 * 1024,
+                },
+                ttl: Duration::from_secs(60),
+                vary: VaryConfig::default(),
+            })
+        );
+        assert_eq!(
+            connectors.upstreams[1].cache,
+            Some(CacheConfig {
+                storage: CacheStorage::Memory {
+                    max_bytes: 256 * 1024 * 1024,
+                },
+                ttl: Duration::from_secs(3600),
+                vary: VaryConfig {
+                    ignore: vec![http::HeaderName::from_static("user-agent")],
+                    buckets: Vec::new(),
+                },
+            })
+        );
+        assert_eq!(
+            connectors.upstreams[2].cache,
+            Some(CacheConfig {
+                storage: CacheStorage::Disk {
+                    path: "/var/cache/motya".into(),
+                    max_bytes: 10 * 1024 * 1024 * 1024,
+                },
+                ttl: Duration::from_secs(60),
+                vary: VaryConfig::default(),
+            })
+        );
+
+        let cases = [
+            (
+                "cache max-size-mb=0",
+                "'max-size-mb' must be greater than 0",
+            ),
+            ("cache ttl-secs=0", "'ttl-secs' must be greater than 0"),
+            (r#"cache storage="redis""#, "unknown cache storage 'redis'"),
+            (r#"cache storage="disk""#, "needs the 'path'"),
+            (
+                r#"cache storage="disk" path="/tmp" max-size-mb=64"#,
+                "sized with 'max-size-gb'",
+            ),
+            (r#"cache path="/tmp""#, "only apply to a disk cache"),
+        ];
+
+        for (cache, expected) in cases {
+            let err = parse_config(&format!(
+                r#"connectors {{ {cache}; proxy "http://0.0.0.0:8000"; }}"#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
         }
     }
-    "#;
 
     #[test]
-    fn test_anonymous_chain() {
-        let connectors = parse_config(ANONYMOUS_CHAIN_TEST).expect("Parsing failed");
-        let upstream = &connectors.upstreams[0];
+    fn test_max_request_body_bytes() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                max-request-body-bytes 1048576
+                section "/api" {
+                    proxy "http://0.0.0.0:8000"
+                }
+                section "/upload" {
+                    max-request-body-bytes 104857600
+                    proxy "http://0.0.0.0:8001"
+                }
+            }
+            "#,
+        )
+        .unwrap();
 
-        match &upstream.chains[0] {
-            Modificator::Chain(named_chain) => {
-                assert_eq!(named_chain.chain.filters.len(), 1);
-                let filter = &named_chain.chain.filters[0];
-                assert_eq!(filter.name, "logger");
-                let level_arg = filter.args.get("level").expect("Argument 'level' missing");
-                assert_eq!(level_arg, "debug");
-                assert_eq!(filter.args.len(), 1);
+        assert_eq!(
+            connectors.upstreams[0].max_request_body_bytes,
+            Some(1048576)
+        );
+        assert_eq!(
+            connectors.upstreams[1].max_request_body_bytes,
+            Some(104857600)
+        );
+
+        let err = parse_config(
+            r#"
+            connectors {
+                max-request-body-bytes 0
+                proxy "http://0.0.0.0:8000"
             }
-        }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "must be greater than 0");
     }
 
-    const DEFS_SIMPLE: &str = r#"
-    definitions {
-        modifiers {
-            chain-filters "security" {
-                filter name="block-ip"
-                filter name="auth-check"
+    #[test]
+    fn test_buffering() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                buffering #true
+                section "/api" {
+                    proxy "http://0.0.0.0:8000"
+                }
+                section "/large" {
+                    buffering #true max-bytes=65536
+                    proxy "http://0.0.0.0:8001"
+                }
+                section "/events" {
+                    buffering #false
+                    proxy "http://0.0.0.0:8002"
+                }
+                section "/downloads" {
+                    buffering #true max-bytes=262144 low-watermark=65536 upstream-recv-bytes=131072
+                    proxy "http://0.0.0.0:8003"
+                }
             }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            connectors.upstreams[0].buffering,
+            Some(BufferingConfig {
+                max_bytes: DEFAULT_BUFFERING_MAX_BYTES,
+                low_watermark: None,
+                upstream_recv_bytes: None,
+            })
+        );
+        assert_eq!(
+            connectors.upstreams[1].buffering,
+            Some(BufferingConfig {
+                max_bytes: 65536,
+                low_watermark: None,
+                upstream_recv_bytes: None,
+            })
+        );
+        assert_eq!(connectors.upstreams[2].buffering, None);
+        assert_eq!(
+            connectors.upstreams[3].buffering,
+            Some(BufferingConfig {
+                max_bytes: 262144,
+                low_watermark: Some(65536),
+                upstream_recv_bytes: Some(131072),
+            })
+        );
+
+        let cases = [
+            (
+                "buffering #false max-bytes=1024",
+                "only apply to 'buffering #true'",
+            ),
+            (
+                "buffering #true max-bytes=1024 low-watermark=1024",
+                "must be below 'max-bytes'",
+            ),
+            (
+                "buffering #true upstream-recv-bytes=0",
+                "must be greater than 0",
+            ),
+        ];
+
+        for (buffering, expected) in cases {
+            let err = parse_config(&format!(
+                r#"connectors {{ {buffering}; proxy "http://0.0.0.0:8000"; }}"#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
         }
     }
-    "#;
 
-    const SIMPLE_CHAIN_CONN: &str = r#"
-    connectors {
-        use-chain "security"
-        proxy "http://127.0.0.1:8080"
+    #[test]
+    fn test_retry_errors() {
+        let cases = [
+            ("retry attempts=1", "must be at least 2"),
+            ("retry on=\"timeout\"", "unknown retry condition 'timeout'"),
+            ("retry budget-percent=0", "must be greater than zero"),
+        ];
+
+        for (retry, expected) in cases {
+            let err = parse_config(&format!(
+                r#"
+                connectors {{
+                    {retry}
+                    proxy "http://0.0.0.0:8000"
+                }}
+                "#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
     }
-    "#;
 
     #[test]
-    fn test_use_chain_simple() {
-        let connectors =
-            parse_config_with_defs(DEFS_SIMPLE, SIMPLE_CHAIN_CONN).expect("Parsing failed");
-        let upstream = &connectors.upstreams[0];
+    fn test_circuit_breaker() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                load-balance {
+                    circuit-breaker consecutive-failures=3 error-rate-percent=50 cool-down-secs=5
+                }
+                proxy {
+                    server "10.0.0.1:80"
+                    server "10.0.0.2:80"
+                }
+            }
+            "#,
+        )
+        .unwrap();
 
-        assert_eq!(upstream.chains.len(), 1, "Should have 1 rule (the chain)");
+        assert_eq!(
+            connectors.upstreams[0]
+                .lb_options
+                .as_ref()
+                .unwrap()
+                .circuit_breaker,
+            Some(CircuitBreakerConfig {
+                consecutive_failures: 3,
+                error_rate: Some(50),
+                window: Duration::from_secs(10),
+                min_requests: 20,
+                cool_down: Duration::from_secs(5),
+            })
+        );
 
-        match &upstream.chains[0] {
-            Modificator::Chain(named_chain) => {
-                assert_eq!(named_chain.chain.filters.len(), 2);
-                assert_eq!(named_chain.chain.filters[0].name, "block-ip");
-                assert_eq!(named_chain.chain.filters[1].name, "auth-check");
+        let err = parse_config(
+            r#"
+            connectors {
+                load-balance {
+                    circuit-breaker error-rate-percent=150
+                }
+                proxy {
+                    server "10.0.0.1:80"
+                }
             }
-        }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "between 1 and 100");
     }
 
-    const DEFS_NESTED: &str = r#"
-    definitions {
-        modifiers {
-            chain-filters "global-log" {
-                filter name="logger"
-            }
-            chain-filters "api-protection" {
-                filter name="rate-limit"
-            }
-        }
+    #[test]
+    fn test_when_down() {
+        let when_down = |node: &str| {
+            parse_config(&format!(
+                r#"
+                connectors {{
+                    load-balance {{
+                        {node}
+                    }}
+                    proxy {{
+                        server "10.0.0.1:80"
+                    }}
+                }}
+                "#
+            ))
+            .map(|connectors| {
+                connectors.upstreams[0]
+                    .lb_options
+                    .clone()
+                    .unwrap()
+                    .when_down
+            })
+        };
+
+        assert_eq!(
+            when_down(r#"when-down code=200 response="maintenance" retry-after-secs=30"#).unwrap(),
+            Some(WhenDownConfig {
+                status: StatusCode::OK,
+                body: "maintenance".into(),
+                retry_after: Some(Duration::from_secs(30)),
+            })
+        );
+        assert_eq!(
+            when_down("when-down").unwrap(),
+            Some(WhenDownConfig {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                body: String::new(),
+                retry_after: None,
+            })
+        );
+        assert_eq!(when_down("selection \"RoundRobin\"").unwrap(), None);
+
+        assert!(when_down("when-down code=42").is_err());
+        assert!(when_down("when-down 503").is_err());
     }
-    "#;
 
-    const NESTED_INHERITANCE_CONN: &str = r#"
-    connectors {
-        use-chain "global-log"
+    #[test]
+    fn test_inline_header_key() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                load-balance {
+                    selection "Ketama" key="Header" header-name="X-Tenant-Id"
+                }
+                proxy {
+                    server "10.0.0.1:80"
+                    server "10.0.0.2:80"
+                }
+            }
+            "#,
+        )
+        .unwrap();
 
-        section "/api" {
-            use-chain "api-protection"
-            proxy "http://127.0.0.1:8081"
-        }
+        let lb_options = connectors.upstreams[0].lb_options.as_ref().unwrap();
+        assert_eq!(lb_options.selection, SelectionKind::KetamaHashing);
+        assert_eq!(
+            lb_options.template.as_ref().unwrap().source,
+            "${header-X-Tenant-Id}"
+        );
 
-        section "/public" {
-            proxy "http://127.0.0.1:8082"
+        let cases = [
+            (r#"selection "FNV" key="Header""#, "requires 'header-name'"),
+            (
+                r#"selection "FNV" header-name="x-tenant""#,
+                "requires 'key=\"Header\"'",
+            ),
+            (
+                r#"selection "FNV" key="UriPath" use-key-profile="ip-profile""#,
+                "Cannot combine 'key'",
+            ),
+        ];
+
+        for (selection, expected) in cases {
+            let err = parse_config(&format!(
+                r#"
+                connectors {{
+                    load-balance {{
+                        {selection}
+                    }}
+                    proxy {{
+                        server "10.0.0.1:80"
+                    }}
+                }}
+                "#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
         }
     }
-    "#;
 
     #[test]
-    fn test_use_chain_inheritance() {
-        let connectors =
-            parse_config_with_defs(DEFS_NESTED, NESTED_INHERITANCE_CONN).expect("Parsing failed");
-
-        let api_upstream = connectors.upstreams.iter()
-            .find(|u| match &u.upstream {
-                UpstreamConfig::Service(s) => &s.prefix_path,
-                _ => panic!("not for this test")
-            } == "/api")
-            .expect("API upstream not found");
+    fn test_inline_cookie_and_query_key() {
+        for (selection, expected) in [
+            (r#"key="Cookie" param="session""#, "${cookie-session}"),
+            (r#"key="QueryParam" param="user_id""#, "${query?user_id}"),
+        ] {
+            let connectors = parse_config(&format!(
+                r#"
+                connectors {{
+                    load-balance {{
+                        selection "FNV" {selection}
+                    }}
+                    proxy {{
+                        server "10.0.0.1:80"
+                    }}
+                }}
+                "#
+            ))
+            .unwrap();
+
+            let lb_options = connectors.upstreams[0].lb_options.as_ref().unwrap();
+            assert_eq!(lb_options.template.as_ref().unwrap().source, expected);
+        }
 
-        assert_eq!(api_upstream.chains.len(), 2);
-        let Modificator::Chain(r1) = &api_upstream.chains[0];
-        assert_eq!(r1.chain.filters[0].name, "logger");
-        let Modificator::Chain(r2) = &api_upstream.chains[1];
-        assert_eq!(r2.chain.filters[0].name, "rate-limit");
+        let err = parse_config(
+            r#"
+            connectors {
+                load-balance {
+                    selection "FNV" param="user_id"
+                }
+                proxy {
+                    server "10.0.0.1:80"
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "'param' requires 'key=\"Cookie\"'"
+        );
+    }
 
-        let public_upstream = connectors.upstreams.iter()
-            .find(|u| match &u.upstream {
-                UpstreamConfig::Service(s) => &s.prefix_path,
-                _ => panic!("not for this test")
-            } == "/public")
-            .expect("Public upstream not found");
+    #[test]
+    fn test_cookie_sticky() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                load-balance {
+                    selection "CookieSticky" cookie="river_affinity" ttl-secs=600 fallback="Random"
+                }
+                proxy {
+                    server "10.0.0.1:80"
+                    server "10.0.0.2:80"
+                }
+            }
+            "#,
+        )
+        .unwrap();
 
-        assert_eq!(public_upstream.chains.len(), 1);
-        let Modificator::Chain(r1) = &public_upstream.chains[0];
-        assert_eq!(r1.chain.filters[0].name, "logger");
-    }
+        let lb_options = connectors.upstreams[0].lb_options.as_ref().unwrap();
+        assert_eq!(lb_options.selection, SelectionKind::Random);
+        assert_eq!(
+            lb_options.sticky,
+            Some(StickyCookieConfig {
+                cookie: "river_affinity".into(),
+                ttl: Duration::from_secs(600),
+            })
+        );
 
-    const DEFS_MULTI: &str = r#"
-    definitions {
-        modifiers {
-            chain-filters "a" { filter name="A" }
-            chain-filters "b" { filter name="B" }
+        let cases = [
+            (
+                r#"selection "CookieSticky" ttl-secs=0"#,
+                "must be greater than zero",
+            ),
+            (
+                r#"selection "CookieSticky" fallback="Sticky""#,
+                "unknown selection kind",
+            ),
+            (
+                r#"selection "RoundRobin" cookie="a""#,
+                "only apply to 'CookieSticky'",
+            ),
+        ];
+
+        for (selection, expected) in cases {
+            let err = parse_config(&format!(
+                r#"
+                connectors {{
+                    load-balance {{
+                        {selection}
+                    }}
+                    proxy {{
+                        server "10.0.0.1:80"
+                    }}
+                }}
+                "#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
         }
     }
-    "#;
 
-    const MULTIPLE_CHAINS_CONN: &str = r#"
+    const KETAMA_VNODES: &str = r#"
     connectors {
-        section "/combo" {
-            use-chain "a"
-            use-chain "b"
-            proxy "http://127.0.0.1:9000"
+        load-balance {
+            selection "Ketama" use-key-profile="ip-profile"
+        }
+        proxy {
+            server "10.0.0.1:80" weight=2
+            server "10.0.0.2:80" vnodes=40
         }
     }
     "#;
 
     #[test]
-    fn test_multiple_chains_same_level() {
+    fn test_ketama_weights_and_vnodes() {
         let connectors =
-            parse_config_with_defs(DEFS_MULTI, MULTIPLE_CHAINS_CONN).expect("Parsing failed");
-        let upstream = &connectors.upstreams[0];
+            parse_config_with_defs(DEFS_KEY_PROFILE, KETAMA_VNODES).expect("Parsing failed");
 
-        assert_eq!(upstream.chains.len(), 2);
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream");
+        };
 
-        let Modificator::Chain(r) = &upstream.chains[0];
-        assert_eq!(r.chain.filters[0].name, "A");
-        let Modificator::Chain(r) = &upstream.chains[1];
-        assert_eq!(r.chain.filters[0].name, "B");
+        assert_eq!(upstream.servers[0].weight, 2);
+        assert_eq!(upstream.servers[0].vnodes, None);
+        assert_eq!(upstream.servers[1].weight, 1);
+        assert_eq!(upstream.servers[1].vnodes, Some(40));
     }
 
-    const DEFS_MISSING: &str = r#"
-    definitions {
-        modifiers {
-            chain-filters "exists" { filter name="ok" }
+    #[test]
+    fn test_vnodes_errors() {
+        let without_ketama = r#"
+        connectors {
+            load-balance {
+                selection "RoundRobin"
+            }
+            proxy {
+                server "10.0.0.1:80" vnodes=40
+            }
         }
-    }
-    "#;
+        "#;
+        let err = parse_config(without_ketama).unwrap_err();
+        assert_err_contains!(
+            err.to_string(),
+            "only applies to servers balanced with 'Ketama'"
+        );
 
-    const MISSING_CHAIN_CONN: &str = r#"
-    connectors {
-        use-chain "GHOST"
-        proxy "http://127.0.0.1:8080"
-    }
-    "#;
+        let zero = r#"
+        connectors {
+            proxy {
+                server "10.0.0.1:80" vnodes=0
+            }
+        }
+        "#;
+        let err = parse_config(zero).unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "'vnodes' must be greater than zero"
+        );
 
+        let zero_weight = r#"
+        connectors {
+            proxy {
+                server "10.0.0.1:80" weight=0
+            }
+        }
+        "#;
+        let err = parse_config(zero_weight).unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "'weight' must be greater than zero"
+        );
+    }
     #[test]
-    fn test_missing_chain_error() {
-        let result = parse_config_with_defs(DEFS_MISSING, MISSING_CHAIN_CONN);
+    fn test_read_your_writes() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                proxy {
+                    server "10.0.0.2:80"
+                    server "10.0.0.3:80"
+                    read-your-writes ttl-secs=10 header="X-Read-Primary" {
+                        primary "10.0.0.1:80"
+                    }
+                }
+            }
+            "#,
+        )
+        .expect("Parsing failed");
 
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err().help().unwrap().to_string();
-        assert!(err_msg.contains("Chain 'GHOST' not found in definitions"));
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream");
+        };
+
+        assert_eq!(
+            upstream.read_your_writes,
+            Some(ReadYourWritesConfig {
+                primary: vec!["10.0.0.1:80".parse().unwrap()],
+                ttl: Duration::from_secs(10),
+                cookie: "motya-primary".into(),
+                header: Some("x-read-primary".into()),
+            })
+        );
     }
 
-    const CONNECTORS_NESTED_SECTIONS: &str = r#"
-    connectors {
-        proxy "http://0.0.0.0:8000"
-        section "/first" as="prefix" {
-            proxy "http://0.0.0.0:8000"
-            section "/second" {
-                proxy "http://0.0.0.0:8001/something"
-            }
+    #[test]
+    fn test_read_your_writes_errors() {
+        let cases = [
+            (
+                r#"connectors { proxy { server "10.0.0.2:80"; read-your-writes { }; } }"#,
+                "Missing required directive 'primary'",
+            ),
+            (
+                r#"connectors { proxy { server "10.0.0.2:80"; read-your-writes ttl-secs=0 { primary "10.0.0.1:80"; }; } }"#,
+                "'ttl-secs' must be greater than zero",
+            ),
+            (
+                r#"connectors { proxy { server "10.0.0.2:80"; read-your-writes cookie="a b" { primary "10.0.0.1:80"; }; } }"#,
+                "Invalid cookie name",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let err = parse_config(input).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
         }
     }
-    "#;
 
     #[test]
-    fn connectors_with_nested_sections() {
-        let doc: KdlDocument = CONNECTORS_NESTED_SECTIONS.parse().unwrap();
-        let table = DefinitionsTable::default();
-        let mut anon = DefinitionsTable::default();
-        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
-        let mut block = BlockParser::new(ctx).unwrap();
-
-        let nodes = block.required("connectors", |ctx| {
-             ConnectorsSection::new(&table).parse_connections_node(ctx, &mut anon)
-        }).unwrap();
+    fn test_h2c_connectors() {
+        let connectors = parse_config(r#"connectors { proxy "http://10.0.0.1:50051" h2c=#true; }"#)
+            .expect("Parsing failed");
 
-        let ConnectorsLeaf::Upstream(UpstreamConfig::Service(first)) = &nodes[0] else {
-            unreachable!()
-        };
-        let ConnectorsLeaf::Section(second_section) = &nodes[1] else {
-            unreachable!()
+        let UpstreamConfig::Service(peer) = &connectors.upstreams[0].upstream else {
+            panic!("Expected Service upstream");
         };
-        let ConnectorsLeaf::Section(third_section) = &second_section[1] else {
-            unreachable!()
+        assert_eq!((peer.tls, &peer.alpn), (false, &ALPN::H2));
+
+        let connectors = parse_config(
+            r#"
+            connectors {
+                proxy {
+                    server "10.0.0.2:50051"
+                    h2c #true
+                }
+            }
+            "#,
+        )
+        .expect("Parsing failed");
+
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream");
         };
+        assert_eq!((&upstream.tls_sni, &upstream.alpn), (&None, &ALPN::H2));
 
-        assert_eq!(first.prefix_path, "/");
-        assert_eq!(first.target_path, "/");
+        let err = parse_config(
+            r#"connectors { proxy "http://10.0.0.1:50051" h2c=#true tls-sni="example.com"; }"#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "remove 'tls-sni'");
 
-        let ConnectorsLeaf::Upstream(UpstreamConfig::Service(second)) =
-            second_section.first().unwrap()
-        else {
-            unreachable!()
-        };
-        assert_eq!(second.prefix_path, "/first");
-        assert_eq!(second.target_path, "/");
+        let err = parse_config(
+            r#"connectors { proxy "http://10.0.0.1:50051" h2c=#true proto="h2-or-h1"; }"#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "only be combined with 'h2-only'"
+        );
+    }
 
-        let ConnectorsLeaf::Upstream(UpstreamConfig::Service(third)) =
-            third_section.first().unwrap()
-        else {
-            unreachable!()
+    #[test]
+    fn test_split_by_method() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                proxy {
+                    server "10.0.0.2:80"
+                    split-by-method {
+                        primary "10.0.0.1:80"
+                    }
+                }
+            }
+            "#,
+        )
+        .expect("Parsing failed");
+
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream");
         };
-        assert_eq!(third.prefix_path, "/first/second");
-        assert_eq!(third.target_path, "/something");
-    }
 
-    const CONNECTORS_SECTION_WITH_PATH: &str = r#"
-    connectors {
-        section "/old-path" {
-            proxy "http://0.0.0.0:8000/new-path"
-        }
+        assert_eq!(
+            upstream.split_by_method,
+            Some(MethodSplitConfig {
+                primary: vec!["10.0.0.1:80".parse().unwrap()],
+            })
+        );
+
+        let err = parse_config(
+            r#"
+            connectors {
+                proxy {
+                    server "10.0.0.2:80"
+                    read-your-writes { primary "10.0.0.1:80"; }
+                    split-by-method { primary "10.0.0.1:80"; }
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "cannot be combined with 'read-your-writes'"
+        );
     }
-    "#;
 
     #[test]
-    fn service_section_with_path() {
-        let connectors = parse_config(CONNECTORS_SECTION_WITH_PATH).unwrap();
-        let upstream = &connectors.upstreams[0];
-        if let UpstreamConfig::Service(s) = &upstream.upstream {
-            assert_eq!(s.prefix_path, "/old-path");
-            assert_eq!(s.target_path, "/new-path");
-        } else {
-            panic!("Expected Service upstream");
+    fn test_traffic_split() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                proxy {
+                    split sticky=#true override-header="X-Canary" {
+                        group "stable" weight=95 {
+                            server "10.0.0.1:80"
+                            server "10.0.0.2:80"
+                        }
+                        group "canary" weight=5 {
+                            server "10.0.0.3:80"
+                        }
+                    }
+                }
+            }
+            "#,
+        )
+        .expect("Parsing failed");
+
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream");
+        };
+
+        let split = upstream.split.as_ref().unwrap();
+        assert!(split.sticky);
+        assert_eq!(split.override_header.as_deref(), Some("x-canary"));
+        assert_eq!(
+            split.groups[1],
+            SplitGroupConfig {
+                name: "canary".to_string(),
+                weight: 5,
+                servers: vec!["10.0.0.3:80".parse().unwrap()],
+            }
+        );
+        assert_eq!(upstream.servers.len(), 3);
+
+        let cases = [
+            (
+                r#"split { group "a" weight=90 { server "10.0.0.1:80"; }; group "b" weight=5 { server "10.0.0.2:80"; }; }"#,
+                "must add up to 100, found 95",
+            ),
+            (
+                r#"split { group "a" weight=50 { server "10.0.0.1:80"; }; group "a" weight=50 { server "10.0.0.2:80"; }; }"#,
+                "Duplicate split group 'a'",
+            ),
+            (
+                r#"server "10.0.0.1:80"; split { group "a" weight=100 { server "10.0.0.2:80"; }; }"#,
+                "'server' cannot be used next to it",
+            ),
+        ];
+
+        for (proxy, expected) in cases {
+            let err = parse_config(&format!("connectors {{ proxy {{ {proxy} }} }}")).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
         }
+
+        let err = parse_config(
+            r#"
+            connectors {
+                load-balance { selection "Random"; }
+                proxy { split { group "a" weight=100 { server "10.0.0.1:80"; }; }; }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.to_string(), "'load-balance' cannot be applied");
     }
 
-    const CONNECTORS_SECTION: &str = r#"
-    connectors {
-        section "/" {
-            proxy "http://0.0.0.0:8000"
-        }
+    #[test]
+    fn test_backend_override() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                proxy {
+                    server "10.0.0.2:80"
+                    backend-override trusted="10.1.0.0/16, 127.0.0.1/32"
+                }
+            }
+            "#,
+        )
+        .expect("Parsing failed");
+
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream");
+        };
+
+        assert_eq!(
+            upstream.backend_override,
+            Some(BackendOverrideConfig {
+                header: "x-motya-backend".into(),
+                trusted: vec![
+                    "10.1.0.0/16".parse().unwrap(),
+                    "127.0.0.1/32".parse().unwrap()
+                ],
+            })
+        );
+
+        let err = parse_config(
+            r#"connectors { proxy { server "10.0.0.2:80"; backend-override trusted="10.1.0.0/33"; } }"#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "not a valid CIDR range");
     }
-    "#;
 
     #[test]
-    fn service_section() {
-        let connectors = parse_config(CONNECTORS_SECTION).unwrap();
-        let upstream = &connectors.upstreams[0];
-        if let UpstreamConfig::Service(s) = &upstream.upstream {
-            assert_eq!(
-                s.peer_address,
-                SocketAddr::V4("0.0.0.0:8000".parse().unwrap())
-            );
-        } else {
+    fn test_allow_websocket() {
+        let connectors =
+            parse_config(r#"connectors { proxy "http://10.0.0.1:80" allow-websocket=#false; }"#)
+                .expect("Parsing failed");
+
+        let UpstreamConfig::Service(peer) = &connectors.upstreams[0].upstream else {
             panic!("Expected Service upstream");
-        }
-    }
+        };
+        assert!(!peer.allow_websocket);
 
-    const CONNECTORS_PROXY: &str = r#"
-    connectors {
-        proxy "http://0.0.0.0:8000"
+        let connectors = parse_config(
+            r#"connectors { proxy allow-websocket=#false { server "10.0.0.2:80"; }; }"#,
+        )
+        .expect("Parsing failed");
+
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream");
+        };
+        assert!(!upstream.allow_websocket);
     }
-    "#;
 
     #[test]
-    fn service_proxy() {
-        let connectors = parse_config(CONNECTORS_PROXY).unwrap();
-        let upstream = &connectors.upstreams[0];
-        if let UpstreamConfig::Service(s) = &upstream.upstream {
-            assert_eq!(
-                s.peer_address,
-                SocketAddr::V4("0.0.0.0:8000".parse().unwrap())
-            );
-        } else {
+    fn test_pool_min_idle() {
+        let connectors = parse_config(
+            r#"
+            connectors {
+                section "/api" {
+                    proxy "http://10.0.0.1:80" pool-min-idle=4 pool-warm-path="/healthz";
+                }
+                section "/pool" {
+                    proxy pool-min-idle=2 pool-warm-path="/ping?warm=1" {
+                        server "10.0.0.2:80";
+                    };
+                }
+                section "/cold" { proxy "http://10.0.0.3:80"; }
+            }
+            "#,
+        )
+        .expect("Parsing failed");
+
+        let UpstreamConfig::Service(peer) = &connectors.upstreams[0].upstream else {
             panic!("Expected Service upstream");
-        }
-    }
+        };
+        assert_eq!(peer.pool_min_idle, 4);
+        assert_eq!(peer.pool_warm_path.as_ref().unwrap(), "/healthz");
 
-    const CONNECTORS_RETURN_SIMPLE_RESPONSE: &str = r#"
-    connectors {
-        return code=200 response="OK"
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[1].upstream else {
+            panic!("Expected MultiServer upstream");
+        };
+        assert_eq!(upstream.pool_min_idle, 2);
+        assert_eq!(upstream.pool_warm_path.as_ref().unwrap(), "/ping?warm=1");
+
+        let UpstreamConfig::Service(peer) = &connectors.upstreams[2].upstream else {
+            panic!("Expected Service upstream");
+        };
+        assert_eq!(peer.pool_min_idle, 0);
+        assert_eq!(peer.pool_warm_path, None);
+
+        let err = parse_config(
+            r#"
+            connectors {
+                section "^/v[0-9]+/" {
+                    proxy "http://10.0.0.1:80" pool-min-idle=4 pool-warm-path="/healthz";
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "'pool-min-idle' is not supported in a 'regex' section"
+        );
+
+        // The backends are not sent requests nobody asked for.
+        let err = parse_config(
+            r#"
+            connectors {
+                section "/api" { proxy "http://10.0.0.1:80" pool-min-idle=4; }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "'pool-min-idle' requires a 'pool-warm-path'"
+        );
     }
-    "#;
 
     #[test]
-    fn service_return_simple_response() {
-        let connectors = parse_config(CONNECTORS_RETURN_SIMPLE_RESPONSE).unwrap();
-        let upstream = &connectors.upstreams[0];
-        if let UpstreamConfig::Static(response) = &upstream.upstream {
-            assert_eq!(response.http_code, http::StatusCode::OK);
-            assert_eq!(response.response_body, "OK");
-        } else {
-            panic!("Expected Static upstream");
-        }
+    fn test_grpc_connectors() {
+        let connectors =
+            parse_config(r#"connectors { proxy "http://10.0.0.1:50051" proto="grpc"; }"#)
+                .expect("Parsing failed");
+
+        let UpstreamConfig::Service(peer) = &connectors.upstreams[0].upstream else {
+            panic!("Expected Service upstream");
+        };
+        assert!(peer.grpc);
+        assert_eq!((peer.tls, &peer.alpn), (false, &ALPN::H2));
+
+        let connectors = parse_config(
+            r#"
+            connectors {
+                load-balance {
+                    health-check "Grpc" service="shop.Cart" interval-secs=2
+                }
+                proxy {
+                    server "10.0.0.2:50051"
+                    proto "grpc"
+                    tls-sni "cart.internal"
+                }
+            }
+            "#,
+        )
+        .expect("Parsing failed");
+
+        let UpstreamConfig::MultiServer(upstream) = &connectors.upstreams[0].upstream else {
+            panic!("Expected MultiServer upstream");
+        };
+        assert!(upstream.grpc);
+        assert_eq!(
+            (upstream.tls_sni.as_deref(), &upstream.alpn),
+            (Some("cart.internal"), &ALPN::H2)
+        );
+        assert_eq!(
+            connectors.upstreams[0]
+                .lb_options
+                .as_ref()
+                .unwrap()
+                .health_checks,
+            HealthCheckKind::Grpc {
+                service: "shop.Cart".into(),
+                interval: Duration::from_secs(2),
+            }
+        );
+
+        let err = parse_config(
+            r#"connectors { load-balance { health-check "None" interval-secs=2; }; proxy { server "10.0.0.2:80"; }; }"#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "take no options");
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,4 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{path::PathBuf, str::FromStr, time::Duration};
 
 use fqdn::FQDN;
 use motya_macro::validate;
@@ -6,7 +6,10 @@ use motya_macro::validate;
 use crate::{
     block_parser,
     common_types::{
-        definitions::{PluginDefinition, PluginSource},
+        definitions::{
+            FailurePolicy, GeoIpDatabases, HttpClientLimits, PluginDefinition, PluginLimits,
+            PluginSource, PreopenedDir, WasiCapabilities,
+        },
         definitions_table::DefinitionsTable,
         section_parser::SectionParser,
     },
@@ -21,16 +24,27 @@ use crate::{
     },
 };
 
-pub struct DefinitionsSection;
+/// A body read by a plugin is replayed upstream from the retry buffer of the
+/// session, which holds up to 64 KiB.
+const MAX_PLUGIN_REQUEST_BODY: usize = 64 * 1024;
+
+pub struct DefinitionsSection<'a> {
+    /// Definitions known before this section, used to check chain arguments.
+    global: &'a DefinitionsTable,
+}
 
-impl SectionParser<ParseContext<'_>, DefinitionsTable> for DefinitionsSection {
+impl SectionParser<ParseContext<'_>, DefinitionsTable> for DefinitionsSection<'_> {
     #[validate(ensure_node_name = "definitions")]
     fn parse_node(&self, ctx: ParseContext) -> miette::Result<DefinitionsTable> {
         self.extract_definitions(ctx)
     }
 }
 
-impl DefinitionsSection {
+impl<'a> DefinitionsSection<'a> {
+    pub fn new(global: &'a DefinitionsTable) -> Self {
+        Self { global }
+    }
+
     fn extract_definitions(&self, ctx: ParseContext) -> miette::Result<DefinitionsTable> {
         let mut table = DefinitionsTable::default();
 
@@ -38,7 +52,8 @@ impl DefinitionsSection {
             ctx,
             optional("modifiers") => |ctx| self.parse_modifiers(ctx, &mut table),
             optional("plugins") => |ctx| self.parse_plugins(ctx, &mut table),
-            optional("key-profiles") => |ctx| self.parse_key_profiles(ctx, &mut table)
+            optional("key-profiles") => |ctx| self.parse_key_profiles(ctx, &mut table),
+            optional("geoip") => |ctx| self.parse_geoip(ctx, &mut table)
         );
 
         Ok(table)
@@ -108,6 +123,33 @@ impl DefinitionsSection {
         Ok(())
     }
 
+    /// `geoip { country "GeoLite2-Country.mmdb"; asn "GeoLite2-ASN.mmdb"; }`
+    fn parse_geoip(
+        &self,
+        ctx: ParseContext<'_>,
+        table: &mut DefinitionsTable,
+    ) -> miette::Result<()> {
+        ctx.validate(&[Rule::ReqChildren, Rule::NoArgs])?;
+
+        let database = |ctx: ParseContext<'_>| -> miette::Result<PathBuf> {
+            ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
+            Ok(PathBuf::from(ctx.first()?.as_str()?))
+        };
+
+        block_parser!(ctx.enter_block()?,
+            country: optional("country") => database,
+            asn: optional("asn") => database
+        );
+
+        if country.is_none() && asn.is_none() {
+            return Err(ctx.error("'geoip' needs a 'country' or an 'asn' database"));
+        }
+
+        table.set_geoip(GeoIpDatabases { country, asn });
+
+        Ok(())
+    }
+
     fn parse_modifiers(
         &self,
         ctx: ParseContext<'_>,
@@ -169,10 +211,185 @@ impl DefinitionsSection {
                     (Some(_), Some(_)) => Err(ctx.error("Duplicate source: provide either 'path' or 'url', not both")),
                     (None, None) => Err(ctx.error("'load' must provide either 'path' or 'url'")),
                 }
+            },
+
+            capabilities: optional("capabilities") => |ctx| self.parse_capabilities(ctx),
+
+            limits: optional("limits") => |ctx| self.parse_limits(ctx)
+        );
+
+        Ok(PluginDefinition {
+            name,
+            source,
+            capabilities: capabilities.unwrap_or_default(),
+            limits: limits.unwrap_or_default(),
+        })
+    }
+
+    /// `limits max-memory-mb=64 max-ms=5 on-failure="open"`
+    fn parse_limits(&self, ctx: ParseContext<'_>) -> miette::Result<PluginLimits> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("max-memory-mb", PrimitiveType::Integer),
+                ("max-ms", PrimitiveType::Integer),
+                ("on-failure", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let [max_memory, max_time, on_failure] =
+            ctx.props(["max-memory-mb", "max-ms", "on-failure"])?;
+
+        let max_memory = match max_memory.as_usize()? {
+            Some(0) => return Err(ctx.error("'max-memory-mb' must be greater than zero")),
+            max_memory => max_memory.map(|mb| mb * 1024 * 1024),
+        };
+
+        let max_time = match max_time.as_usize()? {
+            Some(0) => return Err(ctx.error("'max-ms' must be greater than zero")),
+            max_time => max_time.map(|ms| Duration::from_millis(ms as u64)),
+        };
+
+        let on_failure = match on_failure.as_str()?.as_deref() {
+            None | Some("closed") => FailurePolicy::Closed,
+            Some("open") => FailurePolicy::Open,
+            Some(other) => {
+                return Err(ctx.error(format!(
+                    "Unknown 'on-failure' policy '{other}', expected 'open' or 'closed'"
+                )))
             }
+        };
+
+        if max_memory.is_none() && max_time.is_none() {
+            return Err(ctx.error("'limits' needs 'max-memory-mb' or 'max-ms'"));
+        }
+
+        Ok(PluginLimits {
+            max_memory,
+            max_time,
+            on_failure,
+        })
+    }
+
+    fn parse_capabilities(&self, ctx: ParseContext<'_>) -> miette::Result<WasiCapabilities> {
+        ctx.validate(&[Rule::ReqChildren, Rule::NoArgs])?;
+
+        let block_ctx = ctx.enter_block()?;
+
+        block_parser!(block_ctx,
+            dirs: repeated("dir") => |ctx| {
+                ctx.validate(&[
+                    Rule::NoChildren,
+                    Rule::ExactArgs(1),
+                    Rule::OnlyKeysTyped(&[
+                        ("guest", PrimitiveType::String),
+                        ("read-only", PrimitiveType::Bool)
+                    ])
+                ])?;
+
+                let host = PathBuf::from(ctx.first()?.as_str()?);
+                let [guest, read_only] = ctx.props(["guest", "read-only"])?;
+
+                if !host.is_absolute() {
+                    return Err(ctx.error("'dir' must be an absolute path"));
+                }
+
+                Ok(PreopenedDir {
+                    guest: guest.as_str()?.unwrap_or_else(|| host.display().to_string()),
+                    read_only: read_only.as_bool()?.unwrap_or(true),
+                    host,
+                })
+            },
+
+            env: repeated("env") => |ctx| {
+                ctx.validate(&[
+                    Rule::NoChildren,
+                    Rule::ExactArgs(1),
+                    Rule::OnlyKeysTyped(&[("value", PrimitiveType::String)])
+                ])?;
+
+                Ok((ctx.first()?.as_str()?, ctx.prop("value")?.as_str()?))
+            },
+
+            allowed_hosts: repeated("allow-host") => |ctx| {
+                ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
+
+                let host = ctx.first()?.as_str()?;
+
+                match host.rsplit_once(':') {
+                    Some((name, port)) if !name.is_empty() && port.parse::<u16>().is_ok() => Ok(host),
+                    _ => Err(ctx.error(format!("'allow-host' expects 'host:port', got '{host}'"))),
+                }
+            },
+
+            request_body: optional("request-body") => |ctx| {
+                ctx.validate(&[
+                    Rule::NoChildren,
+                    Rule::NoPositionalArgs,
+                    Rule::OnlyKeysTyped(&[("max-bytes", PrimitiveType::Integer)])
+                ])?;
+
+                let max = ctx.opt_prop("max-bytes")?.as_usize()?.unwrap_or(MAX_PLUGIN_REQUEST_BODY);
+
+                if max == 0 || max > MAX_PLUGIN_REQUEST_BODY {
+                    return Err(ctx.error(format!(
+                        "'max-bytes' must be between 1 and {MAX_PLUGIN_REQUEST_BODY}"
+                    )));
+                }
+
+                Ok(max)
+            },
+
+            http_client: optional("http-client") => |ctx| self.parse_http_client(ctx)
         );
 
-        Ok(PluginDefinition { name, source })
+        if http_client.is_some() && allowed_hosts.is_empty() {
+            return Err(ctx.error("'http-client' needs an 'allow-host' for every host it calls"));
+        }
+
+        Ok(WasiCapabilities {
+            dirs,
+            env,
+            allowed_hosts,
+            request_body,
+            http_client,
+        })
+    }
+
+    fn parse_http_client(&self, ctx: ParseContext<'_>) -> miette::Result<HttpClientLimits> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("timeout-ms", PrimitiveType::Integer),
+                ("max-concurrent", PrimitiveType::Integer),
+                ("max-response-bytes", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let keys = ["timeout-ms", "max-concurrent", "max-response-bytes"];
+        let defaults = [1000, 16, 1024 * 1024];
+        let mut values = [0; 3];
+
+        for ((key, value), (default, out)) in keys
+            .iter()
+            .zip(ctx.props(keys)?)
+            .zip(defaults.into_iter().zip(&mut values))
+        {
+            *out = match value.as_usize()? {
+                Some(0) => return Err(ctx.error(format!("'{key}' must be greater than zero"))),
+                value => value.unwrap_or(default),
+            };
+        }
+
+        let [timeout, max_concurrent, max_response_bytes] = values;
+
+        Ok(HttpClientLimits {
+            timeout: Duration::from_millis(timeout as u64),
+            max_concurrent,
+            max_response_bytes,
+        })
     }
 
     fn parse_namespace_recursive(
@@ -232,7 +449,7 @@ impl DefinitionsSection {
             return Err(ctx.error(format!("Duplicate chain-filters name: '{}'", chain_name)));
         }
 
-        let chain = ChainParser.parse(ctx.enter_block()?)?;
+        let chain = ChainParser::new(self.global).parse(ctx.enter_block()?)?;
 
         table.insert_chain(chain_name, chain);
 
@@ -253,7 +470,7 @@ mod tests {
             connectors::{ALPN, Connectors, ConnectorsLeaf, RouteMatcher, UpstreamConfig},
             definitions::Modificator,
         },
-        internal::{DiscoveryKind, HealthCheckKind, SelectionKind},
+        internal::{DiscoveryConfig, HealthCheckKind, SelectionKind},
         kdl::{connectors::ConnectorsSection, definitions::DefinitionsSection, parser::{block::BlockParser, ctx::Current}},
     };
 
@@ -274,7 +491,9 @@ mod tests {
         let defs_ctx = ParseContext::new(&defs_doc, Current::Document(&defs_doc), "test");
         let mut defs_block = BlockParser::new(defs_ctx)?;
 
-        let table = defs_block.required("definitions", |ctx| DefinitionsSection.parse_node(ctx))?;
+        let table = defs_block.required("definitions", |ctx| {
+            DefinitionsSection::new(&DefinitionsTable::default()).parse_node(ctx)
+        })?;
 
         let conn_doc: KdlDocument = conn_input.parse().unwrap();
         let conn_ctx = ParseContext::new(&conn_doc, Current::Document(&conn_doc), "test");
@@ -307,7 +526,7 @@ mod tests {
         let lb_options = upstream.lb_options.clone().unwrap();
         assert_eq!(lb_options.selection, SelectionKind::RoundRobin);
         assert_eq!(lb_options.health_checks, HealthCheckKind::None);
-        assert_eq!(lb_options.discovery, DiscoveryKind::Static);
+        assert_eq!(lb_options.discovery, DiscoveryConfig::default());
         assert!(lb_options.template.is_none());
     }
 
@@ -983,4 +1202,195 @@ mod tests {
             panic!("Expected Static upstream");
         }
     }
+
+    fn parse_definitions(input: &str) -> miette::Result<DefinitionsTable> {
+        let doc: KdlDocument = input.parse().unwrap();
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let mut block = BlockParser::new(ctx)?;
+
+        block.required("definitions", |ctx| {
+            DefinitionsSection::new(&DefinitionsTable::default()).parse_node(ctx)
+        })
+    }
+
+    #[test]
+    fn test_plugin_capabilities() {
+        let table = parse_definitions(
+            r#"
+            definitions {
+                plugins {
+                    plugin {
+                        name "geo"
+                        load path="geo.wasm"
+                        capabilities {
+                            dir "/var/lib/geo" guest="/data"
+                            dir "/var/log/geo" read-only=#false
+                            env "GEO_REGION" value="eu"
+                            allow-host "api.example.com:443"
+                            request-body max-bytes=4096
+                            http-client timeout-ms=500 max-concurrent=4
+                        }
+                    }
+                    plugin {
+                        name "plain"
+                        load path="plain.wasm"
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let plugins = table.get_plugins();
+
+        assert_eq!(
+            plugins[&FQDN::from_str("geo").unwrap()].capabilities,
+            WasiCapabilities {
+                dirs: vec![
+                    PreopenedDir {
+                        host: "/var/lib/geo".into(),
+                        guest: "/data".into(),
+                        read_only: true,
+                    },
+                    PreopenedDir {
+                        host: "/var/log/geo".into(),
+                        guest: "/var/log/geo".into(),
+                        read_only: false,
+                    },
+                ],
+                env: vec![("GEO_REGION".into(), "eu".into())],
+                allowed_hosts: vec!["api.example.com:443".into()],
+                request_body: Some(4096),
+                http_client: Some(HttpClientLimits {
+                    timeout: Duration::from_millis(500),
+                    max_concurrent: 4,
+                    max_response_bytes: 1024 * 1024,
+                }),
+            }
+        );
+        assert_eq!(
+            plugins[&FQDN::from_str("plain").unwrap()].capabilities,
+            WasiCapabilities::default()
+        );
+    }
+
+    #[test]
+    fn test_plugin_capabilities_errors() {
+        let cases = [
+            (r#"dir "data""#, "must be an absolute path"),
+            (r#"allow-host "api.example.com""#, "expects 'host:port'"),
+            (r#"env "GEO_REGION""#, "Missing required property 'value'"),
+            ("request-body max-bytes=0", "between 1 and 65536"),
+            ("request-body max-bytes=100000", "between 1 and 65536"),
+            ("http-client", "needs an 'allow-host'"),
+            (
+                r#"allow-host "auth:80"; http-client timeout-ms=0"#,
+                "'timeout-ms' must be greater than zero",
+            ),
+        ];
+
+        for (capability, expected) in cases {
+            let err = parse_definitions(&format!(
+                r#"
+                definitions {{
+                    plugins {{
+                        plugin {{
+                            name "geo"
+                            load path="geo.wasm"
+                            capabilities {{
+                                {capability}
+                            }}
+                        }}
+                    }}
+                }}
+                "#
+            ))
+            .unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_plugin_limits() {
+        let plugin = |limits: &str| {
+            parse_definitions(&format!(
+                r#"
+                definitions {{
+                    plugins {{
+                        plugin {{
+                            name "geo"
+                            load path="geo.wasm"
+                            {limits}
+                        }}
+                    }}
+                }}
+                "#
+            ))
+        };
+
+        let table = plugin(r#"limits max-memory-mb=64 max-ms=5 on-failure="open""#).unwrap();
+        assert_eq!(
+            table.get_plugins()[&FQDN::from_str("geo").unwrap()].limits,
+            PluginLimits {
+                max_memory: Some(64 * 1024 * 1024),
+                max_time: Some(Duration::from_millis(5)),
+                on_failure: FailurePolicy::Open,
+            }
+        );
+
+        let table = plugin("").unwrap();
+        assert_eq!(
+            table.get_plugins()[&FQDN::from_str("geo").unwrap()].limits,
+            PluginLimits::default()
+        );
+
+        let cases = [
+            ("limits", "needs 'max-memory-mb' or 'max-ms'"),
+            ("limits max-ms=0", "'max-ms' must be greater than zero"),
+            (
+                r#"limits max-ms=5 on-failure="ignore""#,
+                "expected 'open' or 'closed'",
+            ),
+        ];
+
+        for (limits, expected) in cases {
+            let err = plugin(limits).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_geoip() {
+        let table = parse_definitions(
+            r#"
+            definitions {
+                geoip {
+                    country "/var/lib/GeoIP/GeoLite2-Country.mmdb"
+                    asn "/var/lib/GeoIP/GeoLite2-ASN.mmdb"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            table.get_geoip(),
+            Some(&GeoIpDatabases {
+                country: Some("/var/lib/GeoIP/GeoLite2-Country.mmdb".into()),
+                asn: Some("/var/lib/GeoIP/GeoLite2-ASN.mmdb".into()),
+            })
+        );
+
+        let err = parse_definitions("definitions { geoip { city \"a.mmdb\"; }; }").unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "Unknown directive");
+
+        let err = parse_definitions("definitions { geoip { asn; }; }").unwrap_err();
+        assert!(err.help().is_some());
+
+        let err = parse_definitions("definitions { geoip { }; }").unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "needs a 'country' or an 'asn' database"
+        );
+    }
 }
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+use motya_macro::validate;
+
+use crate::{
+    common_types::section_parser::SectionParser,
+    kdl::parser::{ctx::ParseContext, ensures::Rule},
+};
+
+/// Parses the `flags` section, the initial state of the runtime feature flags:
+///
+/// ```kdl
+/// flags {
+///     new-router #true
+///     legacy-auth #false
+/// }
+/// ```
+pub struct FlagsSection;
+
+impl SectionParser<ParseContext<'_>, BTreeMap<String, bool>> for FlagsSection {
+    #[validate(ensure_node_name = "flags")]
+    fn parse_node(&self, ctx: ParseContext) -> miette::Result<BTreeMap<String, bool>> {
+        ctx.validate(&[Rule::NoArgs])?;
+
+        let mut flags = BTreeMap::new();
+
+        for node in ctx.req_nodes()? {
+            node.validate(&[Rule::NoChildren, Rule::ExactArgs(1), Rule::OnlyKeys(&[])])?;
+
+            let name = node.name()?.to_string();
+            if name.starts_with('!') {
+                return Err(node.error(format!("Flag '{name}' must not start with '!'")));
+            }
+
+            let enabled = node.first()?.as_bool()?;
+            if flags.insert(name.clone(), enabled).is_some() {
+                return Err(node.error(format!("Flag '{name}' is declared twice")));
+            }
+        }
+
+        Ok(flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_err_contains;
+    use crate::kdl::parser::block::BlockParser;
+    use crate::kdl::parser::ctx::Current;
+    use kdl::KdlDocument;
+
+    fn parse_flags(input: &str) -> miette::Result<BTreeMap<String, bool>> {
+        let doc: KdlDocument = input.parse().unwrap();
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test.kdl");
+        let mut block = BlockParser::new(ctx)?;
+
+        block.required("flags", |ctx| FlagsSection.parse_node(ctx))
+    }
+
+    #[test]
+    fn test_flags() {
+        let flags = parse_flags(
+            r#"
+            flags {
+                new-router #true
+                legacy-auth #false
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            flags,
+            BTreeMap::from([
+                ("legacy-auth".to_string(), false),
+                ("new-router".to_string(), true),
+            ])
+        );
+
+        let err = parse_flags("flags { new-router #true; new-router #false; }").unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "declared twice");
+
+        assert!(parse_flags(r#"flags { new-router "yes"; }"#).is_err());
+    }
+}
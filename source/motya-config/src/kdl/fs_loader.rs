@@ -1,11 +1,13 @@
 use crate::common_types::section_parser::SectionParser;
 use crate::config_source::ConfigSource;
-use crate::kdl::includes::IncludesSection;
+use crate::kdl::includes::{decode_hex, Include, IncludesSection};
 use crate::kdl::parser::block::BlockParser;
 use crate::kdl::parser::ctx::{Current, ParseContext};
 use async_recursion::async_recursion;
+use ed25519_dalek::Signature;
 use kdl::KdlDocument;
 use miette::{miette, Context, IntoDiagnostic, Result};
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
 use std::future::Future;
 use std::marker::PhantomData;
@@ -14,6 +16,29 @@ use std::path::{Path, PathBuf};
 pub trait AsyncFs: Send + Sync + Clone + Default {
     fn canonicalize(path: &Path) -> impl Future<Output = Result<PathBuf>> + Send;
     fn read_to_string(path: &Path) -> impl Future<Output = Result<String>> + Send;
+    /// Writes `contents` to `path`, creating its directory when missing.
+    fn write(path: &Path, contents: &str) -> impl Future<Output = Result<()>> + Send;
+    /// Fetches the document of an `http(s)://` include.
+    fn fetch(url: &str) -> impl Future<Output = Result<String>> + Send;
+}
+
+/// Directory keeping the last fetched copy of the URL includes of `entry_path`.
+///
+/// `/etc/motya/entry.kdl` keeps them in `/etc/motya/entry.include-cache/`.
+pub fn include_cache_dir(entry_path: &Path) -> PathBuf {
+    entry_path.with_extension("include-cache")
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+enum Location {
+    File(PathBuf),
+    Remote(Include),
 }
 
 #[derive(Default, Clone)]
@@ -21,12 +46,18 @@ pub struct FileCollector<F: AsyncFs> {
     fs: PhantomData<F>,
     documents: Vec<(KdlDocument, String)>,
     visited_paths: HashSet<PathBuf>,
+    visited_urls: HashSet<String>,
+    cache_dir: Option<PathBuf>,
 }
 
 impl<F: AsyncFs> ConfigSource for FileCollector<F> {
     async fn collect(&self, entry_path: PathBuf) -> Result<Vec<(KdlDocument, String)>> {
         Self::collect(self.clone(), entry_path).await
     }
+
+    async fn sources(&self, entry_path: PathBuf) -> Result<Vec<PathBuf>> {
+        Self::collect_sources(self.clone(), entry_path).await
+    }
 }
 
 impl<Fs: AsyncFs> FileCollector<Fs> {
@@ -35,45 +66,387 @@ impl<Fs: AsyncFs> FileCollector<Fs> {
             .await
             .context("Failed to resolve entry point")?;
 
-        self.load_recursive(root_path).await?;
+        self.cache_dir = Some(include_cache_dir(&root_path));
+        self.load_recursive(Location::File(root_path)).await?;
 
         Ok(self.documents)
     }
 
+    /// Walks the includes starting at `entry_path` and returns every visited file.
+    pub async fn collect_sources(mut self, entry_path: PathBuf) -> Result<Vec<PathBuf>> {
+        let root_path = Fs::canonicalize(&entry_path)
+            .await
+            .context("Failed to resolve entry point")?;
+
+        self.cache_dir = Some(include_cache_dir(&root_path));
+        self.load_recursive(Location::File(root_path)).await?;
+
+        Ok(self.visited_paths.into_iter().collect())
+    }
+
     #[async_recursion]
-    async fn load_recursive(&mut self, path: PathBuf) -> Result<()> {
-        if self.visited_paths.contains(&path) {
-            return Ok(());
-        }
-        self.visited_paths.insert(path.clone());
+    async fn load_recursive(&mut self, location: Location) -> Result<()> {
+        let (content, name, label) = match &location {
+            Location::File(path) => {
+                if !self.visited_paths.insert(path.clone()) {
+                    return Ok(());
+                }
 
-        let content = Fs::read_to_string(&path)
-            .await
-            .wrap_err_with(|| format!("Failed to read file: {:?}", path))?;
+                let content = Fs::read_to_string(path)
+                    .await
+                    .wrap_err_with(|| format!("Failed to read file: {:?}", path))?;
+
+                let name = path
+                    .file_name()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .ok_or_else(|| miette!("It's not a file: {:?}", path))?;
+
+                (content, name, format!("{:?}", path))
+            }
+            Location::Remote(include) => {
+                if !self.visited_urls.insert(include.location.clone()) {
+                    return Ok(());
+                }
+
+                let content = self.fetch(include).await?;
+                (content, include.location.clone(), include.location.clone())
+            }
+        };
 
         let doc: KdlDocument = content
             .parse()
             .into_diagnostic()
-            .wrap_err_with(|| format!("Failed to parse KDL: {:?}", path))?;
-
-        let name = path
-            .file_name()
-            .map(|s| s.to_string_lossy())
-            .ok_or_else(|| miette!("It's not a file: {:?}", path))?;
+            .wrap_err_with(|| format!("Failed to parse KDL: {label}"))?;
 
         let mut block = BlockParser::new(ParseContext::new(&doc, Current::Document(&doc), &name))?;
 
         let raw_includes = block.optional("includes", |ctx| IncludesSection.parse_node(ctx))?.unwrap_or(vec![]);
 
-        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        for mut include in raw_includes {
+            // URLs found in a signed document must be signed by the same keys.
+            if let Location::Remote(parent) = &location {
+                if include.trusted_keys.is_empty() {
+                    include.trusted_keys = parent.trusted_keys.clone();
+                }
+            }
+
+            let next = match &location {
+                _ if include.is_remote() => Location::Remote(include),
+                Location::File(path) => {
+                    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+                    Location::File(base_dir.join(&include.location))
+                }
+                Location::Remote(_) => {
+                    return Err(miette!(
+                        "Relative include '{}' in '{}' is not supported, remote documents may only include URLs",
+                        include.location,
+                        name
+                    ))
+                }
+            };
+
+            self.load_recursive(next).await?;
+        }
+
+        self.documents.push((doc, name));
+        Ok(())
+    }
+
+    /// Fetches a URL include, falling back to the copy cached by the last
+    /// successful fetch when the server cannot be reached.
+    async fn fetch(&self, include: &Include) -> Result<String> {
+        let url = &include.location;
+        let cache_path = self
+            .cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.kdl", sha256_hex(url.as_bytes()))));
+        let signature_path = cache_path
+            .as_ref()
+            .map(|path| path.with_extension("kdl.sig"));
+
+        match Self::fetch_remote(include).await {
+            Ok((content, signature)) => {
+                Self::verify(include, &content, signature.as_deref())?;
+
+                if let Some(path) = &cache_path {
+                    if let Err(err) = Fs::write(path, &content).await {
+                        tracing::warn!("Failed to cache include '{url}' at {:?}: {err}", path);
+                    }
+                }
+                if let (Some(path), Some(signature)) = (&signature_path, &signature) {
+                    if let Err(err) = Fs::write(path, signature).await {
+                        tracing::warn!("Failed to cache signature of '{url}' at {:?}: {err}", path);
+                    }
+                }
+
+                Ok(content)
+            }
+            Err(err) => {
+                let cached = match &cache_path {
+                    Some(path) => Fs::read_to_string(path).await.ok(),
+                    None => None,
+                };
+                let Some(content) = cached else {
+                    return Err(err.wrap_err(format!(
+                        "Failed to fetch include '{url}' and no cached copy exists"
+                    )));
+                };
+
+                let signature = match &signature_path {
+                    Some(path) if !include.trusted_keys.is_empty() => {
+                        Fs::read_to_string(path).await.ok()
+                    }
+                    _ => None,
+                };
+
+                Self::verify(include, &content, signature.as_deref())?;
+                tracing::warn!("Failed to fetch include '{url}', using the cached copy: {err}");
+
+                Ok(content)
+            }
+        }
+    }
+
+    /// Fetches a URL include and, when it must be signed, its signature.
+    async fn fetch_remote(include: &Include) -> Result<(String, Option<String>)> {
+        let content = Fs::fetch(&include.location).await?;
+
+        if include.trusted_keys.is_empty() {
+            return Ok((content, None));
+        }
+
+        let signature = Fs::fetch(&include.signature_url())
+            .await
+            .wrap_err_with(|| format!("Failed to fetch the signature of '{}'", include.location))?;
+
+        Ok((content, Some(signature)))
+    }
+
+    fn verify(include: &Include, content: &str, signature: Option<&str>) -> Result<()> {
+        if let Some(expected) = &include.sha256 {
+            let actual = sha256_hex(content.as_bytes());
+            if actual != *expected {
+                return Err(miette!(
+                    "Include '{}' does not match its pinned sha256: expected {expected}, got {actual}",
+                    include.location
+                ));
+            }
+        }
+
+        if include.trusted_keys.is_empty() {
+            return Ok(());
+        }
+
+        let signature = signature
+            .and_then(|signature| decode_hex(signature.trim()))
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+            .map(|bytes| Signature::from_bytes(&bytes))
+            .ok_or_else(|| {
+                miette!(
+                    "Include '{}' has no valid signature, expected 128 hex digits at '{}'",
+                    include.location,
+                    include.signature_url()
+                )
+            })?;
 
-        for path_str in raw_includes {
-            let include_path = base_dir.join(path_str);
+        let trusted = include
+            .trusted_keys
+            .iter()
+            .any(|key| key.verify_strict(content.as_bytes(), &signature).is_ok());
 
-            self.load_recursive(include_path).await?;
+        if !trusted {
+            return Err(miette!(
+                "Include '{}' is not signed by any of the trusted keys",
+                include.location
+            ));
         }
 
-        self.documents.push((doc, name.to_string()));
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_err_contains;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    static FILES: Mutex<Option<HashMap<PathBuf, String>>> = Mutex::new(None);
+    static REMOTE: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+    #[derive(Default, Clone)]
+    struct MemoryFs;
+
+    impl AsyncFs for MemoryFs {
+        async fn canonicalize(path: &Path) -> Result<PathBuf> {
+            Ok(path.to_path_buf())
+        }
+
+        async fn read_to_string(path: &Path) -> Result<String> {
+            let files = FILES.lock().unwrap();
+            files
+                .as_ref()
+                .and_then(|files| files.get(path).cloned())
+                .ok_or_else(|| miette!("no such file: {:?}", path))
+        }
+
+        async fn write(path: &Path, contents: &str) -> Result<()> {
+            let mut files = FILES.lock().unwrap();
+            files
+                .get_or_insert_with(HashMap::new)
+                .insert(path.to_path_buf(), contents.to_string());
+            Ok(())
+        }
+
+        async fn fetch(url: &str) -> Result<String> {
+            let remote = REMOTE.lock().unwrap();
+            remote
+                .as_ref()
+                .and_then(|remote| remote.get(url).cloned())
+                .ok_or_else(|| miette!("connection refused: {url}"))
+        }
+    }
+
+    fn file(path: &str, contents: &str) {
+        let mut files = FILES.lock().unwrap();
+        files
+            .get_or_insert_with(HashMap::new)
+            .insert(path.into(), contents.to_string());
+    }
+
+    fn serve(url: &str, contents: Option<&str>) {
+        let mut remote = REMOTE.lock().unwrap();
+        let remote = remote.get_or_insert_with(HashMap::new);
+        match contents {
+            Some(contents) => remote.insert(url.to_string(), contents.to_string()),
+            None => remote.remove(url),
+        };
+    }
+
+    const REMOTE_DOC: &str = "definitions { }\n";
+
+    fn names(documents: &[(KdlDocument, String)]) -> Vec<&str> {
+        documents.iter().map(|(_, name)| name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_remote_include_and_cache_fallback() {
+        let url = "https://config.internal/fleet/common.kdl";
+        let pin = sha256_hex(REMOTE_DOC.as_bytes());
+
+        file(
+            "/remote-test/entry.kdl",
+            &format!("includes {{\n    \"{url}\" sha256=\"{pin}\"\n}}\nsystem {{ }}\n"),
+        );
+        serve(url, Some(REMOTE_DOC));
+
+        let documents = FileCollector::<MemoryFs>::default()
+            .collect("/remote-test/entry.kdl".into())
+            .await
+            .unwrap();
+        assert_eq!(names(&documents), [url, "entry.kdl"]);
+
+        // The server is gone, the copy cached by the last fetch is used.
+        serve(url, None);
+        let documents = FileCollector::<MemoryFs>::default()
+            .collect("/remote-test/entry.kdl".into())
+            .await
+            .unwrap();
+        assert_eq!(names(&documents), [url, "entry.kdl"]);
+
+        // A changed document does not match the pin.
+        serve(url, Some("definitions { }\nsystem { }\n"));
+        let err = FileCollector::<MemoryFs>::default()
+            .collect("/remote-test/entry.kdl".into())
+            .await
+            .unwrap_err();
+        assert_err_contains!(err.to_string(), "does not match its pinned sha256");
+    }
+
+    #[tokio::test]
+    async fn test_remote_include_without_cache() {
+        let url = "https://config.internal/fleet/missing.kdl";
+        file(
+            "/missing-test/entry.kdl",
+            &format!("includes {{\n    \"{url}\"\n}}\n"),
+        );
+
+        let err = FileCollector::<MemoryFs>::default()
+            .collect("/missing-test/entry.kdl".into())
+            .await
+            .unwrap_err();
+        assert_err_contains!(err.to_string(), "no cached copy exists");
+    }
+
+    #[tokio::test]
+    async fn test_remote_document_with_relative_include() {
+        let url = "https://config.internal/fleet/relative.kdl";
+        file(
+            "/relative-test/entry.kdl",
+            &format!("includes {{\n    \"{url}\"\n}}\n"),
+        );
+        serve(url, Some("includes {\n    \"other.kdl\"\n}\n"));
+
+        let err = FileCollector::<MemoryFs>::default()
+            .collect("/relative-test/entry.kdl".into())
+            .await
+            .unwrap_err();
+        assert_err_contains!(err.to_string(), "remote documents may only include URLs");
+    }
+
+    // RFC 8032, test 1, signing `REMOTE_DOC` and `SIGNED_INDEX`.
+    const PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    const REMOTE_DOC_SIG: &str = "77f2f9288b093ca07c070da32c4d255b1176076ff9205416e5635994db05e98c1e4acce236b3e676b7aa09c4f5df8492bb8ed76e4ecfb600accc725e45b4fc0c";
+    const SIGNED_INDEX: &str = "includes {\n    \"https://config.internal/signed/nested.kdl\"\n}\n";
+    const SIGNED_INDEX_SIG: &str = "8a61c9de5a40b7c129bcad1345f95bd664c55a2a1eba0d0131db6b3a8814540d3326dfb83f35058c7d0a7d64ffbe87df56dfde3165cfce000c31d40afad32f0b";
+
+    #[tokio::test]
+    async fn test_signed_includes() {
+        let index = "https://config.internal/signed/index.kdl";
+        let nested = "https://config.internal/signed/nested.kdl";
+
+        file(
+            "/signed-test/entry.kdl",
+            &format!("includes trusted-keys=\"{PUBLIC_KEY}\" {{\n    \"{index}\"\n}}\n"),
+        );
+        serve(index, Some(SIGNED_INDEX));
+        serve(
+            &format!("{index}.sig"),
+            Some(&format!("{SIGNED_INDEX_SIG}\n")),
+        );
+        serve(nested, Some(REMOTE_DOC));
+        serve(&format!("{nested}.sig"), Some(REMOTE_DOC_SIG));
+
+        let documents = FileCollector::<MemoryFs>::default()
+            .collect("/signed-test/entry.kdl".into())
+            .await
+            .unwrap();
+        assert_eq!(names(&documents), [nested, index, "entry.kdl"]);
+
+        // The nested include is checked with the keys of the signed index.
+        serve(nested, Some("definitions { }\nsystem { }\n"));
+        let err = FileCollector::<MemoryFs>::default()
+            .collect("/signed-test/entry.kdl".into())
+            .await
+            .unwrap_err();
+        assert_err_contains!(err.to_string(), "not signed by any of the trusted keys");
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_include_is_refused() {
+        let url = "https://config.internal/unsigned/common.kdl";
+        file(
+            "/unsigned-test/entry.kdl",
+            &format!("includes trusted-keys=\"{PUBLIC_KEY}\" {{\n    \"{url}\"\n}}\n"),
+        );
+        serve(url, Some(REMOTE_DOC));
+        serve(&format!("{url}.sig"), Some("not a signature"));
+
+        let err = FileCollector::<MemoryFs>::default()
+            .collect("/unsigned-test/entry.kdl".into())
+            .await
+            .unwrap_err();
+        assert_err_contains!(err.to_string(), "has no valid signature");
+    }
+}
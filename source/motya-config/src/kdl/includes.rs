@@ -3,35 +3,130 @@ use crate::{
     kdl::parser::{
         ctx::ParseContext,
         ensures::Rule,
+        utils::{OptionTypedValueExt, PrimitiveType},
     },
 };
+use ed25519_dalek::VerifyingKey;
 use miette::Result;
 use motya_macro::validate;
 
+/// An entry of the `includes` section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Include {
+    /// Path relative to the including file, or an `http(s)://` URL.
+    pub location: String,
+    /// Hex SHA-256 a fetched URL must match.
+    pub sha256: Option<String>,
+    /// Keys one of which must have signed a fetched URL, unsigned when empty.
+    pub trusted_keys: Vec<VerifyingKey>,
+}
+
+impl Include {
+    pub fn is_remote(&self) -> bool {
+        self.location.starts_with("https://") || self.location.starts_with("http://")
+    }
+
+    /// Where the signature of a URL include is fetched from.
+    pub fn signature_url(&self) -> String {
+        format!("{}.sig", self.location)
+    }
+}
+
+/// Decodes a string of hex digits, `None` when it is not one.
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
 pub struct IncludesSection;
 
-impl SectionParser<ParseContext<'_>, Vec<String>> for IncludesSection {
+impl SectionParser<ParseContext<'_>, Vec<Include>> for IncludesSection {
     #[validate(ensure_node_name = "includes")]
-    fn parse_node(&self, ctx: ParseContext) -> miette::Result<Vec<String>> {
+    fn parse_node(&self, ctx: ParseContext) -> miette::Result<Vec<Include>> {
         self.extract_includes(ctx)
     }
 }
 
 impl IncludesSection {
-    fn extract_includes(&self, ctx: ParseContext) -> Result<Vec<String>> {
+    fn extract_includes(&self, ctx: ParseContext) -> Result<Vec<Include>> {
+        ctx.validate(&[
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[("trusted-keys", PrimitiveType::String)]),
+        ])?;
+
+        let trusted_keys = match ctx.opt_prop("trusted-keys")?.as_str()? {
+            Some(keys) => self.parse_trusted_keys(&ctx, &keys)?,
+            None => Vec::new(),
+        };
+
         let result = ctx
             .req_nodes()?
             .iter()
             .map(|node| {
-                let path = node.name()?;
+                let location = node.name()?.to_string();
+
+                node.validate(&[
+                    Rule::NoChildren,
+                    Rule::NoPositionalArgs,
+                    Rule::OnlyKeysTyped(&[("sha256", PrimitiveType::String)]),
+                ])?;
+
+                let [sha256] = node.props(["sha256"])?;
+                let mut include = Include {
+                    location,
+                    sha256: sha256.as_str()?.map(|hash| hash.to_ascii_lowercase()),
+                    trusted_keys: Vec::new(),
+                };
+
+                if include.is_remote() {
+                    include.trusted_keys = trusted_keys.clone();
+                }
+
+                if let Some(hash) = &include.sha256 {
+                    if !include.is_remote() {
+                        return Err(node.error("'sha256' is only supported on URL includes"));
+                    }
+                    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(node.error("'sha256' must be 64 hex digits"));
+                    }
+                }
+
+                Ok(include)
+            })
+            .collect::<Result<Vec<Include>>>()?;
 
-                node.validate(&[Rule::NoArgs, Rule::NoChildren])?;
+        Ok(result)
+    }
 
-                Ok(path.to_string())
+    /// Comma separated hex ed25519 public keys.
+    fn parse_trusted_keys(&self, ctx: &ParseContext, keys: &str) -> Result<Vec<VerifyingKey>> {
+        let keys = keys
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                decode_hex(key)
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+                    .ok_or_else(|| {
+                        ctx.error(format!(
+                            "'{key}' is not an ed25519 public key of 64 hex digits"
+                        ))
+                    })
             })
-            .collect::<Result<Vec<String>>>()?;
+            .collect::<Result<Vec<_>>>()?;
 
-        Ok(result)
+        if keys.is_empty() {
+            return Err(ctx.error("'trusted-keys' must list at least one key"));
+        }
+
+        Ok(keys)
     }
 }
 #[cfg(test)]
@@ -42,7 +137,7 @@ mod tests {
     use crate::kdl::parser::ctx::Current;
     use kdl::KdlDocument;
 
-    fn parse_includes(input: &str) -> miette::Result<Vec<String>> {
+    fn parse_includes(input: &str) -> miette::Result<Vec<Include>> {
         let doc: KdlDocument = input.parse().unwrap();
 
         let ctx = ParseContext::new(&doc, Current::Document(&doc), "test.kdl");
@@ -66,9 +161,9 @@ mod tests {
         let paths = parse_includes(VALID_INCLUDES).expect("Should parse valid includes");
 
         assert_eq!(paths.len(), 3);
-        assert_eq!(paths[0], "path/to/first.kdl");
-        assert_eq!(paths[1], "second.kdl");
-        assert_eq!(paths[2], "../parent/config.kdl");
+        assert_eq!(paths[0].location, "path/to/first.kdl");
+        assert_eq!(paths[1].location, "second.kdl");
+        assert_eq!(paths[2].location, "../parent/config.kdl");
     }
 
     const EMPTY_INCLUDES_BLOCK: &str = r#"
@@ -155,8 +250,8 @@ mod tests {
         let paths = parse_includes(COMPLEX_DOCUMENT_WITH_INCLUDES).expect("Should parse includes");
 
         assert_eq!(paths.len(), 2);
-        assert_eq!(paths[0], "definitions.kdl");
-        assert_eq!(paths[1], "plugins/rate-limiter.kdl");
+        assert_eq!(paths[0].location, "definitions.kdl");
+        assert_eq!(paths[1].location, "plugins/rate-limiter.kdl");
     }
 
     const INCLUDE_WITH_COMMENTS: &str = r#"
@@ -175,9 +270,9 @@ mod tests {
         let paths = parse_includes(INCLUDE_WITH_COMMENTS).expect("Should ignore comments");
 
         assert_eq!(paths.len(), 3);
-        assert_eq!(paths[0], "dev/overrides.kdl");
-        assert_eq!(paths[1], "prod/settings.kdl");
-        assert_eq!(paths[2], "common.kdl");
+        assert_eq!(paths[0].location, "dev/overrides.kdl");
+        assert_eq!(paths[1].location, "prod/settings.kdl");
+        assert_eq!(paths[2].location, "common.kdl");
     }
 
     const INCLUDE_WITH_SPECIAL_CHARACTERS: &str = r#"
@@ -195,9 +290,71 @@ mod tests {
             .expect("Should parse paths with special chars");
 
         assert_eq!(paths.len(), 4);
-        assert_eq!(paths[0], "path with spaces.kdl");
-        assert_eq!(paths[1], "C:\\Windows\\Path\\config.kdl");
-        assert_eq!(paths[2], "/unix/path/with-dashes.kdl");
-        assert_eq!(paths[3], "relative/../parent/./current/config.kdl");
+        assert_eq!(paths[0].location, "path with spaces.kdl");
+        assert_eq!(paths[1].location, "C:\\Windows\\Path\\config.kdl");
+        assert_eq!(paths[2].location, "/unix/path/with-dashes.kdl");
+        assert_eq!(paths[3].location, "relative/../parent/./current/config.kdl");
+    }
+
+    #[test]
+    fn test_remote_includes() {
+        let hash = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+        let includes = parse_includes(&format!(
+            r#"
+            includes {{
+                "https://config.internal/fleet/common.kdl" sha256="{hash}"
+                "http://config.internal/fleet/extra.kdl"
+                "local.kdl"
+            }}
+            "#
+        ))
+        .unwrap();
+
+        assert_eq!(
+            includes[0],
+            Include {
+                location: "https://config.internal/fleet/common.kdl".into(),
+                sha256: Some(hash.to_ascii_lowercase()),
+                trusted_keys: vec![],
+            }
+        );
+        assert!(includes[1].is_remote());
+        assert_eq!(includes[1].sha256, None);
+        assert!(!includes[2].is_remote());
+
+        let err = parse_includes(r#"includes { "local.kdl" sha256="ab" }"#).unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "only supported on URL");
+
+        let err = parse_includes(r#"includes { "https://config.internal/a.kdl" sha256="ab" }"#)
+            .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "64 hex digits");
+    }
+
+    // RFC 8032, test 1.
+    const PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+    #[test]
+    fn test_trusted_keys() {
+        let includes = parse_includes(&format!(
+            r#"
+            includes trusted-keys="{PUBLIC_KEY}" {{
+                "https://config.internal/fleet/common.kdl"
+                "local.kdl"
+            }}
+            "#
+        ))
+        .unwrap();
+
+        assert_eq!(includes[0].trusted_keys.len(), 1);
+        assert_eq!(
+            includes[0].trusted_keys[0].as_bytes().as_slice(),
+            decode_hex(PUBLIC_KEY).unwrap()
+        );
+        assert!(includes[1].trusted_keys.is_empty());
+
+        let err =
+            parse_includes(r#"includes trusted-keys="abcd" { "https://config.internal/a.kdl" }"#)
+                .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "not an ed25519 public key");
     }
 }
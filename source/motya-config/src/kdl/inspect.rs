@@ -0,0 +1,257 @@
+//! The configuration as `print-config` shows it: every included document
+//! merged into one, in KDL or as JSON.
+
+use kdl::{KdlDocument, KdlNode, KdlValue};
+
+/// Top-level sections in the order they are printed, `includes` are resolved
+/// and dropped.
+const SECTIONS: &[&str] = &["system", "flags", "definitions", "services"];
+
+/// Merges the documents of a configuration in loading order. The `definitions`
+/// of every file end up in one section, with one block per kind of definition.
+pub fn merge(documents: &[(KdlDocument, String)]) -> KdlDocument {
+    let mut merged = KdlDocument::new();
+
+    for &section in SECTIONS {
+        let nodes: Vec<_> = documents
+            .iter()
+            .flat_map(|(doc, _)| doc.nodes())
+            .filter(|node| node.name().value() == section)
+            .collect();
+
+        if nodes.is_empty() {
+            continue;
+        }
+
+        let children = nodes
+            .into_iter()
+            .filter_map(|node| node.children())
+            .flat_map(|children| children.nodes().iter().cloned());
+
+        let mut block = KdlDocument::new();
+        match section {
+            "definitions" => block.nodes_mut().extend(merge_by_name(children)),
+            _ => block.nodes_mut().extend(children),
+        }
+
+        let mut node = KdlNode::new(section);
+        node.set_children(block);
+        merged.nodes_mut().push(node);
+    }
+
+    merged.autoformat();
+    merged
+}
+
+/// Folds the children of nodes sharing a name into the first of them.
+fn merge_by_name(nodes: impl Iterator<Item = KdlNode>) -> Vec<KdlNode> {
+    let mut merged: Vec<KdlNode> = Vec::new();
+
+    for node in nodes {
+        match merged
+            .iter_mut()
+            .find(|existing| existing.name().value() == node.name().value())
+        {
+            Some(existing) => {
+                if let Some(children) = node.children() {
+                    existing
+                        .ensure_children()
+                        .nodes_mut()
+                        .extend(children.nodes().iter().cloned());
+                }
+            }
+            None => merged.push(node),
+        }
+    }
+
+    merged
+}
+
+/// Renders a document as a JSON array of its nodes, each an object with its
+/// `name`, and its `args`, `props` and `children` when it has any.
+pub fn to_json(doc: &KdlDocument) -> String {
+    let mut out = String::new();
+    write_document(&mut out, doc, 0);
+    out.push('\n');
+    out
+}
+
+fn write_document(out: &mut String, doc: &KdlDocument, depth: usize) {
+    if doc.nodes().is_empty() {
+        out.push_str("[]");
+        return;
+    }
+
+    out.push('[');
+    for (i, node) in doc.nodes().iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(out, depth + 1);
+        write_node(out, node, depth + 1);
+    }
+    newline(out, depth);
+    out.push(']');
+}
+
+fn write_node(out: &mut String, node: &KdlNode, depth: usize) {
+    let args: Vec<_> = node
+        .entries()
+        .iter()
+        .filter(|entry| entry.name().is_none())
+        .map(|entry| entry.value())
+        .collect();
+    let props: Vec<_> = node
+        .entries()
+        .iter()
+        .filter_map(|entry| Some((entry.name()?.value(), entry.value())))
+        .collect();
+
+    out.push('{');
+    newline(out, depth + 1);
+    out.push_str("\"name\": ");
+    write_string(out, node.name().value());
+
+    if !args.is_empty() {
+        out.push(',');
+        newline(out, depth + 1);
+        out.push_str("\"args\": [");
+        for (i, value) in args.into_iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            write_value(out, value);
+        }
+        out.push(']');
+    }
+
+    if !props.is_empty() {
+        out.push(',');
+        newline(out, depth + 1);
+        out.push_str("\"props\": {");
+        for (i, (name, value)) in props.into_iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            write_string(out, name);
+            out.push_str(": ");
+            write_value(out, value);
+        }
+        out.push('}');
+    }
+
+    if let Some(children) = node.children() {
+        out.push(',');
+        newline(out, depth + 1);
+        out.push_str("\"children\": ");
+        write_document(out, children, depth + 1);
+    }
+
+    newline(out, depth);
+    out.push('}');
+}
+
+fn write_value(out: &mut String, value: &KdlValue) {
+    match value {
+        KdlValue::String(s) => write_string(out, s),
+        KdlValue::Integer(i) => out.push_str(&i.to_string()),
+        // JSON has no infinities nor NaN.
+        KdlValue::Float(f) if !f.is_finite() => write_string(out, &f.to_string()),
+        KdlValue::Float(f) => out.push_str(&format!("{f:?}")),
+        KdlValue::Bool(b) => out.push_str(&b.to_string()),
+        KdlValue::Null => out.push_str("null"),
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn newline(out: &mut String, depth: usize) {
+    out.push('\n');
+    out.push_str(&"  ".repeat(depth));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(source: &str, name: &str) -> (KdlDocument, String) {
+        (source.parse().unwrap(), name.to_string())
+    }
+
+    #[test]
+    fn test_merge() {
+        let documents = [
+            doc(
+                r#"
+                includes { include "./api.kdl" }
+                system { }
+                definitions { modifiers { chain-filters "a" { } } }
+                services { Web { } }
+                "#,
+                "entry.kdl",
+            ),
+            doc(
+                r#"
+                definitions { modifiers { chain-filters "b" { } } }
+                services { Api { } }
+                "#,
+                "api.kdl",
+            ),
+        ];
+
+        let merged = merge(&documents);
+        let names: Vec<_> = merged.nodes().iter().map(|n| n.name().value()).collect();
+        assert_eq!(names, ["system", "definitions", "services"]);
+
+        let children = |doc: &KdlDocument, name: &str| doc.get(name)?.children().cloned();
+
+        let definitions = children(&merged, "definitions").unwrap();
+        assert_eq!(definitions.nodes().len(), 1);
+        assert_eq!(
+            children(&definitions, "modifiers").unwrap().nodes().len(),
+            2
+        );
+        assert_eq!(children(&merged, "services").unwrap().nodes().len(), 2);
+
+        // The merged document reads back the same.
+        let printed: KdlDocument = merged.to_string().parse().unwrap();
+        assert_eq!(printed.nodes().len(), 3);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let (doc, _) = doc(r#"route "/a\"b" weight=1.5 on=#true { x }"#, "x.kdl");
+
+        assert_eq!(
+            to_json(&doc),
+            concat!(
+                "[\n",
+                "  {\n",
+                "    \"name\": \"route\",\n",
+                "    \"args\": [\"/a\\\"b\"],\n",
+                "    \"props\": {\"weight\": 1.5, \"on\": true},\n",
+                "    \"children\": [\n",
+                "      {\n",
+                "        \"name\": \"x\"\n",
+                "      }\n",
+                "    ]\n",
+                "  }\n",
+                "]\n",
+            )
+        );
+    }
+}
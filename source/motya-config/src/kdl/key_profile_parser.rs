@@ -5,17 +5,113 @@ use crate::{
 
 pub struct KeyProfileParser;
 
+const DEFAULT_SEPARATOR: &str = "|";
+
+/// Builds the key template of `key "Concat" parts="Header:x-tenant,UriPath"`.
+///
+/// Every part becomes a template variable, joined by `separator` so that
+/// different combinations of values can't produce the same key.
+fn concat_template(parts: &str, separator: &str) -> Result<String, String> {
+    if separator.contains(['$', '{', '}']) {
+        return Err(format!("Invalid separator '{separator}'"));
+    }
+
+    let variables = parts
+        .split(',')
+        .map(str::trim)
+        .map(|part| {
+            let variable = match part.split_once(':') {
+                Some(("Header", name)) if !name.is_empty() => format!("header-{name}"),
+                Some(("Cookie", name)) if !name.is_empty() => format!("cookie-{name}"),
+                Some(("Query", params)) if !params.is_empty() => format!("query?{params}"),
+                None if part == "UriPath" => "uri-path".to_string(),
+                None if part == "ClientIp" => "client-ip".to_string(),
+                None if part == "UserAgent" => "user-agent".to_string(),
+                _ => {
+                    return Err(format!(
+                        "Unknown selector part '{part}', expected one of 'Header:NAME', 'Cookie:NAME', 'Query:PARAMS', 'UriPath', 'ClientIp' or 'UserAgent'"
+                    ))
+                }
+            };
+            Ok(format!("${{{variable}}}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(variables.join(separator))
+}
+
+/// Builds the key template of the inline `key="KEYKIND"` of a hashing `selection`.
+///
+/// `header-name` names the header of `key="Header"`, `param` the cookie or
+/// query parameter of `key="Cookie"` and `key="QueryParam"`.
+pub fn selector_template(
+    key: &str,
+    header_name: Option<&str>,
+    param: Option<&str>,
+) -> Result<KeyTemplateConfig, String> {
+    if header_name.is_some() && key != "Header" {
+        return Err("'header-name' is only allowed with 'key=\"Header\"'".to_string());
+    }
+    if param.is_some() && !matches!(key, "Cookie" | "QueryParam") {
+        return Err(
+            "'param' is only allowed with 'key=\"Cookie\"' or 'key=\"QueryParam\"'".to_string(),
+        );
+    }
+
+    let param = || match param {
+        Some(name) if !name.is_empty() && !name.contains(['&', '=', ';', '}']) => Ok(name),
+        Some(name) => Err(format!("Invalid 'param' '{name}'")),
+        None => Err(format!("'key=\"{key}\"' requires 'param'")),
+    };
+
+    let source = match key {
+        "Header" => match header_name {
+            Some(name) if !name.is_empty() => format!("${{header-{name}}}"),
+            _ => return Err("'key=\"Header\"' requires 'header-name'".to_string()),
+        },
+        "Cookie" => format!("${{cookie-{}}}", param()?),
+        "QueryParam" => format!("${{query?{}}}", param()?),
+        "UriPath" => "${uri-path}".to_string(),
+        "SourceAddrAndUriPath" => format!("${{client-ip}}{DEFAULT_SEPARATOR}${{uri-path}}"),
+        other => {
+            return Err(format!(
+                "Unknown key '{other}', expected one of 'UriPath', 'SourceAddrAndUriPath', 'Header', 'Cookie' or 'QueryParam'"
+            ))
+        }
+    };
+
+    Ok(KeyTemplateConfig {
+        source,
+        fallback: None,
+        algorithm: HashAlgorithm {
+            name: "xxhash64".to_string(),
+            seed: None,
+        },
+        transforms: vec![],
+    })
+}
+
 impl KeyProfileParser {
     pub fn parse(&self, ctx: ParseContext<'_>) -> miette::Result<KeyTemplateConfig> {
         let mut block = BlockParser::new(ctx)?;
 
         let (source, fallback) = block.required("key", |ctx| {
             let source = ctx.first()?.as_str()?;
+            let opts = ctx.args_map_with_only_keys(1.., &["fallback", "parts", "separator"])?;
 
-            let fallback = ctx
-                .args_map_with_only_keys(1.., &["fallback"])?
-                .get("fallback")
-                .map(|s| s.to_string());
+            let source = match (source == "Concat", opts.get("parts")) {
+                (true, Some(parts)) => {
+                    let separator = opts.get("separator").copied().unwrap_or(DEFAULT_SEPARATOR);
+                    concat_template(parts, separator).map_err(|e| ctx.error(e))?
+                }
+                (true, None) => return Err(ctx.error("'Concat' requires 'parts'")),
+                (false, None) if !opts.contains_key("separator") => source,
+                _ => {
+                    return Err(ctx.error("'parts' and 'separator' are only allowed with 'Concat'"))
+                }
+            };
+
+            let fallback = opts.get("fallback").map(|s| s.to_string());
 
             Ok((source, fallback))
         })?;
@@ -130,4 +226,116 @@ mod tests {
         let msg_err = result.unwrap_err().help().unwrap().to_string();
         crate::assert_err_contains!(msg_err, "Missing required directive 'key'");
     }
+
+    #[test]
+    fn test_parse_concat_key() {
+        let kdl_input =
+            r#"key "Concat" parts="Header:x-tenant, UriPath,Cookie:sid" fallback="${client-ip}""#;
+        let doc: KdlDocument = kdl_input.parse().unwrap();
+
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let template = KeyProfileParser.parse(ctx).unwrap();
+
+        assert_eq!(
+            template.source,
+            "${header-x-tenant}|${uri-path}|${cookie-sid}"
+        );
+        assert_eq!(template.fallback.as_deref(), Some("${client-ip}"));
+
+        let kdl_input = r#"key "Concat" parts="ClientIp,Query:id&type" separator=":""#;
+        let doc: KdlDocument = kdl_input.parse().unwrap();
+
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let template = KeyProfileParser.parse(ctx).unwrap();
+
+        assert_eq!(template.source, "${client-ip}:${query?id&type}");
+    }
+
+    #[test]
+    fn test_selector_template() {
+        let template = selector_template("Header", Some("X-Tenant-Id"), None).unwrap();
+        assert_eq!(template.source, "${header-X-Tenant-Id}");
+        assert_eq!(template.algorithm.name, "xxhash64");
+
+        assert_eq!(
+            selector_template("SourceAddrAndUriPath", None, None)
+                .unwrap()
+                .source,
+            "${client-ip}|${uri-path}"
+        );
+        assert_eq!(
+            selector_template("Cookie", None, Some("session"))
+                .unwrap()
+                .source,
+            "${cookie-session}"
+        );
+        assert_eq!(
+            selector_template("QueryParam", None, Some("user_id"))
+                .unwrap()
+                .source,
+            "${query?user_id}"
+        );
+
+        let cases = [
+            ("Header", None, None, "requires 'header-name'"),
+            (
+                "UriPath",
+                Some("x-tenant"),
+                None,
+                "only allowed with 'key=\"Header\"'",
+            ),
+            ("QueryParam", None, None, "requires 'param'"),
+            ("QueryParam", None, Some("a&b"), "Invalid 'param' 'a&b'"),
+            (
+                "Header",
+                Some("x-tenant"),
+                Some("id"),
+                "'param' is only allowed",
+            ),
+            ("Body", None, None, "Unknown key 'Body'"),
+        ];
+
+        for (key, header_name, param, expected) in cases {
+            crate::assert_err_contains!(
+                selector_template(key, header_name, param).unwrap_err(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_concat_key_errors() {
+        let cases = [
+            (r#"key "Concat""#, "'Concat' requires 'parts'"),
+            (
+                r#"key "Concat" parts="Header:""#,
+                "Unknown selector part 'Header:'",
+            ),
+            (
+                r#"key "Concat" parts="UriPath,Body""#,
+                "Unknown selector part 'Body'",
+            ),
+            (
+                r#"key "Concat" parts="UriPath" separator="${""#,
+                "Invalid separator",
+            ),
+            (
+                r#"key "${uri-path}" parts="UriPath""#,
+                "only allowed with 'Concat'",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let doc: KdlDocument = input.parse().unwrap();
+            let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+
+            let msg_err = KeyProfileParser
+                .parse(ctx)
+                .unwrap_err()
+                .help()
+                .unwrap()
+                .to_string();
+            crate::assert_err_contains!(msg_err, expected);
+        }
+    }
 }
@@ -0,0 +1,387 @@
+//! Warnings for configurations that are valid but most likely not what was
+//! meant. They are reported when the configuration is loaded and never fail it.
+
+use std::net::SocketAddr;
+
+use fqdn::fqdn;
+use http::Method;
+use kdl::{KdlDocument, KdlNode};
+use miette::{Diagnostic, NamedSource, SourceSpan};
+
+use crate::{
+    common_types::{
+        connectors::{RouteMatcher, UpstreamConfig, UpstreamContextConfig},
+        definitions::Modificator,
+        listeners::ListenerKind,
+    },
+    internal::{Config, ProxyConfig},
+};
+
+const HSTS_HEADER: &str = "strict-transport-security";
+
+#[derive(thiserror::Error, Debug, Diagnostic)]
+#[error("Suspicious configuration")]
+#[diagnostic(severity(Warning))]
+pub struct Lint {
+    #[help]
+    pub warning: String,
+
+    #[source_code]
+    pub src: NamedSource<String>,
+
+    #[label("here")]
+    pub span: SourceSpan,
+}
+
+/// Lints every proxy service of `config`, which was compiled from `documents`.
+pub fn lint(config: &Config, documents: &[(KdlDocument, String)]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    for proxy in &config.basic_proxies {
+        let Some(service) = Service::find(documents, &proxy.name) else {
+            continue;
+        };
+
+        lint_hsts(proxy, &service, &mut lints);
+        lint_regex_shadowing(proxy, &service, &mut lints);
+        lint_loops(config, proxy, &service, &mut lints);
+    }
+
+    lints
+}
+
+/// The node of a service and the document it was declared in.
+struct Service<'a> {
+    node: &'a KdlNode,
+    doc: &'a KdlDocument,
+    source_name: &'a str,
+}
+
+impl<'a> Service<'a> {
+    fn find(documents: &'a [(KdlDocument, String)], name: &str) -> Option<Self> {
+        documents.iter().find_map(|(doc, source_name)| {
+            let node = doc.get("services")?.children()?.get(name)?;
+            Some(Self {
+                node,
+                doc,
+                source_name,
+            })
+        })
+    }
+
+    /// The first node of the `child` block of the service that `pred` accepts.
+    fn find_in(&self, child: &str, pred: impl Fn(&KdlNode) -> bool) -> Option<&'a KdlNode> {
+        let block = self.node.children()?.get(child)?;
+        find_node(block, &pred)
+    }
+
+    fn lint(&self, warning: String, node: Option<&KdlNode>) -> Lint {
+        Lint {
+            warning,
+            src: NamedSource::new(self.source_name, self.doc.to_string()),
+            span: node.unwrap_or(self.node).span(),
+        }
+    }
+}
+
+fn find_node<'a>(node: &'a KdlNode, pred: &impl Fn(&KdlNode) -> bool) -> Option<&'a KdlNode> {
+    if pred(node) {
+        return Some(node);
+    }
+
+    node.children()?
+        .nodes()
+        .iter()
+        .find_map(|child| find_node(child, pred))
+}
+
+fn has_string_arg(node: &KdlNode, pred: impl Fn(&str) -> bool) -> bool {
+    node.entries()
+        .iter()
+        .any(|entry| entry.name().is_none() && entry.value().as_string().is_some_and(&pred))
+}
+
+/// TLS listeners of a service that never sends `Strict-Transport-Security`.
+fn lint_hsts(proxy: &ProxyConfig, service: &Service, lints: &mut Vec<Lint>) {
+    let sets_hsts = proxy.connectors.upstreams.iter().any(|upstream| {
+        upstream.chains.iter().any(|Modificator::Chain(named)| {
+            named.chain.filters.iter().any(|filter| {
+                filter.name == fqdn!("motya.response.upsert-header")
+                    && filter
+                        .args
+                        .get("key")
+                        .is_some_and(|key| key.eq_ignore_ascii_case(HSTS_HEADER))
+            })
+        })
+    });
+
+    if sets_hsts {
+        return;
+    }
+
+    for listener in &proxy.listeners.list_cfgs {
+        let ListenerKind::Tcp {
+            addr, tls: Some(_), ..
+        } = &listener.source
+        else {
+            continue;
+        };
+
+        let node = service.find_in("listeners", |node| node.name().value() == addr);
+
+        lints.push(service.lint(
+            format!(
+                "TLS listener '{addr}' of '{}' never sends Strict-Transport-Security, add it with 'motya.response.upsert-header'",
+                proxy.name
+            ),
+            node,
+        ));
+    }
+}
+
+fn route(upstream: &UpstreamContextConfig) -> Option<(&str, &RouteMatcher)> {
+    match &upstream.upstream {
+        UpstreamConfig::Service(peer) => Some((peer.prefix_path.path(), &peer.matcher)),
+        UpstreamConfig::MultiServer(multi) => Some((multi.prefix_path.path(), &multi.matcher)),
+        UpstreamConfig::Static(_) => None,
+    }
+}
+
+fn methods_overlap(a: &Option<Vec<Method>>, b: &Option<Vec<Method>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.iter().any(|method| b.contains(method)),
+        _ => true,
+    }
+}
+
+/// Regex routes are tried before prefix routes, so one matching the path of a
+/// prefix route takes its requests.
+fn lint_regex_shadowing(proxy: &ProxyConfig, service: &Service, lints: &mut Vec<Lint>) {
+    let upstreams = &proxy.connectors.upstreams;
+
+    for regex_route in upstreams {
+        let Some((_, RouteMatcher::Regex(pattern))) = route(regex_route) else {
+            continue;
+        };
+
+        let shadowed = upstreams
+            .iter()
+            .find_map(|prefix_route| match route(prefix_route) {
+                Some((path, RouteMatcher::Prefix))
+                    if pattern.regex().is_match(path)
+                        && methods_overlap(&regex_route.methods, &prefix_route.methods) =>
+                {
+                    Some(path)
+                }
+                _ => None,
+            });
+
+        let Some(path) = shadowed else {
+            continue;
+        };
+
+        let source = pattern.regex().as_str();
+        let node = service.find_in("connectors", |node| {
+            node.name().value() == "section" && has_string_arg(node, |arg| arg == source)
+        });
+
+        lints.push(service.lint(
+            format!(
+                "Regex section '{source}' of '{}' matches the prefix section '{path}', whose requests it takes since regex routes are tried first",
+                proxy.name
+            ),
+            node,
+        ));
+    }
+}
+
+/// Whether a connection to `upstream` reaches `listener`.
+fn reaches(upstream: SocketAddr, listener: SocketAddr) -> bool {
+    upstream.port() == listener.port()
+        && (upstream.ip() == listener.ip()
+            || listener.ip().is_unspecified() && upstream.ip().is_loopback())
+}
+
+/// Connectors that send requests back into a listener of this configuration.
+fn lint_loops(config: &Config, proxy: &ProxyConfig, service: &Service, lints: &mut Vec<Lint>) {
+    let listeners: Vec<(&str, SocketAddr)> = config
+        .basic_proxies
+        .iter()
+        .flat_map(|other| {
+            other
+                .listeners
+                .list_cfgs
+                .iter()
+                .filter_map(move |listener| match &listener.source {
+                    ListenerKind::Tcp { addr, .. } => {
+                        Some((other.name.as_str(), addr.parse().ok()?))
+                    }
+                    ListenerKind::Uds(_) => None,
+                })
+        })
+        .collect();
+
+    for upstream in &proxy.connectors.upstreams {
+        let addresses: Vec<SocketAddr> = match &upstream.upstream {
+            UpstreamConfig::Service(peer) => vec![peer.peer_address],
+            UpstreamConfig::MultiServer(multi) => multi
+                .servers
+                .iter()
+                .map(|server| server.address)
+                .chain(
+                    multi
+                        .read_your_writes
+                        .iter()
+                        .flat_map(|ryw| ryw.primary.clone()),
+                )
+                .chain(
+                    multi
+                        .split_by_method
+                        .iter()
+                        .flat_map(|split| split.primary.clone()),
+                )
+                .collect(),
+            UpstreamConfig::Static(_) => continue,
+        };
+
+        for address in addresses {
+            let Some((target, _)) = listeners
+                .iter()
+                .find(|(_, listener)| reaches(address, *listener))
+            else {
+                continue;
+            };
+
+            let text = address.to_string();
+            let node = service.find_in("connectors", |node| {
+                has_string_arg(node, |arg| arg.contains(&text))
+            });
+
+            let warning = if *target == proxy.name {
+                format!(
+                    "Connector '{address}' of '{}' points at its own listener, requests would loop",
+                    proxy.name
+                )
+            } else {
+                format!(
+                    "Connector '{address}' of '{}' points at a listener of '{target}' in the same configuration",
+                    proxy.name
+                )
+            };
+
+            lints.push(service.lint(warning, node));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common_types::definitions_table::DefinitionsTable, kdl::compiler::ConfigCompiler};
+
+    fn warnings(source: &str) -> Vec<String> {
+        let doc: KdlDocument = source.parse().unwrap();
+        let documents = vec![(doc, "main.kdl".to_string())];
+
+        let config = ConfigCompiler::new(documents.clone())
+            .compile(&mut DefinitionsTable::new_with_global())
+            .unwrap();
+
+        lint(&config, &documents)
+            .into_iter()
+            .map(|lint| lint.warning)
+            .collect()
+    }
+
+    #[test]
+    fn test_hsts() {
+        let lints = warnings(
+            r#"
+            system { threads-per-service 1; }
+            services {
+                Site {
+                    listeners {
+                        "127.0.0.1:8443" cert-path="cert.pem" key-path="key.pem"
+                        "127.0.0.1:8080"
+                    }
+                    connectors { proxy "http://10.0.0.1:80"; }
+                }
+            }
+            "#,
+        );
+        assert_eq!(lints.len(), 1);
+        assert!(
+            lints[0].contains("TLS listener '127.0.0.1:8443'"),
+            "{lints:?}"
+        );
+
+        let lints = warnings(
+            r#"
+            system { threads-per-service 1; }
+            definitions {
+                modifiers {
+                    chain-filters "hsts" {
+                        filter name="motya.response.upsert-header" key="Strict-Transport-Security" value="max-age=63072000"
+                    }
+                }
+            }
+            services {
+                Site {
+                    listeners { "127.0.0.1:8443" cert-path="cert.pem" key-path="key.pem"; }
+                    connectors {
+                        use-chain "hsts"
+                        proxy "http://10.0.0.1:80"
+                    }
+                }
+            }
+            "#,
+        );
+        assert!(lints.is_empty(), "{lints:?}");
+    }
+
+    #[test]
+    fn test_regex_shadowing() {
+        let lints = warnings(
+            r#"
+            system { threads-per-service 1; }
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080"; }
+                    connectors {
+                        section "^/api/.*" { proxy "http://10.0.0.1:80"; }
+                        section "/api/v2" as="prefix" { proxy "http://10.0.0.2:80"; }
+                        section "^/static/.*" { proxy "http://10.0.0.3:80"; }
+                    }
+                }
+            }
+            "#,
+        );
+
+        assert_eq!(lints.len(), 1);
+        assert!(lints[0].contains("'^/api/.*'"), "{lints:?}");
+        assert!(lints[0].contains("'/api/v2'"), "{lints:?}");
+    }
+
+    #[test]
+    fn test_loops() {
+        let lints = warnings(
+            r#"
+            system { threads-per-service 1; }
+            services {
+                Edge {
+                    listeners { "0.0.0.0:8080"; }
+                    connectors { proxy "http://127.0.0.1:9090"; }
+                }
+                Internal {
+                    listeners { "127.0.0.1:9090"; }
+                    connectors { proxy "http://127.0.0.1:8080"; }
+                }
+            }
+            "#,
+        );
+
+        assert_eq!(lints.len(), 2);
+        assert!(lints[0].contains("listener of 'Internal'"), "{lints:?}");
+        assert!(lints[1].contains("listener of 'Edge'"), "{lints:?}");
+    }
+}
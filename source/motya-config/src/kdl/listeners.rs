@@ -1,10 +1,15 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf};
 
 use motya_macro::validate;
 
 use crate::{
+    block_parser,
     common_types::{
-        listeners::{ListenerConfig, ListenerKind, Listeners, TlsConfig},
+        host_name::ascii_host,
+        listeners::{
+            AcmeConfig, H2Limits, ListenerConfig, ListenerKind, Listeners, TlsConfig,
+            UnmatchedHost, LETS_ENCRYPT_DIRECTORY,
+        },
         section_parser::SectionParser,
     },
     kdl::parser::{
@@ -33,27 +38,214 @@ impl SectionParser<ParseContext<'_>, Listeners> for ListenersSection {
 impl ListenersSection {
     fn extract_listener(&self, ctx: ParseContext<'_>) -> miette::Result<ListenerConfig> {
         ctx.validate(&[
-            Rule::NoChildren,
             Rule::NoPositionalArgs,
             Rule::OnlyKeysTyped(&[
                 ("cert-path", PrimitiveType::String),
                 ("key-path", PrimitiveType::String),
                 ("offer-h2", PrimitiveType::Bool),
+                ("h2c", PrimitiveType::Bool),
+                ("unmatched-host", PrimitiveType::String),
+                ("redirect-to-https", PrimitiveType::Bool),
+                ("max-request-body-bytes", PrimitiveType::Integer),
+                ("h2-max-header-list-bytes", PrimitiveType::Integer),
+                ("h2-max-concurrent-streams", PrimitiveType::Integer),
+                ("h2-max-reset-streams", PrimitiveType::Integer),
+                ("tls-fingerprint", PrimitiveType::Bool),
             ]),
             Rule::Name(NamePredicate::SocketAddr),
         ])?;
 
         let addr = ctx.validated_name()?.as_socket_addr()?;
 
-        let [cert_opt, key_opt, h2_opt] = ctx.props(["cert-path", "key-path", "offer-h2"])?;
+        let [cert_opt, key_opt, h2_opt, h2c_opt, unmatched_opt, redirect_opt, body_limit_opt] = ctx
+            .props([
+                "cert-path",
+                "key-path",
+                "offer-h2",
+                "h2c",
+                "unmatched-host",
+                "redirect-to-https",
+                "max-request-body-bytes",
+            ])?;
+
+        let acme = if ctx.has_children_block()? {
+            block_parser!(
+                ctx.enter_block()?,
+                acme: required("acme") => |ctx| self.parse_acme(ctx)
+            );
+            Some(acme)
+        } else {
+            None
+        };
 
-        self.resolve_tcp_listener(
+        let mut listener = self.resolve_tcp_listener(
             &ctx,
             addr,
             cert_opt.as_str()?,
             key_opt.as_str()?,
+            acme,
             h2_opt.as_bool()?,
-        )
+        )?;
+
+        if h2c_opt.as_bool()?.unwrap_or(false) {
+            match &mut listener.source {
+                ListenerKind::Tcp { tls: None, h2c, .. } => *h2c = true,
+                _ => {
+                    return Err(
+                        ctx.error("'h2c' is cleartext HTTP/2, use 'offer-h2' on TLS listeners")
+                    )
+                }
+            }
+        }
+
+        if redirect_opt.as_bool()?.unwrap_or(false) {
+            match &listener.source {
+                ListenerKind::Tcp { tls: None, .. } => listener.redirect_to_https = true,
+                _ => return Err(ctx.error("'redirect-to-https' is for listeners without TLS")),
+            }
+        }
+
+        listener.max_request_body_bytes = match body_limit_opt.as_usize()? {
+            Some(0) => return Err(ctx.error("'max-request-body-bytes' must be greater than 0")),
+            max => max,
+        };
+
+        listener.h2_limits = self.parse_h2_limits(&ctx, &listener)?;
+
+        if ctx.opt_prop("tls-fingerprint")?.as_bool()?.unwrap_or(false) {
+            match &listener.source {
+                ListenerKind::Tcp { tls: Some(_), .. } => listener.tls_fingerprint = true,
+                _ => return Err(ctx.error("'tls-fingerprint' is for listeners with TLS")),
+            }
+        }
+
+        listener.unmatched_host = match unmatched_opt.as_str()?.as_deref() {
+            None => None,
+            Some("default") => Some(UnmatchedHost::DefaultService),
+            Some("misdirected") => Some(UnmatchedHost::Misdirected),
+            Some("close") => Some(UnmatchedHost::Close),
+            Some(other) => {
+                return Err(ctx.error(format!(
+                    "Unknown 'unmatched-host' value '{other}', expected 'default', 'misdirected' or 'close'"
+                )))
+            }
+        };
+
+        Ok(listener)
+    }
+
+    /// The `h2-*` limits of a listener, which has to speak HTTP/2 to set any.
+    fn parse_h2_limits(
+        &self,
+        ctx: &ParseContext<'_>,
+        listener: &ListenerConfig,
+    ) -> miette::Result<H2Limits> {
+        let [header_list, streams, resets] = ctx.props([
+            "h2-max-header-list-bytes",
+            "h2-max-concurrent-streams",
+            "h2-max-reset-streams",
+        ])?;
+
+        let limit = |value: Option<usize>, name: &str| match value {
+            Some(0) => Err(ctx.error(format!("'{name}' must be greater than 0"))),
+            Some(value) if value > u32::MAX as usize => {
+                Err(ctx.error(format!("'{name}' is too large")))
+            }
+            value => Ok(value),
+        };
+
+        let header_list = limit(header_list.as_usize()?, "h2-max-header-list-bytes")?;
+        let streams = limit(streams.as_usize()?, "h2-max-concurrent-streams")?;
+        let resets = limit(resets.as_usize()?, "h2-max-reset-streams")?;
+
+        let defaults = H2Limits::default();
+        if header_list.is_none() && streams.is_none() && resets.is_none() {
+            return Ok(defaults);
+        }
+
+        let speaks_h2 = match &listener.source {
+            ListenerKind::Tcp {
+                tls: Some(_),
+                offer_h2,
+                ..
+            } => *offer_h2,
+            ListenerKind::Tcp { h2c, .. } => *h2c,
+            ListenerKind::Uds(_) => false,
+        };
+        if !speaks_h2 {
+            return Err(ctx.error("'h2-*' limits only apply to listeners with 'offer-h2' or 'h2c'"));
+        }
+
+        Ok(H2Limits {
+            max_header_list_bytes: header_list
+                .map_or(defaults.max_header_list_bytes, |max| max as u32),
+            max_concurrent_streams: streams
+                .map_or(defaults.max_concurrent_streams, |max| max as u32),
+            max_pending_reset_streams: resets.unwrap_or(defaults.max_pending_reset_streams),
+        })
+    }
+
+    fn parse_acme(&self, ctx: ParseContext<'_>) -> miette::Result<AcmeConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("domains", PrimitiveType::String),
+                ("email", PrimitiveType::String),
+                ("cache-dir", PrimitiveType::String),
+                ("directory", PrimitiveType::String),
+                ("challenge", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let domains: Vec<String> = ctx
+            .prop("domains")?
+            .as_str()?
+            .split([',', ' '])
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+            .map(|d| ascii_host(d).unwrap_or_else(|| d.to_ascii_lowercase()))
+            .collect();
+
+        if domains.is_empty() {
+            return Err(ctx.error("'domains' must list at least one domain"));
+        }
+
+        // Only HTTP-01 is implemented, which cannot prove control of a wildcard.
+        if let Some(wildcard) = domains.iter().find(|d| d.contains('*')) {
+            return Err(ctx.error(format!(
+                "Wildcard domain '{wildcard}' requires a DNS-01 challenge, which is not supported, list the domains one by one or use 'cert-path' and 'key-path'"
+            )));
+        }
+
+        let email = ctx.prop("email")?.as_str()?;
+        let cache_dir = ctx.prop("cache-dir")?.parse_as::<PathBuf>()?;
+
+        let directory = ctx
+            .opt_prop("directory")?
+            .as_str()?
+            .unwrap_or_else(|| LETS_ENCRYPT_DIRECTORY.to_string());
+
+        match ctx.opt_prop("challenge")?.as_str()?.as_deref() {
+            None | Some("http-01") => {}
+            Some(challenge @ ("tls-alpn-01" | "dns-01")) => {
+                return Err(ctx.error(format!(
+                    "'{challenge}' challenges are not supported yet, use 'http-01'"
+                )))
+            }
+            Some(other) => {
+                return Err(ctx.error(format!(
+                    "Unknown ACME challenge '{other}', expected 'http-01'"
+                )))
+            }
+        }
+
+        Ok(AcmeConfig {
+            domains,
+            email,
+            cache_dir,
+            directory,
+        })
     }
 
     fn resolve_tcp_listener(
@@ -62,37 +254,320 @@ impl ListenersSection {
         addr: SocketAddr,
         cert_path: Option<String>,
         key_path: Option<String>,
+        acme: Option<AcmeConfig>,
         offer_h2: Option<bool>,
     ) -> miette::Result<ListenerConfig> {
-        match (cert_path, key_path, offer_h2) {
+        match (cert_path, key_path, acme, offer_h2) {
+            (Some(_), _, Some(_), _) | (_, Some(_), Some(_), _) => Err(ctx.error(
+                "'acme' provides the certificate, remove 'cert-path' and 'key-path'",
+            )),
+
+            (None, None, Some(acme), offer_h2) => Ok(ListenerConfig {
+                source: ListenerKind::Tcp {
+                    addr: addr.to_string(),
+                    tls: Some(TlsConfig::Acme(acme)),
+                    offer_h2: offer_h2.unwrap_or(true),
+                    h2c: false,
+                },
+                unmatched_host: None,
+                redirect_to_https: false,
+                max_request_body_bytes: None,
+                h2_limits: H2Limits::default(),
+                tls_fingerprint: false,
+            }),
 
-            (None, None, None) => Ok(ListenerConfig {
+            (None, None, None, None) => Ok(ListenerConfig {
                 source: ListenerKind::Tcp {
                     addr: addr.to_string(),
                     tls: None,
                     offer_h2: false,
+                    h2c: false,
                 },
+                unmatched_host: None,
+                redirect_to_https: false,
+                max_request_body_bytes: None,
+                h2_limits: H2Limits::default(),
+                tls_fingerprint: false,
             }),
 
-            (None, Some(_), _) | (Some(_), None, _) => Err(ctx.error(
+            (None, Some(_), None, _) | (Some(_), None, None, _) => Err(ctx.error(
                 "'cert-path' and 'key-path' must either BOTH be present, or NEITHER should be present",
             )),
 
-            (None, None, Some(_)) => Err(ctx.error(
-                "'offer-h2' requires TLS, specify 'cert-path' and 'key-path'",
+            (None, None, None, Some(_)) => Err(ctx.error(
+                "'offer-h2' requires TLS, specify 'cert-path' and 'key-path', or use 'h2c' for cleartext HTTP/2",
             )),
 
-            (Some(cpath), Some(kpath), offer_h2) => Ok(ListenerConfig {
+            (Some(cpath), Some(kpath), None, offer_h2) => Ok(ListenerConfig {
                 source: ListenerKind::Tcp {
                     addr: addr.to_string(),
-                    tls: Some(TlsConfig {
+                    tls: Some(TlsConfig::Files {
                         cert_path: cpath.into(),
                         key_path: kpath.into(),
                     }),
 
                     offer_h2: offer_h2.unwrap_or(true),
+                    h2c: false,
                 },
+                unmatched_host: None,
+                redirect_to_https: false,
+                max_request_body_bytes: None,
+                h2_limits: H2Limits::default(),
+                tls_fingerprint: false,
             }),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_err_contains;
+    use crate::kdl::parser::block::BlockParser;
+    use crate::kdl::parser::ctx::Current;
+    use kdl::KdlDocument;
+
+    fn parse_listeners(input: &str) -> miette::Result<Listeners> {
+        let doc: KdlDocument = input.parse().unwrap();
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let mut block = BlockParser::new(ctx)?;
+
+        block.required("listeners", |ctx| ListenersSection.parse_node(ctx))
+    }
+
+    #[test]
+    fn test_acme_listener() {
+        let listeners = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:443" {
+                    acme domains="Example.com, www.example.com" email="ops@example.com" cache-dir="/var/lib/motya/acme"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            listeners.list_cfgs[0].source,
+            ListenerKind::Tcp {
+                addr: "0.0.0.0:443".into(),
+                tls: Some(TlsConfig::Acme(AcmeConfig {
+                    domains: vec!["example.com".into(), "www.example.com".into()],
+                    email: "ops@example.com".into(),
+                    cache_dir: "/var/lib/motya/acme".into(),
+                    directory: LETS_ENCRYPT_DIRECTORY.into(),
+                })),
+                offer_h2: true,
+                h2c: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_acme_conflicts_with_cert_files() {
+        let err = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:443" cert-path="cert.pem" key-path="key.pem" {
+                    acme domains="example.com" email="ops@example.com" cache-dir="/tmp/acme"
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "remove 'cert-path' and 'key-path'"
+        );
+    }
+
+    #[test]
+    fn test_acme_unsupported_challenges() {
+        let alpn = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:443" {
+                    acme domains="example.com" email="ops@example.com" cache-dir="/tmp/acme" challenge="tls-alpn-01"
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(alpn.help().unwrap().to_string(), "not supported yet");
+
+        let wildcard = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:443" {
+                    acme domains="example.com, *.example.com" email="ops@example.com" cache-dir="/tmp/acme"
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            wildcard.help().unwrap().to_string(),
+            "Wildcard domain '*.example.com' requires a DNS-01 challenge"
+        );
+    }
+
+    #[test]
+    fn test_h2c_listener() {
+        let listeners = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:8080" h2c=#true
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            listeners.list_cfgs[0].source,
+            ListenerKind::Tcp {
+                addr: "0.0.0.0:8080".into(),
+                tls: None,
+                offer_h2: false,
+                h2c: true,
+            }
+        );
+
+        let err = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:8443" cert-path="cert.pem" key-path="key.pem" h2c=#true
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "use 'offer-h2' on TLS listeners"
+        );
+    }
+
+    #[test]
+    fn test_redirect_to_https() {
+        let listeners = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:80" redirect-to-https=#true
+                "0.0.0.0:8080"
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert!(listeners.list_cfgs[0].redirect_to_https);
+        assert!(!listeners.list_cfgs[1].redirect_to_https);
+
+        let err = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:443" cert-path="cert.pem" key-path="key.pem" redirect-to-https=#true
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "'redirect-to-https' is for listeners without TLS"
+        );
+    }
+
+    #[test]
+    fn test_max_request_body_bytes() {
+        let listeners = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:8080" max-request-body-bytes=1048576
+                "0.0.0.0:8081"
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(listeners.list_cfgs[0].max_request_body_bytes, Some(1048576));
+        assert_eq!(listeners.list_cfgs[1].max_request_body_bytes, None);
+
+        let err = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:8080" max-request-body-bytes=0
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "must be greater than 0");
+    }
+
+    #[test]
+    fn test_h2_limits() {
+        let listeners = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:8080" h2c=#true h2-max-header-list-bytes=16384 h2-max-reset-streams=5
+                "0.0.0.0:8081" h2c=#true
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            listeners.list_cfgs[0].h2_limits,
+            H2Limits {
+                max_header_list_bytes: 16384,
+                max_pending_reset_streams: 5,
+                ..H2Limits::default()
+            }
+        );
+        assert_eq!(listeners.list_cfgs[1].h2_limits, H2Limits::default());
+
+        let cases = [
+            (
+                r#""0.0.0.0:8080" h2-max-concurrent-streams=10"#,
+                "only apply to listeners with 'offer-h2' or 'h2c'",
+            ),
+            (
+                r#""0.0.0.0:8080" h2c=#true h2-max-header-list-bytes=0"#,
+                "must be greater than 0",
+            ),
+        ];
+
+        for (listener, expected) in cases {
+            let err = parse_listeners(&format!("listeners {{ {listener} }}")).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_tls_fingerprint() {
+        let listeners = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:443" cert-path="cert.pem" key-path="key.pem" tls-fingerprint=#true
+                "0.0.0.0:8443" cert-path="cert.pem" key-path="key.pem"
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert!(listeners.list_cfgs[0].tls_fingerprint);
+        assert!(!listeners.list_cfgs[1].tls_fingerprint);
+
+        let err = parse_listeners(
+            r#"
+            listeners {
+                "0.0.0.0:80" tls-fingerprint=#true
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "'tls-fingerprint' is for listeners with TLS"
+        );
+    }
+}
@@ -1,13 +1,21 @@
+pub mod active_window;
+pub mod alerts;
+pub mod cache;
 pub mod chain_parser;
 pub mod compiler;
 pub mod connectors;
 pub mod definitions;
 pub mod file_server;
+pub mod flags;
 pub mod fs_loader;
 pub mod includes;
+pub mod inspect;
 pub mod key_profile_parser;
+pub mod lints;
 pub mod listeners;
 pub mod parser;
 pub mod rate_limiter;
+pub mod schema;
 pub mod services;
 pub mod system_data;
+pub mod warm_up;
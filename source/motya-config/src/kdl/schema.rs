@@ -0,0 +1,727 @@
+//! A machine-readable description of the configuration, the sections, nodes,
+//! arguments and properties the section parsers accept, for editors and
+//! external validators. The test below keeps it in step with the parsers.
+
+use crate::kdl::parser::utils::PrimitiveType::{self, Bool, Integer, String as Str};
+
+use self::Occurs::{Optional, Repeated, Required, RequiredRepeated};
+
+/// How often a node may appear in its block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occurs {
+    Required,
+    Optional,
+    Repeated,
+    /// At least once.
+    RequiredRepeated,
+}
+
+impl Occurs {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Occurs::Required => "required",
+            Occurs::Optional => "optional",
+            Occurs::Repeated => "repeated",
+            Occurs::RequiredRepeated => "required-repeated",
+        }
+    }
+}
+
+/// A positional argument, a name ending in `...` takes any number of them.
+#[derive(Debug)]
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: PrimitiveType,
+    /// The accepted values when there are only a few.
+    pub values: &'static [&'static str],
+}
+
+/// A property, the one named `*` stands for properties of any name.
+#[derive(Debug)]
+pub struct Prop {
+    pub name: &'static str,
+    pub kind: PrimitiveType,
+    pub required: bool,
+    pub values: &'static [&'static str],
+}
+
+/// A node, the one named `*` stands for nodes named by the user, such as
+/// services or listener addresses.
+#[derive(Debug)]
+pub struct Node {
+    pub name: &'static str,
+    pub occurs: Occurs,
+    pub description: &'static str,
+    pub args: &'static [Arg],
+    pub props: &'static [Prop],
+    /// Nested blocks may repeat an ancestor, e.g. a `section` in a `section`.
+    pub children: &'static [&'static Node],
+}
+
+const fn node(name: &'static str, occurs: Occurs) -> Node {
+    Node {
+        name,
+        occurs,
+        description: "",
+        args: &[],
+        props: &[],
+        children: &[],
+    }
+}
+
+impl Node {
+    const fn about(self, description: &'static str) -> Self {
+        Self {
+            description,
+            ..self
+        }
+    }
+
+    const fn args(self, args: &'static [Arg]) -> Self {
+        Self { args, ..self }
+    }
+
+    const fn props(self, props: &'static [Prop]) -> Self {
+        Self { props, ..self }
+    }
+
+    const fn children(self, children: &'static [&'static Node]) -> Self {
+        Self { children, ..self }
+    }
+}
+
+const fn arg(name: &'static str, kind: PrimitiveType) -> Arg {
+    Arg {
+        name,
+        kind,
+        values: &[],
+    }
+}
+
+const fn prop(name: &'static str, kind: PrimitiveType) -> Prop {
+    Prop {
+        name,
+        kind,
+        required: false,
+        values: &[],
+    }
+}
+
+impl Arg {
+    const fn values(self, values: &'static [&'static str]) -> Self {
+        Self { values, ..self }
+    }
+}
+
+impl Prop {
+    const fn required(self) -> Self {
+        Self {
+            required: true,
+            ..self
+        }
+    }
+
+    const fn values(self, values: &'static [&'static str]) -> Self {
+        Self { values, ..self }
+    }
+}
+
+/// The top-level sections of a configuration file.
+pub static SECTIONS: &[&Node] = &[&SYSTEM, &INCLUDES, &FLAGS, &DEFINITIONS, &SERVICES];
+
+static SYSTEM: Node = node("system", Required)
+    .about("Settings of the whole server, in the entry point")
+    .children(&[
+        &node("threads-per-service", Optional)
+            .about("Worker threads of each service")
+            .args(&[arg("INT", Integer)]),
+        &node("daemonize", Optional)
+            .about("Detach from the terminal after starting")
+            .args(&[arg("BOOL", Bool)]),
+        &node("upgrade-socket", Optional)
+            .about("Socket of a graceful upgrade")
+            .args(&[arg("PATH", Str)]),
+        &node("pid-file", Optional)
+            .about("Where the pid is written")
+            .args(&[arg("PATH", Str)]),
+        &node("providers", Optional)
+            .about("Where the configuration comes from, exactly one provider")
+            .children(&[
+                &node("files", Optional)
+                    .about("Local files")
+                    .props(&[prop("watch", Bool)]),
+                &node("s3", Optional)
+                    .about("An S3 object polled for changes")
+                    .props(&[
+                        prop("bucket", Str).required(),
+                        prop("key", Str).required(),
+                        prop("region", Str).required(),
+                        prop("interval", Str),
+                        prop("endpoint", Str),
+                    ]),
+                &node("http", Optional)
+                    .about("Configurations pushed over HTTP")
+                    .props(&[
+                        prop("address", Str).required(),
+                        prop("path", Str).required(),
+                        prop("persist", Bool),
+                        prop("token", Str),
+                    ]),
+            ]),
+        &node("events", Optional)
+            .about("Server-sent events of the proxy")
+            .props(&[prop("address", Str).required(), prop("path", Str)]),
+        &node("admin", Optional)
+            .about("Address of the admin API")
+            .args(&[arg("ADDR", Str)]),
+        &node("alerts", Optional)
+            .about("Webhooks called when a rule fires")
+            .children(&[
+                &node("webhook", Repeated)
+                    .about("URL an alert is posted to")
+                    .args(&[arg("URL", Str)])
+                    .props(&[
+                        prop("format", Str).values(&["generic", "slack", "pagerduty"]),
+                        prop("routing-key", Str),
+                    ]),
+                &node("pool-healthy-below", Optional)
+                    .about("Share of healthy backends of a route")
+                    .props(&[prop("percent", Integer).required()]),
+                &node("cert-expiring", Optional)
+                    .about("Days left on a listener certificate")
+                    .props(&[prop("days", Integer).required()]),
+                &node("error-rate-above", Optional)
+                    .about("Share of 5xx answers of a service")
+                    .props(&[
+                        prop("percent", Integer).required(),
+                        prop("window-secs", Integer),
+                        prop("min-requests", Integer),
+                    ]),
+            ]),
+        &node("state-dir", Optional)
+            .about("Directory of the state kept across restarts")
+            .args(&[arg("PATH", Str)]),
+        &node("affinity-keys", Optional)
+            .about("Keys signing affinity cookies")
+            .props(&[prop("rotate-hours", Integer)]),
+        &node("wasm", Optional)
+            .about("Compilation of Wasm plugins")
+            .props(&[
+                prop("compilation", Str).values(&["eager", "lazy"]),
+                prop("cache", Bool),
+                prop("precompiled-dir", Str),
+            ]),
+        &node("shutdown", Optional)
+            .about("Graceful shutdown timings")
+            .props(&[
+                prop("grace-period-secs", Integer),
+                prop("timeout-secs", Integer),
+            ]),
+        &node("client-ip-privacy", Optional)
+            .about("How client addresses are logged")
+            .props(&[
+                prop("strategy", Str).values(&["truncate", "hash"]),
+                prop("ipv4-prefix", Integer),
+                prop("ipv6-prefix", Integer),
+            ]),
+        &node("trusted-proxies", Optional)
+            .about("Proxies whose forwarded-for header is believed")
+            .props(&[prop("addrs", Str).required(), prop("header", Str)]),
+    ]);
+
+static INCLUDES: Node = node("includes", Optional)
+    .about("Other files of the configuration")
+    .props(&[prop("trusted-keys", Str)])
+    .children(&[&node("*", Repeated)
+        .about("A path relative to this file, or an http(s) URL")
+        .props(&[prop("sha256", Str)])]);
+
+static FLAGS: Node = node("flags", Optional)
+    .about("Initial state of the runtime feature flags")
+    .children(&[&node("*", Repeated)
+        .about("A flag")
+        .args(&[arg("BOOL", Bool)])]);
+
+static DEFINITIONS: Node = node("definitions", Optional)
+    .about("Named chains, plugins and key profiles")
+    .children(&[
+        &node("modifiers", Optional)
+            .about("Filter chains and filter names")
+            .children(&[
+                &MODIFIERS_NAMESPACE,
+                &node("chain-filters", Repeated)
+                    .about("A named filter chain")
+                    .args(&[arg("NAME", Str)])
+                    .children(&[&FILTER]),
+            ]),
+        &node("plugins", Optional)
+            .about("Wasm plugins")
+            .children(
+                &[&node("plugin", Repeated).about("A Wasm plugin").children(&[
+                    &node("name", Required)
+                        .about("Name filters refer to the plugin by")
+                        .args(&[arg("FQDN", Str)]),
+                    &node("load", Required)
+                        .about("Where the module is read from, a 'path' or a 'url'")
+                        .props(&[prop("path", Str), prop("url", Str)]),
+                    &node("capabilities", Optional)
+                        .about("What the plugin may access")
+                        .children(&[
+                            &node("dir", Repeated)
+                                .about("A preopened host directory")
+                                .args(&[arg("PATH", Str)])
+                                .props(&[prop("guest", Str), prop("read-only", Bool)]),
+                            &node("env", Repeated)
+                                .about("An environment variable")
+                                .args(&[arg("NAME", Str)])
+                                .props(&[prop("value", Str).required()]),
+                            &node("allow-host", Repeated)
+                                .about("A host the plugin may connect to")
+                                .args(&[arg("HOST:PORT", Str)]),
+                            &node("request-body", Optional)
+                                .about("Request body handed to the plugin")
+                                .props(&[prop("max-bytes", Integer).required()]),
+                            &node("http-client", Optional)
+                                .about("Limits of outgoing HTTP calls")
+                                .props(&[
+                                    prop("timeout-ms", Integer),
+                                    prop("max-concurrent", Integer),
+                                    prop("max-response-bytes", Integer),
+                                ]),
+                        ]),
+                    &node("limits", Optional)
+                        .about("Resources of a call")
+                        .props(&[
+                            prop("max-memory-mb", Integer),
+                            prop("max-ms", Integer),
+                            prop("on-failure", Str).values(&["open", "closed"]),
+                        ]),
+                ])],
+            ),
+        &node("key-profiles", Optional)
+            .about("Key templates of hashing selections")
+            .children(&[&KEY_PROFILES_NAMESPACE, &KEY_TEMPLATE]),
+        &node("geoip", Optional)
+            .about("MaxMind databases of the geoip filter")
+            .children(&[
+                &node("country", Optional)
+                    .about("Country database")
+                    .args(&[arg("PATH", Str)]),
+                &node("asn", Optional)
+                    .about("Autonomous system database")
+                    .args(&[arg("PATH", Str)]),
+            ]),
+    ]);
+
+static MODIFIERS_NAMESPACE: Node = node("namespace", Repeated)
+    .about("A namespace of filter names")
+    .args(&[arg("NAME", Str)])
+    .children(&[
+        &MODIFIERS_NAMESPACE,
+        &node("def", Repeated)
+            .about("A filter name in the namespace")
+            .props(&[prop("name", Str).required()]),
+    ]);
+
+static FILTER: Node = node("filter", Repeated)
+    .about("A filter, its other properties are its arguments")
+    .props(&[prop("name", Str).required(), prop("*", Str)])
+    .children(&[&ACTIVE_BETWEEN]);
+
+static ACTIVE_BETWEEN: Node = node("active-between", Optional)
+    .about("Time window, in RFC 3339")
+    .args(&[arg("START", Str), arg("END", Str)]);
+
+static KEY_PROFILES_NAMESPACE: Node = node("namespace", Repeated)
+    .about("A namespace of key templates")
+    .args(&[arg("NAME", Str)])
+    .children(&[&KEY_PROFILES_NAMESPACE, &KEY_TEMPLATE]);
+
+static KEY_TEMPLATE: Node = node("template", Repeated)
+    .about("A named key template")
+    .args(&[arg("NAME", Str)])
+    .children(KEY_TEMPLATE_NODES);
+
+static KEY_TEMPLATE_NODES: &[&Node] = &[
+    &node("key", Required)
+        .about("Source of the key, 'Concat' joins its 'parts'")
+        .args(&[arg("SOURCE", Str)])
+        .props(&[
+            prop("fallback", Str),
+            prop("parts", Str),
+            prop("separator", Str),
+        ]),
+    &node("algorithm", Optional)
+        .about("Hash of the key")
+        .props(&[prop("name", Str), prop("seed", Str)]),
+    &node("transforms-order", Optional)
+        .about("Transforms applied to the key in order")
+        .children(&[&node("*", Repeated)
+            .about("A transform")
+            .props(&[prop("*", Str)])]),
+];
+
+static SERVICES: Node = node("services", Optional)
+    .about("Proxies and file servers")
+    .children(&[&node("*", Repeated)
+        .about("A service, with 'connectors' or 'file-server'")
+        .children(&[
+            &LISTENERS,
+            &node("hosts", Optional)
+                .about("Hosts served, for listeners shared by services")
+                .args(&[arg("HOST...", Str)]),
+            &node("warm-up", Optional)
+                .about("Requests sent before the service takes traffic")
+                .props(&[prop("timeout-secs", Integer)])
+                .children(&[&node("request", Repeated)
+                    .about("A warm-up request")
+                    .args(&[arg("PATH", Str)])
+                    .props(&[prop("method", Str), prop("repeat", Integer)])]),
+            &node("error-pages", Optional)
+                .about("Directory of the error pages")
+                .args(&[arg("DIR", Str)])
+                .props(&[prop("default-language", Str)]),
+            &node("labels", Optional)
+                .about("Labels exported in metrics")
+                .props(&[prop("*", Str)]),
+            &node("maintenance", Optional)
+                .about("Page answered while in maintenance")
+                .props(&[
+                    prop("enabled", Bool),
+                    prop("code", Integer),
+                    prop("page", Str),
+                    prop("retry-after-secs", Integer),
+                ]),
+            &node("connectors", Optional)
+                .about("Routes of a proxy")
+                .children(CONNECTORS),
+            &node("file-server", Optional)
+                .about("Files served from a directory")
+                .props(&[prop("base-path", Str)]),
+        ])]);
+
+static LISTENERS: Node = node("listeners", Required)
+    .about("Addresses of the service")
+    .children(&[&node("*", RequiredRepeated)
+        .about("A listening address")
+        .props(&[
+            prop("cert-path", Str),
+            prop("key-path", Str),
+            prop("offer-h2", Bool),
+            prop("h2c", Bool),
+            prop("unmatched-host", Str).values(&["default", "misdirected", "close"]),
+            prop("redirect-to-https", Bool),
+            prop("max-request-body-bytes", Integer),
+            prop("h2-max-header-list-bytes", Integer),
+            prop("h2-max-concurrent-streams", Integer),
+            prop("h2-max-reset-streams", Integer),
+            prop("tls-fingerprint", Bool),
+        ])
+        .children(&[&node("acme", Required)
+            .about("Certificates issued by an ACME directory")
+            .props(&[
+                prop("domains", Str).required(),
+                prop("email", Str).required(),
+                prop("cache-dir", Str).required(),
+                prop("directory", Str),
+                prop("challenge", Str).values(&["http-01"]),
+            ])])]);
+
+const PROTOCOLS: &[&str] = &["h1-only", "h2-only", "h2-or-h1", "grpc"];
+
+const SELECTIONS: &[&str] = &["RoundRobin", "Random", "FNV", "Ketama"];
+
+static CONNECTORS: &[&Node] = &[
+    &PROXY,
+    &node("return", Optional)
+        .about("A fixed answer, instead of 'proxy'")
+        .props(&[prop("code", Integer), prop("response", Str)]),
+    &LOAD_BALANCE,
+    &node("shaping", Optional)
+        .about("Bandwidth and latency of the answers")
+        .props(&[
+            prop("bytes-per-sec", Integer),
+            prop("latency-ms", Integer),
+            prop("jitter-ms", Integer),
+        ]),
+    &node("timeouts", Optional)
+        .about("Timeouts of the upstream requests")
+        .props(&[
+            prop("connect-ms", Integer),
+            prop("read-ms", Integer),
+            prop("write-ms", Integer),
+            prop("total-ms", Integer),
+            prop("body", Str),
+        ]),
+    &node("retry", Optional)
+        .about("Retries of failed upstream requests")
+        .props(&[
+            prop("attempts", Integer),
+            prop("backoff-ms", Integer),
+            prop("on", Str),
+            prop("methods", Str),
+            prop("budget-percent", Integer),
+        ]),
+    &node("compression", Optional)
+        .about("Compression of the answers")
+        .props(&[
+            prop("algorithms", Str),
+            prop("min-size", Integer),
+            prop("content-types", Str),
+            prop("dictionary", Str),
+        ]),
+    &node("max-request-body-bytes", Optional)
+        .about("Largest request body accepted")
+        .args(&[arg("BYTES", Integer)]),
+    &node("buffering", Optional)
+        .about("Buffering of the answers")
+        .args(&[arg("BOOL", Bool)])
+        .props(&[
+            prop("max-bytes", Integer),
+            prop("low-watermark", Integer),
+            prop("upstream-recv-bytes", Integer),
+        ]),
+    &node("record-har", Optional)
+        .about("Exchanges written as HAR files")
+        .props(&[
+            prop("dir", Str).required(),
+            prop("one-in", Integer),
+            prop("max-body-bytes", Integer),
+        ]),
+    &node("cache", Optional)
+        .about("GET responses kept and served without the upstream")
+        .props(&[
+            prop("storage", Str).values(&["memory", "disk"]),
+            prop("max-size-mb", Integer),
+            prop("path", Str),
+            prop("max-size-gb", Integer),
+            prop("ttl-secs", Integer),
+        ])
+        .children(&[&node("vary", Optional)
+            .about("How request headers named by Vary split the cache")
+            .children(&[
+                &node("ignore", Repeated)
+                    .about("A header that never splits the cache")
+                    .args(&[arg("HEADER", Str)]),
+                &node("bucket", Repeated)
+                    .about("Values of a header collapsed to the first one found")
+                    .args(&[arg("HEADER", Str), arg("VALUE...", Str)]),
+            ])]),
+    &ACTIVE_BETWEEN,
+    &node("use-chain", Repeated)
+        .about("A chain by name, or an inline chain of filters")
+        .args(&[arg("NAME", Str)])
+        .children(&[&FILTER]),
+    &SECTION,
+];
+
+static SECTION: Node = node("section", Repeated)
+    .about("Routes under a path")
+    .args(&[arg("PATH", Str)])
+    .props(&[
+        prop("as", Str).values(&["prefix", "exact", "regex"]),
+        prop("methods", Str),
+        prop("flag", Str),
+    ])
+    .children(CONNECTORS);
+
+static PROXY: Node = node("proxy", Optional)
+    .about("Upstream of the route, a URI or a block of servers")
+    .args(&[arg("URI", Str)])
+    .props(&[
+        prop("tls-sni", Str),
+        prop("sni-allow", Str),
+        prop("proto", Str).values(PROTOCOLS),
+        prop("h2c", Bool),
+        prop("allow-websocket", Bool),
+        prop("pool-min-idle", Integer),
+        prop("pool-warm-path", Str),
+        prop("set-header", Str),
+        prop("remove-header", Str),
+        prop("strip-prefix", Bool),
+        prop("forwarded-for", Bool),
+    ])
+    .children(&[
+        &node("server", Repeated)
+            .about("An upstream server")
+            .args(&[arg("ADDR", Str)])
+            .props(&[prop("weight", Integer), prop("vnodes", Integer)]),
+        &node("tls-sni", Optional)
+            .about("SNI of the upstream TLS connections")
+            .args(&[arg("SNI", Str)])
+            .props(&[prop("allow", Str)]),
+        &node("proto", Optional)
+            .about("Protocol of the upstream connections")
+            .args(&[arg("PROTO", Str).values(PROTOCOLS)]),
+        &node("h2c", Optional)
+            .about("HTTP/2 without TLS")
+            .args(&[arg("BOOL", Bool)]),
+        &node("read-your-writes", Optional)
+            .about("Clients that wrote read from the primary")
+            .props(&[
+                prop("ttl-secs", Integer),
+                prop("cookie", Str),
+                prop("header", Str),
+            ])
+            .children(&[&PRIMARY]),
+        &node("split-by-method", Optional)
+            .about("Writes go to the primary")
+            .children(&[&PRIMARY]),
+        &node("backend-override", Optional)
+            .about("A header picking the backend")
+            .props(&[prop("header", Str), prop("trusted", Str).required()]),
+        &node("split", Optional)
+            .about("Weighted groups of servers")
+            .props(&[prop("sticky", Bool), prop("override-header", Str)])
+            .children(&[&node("group", RequiredRepeated)
+                .about("A group of servers")
+                .args(&[arg("NAME", Str)])
+                .props(&[prop("weight", Integer).required()])
+                .children(&[&node("server", RequiredRepeated)
+                    .about("A server of the group")
+                    .args(&[arg("ADDR", Str)])])]),
+    ]);
+
+static PRIMARY: Node = node("primary", RequiredRepeated)
+    .about("A primary server")
+    .args(&[arg("ADDR", Str)]);
+
+static LOAD_BALANCE: Node = node("load-balance", Optional)
+    .about("How servers are picked")
+    .children(&[
+        &node("selection", Optional)
+            .about("Selection algorithm, hashing ones need a key")
+            .args(&[arg("KIND", Str).values(&[
+                "RoundRobin",
+                "Random",
+                "FNV",
+                "Ketama",
+                "CookieSticky",
+            ])])
+            .props(&[
+                prop("use-key-profile", Str),
+                prop("key", Str).values(&[
+                    "UriPath",
+                    "SourceAddrAndUriPath",
+                    "Header",
+                    "Cookie",
+                    "QueryParam",
+                ]),
+                prop("header-name", Str),
+                prop("param", Str),
+                prop("cookie", Str),
+                prop("ttl-secs", Integer),
+                prop("fallback", Str).values(SELECTIONS),
+            ])
+            .children(KEY_TEMPLATE_NODES),
+        &node("health-check", Optional)
+            .about("Health checks of the servers")
+            .args(&[arg("KIND", Str).values(&["None", "Grpc"])])
+            .props(&[prop("service", Str), prop("interval-secs", Integer)]),
+        &node("discovery", Optional)
+            .about("Where servers are discovered, settings as properties")
+            .args(&[arg("NAME", Str)])
+            .props(&[prop("*", Str)]),
+        &node("circuit-breaker", Optional)
+            .about("Servers taken out after failing")
+            .props(&[
+                prop("consecutive-failures", Integer),
+                prop("error-rate-percent", Integer),
+                prop("window-secs", Integer),
+                prop("min-requests", Integer),
+                prop("cool-down-secs", Integer),
+            ]),
+        &node("when-down", Optional)
+            .about("Answer when no server is healthy")
+            .props(&[
+                prop("code", Integer),
+                prop("response", Str),
+                prop("retry-after-secs", Integer),
+            ]),
+    ]);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use regex::Regex;
+
+    use super::*;
+
+    /// Parsers of the sections above.
+    const PARSERS: &[&str] = &[
+        include_str!("system_data.rs"),
+        include_str!("alerts.rs"),
+        include_str!("includes.rs"),
+        include_str!("definitions.rs"),
+        include_str!("key_profile_parser.rs"),
+        include_str!("chain_parser.rs"),
+        include_str!("active_window.rs"),
+        include_str!("services.rs"),
+        include_str!("listeners.rs"),
+        include_str!("warm_up.rs"),
+        include_str!("file_server.rs"),
+        include_str!("connectors.rs"),
+        include_str!("cache.rs"),
+    ];
+
+    fn collect(nodes: &[&Node], seen: &mut HashSet<*const Node>, names: &mut HashSet<&str>) {
+        for node in nodes {
+            if !seen.insert(*node) {
+                continue;
+            }
+            names.insert(node.name);
+            names.extend(node.props.iter().map(|prop| prop.name));
+            collect(node.children, seen, names);
+        }
+    }
+
+    #[test]
+    fn test_covers_parsers() {
+        let mut names = HashSet::new();
+        collect(SECTIONS, &mut HashSet::new(), &mut names);
+
+        let directive =
+            Regex::new(r#"(?:optional|required|repeated|required_repeated)\("([a-z0-9-]+)""#)
+                .unwrap();
+        let any = Regex::new(r#"_any\(&\[([^\]]*)\]"#).unwrap();
+        let typed = Regex::new(r#"\("([a-z0-9-]+)", PrimitiveType::"#).unwrap();
+        let quoted = Regex::new(r#""([a-z0-9-]+)""#).unwrap();
+
+        for source in PARSERS {
+            // Tests parse documents of their own.
+            let source = source.split("#[cfg(test)]").next().unwrap();
+
+            let any_names = any
+                .captures_iter(source)
+                .flat_map(|list| quoted.captures_iter(list.get(1).unwrap().as_str()))
+                .map(|name| name.get(1).unwrap().as_str());
+            let names_read = directive
+                .captures_iter(source)
+                .chain(typed.captures_iter(source))
+                .map(|name| name.get(1).unwrap().as_str())
+                .chain(any_names);
+
+            for name in names_read {
+                assert!(names.contains(name), "'{name}' is missing from the schema");
+            }
+        }
+    }
+
+    #[test]
+    fn test_sections() {
+        let sections: Vec<_> = SECTIONS.iter().map(|section| section.name).collect();
+        assert_eq!(
+            sections,
+            ["system", "includes", "flags", "definitions", "services"]
+        );
+
+        // A section holds routes like the connectors holding it.
+        let connectors = SERVICES.children[0]
+            .children
+            .iter()
+            .find(|node| node.name == "connectors")
+            .unwrap();
+        assert!(std::ptr::eq(connectors.children, SECTION.children));
+    }
+}
@@ -1,19 +1,32 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use http::StatusCode;
+
 use motya_macro::validate;
 
 use crate::common_types::{
-    definitions_table::DefinitionsTable, file_server::FileServerConfig, listeners::Listeners,
-    section_parser::SectionParser, services::ServicesConfig,
+    definitions_table::DefinitionsTable, error_pages::ErrorPagesConfig,
+    file_server::FileServerConfig, listeners::Listeners, maintenance::MaintenanceConfig,
+    section_parser::SectionParser, services::ServicesConfig, warm_up::WarmUpConfig,
 };
 use crate::{
     internal::ProxyConfig,
     kdl::{
-        connectors::ConnectorsSection,
+        connectors::{parse_host_pattern, ConnectorsSection},
         file_server::FileServerSection,
         listeners::ListenersSection,
-        parser::{block::BlockParser, ctx::ParseContext},
+        parser::{
+            block::BlockParser,
+            ctx::ParseContext,
+            ensures::Rule,
+            typed_value::TypedValue,
+            utils::{OptionTypedValueExt, PrimitiveType},
+        },
+        warm_up::WarmUpSection,
     },
 };
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum ServiceConfig {
     Proxy(ProxyConfig),
@@ -58,10 +71,39 @@ impl<'a> ServicesSection<'a> {
         let mut block = BlockParser::new(service_ctx.clone())?;
 
         let listeners = block.required("listeners", |ctx| ListenersSection.parse_node(ctx))?;
+        let hosts = block.optional("hosts", |ctx| self.parse_hosts(ctx))?;
+        let warm_up = block.optional("warm-up", |ctx| WarmUpSection.parse_node(ctx))?;
+        let error_pages = block.optional("error-pages", |ctx| self.parse_error_pages(ctx))?;
+        let labels = block.optional("labels", |ctx| self.parse_labels(ctx))?;
+        let maintenance = block.optional("maintenance", |ctx| self.parse_maintenance(ctx))?;
 
         let service_type =
             block.required_any(&["connectors", "file-server"], |ctx, name| match name {
-                "connectors" => self.parse_proxy(ctx, listeners, &service_name),
+                "connectors" => self.parse_proxy(
+                    ctx,
+                    listeners,
+                    hosts.clone().unwrap_or_default(),
+                    warm_up,
+                    error_pages.clone(),
+                    labels.clone().unwrap_or_default(),
+                    maintenance.clone().unwrap_or_default(),
+                    &service_name,
+                ),
+                "file-server" if warm_up.is_some() => {
+                    Err(ctx.error("'warm-up' is only supported by proxy services"))
+                }
+                "file-server" if error_pages.is_some() => {
+                    Err(ctx.error("'error-pages' is only supported by proxy services"))
+                }
+                "file-server" if hosts.is_some() => {
+                    Err(ctx.error("'hosts' is only supported by proxy services"))
+                }
+                "file-server" if labels.is_some() => {
+                    Err(ctx.error("'labels' is only supported by proxy services"))
+                }
+                "file-server" if maintenance.is_some() => {
+                    Err(ctx.error("'maintenance' is only supported by proxy services"))
+                }
                 "file-server" => self.parse_file_server(ctx, listeners, &service_name),
                 _ => unreachable!("Guaranteed by BlockParser"),
             })?;
@@ -71,10 +113,128 @@ impl<'a> ServicesSection<'a> {
         Ok(service_type)
     }
 
+    /// `hosts "example.com" "*.example.com"`
+    fn parse_hosts(&self, ctx: ParseContext<'_>) -> miette::Result<Vec<String>> {
+        ctx.validate(&[Rule::NoChildren, Rule::OnlyKeys(&[])])?;
+
+        let hosts = ctx
+            .args()?
+            .iter()
+            .map(|entry| parse_host_pattern(&ctx, &TypedValue::new(&ctx, entry).as_str()?))
+            .collect::<miette::Result<Vec<_>>>()?;
+
+        if hosts.is_empty() {
+            return Err(ctx.error("'hosts' requires at least one host"));
+        }
+
+        Ok(hosts)
+    }
+
+    /// `labels team="payments" env="prod"`
+    fn parse_labels(&self, ctx: ParseContext<'_>) -> miette::Result<BTreeMap<String, String>> {
+        ctx.validate(&[Rule::NoChildren, Rule::NoPositionalArgs])?;
+
+        let mut labels = BTreeMap::new();
+
+        for entry in ctx.args()? {
+            let Some(name) = entry.name().map(|name| name.value()) else {
+                continue;
+            };
+
+            // Labels become Prometheus labels next to `service`.
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !name.starts_with("__");
+            if !valid || name == "service" {
+                return Err(ctx.error(format!(
+                    "'{name}' is not a valid label name, use letters, digits and '_' and not 'service'"
+                )));
+            }
+
+            labels.insert(name.to_string(), TypedValue::new(&ctx, entry).as_str()?);
+        }
+
+        if labels.is_empty() {
+            return Err(ctx.error("'labels' requires at least one label, e.g. team=\"payments\""));
+        }
+
+        Ok(labels)
+    }
+
+    /// `maintenance enabled=#true code=503 retry-after-secs=60 page="/etc/motya/maintenance.html"`
+    fn parse_maintenance(&self, ctx: ParseContext<'_>) -> miette::Result<MaintenanceConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("enabled", PrimitiveType::Bool),
+                ("code", PrimitiveType::Integer),
+                ("page", PrimitiveType::String),
+                ("retry-after-secs", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let [enabled, code, page, retry_after] =
+            ctx.props(["enabled", "code", "page", "retry-after-secs"])?;
+        let defaults = MaintenanceConfig::default();
+
+        let status = code.parse_as::<StatusCode>()?.unwrap_or(defaults.status);
+        if !status.is_client_error() && !status.is_server_error() {
+            return Err(ctx.error("'code' must be a 4xx or 5xx status"));
+        }
+
+        let retry_after = match retry_after.as_usize()? {
+            None => defaults.retry_after,
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs as u64)),
+        };
+
+        Ok(MaintenanceConfig {
+            enabled: enabled.as_bool()?.unwrap_or(defaults.enabled),
+            status,
+            page: page.as_str()?.map(Into::into),
+            retry_after,
+        })
+    }
+
+    /// `error-pages "/etc/motya/errors" default-language="en"`
+    fn parse_error_pages(&self, ctx: ParseContext<'_>) -> miette::Result<ErrorPagesConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::ExactArgs(1),
+            Rule::OnlyKeysTyped(&[("default-language", PrimitiveType::String)]),
+        ])?;
+
+        let dir = ctx.first()?.as_str()?;
+        let default_language = ctx
+            .opt_prop("default-language")?
+            .as_str()?
+            .unwrap_or_else(|| "en".to_string())
+            .to_ascii_lowercase();
+
+        let valid_tag = default_language
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+        if !valid_tag {
+            return Err(ctx.error(format!("'{default_language}' is not a valid language tag")));
+        }
+
+        Ok(ErrorPagesConfig {
+            dir: dir.into(),
+            default_language,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn parse_proxy(
         &self,
         ctx: ParseContext<'_>,
         listeners: Listeners,
+        hosts: Vec<String>,
+        warm_up: Option<WarmUpConfig>,
+        error_pages: Option<ErrorPagesConfig>,
+        labels: BTreeMap<String, String>,
+        maintenance: MaintenanceConfig,
         service_name: &str,
     ) -> miette::Result<ServiceConfig> {
         let connectors = ConnectorsSection::new(self.global_definitions).parse_node(ctx)?;
@@ -82,7 +242,12 @@ impl<'a> ServicesSection<'a> {
         Ok(ServiceConfig::Proxy(ProxyConfig {
             name: service_name.to_string(),
             listeners,
+            hosts,
             connectors,
+            warm_up,
+            error_pages,
+            labels,
+            maintenance,
         }))
     }
 
@@ -232,4 +397,184 @@ mod tests {
             "Block must contain exactly one of: [\"connectors\", \"file-server\"]"
         );
     }
+
+    #[test]
+    fn test_parse_warm_up() {
+        let config = parse_services(
+            r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    warm-up {
+                        request "/health"
+                    }
+                    connectors {
+                        proxy "http://127.0.0.1:3000"
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let warm_up = config.proxies[0].warm_up.as_ref().unwrap();
+        assert_eq!(warm_up.requests[0].path, "/health");
+
+        let err = parse_services(
+            r#"
+            services {
+                Static {
+                    listeners { "127.0.0.1:8080" }
+                    warm-up {
+                        request "/index.html"
+                    }
+                    file-server base-path="/var/www"
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "only supported by proxy services"
+        );
+    }
+
+    #[test]
+    fn test_parse_error_pages() {
+        let config = parse_services(
+            r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    error-pages "/etc/motya/errors" default-language="pt-BR"
+                    connectors {
+                        proxy "http://127.0.0.1:3000"
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let error_pages = config.proxies[0].error_pages.as_ref().unwrap();
+        assert_eq!(error_pages.dir, std::path::Path::new("/etc/motya/errors"));
+        assert_eq!(error_pages.default_language, "pt-br");
+
+        let err = parse_services(
+            r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    error-pages "/etc/motya/errors" default-language="en_US"
+                    connectors {
+                        proxy "http://127.0.0.1:3000"
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "not a valid language tag");
+
+        let err = parse_services(
+            r#"
+            services {
+                Static {
+                    listeners { "127.0.0.1:8080" }
+                    error-pages "/etc/motya/errors"
+                    file-server base-path="/var/www"
+                }
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "only supported by proxy services"
+        );
+    }
+
+    #[test]
+    fn test_parse_labels() {
+        let service = |labels: &str| {
+            parse_services(&format!(
+                r#"
+                services {{
+                    Api {{
+                        listeners {{ "127.0.0.1:8080" }}
+                        {labels}
+                        connectors {{
+                            proxy "http://127.0.0.1:3000"
+                        }}
+                    }}
+                }}
+                "#
+            ))
+        };
+
+        let config = service(r#"labels team="payments" env="prod""#).unwrap();
+        assert_eq!(
+            config.proxies[0].labels,
+            BTreeMap::from([
+                ("env".to_string(), "prod".to_string()),
+                ("team".to_string(), "payments".to_string()),
+            ])
+        );
+
+        assert!(service("").unwrap().proxies[0].labels.is_empty());
+
+        for (labels, message) in [
+            (r#"labels cost-center="42""#, "not a valid label name"),
+            (r#"labels service="api""#, "not a valid label name"),
+            ("labels", "requires at least one label"),
+        ] {
+            let err = service(labels).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_parse_maintenance() {
+        let service = |maintenance: &str| {
+            parse_services(&format!(
+                r#"
+                services {{
+                    Api {{
+                        listeners {{ "127.0.0.1:8080" }}
+                        {maintenance}
+                        connectors {{
+                            proxy "http://127.0.0.1:3000"
+                        }}
+                    }}
+                }}
+                "#
+            ))
+        };
+
+        assert_eq!(
+            service("").unwrap().proxies[0].maintenance,
+            MaintenanceConfig::default()
+        );
+
+        let config = service(
+            r#"maintenance enabled=#true code=502 retry-after-secs=0 page="/etc/motya/down.html""#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.proxies[0].maintenance,
+            MaintenanceConfig {
+                enabled: true,
+                status: StatusCode::BAD_GATEWAY,
+                page: Some("/etc/motya/down.html".into()),
+                retry_after: None,
+            }
+        );
+
+        let err = service("maintenance code=200").unwrap_err();
+        assert_err_contains!(
+            err.help().unwrap().to_string(),
+            "must be a 4xx or 5xx status"
+        );
+    }
 }
@@ -1,16 +1,22 @@
 use crate::block_parser;
-use crate::common_types::system_data::HttpProviderConfig;
+use crate::common_types::system_data::{
+    AdminConfig, AffinityKeysConfig, ClientIpPrivacy, EventsConfig, HttpProviderConfig,
+    ShutdownConfig, TrustedProxies, WasmCompilation, WasmConfig,
+};
 use crate::common_types::{
     section_parser::SectionParser,
     system_data::{ConfigProvider, FilesProviderConfig, S3ProviderConfig, SystemData},
 };
+use crate::kdl::alerts::AlertsSection;
 use crate::kdl::parser::ctx::ParseContext;
 use crate::kdl::parser::ensures::Rule;
 use crate::kdl::parser::utils::{OptionTypedValueExt, PrimitiveType};
+use cidr::IpCidr;
 use http::uri::PathAndQuery;
 use motya_macro::validate;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub struct SystemDataSection;
 
@@ -29,7 +35,16 @@ impl SystemDataSection {
             daemonize: optional("daemonize") => |ctx| self.parse_daemonize(ctx),
             upgrade: optional("upgrade-socket") => |ctx| self.parse_upgrade_socket(ctx),
             pid: optional("pid-file") => |ctx| self.parse_pid_file(ctx),
-            provider: optional("providers") => |ctx| self.parse_providers(ctx)
+            provider: optional("providers") => |ctx| self.parse_providers(ctx),
+            events: optional("events") => |ctx| self.parse_events(ctx),
+            admin: optional("admin") => |ctx| self.parse_admin(ctx),
+            alerts: optional("alerts") => |ctx| AlertsSection.parse_node(ctx),
+            state_dir: optional("state-dir") => |ctx| self.parse_state_dir(ctx),
+            affinity_keys: optional("affinity-keys") => |ctx| self.parse_affinity_keys(ctx),
+            wasm: optional("wasm") => |ctx| self.parse_wasm(ctx),
+            shutdown: optional("shutdown") => |ctx| self.parse_shutdown(ctx),
+            client_ip_privacy: optional("client-ip-privacy") => |ctx| self.parse_client_ip_privacy(ctx),
+            trusted_proxies: optional("trusted-proxies") => |ctx| self.parse_trusted_proxies(ctx)
         );
 
         Ok(Some(SystemData {
@@ -38,6 +53,15 @@ impl SystemDataSection {
             upgrade_socket: upgrade,
             pid_file: pid,
             provider,
+            events,
+            admin,
+            alerts,
+            state_dir,
+            affinity_keys: affinity_keys.unwrap_or_default(),
+            wasm: wasm.unwrap_or_default(),
+            shutdown: shutdown.unwrap_or_default(),
+            client_ip_privacy,
+            trusted_proxies,
         }))
     }
 
@@ -65,6 +89,167 @@ impl SystemDataSection {
         ctx.first()?.parse_as::<PathBuf>()
     }
 
+    fn parse_state_dir(&self, ctx: ParseContext<'_>) -> miette::Result<PathBuf> {
+        ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1)])?;
+
+        ctx.first()?.parse_as::<PathBuf>()
+    }
+
+    fn parse_affinity_keys(&self, ctx: ParseContext<'_>) -> miette::Result<AffinityKeysConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[("rotate-hours", PrimitiveType::Integer)]),
+        ])?;
+
+        let Some(hours) = ctx.opt_prop("rotate-hours")?.as_usize()? else {
+            return Ok(AffinityKeysConfig::default());
+        };
+
+        if hours == 0 {
+            return Err(ctx.error("'rotate-hours' must be greater than zero"));
+        }
+
+        Ok(AffinityKeysConfig {
+            rotate: Duration::from_secs(hours as u64 * 60 * 60),
+        })
+    }
+
+    fn parse_wasm(&self, ctx: ParseContext<'_>) -> miette::Result<WasmConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("compilation", PrimitiveType::String),
+                ("cache", PrimitiveType::Bool),
+                ("precompiled-dir", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let [compilation, cache, precompiled_dir] =
+            ctx.props(["compilation", "cache", "precompiled-dir"])?;
+        let defaults = WasmConfig::default();
+
+        let compilation = match compilation.as_str()?.as_deref() {
+            None => defaults.compilation,
+            Some("eager") => WasmCompilation::Eager,
+            Some("lazy") => WasmCompilation::Lazy,
+            Some(other) => {
+                return Err(ctx.error(format!(
+                    "Unknown compilation '{other}', expected 'eager' or 'lazy'"
+                )))
+            }
+        };
+
+        Ok(WasmConfig {
+            compilation,
+            cache: cache.as_bool()?.unwrap_or(defaults.cache),
+            precompiled_dir: precompiled_dir.parse_as::<PathBuf>()?,
+        })
+    }
+
+    fn parse_shutdown(&self, ctx: ParseContext<'_>) -> miette::Result<ShutdownConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("grace-period-secs", PrimitiveType::Integer),
+                ("timeout-secs", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let [grace_period, timeout] = ctx.props(["grace-period-secs", "timeout-secs"])?;
+
+        Ok(ShutdownConfig {
+            grace_period: grace_period
+                .as_usize()?
+                .map(|secs| Duration::from_secs(secs as u64)),
+            timeout: timeout
+                .as_usize()?
+                .map(|secs| Duration::from_secs(secs as u64)),
+        })
+    }
+
+    fn parse_client_ip_privacy(&self, ctx: ParseContext<'_>) -> miette::Result<ClientIpPrivacy> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("strategy", PrimitiveType::String),
+                ("ipv4-prefix", PrimitiveType::Integer),
+                ("ipv6-prefix", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let [strategy, ipv4_prefix, ipv6_prefix] =
+            ctx.props(["strategy", "ipv4-prefix", "ipv6-prefix"])?;
+        let ipv4_prefix = ipv4_prefix.as_usize()?;
+        let ipv6_prefix = ipv6_prefix.as_usize()?;
+
+        match strategy.as_str()?.as_deref() {
+            None | Some("truncate") => {
+                if ipv4_prefix.is_some_and(|bits| bits > 32) {
+                    return Err(ctx.error("'ipv4-prefix' must be at most 32"));
+                }
+                if ipv6_prefix.is_some_and(|bits| bits > 128) {
+                    return Err(ctx.error("'ipv6-prefix' must be at most 128"));
+                }
+
+                Ok(ClientIpPrivacy::Truncate {
+                    ipv4_prefix: ipv4_prefix.unwrap_or(24) as u8,
+                    ipv6_prefix: ipv6_prefix.unwrap_or(48) as u8,
+                })
+            }
+            Some("hash") if ipv4_prefix.is_some() || ipv6_prefix.is_some() => {
+                Err(ctx.error("Prefixes only apply to the 'truncate' strategy"))
+            }
+            Some("hash") => Ok(ClientIpPrivacy::Hash),
+            Some(other) => Err(ctx.error(format!(
+                "Unknown strategy '{other}', expected 'truncate' or 'hash'"
+            ))),
+        }
+    }
+
+    fn parse_trusted_proxies(&self, ctx: ParseContext<'_>) -> miette::Result<TrustedProxies> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("addrs", PrimitiveType::String),
+                ("header", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let ranges = ctx
+            .prop("addrs")?
+            .as_str()?
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                range
+                    .parse::<IpCidr>()
+                    .map_err(|_| ctx.error(format!("'{range}' is not a valid CIDR range")))
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+
+        if ranges.is_empty() {
+            return Err(ctx.error("'addrs' must list at least one CIDR range"));
+        }
+
+        let header = ctx
+            .opt_prop("header")?
+            .as_str()?
+            .map(|header| header.to_lowercase())
+            .unwrap_or_else(|| "x-forwarded-for".to_string());
+
+        if http::HeaderName::from_bytes(header.as_bytes()).is_err() {
+            return Err(ctx.error(format!("'{header}' is not a valid header name")));
+        }
+
+        Ok(TrustedProxies { ranges, header })
+    }
+
     fn parse_providers(&self, providers_ctx: ParseContext<'_>) -> miette::Result<ConfigProvider> {
         providers_ctx.validate(&[Rule::ReqChildren, Rule::NoArgs])?;
 
@@ -90,7 +275,7 @@ impl SystemDataSection {
             Rule::OnlyKeysTyped(&[("watch", PrimitiveType::Bool)]),
         ])?;
 
-        let watch = ctx.opt_prop("watch")?.as_bool()?.unwrap_or(false);
+        let watch = ctx.opt_prop("watch")?.as_bool()?.unwrap_or(true);
         Ok(ConfigProvider::Files(FilesProviderConfig { watch }))
     }
 
@@ -135,6 +320,7 @@ impl SystemDataSection {
                 ("address", PrimitiveType::String),
                 ("path", PrimitiveType::String),
                 ("persist", PrimitiveType::Bool),
+                ("token", PrimitiveType::String),
             ]),
         ])?;
 
@@ -147,12 +333,60 @@ impl SystemDataSection {
 
         let persist = ctx.opt_prop("persist")?.as_bool()?.unwrap_or(false);
 
+        let token = ctx.opt_prop("token")?.as_str()?;
+        if token.as_deref().is_some_and(str::is_empty) {
+            return Err(ctx.error("'token' must not be empty"));
+        }
+        // Anyone reaching the listener could replace the whole configuration.
+        if token.is_none() && !address.ip().is_loopback() {
+            return Err(ctx.error(format!(
+                "'http' provider on non-loopback address '{address}' needs a 'token'"
+            )));
+        }
+
         Ok(ConfigProvider::Http(HttpProviderConfig {
             address,
             path,
             persist,
+            token,
         }))
     }
+
+    fn parse_events(&self, ctx: ParseContext<'_>) -> miette::Result<EventsConfig> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::NoPositionalArgs,
+            Rule::OnlyKeysTyped(&[
+                ("address", PrimitiveType::String),
+                ("path", PrimitiveType::String),
+            ]),
+        ])?;
+
+        let address: SocketAddr = ctx
+            .prop("address")?
+            .as_str()?
+            .parse()
+            .map_err(|e| ctx.error(format!("Invalid address format: {e}")))?;
+
+        let path = ctx
+            .opt_prop("path")?
+            .parse_as::<PathAndQuery>()?
+            .unwrap_or_else(|| PathAndQuery::from_static("/events"));
+
+        Ok(EventsConfig { address, path })
+    }
+
+    fn parse_admin(&self, ctx: ParseContext<'_>) -> miette::Result<AdminConfig> {
+        ctx.validate(&[Rule::NoChildren, Rule::ExactArgs(1)])?;
+
+        let address: SocketAddr = ctx
+            .first()?
+            .as_str()?
+            .parse()
+            .map_err(|e| ctx.error(format!("Invalid address format: {e}")))?;
+
+        Ok(AdminConfig { address })
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -189,6 +423,20 @@ mod tests {
         } else {
             panic!("Wrong provider type");
         }
+
+        let opt_out = r#"system { providers { files watch=#false; }; }"#;
+        let data = parse_system(opt_out).expect("Should parse files");
+        assert_eq!(
+            data.provider,
+            Some(ConfigProvider::Files(FilesProviderConfig { watch: false }))
+        );
+
+        let default = r#"system { providers { files; }; }"#;
+        let data = parse_system(default).expect("Should parse files");
+        assert_eq!(
+            data.provider,
+            Some(ConfigProvider::Files(FilesProviderConfig { watch: true }))
+        );
     }
 
     #[test]
@@ -251,6 +499,7 @@ mod tests {
             assert_eq!(cfg.address.port(), 9090);
             assert_eq!(cfg.path, "/admin/config");
             assert!(cfg.persist);
+            assert_eq!(cfg.token, None);
         } else {
             panic!("Wrong provider type");
         }
@@ -261,7 +510,7 @@ mod tests {
         let input = r#"
         system {
             providers {
-                http address="0.0.0.0:8000" path="/update"
+                http address="0.0.0.0:8000" path="/update" token="s3cret"
             }
         }
         "#;
@@ -271,11 +520,39 @@ mod tests {
         if let Some(ConfigProvider::Http(cfg)) = data.provider {
             assert!(!cfg.persist);
             assert_eq!(cfg.path, "/update");
+            assert_eq!(cfg.token.as_deref(), Some("s3cret"));
         } else {
             panic!("Wrong provider type");
         }
     }
 
+    #[test]
+    fn test_http_provider_token_required_off_loopback() {
+        let input = r#"
+        system {
+            providers {
+                http address="0.0.0.0:8000" path="/update"
+            }
+        }
+        "#;
+
+        let err_msg = parse_system(input).unwrap_err().help().unwrap().to_string();
+        assert_err_contains!(
+            err_msg,
+            "'http' provider on non-loopback address '0.0.0.0:8000' needs a 'token'"
+        );
+
+        let input = r#"
+        system {
+            providers {
+                http address="[::1]:8000" path="/update"
+            }
+        }
+        "#;
+
+        assert!(parse_system(input).is_ok());
+    }
+
     #[test]
     fn test_conflict_providers() {
         let input = r#"
@@ -297,4 +574,215 @@ mod tests {
             "Directive 'http' conflicts with 's3' (mutually exclusive)"
         );
     }
+
+    #[test]
+    fn test_events_endpoint() {
+        let input = r#"
+        system {
+            events address="127.0.0.1:9100"
+        }
+        "#;
+
+        let data = parse_system(input).expect("Should parse events");
+        let events = data.events.expect("events should be set");
+
+        assert_eq!(events.address.port(), 9100);
+        assert_eq!(events.path, "/events");
+
+        let data = parse_system(
+            r#"
+            system {
+                events address="127.0.0.1:9100" path="/admin/events"
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(data.events.unwrap().path, "/admin/events");
+    }
+
+    #[test]
+    fn test_events_invalid_address() {
+        let err = parse_system(
+            r#"
+            system {
+                events address="localhost"
+            }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_err_contains!(err.help().unwrap().to_string(), "Invalid address format");
+    }
+
+    #[test]
+    fn test_state_dir_and_affinity_keys() {
+        let data = parse_system(
+            r#"
+            system {
+                state-dir "/var/lib/motya"
+                affinity-keys rotate-hours=24
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(data.state_dir, Some(PathBuf::from("/var/lib/motya")));
+        assert_eq!(data.affinity_keys.rotate, Duration::from_secs(24 * 60 * 60));
+
+        let defaults = parse_system("system { threads-per-service 2; }").unwrap();
+        assert_eq!(defaults.state_dir, None);
+        assert_eq!(defaults.affinity_keys, AffinityKeysConfig::default());
+    }
+
+    #[test]
+    fn test_affinity_keys_zero_rotation() {
+        let err = parse_system(
+            r#"
+            system {
+                affinity-keys rotate-hours=0
+            }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_err_contains!(err.help().unwrap().to_string(), "must be greater than zero");
+    }
+
+    #[test]
+    fn test_admin_listener() {
+        let data = parse_system(
+            r#"
+            system {
+                admin "127.0.0.1:7777"
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(data.admin.unwrap().address.port(), 7777);
+
+        let err = parse_system(
+            r#"
+            system {
+                admin "localhost"
+            }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_err_contains!(err.help().unwrap().to_string(), "Invalid address format");
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let data = parse_system("system { shutdown grace-period-secs=1 timeout-secs=5; }").unwrap();
+
+        assert_eq!(
+            data.shutdown,
+            ShutdownConfig {
+                grace_period: Some(Duration::from_secs(1)),
+                timeout: Some(Duration::from_secs(5)),
+            }
+        );
+
+        let defaults = parse_system("system { threads-per-service 2; }").unwrap();
+        assert_eq!(defaults.shutdown, ShutdownConfig::default());
+    }
+
+    #[test]
+    fn test_wasm_compilation() {
+        let data = parse_system(
+            r#"
+            system {
+                wasm compilation="lazy" cache=#false precompiled-dir="/var/cache/motya/wasm"
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            data.wasm,
+            WasmConfig {
+                compilation: WasmCompilation::Lazy,
+                cache: false,
+                precompiled_dir: Some(PathBuf::from("/var/cache/motya/wasm")),
+            }
+        );
+
+        let defaults = parse_system("system { threads-per-service 2; }").unwrap();
+        assert_eq!(defaults.wasm, WasmConfig::default());
+
+        let err = parse_system(r#"system { wasm compilation="jit"; }"#).unwrap_err();
+        assert_err_contains!(err.help().unwrap().to_string(), "Unknown compilation 'jit'");
+    }
+
+    #[test]
+    fn test_client_ip_privacy() {
+        let data = parse_system("system { client-ip-privacy }").unwrap();
+        assert_eq!(
+            data.client_ip_privacy,
+            Some(ClientIpPrivacy::Truncate {
+                ipv4_prefix: 24,
+                ipv6_prefix: 48
+            })
+        );
+
+        let data = parse_system(r#"system { client-ip-privacy strategy="hash" }"#).unwrap();
+        assert_eq!(data.client_ip_privacy, Some(ClientIpPrivacy::Hash));
+
+        let data = parse_system("system { daemonize #false }").unwrap();
+        assert!(data.client_ip_privacy.is_none());
+
+        let cases = [
+            ("client-ip-privacy ipv4-prefix=33", "at most 32"),
+            (
+                r#"client-ip-privacy strategy="hash" ipv6-prefix=64"#,
+                "only apply to the 'truncate' strategy",
+            ),
+            (r#"client-ip-privacy strategy="drop""#, "Unknown strategy"),
+        ];
+
+        for (node, expected) in cases {
+            let err = parse_system(&format!("system {{ {node} }}")).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let data = parse_system(
+            r#"system { trusted-proxies addrs="10.0.0.0/8, 2001:db8::/32" header="X-Real-IP" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            data.trusted_proxies,
+            Some(TrustedProxies {
+                ranges: vec![
+                    "10.0.0.0/8".parse().unwrap(),
+                    "2001:db8::/32".parse().unwrap()
+                ],
+                header: "x-real-ip".to_string(),
+            })
+        );
+
+        let data = parse_system(r#"system { trusted-proxies addrs="127.0.0.1" }"#).unwrap();
+        assert_eq!(data.trusted_proxies.unwrap().header, "x-forwarded-for");
+
+        let cases = [
+            (r#"trusted-proxies addrs=" , ""#, "at least one CIDR range"),
+            (
+                r#"trusted-proxies addrs="10.0.0.0/33""#,
+                "not a valid CIDR range",
+            ),
+            (
+                r#"trusted-proxies addrs="10.0.0.0/8" header="bad header""#,
+                "not a valid header name",
+            ),
+        ];
+
+        for (node, expected) in cases {
+            let err = parse_system(&format!("system {{ {node} }}")).unwrap_err();
+            assert_err_contains!(err.help().unwrap().to_string(), expected);
+        }
+    }
 }
@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use http::{uri::PathAndQuery, Method};
+use motya_macro::validate;
+
+use crate::{
+    block_parser,
+    common_types::{
+        section_parser::SectionParser,
+        warm_up::{WarmUpConfig, WarmUpRequest},
+    },
+    kdl::parser::{
+        ctx::ParseContext,
+        ensures::Rule,
+        utils::{OptionTypedValueExt, PrimitiveType},
+    },
+};
+
+/// Parses the `warm-up` block of a proxy service:
+///
+/// ```kdl
+/// warm-up timeout-secs=30 {
+///     request "/api/health"
+///     request "/api/search?q=warm" method="GET" repeat=10
+/// }
+/// ```
+pub struct WarmUpSection;
+
+impl SectionParser<ParseContext<'_>, WarmUpConfig> for WarmUpSection {
+    #[validate(ensure_node_name = "warm-up")]
+    fn parse_node(&self, ctx: ParseContext) -> miette::Result<WarmUpConfig> {
+        ctx.validate(&[
+            Rule::NoPositionalArgs,
+            Rule::ReqChildren,
+            Rule::OnlyKeysTyped(&[("timeout-secs", PrimitiveType::Integer)]),
+        ])?;
+
+        let timeout = ctx.opt_prop("timeout-secs")?.as_usize()?.unwrap_or(30) as u64;
+
+        if timeout == 0 {
+            return Err(ctx.error("'timeout-secs' must be greater than zero"));
+        }
+
+        block_parser!(
+            ctx.clone(),
+            requests: repeated("request") => |ctx| self.parse_request(ctx)
+        );
+
+        if requests.is_empty() {
+            return Err(ctx.error("'warm-up' requires at least one 'request'"));
+        }
+
+        Ok(WarmUpConfig {
+            requests,
+            timeout: Duration::from_secs(timeout),
+        })
+    }
+}
+
+impl WarmUpSection {
+    fn parse_request(&self, ctx: ParseContext<'_>) -> miette::Result<WarmUpRequest> {
+        ctx.validate(&[
+            Rule::NoChildren,
+            Rule::ExactArgs(1),
+            Rule::OnlyKeysTyped(&[
+                ("method", PrimitiveType::String),
+                ("repeat", PrimitiveType::Integer),
+            ]),
+        ])?;
+
+        let path = ctx.first()?.parse_as::<PathAndQuery>()?;
+
+        if !path.as_str().starts_with('/') {
+            return Err(ctx.error("Warm-up request path must start with '/'"));
+        }
+
+        let [method, repeat] = ctx.props(["method", "repeat"])?;
+        let method = method.as_str()?.unwrap_or_else(|| "GET".to_string());
+        let method = method
+            .to_ascii_uppercase()
+            .parse::<Method>()
+            .map_err(|_| ctx.error(format!("Invalid method '{method}'")))?;
+        let repeat = repeat.as_usize()?.unwrap_or(1);
+
+        if repeat == 0 {
+            return Err(ctx.error("'repeat' must be greater than zero"));
+        }
+
+        Ok(WarmUpRequest {
+            method,
+            path: path.to_string(),
+            repeat,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_err_contains;
+    use crate::kdl::parser::block::BlockParser;
+    use crate::kdl::parser::ctx::Current;
+    use kdl::KdlDocument;
+
+    fn parse_warm_up(input: &str) -> miette::Result<WarmUpConfig> {
+        let doc: KdlDocument = input.parse().unwrap();
+        let ctx = ParseContext::new(&doc, Current::Document(&doc), "test");
+        let mut block = BlockParser::new(ctx)?;
+
+        block.required("warm-up", |ctx| WarmUpSection.parse_node(ctx))
+    }
+
+    #[test]
+    fn test_warm_up() {
+        let config = parse_warm_up(
+            r#"
+            warm-up timeout-secs=10 {
+                request "/health"
+                request "/api/search?q=warm" method="post" repeat=5
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            WarmUpConfig {
+                requests: vec![
+                    WarmUpRequest {
+                        method: Method::GET,
+                        path: "/health".into(),
+                        repeat: 1,
+                    },
+                    WarmUpRequest {
+                        method: Method::POST,
+                        path: "/api/search?q=warm".into(),
+                        repeat: 5,
+                    },
+                ],
+                timeout: Duration::from_secs(10),
+            }
+        );
+    }
+
+    #[test]
+    fn test_warm_up_invalid() {
+        let empty = parse_warm_up(
+            r#"
+            warm-up {
+                request "/health" repeat=0
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(
+            empty.help().unwrap().to_string(),
+            "'repeat' must be greater than zero"
+        );
+
+        let relative = parse_warm_up(
+            r#"
+            warm-up {
+                request "health"
+            }
+            "#,
+        )
+        .unwrap_err();
+        assert_err_contains!(relative.help().unwrap().to_string(), "must start with '/'");
+    }
+}
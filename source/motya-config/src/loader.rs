@@ -1,4 +1,5 @@
-use miette::Result;
+use kdl::KdlDocument;
+use miette::{miette, IntoDiagnostic, Result};
 use std::path::PathBuf;
 
 use crate::common_types::definitions_table::DefinitionsTable;
@@ -43,3 +44,64 @@ impl<S: ConfigSource> ConfigLoader<S> {
         Self { source }
     }
 }
+
+/// Compiles a single self-contained KDL document, such as one pushed by the
+/// `http` config provider.
+///
+/// There is no base directory to resolve against, so `includes` are rejected.
+pub fn load_document(
+    source: &str,
+    name: &str,
+    global_definitions: &mut DefinitionsTable,
+) -> Result<Config> {
+    let doc: KdlDocument = source.parse().into_diagnostic()?;
+
+    if doc.get("includes").is_some() {
+        return Err(miette!(
+            "'includes' are not supported in '{name}', the document must be self-contained"
+        ));
+    }
+
+    ConfigCompiler::new(vec![(doc, name.to_string())]).compile(global_definitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_document() {
+        let source = r#"
+            system { }
+            services {
+                Pushed {
+                    listeners { "127.0.0.1:8080" }
+                    connectors {
+                        return code=200 response="OK"
+                    }
+                }
+            }
+        "#;
+
+        let mut table = DefinitionsTable::new_with_global();
+        let config = load_document(source, "pushed.kdl", &mut table).unwrap();
+
+        assert_eq!(config.basic_proxies.len(), 1);
+        assert_eq!(config.basic_proxies[0].name, "Pushed");
+    }
+
+    #[test]
+    fn test_load_document_rejects_includes() {
+        let source = r#"
+            includes {
+                include "./other.kdl"
+            }
+            system { }
+        "#;
+
+        let mut table = DefinitionsTable::new_with_global();
+        let err = load_document(source, "pushed.kdl", &mut table).unwrap_err();
+
+        crate::assert_err_contains!(err.to_string(), "'includes' are not supported");
+    }
+}
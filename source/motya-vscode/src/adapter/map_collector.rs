@@ -65,8 +65,13 @@ impl MapCollector {
 
         let base_dir = path.parent().unwrap_or_else(|| std::path::Path::new("."));
 
-        for path_str in includes {
-            let resolved_path = normalize_path(base_dir, &path_str);
+        for include in includes {
+            // Only the files of the snapshot are known here.
+            if include.is_remote() {
+                continue;
+            }
+
+            let resolved_path = normalize_path(base_dir, &include.location);
 
             let mut sub_docs = self.load_recursive(resolved_path).await?;
             docs.append(&mut sub_docs);
@@ -0,0 +1,61 @@
+//! Records build provenance for `GET /version` of the admin API.
+
+use std::{env, fs, path::Path, process::Command};
+
+fn main() {
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+
+    println!("cargo:rerun-if-env-changed=MOTYA_GIT_SHA");
+    let git_sha = env::var("MOTYA_GIT_SHA")
+        .ok()
+        .or_else(|| git(&workspace, &["rev-parse", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=MOTYA_GIT_SHA={git_sha}");
+
+    if let Some(git_dir) = git(&workspace, &["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/refs");
+    }
+
+    let lock = workspace.join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    let pingora = locked_version(&lock, "pingora").unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=MOTYA_PINGORA_VERSION={pingora}");
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            let feature = key.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=MOTYA_FEATURES={}", features.join(","));
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The version of `package` the lock file resolved.
+fn locked_version(lock: &Path, package: &str) -> Option<String> {
+    let lock = fs::read_to_string(lock).ok()?;
+    let name = format!("name = \"{package}\"");
+    let mut lines = lock.lines();
+
+    lines.find(|line| *line == name)?;
+
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+        .map(str::to_string)
+}
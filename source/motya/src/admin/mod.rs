@@ -0,0 +1,669 @@
+use std::{collections::BTreeMap, net::SocketAddr};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use pingora::{server::Server, upstreams::peer::HttpPeer, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::{ProxyHttp, Session};
+use serde_json::{json, Value};
+
+use crate::{
+    fingerprint, flags, maintenance, metrics,
+    proxy::{
+        balancer::{
+            drain,
+            ring::{KetamaRing, RingNode},
+        },
+        upstream_router::UpstreamContextTrait,
+        warm_up,
+        watcher::file_watcher,
+        SharedProxyState,
+    },
+    service_labels,
+};
+use motya_config::common_types::system_data::AdminConfig;
+
+type Reply = (StatusCode, Value);
+
+/// Admin API listener exposing runtime state as JSON.
+pub struct AdminService {
+    proxies: BTreeMap<String, SharedProxyState>,
+}
+
+pub fn admin_service(
+    config: &AdminConfig,
+    proxies: BTreeMap<String, SharedProxyState>,
+    server: &Server,
+) -> Box<dyn pingora::services::Service> {
+    let mut service = pingora_proxy::http_proxy_service_with_name(
+        &server.configuration,
+        AdminService { proxies },
+        "motya-admin",
+    );
+
+    service.add_tcp(&config.address.to_string());
+
+    Box::new(service)
+}
+
+/// A backend change to evaluate with `rebalance-report`.
+#[derive(Debug, Default, PartialEq)]
+struct Proposal {
+    service: Option<String>,
+    route: Option<String>,
+    add: Vec<SocketAddr>,
+    remove: Vec<SocketAddr>,
+}
+
+impl Proposal {
+    fn parse(query: Option<&str>) -> Result<Self, String> {
+        let mut proposal = Proposal::default();
+
+        for pair in query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|p| !p.is_empty())
+        {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+            let addr = || {
+                value
+                    .parse::<SocketAddr>()
+                    .map_err(|e| format!("invalid backend '{value}': {e}"))
+            };
+
+            match key {
+                "service" => proposal.service = Some(value.to_string()),
+                "route" => proposal.route = Some(value.to_string()),
+                "add" => proposal.add.push(addr()?),
+                "remove" => proposal.remove.push(addr()?),
+                other => return Err(format!("unknown parameter '{other}'")),
+            }
+        }
+
+        if proposal.add.is_empty() && proposal.remove.is_empty() {
+            return Err("nothing to evaluate, pass 'add' or 'remove'".to_string());
+        }
+
+        Ok(proposal)
+    }
+
+    fn apply(&self, ring: &KetamaRing) -> Result<KetamaRing, String> {
+        let mut nodes = ring.nodes().to_vec();
+
+        for addr in &self.remove {
+            let before = nodes.len();
+            nodes.retain(|node| node.addr != *addr);
+            if nodes.len() == before {
+                return Err(format!("backend {addr} is not in the ring"));
+            }
+        }
+
+        for addr in &self.add {
+            if nodes.iter().any(|node| node.addr == *addr) {
+                return Err(format!("backend {addr} is already in the ring"));
+            }
+            nodes.push(RingNode {
+                addr: *addr,
+                weight: 1,
+                vnodes: None,
+            });
+        }
+
+        Ok(KetamaRing::new(nodes))
+    }
+}
+
+/// The `enabled=true|false` query of a flag toggle.
+fn parse_enabled(query: Option<&str>) -> Result<bool, String> {
+    match query.unwrap_or_default() {
+        "enabled=true" => Ok(true),
+        "enabled=false" => Ok(false),
+        other => Err(format!(
+            "expected 'enabled=true' or 'enabled=false', got '{other}'"
+        )),
+    }
+}
+
+impl AdminService {
+    /// Every Ketama ring as `(service, route, ring)`.
+    fn rings(&self) -> Vec<(&str, String, KetamaRing)> {
+        self.proxies
+            .iter()
+            .flat_map(|(name, state)| {
+                state
+                    .load()
+                    .upstreams
+                    .iter()
+                    .filter_map(|upstream| {
+                        let ring = upstream.get_balancer()?.ketama_ring()?.clone();
+                        Some((
+                            name.as_str(),
+                            upstream.get_prefix_path().path().to_string(),
+                            ring,
+                        ))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn list_rings(&self) -> Reply {
+        let rings: Vec<Value> = self
+            .rings()
+            .into_iter()
+            .map(|(service, route, ring)| {
+                json!({
+                    "service": service,
+                    "route": route,
+                    "backends": ring.layout(),
+                })
+            })
+            .collect();
+
+        (StatusCode::OK, json!(rings))
+    }
+
+    fn rebalance_report(&self, query: Option<&str>) -> Reply {
+        let proposal = match Proposal::parse(query) {
+            Ok(proposal) => proposal,
+            Err(err) => return (StatusCode::BAD_REQUEST, json!({ "error": err })),
+        };
+
+        let mut candidates: Vec<_> = self
+            .rings()
+            .into_iter()
+            .filter(|(service, route, _)| {
+                proposal.service.as_deref().is_none_or(|s| s == *service)
+                    && proposal.route.as_deref().is_none_or(|r| r == route)
+            })
+            .collect();
+
+        let (service, route, ring) = match candidates.len() {
+            1 => candidates.remove(0),
+            0 => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    json!({ "error": "no matching Ketama ring" }),
+                )
+            }
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    json!({ "error": "several Ketama rings match, narrow down with 'service' and 'route'" }),
+                )
+            }
+        };
+
+        let proposed = match proposal.apply(&ring) {
+            Ok(proposed) => proposed,
+            Err(err) => return (StatusCode::BAD_REQUEST, json!({ "error": err })),
+        };
+
+        let report = ring.rebalance(&proposed);
+
+        (
+            StatusCode::OK,
+            json!({
+                "service": service,
+                "route": route,
+                "moved": report.moved,
+                "transfers": report.transfers,
+                "proposed": proposed.layout(),
+            }),
+        )
+    }
+
+    /// Circuit breaker state of every backend of the routes that have one.
+    fn list_breakers(&self) -> Reply {
+        let breakers: Vec<Value> = self
+            .proxies
+            .iter()
+            .flat_map(|(name, state)| {
+                state
+                    .load()
+                    .upstreams
+                    .iter()
+                    .filter_map(|upstream| {
+                        let breaker = upstream.get_balancer()?.breaker.as_ref()?;
+                        let backends: Vec<Value> = breaker
+                            .states()
+                            .into_iter()
+                            .map(|(addr, state)| json!({ "backend": addr, "state": state }))
+                            .collect();
+
+                        Some(json!({
+                            "service": name,
+                            "route": upstream.get_prefix_path().path(),
+                            "backends": backends,
+                        }))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        (StatusCode::OK, json!(breakers))
+    }
+
+    /// Backends of every balanced route, with whether they pass their health
+    /// checks and are drained.
+    fn list_health(&self) -> Reply {
+        let routes: Vec<Value> = self
+            .proxies
+            .iter()
+            .flat_map(|(name, state)| {
+                state
+                    .load()
+                    .upstreams
+                    .iter()
+                    .filter_map(|upstream| {
+                        let backends: Vec<Value> = upstream
+                            .get_balancer()?
+                            .health()
+                            .into_iter()
+                            .map(|(addr, healthy)| {
+                                json!({
+                                    "backend": addr,
+                                    "healthy": healthy,
+                                    "drained": drain::is_drained(&addr),
+                                })
+                            })
+                            .collect();
+
+                        Some(json!({
+                            "service": name,
+                            "route": upstream.get_prefix_path().path(),
+                            "backends": backends,
+                        }))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        (StatusCode::OK, json!(routes))
+    }
+
+    /// Usage of the `cache` of every route that has one.
+    async fn list_caches(&self) -> Reply {
+        let mut caches = vec![];
+
+        for (name, state) in &self.proxies {
+            let router = state.load_full();
+
+            for upstream in &router.upstreams {
+                let Some(cache) = &upstream.cache else {
+                    continue;
+                };
+                let stats = cache.stats().await;
+
+                caches.push(json!({
+                    "service": name,
+                    "route": upstream.get_prefix_path().path(),
+                    "storage": stats.storage,
+                    "entries": stats.entries,
+                    "used-bytes": stats.used_bytes,
+                    "max-bytes": stats.max_bytes,
+                    "hits": stats.hits,
+                    "misses": stats.misses,
+                }));
+            }
+        }
+
+        (StatusCode::OK, json!(caches))
+    }
+
+    /// Every drained backend.
+    fn list_drained() -> Reply {
+        (StatusCode::OK, json!(*drain::snapshot()))
+    }
+
+    /// Drains or undrains a backend in every route balancing it.
+    fn set_drained(addr: &str, query: Option<&str>) -> Reply {
+        let Ok(addr) = addr.parse::<SocketAddr>() else {
+            return (
+                StatusCode::BAD_REQUEST,
+                json!({ "error": format!("'{addr}' is not a backend address") }),
+            );
+        };
+        let drained = match parse_enabled(query) {
+            Ok(drained) => drained,
+            Err(err) => return (StatusCode::BAD_REQUEST, json!({ "error": err })),
+        };
+
+        drain::set(addr, drained);
+
+        (
+            StatusCode::OK,
+            json!({ "backend": addr, "drained": drained }),
+        )
+    }
+
+    /// Reloads the configuration files, the outcome is reported like the one
+    /// of a reload after a file changed.
+    fn reload() -> Reply {
+        Self::reload_reply(file_watcher::request_reload())
+    }
+
+    fn reload_reply(requested: bool) -> Reply {
+        if requested {
+            (StatusCode::ACCEPTED, json!({ "reload": "requested" }))
+        } else {
+            (
+                StatusCode::CONFLICT,
+                json!({ "error": "the configuration is not loaded from files" }),
+            )
+        }
+    }
+
+    /// Every proxy service with its `labels`.
+    fn list_services(&self) -> Reply {
+        let labels = service_labels::snapshot();
+
+        let services: Vec<Value> = self
+            .proxies
+            .keys()
+            .map(|name| {
+                json!({
+                    "service": name,
+                    "labels": labels.get(name).cloned().unwrap_or_default(),
+                })
+            })
+            .collect();
+
+        (StatusCode::OK, json!(services))
+    }
+
+    /// Fingerprint of the configuration in effect, `null` when it was built
+    /// from CLI flags.
+    fn config(&self) -> Reply {
+        (
+            StatusCode::OK,
+            json!({ "fingerprint": fingerprint::current().as_deref() }),
+        )
+    }
+
+    /// Feature flags as requests see them now.
+    fn list_flags() -> Reply {
+        let snapshot = flags::snapshot();
+        let flags: BTreeMap<&str, bool> = snapshot.iter().collect();
+
+        (StatusCode::OK, json!(flags))
+    }
+
+    /// Turns a flag on or off until the next reload.
+    fn set_flag(name: &str, query: Option<&str>) -> Reply {
+        let enabled = match parse_enabled(query) {
+            Ok(enabled) => enabled,
+            Err(err) => return (StatusCode::BAD_REQUEST, json!({ "error": err })),
+        };
+
+        match flags::set(name, enabled) {
+            Ok(()) => (StatusCode::OK, json!({ "flag": name, "enabled": enabled })),
+            Err(err) => (StatusCode::NOT_FOUND, json!({ "error": err })),
+        }
+    }
+
+    /// Maintenance state of every proxy service.
+    fn list_maintenance() -> Reply {
+        let snapshot = maintenance::snapshot();
+        let services: BTreeMap<&str, bool> = snapshot
+            .iter()
+            .map(|(service, enabled)| (service.as_str(), *enabled))
+            .collect();
+
+        (StatusCode::OK, json!(services))
+    }
+
+    /// Puts a service in or out of maintenance until the next reload.
+    fn set_maintenance(service: &str, query: Option<&str>) -> Reply {
+        let enabled = match parse_enabled(query) {
+            Ok(enabled) => enabled,
+            Err(err) => return (StatusCode::BAD_REQUEST, json!({ "error": err })),
+        };
+
+        match maintenance::set(service, enabled) {
+            Ok(()) => (
+                StatusCode::OK,
+                json!({ "service": service, "maintenance": enabled }),
+            ),
+            Err(err) => (StatusCode::NOT_FOUND, json!({ "error": err })),
+        }
+    }
+
+    /// Build provenance, for bug reports.
+    fn version() -> Reply {
+        let features: Vec<&str> = env!("MOTYA_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect();
+
+        (
+            StatusCode::OK,
+            json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "git-sha": env!("MOTYA_GIT_SHA"),
+                "features": features,
+                "pingora": env!("MOTYA_PINGORA_VERSION"),
+            }),
+        )
+    }
+
+    /// `503` while a service is still warming up.
+    fn ready(&self) -> Reply {
+        let pending = warm_up::pending();
+
+        if pending.is_empty() {
+            (StatusCode::OK, json!({ "ready": true }))
+        } else {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({ "ready": false, "warming-up": pending }),
+            )
+        }
+    }
+
+    async fn handle(&self, req: &RequestHeader) -> Reply {
+        if let Some(name) = req.uri.path().strip_prefix("/flags/") {
+            if req.method != Method::PUT {
+                return (
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    json!({ "error": "flags are set with PUT" }),
+                );
+            }
+
+            return Self::set_flag(name, req.uri.query());
+        }
+
+        if let Some(service) = req.uri.path().strip_prefix("/maintenance/") {
+            if req.method != Method::PUT {
+                return (
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    json!({ "error": "maintenance is set with PUT" }),
+                );
+            }
+
+            return Self::set_maintenance(service, req.uri.query());
+        }
+
+        if let Some(addr) = req.uri.path().strip_prefix("/drain/") {
+            if req.method != Method::PUT {
+                return (
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    json!({ "error": "backends are drained with PUT" }),
+                );
+            }
+
+            return Self::set_drained(addr, req.uri.query());
+        }
+
+        if req.uri.path() == "/reload" {
+            if req.method != Method::POST {
+                return (
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    json!({ "error": "reloads are requested with POST" }),
+                );
+            }
+
+            return Self::reload();
+        }
+
+        if req.method != Method::GET {
+            return (
+                StatusCode::METHOD_NOT_ALLOWED,
+                json!({ "error": "only GET is supported" }),
+            );
+        }
+
+        match req.uri.path() {
+            "/breakers" => self.list_breakers(),
+            "/cache" => self.list_caches().await,
+            "/config" => self.config(),
+            "/drain" => Self::list_drained(),
+            "/flags" => Self::list_flags(),
+            "/health" => self.list_health(),
+            "/maintenance" => Self::list_maintenance(),
+            "/ready" => self.ready(),
+            "/rings" => self.list_rings(),
+            "/rings/rebalance-report" => self.rebalance_report(req.uri.query()),
+            "/services" => self.list_services(),
+            "/version" => Self::version(),
+            _ => (StatusCode::NOT_FOUND, json!({ "error": "not found" })),
+        }
+    }
+}
+
+#[async_trait]
+impl ProxyHttp for AdminService {
+    type CTX = ();
+
+    fn new_ctx(&self) -> Self::CTX {}
+
+    async fn upstream_peer(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Self::CTX,
+    ) -> Result<Box<HttpPeer>> {
+        // Every request is answered in `request_filter`.
+        Err(pingora::Error::new_str("Request Failed"))
+    }
+
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut Self::CTX) -> Result<bool> {
+        let req = session.req_header();
+
+        let (status, content_type, body) =
+            if req.method == Method::GET && req.uri.path() == "/metrics" {
+                (StatusCode::OK, metrics::CONTENT_TYPE, metrics::render())
+            } else {
+                let (status, body) = self.handle(req).await;
+                (status, "application/json", body.to_string())
+            };
+
+        let mut response = ResponseHeader::build(status, Some(2))?;
+        response.insert_header("Content-Type", content_type)?;
+        response.insert_header("Content-Length", body.len().to_string())?;
+
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(Bytes::from(body), true)
+            .await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(addrs: &[&str]) -> KetamaRing {
+        KetamaRing::new(
+            addrs
+                .iter()
+                .map(|a| RingNode {
+                    addr: a.parse().unwrap(),
+                    weight: 1,
+                    vnodes: None,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse_enabled() {
+        assert_eq!(parse_enabled(Some("enabled=true")), Ok(true));
+        assert_eq!(parse_enabled(Some("enabled=false")), Ok(false));
+        assert!(parse_enabled(Some("enabled=yes")).is_err());
+        assert!(parse_enabled(None).is_err());
+    }
+
+    #[test]
+    fn test_drain_and_reload() {
+        let (status, _) = AdminService::set_drained("10.0.0.1", Some("enabled=true"));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = AdminService::set_drained("10.0.0.7:80", Some("enabled=true"));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["drained"], true);
+        assert!(drain::is_drained(&"10.0.0.7:80".parse().unwrap()));
+
+        assert_eq!(AdminService::reload_reply(true).0, StatusCode::ACCEPTED);
+        assert_eq!(AdminService::reload_reply(false).0, StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn test_parse_proposal() {
+        let proposal = Proposal::parse(Some(
+            "service=api&route=/cache&add=10.0.0.4:80&remove=10.0.0.1:80",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            proposal,
+            Proposal {
+                service: Some("api".into()),
+                route: Some("/cache".into()),
+                add: vec!["10.0.0.4:80".parse().unwrap()],
+                remove: vec!["10.0.0.1:80".parse().unwrap()],
+            }
+        );
+
+        assert!(Proposal::parse(Some("service=api")).is_err());
+        assert!(Proposal::parse(Some("add=localhost")).is_err());
+        assert!(Proposal::parse(Some("add=10.0.0.4:80&weight=2")).is_err());
+    }
+
+    #[test]
+    fn test_apply_proposal() {
+        let current = ring(&["10.0.0.1:80", "10.0.0.2:80"]);
+
+        let proposal = Proposal::parse(Some("add=10.0.0.3:80&remove=10.0.0.1:80")).unwrap();
+        let proposed = proposal.apply(&current).unwrap();
+        let addrs: Vec<String> = proposed
+            .nodes()
+            .iter()
+            .map(|n| n.addr.to_string())
+            .collect();
+        assert_eq!(addrs, ["10.0.0.2:80", "10.0.0.3:80"]);
+
+        let unknown = Proposal::parse(Some("remove=10.0.0.9:80")).unwrap();
+        assert!(unknown.apply(&current).is_err());
+
+        let duplicate = Proposal::parse(Some("add=10.0.0.2:80")).unwrap();
+        assert!(duplicate.apply(&current).is_err());
+    }
+
+    #[test]
+    fn test_version() {
+        let (status, body) = AdminService::version();
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["git-sha"].is_string());
+        assert!(body["features"].is_array());
+    }
+}
@@ -0,0 +1,634 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwapOption;
+use async_trait::async_trait;
+use pingora::{
+    server::ShutdownWatch,
+    services::background::{background_service, BackgroundService},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    events::{self, Event},
+    proxy::acme,
+};
+use motya_config::{
+    common_types::{
+        alerts::{AlertRule, AlertsConfig},
+        connectors::UpstreamConfig,
+        listeners::{ListenerKind, TlsConfig},
+    },
+    internal::{HealthCheckKind, ProxyConfig, SelectionKind},
+};
+
+pub mod webhook;
+
+/// How often certificates are re-checked when no error rate window is configured.
+const DEFAULT_TICK: Duration = Duration::from_secs(60);
+
+static RESPONSES: AtomicU64 = AtomicU64::new(0);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a finished downstream response for the error rate rule.
+///
+/// `None` means the request failed before a response was written.
+pub fn record_response(status: Option<u16>) {
+    RESPONSES.fetch_add(1, Ordering::Relaxed);
+
+    if status.is_none_or(|status| status >= 500) {
+        ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Certificates and health checked backends of the current configuration.
+#[derive(Debug, Default, PartialEq)]
+pub struct Inventory {
+    /// Certificate file of every TLS listener, by the subject of its alerts:
+    /// the primary domain of an `acme` listener, the file itself otherwise.
+    pub certs: BTreeMap<String, PathBuf>,
+    /// Backends of the health checked upstreams, by proxy service.
+    pub pools: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Inventory {
+    pub fn new(proxies: &[ProxyConfig]) -> Self {
+        let mut inventory = Self::default();
+
+        for proxy in proxies {
+            for listener in &proxy.listeners.list_cfgs {
+                let ListenerKind::Tcp { tls: Some(tls), .. } = &listener.source else {
+                    continue;
+                };
+
+                let (subject, path) = match tls {
+                    TlsConfig::Files { cert_path, .. } => {
+                        (cert_path.display().to_string(), cert_path.clone())
+                    }
+                    TlsConfig::Acme(acme) => (acme.domains[0].clone(), acme::cert_paths(acme).0),
+                };
+                inventory.certs.insert(subject, path);
+            }
+
+            for upstream in &proxy.connectors.upstreams {
+                let (UpstreamConfig::MultiServer(m), Some(lb_options)) =
+                    (&upstream.upstream, &upstream.lb_options)
+                else {
+                    continue;
+                };
+
+                // Ketama rings are never health checked.
+                if matches!(lb_options.health_checks, HealthCheckKind::None)
+                    || matches!(lb_options.selection, SelectionKind::KetamaHashing)
+                {
+                    continue;
+                }
+
+                inventory
+                    .pools
+                    .entry(proxy.name.clone())
+                    .or_default()
+                    .extend(m.servers.iter().map(|s| s.address.to_string()));
+            }
+        }
+
+        inventory
+    }
+}
+
+static INVENTORY: ArcSwapOption<Inventory> = ArcSwapOption::const_empty();
+
+/// Sets the inventory of a configuration that was just loaded or reloaded. The
+/// alerting service picks it up when it starts and on every `config-reloaded`.
+pub fn apply(proxies: &[ProxyConfig]) {
+    INVENTORY.store(Some(Arc::new(Inventory::new(proxies))));
+}
+
+/// Expiry of every certificate of `inventory` that can be read.
+async fn read_certs(inventory: &Inventory) -> HashMap<String, SystemTime> {
+    let mut certs = HashMap::new();
+
+    for (subject, path) in &inventory.certs {
+        let pem = match tokio::fs::read(path).await {
+            Ok(pem) => pem,
+            // An `acme` listener that has no certificate yet.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                tracing::warn!("Failed to read certificate {path:?}: {err}");
+                continue;
+            }
+        };
+
+        match acme::leaf_expiry(&pem) {
+            Ok(expires_at) => {
+                certs.insert(
+                    subject.clone(),
+                    UNIX_EPOCH + Duration::from_secs(expires_at),
+                );
+            }
+            Err(err) => tracing::warn!("Failed to parse certificate {path:?}: {err}"),
+        }
+    }
+
+    certs
+}
+
+/// Responses and errors counted since the previous call.
+fn take_response_counts() -> (u64, u64) {
+    (
+        RESPONSES.swap(0, Ordering::Relaxed),
+        ERRORS.swap(0, Ordering::Relaxed),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub rule: &'static str,
+    /// What the alert is about: a service, a domain, or `global`.
+    pub subject: String,
+    pub status: AlertStatus,
+    pub summary: String,
+}
+
+impl Alert {
+    /// Stable identifier shared by the firing and the resolving notification.
+    pub fn dedup_key(&self) -> String {
+        format!("motya/{}/{}", self.rule, self.subject)
+    }
+}
+
+/// Tracks the observed state and turns rule transitions into [`Alert`]s.
+///
+/// An alert is emitted once when its condition starts to hold and once more
+/// when it stops holding.
+pub struct AlertEvaluator {
+    rules: Vec<AlertRule>,
+    pools: HashMap<String, HashMap<String, bool>>,
+    certs: HashMap<String, SystemTime>,
+    firing: HashSet<(&'static str, String)>,
+}
+
+impl AlertEvaluator {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules,
+            pools: HashMap::new(),
+            certs: HashMap::new(),
+            firing: HashSet::new(),
+        }
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        self.rules
+            .iter()
+            .find_map(|rule| match rule {
+                AlertRule::ErrorRateAbove { window, .. } => Some(*window),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_TICK)
+    }
+
+    pub fn observe(&mut self, event: &Event, now: SystemTime) -> Vec<Alert> {
+        match event {
+            Event::BackendHealthChanged {
+                service,
+                backend,
+                healthy,
+            } => {
+                self.pools
+                    .entry(service.clone())
+                    .or_default()
+                    .insert(backend.clone(), *healthy);
+
+                self.check_pool(service).into_iter().collect()
+            }
+            Event::CertRenewed { domain, expires_at } => {
+                self.certs.insert(
+                    domain.clone(),
+                    UNIX_EPOCH + Duration::from_secs(*expires_at),
+                );
+
+                self.check_cert(domain, now).into_iter().collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Replaces the certificates and the pool members with those of the current
+    /// configuration. Backends new to a pool count as healthy, as they do in the
+    /// load balancer until a check fails, the others keep their last state.
+    pub fn seed(
+        &mut self,
+        certs: HashMap<String, SystemTime>,
+        pools: &BTreeMap<String, BTreeSet<String>>,
+        now: SystemTime,
+    ) -> Vec<Alert> {
+        let mut alerts = vec![];
+
+        let dropped: Vec<String> = self
+            .certs
+            .keys()
+            .filter(|domain| !certs.contains_key(*domain))
+            .cloned()
+            .collect();
+        alerts.extend(dropped.iter().filter_map(|domain| {
+            self.transition(
+                "cert-expiring",
+                domain,
+                false,
+                format!("certificate for '{domain}' is no longer served"),
+            )
+        }));
+
+        let dropped: Vec<String> = self
+            .pools
+            .keys()
+            .filter(|service| !pools.contains_key(*service))
+            .cloned()
+            .collect();
+        alerts.extend(dropped.iter().filter_map(|service| {
+            self.transition(
+                "pool-healthy-below",
+                service,
+                false,
+                format!("backends of '{service}' are no longer health checked"),
+            )
+        }));
+
+        self.certs = certs;
+        self.pools = pools
+            .iter()
+            .map(|(service, backends)| {
+                let known = self.pools.remove(service).unwrap_or_default();
+                let pool = backends
+                    .iter()
+                    .map(|backend| {
+                        let healthy = known.get(backend).copied().unwrap_or(true);
+                        (backend.clone(), healthy)
+                    })
+                    .collect();
+                (service.clone(), pool)
+            })
+            .collect();
+
+        let domains: Vec<String> = self.certs.keys().cloned().collect();
+        alerts.extend(
+            domains
+                .iter()
+                .filter_map(|domain| self.check_cert(domain, now)),
+        );
+        alerts.extend(pools.keys().filter_map(|service| self.check_pool(service)));
+        alerts
+    }
+
+    /// Periodic evaluation; `responses` and `errors` were counted since the previous tick.
+    pub fn tick(&mut self, now: SystemTime, responses: u64, errors: u64) -> Vec<Alert> {
+        let domains: Vec<String> = self.certs.keys().cloned().collect();
+
+        let mut alerts: Vec<Alert> = domains
+            .iter()
+            .filter_map(|domain| self.check_cert(domain, now))
+            .collect();
+
+        alerts.extend(self.check_error_rate(responses, errors));
+        alerts
+    }
+
+    fn check_pool(&mut self, service: &str) -> Option<Alert> {
+        let threshold = self.rules.iter().find_map(|rule| match rule {
+            AlertRule::PoolHealthyBelow { percent } => Some(*percent as u64),
+            _ => None,
+        })?;
+
+        let pool = self.pools.get(service)?;
+        let total = pool.len() as u64;
+        let healthy = pool.values().filter(|healthy| **healthy).count() as u64;
+
+        self.transition(
+            "pool-healthy-below",
+            service,
+            healthy * 100 < threshold * total,
+            format!(
+                "{healthy}/{total} backends of '{service}' are healthy, threshold is {threshold}%"
+            ),
+        )
+    }
+
+    fn check_cert(&mut self, domain: &str, now: SystemTime) -> Option<Alert> {
+        let within = self.rules.iter().find_map(|rule| match rule {
+            AlertRule::CertExpiring { within } => Some(*within),
+            _ => None,
+        })?;
+
+        let expires_at = *self.certs.get(domain)?;
+        let left = expires_at.duration_since(now).unwrap_or_default();
+
+        self.transition(
+            "cert-expiring",
+            domain,
+            left < within,
+            format!(
+                "certificate for '{domain}' expires in {} hours",
+                left.as_secs() / 3600
+            ),
+        )
+    }
+
+    fn check_error_rate(&mut self, responses: u64, errors: u64) -> Option<Alert> {
+        let (percent, min_requests) = self.rules.iter().find_map(|rule| match rule {
+            AlertRule::ErrorRateAbove {
+                percent,
+                min_requests,
+                ..
+            } => Some((*percent as u64, *min_requests)),
+            _ => None,
+        })?;
+
+        let active = responses >= min_requests && errors * 100 > percent * responses;
+
+        self.transition(
+            "error-rate-above",
+            "global",
+            active,
+            format!("{errors} of {responses} responses failed, threshold is {percent}%"),
+        )
+    }
+
+    fn transition(
+        &mut self,
+        rule: &'static str,
+        subject: &str,
+        active: bool,
+        summary: String,
+    ) -> Option<Alert> {
+        let key = (rule, subject.to_string());
+
+        let status = match (active, self.firing.contains(&key)) {
+            (true, false) => {
+                self.firing.insert(key);
+                AlertStatus::Firing
+            }
+            (false, true) => {
+                self.firing.remove(&key);
+                AlertStatus::Resolved
+            }
+            _ => return None,
+        };
+
+        Some(Alert {
+            rule,
+            subject: subject.to_string(),
+            status,
+            summary,
+        })
+    }
+}
+
+/// Background task evaluating the alert rules and posting the webhooks.
+pub struct AlertingService {
+    config: AlertsConfig,
+    client: reqwest::Client,
+}
+
+impl AlertingService {
+    pub fn new(config: AlertsConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Seeds `evaluator` with the inventory of the current configuration.
+    async fn seed(&self, evaluator: &mut AlertEvaluator) -> Vec<Alert> {
+        let inventory = INVENTORY.load_full().unwrap_or_default();
+        let certs = read_certs(&inventory).await;
+
+        evaluator.seed(certs, &inventory.pools, SystemTime::now())
+    }
+
+    fn notify(&self, alert: Alert) {
+        tracing::warn!(
+            rule = alert.rule,
+            subject = %alert.subject,
+            status = ?alert.status,
+            "{}",
+            alert.summary
+        );
+
+        for hook in &self.config.webhooks {
+            let client = self.client.clone();
+            let hook = hook.clone();
+            let alert = alert.clone();
+
+            tokio::spawn(async move {
+                if let Err(err) = webhook::send(&client, &hook, &alert).await {
+                    tracing::error!("Failed to deliver alert to {}: {err}", hook.url);
+                }
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for AlertingService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut evaluator = AlertEvaluator::new(self.config.rules.clone());
+        let mut events = events::subscribe();
+
+        for alert in self.seed(&mut evaluator).await {
+            self.notify(alert);
+        }
+
+        let mut ticker = tokio::time::interval(evaluator.tick_interval());
+        // The first tick completes immediately and would see an empty window.
+        ticker.tick().await;
+
+        loop {
+            let alerts = tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = ticker.tick() => {
+                    let (responses, errors) = take_response_counts();
+                    evaluator.tick(SystemTime::now(), responses, errors)
+                }
+                record = events.recv() => match record {
+                    Ok(record) => {
+                        let mut alerts = evaluator.observe(&record.event, SystemTime::now());
+                        if let Event::ConfigReloaded { .. } = record.event {
+                            alerts.extend(self.seed(&mut evaluator).await);
+                        }
+                        alerts
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("Alerting fell behind, {missed} events dropped");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+            };
+
+            for alert in alerts {
+                self.notify(alert);
+            }
+        }
+    }
+}
+
+pub fn alerting_service(config: AlertsConfig) -> Box<dyn pingora::services::Service> {
+    Box::new(background_service(
+        "motya-alerting",
+        AlertingService::new(config),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health(service: &str, backend: &str, healthy: bool) -> Event {
+        Event::BackendHealthChanged {
+            service: service.into(),
+            backend: backend.into(),
+            healthy,
+        }
+    }
+
+    #[test]
+    fn test_pool_health_fires_and_resolves_once() {
+        let mut evaluator = AlertEvaluator::new(vec![AlertRule::PoolHealthyBelow { percent: 50 }]);
+        let now = SystemTime::now();
+
+        assert!(evaluator.observe(&health("api", "a", true), now).is_empty());
+        assert!(evaluator.observe(&health("api", "b", true), now).is_empty());
+        assert!(evaluator.observe(&health("api", "c", true), now).is_empty());
+
+        // 1/3 healthy
+        assert!(evaluator
+            .observe(&health("api", "a", false), now)
+            .is_empty());
+        let fired = evaluator.observe(&health("api", "b", false), now);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].status, AlertStatus::Firing);
+        assert_eq!(fired[0].dedup_key(), "motya/pool-healthy-below/api");
+
+        // Still below, no duplicate
+        assert!(evaluator
+            .observe(&health("api", "c", false), now)
+            .is_empty());
+
+        assert!(evaluator.observe(&health("api", "a", true), now).is_empty());
+        let resolved = evaluator.observe(&health("api", "b", true), now);
+        assert_eq!(resolved[0].status, AlertStatus::Resolved);
+
+        // Other services are tracked separately
+        assert!(evaluator.observe(&health("web", "x", true), now).is_empty());
+    }
+
+    #[test]
+    fn test_cert_expiring() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut evaluator = AlertEvaluator::new(vec![AlertRule::CertExpiring { within: day * 14 }]);
+
+        let now = UNIX_EPOCH + day * 1000;
+        let renewed = |expires: SystemTime| Event::CertRenewed {
+            domain: "example.com".into(),
+            expires_at: expires.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        };
+
+        assert!(evaluator.observe(&renewed(now + day * 30), now).is_empty());
+
+        let fired = evaluator.tick(now + day * 20, 0, 0);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].status, AlertStatus::Firing);
+        assert!(evaluator.tick(now + day * 21, 0, 0).is_empty());
+
+        let resolved = evaluator.observe(&renewed(now + day * 90), now + day * 21);
+        assert_eq!(resolved[0].status, AlertStatus::Resolved);
+    }
+
+    #[test]
+    fn test_seed() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut evaluator = AlertEvaluator::new(vec![
+            AlertRule::PoolHealthyBelow { percent: 50 },
+            AlertRule::CertExpiring { within: day * 14 },
+        ]);
+        let now = UNIX_EPOCH + day * 1000;
+        let pool = |backends: &[&str]| {
+            BTreeMap::from([(
+                "api".to_string(),
+                backends.iter().map(|b| b.to_string()).collect(),
+            )])
+        };
+
+        let fired = evaluator.seed(
+            HashMap::from([("cert.pem".to_string(), now + day)]),
+            &pool(&["a", "b"]),
+            now,
+        );
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].dedup_key(), "motya/cert-expiring/cert.pem");
+
+        // Seeded backends count as healthy until they fail
+        assert!(evaluator
+            .observe(&health("api", "a", false), now)
+            .is_empty());
+        let fired = evaluator.observe(&health("api", "b", false), now);
+        assert_eq!(fired[0].dedup_key(), "motya/pool-healthy-below/api");
+
+        // A reload keeps the state of the backends still configured
+        let resolved = evaluator.seed(HashMap::new(), &pool(&["a", "c"]), now);
+        assert_eq!(resolved.len(), 2);
+        assert!(resolved
+            .iter()
+            .all(|alert| alert.status == AlertStatus::Resolved));
+        let fired = evaluator.observe(&health("api", "c", false), now);
+        assert_eq!(fired[0].status, AlertStatus::Firing);
+    }
+
+    #[test]
+    fn test_error_rate() {
+        let mut evaluator = AlertEvaluator::new(vec![AlertRule::ErrorRateAbove {
+            percent: 10,
+            window: Duration::from_secs(30),
+            min_requests: 20,
+        }]);
+        let now = SystemTime::now();
+
+        assert_eq!(evaluator.tick_interval(), Duration::from_secs(30));
+
+        // Not enough traffic to judge
+        assert!(evaluator.tick(now, 10, 10).is_empty());
+        assert!(evaluator.tick(now, 100, 10).is_empty());
+
+        let fired = evaluator.tick(now, 100, 11);
+        assert_eq!(fired[0].status, AlertStatus::Firing);
+        assert_eq!(fired[0].subject, "global");
+
+        let resolved = evaluator.tick(now, 100, 1);
+        assert_eq!(resolved[0].status, AlertStatus::Resolved);
+    }
+
+    #[test]
+    fn test_rules_without_config_are_ignored() {
+        let mut evaluator = AlertEvaluator::new(vec![]);
+
+        assert!(evaluator
+            .observe(&health("api", "a", false), SystemTime::now())
+            .is_empty());
+        assert!(evaluator.tick(SystemTime::now(), 10, 10).is_empty());
+        assert_eq!(evaluator.tick_interval(), DEFAULT_TICK);
+    }
+}
@@ -0,0 +1,118 @@
+use http::header::CONTENT_TYPE;
+use serde_json::{json, Value};
+
+use crate::alerting::{Alert, AlertStatus};
+use motya_config::common_types::alerts::{WebhookConfig, WebhookFormat};
+
+/// JSON body posted for `alert` in the given `format`.
+pub fn payload(format: &WebhookFormat, alert: &Alert) -> Value {
+    let status = match alert.status {
+        AlertStatus::Firing => "firing",
+        AlertStatus::Resolved => "resolved",
+    };
+
+    match format {
+        WebhookFormat::Generic => json!({
+            "alert": alert.rule,
+            "subject": alert.subject,
+            "status": status,
+            "summary": alert.summary,
+        }),
+        WebhookFormat::Slack => {
+            let icon = match alert.status {
+                AlertStatus::Firing => ":rotating_light:",
+                AlertStatus::Resolved => ":white_check_mark:",
+            };
+            json!({
+                "text": format!("{icon} [{status}] {}: {}", alert.rule, alert.summary),
+            })
+        }
+        WebhookFormat::PagerDuty { routing_key } => {
+            let action = match alert.status {
+                AlertStatus::Firing => "trigger",
+                AlertStatus::Resolved => "resolve",
+            };
+            json!({
+                "routing_key": routing_key,
+                "event_action": action,
+                "dedup_key": alert.dedup_key(),
+                "payload": {
+                    "summary": alert.summary,
+                    "source": "motya",
+                    "severity": "warning",
+                    "component": alert.subject,
+                    "class": alert.rule,
+                },
+            })
+        }
+    }
+}
+
+pub async fn send(
+    client: &reqwest::Client,
+    hook: &WebhookConfig,
+    alert: &Alert,
+) -> Result<(), reqwest::Error> {
+    client
+        .post(hook.url.to_string())
+        .header(CONTENT_TYPE, "application/json")
+        .body(payload(&hook.format, alert).to_string())
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(status: AlertStatus) -> Alert {
+        Alert {
+            rule: "pool-healthy-below",
+            subject: "api".into(),
+            status,
+            summary: "1/3 backends of 'api' are healthy, threshold is 50%".into(),
+        }
+    }
+
+    #[test]
+    fn test_generic_payload() {
+        assert_eq!(
+            payload(&WebhookFormat::Generic, &alert(AlertStatus::Firing)),
+            json!({
+                "alert": "pool-healthy-below",
+                "subject": "api",
+                "status": "firing",
+                "summary": "1/3 backends of 'api' are healthy, threshold is 50%",
+            })
+        );
+    }
+
+    #[test]
+    fn test_slack_payload() {
+        let body = payload(&WebhookFormat::Slack, &alert(AlertStatus::Resolved));
+
+        assert_eq!(
+            body["text"],
+            ":white_check_mark: [resolved] pool-healthy-below: 1/3 backends of 'api' are healthy, threshold is 50%"
+        );
+    }
+
+    #[test]
+    fn test_pagerduty_payload() {
+        let format = WebhookFormat::PagerDuty {
+            routing_key: "key".into(),
+        };
+
+        let firing = payload(&format, &alert(AlertStatus::Firing));
+        let resolved = payload(&format, &alert(AlertStatus::Resolved));
+
+        assert_eq!(firing["routing_key"], "key");
+        assert_eq!(firing["event_action"], "trigger");
+        assert_eq!(resolved["event_action"], "resolve");
+        assert_eq!(firing["dedup_key"], resolved["dedup_key"]);
+        assert_eq!(firing["payload"]["source"], "motya");
+    }
+}
@@ -1,15 +1,41 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use crate::{
+    admin::admin_service,
+    alerting::{self, alerting_service},
+    client_ip,
+    diagnostics::diagnostics_service,
+    events::sse::event_stream_service,
     files::motya_file_server,
+    fingerprint, flags,
     fs_adapter::TokioFs,
+    geoip::{self, geoip_service},
+    maintenance,
     proxy::{
+        balancer::{
+            affinity_keys::{affinity_key_rotation_service, AffinityKeyStore},
+            discovery::DiscoveryRegistry,
+        },
         filters::{chain_resolver::ChainResolver, generate_registry},
-        motya_proxy_service,
         plugins::store::WasmPluginStore,
+        pool_warm::{pool_warm_service, wants_pool_warm},
+        populate_listeners::check_tls_files,
+        shared_proxy_services,
         upstream_factory::UpstreamFactory,
-        watcher::file_watcher::ConfigWatcher,
+        virtual_host,
+        warm_up::warm_up_service,
+        watcher::{
+            file_watcher::ConfigWatcher,
+            http_provider::{config_push_service, last_good_path},
+        },
     },
+    runtime_metrics::runtime_metrics_service,
+    service_labels,
+    startup::{init_all, OnFailure},
 };
 
 use motya_config::{
@@ -17,12 +43,15 @@ use motya_config::{
         builder::CliConfigBuilder,
         cli_struct::{Cli, Commands},
     },
-    common_types::definitions_table::DefinitionsTable,
+    common_types::{
+        definitions_table::DefinitionsTable,
+        system_data::{ConfigProvider, FilesProviderConfig},
+    },
 };
 use motya_config::{
     internal::Config,
     kdl::fs_loader::FileCollector,
-    loader::{ConfigLoader, FileConfigLoaderProvider},
+    loader::{load_document, ConfigLoader, FileConfigLoaderProvider},
 };
 use pingora::{
     server::{
@@ -37,11 +66,15 @@ use tokio::sync::Mutex;
 pub struct AppContext {
     config: Config,
     resolver: ChainResolver,
-    watcher: ConfigWatcher,
+    /// Opened when `system.state-dir` is set.
+    affinity_keys: Option<Arc<AffinityKeyStore>>,
+    discovery: Arc<DiscoveryRegistry>,
+    watcher: Option<ConfigWatcher>,
+    config_path: PathBuf,
     server: Server,
 }
 
-fn resolve_config_path(cli: &Cli) -> PathBuf {
+pub fn resolve_config_path(cli: &Cli) -> PathBuf {
     if let Some(path) = &cli.config_entry {
         return path.clone();
     }
@@ -55,6 +88,16 @@ fn resolve_config_path(cli: &Cli) -> PathBuf {
 
 impl AppContext {
     pub async fn bootstrap(cli_args: Cli) -> miette::Result<AppContext> {
+        Self::bootstrap_with_discovery(cli_args, DiscoveryRegistry::default()).await
+    }
+
+    /// Same as [`AppContext::bootstrap`], with discovery backends registered
+    /// by the embedding binary next to the builtin ones.
+    pub async fn bootstrap_with_discovery(
+        cli_args: Cli,
+        discovery: DiscoveryRegistry,
+    ) -> miette::Result<AppContext> {
+        let discovery = Arc::new(discovery);
         let config_path = resolve_config_path(&cli_args);
 
         tracing::info!(config = ?cli_args, "CLI config parsed");
@@ -65,20 +108,50 @@ impl AppContext {
 
         // 3. Load Config File
         let config = Self::load_config(&cli_args, &config_path, &mut global_definitions).await?;
+        fingerprint::apply(config.fingerprint.as_deref());
+        flags::apply(&config.flags);
+        service_labels::apply(&config.basic_proxies);
+        maintenance::apply(&config.basic_proxies);
+        alerting::apply(&config.basic_proxies);
+        client_ip::apply(config.client_ip_privacy, config.trusted_proxies.clone());
+        geoip::apply(geoip::Databases::open(global_definitions.get_geoip())?);
 
         // 4. Compile WASM & Setup Resolver
-        let store = WasmPluginStore::compile(&global_definitions).await?;
-        store.register_into(&mut registry_map);
+        let on_failure = OnFailure::new(config.allow_partial_start);
+        let store = WasmPluginStore::compile(&global_definitions, &config.wasm, on_failure).await?;
+        store.register_into(&mut registry_map, config.threads_per_service);
 
         let registry = Arc::new(Mutex::new(registry_map));
-        let resolver = ChainResolver::new(global_definitions.clone(), registry.clone()).await?;
+        let resolver = match on_failure {
+            OnFailure::Abort => {
+                ChainResolver::new(global_definitions.clone(), registry.clone()).await?
+            }
+            // Chains of skipped plugins fail the services using them.
+            OnFailure::Skip => {
+                ChainResolver::unchecked(global_definitions.clone(), registry.clone())
+            }
+        };
+
+        let affinity_keys = match &config.state_dir {
+            Some(state_dir) => Some(Arc::new(
+                AffinityKeyStore::open(state_dir, config.affinity_keys.rotate)
+                    .await
+                    .map_err(|e| {
+                        miette::miette!("Failed to load affinity keys from {:?}: {e}", state_dir)
+                    })?,
+            )),
+            None => None,
+        };
 
         // 5. Setup Watcher
         let watcher = ConfigWatcher::new(
             config.clone(),
             global_definitions,
-            config_path,
-            UpstreamFactory::new(resolver.clone()),
+            config_path.clone(),
+            UpstreamFactory::new(resolver.clone())
+                .with_affinity_keys(affinity_keys.clone())
+                .with_discovery(discovery.clone())
+                .with_state_dir(config.state_dir.clone()),
             ConfigLoader::new(FileCollector::default()),
         );
 
@@ -89,42 +162,149 @@ impl AppContext {
         Ok(AppContext {
             config,
             resolver,
-            watcher,
+            affinity_keys,
+            discovery,
+            watcher: Some(watcher),
+            config_path,
             server,
         })
     }
 
     pub async fn build_services(&mut self) -> miette::Result<Vec<Box<dyn Service>>> {
         let mut services: Vec<Box<dyn Service>> = vec![];
+        let mut proxy_states = BTreeMap::new();
 
         tracing::info!("Configuring Basic Proxies...");
 
-        for proxy_conf in &self.config.basic_proxies {
-            tracing::info!("Configuring Basic Proxy: {}", proxy_conf.name);
+        let on_failure = OnFailure::new(self.config.allow_partial_start);
+        let started = init_all(
+            "services",
+            &self.config.basic_proxies,
+            on_failure,
+            |proxy_conf| {
+                tracing::info!(
+                    labels = ?proxy_conf.labels,
+                    "Configuring Basic Proxy: {}",
+                    proxy_conf.name
+                );
 
-            let (motya_service, shared_state) =
-                motya_proxy_service(proxy_conf.clone(), self.resolver.clone(), &self.server)
-                    .await
-                    .map_err(|e| {
-                        miette::miette!("Failed create service {}: {}", proxy_conf.name, e)
-                    })?;
+                let factory = UpstreamFactory::new(self.resolver.clone())
+                    .with_affinity_keys(self.affinity_keys.clone())
+                    .with_discovery(self.discovery.clone())
+                    .with_state_dir(self.config.state_dir.clone())
+                    .with_service(&proxy_conf.name);
+
+                let init = async move {
+                    check_tls_files(&proxy_conf.listeners)?;
+                    let host = virtual_host(proxy_conf, factory).await?;
+                    Ok::<_, miette::Report>((proxy_conf.clone(), host))
+                };
+                (proxy_conf.name.clone(), init)
+            },
+        )
+        .await?;
+
+        if started.is_empty() && !self.config.basic_proxies.is_empty() {
+            return Err(miette::miette!("None of the services could be initialized"));
+        }
 
-            self.watcher
-                .insert_proxy_state(motya_service.name().to_string(), shared_state);
-            services.push(motya_service);
+        let (proxies, hosts): (Vec<_>, Vec<_>) = started.into_iter().unzip();
+
+        for (proxy_conf, host) in proxies.iter().zip(&hosts) {
+            proxy_states.insert(proxy_conf.name.clone(), host.state.clone());
+
+            if let Some(watcher) = self.watcher.as_mut() {
+                watcher.insert_proxy_state(proxy_conf.name.clone(), host.state.clone());
+            }
+
+            if wants_pool_warm(proxy_conf) {
+                services.push(pool_warm_service(proxy_conf, host.state.clone()));
+            }
+
+            if let Some(warm_up) = &proxy_conf.warm_up {
+                tracing::info!(
+                    "Warming up {} with {} request(s)",
+                    proxy_conf.name,
+                    warm_up.requests.len()
+                );
+                services.push(warm_up_service(proxy_conf, warm_up.clone()));
+            }
         }
 
+        services.extend(shared_proxy_services(&proxies, &hosts, &self.server));
+
         for fs_conf in &self.config.file_servers {
             tracing::info!("Configuring File Server: {}", fs_conf.name);
             let service = motya_file_server(fs_conf.clone(), &self.server);
             services.push(service);
         }
 
+        if let Some(events) = &self.config.events {
+            tracing::info!("Streaming events on {}{}", events.address, events.path);
+            services.push(event_stream_service(events, &self.server));
+        }
+
+        if let Some(admin) = &self.config.admin {
+            tracing::info!("Serving admin API on {}", admin.address);
+            services.push(admin_service(admin, proxy_states, &self.server));
+        }
+
+        if let Some(store) = &self.affinity_keys {
+            services.push(affinity_key_rotation_service(store.clone()));
+        }
+
+        if let Some(alerts) = &self.config.alerts {
+            tracing::info!(
+                "Alerting enabled with {} rule(s) and {} webhook(s)",
+                alerts.rules.len(),
+                alerts.webhooks.len()
+            );
+            services.push(alerting_service(alerts.clone()));
+        }
+
+        services.push(diagnostics_service(self.config.state_dir.clone()));
+        services.push(runtime_metrics_service());
+        services.push(geoip_service());
+
+        if let Some(ConfigProvider::Http(provider)) = &self.config.provider {
+            if let Some(watcher) = self.watcher.take() {
+                tracing::info!(
+                    "Accepting config pushes on {}{}",
+                    provider.address,
+                    provider.path
+                );
+                services.push(config_push_service(
+                    provider,
+                    &self.config_path,
+                    watcher,
+                    &self.server,
+                ));
+            }
+        }
+
         Ok(services)
     }
 
-    pub fn ready(self) -> (Server, ConfigWatcher) {
-        (self.server, self.watcher)
+    /// Lets `self-test` reroute the services before they are built.
+    pub fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    /// Hands out the server and, unless the configuration is pushed over HTTP,
+    /// the watcher reloading the configuration files. It follows the files
+    /// unless `providers { files watch=#false }` is set, and only reloads on
+    /// request then.
+    pub fn ready(self) -> (Server, Option<ConfigWatcher>) {
+        let watch = !matches!(
+            self.config.provider,
+            Some(ConfigProvider::Files(FilesProviderConfig { watch: false }))
+        );
+
+        (
+            self.server,
+            self.watcher
+                .map(|watcher| watcher.with_file_watching(watch)),
+        )
     }
 
     async fn load_config(
@@ -155,16 +335,42 @@ impl AppContext {
 
                 CliConfigBuilder::build_routes(*port, routes)?
             }
-            None => {
+            Some(Commands::Filters { .. }) => {
+                return Err(miette::miette!("`filters` commands do not start a server"))
+            }
+            Some(Commands::Replay { .. }) => {
+                return Err(miette::miette!("`replay` does not start a server"))
+            }
+            Some(Commands::Validate | Commands::PrintConfig { .. } | Commands::Schema) => {
+                return Err(miette::miette!(
+                    "`validate`, `print-config` and `schema` do not start a server"
+                ))
+            }
+            None | Some(Commands::SelfTest { .. }) => {
+                let base_definitions = global_definitions.clone();
                 let loader = ConfigLoader::new(FileCollector::<TokioFs>::default());
-                loader
+                let config = loader
                     .load_entry_point(Some(config_path.into()), global_definitions)
                     .await?
                     .inspect(|_| tracing::info!("Applying config"))
                     .unwrap_or_else(|| {
                         tracing::warn!("No configuration file provided, using default");
                         Config::default()
-                    })
+                    });
+
+                // The entry point stays authoritative for the provider itself.
+                match config.provider.clone() {
+                    Some(ConfigProvider::Http(provider)) if provider.persist => {
+                        Self::load_last_good(config_path, base_definitions, global_definitions)
+                            .await?
+                            .map(|mut restored| {
+                                restored.provider = Some(ConfigProvider::Http(provider));
+                                restored
+                            })
+                            .unwrap_or(config)
+                    }
+                    _ => config,
+                }
             }
         };
 
@@ -172,6 +378,12 @@ impl AppContext {
 
         // Apply CLI overrides & Validate
         apply_cli(&mut config, cli_args);
+
+        // A self-test reports on the terminal it was started from.
+        if let Some(Commands::SelfTest { .. }) = cli_args.command {
+            config.daemonize = false;
+            config.upgrade = false;
+        }
         tracing::debug!(?config, "Full configuration");
 
         tracing::info!("Validating configuration...");
@@ -180,6 +392,29 @@ impl AppContext {
 
         Ok(config)
     }
+
+    /// Loads the config persisted by the `http` provider, if one was ever pushed.
+    async fn load_last_good(
+        config_path: &Path,
+        base_definitions: DefinitionsTable,
+        global_definitions: &mut DefinitionsTable,
+    ) -> miette::Result<Option<Config>> {
+        let path = last_good_path(config_path);
+
+        let Ok(source) = tokio::fs::read_to_string(&path).await else {
+            return Ok(None);
+        };
+
+        tracing::info!("Restoring last pushed config from {:?}", path);
+
+        let mut definitions = base_definitions;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let config = load_document(&source, &name, &mut definitions)?;
+
+        *global_definitions = definitions;
+
+        Ok(Some(config))
+    }
 }
 
 fn apply_cli(conf: &mut Config, cli: &Cli) {
@@ -191,12 +426,14 @@ fn apply_cli(conf: &mut Config, cli: &Cli) {
         upgrade,
         pidfile,
         upgrade_socket,
+        allow_partial_start,
         command: _,
     } = cli;
 
     conf.validate_configs |= validate_configs;
     conf.daemonize |= daemonize;
     conf.upgrade |= upgrade;
+    conf.allow_partial_start |= allow_partial_start;
 
     if let Some(pidfile) = pidfile {
         if let Some(current_pidfile) = conf.pid_file.as_ref() {
@@ -264,6 +501,8 @@ pub fn pingora_server_conf(config: &Config) -> PingoraServerConf {
         threads: config.threads_per_service,
         work_stealing: true,
         ca_file: None,
+        grace_period_seconds: config.shutdown.grace_period.map(|d| d.as_secs()),
+        graceful_shutdown_timeout_seconds: config.shutdown.timeout.map(|d| d.as_secs()),
         ..PingoraServerConf::default()
     }
 }
@@ -0,0 +1,213 @@
+//! Client addresses: the one behind the `system.trusted-proxies`, for every
+//! filter and balancing key, and anonymized by `system.client-ip-privacy` for
+//! what keeps or forwards them. Access controls such as `block-cidr-range`
+//! see the address before it is anonymized.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, OnceLock},
+};
+
+use arc_swap::ArcSwapOption;
+use hmac::{Hmac, Mac};
+use pingora_http::RequestHeader;
+use pingora_proxy::Session;
+use sha2::Sha256;
+
+use motya_config::common_types::system_data::{ClientIpPrivacy, TrustedProxies};
+
+static CURRENT: ArcSwapOption<ClientIpPrivacy> = ArcSwapOption::const_empty();
+
+static TRUSTED: ArcSwapOption<TrustedProxies> = ArcSwapOption::const_empty();
+
+/// Sets the privacy mode and trusted proxies of a configuration that was just
+/// loaded or reloaded.
+pub fn apply(privacy: Option<ClientIpPrivacy>, trusted: Option<TrustedProxies>) {
+    CURRENT.store(privacy.map(Arc::new));
+    TRUSTED.store(trusted.map(Arc::new));
+}
+
+/// The address of the client of `session`, `None` on Unix sockets.
+pub fn of(session: &Session) -> Option<IpAddr> {
+    let peer = session.client_addr()?.as_inet()?.ip();
+    Some(resolve(peer, session.req_header()))
+}
+
+/// The address of the client when `peer` sent `req`: `peer` itself, or the
+/// one the forwarded header names when `peer` is a trusted proxy.
+pub fn resolve(peer: IpAddr, req: &RequestHeader) -> IpAddr {
+    match TRUSTED.load().as_deref() {
+        None => peer,
+        Some(trusted) => resolve_with(trusted, peer, req),
+    }
+}
+
+/// Walks the forwarded header from the nearest hop back, to the first one
+/// that is not a trusted proxy. Entries before it were written by the client
+/// and cannot be believed.
+fn resolve_with(trusted: &TrustedProxies, peer: IpAddr, req: &RequestHeader) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.ranges.iter().any(|range| range.contains(ip));
+
+    let hops = req
+        .headers
+        .get_all(trusted.header.as_str())
+        .iter()
+        .rev()
+        .flat_map(|value| value.to_str().unwrap_or_default().rsplit(','));
+
+    let mut client = peer;
+    for hop in hops {
+        if !is_trusted(&client) {
+            break;
+        }
+        // A hop that is not an address ends the walk at the proxy that
+        // reported it.
+        let Some(ip) = parse_hop(hop.trim()) else {
+            break;
+        };
+        client = ip;
+    }
+    client
+}
+
+/// `192.0.2.1`, `2001:db8::1`, and either with a port.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+/// The address as the privacy mode in effect lets it be kept.
+pub fn anonymize(ip: IpAddr) -> IpAddr {
+    match CURRENT.load().as_deref() {
+        None => ip,
+        Some(privacy) => anonymize_with(*privacy, ip),
+    }
+}
+
+fn anonymize_with(privacy: ClientIpPrivacy, ip: IpAddr) -> IpAddr {
+    match (privacy, ip) {
+        (ClientIpPrivacy::Truncate { ipv4_prefix, .. }, IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - ipv4_prefix as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+        }
+        (ClientIpPrivacy::Truncate { ipv6_prefix, .. }, IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - ipv6_prefix as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+        }
+        (ClientIpPrivacy::Hash, ip) => hashed(ip),
+    }
+}
+
+/// Key of the hashes, never stored so they cannot be reversed by trying
+/// every address once the process is gone.
+fn key() -> &'static [u8; 32] {
+    static KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+    KEY.get_or_init(|| {
+        let mut key = [0; 32];
+        getrandom::fill(&mut key).expect("system randomness should be available");
+        key
+    })
+}
+
+/// A keyed hash of the address in an address of the same family, so every
+/// client keeps its own balancing key.
+fn hashed(ip: IpAddr) -> IpAddr {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key()).expect("HMAC accepts any key size");
+    mac.update(ip.to_string().as_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    match ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(digest[0], digest[1], digest[2], digest[3])),
+        IpAddr::V6(_) => {
+            let bytes: [u8; 16] = digest[..16].try_into().expect("digest has 32 bytes");
+            IpAddr::V6(Ipv6Addr::from(bytes))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        let truncate = ClientIpPrivacy::Truncate {
+            ipv4_prefix: 24,
+            ipv6_prefix: 48,
+        };
+
+        assert_eq!(
+            anonymize_with(truncate, "203.0.113.77".parse().unwrap()),
+            "203.0.113.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            anonymize_with(truncate, "2001:db8:abcd:12::1".parse().unwrap()),
+            "2001:db8:abcd::".parse::<IpAddr>().unwrap()
+        );
+
+        let everything = ClientIpPrivacy::Truncate {
+            ipv4_prefix: 0,
+            ipv6_prefix: 128,
+        };
+        assert_eq!(
+            anonymize_with(everything, "203.0.113.77".parse().unwrap()),
+            "0.0.0.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            anonymize_with(everything, "2001:db8::1".parse().unwrap()),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hash() {
+        let client: IpAddr = "203.0.113.77".parse().unwrap();
+        let hashed = anonymize_with(ClientIpPrivacy::Hash, client);
+
+        assert!(hashed.is_ipv4());
+        assert_ne!(hashed, client);
+        assert_eq!(anonymize_with(ClientIpPrivacy::Hash, client), hashed);
+        assert_ne!(
+            anonymize_with(ClientIpPrivacy::Hash, "203.0.113.78".parse().unwrap()),
+            hashed
+        );
+        assert!(anonymize_with(ClientIpPrivacy::Hash, "2001:db8::1".parse().unwrap()).is_ipv6());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let trusted = TrustedProxies {
+            ranges: vec!["10.0.0.0/8".parse().unwrap()],
+            header: "x-forwarded-for".to_string(),
+        };
+        let resolve = |peer: &str, forwarded: &[&str]| {
+            let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+            for value in forwarded {
+                req.append_header("x-forwarded-for", *value).unwrap();
+            }
+            resolve_with(&trusted, peer.parse().unwrap(), &req).to_string()
+        };
+
+        // Only trusted peers are believed.
+        assert_eq!(resolve("198.51.100.7", &["203.0.113.1"]), "198.51.100.7");
+        assert_eq!(resolve("10.0.0.2", &[]), "10.0.0.2");
+
+        assert_eq!(resolve("10.0.0.2", &["203.0.113.1"]), "203.0.113.1");
+        assert_eq!(
+            resolve("10.0.0.2", &["192.0.2.9, 203.0.113.1, 10.0.0.1"]),
+            "203.0.113.1"
+        );
+        assert_eq!(
+            resolve("10.0.0.2", &["192.0.2.9", "203.0.113.1:5000", "10.0.0.1"]),
+            "203.0.113.1"
+        );
+        assert_eq!(resolve("10.0.0.2", &["[2001:db8::1]:443"]), "2001:db8::1");
+
+        // Every hop trusted, the first one is the client.
+        assert_eq!(resolve("10.0.0.2", &["10.0.0.3, 10.0.0.1"]), "10.0.0.3");
+        // Garbage stops at the proxy that reported it.
+        assert_eq!(resolve("10.0.0.2", &["203.0.113.1, unknown"]), "10.0.0.2");
+    }
+}
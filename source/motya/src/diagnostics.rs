@@ -0,0 +1,125 @@
+//! Diagnostic snapshots written on `SIGUSR2`, for looking back at an incident
+//! after the fact.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use pingora::{
+    server::ShutdownWatch,
+    services::background::{background_service, BackgroundService},
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{fingerprint, metrics};
+
+/// The state of the process as text: when and what it is running, then every
+/// metric, including in-flight requests per listener and backend.
+pub fn snapshot(now: SystemTime) -> String {
+    let config = fingerprint::current();
+    let mut dump = String::new();
+
+    let _ = writeln!(dump, "# Motya diagnostic snapshot");
+    let _ = writeln!(dump, "# time: {}", unix_secs(now));
+    let _ = writeln!(dump, "# pid: {}", process::id());
+    let _ = writeln!(dump, "# version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(
+        dump,
+        "# config fingerprint: {}",
+        config.as_deref().map_or("none", String::as_str)
+    );
+    dump.push('\n');
+    dump.push_str(&metrics::render());
+
+    dump
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Where the snapshot taken at `now` goes in `dir`.
+fn snapshot_path(dir: &Path, now: SystemTime) -> PathBuf {
+    dir.join(format!("diagnostics-{}.txt", unix_secs(now)))
+}
+
+/// Background task writing a [snapshot] to the state directory, or to the
+/// log without one, whenever the process gets `SIGUSR2`.
+struct DiagnosticDump {
+    state_dir: Option<PathBuf>,
+}
+
+impl DiagnosticDump {
+    async fn dump(&self) {
+        let now = SystemTime::now();
+        let dump = snapshot(now);
+
+        let Some(dir) = &self.state_dir else {
+            tracing::info!("Diagnostic snapshot:\n{dump}");
+            return;
+        };
+
+        let path = snapshot_path(dir, now);
+        match tokio::fs::write(&path, dump).await {
+            Ok(()) => tracing::info!("Diagnostic snapshot written to {:?}", path),
+            Err(err) => tracing::error!("Failed to write diagnostic snapshot to {:?}: {err}", path),
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for DiagnosticDump {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut signals = match signal(SignalKind::user_defined2()) {
+            Ok(signals) => signals,
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGUSR2, diagnostic dumps are off: {err}");
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                received = signals.recv() => match received {
+                    Some(()) => self.dump().await,
+                    None => return,
+                },
+            }
+        }
+    }
+}
+
+pub fn diagnostics_service(state_dir: Option<PathBuf>) -> Box<dyn pingora::services::Service> {
+    Box::new(background_service(
+        "motya-diagnostics",
+        DiagnosticDump { state_dir },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let dump = snapshot(now);
+        assert!(dump.starts_with("# Motya diagnostic snapshot\n# time: 1700000000\n"));
+        assert!(dump.contains(&format!("# pid: {}", process::id())));
+
+        assert_eq!(
+            snapshot_path(Path::new("/var/lib/motya"), now),
+            Path::new("/var/lib/motya/diagnostics-1700000000.txt")
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+pub mod sse;
+
+/// How many events a slow subscriber may fall behind before it starts losing them.
+const CAPACITY: usize = 1024;
+
+/// Structured runtime event published to operators.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    /// A new configuration was applied. `services` lists the proxies whose routing changed.
+    ConfigReloaded { services: Vec<String> },
+    /// A configuration was rejected and the previous one is still active.
+    ConfigRejected { reason: String },
+    BackendHealthChanged {
+        service: String,
+        backend: String,
+        healthy: bool,
+    },
+    CertRenewed {
+        domain: String,
+        /// Expiry of the new certificate, seconds since the Unix epoch.
+        expires_at: u64,
+    },
+}
+
+impl Event {
+    /// Name used for the SSE `event:` field, same as the serialized `type`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::ConfigReloaded { .. } => "config-reloaded",
+            Event::ConfigRejected { .. } => "config-rejected",
+            Event::BackendHealthChanged { .. } => "backend-health-changed",
+            Event::CertRenewed { .. } => "cert-renewed",
+        }
+    }
+}
+
+/// An [`Event`] stamped with the time it was emitted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EventRecord {
+    /// Milliseconds since the Unix epoch.
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl EventRecord {
+    pub fn new(event: Event) -> Self {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        Self { at, event }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("events always serialize")
+    }
+}
+
+fn bus() -> &'static broadcast::Sender<EventRecord> {
+    static BUS: OnceLock<broadcast::Sender<EventRecord>> = OnceLock::new();
+    BUS.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+/// Publishes `event` to every current subscriber. Events emitted while nobody
+/// listens are dropped.
+pub fn emit(event: Event) {
+    tracing::debug!(kind = event.kind(), "Emitting event");
+    let _ = bus().send(EventRecord::new(event));
+}
+
+pub fn subscribe() -> broadcast::Receiver<EventRecord> {
+    bus().subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_json() {
+        let record = EventRecord {
+            at: 42,
+            event: Event::BackendHealthChanged {
+                service: "api".into(),
+                backend: "10.0.0.1:80".into(),
+                healthy: false,
+            },
+        };
+
+        assert_eq!(
+            record.to_json(),
+            r#"{"at":42,"type":"backend-health-changed","service":"api","backend":"10.0.0.1:80","healthy":false}"#
+        );
+        assert_eq!(record.event.kind(), "backend-health-changed");
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_events() {
+        let mut rx = subscribe();
+
+        emit(Event::CertRenewed {
+            domain: "example.com".into(),
+            expires_at: 0,
+        });
+
+        loop {
+            let record = rx.recv().await.unwrap();
+            if let Event::CertRenewed { domain, .. } = record.event {
+                assert_eq!(domain, "example.com");
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use pingora::{server::Server, upstreams::peer::HttpPeer, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::{ProxyHttp, Session};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::{subscribe, EventRecord};
+use motya_config::common_types::system_data::EventsConfig;
+
+/// Idle streams get a comment line this often, so proxies and clients keep them open.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Admin listener streaming [`Event`](crate::events::Event)s as Server-Sent Events.
+pub struct EventStreamService {
+    path: String,
+}
+
+pub fn event_stream_service(
+    config: &EventsConfig,
+    server: &Server,
+) -> Box<dyn pingora::services::Service> {
+    let events = EventStreamService {
+        path: config.path.path().to_string(),
+    };
+
+    let mut service =
+        pingora_proxy::http_proxy_service_with_name(&server.configuration, events, "motya-events");
+
+    service.add_tcp(&config.address.to_string());
+
+    Box::new(service)
+}
+
+/// Encodes a record as one SSE message.
+pub fn sse_message(record: &EventRecord) -> String {
+    format!(
+        "event: {}\ndata: {}\n\n",
+        record.event.kind(),
+        record.to_json()
+    )
+}
+
+impl EventStreamService {
+    async fn reject(session: &mut Session, status: StatusCode) -> Result<bool> {
+        session
+            .downstream_session
+            .respond_error(status.as_u16())
+            .await?;
+        Ok(true)
+    }
+
+    async fn stream(session: &mut Session) -> Result<bool> {
+        // Subscribe before answering, so nothing emitted after the headers is missed.
+        let mut events = subscribe();
+
+        let mut response = ResponseHeader::build(StatusCode::OK, Some(4))?;
+        response.insert_header("Content-Type", "text/event-stream")?;
+        response.insert_header("Cache-Control", "no-cache")?;
+        response.insert_header("Transfer-Encoding", "chunked")?;
+
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+
+        loop {
+            let chunk = match tokio::time::timeout(KEEPALIVE_INTERVAL, events.recv()).await {
+                Ok(Ok(record)) => sse_message(&record),
+                Ok(Err(RecvError::Lagged(missed))) => {
+                    tracing::warn!("Event stream subscriber lagged, {missed} events dropped");
+                    format!(": {missed} events dropped\n\n")
+                }
+                Ok(Err(RecvError::Closed)) => break,
+                Err(_) => ": keepalive\n\n".to_string(),
+            };
+
+            // A failed write means the client went away.
+            if session
+                .downstream_session
+                .write_response_body(Bytes::from(chunk), false)
+                .await
+                .is_err()
+            {
+                return Ok(true);
+            }
+        }
+
+        session
+            .downstream_session
+            .write_response_body(Bytes::new(), true)
+            .await?;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl ProxyHttp for EventStreamService {
+    type CTX = ();
+
+    fn new_ctx(&self) -> Self::CTX {}
+
+    async fn upstream_peer(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Self::CTX,
+    ) -> Result<Box<HttpPeer>> {
+        // Every request is answered in `request_filter`.
+        Err(pingora::Error::new_str("Request Failed"))
+    }
+
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut Self::CTX) -> Result<bool> {
+        let req = session.req_header();
+
+        if req.uri.path() != self.path {
+            return Self::reject(session, StatusCode::NOT_FOUND).await;
+        }
+
+        if req.method != Method::GET {
+            return Self::reject(session, StatusCode::METHOD_NOT_ALLOWED).await;
+        }
+
+        Self::stream(session).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+
+    #[test]
+    fn test_sse_message() {
+        let record = EventRecord {
+            at: 1,
+            event: Event::ConfigRejected {
+                reason: "bad".into(),
+            },
+        };
+
+        assert_eq!(
+            sse_message(&record),
+            "event: config-rejected\ndata: {\"at\":1,\"type\":\"config-rejected\",\"reason\":\"bad\"}\n\n"
+        );
+    }
+}
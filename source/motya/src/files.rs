@@ -9,7 +9,7 @@ use pingora::{server::Server, upstreams::peer::HttpPeer, Result};
 use pingora_proxy::{ProxyHttp, Session};
 use static_files_module::{StaticFilesConf, StaticFilesHandler};
 
-use crate::proxy::populate_listeners::populate_listners;
+use crate::proxy::{acme, populate_listeners::populate_listners};
 
 pub fn motya_file_server(
     conf: FileServerConfig,
@@ -93,6 +93,10 @@ impl ProxyHttp for FileServer {
     }
 
     async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        if acme::challenges::answer(session).await? {
+            return Ok(true);
+        }
+
         let mut wrap = SesWrap {
             extensions: ctx,
             session,
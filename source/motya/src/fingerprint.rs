@@ -0,0 +1,42 @@
+//! Fingerprint of the configuration in effect, so every instance of a fleet
+//! can be checked for the intended version.
+
+use std::sync::{Arc, OnceLock};
+
+use arc_swap::ArcSwapOption;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+
+static CURRENT: ArcSwapOption<String> = ArcSwapOption::const_empty();
+
+fn info() -> &'static IntGaugeVec {
+    static INFO: OnceLock<IntGaugeVec> = OnceLock::new();
+
+    INFO.get_or_init(|| {
+        register_int_gauge_vec!(
+            "motya_config_info",
+            "Always 1, labelled with the fingerprint of the configuration in effect",
+            &["fingerprint"]
+        )
+        .expect("metric should register once")
+    })
+}
+
+/// Records the fingerprint of a configuration that was just loaded or
+/// reloaded. Configurations built from CLI flags have none.
+pub fn apply(fingerprint: Option<&str>) {
+    let Some(fingerprint) = fingerprint else {
+        return;
+    };
+
+    tracing::info!("Configuration fingerprint: {fingerprint}");
+
+    let info = info();
+    info.reset();
+    info.with_label_values(&[fingerprint]).set(1);
+
+    CURRENT.store(Some(Arc::new(fingerprint.to_string())));
+}
+
+pub fn current() -> Option<Arc<String>> {
+    CURRENT.load_full()
+}
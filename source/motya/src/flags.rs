@@ -0,0 +1,60 @@
+//! Feature flags of the `flags` section. Routes gated on a flag and filters
+//! read them per request, the admin API can toggle them without a reload.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use arc_swap::ArcSwapOption;
+
+static CURRENT: ArcSwapOption<Flags> = ArcSwapOption::const_empty();
+
+/// The state of every declared flag at one point in time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Flags(BTreeMap<String, bool>);
+
+impl Flags {
+    pub fn new(flags: BTreeMap<String, bool>) -> Self {
+        Self(flags)
+    }
+
+    /// Whether `name` is on, undeclared flags are off.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.0.get(name).copied().unwrap_or(false)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.0
+            .iter()
+            .map(|(name, enabled)| (name.as_str(), *enabled))
+    }
+}
+
+/// Sets the flags of a configuration that was just loaded or reloaded,
+/// dropping whatever the admin API changed before.
+pub fn apply(flags: &BTreeMap<String, bool>) {
+    CURRENT.store(Some(Arc::new(Flags::new(flags.clone()))));
+}
+
+/// The flags in effect, a request keeps the snapshot it started with.
+pub fn snapshot() -> Arc<Flags> {
+    CURRENT.load_full().unwrap_or_default()
+}
+
+/// Turns a declared flag on or off until the next reload.
+pub fn set(name: &str, enabled: bool) -> Result<(), String> {
+    if !snapshot().0.contains_key(name) {
+        return Err(format!("Unknown flag '{name}'"));
+    }
+
+    CURRENT.rcu(|current| {
+        let mut flags = current.as_deref().cloned().unwrap_or_default();
+        // A reload in between may have dropped the flag.
+        if let Some(flag) = flags.0.get_mut(name) {
+            *flag = enabled;
+        }
+        Some(Arc::new(flags))
+    });
+
+    tracing::info!("Flag '{name}' set to {enabled}");
+
+    Ok(())
+}
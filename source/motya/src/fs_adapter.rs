@@ -1,9 +1,15 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use miette::{IntoDiagnostic, Result};
 use motya_config::kdl::fs_loader::AsyncFs;
 use tokio::fs;
 
+/// Upper bound for fetching a URL include, the cached copy is used past it.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Clone, Default)]
 pub struct TokioFs;
 
@@ -15,4 +21,26 @@ impl AsyncFs for TokioFs {
     async fn read_to_string(path: &Path) -> Result<String> {
         fs::read_to_string(path).await.into_diagnostic()
     }
+
+    async fn write(path: &Path, contents: &str) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await.into_diagnostic()?;
+        }
+        fs::write(path, contents).await.into_diagnostic()
+    }
+
+    async fn fetch(url: &str) -> Result<String> {
+        reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .into_diagnostic()?
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .into_diagnostic()?
+            .text()
+            .await
+            .into_diagnostic()
+    }
 }
@@ -0,0 +1,215 @@
+//! MaxMind databases of `definitions.geoip`, looked up by the geoip filters.
+//! A database replaced on disk, e.g. by `geoipupdate`, is reopened without
+//! reloading the configuration.
+
+use std::{
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwapOption;
+use async_trait::async_trait;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use pingora::{
+    server::ShutdownWatch,
+    services::background::{background_service, BackgroundService},
+};
+
+use motya_config::common_types::definitions::GeoIpDatabases;
+
+/// How often the database files are checked for changes.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+static CURRENT: ArcSwapOption<Databases> = ArcSwapOption::const_empty();
+
+/// What the databases know of an address.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Location {
+    /// ISO 3166-1 alpha-2 code, e.g. `DE`.
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+}
+
+struct Database {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    reader: Reader<Vec<u8>>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Self, MaxMindDBError> {
+        let modified = modified(path);
+        let reader = Reader::open_readfile(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            modified,
+            reader,
+        })
+    }
+
+    /// The database reopened when its file changed, the same one when it did
+    /// not or the new file cannot be read yet.
+    fn refreshed(self: &Arc<Self>) -> Arc<Self> {
+        if modified(&self.path) == self.modified {
+            return self.clone();
+        }
+
+        match Self::open(&self.path) {
+            Ok(database) => {
+                tracing::info!(path = ?self.path, "GeoIP database reopened");
+                Arc::new(database)
+            }
+            Err(e) => {
+                tracing::warn!(path = ?self.path, "Keeping the previous GeoIP database: {e}");
+                self.clone()
+            }
+        }
+    }
+}
+
+/// The databases of a configuration, opened before it is applied.
+pub struct Databases {
+    country: Option<Arc<Database>>,
+    asn: Option<Arc<Database>>,
+}
+
+impl Databases {
+    /// Opens the databases of `definitions.geoip`, `None` without it.
+    pub fn open(config: Option<&GeoIpDatabases>) -> miette::Result<Option<Self>> {
+        let Some(config) = config else {
+            return Ok(None);
+        };
+
+        let open = |path: &Option<PathBuf>| {
+            path.as_deref()
+                .map(|path| {
+                    Database::open(path)
+                        .map(Arc::new)
+                        .map_err(|e| miette::miette!("Failed to open GeoIP database {path:?}: {e}"))
+                })
+                .transpose()
+        };
+
+        Ok(Some(Self {
+            country: open(&config.country)?,
+            asn: open(&config.asn)?,
+        }))
+    }
+
+    fn locate(&self, ip: IpAddr) -> Location {
+        let country = self
+            .country
+            .as_ref()
+            .and_then(|db| db.reader.lookup::<geoip2::Country>(ip).ok())
+            .and_then(|record| record.country?.iso_code.map(str::to_string));
+
+        let asn = self
+            .asn
+            .as_ref()
+            .and_then(|db| db.reader.lookup::<geoip2::Asn>(ip).ok())
+            .and_then(|record| record.autonomous_system_number);
+
+        Location { country, asn }
+    }
+}
+
+/// Sets the databases of a configuration that was just loaded or reloaded.
+pub fn apply(databases: Option<Databases>) {
+    CURRENT.store(databases.map(Arc::new));
+}
+
+/// What the databases in effect know of `ip`, nothing without
+/// `definitions.geoip`.
+pub fn locate(ip: IpAddr) -> Location {
+    CURRENT
+        .load()
+        .as_deref()
+        .map(|databases| databases.locate(ip))
+        .unwrap_or_default()
+}
+
+fn refresh() {
+    let current = CURRENT.load_full();
+    let Some(databases) = &current else {
+        return;
+    };
+
+    let country = databases.country.as_ref().map(Database::refreshed);
+    let asn = databases.asn.as_ref().map(Database::refreshed);
+
+    let same = |old: &Option<Arc<Database>>, new: &Option<Arc<Database>>| match (old, new) {
+        (Some(old), Some(new)) => Arc::ptr_eq(old, new),
+        _ => true,
+    };
+    if same(&databases.country, &country) && same(&databases.asn, &asn) {
+        return;
+    }
+
+    // Databases of a configuration applied in the meantime are kept.
+    CURRENT.compare_and_swap(&current, Some(Arc::new(Databases { country, asn })));
+}
+
+struct GeoIpRefresh;
+
+#[async_trait]
+impl BackgroundService for GeoIpRefresh {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut ticker = tokio::time::interval(REFRESH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = ticker.tick() => {
+                    if let Err(e) = tokio::task::spawn_blocking(refresh).await {
+                        tracing::error!("GeoIP refresh failed: {e}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn geoip_service() -> Box<dyn pingora::services::Service> {
+    Box::new(background_service("motya-geoip", GeoIpRefresh))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        assert!(Databases::open(None).unwrap().is_none());
+
+        let empty = Databases::open(Some(&GeoIpDatabases::default()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            empty.locate("203.0.113.77".parse().unwrap()),
+            Location::default()
+        );
+
+        let missing = GeoIpDatabases {
+            country: Some("/nonexistent/GeoLite2-Country.mmdb".into()),
+            asn: None,
+        };
+        assert!(Databases::open(Some(&missing)).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let corrupt = dir.path().join("GeoLite2-ASN.mmdb");
+        fs::write(&corrupt, b"not a database").unwrap();
+        let corrupt = GeoIpDatabases {
+            country: None,
+            asn: Some(corrupt),
+        };
+        assert!(Databases::open(Some(&corrupt)).is_err());
+    }
+}
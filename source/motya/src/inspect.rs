@@ -0,0 +1,56 @@
+//! `motya validate` and `motya print-config`: the configuration loaded as it
+//! would be at startup, without starting anything.
+
+use std::path::Path;
+
+use motya_config::{
+    cli::cli_struct::ConfigFormat,
+    common_types::definitions_table::DefinitionsTable,
+    kdl::{compiler::ConfigCompiler, fs_loader::FileCollector, inspect},
+};
+
+use crate::{fs_adapter::TokioFs, proxy::filters::generate_registry::load_registry};
+
+/// The builtin filters, which chains are checked against.
+fn definitions() -> DefinitionsTable {
+    let mut definitions = DefinitionsTable::default();
+    load_registry(&mut definitions);
+    definitions
+}
+
+/// Runs both passes of the loader over `config_path` and its includes. Errors
+/// are returned with their diagnostics, lints are logged as at startup.
+pub async fn validate(config_path: &Path) -> miette::Result<()> {
+    let documents = FileCollector::<TokioFs>::default()
+        .collect(config_path.into())
+        .await?;
+    let files = documents.len();
+
+    let config = ConfigCompiler::new(documents).compile(&mut definitions())?;
+
+    println!(
+        "{}: valid, {files} file(s), {} proxy service(s), {} file server(s), fingerprint {}",
+        config_path.display(),
+        config.basic_proxies.len(),
+        config.file_servers.len(),
+        config.fingerprint.unwrap_or_default(),
+    );
+    Ok(())
+}
+
+/// Prints `config_path` with its includes merged into one document, once it
+/// is known to load.
+pub async fn print_config(config_path: &Path, format: ConfigFormat) -> miette::Result<()> {
+    let documents = FileCollector::<TokioFs>::default()
+        .collect(config_path.into())
+        .await?;
+
+    ConfigCompiler::new(documents.clone()).compile(&mut definitions())?;
+
+    let merged = inspect::merge(&documents);
+    match format {
+        ConfigFormat::Kdl => println!("{}", merged.to_string().trim_end()),
+        ConfigFormat::Json => print!("{}", inspect::to_json(&merged)),
+    }
+    Ok(())
+}
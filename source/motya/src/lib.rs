@@ -1,5 +1,20 @@
+pub mod admin;
+pub mod alerting;
 pub mod app_context;
+pub mod client_ip;
 pub mod config_aggregator;
+pub mod diagnostics;
+pub mod events;
 pub mod files;
+pub mod fingerprint;
+pub mod flags;
 pub mod fs_adapter;
+pub mod geoip;
+pub mod inspect;
+pub mod maintenance;
+pub mod metrics;
 pub mod proxy;
+pub mod runtime_metrics;
+pub mod schema;
+pub mod service_labels;
+pub mod startup;
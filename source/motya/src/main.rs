@@ -1,38 +1,120 @@
+mod admin;
+mod alerting;
 mod app_context;
+mod client_ip;
+mod diagnostics;
+mod events;
 mod files;
+mod fingerprint;
+mod flags;
 pub mod fs_adapter;
+mod geoip;
+mod inspect;
+mod maintenance;
+mod metrics;
 mod proxy;
+mod replay;
+mod runtime_metrics;
+mod schema;
+mod self_test;
+mod service_labels;
+mod startup;
 
-use std::process;
+use std::{process, time::Duration};
 
 use clap::{CommandFactory, FromArgMatches};
-use motya_config::cli::cli_struct::{Cli, BANNER};
+use motya_config::{
+    cli::cli_struct::{Cli, Commands, FiltersCommand, BANNER},
+    common_types::definitions_table::DefinitionsTable,
+};
 use tokio::runtime::Runtime;
 
-use crate::app_context::AppContext;
+use crate::{
+    app_context::{resolve_config_path, AppContext},
+    proxy::filters::generate_registry::load_registry,
+};
 
 fn main() -> miette::Result<()> {
-    tracing_subscriber::fmt().with_thread_ids(true).init();
-
-    let rt = Runtime::new().expect("Failed to build Tokio runtime");
-
     let command = Cli::command()
         .before_help(BANNER.replace("__p__", env!("CARGO_PKG_VERSION")))
         .get_matches();
     let cli_args = Cli::from_arg_matches(&command).expect("Failed to parse args");
 
+    let logs = tracing_subscriber::fmt().with_thread_ids(true);
+    // `print-config` keeps stdout for the configuration.
+    if let Some(Commands::PrintConfig { .. }) = cli_args.command {
+        logs.with_writer(std::io::stderr).init();
+    } else {
+        logs.init();
+    }
+
+    let rt = Runtime::new().expect("Failed to build Tokio runtime");
+
+    if let Some(Commands::Filters {
+        command: FiltersCommand::List,
+    }) = &cli_args.command
+    {
+        let registry = load_registry(&mut DefinitionsTable::default());
+        print!("{}", registry.listing());
+        return Ok(());
+    }
+
+    if let Some(Commands::SelfTest {
+        pattern,
+        duration_secs,
+        concurrency,
+        path,
+    }) = cli_args.command.clone()
+    {
+        let plan = self_test::Plan {
+            pattern,
+            duration: Duration::from_secs(duration_secs.max(1)),
+            concurrency: concurrency.max(1),
+            path,
+        };
+        return rt.block_on(self_test::run(cli_args, plan));
+    }
+
+    if let Some(Commands::Replay {
+        har,
+        target,
+        max_rate,
+    }) = cli_args.command.clone()
+    {
+        return rt.block_on(replay::run(&har, &target, max_rate));
+    }
+
+    if let Some(Commands::Validate) = cli_args.command {
+        return rt.block_on(inspect::validate(&resolve_config_path(&cli_args)));
+    }
+
+    if let Some(Commands::PrintConfig { format }) = cli_args.command {
+        let config_path = resolve_config_path(&cli_args);
+        return rt.block_on(inspect::print_config(&config_path, format));
+    }
+
+    if let Some(Commands::Schema) = cli_args.command {
+        print!("{}", schema::render());
+        return Ok(());
+    }
+
     let mut ctx = rt.block_on(AppContext::bootstrap(cli_args))?;
 
     let services = rt.block_on(ctx.build_services())?;
 
     tracing::info!("Server running (PID: {})", process::id());
 
-    let (mut server, mut watcher) = ctx.ready();
+    let (mut server, watcher) = ctx.ready();
 
     server.bootstrap();
     server.add_services(services);
 
-    rt.spawn(async move { watcher.watch().await });
+    match watcher {
+        Some(mut watcher) => {
+            rt.spawn(async move { watcher.watch().await });
+        }
+        None => tracing::info!("Config files are not reloaded, they are pushed over HTTP"),
+    }
 
     tracing::info!("Starting Pingora Server...");
 
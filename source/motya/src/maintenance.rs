@@ -0,0 +1,163 @@
+//! Maintenance mode of the proxy services. A service in maintenance answers
+//! with its `maintenance` page instead of contacting its upstreams, the admin
+//! API switches it on and off without a reload.
+
+use std::{collections::BTreeMap, fs, sync::Arc};
+
+use arc_swap::ArcSwapOption;
+use bytes::Bytes;
+use miette::miette;
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use motya_config::{common_types::maintenance::MaintenanceConfig, internal::ProxyConfig};
+
+static CURRENT: ArcSwapOption<BTreeMap<String, bool>> = ArcSwapOption::const_empty();
+
+/// Sets the state of a configuration that was just loaded or reloaded,
+/// dropping whatever the admin API changed before.
+pub fn apply(proxies: &[ProxyConfig]) {
+    let services = proxies
+        .iter()
+        .map(|proxy| (proxy.name.clone(), proxy.maintenance.enabled))
+        .collect();
+
+    CURRENT.store(Some(Arc::new(services)));
+}
+
+/// The maintenance state of every proxy service.
+pub fn snapshot() -> Arc<BTreeMap<String, bool>> {
+    CURRENT.load_full().unwrap_or_default()
+}
+
+/// Whether `service` is in maintenance, unknown services are not.
+pub fn is_on(service: &str) -> bool {
+    snapshot().get(service).copied().unwrap_or(false)
+}
+
+/// Puts a service in or out of maintenance until the next reload.
+pub fn set(service: &str, enabled: bool) -> Result<(), String> {
+    if !snapshot().contains_key(service) {
+        return Err(format!("Unknown service '{service}'"));
+    }
+
+    CURRENT.rcu(|current| {
+        let mut services = current.as_deref().cloned().unwrap_or_default();
+        // A reload in between may have dropped the service.
+        if let Some(state) = services.get_mut(service) {
+            *state = enabled;
+        }
+        Some(Arc::new(services))
+    });
+
+    tracing::warn!("Maintenance of '{service}' set to {enabled}");
+
+    Ok(())
+}
+
+/// The answer of a service in maintenance, its page read at startup.
+#[derive(Debug)]
+pub struct MaintenancePage {
+    service: String,
+    header: ResponseHeader,
+    body: Bytes,
+}
+
+impl MaintenancePage {
+    pub fn load(service: &str, config: &MaintenanceConfig) -> miette::Result<Self> {
+        let (content_type, body) = match &config.page {
+            Some(path) => {
+                let page = fs::read(path).map_err(|err| {
+                    miette!(
+                        "Failed to read maintenance page '{}': {err}",
+                        path.display()
+                    )
+                })?;
+                ("text/html; charset=utf-8", Bytes::from(page))
+            }
+            None => (
+                "text/plain",
+                Bytes::from_static(b"The service is down for maintenance\n"),
+            ),
+        };
+
+        let header = || -> pingora::Result<ResponseHeader> {
+            let mut header = ResponseHeader::build(config.status, Some(4))?;
+            header.insert_header("Content-Type", content_type)?;
+            header.insert_header("Content-Length", body.len().to_string())?;
+            header.insert_header("Cache-Control", "no-store")?;
+
+            if let Some(retry_after) = config.retry_after {
+                header.insert_header("Retry-After", retry_after.as_secs().to_string())?;
+            }
+            Ok(header)
+        };
+
+        Ok(Self {
+            service: service.to_string(),
+            header: header().map_err(|err| miette!("{err}"))?,
+            body,
+        })
+    }
+
+    pub fn is_on(&self) -> bool {
+        is_on(&self.service)
+    }
+
+    pub async fn respond(&self, session: &mut Session) -> pingora::Result<bool> {
+        session
+            .downstream_session
+            .write_response_header(Box::new(self.header.clone()))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(self.body.clone(), true)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use motya_config::cli::builder::CliConfigBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let mut proxy = CliConfigBuilder::build_hello(8080, "hi".to_string())
+            .unwrap()
+            .basic_proxies
+            .remove(0);
+        proxy.name = "test-maintenance".to_string();
+        proxy.maintenance.enabled = true;
+
+        apply(&[proxy]);
+        assert!(is_on("test-maintenance"));
+
+        set("test-maintenance", false).unwrap();
+        assert!(!is_on("test-maintenance"));
+        assert!(set("unknown", true).is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let config = MaintenanceConfig {
+            retry_after: Some(Duration::from_secs(120)),
+            ..Default::default()
+        };
+        let page = MaintenancePage::load("api", &config).unwrap();
+
+        assert_eq!(page.header.status, 503);
+        assert_eq!(page.header.headers["Retry-After"], "120");
+        assert_eq!(page.header.headers["Content-Type"], "text/plain");
+
+        let config = MaintenanceConfig {
+            page: Some("/nonexistent/maintenance.html".into()),
+            ..Default::default()
+        };
+        assert!(MaintenancePage::load("api", &config).is_err());
+    }
+}
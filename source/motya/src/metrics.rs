@@ -0,0 +1,34 @@
+//! Prometheus metrics, served by the admin API on `/metrics`.
+
+use prometheus::{Encoder, IntGauge, TextEncoder};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Every metric of the default registry in the text exposition format,
+/// including the ones registered by Pingora.
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+
+    if let Err(err) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        tracing::error!("Failed to encode metrics: {err}");
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+/// Counts something in `gauge` for as long as it is held, e.g. a request in
+/// flight, even when it ends on an error path.
+pub struct Active(IntGauge);
+
+impl Active {
+    pub fn new(gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
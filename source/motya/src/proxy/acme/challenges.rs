@@ -0,0 +1,83 @@
+use std::{
+    collections::HashMap,
+    sync::{OnceLock, RwLock},
+};
+
+use bytes::Bytes;
+use pingora::Result;
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Pending HTTP-01 key authorizations, shared by every listener so the
+/// challenge can be answered whichever port 80 service receives it.
+fn pending() -> &'static RwLock<HashMap<String, String>> {
+    static PENDING: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+    PENDING.get_or_init(Default::default)
+}
+
+pub fn publish(token: &str, key_authorization: String) {
+    pending()
+        .write()
+        .expect("challenge lock poisoned")
+        .insert(token.to_string(), key_authorization);
+}
+
+pub fn withdraw(token: &str) {
+    pending()
+        .write()
+        .expect("challenge lock poisoned")
+        .remove(token);
+}
+
+fn lookup(path: &str) -> Option<String> {
+    let token = path.strip_prefix(CHALLENGE_PREFIX)?;
+
+    pending()
+        .read()
+        .expect("challenge lock poisoned")
+        .get(token)
+        .cloned()
+}
+
+/// Answers a pending ACME HTTP-01 challenge. Returns `true` when the request
+/// was handled and must not be proxied.
+pub async fn answer(session: &mut Session) -> Result<bool> {
+    let Some(key_authorization) = lookup(session.req_header().uri.path()) else {
+        return Ok(false);
+    };
+
+    let body = Bytes::from(key_authorization);
+
+    let mut header = ResponseHeader::build(200, None)?;
+    header.insert_header("Content-Type", "application/octet-stream")?;
+    header.insert_header("Content-Length", body.len().to_string())?;
+
+    session
+        .write_response_header(Box::new(header), false)
+        .await?;
+    session.write_response_body(Some(body), true).await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_and_withdraw() {
+        publish("token-a", "token-a.thumb".into());
+
+        assert_eq!(
+            lookup("/.well-known/acme-challenge/token-a").as_deref(),
+            Some("token-a.thumb")
+        );
+        assert_eq!(lookup("/token-a"), None);
+        assert_eq!(lookup("/.well-known/acme-challenge/token-b"), None);
+
+        withdraw("token-a");
+        assert_eq!(lookup("/.well-known/acme-challenge/token-a"), None);
+    }
+}
@@ -0,0 +1,492 @@
+//! Minimal ACME (RFC 8555) client, just enough to obtain a certificate with
+//! HTTP-01 challenges.
+
+use std::time::Duration;
+
+use http::{header, StatusCode};
+use miette::{miette, IntoDiagnostic, Result};
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, BigNumContext, MsbOption},
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    sha::sha256,
+    stack::Stack,
+    x509::{extension::SubjectAlternativeName, X509Builder, X509NameBuilder, X509ReqBuilder, X509},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use crate::proxy::acme::challenges;
+
+const POLL_ATTEMPTS: usize = 30;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+/// PEM certificate chain and private key returned by [`AcmeClient::issue`].
+pub struct Issued {
+    pub chain_pem: String,
+    pub key_pem: Vec<u8>,
+}
+
+pub struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+    key: EcKey<Private>,
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    pub async fn new(directory_url: &str, key: EcKey<Private>) -> Result<Self> {
+        let http = reqwest::Client::new();
+
+        let body = http
+            .get(directory_url)
+            .send()
+            .await
+            .into_diagnostic()?
+            .error_for_status()
+            .into_diagnostic()?
+            .bytes()
+            .await
+            .into_diagnostic()?;
+
+        let directory = serde_json::from_slice(&body)
+            .map_err(|e| miette!("Invalid ACME directory at {directory_url}: {e}"))?;
+
+        Ok(Self {
+            http,
+            directory,
+            key,
+            kid: None,
+            nonce: None,
+        })
+    }
+
+    /// Creates the account, or looks up the existing one bound to the key.
+    pub async fn register(&mut self, email: &str) -> Result<()> {
+        let url = self.directory.new_account.clone();
+        let payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": [format!("mailto:{email}")],
+        });
+
+        let resp = self.post(&url, Some(&payload)).await?;
+        self.kid = Some(location(&resp)?);
+
+        Ok(())
+    }
+
+    /// Orders a certificate for `domains`, answering every authorization with HTTP-01.
+    pub async fn issue(&mut self, domains: &[String]) -> Result<Issued> {
+        let url = self.directory.new_order.clone();
+        let identifiers: Vec<Value> = domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect();
+
+        let resp = self
+            .post(&url, Some(&json!({ "identifiers": identifiers })))
+            .await?;
+        let order_url = location(&resp)?;
+        let order: Order = parse(resp).await?;
+
+        for authz_url in &order.authorizations {
+            self.authorize(authz_url).await?;
+        }
+
+        let order = self
+            .poll::<Order>(&order_url, |o| o.status != "pending")
+            .await?;
+        if order.status != "ready" {
+            return Err(miette!(
+                "ACME order is '{}', expected 'ready'",
+                order.status
+            ));
+        }
+
+        let (key, csr) = csr(domains)?;
+        self.post(&order.finalize, Some(&json!({ "csr": b64(&csr) })))
+            .await?;
+
+        let order = self
+            .poll::<Order>(&order_url, |o| {
+                o.status != "processing" && o.status != "ready"
+            })
+            .await?;
+
+        let certificate = match (order.status.as_str(), order.certificate) {
+            ("valid", Some(url)) => url,
+            (status, _) => return Err(miette!("ACME order finished as '{status}'")),
+        };
+
+        let chain_pem = self
+            .post(&certificate, None)
+            .await?
+            .text()
+            .await
+            .into_diagnostic()?;
+
+        Ok(Issued {
+            chain_pem,
+            key_pem: key.private_key_to_pem_pkcs8().into_diagnostic()?,
+        })
+    }
+
+    async fn authorize(&mut self, authz_url: &str) -> Result<()> {
+        let authz: Authorization = parse(self.post(authz_url, None).await?).await?;
+
+        if authz.status == "valid" {
+            return Ok(());
+        }
+
+        let challenge = authz
+            .challenges
+            .iter()
+            .find(|c| c.kind == "http-01")
+            .ok_or_else(|| {
+                miette!(
+                    "ACME server offers no http-01 challenge for '{}'",
+                    authz.identifier.value
+                )
+            })?;
+
+        let key_authorization = format!("{}.{}", challenge.token, self.thumbprint()?);
+        challenges::publish(&challenge.token, key_authorization);
+
+        let result = async {
+            self.post(&challenge.url, Some(&json!({}))).await?;
+            self.poll::<Authorization>(authz_url, |a| a.status != "pending")
+                .await
+        }
+        .await;
+
+        challenges::withdraw(&challenge.token);
+
+        match result?.status.as_str() {
+            "valid" => Ok(()),
+            status => Err(miette!(
+                "Authorization for '{}' is '{status}'",
+                authz.identifier.value
+            )),
+        }
+    }
+
+    async fn poll<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        done: impl Fn(&T) -> bool,
+    ) -> Result<T> {
+        for _ in 0..POLL_ATTEMPTS {
+            let value: T = parse(self.post(url, None).await?).await?;
+            if done(&value) {
+                return Ok(value);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+
+        Err(miette!("Timed out waiting for {url}"))
+    }
+
+    /// Signed POST; `None` payload makes a POST-as-GET.
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<reqwest::Response> {
+        let mut retried = false;
+
+        loop {
+            let nonce = self.nonce().await?;
+            let body = self.jws(url, &nonce, payload)?;
+
+            let resp = self
+                .http
+                .post(url)
+                .header(header::CONTENT_TYPE, "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .await
+                .into_diagnostic()?;
+
+            self.nonce = replay_nonce(&resp);
+
+            if resp.status().is_success() {
+                return Ok(resp);
+            }
+
+            let status = resp.status();
+            let problem = resp.text().await.unwrap_or_default();
+
+            // Nonces expire, the server expects a single retry with a fresh one.
+            if status == StatusCode::BAD_REQUEST && problem.contains("badNonce") && !retried {
+                retried = true;
+                continue;
+            }
+
+            return Err(miette!(
+                "ACME request to {url} failed with {status}: {problem}"
+            ));
+        }
+    }
+
+    async fn nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+
+        let resp = self
+            .http
+            .head(&self.directory.new_nonce)
+            .send()
+            .await
+            .into_diagnostic()?;
+
+        replay_nonce(&resp).ok_or_else(|| miette!("ACME server returned no Replay-Nonce"))
+    }
+
+    fn jws(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<Value> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk()?,
+        }
+
+        let protected = b64(protected.to_string().as_bytes());
+        let payload = payload
+            .map(|p| b64(p.to_string().as_bytes()))
+            .unwrap_or_default();
+
+        let digest = sha256(format!("{protected}.{payload}").as_bytes());
+        let sig = EcdsaSig::sign(&digest, &self.key).into_diagnostic()?;
+
+        let mut signature = sig.r().to_vec_padded(32).into_diagnostic()?;
+        signature.extend(sig.s().to_vec_padded(32).into_diagnostic()?);
+
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(&signature),
+        }))
+    }
+
+    fn coordinates(&self) -> Result<(String, String)> {
+        let mut ctx = BigNumContext::new().into_diagnostic()?;
+        let mut x = BigNum::new().into_diagnostic()?;
+        let mut y = BigNum::new().into_diagnostic()?;
+
+        self.key
+            .public_key()
+            .affine_coordinates_gfp(self.key.group(), &mut x, &mut y, &mut ctx)
+            .into_diagnostic()?;
+
+        Ok((
+            b64(&x.to_vec_padded(32).into_diagnostic()?),
+            b64(&y.to_vec_padded(32).into_diagnostic()?),
+        ))
+    }
+
+    fn jwk(&self) -> Result<Value> {
+        let (x, y) = self.coordinates()?;
+        Ok(json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y }))
+    }
+
+    /// RFC 7638 thumbprint, members in lexicographic order without whitespace.
+    fn thumbprint(&self) -> Result<String> {
+        let (x, y) = self.coordinates()?;
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#);
+
+        Ok(b64(&sha256(jwk.as_bytes())))
+    }
+}
+
+pub fn generate_key() -> Result<EcKey<Private>> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).into_diagnostic()?;
+    EcKey::generate(&group).into_diagnostic()
+}
+
+/// Builds a fresh certificate key and the DER CSR for `domains`.
+fn csr(domains: &[String]) -> Result<(PKey<Private>, Vec<u8>)> {
+    let key = PKey::from_ec_key(generate_key()?).into_diagnostic()?;
+
+    let mut name = X509NameBuilder::new().into_diagnostic()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, &domains[0])
+        .into_diagnostic()?;
+    let name = name.build();
+
+    let mut req = X509ReqBuilder::new().into_diagnostic()?;
+    req.set_pubkey(&key).into_diagnostic()?;
+    req.set_subject_name(&name).into_diagnostic()?;
+
+    let mut san = SubjectAlternativeName::new();
+    for domain in domains {
+        san.dns(domain);
+    }
+    let san = san.build(&req.x509v3_context(None)).into_diagnostic()?;
+
+    let mut extensions = Stack::new().into_diagnostic()?;
+    extensions.push(san).into_diagnostic()?;
+    req.add_extensions(&extensions).into_diagnostic()?;
+
+    req.sign(&key, MessageDigest::sha256()).into_diagnostic()?;
+
+    let der = req.build().to_der().into_diagnostic()?;
+    Ok((key, der))
+}
+
+/// Short-lived self-signed certificate served until the real one is issued.
+pub fn self_signed(domains: &[String]) -> Result<(X509, PKey<Private>)> {
+    let key = PKey::from_ec_key(generate_key()?).into_diagnostic()?;
+
+    let mut name = X509NameBuilder::new().into_diagnostic()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, &domains[0])
+        .into_diagnostic()?;
+    let name = name.build();
+
+    let mut serial = BigNum::new().into_diagnostic()?;
+    serial
+        .rand(64, MsbOption::MAYBE_ZERO, false)
+        .into_diagnostic()?;
+
+    let serial = serial.to_asn1_integer().into_diagnostic()?;
+    let not_before = Asn1Time::days_from_now(0).into_diagnostic()?;
+    let not_after = Asn1Time::days_from_now(1).into_diagnostic()?;
+
+    let mut cert = X509Builder::new().into_diagnostic()?;
+    cert.set_version(2).into_diagnostic()?;
+    cert.set_serial_number(&serial).into_diagnostic()?;
+    cert.set_subject_name(&name).into_diagnostic()?;
+    cert.set_issuer_name(&name).into_diagnostic()?;
+    cert.set_pubkey(&key).into_diagnostic()?;
+    cert.set_not_before(&not_before).into_diagnostic()?;
+    cert.set_not_after(&not_after).into_diagnostic()?;
+
+    let mut san = SubjectAlternativeName::new();
+    for domain in domains {
+        san.dns(domain);
+    }
+    let san = san
+        .build(&cert.x509v3_context(None, None))
+        .into_diagnostic()?;
+    cert.append_extension(san).into_diagnostic()?;
+
+    cert.sign(&key, MessageDigest::sha256()).into_diagnostic()?;
+
+    Ok((cert.build(), key))
+}
+
+/// Unpadded base64url, as required by JWS.
+fn b64(data: &[u8]) -> String {
+    openssl::base64::encode_block(data)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+fn replay_nonce(resp: &reqwest::Response) -> Option<String> {
+    resp.headers()
+        .get("replay-nonce")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn location(resp: &reqwest::Response) -> Result<String> {
+    resp.headers()
+        .get(header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| miette!("ACME response from {} has no Location", resp.url()))
+}
+
+async fn parse<T: DeserializeOwned>(resp: reqwest::Response) -> Result<T> {
+    let url = resp.url().to_string();
+    let body = resp.bytes().await.into_diagnostic()?;
+
+    serde_json::from_slice(&body).map_err(|e| miette!("Unexpected ACME response from {url}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_b64_is_unpadded_url_safe() {
+        assert_eq!(b64(b"\xfb\xff"), "-_8");
+        assert_eq!(b64(b"{}"), "e30");
+    }
+
+    #[test]
+    fn test_jws_signature_shape() {
+        let client = AcmeClient {
+            http: reqwest::Client::new(),
+            directory: Directory {
+                new_nonce: String::new(),
+                new_account: String::new(),
+                new_order: String::new(),
+            },
+            key: generate_key().unwrap(),
+            kid: None,
+            nonce: None,
+        };
+
+        let jws = client
+            .jws("https://acme.test/new-acct", "nonce", Some(&json!({})))
+            .unwrap();
+
+        assert_eq!(jws["payload"], "e30");
+        // ES256 signatures are the raw 64 byte r || s, 86 chars unpadded.
+        assert_eq!(jws["signature"].as_str().unwrap().len(), 86);
+        // The thumbprint is a SHA-256 digest, 43 chars unpadded.
+        assert_eq!(client.thumbprint().unwrap().len(), 43);
+    }
+
+    #[test]
+    fn test_self_signed_covers_domains() {
+        let domains = vec!["example.com".to_string(), "www.example.com".to_string()];
+        let (cert, _key) = self_signed(&domains).unwrap();
+
+        let names: Vec<String> = cert
+            .subject_alt_names()
+            .unwrap()
+            .iter()
+            .filter_map(|n| n.dnsname().map(str::to_string))
+            .collect();
+
+        assert_eq!(names, domains);
+    }
+}
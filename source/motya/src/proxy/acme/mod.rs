@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use miette::{miette, IntoDiagnostic, Result};
+use openssl::{
+    asn1::{Asn1Time, Asn1TimeRef},
+    ec::EcKey,
+};
+use pingora::{
+    listeners::TlsAccept,
+    tls::{
+        ext::{ssl_add_chain_cert, ssl_use_certificate, ssl_use_private_key},
+        pkey::{PKey, Private},
+        ssl::SslRef,
+        x509::X509,
+    },
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::events::{self, Event};
+use motya_config::common_types::listeners::AcmeConfig;
+
+pub mod challenges;
+pub mod client;
+
+/// Certificates are renewed once they have less than this left.
+const RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const ACCOUNT_KEY_FILE: &str = "account.key.pem";
+
+struct CertifiedKey {
+    chain: Vec<X509>,
+    key: PKey<Private>,
+    /// Seconds since the Unix epoch.
+    expires_at: u64,
+}
+
+impl CertifiedKey {
+    fn from_pem(chain: &[u8], key: &[u8]) -> Result<Self> {
+        let chain = X509::stack_from_pem(chain).into_diagnostic()?;
+        let key = PKey::private_key_from_pem(key).into_diagnostic()?;
+
+        let leaf = chain
+            .first()
+            .ok_or_else(|| miette!("Certificate chain is empty"))?;
+        let expires_at = unix_secs(leaf.not_after())?;
+
+        Ok(Self {
+            chain,
+            key,
+            expires_at,
+        })
+    }
+
+    fn renewal_due(&self, now: SystemTime) -> bool {
+        let expires_at = UNIX_EPOCH + Duration::from_secs(self.expires_at);
+        expires_at.duration_since(now).unwrap_or_default() < RENEW_BEFORE
+    }
+}
+
+/// Serves the current certificate of an ACME managed listener and swaps it
+/// in place when it is renewed, without restarting the listener.
+#[derive(Clone)]
+pub struct AcmeCertResolver {
+    current: Arc<ArcSwap<CertifiedKey>>,
+}
+
+#[async_trait]
+impl TlsAccept for AcmeCertResolver {
+    async fn certificate_callback(&self, ssl: &mut SslRef) {
+        let cert = self.current.load();
+
+        let Some((leaf, intermediates)) = cert.chain.split_first() else {
+            return;
+        };
+
+        let result = ssl_use_certificate(ssl, leaf)
+            .and_then(|_| ssl_use_private_key(ssl, &cert.key))
+            .and_then(|_| {
+                intermediates
+                    .iter()
+                    .try_for_each(|c| ssl_add_chain_cert(ssl, c))
+            });
+
+        if let Err(err) = result {
+            tracing::error!("Failed to install ACME certificate: {err}");
+        }
+    }
+}
+
+/// Returns the resolver for `config`, starting its renewal task on first use.
+///
+/// Listeners sharing the same `acme` block share one certificate and one
+/// renewal task.
+pub fn resolver(config: &AcmeConfig) -> Result<AcmeCertResolver> {
+    static RESOLVERS: OnceLock<Mutex<HashMap<AcmeConfig, AcmeCertResolver>>> = OnceLock::new();
+
+    let mut resolvers = RESOLVERS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("resolver lock poisoned");
+
+    if let Some(resolver) = resolvers.get(config) {
+        return Ok(resolver.clone());
+    }
+
+    let (cert_path, key_path) = cert_paths(config);
+    let initial = match (std::fs::read(&cert_path), std::fs::read(&key_path)) {
+        (Ok(chain), Ok(key)) => CertifiedKey::from_pem(&chain, &key)
+            .inspect_err(|err| {
+                tracing::warn!("Ignoring cached certificate {cert_path:?}: {err}");
+            })
+            .ok(),
+        _ => None,
+    };
+
+    let initial = match initial {
+        Some(cert) => cert,
+        None => {
+            // Lets the listener start, the real certificate replaces it once issued.
+            let (cert, key) = client::self_signed(&config.domains)?;
+            CertifiedKey {
+                chain: vec![cert],
+                key,
+                expires_at: 0,
+            }
+        }
+    };
+
+    let resolver = AcmeCertResolver {
+        current: Arc::new(ArcSwap::from_pointee(initial)),
+    };
+
+    let manager = AcmeManager {
+        config: config.clone(),
+        current: resolver.current.clone(),
+    };
+
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            handle.spawn(manager.run());
+        }
+        Err(_) => tracing::warn!(
+            "No runtime available, certificates for {:?} won't be renewed",
+            config.domains
+        ),
+    }
+
+    resolvers.insert(config.clone(), resolver.clone());
+
+    Ok(resolver)
+}
+
+/// The cached certificate chain and key of `config`.
+pub(crate) fn cert_paths(config: &AcmeConfig) -> (PathBuf, PathBuf) {
+    let primary = &config.domains[0];
+    (
+        config.cache_dir.join(format!("{primary}.crt.pem")),
+        config.cache_dir.join(format!("{primary}.key.pem")),
+    )
+}
+
+struct AcmeManager {
+    config: AcmeConfig,
+    current: Arc<ArcSwap<CertifiedKey>>,
+}
+
+impl AcmeManager {
+    async fn run(self) {
+        loop {
+            let wait = if !self.current.load().renewal_due(SystemTime::now()) {
+                CHECK_INTERVAL
+            } else {
+                match self.renew().await {
+                    Ok(()) => CHECK_INTERVAL,
+                    Err(err) => {
+                        tracing::error!(
+                            "Failed to obtain certificate for {:?}: {err:?}",
+                            self.config.domains
+                        );
+                        RETRY_INTERVAL
+                    }
+                }
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    async fn renew(&self) -> Result<()> {
+        tracing::info!(
+            "Requesting certificate for {:?} from {}",
+            self.config.domains,
+            self.config.directory
+        );
+
+        fs::create_dir_all(&self.config.cache_dir)
+            .await
+            .into_diagnostic()?;
+
+        let account_key = self.account_key().await?;
+        let mut acme = client::AcmeClient::new(&self.config.directory, account_key).await?;
+        acme.register(&self.config.email).await?;
+
+        let issued = acme.issue(&self.config.domains).await?;
+        let cert = CertifiedKey::from_pem(issued.chain_pem.as_bytes(), &issued.key_pem)?;
+
+        let (cert_path, key_path) = cert_paths(&self.config);
+        write_private(&key_path, &issued.key_pem).await?;
+        write_private(&cert_path, issued.chain_pem.as_bytes()).await?;
+
+        let expires_at = cert.expires_at;
+        self.current.store(Arc::new(cert));
+
+        tracing::info!("Installed new certificate for {:?}", self.config.domains);
+
+        events::emit(Event::CertRenewed {
+            domain: self.config.domains[0].clone(),
+            expires_at,
+        });
+
+        Ok(())
+    }
+
+    async fn account_key(&self) -> Result<EcKey<Private>> {
+        let path = self.config.cache_dir.join(ACCOUNT_KEY_FILE);
+
+        match fs::read(&path).await {
+            Ok(pem) => EcKey::private_key_from_pem(&pem).into_diagnostic(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let key = client::generate_key()?;
+                write_private(&path, &key.private_key_to_pem().into_diagnostic()?).await?;
+                Ok(key)
+            }
+            Err(err) => Err(err).into_diagnostic(),
+        }
+    }
+}
+
+async fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(&tmp).await.into_diagnostic()?;
+    file.write_all(contents).await.into_diagnostic()?;
+    file.sync_all().await.into_diagnostic()?;
+
+    fs::rename(&tmp, path).await.into_diagnostic()
+}
+
+/// Expiry of the first certificate of a PEM chain, seconds since the Unix epoch.
+pub(crate) fn leaf_expiry(chain: &[u8]) -> Result<u64> {
+    let leaf = X509::from_pem(chain).into_diagnostic()?;
+    unix_secs(leaf.not_after())
+}
+
+fn unix_secs(time: &Asn1TimeRef) -> Result<u64> {
+    let diff = Asn1Time::from_unix(0)
+        .into_diagnostic()?
+        .diff(time)
+        .into_diagnostic()?;
+
+    Ok((diff.days as i64 * 86400 + diff.secs as i64).max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholder_is_due_for_renewal() {
+        let domains = vec!["example.com".to_string()];
+        let (cert, key) = client::self_signed(&domains).unwrap();
+
+        let chain = cert.to_pem().unwrap();
+        let key = key.private_key_to_pem_pkcs8().unwrap();
+        let parsed = CertifiedKey::from_pem(&chain, &key).unwrap();
+
+        let now = SystemTime::now();
+        let expires_in = parsed.expires_at - now.duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        // Self-signed certificates live a day, far below the renewal margin.
+        assert!(expires_in <= 24 * 60 * 60 + 60);
+        assert!(parsed.renewal_due(now));
+    }
+
+    #[test]
+    fn test_cert_paths_use_primary_domain() {
+        let config = AcmeConfig {
+            domains: vec!["example.com".into(), "www.example.com".into()],
+            email: "ops@example.com".into(),
+            cache_dir: "/var/lib/motya/acme".into(),
+            directory: motya_config::common_types::listeners::LETS_ENCRYPT_DIRECTORY.into(),
+        };
+
+        let (cert, key) = cert_paths(&config);
+        assert_eq!(cert, Path::new("/var/lib/motya/acme/example.com.crt.pem"));
+        assert_eq!(key, Path::new("/var/lib/motya/acme/example.com.key.pem"));
+    }
+}
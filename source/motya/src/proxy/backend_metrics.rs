@@ -0,0 +1,135 @@
+use std::{net::SocketAddr, sync::OnceLock, time::Duration};
+
+use http::StatusCode;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramVec,
+    IntCounterVec, IntGaugeVec,
+};
+
+use crate::metrics::Active;
+
+struct BackendMetrics {
+    responses: IntCounterVec,
+    latency: HistogramVec,
+    in_flight: IntGaugeVec,
+}
+
+fn metrics() -> &'static BackendMetrics {
+    static METRICS: OnceLock<BackendMetrics> = OnceLock::new();
+
+    METRICS.get_or_init(|| BackendMetrics {
+        responses: register_int_counter_vec!(
+            "motya_backend_responses_total",
+            "Upstream attempts per backend, by status class or 'error' without a response",
+            &["route", "backend", "class"]
+        )
+        .expect("metric should register once"),
+        latency: register_histogram_vec!(
+            "motya_backend_response_seconds",
+            "Time from picking a backend to its response header or failure",
+            &["route", "backend"]
+        )
+        .expect("metric should register once"),
+        in_flight: register_int_gauge_vec!(
+            "motya_backend_requests_in_flight",
+            "Upstream attempts picked for a backend and not finished yet",
+            &["backend"]
+        )
+        .expect("metric should register once"),
+    })
+}
+
+/// `2xx` and the like, `error` for an attempt that got no response.
+fn class(status: Option<StatusCode>) -> &'static str {
+    match status.map(|status| status.as_u16() / 100) {
+        Some(1) => "1xx",
+        Some(2) => "2xx",
+        Some(3) => "3xx",
+        Some(4) => "4xx",
+        Some(5) => "5xx",
+        _ => "error",
+    }
+}
+
+/// Counts an upstream attempt of `route` against `backend`, with the status
+/// it was answered with if any.
+pub fn record(route: &str, backend: SocketAddr, status: Option<StatusCode>, elapsed: Duration) {
+    let metrics = metrics();
+    let backend = backend.to_string();
+
+    metrics
+        .responses
+        .with_label_values(&[route, &backend, class(status)])
+        .inc();
+    metrics
+        .latency
+        .with_label_values(&[route, &backend])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Counts an attempt against `backend` as in flight until the guard is dropped.
+pub fn in_flight(backend: SocketAddr) -> Active {
+    Active::new(
+        metrics()
+            .in_flight
+            .with_label_values(&[&backend.to_string()]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let backend: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+
+        record(
+            "/metrics-test",
+            backend,
+            Some(StatusCode::OK),
+            Duration::from_millis(5),
+        );
+        record(
+            "/metrics-test",
+            backend,
+            Some(StatusCode::BAD_GATEWAY),
+            Duration::from_millis(7),
+        );
+        record("/metrics-test", backend, None, Duration::from_secs(1));
+
+        let responses = |class: &str| {
+            metrics()
+                .responses
+                .with_label_values(&["/metrics-test", "10.0.0.1:8080", class])
+                .get()
+        };
+        assert_eq!(responses("2xx"), 1);
+        assert_eq!(responses("5xx"), 1);
+        assert_eq!(responses("error"), 1);
+
+        let latency = metrics()
+            .latency
+            .with_label_values(&["/metrics-test", "10.0.0.1:8080"]);
+        assert_eq!(latency.get_sample_count(), 3);
+    }
+
+    #[test]
+    fn test_in_flight() {
+        let backend: SocketAddr = "10.0.0.2:8080".parse().unwrap();
+        let gauge = || {
+            metrics()
+                .in_flight
+                .with_label_values(&["10.0.0.2:8080"])
+                .get()
+        };
+
+        let first = in_flight(backend);
+        let second = in_flight(backend);
+        assert_eq!(gauge(), 2);
+
+        drop(first);
+        drop(second);
+        assert_eq!(gauge(), 0);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use cidr::IpCidr;
+use pingora::prelude::HttpPeer;
+
+use crate::proxy::balancer::key_selector::KeySourceContext;
+use motya_config::common_types::connectors::BackendOverrideConfig;
+
+/// Forces the backend named in a request header, for trusted clients only.
+pub struct BackendOverride {
+    header: String,
+    trusted: Vec<IpCidr>,
+    peers: HashMap<SocketAddr, HttpPeer>,
+}
+
+impl BackendOverride {
+    /// Only the backends in `peers` can be picked, other addresses are ignored.
+    pub fn new(config: BackendOverrideConfig, peers: HashMap<SocketAddr, HttpPeer>) -> Self {
+        Self {
+            header: config.header,
+            trusted: config.trusted,
+            peers,
+        }
+    }
+
+    pub fn pick<C: KeySourceContext>(&self, ctx: &C) -> Option<HttpPeer> {
+        let value = ctx.get_header(&self.header)?;
+
+        let trusted = ctx
+            .get_ip()
+            .is_some_and(|ip| self.trusted.iter().any(|range| range.contains(&ip)));
+
+        if !trusted {
+            tracing::debug!("Ignoring '{}' from an untrusted client", self.header);
+            return None;
+        }
+
+        let peer = value
+            .trim()
+            .parse::<SocketAddr>()
+            .ok()
+            .and_then(|addr| self.peers.get(&addr));
+
+        if peer.is_none() {
+            tracing::debug!("Ignoring '{}', '{value}' is not a backend", self.header);
+        }
+
+        peer.cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::IpAddr};
+
+    use http::uri::PathAndQuery;
+
+    use super::*;
+
+    struct MockContext {
+        ip: Option<IpAddr>,
+        backend: Option<String>,
+    }
+
+    impl KeySourceContext for MockContext {
+        fn get_header(&self, name: &str) -> Option<&str> {
+            assert_eq!(name, "x-motya-backend");
+            self.backend.as_deref()
+        }
+        fn get_cookie(&self, _: &str) -> Option<&str> {
+            None
+        }
+        fn get_ip(&self) -> Option<IpAddr> {
+            self.ip
+        }
+        fn get_path(&self) -> &PathAndQuery {
+            static PATH: PathAndQuery = PathAndQuery::from_static("/");
+            &PATH
+        }
+    }
+
+    fn backend_override() -> BackendOverride {
+        let addr: SocketAddr = "10.0.0.5:8080".parse().unwrap();
+
+        BackendOverride::new(
+            BackendOverrideConfig {
+                header: "x-motya-backend".into(),
+                trusted: vec!["192.168.0.0/16".parse().unwrap()],
+            },
+            HashMap::from([(addr, HttpPeer::new(addr, false, String::new()))]),
+        )
+    }
+
+    fn ctx(ip: &str, backend: &str) -> MockContext {
+        MockContext {
+            ip: Some(ip.parse().unwrap()),
+            backend: Some(backend.into()),
+        }
+    }
+
+    #[test]
+    fn test_trusted_client_picks_backend() {
+        let peer = backend_override()
+            .pick(&ctx("192.168.1.10", "10.0.0.5:8080"))
+            .unwrap();
+
+        assert_eq!(peer._address.to_string(), "10.0.0.5:8080");
+    }
+
+    #[test]
+    fn test_override_ignored() {
+        let rule = backend_override();
+
+        // untrusted client
+        assert!(rule.pick(&ctx("172.16.0.1", "10.0.0.5:8080")).is_none());
+        // not a backend of the upstream
+        assert!(rule.pick(&ctx("192.168.1.10", "10.0.0.9:8080")).is_none());
+        assert!(rule.pick(&ctx("192.168.1.10", "garbage")).is_none());
+        // no header
+        let no_header = MockContext {
+            ip: Some("192.168.1.10".parse().unwrap()),
+            backend: None,
+        };
+        assert!(rule.pick(&no_header).is_none());
+    }
+}
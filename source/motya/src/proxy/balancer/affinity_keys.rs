@@ -0,0 +1,359 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use pingora::{
+    server::ShutdownWatch,
+    services::background::{background_service, BackgroundService},
+};
+use sha2::Sha256;
+use tokio::{fs, io::AsyncWriteExt};
+
+const KEY_FILE: &str = "affinity-keys";
+const SECRET_LEN: usize = 32;
+/// Truncated HMAC length, keeps cookies short while staying unguessable.
+const TAG_LEN: usize = 16;
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SigningKey {
+    id: u32,
+    created: u64,
+    secret: [u8; SECRET_LEN],
+}
+
+impl SigningKey {
+    fn generate(id: u32, now: SystemTime) -> io::Result<Self> {
+        let mut secret = [0u8; SECRET_LEN];
+        getrandom::fill(&mut secret).map_err(io::Error::other)?;
+
+        Ok(Self {
+            id,
+            created: unix_secs(now),
+            secret,
+        })
+    }
+
+    fn mac(&self, value: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts any key size");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    fn encode(&self) -> String {
+        format!("{} {} {}", self.id, self.created, hex_encode(&self.secret))
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+
+        let id = parts.next()?.parse().ok()?;
+        let created = parts.next()?.parse().ok()?;
+        let secret = hex_decode(parts.next()?)?.try_into().ok()?;
+
+        parts.next().is_none().then_some(Self {
+            id,
+            created,
+            secret,
+        })
+    }
+}
+
+/// The active key and the one it replaced.
+///
+/// Cookies signed with the previous key stay valid for one more rotation period.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyRing {
+    current: SigningKey,
+    previous: Option<SigningKey>,
+}
+
+impl KeyRing {
+    fn encode(&self) -> String {
+        let mut out = self.current.encode();
+        out.push('\n');
+        if let Some(previous) = &self.previous {
+            out.push_str(&previous.encode());
+            out.push('\n');
+        }
+        out
+    }
+
+    fn decode(source: &str) -> Option<Self> {
+        let mut lines = source.lines().filter(|line| !line.trim().is_empty());
+
+        let current = SigningKey::decode(lines.next()?)?;
+        let previous = match lines.next() {
+            Some(line) => Some(SigningKey::decode(line)?),
+            None => None,
+        };
+
+        Some(Self { current, previous })
+    }
+
+    fn key(&self, id: u32) -> Option<&SigningKey> {
+        std::iter::once(&self.current)
+            .chain(self.previous.as_ref())
+            .find(|key| key.id == id)
+    }
+}
+
+/// Signing keys for sticky-session cookies, persisted in the state directory.
+///
+/// Signed cookies can't be forged to pin a client to an arbitrary backend, and
+/// since the keys are reloaded on startup, affinity survives restarts.
+pub struct AffinityKeyStore {
+    path: PathBuf,
+    rotate: Duration,
+    ring: ArcSwap<KeyRing>,
+}
+
+impl AffinityKeyStore {
+    /// Loads the keys from `state_dir`, generating them on first start and
+    /// rotating them when they are older than `rotate`.
+    pub async fn open(state_dir: &Path, rotate: Duration) -> io::Result<Self> {
+        fs::create_dir_all(state_dir).await?;
+        let path = state_dir.join(KEY_FILE);
+
+        let loaded = match fs::read_to_string(&path).await {
+            Ok(source) => {
+                let ring = KeyRing::decode(&source);
+                if ring.is_none() {
+                    tracing::warn!("Affinity keys in {:?} are corrupted, regenerating", path);
+                }
+                ring
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        let ring = match loaded {
+            Some(ring) => ring,
+            None => {
+                let ring = KeyRing {
+                    current: SigningKey::generate(1, SystemTime::now())?,
+                    previous: None,
+                };
+                persist(&path, &ring).await?;
+                ring
+            }
+        };
+
+        let store = Self {
+            path,
+            rotate,
+            ring: ArcSwap::from_pointee(ring),
+        };
+
+        store.rotate_if_due(SystemTime::now()).await?;
+
+        Ok(store)
+    }
+
+    /// Replaces the current key once it is older than the rotation period.
+    pub async fn rotate_if_due(&self, now: SystemTime) -> io::Result<bool> {
+        let ring = self.ring.load();
+
+        if unix_secs(now).saturating_sub(ring.current.created) < self.rotate.as_secs() {
+            return Ok(false);
+        }
+
+        let rotated = KeyRing {
+            current: SigningKey::generate(ring.current.id.wrapping_add(1), now)?,
+            previous: Some(ring.current.clone()),
+        };
+
+        persist(&self.path, &rotated).await?;
+        self.ring.store(Arc::new(rotated));
+
+        tracing::info!("Rotated affinity signing key");
+
+        Ok(true)
+    }
+
+    /// Returns `value.key-id.signature`.
+    pub fn sign(&self, value: &str) -> String {
+        let ring = self.ring.load();
+        let tag = ring.current.mac(value).finalize().into_bytes();
+
+        format!(
+            "{value}.{:x}.{}",
+            ring.current.id,
+            hex_encode(&tag[..TAG_LEN])
+        )
+    }
+
+    /// Returns the original value of a cookie produced by [`Self::sign`], or
+    /// `None` when the signature is invalid or the key has been rotated out.
+    pub fn verify<'a>(&self, signed: &'a str) -> Option<&'a str> {
+        let mut parts = signed.rsplitn(3, '.');
+
+        let tag = hex_decode(parts.next()?)?;
+        let id = u32::from_str_radix(parts.next()?, 16).ok()?;
+        let value = parts.next()?;
+
+        if tag.len() != TAG_LEN {
+            return None;
+        }
+
+        let ring = self.ring.load();
+        let key = ring.key(id)?;
+
+        key.mac(value)
+            .verify_truncated_left(&tag)
+            .ok()
+            .map(|_| value)
+    }
+
+    fn check_interval(&self) -> Duration {
+        self.rotate.min(MAX_CHECK_INTERVAL)
+    }
+}
+
+async fn persist(path: &Path, ring: &KeyRing) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(&tmp).await?;
+    file.write_all(ring.encode().as_bytes()).await?;
+    file.sync_all().await?;
+
+    fs::rename(&tmp, path).await
+}
+
+/// Background task rotating the keys on schedule.
+struct AffinityKeyRotation {
+    store: Arc<AffinityKeyStore>,
+}
+
+#[async_trait]
+impl BackgroundService for AffinityKeyRotation {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut ticker = tokio::time::interval(self.store.check_interval());
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = ticker.tick() => {
+                    if let Err(err) = self.store.rotate_if_due(SystemTime::now()).await {
+                        tracing::error!("Failed to rotate affinity signing key: {err}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn affinity_key_rotation_service(
+    store: Arc<AffinityKeyStore>,
+) -> Box<dyn pingora::services::Service> {
+    Box::new(background_service(
+        "motya-affinity-keys",
+        AffinityKeyRotation { store },
+    ))
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    #[tokio::test]
+    async fn test_sign_and_verify() {
+        let dir = tempdir().unwrap();
+        let store = AffinityKeyStore::open(dir.path(), WEEK).await.unwrap();
+
+        let signed = store.sign("backend-2");
+        assert_eq!(store.verify(&signed), Some("backend-2"));
+
+        let forged = signed.replace("backend-2", "backend-3");
+        assert_eq!(store.verify(&forged), None);
+        assert_eq!(store.verify("backend-3"), None);
+        assert_eq!(store.verify("backend-3.1.zz"), None);
+    }
+
+    #[tokio::test]
+    async fn test_keys_survive_restart() {
+        let dir = tempdir().unwrap();
+
+        let signed = AffinityKeyStore::open(dir.path(), WEEK)
+            .await
+            .unwrap()
+            .sign("10.0.0.1:80");
+
+        let reopened = AffinityKeyStore::open(dir.path(), WEEK).await.unwrap();
+        assert_eq!(reopened.verify(&signed), Some("10.0.0.1:80"));
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_previous_key() {
+        let dir = tempdir().unwrap();
+        let store = AffinityKeyStore::open(dir.path(), WEEK).await.unwrap();
+        let now = SystemTime::now();
+
+        let old = store.sign("a");
+
+        assert!(!store.rotate_if_due(now).await.unwrap());
+        assert!(store.rotate_if_due(now + WEEK).await.unwrap());
+
+        let new = store.sign("a");
+        assert_ne!(old, new);
+        assert_eq!(store.verify(&old), Some("a"));
+        assert_eq!(store.verify(&new), Some("a"));
+
+        // A second rotation drops the original key.
+        assert!(store.rotate_if_due(now + WEEK * 2).await.unwrap());
+        assert_eq!(store.verify(&old), None);
+        assert_eq!(store.verify(&new), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_file_is_regenerated() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join(KEY_FILE), "garbage").unwrap();
+
+        let store = AffinityKeyStore::open(dir.path(), WEEK).await.unwrap();
+        let signed = store.sign("a");
+
+        assert_eq!(store.verify(&signed), Some("a"));
+        assert!(
+            KeyRing::decode(&std::fs::read_to_string(dir.path().join(KEY_FILE)).unwrap()).is_some()
+        );
+    }
+}
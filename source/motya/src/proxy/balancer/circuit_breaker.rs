@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+use serde::Serialize;
+
+use motya_config::internal::CircuitBreakerConfig;
+
+struct BreakerMetrics {
+    state: IntGaugeVec,
+    trips: IntCounterVec,
+}
+
+fn metrics() -> &'static BreakerMetrics {
+    static METRICS: OnceLock<BreakerMetrics> = OnceLock::new();
+
+    METRICS.get_or_init(|| BreakerMetrics {
+        state: register_int_gauge_vec!(
+            "motya_circuit_breaker_state",
+            "Circuit breaker state per backend, 0 closed, 1 half-open, 2 open",
+            &["route", "backend"]
+        )
+        .expect("metric should register once"),
+        trips: register_int_counter_vec!(
+            "motya_circuit_breaker_trips_total",
+            "Times a backend circuit breaker opened",
+            &["route", "backend"]
+        )
+        .expect("metric should register once"),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CircuitState {
+    Closed,
+    /// Lets a single trial request through to probe the backend.
+    HalfOpen,
+    Open,
+}
+
+impl CircuitState {
+    fn gauge(self) -> i64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen => 1,
+            CircuitState::Open => 2,
+        }
+    }
+}
+
+struct BackendCircuit {
+    state: CircuitState,
+    /// When the breaker opened, or when the current trial request started.
+    since: Instant,
+    trial_in_flight: bool,
+    consecutive_failures: usize,
+    window_started: Instant,
+    requests: usize,
+    failures: usize,
+}
+
+impl BackendCircuit {
+    fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            state: CircuitState::Closed,
+            since: now,
+            trial_in_flight: false,
+            consecutive_failures: 0,
+            window_started: now,
+            requests: 0,
+            failures: 0,
+        }
+    }
+
+    fn reset_counters(&mut self) {
+        self.consecutive_failures = 0;
+        self.window_started = Instant::now();
+        self.requests = 0;
+        self.failures = 0;
+    }
+}
+
+/// Per-backend circuit breakers of a balanced route.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    route: String,
+    circuits: HashMap<SocketAddr, Mutex<BackendCircuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(
+        config: CircuitBreakerConfig,
+        route: String,
+        backends: impl IntoIterator<Item = SocketAddr>,
+    ) -> Self {
+        let circuits = backends
+            .into_iter()
+            .map(|addr| (addr, Mutex::new(BackendCircuit::new())))
+            .collect();
+
+        let breaker = Self {
+            config,
+            route,
+            circuits,
+        };
+
+        for addr in breaker.circuits.keys() {
+            breaker.publish(addr, CircuitState::Closed);
+        }
+
+        breaker
+    }
+
+    fn circuit(&self, addr: &SocketAddr) -> Option<std::sync::MutexGuard<'_, BackendCircuit>> {
+        self.circuits
+            .get(addr)
+            .map(|circuit| circuit.lock().expect("circuit breaker poisoned"))
+    }
+
+    fn publish(&self, addr: &SocketAddr, state: CircuitState) {
+        metrics()
+            .state
+            .with_label_values(&[self.route.as_str(), &addr.to_string()])
+            .set(state.gauge());
+    }
+
+    fn transition(&self, addr: &SocketAddr, circuit: &mut BackendCircuit, state: CircuitState) {
+        circuit.state = state;
+        circuit.since = Instant::now();
+        circuit.trial_in_flight = false;
+
+        match state {
+            CircuitState::Open => {
+                tracing::warn!("Circuit breaker of {addr} on '{}' opened", self.route);
+                metrics()
+                    .trips
+                    .with_label_values(&[self.route.as_str(), &addr.to_string()])
+                    .inc();
+            }
+            CircuitState::Closed => {
+                tracing::info!("Circuit breaker of {addr} on '{}' closed", self.route);
+                circuit.reset_counters();
+            }
+            CircuitState::HalfOpen => {}
+        }
+
+        self.publish(addr, state);
+    }
+
+    /// Whether a request may be sent to `addr`. A half-open breaker admits one
+    /// trial request at a time, so only call this for the backend that is used.
+    pub fn allows(&self, addr: &SocketAddr) -> bool {
+        let Some(mut circuit) = self.circuit(addr) else {
+            return true;
+        };
+
+        let state = circuit.state;
+
+        match state {
+            CircuitState::Closed => true,
+            CircuitState::Open if circuit.since.elapsed() >= self.config.cool_down => {
+                self.transition(addr, &mut circuit, CircuitState::HalfOpen);
+                circuit.trial_in_flight = true;
+                true
+            }
+            CircuitState::Open => false,
+            // A trial that never reported back, e.g. the client went away,
+            // is replaced after another cool-down.
+            CircuitState::HalfOpen
+                if !circuit.trial_in_flight || circuit.since.elapsed() >= self.config.cool_down =>
+            {
+                circuit.trial_in_flight = true;
+                circuit.since = Instant::now();
+                true
+            }
+            CircuitState::HalfOpen => false,
+        }
+    }
+
+    /// Feeds the outcome of a request sent to `addr`.
+    pub fn record(&self, addr: &SocketAddr, success: bool) {
+        let Some(mut circuit) = self.circuit(addr) else {
+            return;
+        };
+
+        let state = circuit.state;
+
+        match state {
+            CircuitState::HalfOpen if success => {
+                self.transition(addr, &mut circuit, CircuitState::Closed)
+            }
+            CircuitState::HalfOpen => self.transition(addr, &mut circuit, CircuitState::Open),
+            // Requests that were in flight when the breaker opened.
+            CircuitState::Open => {}
+            CircuitState::Closed => {
+                if circuit.window_started.elapsed() >= self.config.window {
+                    circuit.window_started = Instant::now();
+                    circuit.requests = 0;
+                    circuit.failures = 0;
+                }
+
+                circuit.requests += 1;
+
+                if success {
+                    circuit.consecutive_failures = 0;
+                    return;
+                }
+
+                circuit.failures += 1;
+                circuit.consecutive_failures += 1;
+
+                let rate_exceeded = self.config.error_rate.is_some_and(|rate| {
+                    circuit.requests >= self.config.min_requests
+                        && circuit.failures * 100 >= rate * circuit.requests
+                });
+
+                if circuit.consecutive_failures >= self.config.consecutive_failures || rate_exceeded
+                {
+                    self.transition(addr, &mut circuit, CircuitState::Open);
+                }
+            }
+        }
+    }
+
+    /// State of every backend, sorted by address.
+    pub fn states(&self) -> Vec<(SocketAddr, CircuitState)> {
+        let mut states: Vec<_> = self
+            .circuits
+            .iter()
+            .map(|(addr, circuit)| {
+                let state = circuit.lock().expect("circuit breaker poisoned").state;
+                (*addr, state)
+            })
+            .collect();
+
+        states.sort_by_key(|(addr, _)| *addr);
+        states
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn addr() -> SocketAddr {
+        "10.0.0.1:80".parse().unwrap()
+    }
+
+    fn breaker(error_rate: Option<usize>, cool_down: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(
+            CircuitBreakerConfig {
+                consecutive_failures: 3,
+                error_rate,
+                window: Duration::from_secs(10),
+                min_requests: 4,
+                cool_down,
+            },
+            "/api".into(),
+            [addr()],
+        )
+    }
+
+    fn state(breaker: &CircuitBreaker) -> CircuitState {
+        breaker.states()[0].1
+    }
+
+    #[test]
+    fn test_consecutive_failures() {
+        let breaker = breaker(None, Duration::from_secs(30));
+
+        breaker.record(&addr(), false);
+        breaker.record(&addr(), false);
+        breaker.record(&addr(), true);
+        breaker.record(&addr(), false);
+        breaker.record(&addr(), false);
+        assert_eq!(state(&breaker), CircuitState::Closed);
+        assert!(breaker.allows(&addr()));
+
+        breaker.record(&addr(), false);
+        assert_eq!(state(&breaker), CircuitState::Open);
+        assert!(!breaker.allows(&addr()));
+    }
+
+    #[test]
+    fn test_error_rate() {
+        let breaker = breaker(Some(50), Duration::from_secs(30));
+
+        breaker.record(&addr(), false);
+        breaker.record(&addr(), true);
+        breaker.record(&addr(), false);
+        // Three requests, below `min-requests`.
+        assert_eq!(state(&breaker), CircuitState::Closed);
+
+        breaker.record(&addr(), true);
+        assert_eq!(state(&breaker), CircuitState::Closed);
+
+        breaker.record(&addr(), false);
+        assert_eq!(state(&breaker), CircuitState::Open);
+    }
+
+    #[test]
+    fn test_half_open() {
+        let breaker = breaker(None, Duration::ZERO);
+
+        for _ in 0..3 {
+            breaker.record(&addr(), false);
+        }
+        assert_eq!(state(&breaker), CircuitState::Open);
+
+        // Cool-down elapsed, one trial goes through.
+        assert!(breaker.allows(&addr()));
+        assert_eq!(state(&breaker), CircuitState::HalfOpen);
+
+        breaker.record(&addr(), false);
+        assert_eq!(state(&breaker), CircuitState::Open);
+
+        assert!(breaker.allows(&addr()));
+        breaker.record(&addr(), true);
+        assert_eq!(state(&breaker), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_single_trial() {
+        let breaker = breaker(None, Duration::from_secs(30));
+
+        for _ in 0..3 {
+            breaker.record(&addr(), false);
+        }
+
+        let mut circuit = breaker.circuit(&addr()).unwrap();
+        breaker.transition(&addr(), &mut circuit, CircuitState::HalfOpen);
+        drop(circuit);
+
+        assert!(breaker.allows(&addr()));
+        assert!(!breaker.allows(&addr()));
+    }
+
+    #[test]
+    fn test_unknown_backend() {
+        let breaker = breaker(None, Duration::from_secs(30));
+        let other = "10.0.0.9:80".parse().unwrap();
+
+        for _ in 0..10 {
+            breaker.record(&other, false);
+        }
+        assert!(breaker.allows(&other));
+    }
+}
@@ -0,0 +1,230 @@
+//! Discovery backends of balanced routes, picked by the name in `discovery "NAME"`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use pingora::{prelude::HttpPeer, Error, ErrorType, Result};
+use pingora_load_balancing::{
+    discovery::{ServiceDiscovery, Static},
+    Backend,
+};
+
+use motya_config::{common_types::connectors::ALPN, internal::DiscoveryConfig};
+
+use crate::proxy::upstream_router::http_peer;
+
+/// How often a balancer with a discovery other than `Static` asks it again.
+pub const DISCOVERY_REFRESH: Duration = Duration::from_secs(10);
+
+/// What a discovery backend is built from.
+pub struct DiscoveryInput<'a> {
+    /// Path of the route, for logs and metrics.
+    pub route: &'a str,
+    /// The `server` entries of the route.
+    pub servers: BTreeSet<Backend>,
+    /// Properties of the `discovery` node.
+    pub settings: &'a BTreeMap<String, String>,
+}
+
+type DiscoveryFactoryFn = Box<
+    dyn Fn(DiscoveryInput<'_>) -> Result<Box<dyn ServiceDiscovery + Send + Sync>> + Send + Sync,
+>;
+
+/// Factories of the discovery backends available to `discovery "NAME"`.
+///
+/// Holds the builtin `Static` backend, embedders register their own with
+/// [`DiscoveryRegistry::register_factory`] before the routes are built.
+pub struct DiscoveryRegistry {
+    factories: HashMap<String, DiscoveryFactoryFn>,
+}
+
+impl Default for DiscoveryRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            factories: HashMap::new(),
+        };
+
+        registry.register_factory(DiscoveryConfig::STATIC, |input| {
+            Ok(Static::new(input.servers))
+        });
+
+        registry
+    }
+}
+
+impl DiscoveryRegistry {
+    pub fn register_factory<F>(&mut self, name: impl Into<String>, factory: F)
+    where
+        F: Fn(DiscoveryInput<'_>) -> Result<Box<dyn ServiceDiscovery + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let name = name.into();
+        if self
+            .factories
+            .insert(name.clone(), Box::new(factory))
+            .is_some()
+        {
+            tracing::warn!("Discovery factory '{}' was overwritten", name);
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// Builds the discovery of `config`, its backends reach pingora with the
+    /// peer settings of the route attached.
+    pub fn build(
+        &self,
+        config: &DiscoveryConfig,
+        input: DiscoveryInput<'_>,
+        tls_sni: Option<&str>,
+        alpn: &ALPN,
+    ) -> Result<Box<dyn ServiceDiscovery + Send + Sync>> {
+        let factory = self.factories.get(&config.name).ok_or_else(|| {
+            Error::new(ErrorType::Custom(
+                "Discovery is not registered in the binary",
+            ))
+            .more_context(format!("discovery name: '{}'", config.name))
+        })?;
+
+        Ok(Box::new(WithPeers {
+            inner: factory(input)?,
+            tls_sni: tls_sni.map(str::to_string),
+            alpn: alpn.clone(),
+        }))
+    }
+}
+
+/// Attaches the [`HttpPeer`] the proxy connects with to backends that were
+/// discovered without one.
+struct WithPeers {
+    inner: Box<dyn ServiceDiscovery + Send + Sync>,
+    tls_sni: Option<String>,
+    alpn: ALPN,
+}
+
+#[async_trait]
+impl ServiceDiscovery for WithPeers {
+    async fn discover(&self) -> Result<(BTreeSet<Backend>, HashMap<u64, bool>)> {
+        let (backends, enablement) = self.inner.discover().await?;
+
+        let backends = backends
+            .into_iter()
+            .filter_map(|mut backend| {
+                if backend.ext.get::<HttpPeer>().is_none() {
+                    let Some(addr) = backend.addr.as_inet() else {
+                        tracing::warn!(
+                            "Ignoring discovered backend {} without an IP",
+                            backend.addr
+                        );
+                        return None;
+                    };
+                    let peer = http_peer(addr, self.tls_sni.as_deref(), &self.alpn);
+                    backend.ext.insert(peer);
+                }
+                Some(backend)
+            })
+            .collect();
+
+        Ok((backends, enablement))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str);
+
+    #[async_trait]
+    impl ServiceDiscovery for Fixed {
+        async fn discover(&self) -> Result<(BTreeSet<Backend>, HashMap<u64, bool>)> {
+            Ok((BTreeSet::from([Backend::new(self.0)?]), HashMap::new()))
+        }
+    }
+
+    fn input(settings: &BTreeMap<String, String>) -> DiscoveryInput<'_> {
+        DiscoveryInput {
+            route: "/api",
+            servers: BTreeSet::from([Backend::new("10.0.0.1:80").unwrap()]),
+            settings,
+        }
+    }
+
+    fn config(name: &str) -> DiscoveryConfig {
+        DiscoveryConfig {
+            name: name.to_string(),
+            settings: BTreeMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_discovery() {
+        let mut registry = DiscoveryRegistry::default();
+        registry.register_factory("test.fixed", |input| {
+            match input.settings.get("addr").map(String::as_str) {
+                Some("10.0.0.9:80") => Ok(Box::new(Fixed("10.0.0.9:80"))),
+                _ => Error::e_explain(ErrorType::Custom("bad settings"), "missing 'addr'"),
+            }
+        });
+
+        let settings = BTreeMap::from([("addr".to_string(), "10.0.0.9:80".to_string())]);
+        let discovery = registry
+            .build(
+                &config("test.fixed"),
+                input(&settings),
+                Some("api.internal"),
+                &ALPN::H1,
+            )
+            .unwrap();
+
+        let (backends, _) = discovery.discover().await.unwrap();
+        let backend = backends.first().unwrap();
+        assert_eq!(backend.addr.to_string(), "10.0.0.9:80");
+
+        let peer = backend.ext.get::<HttpPeer>().unwrap();
+        assert_eq!(peer.sni, "api.internal");
+
+        assert!(registry
+            .build(
+                &config("test.fixed"),
+                input(&BTreeMap::new()),
+                None,
+                &ALPN::H1
+            )
+            .is_err());
+        assert!(registry
+            .build(
+                &config("test.missing"),
+                input(&BTreeMap::new()),
+                None,
+                &ALPN::H1
+            )
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_static_discovery() {
+        let registry = DiscoveryRegistry::default();
+        assert!(registry.contains(DiscoveryConfig::STATIC));
+
+        let discovery = registry
+            .build(
+                &DiscoveryConfig::default(),
+                input(&BTreeMap::new()),
+                None,
+                &ALPN::H1,
+            )
+            .unwrap();
+
+        let (backends, _) = discovery.discover().await.unwrap();
+        assert_eq!(backends.len(), 1);
+        assert!(backends.first().unwrap().ext.get::<HttpPeer>().is_some());
+    }
+}
@@ -0,0 +1,53 @@
+//! Backends taken out of balancing by the admin API, e.g. ahead of a deploy.
+//! Requests in flight finish, new ones go to the other backends of the route.
+
+use std::{collections::BTreeSet, net::SocketAddr, sync::Arc};
+
+use arc_swap::ArcSwapOption;
+
+static DRAINED: ArcSwapOption<BTreeSet<SocketAddr>> = ArcSwapOption::const_empty();
+
+/// Whether `addr` is drained, in every route balancing it.
+pub fn is_drained(addr: &SocketAddr) -> bool {
+    DRAINED
+        .load()
+        .as_ref()
+        .is_some_and(|drained| drained.contains(addr))
+}
+
+/// Every drained backend.
+pub fn snapshot() -> Arc<BTreeSet<SocketAddr>> {
+    DRAINED.load_full().unwrap_or_default()
+}
+
+/// Drains or undrains `addr`, which stays so across reloads until a restart.
+pub fn set(addr: SocketAddr, drained: bool) {
+    DRAINED.rcu(|current| {
+        let mut backends = current.as_deref().cloned().unwrap_or_default();
+        if drained {
+            backends.insert(addr);
+        } else {
+            backends.remove(&addr);
+        }
+        Some(Arc::new(backends))
+    });
+
+    tracing::warn!("Backend {addr} drained: {drained}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let addr: SocketAddr = "192.0.2.10:8080".parse().unwrap();
+
+        set(addr, true);
+        assert!(is_drained(&addr));
+        assert!(snapshot().contains(&addr));
+
+        set(addr, false);
+        assert!(!is_drained(&addr));
+    }
+}
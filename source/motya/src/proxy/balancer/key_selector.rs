@@ -1,15 +1,31 @@
 use http::uri::PathAndQuery;
 use pingora_load_balancing::{
     prelude::RoundRobin,
-    selection::{consistent::KetamaHashing, FNVHash, Random},
+    selection::{BackendIter, BackendSelection, FNVHash, Random},
     Backend, LoadBalancer,
 };
 use std::hash::Hasher;
-use std::{io::Cursor, net::IpAddr};
+use std::{
+    io::Cursor,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::{
+    client_ip,
+    proxy::balancer::{
+        circuit_breaker::CircuitBreaker,
+        drain,
+        ring::{KetamaBalancer, KetamaRing},
+        sticky::CookieSticky,
+    },
+};
 
 pub struct Balancer {
     pub selector: Option<KeySelector>,
     pub balancer_type: BalancerType,
+    pub breaker: Option<CircuitBreaker>,
+    pub sticky: Option<CookieSticky>,
 }
 
 pub trait KeySourceContext {
@@ -54,6 +70,7 @@ impl KeySelector {
                     }
                     KeyPart::ClientIp => {
                         if let Some(val) = ctx.get_ip() {
+                            let val = client_ip::anonymize(val);
                             buffer.extend_from_slice(val.to_string().as_bytes());
                         }
                     }
@@ -153,11 +170,24 @@ impl HashOp {
 }
 
 impl Balancer {
-    pub fn select_backend<C: KeySourceContext>(&self, ctx: &C) -> Option<Backend> {
+    /// Picks the backend for a request, building the hashing key in `buffer`
+    /// so its allocation is reused across attempts.
+    pub fn select_backend<C: KeySourceContext>(
+        &self,
+        ctx: &C,
+        buffer: &mut Vec<u8>,
+    ) -> Option<Backend> {
+        if let Some(backend) = self
+            .sticky
+            .as_ref()
+            .and_then(|sticky| sticky.pinned(ctx))
+            .and_then(|addr| self.pinned_backend(&addr))
+        {
+            return Some(backend);
+        }
+
         if let Some(selector) = &self.selector {
-            //TODO: Profiling.
-            let mut buffer = vec![];
-            let key = selector.select(ctx, &mut buffer).unwrap_or(0);
+            let key = selector.select(ctx, buffer).unwrap_or(0);
 
             self.select(&key.to_le_bytes())
         } else {
@@ -165,21 +195,112 @@ impl Balancer {
         }
     }
 
+    /// Ring layout of a Ketama balancer, `None` for the other selection kinds.
+    pub fn ketama_ring(&self) -> Option<&KetamaRing> {
+        match &self.balancer_type {
+            BalancerType::KetamaHashing(b) => Some(b.ring()),
+            _ => None,
+        }
+    }
+
+    /// Skips unhealthy and drained backends, and the ones whose circuit
+    /// breaker is open.
+    fn accepts(&self, backend: &Backend, healthy: bool) -> bool {
+        let Some(addr) = backend.addr.as_inet() else {
+            return healthy;
+        };
+
+        healthy
+            && !drain::is_drained(addr)
+            && self
+                .breaker
+                .as_ref()
+                .is_none_or(|breaker| breaker.allows(addr))
+    }
+
     fn select(&self, key: &[u8]) -> Option<Backend> {
+        let accept = |backend: &Backend, healthy: bool| self.accepts(backend, healthy);
+
         match &self.balancer_type {
-            BalancerType::FNVHash(b) => b.select(key, 256),
-            BalancerType::Random(b) => b.select(key, 256),
-            BalancerType::KetamaHashing(b) => b.select(key, 256),
-            BalancerType::RoundRobin(b) => b.select(key, 256),
+            BalancerType::FNVHash(b) => b.select_with(key, 256, accept),
+            BalancerType::Random(b) => b.select_with(key, 256, accept),
+            BalancerType::KetamaHashing(b) => b.select_with(key, |backend| accept(backend, true)),
+            BalancerType::RoundRobin(b) => b.select_with(key, 256, accept),
         }
     }
+
+    /// Every backend with whether it passes its health checks, the ones of a
+    /// Ketama ring are not checked.
+    pub fn health(&self) -> Vec<(SocketAddr, bool)> {
+        match &self.balancer_type {
+            BalancerType::FNVHash(b) => backends_health(b),
+            BalancerType::Random(b) => backends_health(b),
+            BalancerType::RoundRobin(b) => backends_health(b),
+            BalancerType::KetamaHashing(b) => b
+                .ring()
+                .nodes()
+                .iter()
+                .map(|node| (node.addr, true))
+                .collect(),
+        }
+    }
+
+    /// Whether `addr` is a backend that currently takes requests.
+    pub fn is_available(&self, addr: &SocketAddr) -> bool {
+        self.pinned_backend(addr).is_some()
+    }
+
+    /// The backend a `CookieSticky` client is pinned to, `None` when it is gone
+    /// or skipped, so the fallback selection picks another one.
+    fn pinned_backend(&self, addr: &SocketAddr) -> Option<Backend> {
+        let (backend, healthy) = match &self.balancer_type {
+            BalancerType::FNVHash(b) => find_backend(b, addr),
+            BalancerType::Random(b) => find_backend(b, addr),
+            BalancerType::RoundRobin(b) => find_backend(b, addr),
+            BalancerType::KetamaHashing(b) => b.backend(addr).map(|b| (b.clone(), true)),
+        }?;
+
+        self.accepts(&backend, healthy).then_some(backend)
+    }
+}
+
+fn backends_health<S>(lb: &LoadBalancer<S>) -> Vec<(SocketAddr, bool)>
+where
+    S: BackendSelection + 'static,
+    S::Iter: BackendIter,
+{
+    let backends = lb.backends();
+
+    backends
+        .get_backend()
+        .iter()
+        .filter_map(|backend| Some((*backend.addr.as_inet()?, backends.ready(backend))))
+        .collect()
+}
+
+/// Backend at `addr` and whether it passes its health checks.
+fn find_backend<S>(lb: &LoadBalancer<S>, addr: &SocketAddr) -> Option<(Backend, bool)>
+where
+    S: BackendSelection + 'static,
+    S::Iter: BackendIter,
+{
+    let backends = lb.backends();
+    let backend = backends
+        .get_backend()
+        .iter()
+        .find(|backend| backend.addr.as_inet() == Some(addr))
+        .cloned()?;
+    let healthy = backends.ready(&backend);
+
+    Some((backend, healthy))
 }
 
 pub enum BalancerType {
-    RoundRobin(LoadBalancer<RoundRobin>),
-    Random(LoadBalancer<Random>),
-    FNVHash(LoadBalancer<FNVHash>),
-    KetamaHashing(LoadBalancer<KetamaHashing>),
+    // Shared with the health check task, if any.
+    RoundRobin(Arc<LoadBalancer<RoundRobin>>),
+    Random(Arc<LoadBalancer<Random>>),
+    FNVHash(Arc<LoadBalancer<FNVHash>>),
+    KetamaHashing(KetamaBalancer),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -1,2 +1,8 @@
+pub mod affinity_keys;
+pub mod circuit_breaker;
+pub mod discovery;
+pub mod drain;
 pub mod key_selector;
 pub mod key_selector_builder;
+pub mod ring;
+pub mod sticky;
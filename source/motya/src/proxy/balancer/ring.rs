@@ -0,0 +1,400 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+};
+
+use crc32fast::Hasher;
+use pingora_load_balancing::Backend;
+use serde::Serialize;
+
+/// Points placed on the ring per unit of weight, same as `pingora-ketama`.
+pub const POINTS_PER_WEIGHT: usize = 160;
+
+/// Size of the crc32 key space.
+const KEY_SPACE: f64 = (u32::MAX as u64 + 1) as f64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingNode {
+    pub addr: SocketAddr,
+    pub weight: usize,
+    /// Overrides the `weight * POINTS_PER_WEIGHT` points of this node.
+    pub vnodes: Option<usize>,
+}
+
+impl RingNode {
+    pub fn points(&self) -> usize {
+        self.vnodes.unwrap_or(self.weight * POINTS_PER_WEIGHT)
+    }
+}
+
+/// Share of the key space owned by one backend.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeShare {
+    pub backend: SocketAddr,
+    pub weight: usize,
+    pub virtual_nodes: usize,
+    /// Fraction of the key space, `0.0..=1.0`.
+    pub share: f64,
+}
+
+/// Keys that would change owner, grouped by the old and the new backend.
+/// `None` stands for a backend that is absent on that side.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Transfer {
+    pub from: Option<SocketAddr>,
+    pub to: Option<SocketAddr>,
+    pub share: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RebalanceReport {
+    /// Fraction of the key space that moves to another backend.
+    pub moved: f64,
+    pub transfers: Vec<Transfer>,
+}
+
+/// Ketama continuum used by the `Ketama` selection.
+///
+/// Points are derived like `pingora-ketama` does, so rings without `vnodes`
+/// overrides place keys exactly as the stock implementation.
+#[derive(Debug, Clone)]
+pub struct KetamaRing {
+    nodes: Vec<RingNode>,
+    /// `(hash, node index)`, sorted by hash.
+    points: Vec<(u32, usize)>,
+}
+
+impl KetamaRing {
+    pub fn new(nodes: Vec<RingNode>) -> Self {
+        let mut points = Vec::new();
+
+        for (idx, node) in nodes.iter().enumerate() {
+            let mut base = Hasher::new();
+            base.update(format!("{}\0{}", node.addr.ip(), node.addr.port()).as_bytes());
+
+            let mut prev_hash: u32 = 0;
+            for _ in 0..node.points() {
+                let mut hasher = base.clone();
+                hasher.update(&prev_hash.to_le_bytes());
+
+                let hash = hasher.finalize();
+                points.push((hash, idx));
+                prev_hash = hash;
+            }
+        }
+
+        points.sort_unstable();
+        points.dedup_by_key(|(hash, _)| *hash);
+
+        Self { nodes, points }
+    }
+
+    pub fn nodes(&self) -> &[RingNode] {
+        &self.nodes
+    }
+
+    /// Backend a raw key lands on.
+    pub fn lookup(&self, key: &[u8]) -> Option<SocketAddr> {
+        self.owner(crc32fast::hash(key))
+    }
+
+    /// Like [`KetamaRing::lookup`], but walks on along the ring past the
+    /// backends `accept` rejects.
+    pub fn lookup_with(
+        &self,
+        key: &[u8],
+        mut accept: impl FnMut(SocketAddr) -> bool,
+    ) -> Option<SocketAddr> {
+        let hash = crc32fast::hash(key);
+        let start = self.points.partition_point(|(point, _)| *point < hash);
+        let mut tried = vec![false; self.nodes.len()];
+
+        for i in 0..self.points.len() {
+            let (_, node) = self.points[(start + i) % self.points.len()];
+
+            if std::mem::replace(&mut tried[node], true) {
+                continue;
+            }
+
+            let addr = self.nodes[node].addr;
+            if accept(addr) {
+                return Some(addr);
+            }
+        }
+
+        None
+    }
+
+    fn owner(&self, hash: u32) -> Option<SocketAddr> {
+        let idx = self.points.partition_point(|(point, _)| *point < hash);
+        let (_, node) = self.points.get(idx).or_else(|| self.points.first())?;
+
+        Some(self.nodes[*node].addr)
+    }
+
+    fn hashes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.points.iter().map(|(hash, _)| *hash)
+    }
+
+    pub fn layout(&self) -> Vec<NodeShare> {
+        let mut owned = vec![0u64; self.nodes.len()];
+        let mut virtual_nodes = vec![0usize; self.nodes.len()];
+
+        for (_, node) in &self.points {
+            virtual_nodes[*node] += 1;
+        }
+
+        let boundaries: Vec<u32> = self.hashes().collect();
+        for (end, len) in segments(&boundaries) {
+            let idx = self.points.partition_point(|(point, _)| *point < end);
+            owned[self.points[idx].1] += len;
+        }
+
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| NodeShare {
+                backend: node.addr,
+                weight: node.weight,
+                virtual_nodes: virtual_nodes[idx],
+                share: owned[idx] as f64 / KEY_SPACE,
+            })
+            .collect()
+    }
+
+    /// Estimates how keys move when this ring is replaced by `proposed`.
+    pub fn rebalance(&self, proposed: &KetamaRing) -> RebalanceReport {
+        let mut boundaries: Vec<u32> = self.hashes().chain(proposed.hashes()).collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let mut transfers: BTreeMap<(Option<SocketAddr>, Option<SocketAddr>), u64> =
+            BTreeMap::new();
+
+        for (end, len) in segments(&boundaries) {
+            let from = self.owner(end);
+            let to = proposed.owner(end);
+
+            if from != to {
+                *transfers.entry((from, to)).or_default() += len;
+            }
+        }
+
+        let moved = transfers.values().sum::<u64>() as f64 / KEY_SPACE;
+        let mut transfers: Vec<Transfer> = transfers
+            .into_iter()
+            .map(|((from, to), len)| Transfer {
+                from,
+                to,
+                share: len as f64 / KEY_SPACE,
+            })
+            .collect();
+        transfers.sort_by(|a, b| b.share.total_cmp(&a.share));
+
+        RebalanceReport { moved, transfers }
+    }
+}
+
+/// `Ketama` selection over a [`KetamaRing`], unlike `pingora-ketama` it
+/// honors per-backend `vnodes`.
+pub struct KetamaBalancer {
+    ring: KetamaRing,
+    backends: HashMap<SocketAddr, Backend>,
+}
+
+impl KetamaBalancer {
+    /// `backends` pairs every backend with its `vnodes` override.
+    pub fn new(backends: impl IntoIterator<Item = (Backend, Option<usize>)>) -> Self {
+        let mut nodes = Vec::new();
+        let mut by_addr = HashMap::new();
+
+        for (backend, vnodes) in backends {
+            let Some(addr) = backend.addr.as_inet().copied() else {
+                continue;
+            };
+
+            nodes.push(RingNode {
+                addr,
+                weight: backend.weight,
+                vnodes,
+            });
+            by_addr.insert(addr, backend);
+        }
+
+        Self {
+            ring: KetamaRing::new(nodes),
+            backends: by_addr,
+        }
+    }
+
+    pub fn ring(&self) -> &KetamaRing {
+        &self.ring
+    }
+
+    pub fn backend(&self, addr: &SocketAddr) -> Option<&Backend> {
+        self.backends.get(addr)
+    }
+
+    pub fn select(&self, key: &[u8]) -> Option<Backend> {
+        let addr = self.ring.lookup(key)?;
+        self.backends.get(&addr).cloned()
+    }
+
+    /// Like [`KetamaBalancer::select`], falling over to the next backend on
+    /// the ring while `accept` rejects them.
+    pub fn select_with(
+        &self,
+        key: &[u8],
+        mut accept: impl FnMut(&Backend) -> bool,
+    ) -> Option<Backend> {
+        let addr = self.ring.lookup_with(key, |addr| {
+            self.backends.get(&addr).is_some_and(&mut accept)
+        })?;
+        self.backends.get(&addr).cloned()
+    }
+}
+
+/// Splits the key space at sorted, unique `boundaries` into `(end, length)`
+/// segments. Every key of a segment lands on the point at `end`; the keys past
+/// the last boundary wrap around and are counted in the first segment.
+fn segments(boundaries: &[u32]) -> impl Iterator<Item = (u32, u64)> + '_ {
+    let first = boundaries.first().copied().unwrap_or_default() as u64;
+    let last = boundaries.last().copied().unwrap_or_default() as u64;
+
+    boundaries.iter().enumerate().map(move |(i, end)| {
+        let len = match i {
+            0 => first + 1 + (u32::MAX as u64 - last),
+            _ => *end as u64 - boundaries[i - 1] as u64,
+        };
+        (*end, len)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(addr: &str, weight: usize) -> RingNode {
+        RingNode {
+            addr: addr.parse().unwrap(),
+            weight,
+            vnodes: None,
+        }
+    }
+
+    fn ring(nodes: &[(&str, usize)]) -> KetamaRing {
+        KetamaRing::new(nodes.iter().map(|(a, w)| node(a, *w)).collect())
+    }
+
+    #[test]
+    fn test_layout_covers_key_space() {
+        let ring = ring(&[("10.0.0.1:80", 1), ("10.0.0.2:80", 1), ("10.0.0.3:80", 2)]);
+        let layout = ring.layout();
+
+        let total: f64 = layout.iter().map(|n| n.share).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        assert_eq!(layout[0].virtual_nodes, POINTS_PER_WEIGHT);
+        assert_eq!(layout[2].virtual_nodes, 2 * POINTS_PER_WEIGHT);
+        // The double weight node should own roughly half of the keys.
+        assert!(layout[2].share > 0.4 && layout[2].share < 0.6);
+    }
+
+    #[test]
+    fn test_lookup_agrees_with_layout() {
+        let ring = ring(&[("10.0.0.1:80", 1), ("10.0.0.2:80", 1)]);
+        let layout = ring.layout();
+
+        let samples = 20_000u64;
+        let first = (0..samples)
+            .filter(|i| ring.lookup(&i.to_le_bytes()) == Some(layout[0].backend))
+            .count();
+
+        let observed = first as f64 / samples as f64;
+        assert!((observed - layout[0].share).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_rebalance_adding_backend() {
+        let current = ring(&[("10.0.0.1:80", 1), ("10.0.0.2:80", 1), ("10.0.0.3:80", 1)]);
+        let proposed = ring(&[
+            ("10.0.0.1:80", 1),
+            ("10.0.0.2:80", 1),
+            ("10.0.0.3:80", 1),
+            ("10.0.0.4:80", 1),
+        ]);
+
+        let report = current.rebalance(&proposed);
+        let added: SocketAddr = "10.0.0.4:80".parse().unwrap();
+
+        // Only keys taken over by the new backend move.
+        assert!(report.transfers.iter().all(|t| t.to == Some(added)));
+        let new_share = proposed.layout()[3].share;
+        assert!((report.moved - new_share).abs() < 1e-9);
+        assert!(report.moved > 0.15 && report.moved < 0.35);
+    }
+
+    #[test]
+    fn test_rebalance_identical_rings() {
+        let current = ring(&[("10.0.0.1:80", 1), ("10.0.0.2:80", 1)]);
+        let report = current.rebalance(&current.clone());
+
+        assert_eq!(report.moved, 0.0);
+        assert!(report.transfers.is_empty());
+    }
+
+    #[test]
+    fn test_rebalance_to_empty_ring() {
+        let current = ring(&[("10.0.0.1:80", 1)]);
+        let report = current.rebalance(&ring(&[]));
+
+        assert_eq!(report.moved, 1.0);
+        assert_eq!(report.transfers[0].to, None);
+    }
+
+    #[test]
+    fn test_vnodes_override_weight() {
+        let mut light = node("10.0.0.1:80", 4);
+        light.vnodes = Some(40);
+        let ring = KetamaRing::new(vec![light, node("10.0.0.2:80", 1)]);
+
+        let layout = ring.layout();
+        assert_eq!(layout[0].virtual_nodes, 40);
+        assert_eq!(layout[1].virtual_nodes, POINTS_PER_WEIGHT);
+        assert!(layout[0].share < layout[1].share);
+    }
+
+    #[test]
+    fn test_balancer_selects_ring_owner() {
+        let backends = ["10.0.0.1:80", "10.0.0.2:80"]
+            .map(|addr| (Backend::new_with_weight(addr, 1).unwrap(), None));
+        let balancer = KetamaBalancer::new(backends);
+
+        for key in 0u64..100 {
+            let key = key.to_le_bytes();
+            let selected = balancer.select(&key).unwrap();
+
+            assert_eq!(
+                selected.addr.as_inet().copied(),
+                balancer.ring().lookup(&key)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lookup_with_skips_rejected() {
+        let ring = ring(&[("10.0.0.1:80", 1), ("10.0.0.2:80", 1), ("10.0.0.3:80", 1)]);
+
+        for key in 0u64..100 {
+            let key = key.to_le_bytes();
+            let owner = ring.lookup(&key).unwrap();
+
+            assert_eq!(ring.lookup_with(&key, |_| true), Some(owner));
+
+            let fallback = ring.lookup_with(&key, |addr| addr != owner).unwrap();
+            assert_ne!(fallback, owner);
+        }
+
+        assert_eq!(ring.lookup_with(b"key", |_| false), None);
+    }
+}
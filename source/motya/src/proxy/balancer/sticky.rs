@@ -0,0 +1,122 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use motya_config::internal::StickyCookieConfig;
+
+use crate::proxy::balancer::{affinity_keys::AffinityKeyStore, key_selector::KeySourceContext};
+
+/// `CookieSticky` selection, pins a client to the backend that served its
+/// first response with a cookie signed by the [`AffinityKeyStore`].
+pub struct CookieSticky {
+    cookie: String,
+    ttl: Duration,
+    keys: Arc<AffinityKeyStore>,
+}
+
+impl CookieSticky {
+    pub fn new(config: StickyCookieConfig, keys: Arc<AffinityKeyStore>) -> Self {
+        Self {
+            cookie: config.cookie,
+            ttl: config.ttl,
+            keys,
+        }
+    }
+
+    /// Backend named by the affinity cookie, if it carries a valid signature.
+    pub fn pinned<C: KeySourceContext>(&self, ctx: &C) -> Option<SocketAddr> {
+        let signed = ctx.get_cookie(&self.cookie)?;
+        self.keys.verify(signed)?.parse().ok()
+    }
+
+    /// `Set-Cookie` value pinning the client to `backend` for `ttl`, scoped to
+    /// `path`. `None` when the request is already pinned to it.
+    pub fn set_cookie<C: KeySourceContext>(
+        &self,
+        ctx: &C,
+        backend: SocketAddr,
+        path: &str,
+    ) -> Option<String> {
+        if self.pinned(ctx) == Some(backend) {
+            return None;
+        }
+
+        Some(format!(
+            "{}={}; Max-Age={}; Path={}; HttpOnly",
+            self.cookie,
+            self.keys.sign(&backend.to_string()),
+            self.ttl.as_secs(),
+            path
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::IpAddr};
+
+    use http::uri::PathAndQuery;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockContext {
+        cookies: HashMap<String, String>,
+    }
+
+    impl KeySourceContext for MockContext {
+        fn get_header(&self, _name: &str) -> Option<&str> {
+            None
+        }
+        fn get_cookie(&self, name: &str) -> Option<&str> {
+            self.cookies.get(name).map(|s| s.as_str())
+        }
+        fn get_ip(&self) -> Option<IpAddr> {
+            None
+        }
+        fn get_path(&self) -> &PathAndQuery {
+            static PATH: PathAndQuery = PathAndQuery::from_static("/");
+            &PATH
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_cookie_and_pinned() {
+        let dir = tempdir().unwrap();
+        let keys = AffinityKeyStore::open(dir.path(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        let sticky = CookieSticky::new(
+            StickyCookieConfig {
+                cookie: "river_affinity".into(),
+                ttl: Duration::from_secs(3600),
+            },
+            Arc::new(keys),
+        );
+        let backend: SocketAddr = "10.0.0.2:80".parse().unwrap();
+        let mut ctx = MockContext::default();
+
+        assert_eq!(sticky.pinned(&ctx), None);
+
+        let cookie = sticky.set_cookie(&ctx, backend, "/api").unwrap();
+        assert!(cookie.ends_with("; Max-Age=3600; Path=/api; HttpOnly"));
+
+        let value = cookie
+            .strip_prefix("river_affinity=")
+            .and_then(|rest| rest.split(';').next())
+            .unwrap();
+        ctx.cookies.insert("river_affinity".into(), value.into());
+
+        assert_eq!(sticky.pinned(&ctx), Some(backend));
+        assert_eq!(sticky.set_cookie(&ctx, backend, "/api"), None);
+        assert!(sticky
+            .set_cookie(&ctx, "10.0.0.1:80".parse().unwrap(), "/api")
+            .is_some());
+
+        // A client can't pick its backend by editing the cookie.
+        ctx.cookies.insert(
+            "river_affinity".into(),
+            value.replace("10.0.0.2", "10.0.0.1"),
+        );
+        assert_eq!(sticky.pinned(&ctx), None);
+    }
+}
@@ -0,0 +1,81 @@
+use http::header::CONTENT_LENGTH;
+use pingora::{Error, ErrorType, Result};
+use pingora_http::RequestHeader;
+
+fn too_large() -> Box<Error> {
+    Error::explain(
+        ErrorType::HTTPStatus(413),
+        "request body exceeds max-request-body-bytes",
+    )
+}
+
+/// The `max-request-body-bytes` of a request, counting the body as it is
+/// read from downstream rather than buffering it.
+#[derive(Debug)]
+pub struct RequestBodyLimit {
+    max: usize,
+    seen: usize,
+}
+
+impl RequestBodyLimit {
+    pub fn new(max: usize) -> Self {
+        Self { max, seen: 0 }
+    }
+
+    /// Refuses a request announcing a larger `Content-Length`, before any of
+    /// its body is read.
+    pub fn check_header(&self, req: &RequestHeader) -> Result<()> {
+        let announced = req
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<usize>().ok());
+
+        match announced {
+            Some(len) if len > self.max => Err(too_large()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts a chunk read from downstream, refusing the request once the
+    /// body went past the limit.
+    pub fn take(&mut self, len: usize) -> Result<()> {
+        self.seen += len;
+
+        if self.seen > self.max {
+            return Err(too_large());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_length() {
+        let limit = RequestBodyLimit::new(10);
+
+        let mut req = RequestHeader::build("POST", b"/upload", None).unwrap();
+        assert!(limit.check_header(&req).is_ok());
+
+        req.insert_header("Content-Length", "10").unwrap();
+        assert!(limit.check_header(&req).is_ok());
+
+        req.insert_header("Content-Length", "11").unwrap();
+        let err = limit.check_header(&req).unwrap_err();
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(413));
+    }
+
+    #[test]
+    fn test_streamed_body() {
+        let mut limit = RequestBodyLimit::new(10);
+        assert!(limit.take(6).is_ok());
+        assert!(limit.take(4).is_ok());
+
+        let err = limit.take(1).unwrap_err();
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(413));
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::OnceLock;
+
+use bytes::{Bytes, BytesMut};
+use http::StatusCode;
+use pingora::prelude::HttpPeer;
+use pingora_http::ResponseHeader;
+use prometheus::{register_int_gauge, IntGauge};
+
+use motya_config::common_types::connectors::BufferingConfig;
+
+fn occupancy_metric() -> &'static IntGauge {
+    static METRIC: OnceLock<IntGauge> = OnceLock::new();
+
+    METRIC.get_or_init(|| {
+        register_int_gauge!(
+            "motya_response_buffer_bytes",
+            "Upstream response bytes held by route buffering across all connections"
+        )
+        .expect("metric should register once")
+    })
+}
+
+/// The `buffering` of a route, reading upstream responses ahead of slow
+/// clients so the backend is freed early.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseBuffering {
+    max_bytes: usize,
+    low_watermark: Option<usize>,
+    upstream_recv_bytes: Option<usize>,
+}
+
+impl From<BufferingConfig> for ResponseBuffering {
+    fn from(value: BufferingConfig) -> Self {
+        Self {
+            max_bytes: value.max_bytes,
+            low_watermark: value.low_watermark,
+            upstream_recv_bytes: value.upstream_recv_bytes,
+        }
+    }
+}
+
+impl ResponseBuffering {
+    /// Returns the buffer of this response, `None` for upgraded connections,
+    /// which have no end to wait for.
+    pub fn start(&self, header: &ResponseHeader) -> Option<ResponseBuffer> {
+        (header.status != StatusCode::SWITCHING_PROTOCOLS).then(|| ResponseBuffer {
+            max_bytes: self.max_bytes,
+            low_watermark: self.low_watermark,
+            held: BytesMut::new(),
+            streaming: false,
+        })
+    }
+
+    /// Sizes the receive buffer of the upstream socket, bounding how far the
+    /// upstream gets ahead in the kernel.
+    pub fn apply(&self, peer: &mut HttpPeer) {
+        if let Some(bytes) = self.upstream_recv_bytes {
+            peer.options.tcp_recv_buf = Some(bytes);
+        }
+    }
+}
+
+/// Holds the body of a response back until it is complete or reached
+/// `max_bytes`. Past it, the rest streams through, or with a `low_watermark`
+/// everything above that is sent and holding goes on.
+#[derive(Debug)]
+pub struct ResponseBuffer {
+    max_bytes: usize,
+    low_watermark: Option<usize>,
+    held: BytesMut,
+    streaming: bool,
+}
+
+impl ResponseBuffer {
+    pub fn filter(&mut self, body: &mut Option<Bytes>, end_of_stream: bool) {
+        if self.streaming {
+            return;
+        }
+
+        let before = self.held.len();
+        if let Some(chunk) = body.take() {
+            self.held.extend_from_slice(&chunk);
+        }
+
+        let release = if end_of_stream {
+            self.streaming = true;
+            self.held.len()
+        } else if self.held.len() >= self.max_bytes {
+            match self.low_watermark {
+                Some(low) => self.held.len() - low,
+                None => {
+                    self.streaming = true;
+                    self.held.len()
+                }
+            }
+        } else {
+            0
+        };
+
+        let released = self.held.split_to(release).freeze();
+        occupancy_metric().add(self.held.len() as i64 - before as i64);
+
+        // An empty chunk would read as the end of a chunked body.
+        *body = (!released.is_empty()).then_some(released);
+    }
+}
+
+impl Drop for ResponseBuffer {
+    fn drop(&mut self) {
+        occupancy_metric().sub(self.held.len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_bytes: usize, low_watermark: Option<usize>) -> BufferingConfig {
+        BufferingConfig {
+            max_bytes,
+            low_watermark,
+            upstream_recv_bytes: None,
+        }
+    }
+
+    fn buffering(max_bytes: usize, low_watermark: Option<usize>) -> ResponseBuffer {
+        let header = ResponseHeader::build(200, None).unwrap();
+        ResponseBuffering::from(config(max_bytes, low_watermark))
+            .start(&header)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_holds_until_end() {
+        let mut buffer = buffering(1024, None);
+
+        let mut body = Some(Bytes::from_static(b"hello "));
+        buffer.filter(&mut body, false);
+        assert_eq!(body, None);
+
+        let mut body = Some(Bytes::from_static(b"world"));
+        buffer.filter(&mut body, true);
+        assert_eq!(body, Some(Bytes::from_static(b"hello world")));
+    }
+
+    #[test]
+    fn test_streams_past_max_bytes() {
+        let mut buffer = buffering(8, None);
+
+        let mut body = Some(Bytes::from_static(b"hello"));
+        buffer.filter(&mut body, false);
+        assert_eq!(body, None);
+
+        let mut body = Some(Bytes::from_static(b" world"));
+        buffer.filter(&mut body, false);
+        assert_eq!(body, Some(Bytes::from_static(b"hello world")));
+
+        let mut body = Some(Bytes::from_static(b"!"));
+        buffer.filter(&mut body, true);
+        assert_eq!(body, Some(Bytes::from_static(b"!")));
+    }
+
+    #[test]
+    fn test_upgrade_is_not_buffered() {
+        let header = ResponseHeader::build(101, None).unwrap();
+        let buffering = ResponseBuffering::from(config(1024, None));
+        assert!(buffering.start(&header).is_none());
+    }
+
+    #[test]
+    fn test_low_watermark() {
+        let mut buffer = buffering(8, Some(3));
+
+        let mut body = Some(Bytes::from_static(b"hello world"));
+        buffer.filter(&mut body, false);
+        assert_eq!(body, Some(Bytes::from_static(b"hello wo")));
+
+        // Holding goes on past the first release.
+        let mut body = Some(Bytes::from_static(b"!"));
+        buffer.filter(&mut body, false);
+        assert_eq!(body, None);
+
+        let mut body = None;
+        buffer.filter(&mut body, true);
+        assert_eq!(body, Some(Bytes::from_static(b"rld!")));
+    }
+
+    #[test]
+    fn test_upstream_recv_bytes() {
+        let buffering = ResponseBuffering::from(BufferingConfig {
+            upstream_recv_bytes: Some(65536),
+            ..config(1024, None)
+        });
+
+        let mut peer = HttpPeer::new("127.0.0.1:80", false, String::new());
+        buffering.apply(&mut peer);
+        assert_eq!(peer.options.tcp_recv_buf, Some(65536));
+    }
+}
@@ -0,0 +1,235 @@
+use std::time::SystemTime;
+
+use http::{header, Method, StatusCode};
+use pingora::Result;
+use pingora_http::{RequestHeader, ResponseHeader};
+
+/// Validators remembered for a cached response.
+///
+/// They are enough to answer a conditional request with `304 Not Modified`
+/// without contacting the upstream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conditional {
+    /// The client copy is still fresh, answer with `304 Not Modified`.
+    NotModified,
+    /// The request has to be served normally.
+    Forward,
+}
+
+/// Returns `true` when the request carries `If-None-Match` or `If-Modified-Since`.
+///
+/// On a cache miss such requests are forwarded untouched, so the upstream can
+/// answer the conditional on its own.
+pub fn is_conditional(req: &RequestHeader) -> bool {
+    req.headers.contains_key(header::IF_NONE_MATCH)
+        || req.headers.contains_key(header::IF_MODIFIED_SINCE)
+}
+
+impl Validators {
+    pub fn from_response(resp: &ResponseHeader) -> Self {
+        let etag = resp
+            .headers
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string());
+
+        let last_modified = resp
+            .headers
+            .get(header::LAST_MODIFIED)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok());
+
+        Self {
+            etag,
+            last_modified,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Evaluates the preconditions of `req` against the cached validators
+    /// following RFC 9110 section 13.2.2.
+    ///
+    /// `If-None-Match` takes precedence: when present, `If-Modified-Since` is ignored.
+    pub fn evaluate(&self, req: &RequestHeader) -> Conditional {
+        if req.method != Method::GET && req.method != Method::HEAD {
+            return Conditional::Forward;
+        }
+
+        if let Some(inm) = req.headers.get(header::IF_NONE_MATCH) {
+            let Some(etag) = self.etag.as_deref() else {
+                return Conditional::Forward;
+            };
+            let Ok(inm) = inm.to_str() else {
+                return Conditional::Forward;
+            };
+
+            return if etag_list_matches(inm, etag) {
+                Conditional::NotModified
+            } else {
+                Conditional::Forward
+            };
+        }
+
+        if let Some(ims) = req.headers.get(header::IF_MODIFIED_SINCE) {
+            let since = ims
+                .to_str()
+                .ok()
+                .and_then(|v| httpdate::parse_http_date(v).ok());
+
+            if let (Some(since), Some(last_modified)) = (since, self.last_modified) {
+                if last_modified <= since {
+                    return Conditional::NotModified;
+                }
+            }
+        }
+
+        Conditional::Forward
+    }
+
+    /// Builds the `304 Not Modified` answer carrying the cached validators.
+    pub fn not_modified(&self) -> Result<ResponseHeader> {
+        let mut resp = ResponseHeader::build(StatusCode::NOT_MODIFIED, Some(2))?;
+
+        if let Some(etag) = &self.etag {
+            resp.insert_header(header::ETAG, etag.as_str())?;
+        }
+        if let Some(last_modified) = self.last_modified {
+            resp.insert_header(
+                header::LAST_MODIFIED,
+                httpdate::fmt_http_date(last_modified),
+            )?;
+        }
+
+        Ok(resp)
+    }
+}
+
+/// Weak comparison of an `If-None-Match` list against a stored entity tag.
+fn etag_list_matches(list: &str, etag: &str) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+
+    let stored = strip_weak(etag);
+    list.split(',')
+        .map(|candidate| strip_weak(candidate.trim()))
+        .any(|candidate| candidate == stored)
+}
+
+fn strip_weak(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    fn request(method: &str, headers: &[(&'static str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build(method, b"/index.html", None).unwrap();
+        for (name, value) in headers {
+            req.insert_header(*name, *value).unwrap();
+        }
+        req
+    }
+
+    fn validators() -> Validators {
+        Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        }
+    }
+
+    #[test]
+    fn test_from_response() {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("ETag", "W/\"abc\"").unwrap();
+        resp.insert_header("Last-Modified", "Tue, 14 Nov 2023 22:13:20 GMT")
+            .unwrap();
+
+        let v = Validators::from_response(&resp);
+        assert_eq!(v.etag.as_deref(), Some("W/\"abc\""));
+        assert_eq!(
+            v.last_modified,
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+
+        let empty = ResponseHeader::build(200, None).unwrap();
+        assert!(Validators::from_response(&empty).is_empty());
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let v = validators();
+
+        let hit = request("GET", &[("If-None-Match", "\"other\", W/\"v1\"")]);
+        assert_eq!(v.evaluate(&hit), Conditional::NotModified);
+
+        let any = request("HEAD", &[("If-None-Match", "*")]);
+        assert_eq!(v.evaluate(&any), Conditional::NotModified);
+
+        let miss = request("GET", &[("If-None-Match", "\"v2\"")]);
+        assert_eq!(v.evaluate(&miss), Conditional::Forward);
+
+        let post = request("POST", &[("If-None-Match", "\"v1\"")]);
+        assert_eq!(v.evaluate(&post), Conditional::Forward);
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let v = validators();
+
+        let fresh = request(
+            "GET",
+            &[("If-Modified-Since", "Tue, 14 Nov 2023 22:13:20 GMT")],
+        );
+        assert_eq!(v.evaluate(&fresh), Conditional::NotModified);
+
+        let stale = request(
+            "GET",
+            &[("If-Modified-Since", "Mon, 13 Nov 2023 22:13:20 GMT")],
+        );
+        assert_eq!(v.evaluate(&stale), Conditional::Forward);
+
+        let garbage = request("GET", &[("If-Modified-Since", "yesterday")]);
+        assert_eq!(v.evaluate(&garbage), Conditional::Forward);
+    }
+
+    #[test]
+    fn test_if_none_match_takes_precedence() {
+        let v = validators();
+
+        let req = request(
+            "GET",
+            &[
+                ("If-None-Match", "\"v2\""),
+                ("If-Modified-Since", "Tue, 14 Nov 2023 22:13:20 GMT"),
+            ],
+        );
+        assert_eq!(v.evaluate(&req), Conditional::Forward);
+        assert!(is_conditional(&req));
+        assert!(!is_conditional(&request("GET", &[])));
+    }
+
+    #[test]
+    fn test_not_modified_response() {
+        let resp = validators().not_modified().unwrap();
+
+        assert_eq!(resp.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers.get("ETag").unwrap(), "\"v1\"");
+        assert_eq!(
+            resp.headers.get("Last-Modified").unwrap(),
+            "Tue, 14 Nov 2023 22:13:20 GMT"
+        );
+    }
+}
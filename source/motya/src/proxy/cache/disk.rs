@@ -0,0 +1,583 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
+use xxhash_rust::xxh64::xxh64;
+
+const META_MAGIC: &[u8; 8] = b"MOTYAC01";
+const BODY_EXT: &str = "body";
+const META_EXT: &str = "meta";
+const TMP_EXT: &str = "tmp";
+
+/// Metadata persisted next to every cached body.
+///
+/// The `.meta` file is written last and acts as the commit marker of an entry:
+/// a body without a matching meta file is considered garbage left by a crash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskEntryMeta {
+    pub key: String,
+    pub size: u64,
+    pub stored_at: u64,
+}
+
+impl DiskEntryMeta {
+    fn encode(&self) -> Vec<u8> {
+        let key = self.key.as_bytes();
+        let mut out = Vec::with_capacity(META_MAGIC.len() + 24 + key.len());
+        out.extend_from_slice(META_MAGIC);
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.stored_at.to_le_bytes());
+        out.extend_from_slice(&(key.len() as u64).to_le_bytes());
+        out.extend_from_slice(key);
+        out
+    }
+
+    fn decode(raw: &[u8]) -> Option<Self> {
+        let rest = raw.strip_prefix(&META_MAGIC[..])?;
+        let read_u64 = |bytes: &[u8], at: usize| -> Option<u64> {
+            Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+        };
+
+        let size = read_u64(rest, 0)?;
+        let stored_at = read_u64(rest, 8)?;
+        let key_len = read_u64(rest, 16)? as usize;
+        let key = rest.get(24..24 + key_len)?;
+
+        if rest.len() != 24 + key_len {
+            return None;
+        }
+
+        Some(Self {
+            key: String::from_utf8(key.to_vec()).ok()?,
+            size,
+            stored_at,
+        })
+    }
+}
+
+struct IndexEntry {
+    key: String,
+    size: u64,
+    stored_at: u64,
+    last_access: u64,
+}
+
+#[derive(Default)]
+struct DiskIndex {
+    entries: HashMap<u64, IndexEntry>,
+    lru: BTreeMap<u64, u64>,
+    used: u64,
+    clock: u64,
+}
+
+impl DiskIndex {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn touch(&mut self, id: u64) {
+        let tick = self.tick();
+        if let Some(entry) = self.entries.get_mut(&id) {
+            self.lru.remove(&entry.last_access);
+            entry.last_access = tick;
+            self.lru.insert(tick, id);
+        }
+    }
+
+    fn insert(&mut self, id: u64, meta: DiskEntryMeta) {
+        self.remove(id);
+        let tick = self.tick();
+        self.used += meta.size;
+        self.lru.insert(tick, id);
+        self.entries.insert(
+            id,
+            IndexEntry {
+                key: meta.key,
+                size: meta.size,
+                stored_at: meta.stored_at,
+                last_access: tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, id: u64) -> Option<IndexEntry> {
+        let entry = self.entries.remove(&id)?;
+        self.lru.remove(&entry.last_access);
+        self.used -= entry.size;
+        Some(entry)
+    }
+
+    fn oldest(&self) -> Option<u64> {
+        self.lru.values().next().copied()
+    }
+}
+
+/// Persistent, size-bounded cache tier.
+///
+/// Every entry lives in two files named after the hash of its key: the body and
+/// a small metadata record. Writes go through temporary files that are renamed
+/// into place, so a crash leaves either the previous state or a complete entry.
+/// [`DiskCache::open`] scans the directory to rebuild the index, which lets the
+/// cache survive restarts.
+///
+/// With a TTL, entries stored longer ago are dropped when they are looked up,
+/// on [`DiskCache::purge_expired`] and by the startup scan.
+pub struct DiskCache {
+    root: PathBuf,
+    max_size: u64,
+    ttl: Option<Duration>,
+    index: Mutex<DiskIndex>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl DiskCache {
+    /// Opens the cache at `root`, creating the directory when missing.
+    ///
+    /// Incomplete entries and leftover temporary files are removed, and the
+    /// least recently stored entries are evicted if the directory is over `max_size`.
+    pub async fn open(root: impl Into<PathBuf>, max_size: u64) -> io::Result<Self> {
+        Self::open_with_ttl(root, max_size, None).await
+    }
+
+    /// Same as [`DiskCache::open`], expiring entries `ttl` after they were stored.
+    pub async fn open_with_ttl(
+        root: impl Into<PathBuf>,
+        max_size: u64,
+        ttl: Option<Duration>,
+    ) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root).await?;
+
+        let cache = Self {
+            root,
+            max_size,
+            ttl,
+            index: Mutex::new(DiskIndex::default()),
+        };
+
+        cache.scan().await?;
+
+        Ok(cache)
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    fn is_expired(&self, stored_at: u64, now: u64) -> bool {
+        self.ttl
+            .is_some_and(|ttl| now.saturating_sub(stored_at) >= ttl.as_secs())
+    }
+
+    pub async fn used(&self) -> u64 {
+        self.index.lock().await.used
+    }
+
+    pub async fn len(&self) -> usize {
+        self.index.lock().await.entries.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    pub async fn contains(&self, key: &str) -> bool {
+        let index = self.index.lock().await;
+        index
+            .entries
+            .get(&Self::id(key))
+            .is_some_and(|entry| entry.key == key)
+    }
+
+    pub async fn get(&self, key: &str) -> io::Result<Option<Bytes>> {
+        let id = Self::id(key);
+
+        {
+            let mut index = self.index.lock().await;
+            match index.entries.get(&id) {
+                Some(entry) if entry.key == key => {
+                    if self.is_expired(entry.stored_at, now_secs()) {
+                        index.remove(id);
+                        self.delete_files(id).await;
+                        return Ok(None);
+                    }
+                    index.touch(id);
+                }
+                _ => return Ok(None),
+            }
+        }
+
+        match fs::read(self.body_path(id)).await {
+            Ok(body) => Ok(Some(Bytes::from(body))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                tracing::warn!("Disk cache entry '{key}' vanished from disk, dropping it");
+                self.index.lock().await.remove(id);
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Stores `body` under `key`, evicting least recently used entries to make room.
+    ///
+    /// Returns `false` without touching the disk when the body alone exceeds the cap.
+    pub async fn put(&self, key: &str, body: &[u8]) -> io::Result<bool> {
+        let size = body.len() as u64;
+        if size > self.max_size {
+            return Ok(false);
+        }
+
+        let id = Self::id(key);
+        let meta = DiskEntryMeta {
+            key: key.to_string(),
+            size,
+            stored_at: now_secs(),
+        };
+
+        let mut index = self.index.lock().await;
+
+        if index.remove(id).is_some() {
+            self.delete_files(id).await;
+        }
+
+        while index.used + size > self.max_size {
+            let Some(victim) = index.oldest() else {
+                break;
+            };
+            index.remove(victim);
+            self.delete_files(victim).await;
+        }
+
+        self.write_atomic(&self.body_path(id), body).await?;
+        self.write_atomic(&self.meta_path(id), &meta.encode())
+            .await?;
+
+        index.insert(id, meta);
+
+        Ok(true)
+    }
+
+    /// Drops every expired entry, returning how many were removed.
+    pub async fn purge_expired(&self) -> usize {
+        let now = now_secs();
+        let mut index = self.index.lock().await;
+
+        let expired: Vec<u64> = index
+            .entries
+            .iter()
+            .filter(|(_, entry)| self.is_expired(entry.stored_at, now))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &expired {
+            index.remove(*id);
+            self.delete_files(*id).await;
+        }
+
+        expired.len()
+    }
+
+    pub async fn remove(&self, key: &str) -> bool {
+        let id = Self::id(key);
+        let mut index = self.index.lock().await;
+
+        match index.entries.get(&id) {
+            Some(entry) if entry.key == key => {
+                index.remove(id);
+                self.delete_files(id).await;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    async fn scan(&self) -> io::Result<()> {
+        let mut found = Vec::new();
+        let mut dir = fs::read_dir(&self.root).await?;
+
+        while let Some(item) = dir.next_entry().await? {
+            let path = item.path();
+            let ext = path.extension().and_then(|e| e.to_str());
+
+            match ext {
+                Some(TMP_EXT) => {
+                    let _ = fs::remove_file(&path).await;
+                }
+                Some(META_EXT) => {
+                    let Some(id) = Self::id_from_path(&path) else {
+                        continue;
+                    };
+                    match self.load_entry(id, &path).await {
+                        Some(meta) if self.is_expired(meta.stored_at, now_secs()) => {
+                            self.delete_files(id).await;
+                        }
+                        Some(meta) => {
+                            let mtime = item
+                                .metadata()
+                                .await
+                                .and_then(|m| m.modified())
+                                .unwrap_or(UNIX_EPOCH);
+                            found.push((mtime, id, meta));
+                        }
+                        None => {
+                            tracing::warn!("Dropping broken disk cache entry {}", path.display());
+                            self.delete_files(id).await;
+                        }
+                    }
+                }
+                Some(BODY_EXT) => {
+                    if let Some(id) = Self::id_from_path(&path) {
+                        if fs::metadata(self.meta_path(id)).await.is_err() {
+                            let _ = fs::remove_file(&path).await;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Oldest first, so that the rebuilt access order follows the on-disk write order.
+        found.sort_by_key(|(mtime, _, _)| *mtime);
+
+        let mut index = self.index.lock().await;
+        for (_, id, meta) in found {
+            index.insert(id, meta);
+        }
+
+        while index.used > self.max_size {
+            let Some(victim) = index.oldest() else {
+                break;
+            };
+            index.remove(victim);
+            self.delete_files(victim).await;
+        }
+
+        tracing::info!(
+            "Disk cache at {} restored {} entries ({} bytes)",
+            self.root.display(),
+            index.entries.len(),
+            index.used
+        );
+
+        Ok(())
+    }
+
+    async fn load_entry(&self, id: u64, meta_path: &Path) -> Option<DiskEntryMeta> {
+        let raw = fs::read(meta_path).await.ok()?;
+        let meta = DiskEntryMeta::decode(&raw)?;
+
+        if Self::id(&meta.key) != id {
+            return None;
+        }
+
+        let body = fs::metadata(self.body_path(id)).await.ok()?;
+        (body.len() == meta.size).then_some(meta)
+    }
+
+    async fn write_atomic(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{TMP_EXT}"));
+
+        let mut file = fs::File::create(&tmp).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        fs::rename(&tmp, path).await
+    }
+
+    async fn delete_files(&self, id: u64) {
+        // Meta first: once it is gone the entry is no longer considered valid.
+        let _ = fs::remove_file(self.meta_path(id)).await;
+        let _ = fs::remove_file(self.body_path(id)).await;
+    }
+
+    fn id(key: &str) -> u64 {
+        xxh64(key.as_bytes(), 0)
+    }
+
+    fn id_from_path(path: &Path) -> Option<u64> {
+        let stem = path.file_stem()?.to_str()?;
+        u64::from_str_radix(stem, 16).ok()
+    }
+
+    fn body_path(&self, id: u64) -> PathBuf {
+        self.root.join(format!("{id:016x}.{BODY_EXT}"))
+    }
+
+    fn meta_path(&self, id: u64) -> PathBuf {
+        self.root.join(format!("{id:016x}.{META_EXT}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_meta_roundtrip() {
+        let meta = DiskEntryMeta {
+            key: "GET /assets/app.js".to_string(),
+            size: 42,
+            stored_at: 1_700_000_000,
+        };
+
+        assert_eq!(DiskEntryMeta::decode(&meta.encode()), Some(meta.clone()));
+
+        let mut truncated = meta.encode();
+        truncated.pop();
+        assert_eq!(DiskEntryMeta::decode(&truncated), None);
+    }
+
+    #[tokio::test]
+    async fn test_put_get_remove() {
+        let dir = tempdir().unwrap();
+        let cache = DiskCache::open(dir.path(), 1024).await.unwrap();
+
+        assert!(cache.put("a", b"hello").await.unwrap());
+        assert_eq!(
+            cache.get("a").await.unwrap().as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(cache.used().await, 5);
+
+        assert!(cache.remove("a").await);
+        assert_eq!(cache.get("a").await.unwrap(), None);
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let dir = tempdir().unwrap();
+        let cache = DiskCache::open(dir.path(), 10).await.unwrap();
+
+        cache.put("a", b"aaaa").await.unwrap();
+        cache.put("b", b"bbbb").await.unwrap();
+        cache.get("a").await.unwrap();
+        cache.put("c", b"cccc").await.unwrap();
+
+        assert!(cache.contains("a").await);
+        assert!(!cache.contains("b").await);
+        assert!(cache.contains("c").await);
+        assert!(cache.used().await <= 10);
+
+        assert!(!cache.put("huge", &[0; 11]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_startup_scan_restores_and_cleans() {
+        let dir = tempdir().unwrap();
+
+        {
+            let cache = DiskCache::open(dir.path(), 1024).await.unwrap();
+            cache.put("kept", b"payload").await.unwrap();
+        }
+
+        std::fs::write(dir.path().join("deadbeef.tmp"), b"partial").unwrap();
+        std::fs::write(dir.path().join("00000000000000ff.body"), b"orphan").unwrap();
+
+        let cache = DiskCache::open(dir.path(), 1024).await.unwrap();
+
+        assert_eq!(cache.len().await, 1);
+        assert_eq!(
+            cache.get("kept").await.unwrap().as_deref(),
+            Some(&b"payload"[..])
+        );
+        assert!(!dir.path().join("deadbeef.tmp").exists());
+        assert!(!dir.path().join("00000000000000ff.body").exists());
+    }
+
+    #[tokio::test]
+    async fn test_ttl_expiry() {
+        let dir = tempdir().unwrap();
+        let ttl = Some(Duration::from_secs(3600));
+        let cache = DiskCache::open_with_ttl(dir.path(), 1024, ttl)
+            .await
+            .unwrap();
+
+        cache.put("fresh", b"fresh").await.unwrap();
+        cache.put("stale", b"stale").await.unwrap();
+        cache.put("gone", b"gone").await.unwrap();
+
+        {
+            let mut index = cache.index.lock().await;
+            for key in ["stale", "gone"] {
+                index
+                    .entries
+                    .get_mut(&DiskCache::id(key))
+                    .unwrap()
+                    .stored_at -= 3600;
+            }
+        }
+
+        assert_eq!(cache.get("stale").await.unwrap(), None);
+        assert_eq!(cache.purge_expired().await, 1);
+        assert!(!cache.contains("gone").await);
+        assert_eq!(
+            cache.get("fresh").await.unwrap().as_deref(),
+            Some(&b"fresh"[..])
+        );
+        assert_eq!(cache.used().await, 5);
+    }
+
+    #[tokio::test]
+    async fn test_startup_scan_drops_expired() {
+        let dir = tempdir().unwrap();
+
+        {
+            let cache = DiskCache::open(dir.path(), 1024).await.unwrap();
+            cache.put("old", b"old").await.unwrap();
+            cache.put("new", b"new").await.unwrap();
+        }
+
+        let old = DiskEntryMeta {
+            key: "old".to_string(),
+            size: 3,
+            stored_at: now_secs() - 7200,
+        };
+        let meta_path = dir
+            .path()
+            .join(format!("{:016x}.{META_EXT}", DiskCache::id("old")));
+        std::fs::write(&meta_path, old.encode()).unwrap();
+
+        let cache = DiskCache::open_with_ttl(dir.path(), 1024, Some(Duration::from_secs(3600)))
+            .await
+            .unwrap();
+
+        assert!(!cache.contains("old").await);
+        assert!(cache.contains("new").await);
+        assert!(!meta_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_startup_scan_enforces_smaller_cap() {
+        let dir = tempdir().unwrap();
+
+        {
+            let cache = DiskCache::open(dir.path(), 1024).await.unwrap();
+            cache.put("a", b"aaaa").await.unwrap();
+            cache.put("b", b"bbbb").await.unwrap();
+        }
+
+        let cache = DiskCache::open(dir.path(), 4).await.unwrap();
+        assert_eq!(cache.len().await, 1);
+        assert!(cache.used().await <= 4);
+    }
+}
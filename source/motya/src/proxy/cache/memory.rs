@@ -0,0 +1,317 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use http::StatusCode;
+use pingora_http::{RequestHeader, ResponseHeader};
+
+use crate::proxy::cache::{
+    cache_key,
+    conditional::Validators,
+    freshness,
+    vary::{Variance, VaryRules},
+    CachedResponse,
+};
+
+struct Variant {
+    variance: String,
+    response: CachedResponse,
+}
+
+struct Entry {
+    /// The response the variances were computed against, its `Vary` is the
+    /// same for every variant.
+    vary_source: ResponseHeader,
+    variants: Vec<Variant>,
+    size: usize,
+    last_access: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    lru: BTreeMap<u64, String>,
+    used: usize,
+    clock: u64,
+}
+
+impl Index {
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        let tick = self.clock;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.lru.remove(&entry.last_access);
+            entry.last_access = tick;
+            self.lru.insert(tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.last_access);
+        self.used -= entry.size;
+        Some(entry)
+    }
+}
+
+/// Size-bounded cache of `GET` responses kept in memory.
+///
+/// Responses are keyed by method, host and path, and by the request headers
+/// named in their `Vary`. They are kept for their `s-maxage` or `max-age` up
+/// to `ttl`, or `ttl` when they set neither, and the least recently used keys
+/// are evicted once `max_size` bytes of bodies are held.
+pub struct MemoryCache {
+    max_size: usize,
+    ttl: Duration,
+    vary: VaryRules,
+    index: Mutex<Index>,
+}
+
+impl MemoryCache {
+    pub fn new(max_size: usize, ttl: Duration, vary: VaryRules) -> Self {
+        Self {
+            max_size,
+            ttl,
+            vary,
+            index: Mutex::new(Index::default()),
+        }
+    }
+
+    /// The fresh response cached for `req`.
+    pub fn lookup(&self, req: &RequestHeader) -> Option<CachedResponse> {
+        self.lookup_at(req, Instant::now())
+    }
+
+    fn lookup_at(&self, req: &RequestHeader, now: Instant) -> Option<CachedResponse> {
+        let key = cache_key(req)?;
+        let mut index = self.index.lock().expect("cache index poisoned");
+
+        let entry = index.entries.get_mut(&key)?;
+        let Variance::Key(variance) = self.vary.variance(&entry.vary_source, req) else {
+            return None;
+        };
+
+        entry
+            .variants
+            .retain(|variant| variant.response.expires > now);
+        let found = entry
+            .variants
+            .iter()
+            .find(|variant| variant.variance == variance)
+            .map(|variant| variant.response.clone());
+
+        if entry.variants.is_empty() {
+            index.remove(&key);
+        } else if found.is_some() {
+            index.touch(&key);
+        }
+
+        found
+    }
+
+    /// Stores the answer to `req` if it may be cached, returning whether it was.
+    pub fn store(&self, req: &RequestHeader, resp: &ResponseHeader, body: Bytes) -> bool {
+        self.store_at(req, resp, body, Instant::now())
+    }
+
+    fn store_at(
+        &self,
+        req: &RequestHeader,
+        resp: &ResponseHeader,
+        body: Bytes,
+        now: Instant,
+    ) -> bool {
+        let Some(key) = cache_key(req) else {
+            return false;
+        };
+        if resp.status != StatusCode::OK || body.len() > self.max_size {
+            return false;
+        }
+        let Some(ttl) = freshness(resp, self.ttl) else {
+            return false;
+        };
+        let Variance::Key(variance) = self.vary.variance(resp, req) else {
+            return false;
+        };
+
+        let variant = Variant {
+            variance,
+            response: CachedResponse {
+                header: resp.clone(),
+                validators: Validators::from_response(resp),
+                body,
+                expires: now + ttl,
+            },
+        };
+
+        let mut index = self.index.lock().expect("cache index poisoned");
+
+        // A response varying on other headers replaces every variant.
+        let mut variants = match index.remove(&key) {
+            Some(entry)
+                if self.vary.varied_headers(&entry.vary_source)
+                    == self.vary.varied_headers(resp) =>
+            {
+                entry.variants
+            }
+            _ => Vec::new(),
+        };
+        variants.retain(|existing| {
+            existing.variance != variant.variance && existing.response.expires > now
+        });
+        variants.push(variant);
+
+        let mut size: usize = variants.iter().map(|v| v.response.body.len()).sum();
+        while size > self.max_size {
+            size -= variants.remove(0).response.body.len();
+        }
+
+        while index.used + size > self.max_size {
+            let Some((_, oldest)) = index.lru.pop_first() else {
+                break;
+            };
+            if let Some(entry) = index.entries.remove(&oldest) {
+                index.used -= entry.size;
+            }
+        }
+
+        index.clock += 1;
+        let tick = index.clock;
+        index.used += size;
+        index.lru.insert(tick, key.clone());
+        index.entries.insert(
+            key,
+            Entry {
+                vary_source: resp.clone(),
+                variants,
+                size,
+                last_access: tick,
+            },
+        );
+
+        true
+    }
+
+    /// Bytes of bodies held.
+    pub fn used(&self) -> usize {
+        self.index.lock().expect("cache index poisoned").used
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Keys held, each with one or more variants.
+    pub fn len(&self) -> usize {
+        self.index
+            .lock()
+            .expect("cache index poisoned")
+            .entries
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use motya_config::common_types::cache::VaryConfig;
+
+    use super::*;
+
+    fn request(path: &str, encoding: &str) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", path.as_bytes(), None).unwrap();
+        req.insert_header("Host", "example.com").unwrap();
+        req.insert_header("Accept-Encoding", encoding).unwrap();
+        req
+    }
+
+    fn response(cache_control: Option<&str>) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("ETag", "\"v1\"").unwrap();
+        resp.insert_header("Vary", "Accept-Encoding").unwrap();
+        if let Some(value) = cache_control {
+            resp.insert_header("Cache-Control", value).unwrap();
+        }
+        resp
+    }
+
+    fn cache(max_size: usize) -> MemoryCache {
+        MemoryCache::new(
+            max_size,
+            Duration::from_secs(60),
+            VaryRules::from(VaryConfig::default()),
+        )
+    }
+
+    #[test]
+    fn test_store_and_lookup() {
+        let cache = cache(1024);
+        let req = request("/a", "gzip");
+
+        assert!(cache.lookup(&req).is_none());
+        assert!(cache.store(&req, &response(None), Bytes::from_static(b"hello")));
+
+        let hit = cache.lookup(&req).unwrap();
+        assert_eq!(hit.body, "hello");
+        assert_eq!(hit.validators.etag.as_deref(), Some("\"v1\""));
+
+        // Another encoding is another variant, another path another key.
+        assert!(cache.lookup(&request("/a", "br")).is_none());
+        assert!(cache.lookup(&request("/b", "gzip")).is_none());
+
+        let mut post = request("/a", "gzip");
+        post.set_method(Method::POST);
+        assert!(cache.lookup(&post).is_none());
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let cache = cache(1024);
+        let req = request("/a", "gzip");
+        let body = Bytes::from_static(b"hello");
+
+        for value in ["no-store", "private, max-age=60", "max-age=0"] {
+            assert!(!cache.store(&req, &response(Some(value)), body.clone()));
+        }
+
+        let now = Instant::now();
+        let resp = response(Some("max-age=600, s-maxage=10"));
+        assert!(cache.store_at(&req, &resp, body, now));
+
+        let later = |secs| now + Duration::from_secs(secs);
+        assert!(cache.lookup_at(&req, later(9)).is_some());
+        assert!(cache.lookup_at(&req, later(10)).is_none());
+        assert_eq!(cache.used(), 0);
+
+        // Longer lifetimes are cut to the ttl.
+        let resp = response(Some("max-age=3600"));
+        assert!(cache.store_at(&req, &resp, Bytes::from_static(b"hello"), now));
+        assert!(cache.lookup_at(&req, later(59)).is_some());
+        assert!(cache.lookup_at(&req, later(60)).is_none());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = cache(10);
+        let resp = response(None);
+
+        cache.store(&request("/a", "gzip"), &resp, Bytes::from_static(b"aaaa"));
+        cache.store(&request("/b", "gzip"), &resp, Bytes::from_static(b"bbbb"));
+        cache.lookup(&request("/a", "gzip"));
+        cache.store(&request("/c", "gzip"), &resp, Bytes::from_static(b"cccc"));
+
+        assert!(cache.lookup(&request("/a", "gzip")).is_some());
+        assert!(cache.lookup(&request("/b", "gzip")).is_none());
+        assert!(cache.lookup(&request("/c", "gzip")).is_some());
+        assert_eq!(cache.used(), 8);
+
+        assert!(!cache.store(&request("/d", "gzip"), &resp, Bytes::from(vec![0; 11])));
+    }
+}
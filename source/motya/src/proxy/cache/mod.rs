@@ -0,0 +1,613 @@
+//! `cache` of a route: `GET` responses kept and served to later requests
+//! without asking the upstream.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock, Weak,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Bytes, BytesMut};
+use http::{header, HeaderName, Method, StatusCode};
+use miette::miette;
+use pingora::Result;
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde_json::{json, Value};
+
+use motya_config::common_types::cache::{CacheConfig, CacheStorage};
+
+use crate::proxy::cache::{
+    conditional::{is_conditional, Conditional, Validators},
+    disk::DiskCache,
+    memory::MemoryCache,
+    vary::VaryRules,
+};
+
+pub mod conditional;
+pub mod disk;
+pub mod memory;
+pub mod vary;
+
+fn lookups() -> &'static IntCounterVec {
+    static LOOKUPS: OnceLock<IntCounterVec> = OnceLock::new();
+
+    LOOKUPS.get_or_init(|| {
+        register_int_counter_vec!(
+            "motya_cache_lookups_total",
+            "Lookups of the response caches of the routes, by 'hit' or 'miss'",
+            &["result"]
+        )
+        .expect("metric should register once")
+    })
+}
+
+/// A response held by the cache.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub header: ResponseHeader,
+    pub body: Bytes,
+    pub validators: Validators,
+    expires: Instant,
+}
+
+/// The `cache` of a route.
+pub struct RouteCache {
+    storage: Storage,
+    /// Largest body stored, bodies growing past it are not read any further.
+    max_body: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Usage of a route cache since it was opened, for the admin API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub storage: &'static str,
+    /// Keys stored, a disk cache keeps one more for every response varying
+    /// on request headers.
+    pub entries: usize,
+    pub used_bytes: u64,
+    pub max_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+enum Storage {
+    Memory(MemoryCache),
+    Disk {
+        disk: Arc<DiskCache>,
+        ttl: Duration,
+        vary: VaryRules,
+    },
+}
+
+impl RouteCache {
+    /// Opens the storage of the cache, reading back the entries a disk cache
+    /// kept from earlier runs.
+    pub async fn open(config: CacheConfig) -> miette::Result<Self> {
+        let vary = VaryRules::from(config.vary);
+
+        Ok(match config.storage {
+            CacheStorage::Memory { max_bytes } => Self {
+                storage: Storage::Memory(MemoryCache::new(max_bytes, config.ttl, vary)),
+                max_body: max_bytes,
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            },
+            CacheStorage::Disk { path, max_bytes } => {
+                let disk = open_disk(path, max_bytes, config.ttl).await?;
+
+                Self {
+                    storage: Storage::Disk {
+                        disk,
+                        ttl: config.ttl,
+                        vary,
+                    },
+                    max_body: usize::try_from(max_bytes).unwrap_or(usize::MAX),
+                    hits: AtomicU64::new(0),
+                    misses: AtomicU64::new(0),
+                }
+            }
+        })
+    }
+
+    /// The fresh response cached for `req`, counted as a hit or a miss.
+    pub async fn lookup(&self, req: &RequestHeader) -> Option<CachedResponse> {
+        let found = match &self.storage {
+            Storage::Memory(memory) => memory.lookup(req),
+            Storage::Disk { disk, vary, .. } => lookup_disk(disk, vary, req).await,
+        };
+
+        let (result, counter) = if found.is_some() {
+            ("hit", &self.hits)
+        } else {
+            ("miss", &self.misses)
+        };
+        lookups().with_label_values(&[result]).inc();
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        found
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let (storage, entries, used_bytes, max_bytes) = match &self.storage {
+            Storage::Memory(memory) => (
+                "memory",
+                memory.len(),
+                memory.used() as u64,
+                memory.max_size() as u64,
+            ),
+            Storage::Disk { disk, .. } => {
+                ("disk", disk.len().await, disk.used().await, disk.max_size())
+            }
+        };
+
+        CacheStats {
+            storage,
+            entries,
+            used_bytes,
+            max_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Starts reading `resp` into the cache if it may be stored.
+    pub fn start(&self, req: &RequestHeader, resp: &ResponseHeader) -> Option<CacheFill> {
+        cache_key(req)?;
+
+        if resp.status != StatusCode::OK || resp.headers.contains_key(header::SET_COOKIE) {
+            return None;
+        }
+        freshness(resp, Duration::MAX)?;
+
+        Some(CacheFill {
+            request: req.clone(),
+            response: resp.clone(),
+            body: BytesMut::new(),
+            max_body: self.max_body,
+        })
+    }
+
+    /// Stores a response read in full.
+    pub fn store(&self, mut fill: CacheFill) {
+        let body = std::mem::take(&mut fill.body).freeze();
+
+        match &self.storage {
+            Storage::Memory(memory) => {
+                memory.store(&fill.request, &fill.response, body);
+            }
+            Storage::Disk { disk, ttl, vary } => store_disk(disk, *ttl, vary, fill, body),
+        }
+    }
+}
+
+/// Opens the disk cache at `path`, or shares the one routes already use.
+///
+/// Sections inherit the `cache` of their parent, and a reload builds the
+/// routes again while the old ones still serve requests, so several routes
+/// can name the same directory. They all go through one index of it.
+///
+/// Files older than `ttl` are deleted every `ttl`, until the last route
+/// using the cache was dropped.
+async fn open_disk(path: PathBuf, max_bytes: u64, ttl: Duration) -> miette::Result<Arc<DiskCache>> {
+    static OPEN: OnceLock<tokio::sync::Mutex<HashMap<PathBuf, Weak<DiskCache>>>> = OnceLock::new();
+
+    let mut open = OPEN.get_or_init(Default::default).lock().await;
+    open.retain(|_, disk| disk.strong_count() > 0);
+
+    if let Some(disk) = open.get(&path).and_then(Weak::upgrade) {
+        if disk.max_size() == max_bytes && disk.ttl() == Some(ttl) {
+            return Ok(disk);
+        }
+    }
+
+    let disk = DiskCache::open_with_ttl(&path, max_bytes, Some(ttl))
+        .await
+        .map(Arc::new)
+        .map_err(|e| miette!("Failed to open the disk cache at {path:?}: {e}"))?;
+    open.insert(path, Arc::downgrade(&disk));
+
+    let weak = Arc::downgrade(&disk);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(ttl);
+        // The first tick completes at once, opening purged the directory.
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let Some(disk) = weak.upgrade() else {
+                return;
+            };
+            disk.purge_expired().await;
+        }
+    });
+
+    Ok(disk)
+}
+
+/// Seconds since the Unix epoch, the clock of the entries on disk.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Reads the response for `req` from disk.
+///
+/// The entry under the key of the request lists the headers its responses
+/// vary on, and the response itself is stored under the key followed by the
+/// variance of the request.
+async fn lookup_disk(
+    disk: &DiskCache,
+    vary: &VaryRules,
+    req: &RequestHeader,
+) -> Option<CachedResponse> {
+    let key = cache_key(req)?;
+
+    let read = |key: String| async move {
+        disk.get(&key).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to read '{key}' from the disk cache: {e}");
+            None
+        })
+    };
+
+    let names = read(key.clone()).await?;
+    let names = std::str::from_utf8(&names)
+        .ok()?
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    let record = read(format!("{key}\n{}", vary.key(&names, req))).await?;
+    decode_record(&record, now_secs())
+}
+
+/// Writes the response to disk in the background, see [`lookup_disk`].
+fn store_disk(
+    disk: &Arc<DiskCache>,
+    ttl: Duration,
+    vary: &VaryRules,
+    fill: CacheFill,
+    body: Bytes,
+) {
+    let Some(key) = cache_key(&fill.request) else {
+        return;
+    };
+    let Some(fresh) = freshness(&fill.response, ttl) else {
+        return;
+    };
+    let Some(names) = vary.varied_headers(&fill.response) else {
+        return;
+    };
+
+    let variant = format!("{key}\n{}", vary.key(&names, &fill.request));
+    let record = encode_record(&fill.response, &body, now_secs() + fresh.as_secs());
+    let names = names
+        .iter()
+        .map(HeaderName::as_str)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let disk = disk.clone();
+    tokio::spawn(async move {
+        let stored = match disk.put(&key, names.as_bytes()).await {
+            Ok(_) => disk.put(&variant, &record).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = stored {
+            tracing::warn!("Failed to write '{key}' to the disk cache: {e}");
+        }
+    });
+}
+
+/// A response as it is kept on disk: the length of its metadata as 4 bytes
+/// little endian, the metadata as JSON, then the body.
+fn encode_record(resp: &ResponseHeader, body: &[u8], expires: u64) -> Vec<u8> {
+    let headers: Vec<_> = resp
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+    let meta = json!({
+        "status": resp.status.as_u16(),
+        "headers": headers,
+        "expires": expires,
+    })
+    .to_string();
+
+    let mut record = Vec::with_capacity(4 + meta.len() + body.len());
+    record.extend_from_slice(&(meta.len() as u32).to_le_bytes());
+    record.extend_from_slice(meta.as_bytes());
+    record.extend_from_slice(body);
+    record
+}
+
+/// The response of a record written by [`encode_record`], `None` when it is
+/// damaged or expired at `now`.
+fn decode_record(record: &[u8], now: u64) -> Option<CachedResponse> {
+    let len = u32::from_le_bytes(record.get(..4)?.try_into().ok()?) as usize;
+    let meta: Value = serde_json::from_slice(record.get(4..4 + len)?).ok()?;
+
+    let expires = meta["expires"].as_u64()?;
+    if expires <= now {
+        return None;
+    }
+
+    let status = u16::try_from(meta["status"].as_u64()?).ok()?;
+    let mut header = ResponseHeader::build(status, None).ok()?;
+    for pair in meta["headers"].as_array()? {
+        let name = HeaderName::from_bytes(pair[0].as_str()?.as_bytes()).ok()?;
+        header.append_header(name, pair[1].as_str()?).ok()?;
+    }
+
+    Some(CachedResponse {
+        validators: Validators::from_response(&header),
+        header,
+        body: Bytes::copy_from_slice(&record[4 + len..]),
+        expires: Instant::now() + Duration::from_secs(expires - now),
+    })
+}
+
+/// A response read into the cache as it is sent to the client.
+pub struct CacheFill {
+    request: RequestHeader,
+    response: ResponseHeader,
+    body: BytesMut,
+    max_body: usize,
+}
+
+impl CacheFill {
+    /// Adds a piece of the body as it was sent to the client, `false` once
+    /// the body grew too large to be stored.
+    pub fn push(&mut self, chunk: &[u8]) -> bool {
+        if self.body.len() + chunk.len() > self.max_body {
+            return false;
+        }
+
+        self.body.extend_from_slice(chunk);
+        true
+    }
+}
+
+/// Answers the request with a cached response, or with `304 Not Modified`
+/// when the client's copy matches it.
+pub async fn respond(session: &mut Session, cached: CachedResponse) -> Result<()> {
+    let req = session.req_header();
+    if is_conditional(req) && cached.validators.evaluate(req) == Conditional::NotModified {
+        let response = cached.validators.not_modified()?;
+
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(Bytes::new(), true)
+            .await?;
+
+        return Ok(());
+    }
+
+    let mut response = cached.header;
+    // The body is sent in one piece now, whatever the upstream did.
+    response.remove_header(&header::TRANSFER_ENCODING);
+    response.insert_header(header::CONTENT_LENGTH, cached.body.len().to_string())?;
+
+    session
+        .downstream_session
+        .write_response_header(Box::new(response))
+        .await?;
+    session
+        .downstream_session
+        .write_response_body(cached.body, true)
+        .await?;
+
+    Ok(())
+}
+
+/// `GET host path`, `None` for requests that are never cached.
+fn cache_key(req: &RequestHeader) -> Option<String> {
+    if req.method != Method::GET
+        || req.headers.contains_key(header::AUTHORIZATION)
+        || req.headers.contains_key(header::UPGRADE)
+    {
+        return None;
+    }
+
+    let host = req
+        .uri
+        .host()
+        .or_else(|| req.headers.get(header::HOST)?.to_str().ok())
+        .unwrap_or_default();
+    let path = req.uri.path_and_query().map_or("/", |path| path.as_str());
+
+    Some(format!("GET {host} {path}"))
+}
+
+/// How long `resp` may be served from a shared cache, its `s-maxage` or
+/// `max-age` up to `ttl`, or `ttl` when it sets neither. `None` when it must
+/// not be stored.
+fn freshness(resp: &ResponseHeader, ttl: Duration) -> Option<Duration> {
+    let mut max_age = None;
+    let mut s_maxage = None;
+
+    for value in resp.headers.get_all(header::CACHE_CONTROL) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+
+        for directive in value.split(',').map(str::trim) {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" | "no-cache" | "private" => return None,
+                "max-age" => max_age = arg.and_then(|arg| arg.parse().ok()),
+                "s-maxage" => s_maxage = arg.and_then(|arg| arg.parse().ok()),
+                _ => {}
+            }
+        }
+    }
+
+    match s_maxage.or(max_age) {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs).min(ttl)),
+        None => Some(ttl),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::cache::VaryConfig;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn request(upgrade: bool) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/a", None).unwrap();
+        req.insert_header("Host", "example.com").unwrap();
+        if upgrade {
+            req.insert_header("Upgrade", "websocket").unwrap();
+        }
+        req
+    }
+
+    #[tokio::test]
+    async fn test_fill_and_lookup() {
+        let cache = RouteCache::open(CacheConfig {
+            storage: CacheStorage::Memory { max_bytes: 8 },
+            ttl: Duration::from_secs(60),
+            vary: Default::default(),
+        })
+        .await
+        .unwrap();
+        let resp = ResponseHeader::build(200, None).unwrap();
+
+        assert!(cache.start(&request(true), &resp).is_none());
+
+        let mut with_cookie = resp.clone();
+        with_cookie.insert_header("Set-Cookie", "id=1").unwrap();
+        assert!(cache.start(&request(false), &with_cookie).is_none());
+
+        let mut fill = cache.start(&request(false), &resp).unwrap();
+        assert!(fill.push(b"hello"));
+        assert!(!fill.push(b" world"));
+
+        let mut fill = cache.start(&request(false), &resp).unwrap();
+        assert!(fill.push(b"hello"));
+        cache.store(fill);
+
+        let hit = cache.lookup(&request(false)).await.unwrap();
+        assert_eq!(hit.body, "hello");
+
+        assert_eq!(
+            cache.stats().await,
+            CacheStats {
+                storage: "memory",
+                entries: 1,
+                used_bytes: 5,
+                max_bytes: 8,
+                hits: 1,
+                misses: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vary_rules() {
+        let cache = RouteCache::open(CacheConfig {
+            storage: CacheStorage::Memory { max_bytes: 1024 },
+            ttl: Duration::from_secs(60),
+            vary: VaryConfig {
+                ignore: vec![header::USER_AGENT],
+                buckets: Vec::new(),
+            },
+        })
+        .await
+        .unwrap();
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Vary", "User-Agent").unwrap();
+
+        let with_agent = |agent: &str| {
+            let mut req = request(false);
+            req.insert_header("User-Agent", agent).unwrap();
+            req
+        };
+
+        let mut fill = cache.start(&with_agent("curl/8"), &resp).unwrap();
+        assert!(fill.push(b"hello"));
+        cache.store(fill);
+
+        assert!(cache.lookup(&with_agent("Mozilla/5.0")).await.is_some());
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("ETag", "\"v1\"").unwrap();
+        resp.insert_header("Content-Type", "text/css").unwrap();
+
+        let record = encode_record(&resp, b"body {}", 1_000);
+
+        let cached = decode_record(&record, 999).unwrap();
+        assert_eq!(cached.header.status, StatusCode::OK);
+        assert_eq!(cached.header.headers["content-type"], "text/css");
+        assert_eq!(cached.validators.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(cached.body, "body {}");
+
+        assert!(decode_record(&record, 1_000).is_none());
+        assert!(decode_record(&record[..10], 999).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disk_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let config = CacheConfig {
+            storage: CacheStorage::Disk {
+                path: dir.path().to_path_buf(),
+                max_bytes: 1024,
+            },
+            ttl: Duration::from_secs(60),
+            vary: Default::default(),
+        };
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Vary", "Accept-Encoding").unwrap();
+
+        let cache = RouteCache::open(config.clone()).await.unwrap();
+        let mut fill = cache.start(&request(false), &resp).unwrap();
+        assert!(fill.push(b"hello"));
+        cache.store(fill);
+
+        // Stores are written in the background.
+        for _ in 0..50 {
+            if cache.lookup(&request(false)).await.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(cache);
+
+        let reopened = RouteCache::open(config).await.unwrap();
+        let hit = reopened.lookup(&request(false)).await.unwrap();
+        assert_eq!(hit.body, "hello");
+        assert_eq!(hit.header.headers["vary"], "Accept-Encoding");
+
+        let mut gzip = request(false);
+        gzip.insert_header("Accept-Encoding", "gzip").unwrap();
+        assert!(reopened.lookup(&gzip).await.is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+
+use http::{header, HeaderName};
+use pingora_http::{RequestHeader, ResponseHeader};
+
+use motya_config::common_types::cache::VaryConfig;
+
+use crate::proxy::negotiation::accepted_token;
+
+/// Secondary cache key derived from a response's `Vary` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Variance {
+    /// `Vary: *`, the response must not be served from cache.
+    Uncacheable,
+    /// Canonical representation of the varied request headers.
+    /// Empty when the response does not vary on anything relevant.
+    Key(String),
+}
+
+/// Normalization rules applied while computing a [`Variance`].
+#[derive(Debug, Clone, Default)]
+pub struct VaryRules {
+    ignore: HashSet<HeaderName>,
+    buckets: HashMap<HeaderName, Vec<String>>,
+}
+
+impl From<VaryConfig> for VaryRules {
+    fn from(value: VaryConfig) -> Self {
+        Self {
+            ignore: value.ignore.into_iter().collect(),
+            buckets: value
+                .buckets
+                .into_iter()
+                .map(|bucket| (bucket.header, bucket.values))
+                .collect(),
+        }
+    }
+}
+
+impl VaryRules {
+    /// Request headers named by `resp`'s `Vary`, minus the ignored ones,
+    /// sorted and deduplicated. Returns `None` for `Vary: *`.
+    pub fn varied_headers(&self, resp: &ResponseHeader) -> Option<Vec<HeaderName>> {
+        let mut names = Vec::new();
+
+        for value in resp.headers.get_all(header::VARY) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+
+            for token in value.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+                if token == "*" {
+                    return None;
+                }
+                if let Ok(name) = HeaderName::from_bytes(token.as_bytes()) {
+                    if !self.ignore.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+
+        names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        names.dedup();
+
+        Some(names)
+    }
+
+    /// Computes the variance of `req` for the cached response `resp`.
+    ///
+    /// Two requests with the same variance can be served the same cached response.
+    pub fn variance(&self, resp: &ResponseHeader, req: &RequestHeader) -> Variance {
+        let Some(names) = self.varied_headers(resp) else {
+            return Variance::Uncacheable;
+        };
+
+        Variance::Key(self.key(&names, req))
+    }
+
+    /// Variance of `req` over the request headers `names`, as returned by
+    /// [`VaryRules::varied_headers`].
+    pub fn key(&self, names: &[HeaderName], req: &RequestHeader) -> String {
+        names
+            .iter()
+            .map(|name| format!("{name}={}", self.normalize(name, req)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Normalized value of the request header `name`.
+    ///
+    /// Bucketed headers collapse to one of the configured values, every other
+    /// header is reduced to its trimmed, comma-joined values.
+    pub fn normalize(&self, name: &HeaderName, req: &RequestHeader) -> String {
+        let values = req
+            .headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .map(str::trim);
+
+        let Some(bucket) = self.buckets.get(name) else {
+            return values.collect::<Vec<_>>().join(",");
+        };
+
+        let tokens: Vec<String> = values
+            .flat_map(|v| v.split(','))
+            .filter_map(accepted_token)
+            .collect();
+
+        let wildcard = tokens.iter().any(|t| t == "*");
+
+        bucket
+            .iter()
+            .find(|value| wildcard || tokens.iter().any(|t| t == *value))
+            .or(bucket.last())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use motya_config::common_types::cache::VaryBucket;
+
+    fn rules() -> VaryRules {
+        VaryConfig {
+            ignore: vec![header::USER_AGENT],
+            buckets: vec![VaryBucket {
+                header: header::ACCEPT_ENCODING,
+                values: vec!["br".into(), "gzip".into(), "identity".into()],
+            }],
+        }
+        .into()
+    }
+
+    fn response(vary: &str) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(header::VARY, vary).unwrap();
+        resp
+    }
+
+    fn request(headers: &[(HeaderName, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        for (name, value) in headers {
+            req.append_header(name.clone(), *value).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_varied_headers() {
+        let rules = rules();
+
+        assert_eq!(
+            rules.varied_headers(&response("User-Agent, Accept-Language, accept-encoding")),
+            Some(vec![header::ACCEPT_ENCODING, header::ACCEPT_LANGUAGE])
+        );
+        assert_eq!(rules.varied_headers(&response("Accept, *")), None);
+    }
+
+    #[test]
+    fn test_accept_encoding_buckets() {
+        let rules = rules();
+        let resp = response("Accept-Encoding");
+
+        let chrome = request(&[(header::ACCEPT_ENCODING, "gzip, deflate, br, zstd")]);
+        let curl = request(&[(header::ACCEPT_ENCODING, "deflate, gzip")]);
+        let refused = request(&[(header::ACCEPT_ENCODING, "br;q=0, gzip;q=0.5")]);
+        let none = request(&[]);
+
+        assert_eq!(
+            rules.variance(&resp, &chrome),
+            Variance::Key("accept-encoding=br".into())
+        );
+        assert_eq!(
+            rules.variance(&resp, &curl),
+            Variance::Key("accept-encoding=gzip".into())
+        );
+        assert_eq!(
+            rules.variance(&resp, &refused),
+            Variance::Key("accept-encoding=gzip".into())
+        );
+        assert_eq!(
+            rules.variance(&resp, &none),
+            Variance::Key("accept-encoding=identity".into())
+        );
+    }
+
+    #[test]
+    fn test_ignored_headers_do_not_fragment() {
+        let rules = rules();
+        let resp = response("User-Agent");
+
+        let a = request(&[(header::USER_AGENT, "curl/8.0")]);
+        let b = request(&[(header::USER_AGENT, "Mozilla/5.0")]);
+
+        assert_eq!(rules.variance(&resp, &a), Variance::Key(String::new()));
+        assert_eq!(rules.variance(&resp, &a), rules.variance(&resp, &b));
+    }
+
+    #[test]
+    fn test_plain_headers_vary_by_value() {
+        let rules = VaryRules::default();
+        let resp = response("Accept-Language");
+
+        let en = request(&[(header::ACCEPT_LANGUAGE, " en ")]);
+        let de = request(&[(header::ACCEPT_LANGUAGE, "de")]);
+
+        assert_eq!(
+            rules.variance(&resp, &en),
+            Variance::Key("accept-language=en".into())
+        );
+        assert_ne!(rules.variance(&resp, &en), rules.variance(&resp, &de));
+        assert_eq!(
+            rules.variance(&resp, &request(&[])),
+            Variance::Key("accept-language=".into())
+        );
+    }
+}
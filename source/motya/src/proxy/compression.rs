@@ -0,0 +1,500 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use brotli::CompressorWriter;
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use http::{
+    header::{
+        ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, VARY,
+    },
+    Method, StatusCode,
+};
+use miette::miette;
+use pingora::{Error, ErrorType, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+
+use motya_config::common_types::connectors::{CompressionAlgorithm, CompressionConfig};
+
+use crate::proxy::{filters::types::BodyStream, negotiation::accepted_token};
+
+// Brotli and zstd settings meant for compressing every response anew rather
+// than once ahead of time.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER: usize = 4096;
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compresses the upstream responses of a route for clients that accept it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseCompression {
+    algorithms: Vec<CompressionAlgorithm>,
+    min_size: usize,
+    content_types: Vec<String>,
+    dictionary: Option<Dictionary>,
+}
+
+/// A zstd dictionary, for clients that have it too.
+#[derive(Debug, Clone, PartialEq)]
+struct Dictionary {
+    /// `zstd-dict-NAME`, the encoding clients accept it by.
+    token: String,
+    bytes: Arc<[u8]>,
+}
+
+/// How a response is compressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding<'a> {
+    Algorithm(CompressionAlgorithm),
+    Dictionary(&'a Dictionary),
+}
+
+impl Encoding<'_> {
+    fn token(&self) -> &str {
+        match self {
+            Self::Algorithm(algorithm) => algorithm.token(),
+            Self::Dictionary(dictionary) => &dictionary.token,
+        }
+    }
+
+    fn encoder(&self) -> io::Result<Encoder> {
+        match self {
+            Self::Algorithm(algorithm) => Encoder::new(*algorithm),
+            Self::Dictionary(dictionary) => Encoder::with_dictionary(&dictionary.bytes),
+        }
+    }
+}
+
+impl ResponseCompression {
+    /// The compression of a route, with its dictionary read from
+    /// `dictionaries/NAME.dict` of the state directory.
+    pub async fn open(config: CompressionConfig, state_dir: Option<&Path>) -> miette::Result<Self> {
+        let dictionary = match &config.dictionary {
+            None => None,
+            Some(name) => {
+                let state_dir = state_dir.ok_or_else(|| {
+                    miette!("Compression dictionary '{name}' needs 'system.state-dir'")
+                })?;
+                let path = state_dir.join("dictionaries").join(format!("{name}.dict"));
+
+                let bytes = tokio::fs::read(&path)
+                    .await
+                    .map_err(|e| miette!("Failed to read compression dictionary {path:?}: {e}"))?;
+                if bytes.is_empty() {
+                    return Err(miette!("Compression dictionary {path:?} is empty"));
+                }
+
+                Some(Dictionary {
+                    token: format!("zstd-dict-{name}"),
+                    bytes: bytes.into(),
+                })
+            }
+        };
+
+        Ok(Self::new(config, dictionary))
+    }
+
+    fn new(config: CompressionConfig, dictionary: Option<Dictionary>) -> Self {
+        Self {
+            algorithms: config.algorithms,
+            min_size: config.min_size,
+            content_types: config.content_types,
+            dictionary,
+        }
+    }
+
+    /// Returns the stream compressing this response, with `header` changed to
+    /// match, or `None` when it is sent as it is.
+    pub fn start(
+        &self,
+        req: &RequestHeader,
+        header: &mut ResponseHeader,
+    ) -> Result<Option<Box<dyn BodyStream>>> {
+        if !self.compressible(req, header) {
+            return Ok(None);
+        }
+
+        // Caches must not hand one client's encoding to another.
+        header.append_header(VARY, "Accept-Encoding")?;
+
+        let Some(encoding) = self.negotiate(req) else {
+            return Ok(None);
+        };
+
+        let encoder = encoding
+            .encoder()
+            .map_err(|err| Error::because(ErrorType::InternalError, "compression failed", err))?;
+
+        header.insert_header(CONTENT_ENCODING, encoding.token())?;
+
+        // The compressed body is no longer byte for byte the tagged one.
+        if let Some(etag) = header
+            .headers
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .filter(|etag| etag.starts_with('"'))
+        {
+            let weak = format!("W/{etag}");
+            header.insert_header(ETAG, weak)?;
+        }
+
+        Ok(Some(Box::new(CompressStream {
+            encoder: Some(encoder),
+        })))
+    }
+
+    fn compressible(&self, req: &RequestHeader, header: &ResponseHeader) -> bool {
+        let no_transform = header
+            .headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+
+        let too_small = header
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok()?.parse::<usize>().ok())
+            .is_some_and(|len| len < self.min_size);
+
+        req.method != Method::HEAD
+            && !header.status.is_informational()
+            && !matches!(
+                header.status,
+                StatusCode::NO_CONTENT | StatusCode::PARTIAL_CONTENT | StatusCode::NOT_MODIFIED
+            )
+            && !header.headers.contains_key(CONTENT_ENCODING)
+            && !no_transform
+            && !too_small
+            && self.matches_type(header)
+    }
+
+    fn matches_type(&self, header: &ResponseHeader) -> bool {
+        let Some(media_type) = header
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase())
+        else {
+            return false;
+        };
+
+        self.content_types
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(kind) => media_type
+                    .split_once('/')
+                    .is_some_and(|(other, _)| other == kind),
+                None => *pattern == media_type,
+            })
+    }
+
+    /// The dictionary when the client has it, otherwise the first algorithm of
+    /// the route the client accepts.
+    fn negotiate(&self, req: &RequestHeader) -> Option<Encoding<'_>> {
+        let accepted: Vec<String> = req
+            .headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(accepted_token)
+            .collect();
+
+        if let Some(dictionary) = self
+            .dictionary
+            .as_ref()
+            .filter(|dictionary| accepted.contains(&dictionary.token))
+        {
+            return Some(Encoding::Dictionary(dictionary));
+        }
+
+        self.algorithms
+            .iter()
+            .copied()
+            .find(|algorithm| accepted.iter().any(|token| token == algorithm.token()))
+            .map(Encoding::Algorithm)
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+    /// The zstd context may move between threads but not be shared.
+    Zstd(Mutex<zstd::Encoder<'static, Vec<u8>>>),
+}
+
+impl Encoder {
+    fn new(algorithm: CompressionAlgorithm) -> io::Result<Self> {
+        Ok(match algorithm {
+            CompressionAlgorithm::Gzip => {
+                Self::Gzip(GzEncoder::new(Vec::new(), Compression::default()))
+            }
+            CompressionAlgorithm::Brotli => Self::Brotli(Box::new(CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            ))),
+            CompressionAlgorithm::Zstd => {
+                Self::Zstd(Mutex::new(zstd::Encoder::new(Vec::new(), ZSTD_LEVEL)?))
+            }
+        })
+    }
+
+    fn with_dictionary(dictionary: &[u8]) -> io::Result<Self> {
+        let encoder = zstd::Encoder::with_dictionary(Vec::new(), ZSTD_LEVEL, dictionary)?;
+        Ok(Self::Zstd(Mutex::new(encoder)))
+    }
+
+    /// Compresses `chunk` and takes the output produced so far.
+    fn write(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Self::Brotli(encoder) => {
+                encoder.write_all(chunk)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Self::Zstd(encoder) => {
+                let encoder = encoder.get_mut().unwrap_or_else(PoisonError::into_inner);
+                encoder.write_all(chunk)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    /// Ends the stream and returns the output not taken yet.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Brotli(encoder) => Ok(encoder.into_inner()),
+            Self::Zstd(encoder) => encoder
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner)
+                .finish(),
+        }
+    }
+}
+
+struct CompressStream {
+    /// `None` once the stream was finished.
+    encoder: Option<Encoder>,
+}
+
+impl BodyStream for CompressStream {
+    fn filter(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<Bytes> {
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(Bytes::new());
+        };
+
+        let failed = |err| Error::because(ErrorType::InternalError, "compression failed", err);
+        let mut output = encoder.write(&chunk).map_err(failed)?;
+
+        if end_of_stream {
+            let encoder = self.encoder.take().expect("checked above");
+            output.extend(encoder.finish().map_err(failed)?);
+        }
+
+        Ok(output.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn compression_config() -> CompressionConfig {
+        CompressionConfig {
+            algorithms: vec![CompressionAlgorithm::Zstd, CompressionAlgorithm::Gzip],
+            min_size: 100,
+            content_types: vec!["text/*".into(), "application/json".into()],
+            dictionary: None,
+        }
+    }
+
+    fn compression() -> ResponseCompression {
+        ResponseCompression::new(compression_config(), None)
+    }
+
+    fn request(accept_encoding: &str) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(ACCEPT_ENCODING, accept_encoding).unwrap();
+        req
+    }
+
+    fn response(content_type: &str, len: usize) -> ResponseHeader {
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header.insert_header(CONTENT_TYPE, content_type).unwrap();
+        header.insert_header(CONTENT_LENGTH, len).unwrap();
+        header
+    }
+
+    fn encoding(req: &RequestHeader, mut header: ResponseHeader) -> Option<String> {
+        compression().start(req, &mut header).unwrap()?;
+        Some(
+            header.headers[CONTENT_ENCODING]
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let html = || response("text/html; charset=utf-8", 4096);
+
+        assert_eq!(
+            encoding(&request("gzip, zstd"), html()).as_deref(),
+            Some("zstd")
+        );
+        assert_eq!(
+            encoding(&request("gzip, br"), html()).as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(
+            encoding(&request("zstd;q=0, gzip"), html()).as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(encoding(&request("br"), html()), None);
+    }
+
+    #[test]
+    fn test_skipped_responses() {
+        let req = request("gzip");
+
+        assert_eq!(encoding(&req, response("image/png", 4096)), None);
+        assert_eq!(encoding(&req, response("application/json", 99)), None);
+
+        let mut encoded = response("application/json", 4096);
+        encoded.insert_header(CONTENT_ENCODING, "br").unwrap();
+        assert_eq!(encoding(&req, encoded), None);
+
+        let mut no_transform = response("text/plain", 4096);
+        no_transform
+            .insert_header(CACHE_CONTROL, "public, no-transform")
+            .unwrap();
+        assert_eq!(encoding(&req, no_transform), None);
+
+        let mut head = req.clone();
+        head.set_method(Method::HEAD);
+        assert_eq!(encoding(&head, response("text/plain", 4096)), None);
+    }
+
+    #[test]
+    fn test_header_changes() {
+        let mut header = response("text/plain", 4096);
+        header.insert_header(ETAG, "\"v1\"").unwrap();
+
+        assert!(compression()
+            .start(&request("gzip"), &mut header)
+            .unwrap()
+            .is_some());
+        assert_eq!(header.headers[VARY], "Accept-Encoding");
+        assert_eq!(header.headers[ETAG], "W/\"v1\"");
+
+        // Responses that could be compressed vary even when this one is not.
+        let mut header = response("text/plain", 4096);
+        assert!(compression()
+            .start(&request("identity"), &mut header)
+            .unwrap()
+            .is_none());
+        assert_eq!(header.headers[VARY], "Accept-Encoding");
+    }
+
+    fn compress(algorithm: CompressionAlgorithm, chunks: &[&str]) -> Vec<u8> {
+        let mut stream = CompressStream {
+            encoder: Some(Encoder::new(algorithm).unwrap()),
+        };
+
+        let mut compressed = Vec::new();
+        for chunk in chunks {
+            compressed.extend(
+                stream
+                    .filter(Bytes::from(chunk.to_string()), false)
+                    .unwrap(),
+            );
+        }
+        compressed.extend(stream.filter(Bytes::new(), true).unwrap());
+        compressed
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunks = ["hello ", "streaming ", "world"];
+
+        let mut body = String::new();
+        GzDecoder::new(compress(CompressionAlgorithm::Gzip, &chunks).as_slice())
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "hello streaming world");
+
+        let mut body = String::new();
+        brotli::Decompressor::new(
+            compress(CompressionAlgorithm::Brotli, &chunks).as_slice(),
+            4096,
+        )
+        .read_to_string(&mut body)
+        .unwrap();
+        assert_eq!(body, "hello streaming world");
+
+        let body = zstd::decode_all(compress(CompressionAlgorithm::Zstd, &chunks).as_slice());
+        assert_eq!(body.unwrap(), b"hello streaming world");
+    }
+
+    #[tokio::test]
+    async fn test_dictionary() {
+        let config = CompressionConfig {
+            dictionary: Some("api-v1".into()),
+            ..compression_config()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(ResponseCompression::open(config.clone(), None)
+            .await
+            .is_err());
+        assert!(ResponseCompression::open(config.clone(), Some(dir.path()))
+            .await
+            .is_err());
+
+        // Bytes without the dictionary magic number are taken as raw content.
+        let dictionary = br#"{"id":1,"status":"active","owner":"team-1"}"#.repeat(4);
+        std::fs::create_dir(dir.path().join("dictionaries")).unwrap();
+        std::fs::write(dir.path().join("dictionaries/api-v1.dict"), &dictionary).unwrap();
+
+        let compression = ResponseCompression::open(config, Some(dir.path()))
+            .await
+            .unwrap();
+
+        let mut header = response("application/json", 4096);
+        let mut stream = compression
+            .start(&request("gzip, zstd-dict-api-v1"), &mut header)
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.headers[CONTENT_ENCODING], "zstd-dict-api-v1");
+
+        let body = r#"{"id":7,"status":"active","owner":"team-7"}"#;
+        let compressed = stream.filter(Bytes::from(body), true).unwrap();
+
+        let mut decoder = zstd::Decoder::with_dictionary(compressed.as_ref(), &dictionary).unwrap();
+        let mut decompressed = String::new();
+        decoder.read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, body);
+
+        // Clients without the dictionary get the regular algorithms.
+        let mut header = response("application/json", 4096);
+        compression.start(&request("zstd"), &mut header).unwrap();
+        assert_eq!(header.headers[CONTENT_ENCODING], "zstd");
+    }
+}
@@ -3,7 +3,7 @@ use std::net::IpAddr;
 use http::uri::PathAndQuery;
 use pingora_http::RequestHeader;
 
-use crate::proxy::balancer::key_selector::KeySourceContext;
+use crate::{client_ip, proxy::balancer::key_selector::KeySourceContext};
 use pingora::protocols::l4::socket::SocketAddr;
 
 pub struct SessionInfo<'a> {
@@ -12,7 +12,13 @@ pub struct SessionInfo<'a> {
     pub path: &'a PathAndQuery,
 }
 
-pub struct ContextInfo {}
+#[derive(Default)]
+pub struct ContextInfo {
+    /// A replica refused the connection, `split-by-method` retries on a primary.
+    pub replica_failed: bool,
+    /// Scratch space of the balancing key, kept for the retries of the request.
+    pub key_buffer: Vec<u8>,
+}
 
 impl KeySourceContext for SessionInfo<'_> {
     fn get_path(&self) -> &PathAndQuery {
@@ -43,6 +49,6 @@ impl KeySourceContext for SessionInfo<'_> {
     fn get_ip(&self) -> Option<IpAddr> {
         self.client_addr
             .and_then(|addr| addr.as_inet())
-            .map(|addr| addr.ip())
+            .map(|addr| client_ip::resolve(addr.ip(), self.headers))
     }
 }
@@ -0,0 +1,261 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+};
+
+use bytes::Bytes;
+use http::{
+    header::{
+        ACCEPT_LANGUAGE, CACHE_CONTROL, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE, VARY,
+    },
+    Method, StatusCode,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+use motya_config::common_types::error_pages::ErrorPagesConfig;
+
+/// Error pages of a proxy service, read from its `error-pages` directory at
+/// startup.
+#[derive(Debug)]
+pub struct ErrorPages {
+    /// Pages by status, then by language, `""` for the language-neutral one.
+    pages: HashMap<u16, BTreeMap<String, Bytes>>,
+    default_language: String,
+}
+
+impl ErrorPages {
+    /// Reads `<code>.<lang>.html` and `<code>.html` pages, filling in their
+    /// `{status}` and `{reason}` placeholders.
+    pub fn load(config: &ErrorPagesConfig) -> Result<Self> {
+        let entries = fs::read_dir(&config.dir).map_err(|err| {
+            miette!(
+                "Failed to read error pages from '{}': {err}",
+                config.dir.display()
+            )
+        })?;
+
+        let mut pages: HashMap<u16, BTreeMap<String, Bytes>> = HashMap::new();
+
+        for entry in entries {
+            let path = entry.into_diagnostic()?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let Some((code, language)) = page_name(name) else {
+                tracing::warn!("Ignoring '{}', not an error page", path.display());
+                continue;
+            };
+
+            let template = fs::read_to_string(&path)
+                .map_err(|err| miette!("Failed to read error page '{}': {err}", path.display()))?;
+            let reason = StatusCode::from_u16(code)
+                .ok()
+                .and_then(|status| status.canonical_reason())
+                .unwrap_or_default();
+            let page = template
+                .replace("{status}", &code.to_string())
+                .replace("{reason}", reason);
+
+            pages
+                .entry(code)
+                .or_default()
+                .insert(language, Bytes::from(page));
+        }
+
+        if pages.is_empty() {
+            tracing::warn!("No error pages found in '{}'", config.dir.display());
+        }
+
+        Ok(Self {
+            pages,
+            default_language: config.default_language.clone(),
+        })
+    }
+
+    /// The page for `code` in the language the client prefers, with that
+    /// language, `""` for a language-neutral page.
+    pub fn negotiate(&self, code: u16, accept_language: Option<&str>) -> Option<(&str, &Bytes)> {
+        let pages = self.pages.get(&code)?;
+        let page = |language: &str| pages.get_key_value(language);
+
+        let accepted = accept_language.map(accepted_languages).unwrap_or_default();
+        let preferred = accepted.iter().find_map(|tag| match tag.as_str() {
+            "*" => page(&self.default_language),
+            tag => page(tag).or_else(|| page(tag.split('-').next()?)),
+        });
+
+        preferred
+            .or_else(|| page(&self.default_language))
+            .or_else(|| page(""))
+            .map(|(language, body)| (language.as_str(), body))
+    }
+
+    /// Answers with the page for `code`, returning `false` when there is none.
+    pub async fn respond(&self, session: &mut Session, code: u16) -> bool {
+        let Some((header, body)) = self.response(code, session.req_header()) else {
+            return false;
+        };
+
+        if session.response_written().is_none() {
+            let result = async {
+                session
+                    .write_response_header(Box::new(header), false)
+                    .await?;
+                session.write_response_body(body, true).await
+            }
+            .await;
+
+            if let Err(err) = result {
+                tracing::error!("Failed to send error page to downstream: {err}");
+            }
+        }
+
+        true
+    }
+
+    fn response(&self, code: u16, req: &RequestHeader) -> Option<(ResponseHeader, Option<Bytes>)> {
+        let accept_language = req
+            .headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok());
+        let (language, body) = self.negotiate(code, accept_language)?;
+
+        let header = header(code, language, body.len())
+            .inspect_err(|err| tracing::error!("Failed to build error page header: {err}"))
+            .ok()?;
+        let body = (req.method != Method::HEAD).then(|| body.clone());
+
+        Some((header, body))
+    }
+}
+
+fn header(code: u16, language: &str, length: usize) -> pingora::Result<ResponseHeader> {
+    let mut header = ResponseHeader::build(code, Some(5))?;
+    header.insert_header(CONTENT_TYPE, "text/html; charset=utf-8")?;
+    header.insert_header(CONTENT_LENGTH, length.to_string())?;
+    header.insert_header(CACHE_CONTROL, "private, no-store")?;
+    header.insert_header(VARY, "Accept-Language")?;
+
+    if !language.is_empty() {
+        header.insert_header(CONTENT_LANGUAGE, language)?;
+    }
+
+    Ok(header)
+}
+
+/// The status and language of a page file, `404.de.html` or `404.html`.
+fn page_name(name: &str) -> Option<(u16, String)> {
+    let stem = name.strip_suffix(".html")?;
+    let (code, language) = stem.split_once('.').unwrap_or((stem, ""));
+
+    let code = code
+        .parse::<u16>()
+        .ok()
+        .filter(|code| (400..600).contains(code))?;
+
+    Some((code, language.to_ascii_lowercase()))
+}
+
+/// Language tags of an `Accept-Language` header, most preferred first.
+fn accepted_languages(header: &str) -> Vec<String> {
+    let mut languages = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+
+            (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+        })
+        .collect::<Vec<_>>();
+
+    // Stable, so equal qualities keep the client's order.
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_pages(files: &[(&str, &str)]) -> ErrorPages {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+
+        ErrorPages::load(&ErrorPagesConfig {
+            dir: dir.path().to_path_buf(),
+            default_language: "en".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_load() {
+        let pages = error_pages(&[
+            ("502.en.html", "{status} {reason}"),
+            ("502.PT-BR.html", "Erro {status}"),
+            ("502.html", "{status}"),
+            ("200.html", "OK"),
+            ("README.md", "notes"),
+        ]);
+
+        let pages_502 = &pages.pages[&502];
+        assert_eq!(pages_502["en"], "502 Bad Gateway");
+        assert_eq!(pages_502["pt-br"], "Erro 502");
+        assert_eq!(pages_502[""], "502");
+        assert_eq!(pages.pages.len(), 1);
+
+        let missing = ErrorPages::load(&ErrorPagesConfig {
+            dir: "/nonexistent/motya/errors".into(),
+            default_language: "en".to_string(),
+        });
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let pages = error_pages(&[
+            ("503.en.html", "en"),
+            ("503.de.html", "de"),
+            ("503.pt-br.html", "pt-br"),
+            ("404.html", "neutral"),
+        ]);
+
+        let language = |code, accept| pages.negotiate(code, accept).map(|(language, _)| language);
+
+        assert_eq!(language(503, Some("de-AT, en;q=0.5")), Some("de"));
+        assert_eq!(language(503, Some("en;q=0.5, pt-BR")), Some("pt-br"));
+        assert_eq!(language(503, Some("fr, *;q=0.1")), Some("en"));
+        assert_eq!(language(503, Some("fr, de;q=0")), Some("en"));
+        assert_eq!(language(503, None), Some("en"));
+        assert_eq!(language(404, Some("de")), Some(""));
+        assert_eq!(language(500, Some("de")), None);
+    }
+
+    #[test]
+    fn test_response() {
+        let pages = error_pages(&[("502.de.html", "Fehler")]);
+
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(ACCEPT_LANGUAGE, "de").unwrap();
+
+        let (header, body) = pages.response(502, &req).unwrap();
+        assert_eq!(header.status, StatusCode::BAD_GATEWAY);
+        assert_eq!(header.headers[CONTENT_LANGUAGE], "de");
+        assert_eq!(header.headers[CONTENT_LENGTH], "6");
+        assert_eq!(header.headers[VARY], "Accept-Language");
+        assert_eq!(body.as_deref(), Some(&b"Fehler"[..]));
+
+        let mut req = RequestHeader::build("HEAD", b"/", None).unwrap();
+        req.insert_header(ACCEPT_LANGUAGE, "de").unwrap();
+        let (_, body) = pages.response(502, &req).unwrap();
+        assert!(body.is_none());
+    }
+}
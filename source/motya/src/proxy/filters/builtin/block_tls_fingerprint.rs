@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, HashSet};
+
+use async_trait::async_trait;
+use pingora::{Error, Result};
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{builtin::helpers::ensure_empty, types::RequestFilterMod},
+    tls_fingerprint::TlsFingerprint,
+    MotyaContext,
+};
+
+/// Answers with `status` when the TLS client hello of the connection has one
+/// of the listed fingerprints. Requests on listeners without
+/// `tls-fingerprint` pass.
+pub struct BlockTlsFingerprint {
+    ja3: HashSet<String>,
+    ja4: HashSet<String>,
+    status: u16,
+}
+
+fn fingerprints(list: Option<String>) -> HashSet<String> {
+    list.unwrap_or_default()
+        .split(',')
+        .map(|fingerprint| fingerprint.trim().to_ascii_lowercase())
+        .filter(|fingerprint| !fingerprint.is_empty())
+        .collect()
+}
+
+impl BlockTlsFingerprint {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "ja3",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated JA3 hashes to reject",
+        },
+        FilterArg {
+            name: "ja4",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated JA4 fingerprints to reject",
+        },
+        FilterArg {
+            name: "status",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "Status of rejected requests, defaults to 403",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let ja3 = fingerprints(settings.remove("ja3"));
+        let ja4 = fingerprints(settings.remove("ja4"));
+
+        if ja3.is_empty() && ja4.is_empty() {
+            tracing::error!("Expected fingerprints in 'ja3' or 'ja4'");
+            return Err(Error::new_str("Missing TLS fingerprints"));
+        }
+
+        let status = match settings.remove("status") {
+            None => 403,
+            Some(status) => match status.parse::<u16>() {
+                Ok(status) if (400..600).contains(&status) => status,
+                _ => {
+                    tracing::error!("Status must be a 4xx or 5xx code, found '{status}'");
+                    return Err(Error::new_str("Invalid fingerprint status"));
+                }
+            },
+        };
+
+        ensure_empty(&settings)?;
+
+        Ok(Self { ja3, ja4, status })
+    }
+
+    fn blocks(&self, fingerprint: &TlsFingerprint) -> bool {
+        self.ja3.contains(&fingerprint.ja3) || self.ja4.contains(&fingerprint.ja4)
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for BlockTlsFingerprint {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        let Some(fingerprint) = ctx.extensions().get::<TlsFingerprint>() else {
+            return Ok(false);
+        };
+
+        if !self.blocks(fingerprint) {
+            return Ok(false);
+        }
+
+        session
+            .downstream_session
+            .respond_error(self.status)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(settings: &[(&str, &str)]) -> Result<BlockTlsFingerprint> {
+        BlockTlsFingerprint::from_settings(
+            settings
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_blocks() {
+        let filter = block(&[
+            ("ja3", "E7D705A3286E19EA42F587B344EE6865, "),
+            ("ja4", "t13d1516h2_8daaf6152771_e5627efa2ab1"),
+        ])
+        .unwrap();
+
+        let fingerprint = |ja3: &str, ja4: &str| TlsFingerprint {
+            ja3: ja3.to_string(),
+            ja4: ja4.to_string(),
+        };
+
+        assert!(filter.blocks(&fingerprint("e7d705a3286e19ea42f587b344ee6865", "other")));
+        assert!(filter.blocks(&fingerprint(
+            "other",
+            "t13d1516h2_8daaf6152771_e5627efa2ab1"
+        )));
+        assert!(!filter.blocks(&fingerprint("other", "other")));
+    }
+
+    #[test]
+    fn test_from_settings() {
+        assert_eq!(block(&[("ja4", "t13d")]).unwrap().status, 403);
+        assert!(block(&[]).is_err());
+        assert!(block(&[("ja3", " , ")]).is_err());
+        assert!(block(&[("ja3", "abc"), ("status", "200")]).is_err());
+    }
+}
@@ -1,41 +1,270 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use cidr::IpCidr;
-use pingora::{protocols::l4::socket::SocketAddr, Error, ErrorType, Result};
+use pingora::{Error, ErrorType, Result};
 use pingora_proxy::Session;
 
-use crate::proxy::{
-    filters::{builtin::helpers::extract_val, types::RequestFilterMod},
-    MotyaContext,
+use motya_config::common_types::{
+    filter_schema::{ArgKind, FilterArg},
+    host_name::ascii_host,
+};
+
+use crate::{
+    client_ip,
+    proxy::{
+        filters::{builtin::helpers::ensure_empty, types::RequestFilterMod},
+        virtual_host::{host_matches, request_host},
+        MotyaContext,
+    },
 };
 
+/// How often a `file` is checked for changes, by the first request after.
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Requests matching a rule are rejected.
+    Block,
+    /// Requests matching no rule are rejected.
+    Allow,
+}
+
+/// Rules on the source address and the requested host. A request matches
+/// when its source is in one of the ranges or its host matches one of the
+/// host rules.
 pub struct CidrRangeFilter {
-    blocks: Vec<IpCidr>,
+    mode: Mode,
+    ranges: Vec<IpCidr>,
+    file: Option<CidrFile>,
+    hosts: Vec<HostRule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum HostRule {
+    Exact(String),
+    /// `*.example.com`, one label in front of the domain like the `hosts`
+    /// of services.
+    Wildcard(String),
+    /// `.example.com`, the domain and all of its subdomains.
+    Suffix(String),
+}
+
+impl HostRule {
+    fn parse(rule: &str) -> Result<Self> {
+        let rule = rule.trim();
+        let parsed = if let Some(domain) = rule.strip_prefix("*.") {
+            ascii_host(domain).map(|domain| Self::Wildcard(format!("*.{domain}")))
+        } else if let Some(domain) = rule.strip_prefix('.') {
+            ascii_host(domain).map(Self::Suffix)
+        } else {
+            ascii_host(rule).map(Self::Exact)
+        };
+
+        parsed.ok_or_else(|| {
+            tracing::error!("Failed to parse '{rule}' as a host name or pattern");
+            Error::new(ErrorType::Custom("Invalid configuration"))
+        })
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            Self::Exact(name) => host == name,
+            Self::Wildcard(pattern) => host_matches(pattern, host),
+            Self::Suffix(domain) => host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.ends_with('.')),
+        }
+    }
+}
+
+/// Ranges listed in a file, one per line, read again when it changes.
+struct CidrFile {
+    path: PathBuf,
+    ranges: ArcSwap<Vec<IpCidr>>,
+    /// Last check for changes and the modification time then.
+    checked: Mutex<(Instant, Option<SystemTime>)>,
+}
+
+fn parse_ranges<'a>(addrs: impl Iterator<Item = &'a str>) -> Result<Vec<IpCidr>> {
+    addrs
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| {
+            addr.parse::<IpCidr>().map_err(|_| {
+                tracing::error!("Failed to parse '{addr}' as a valid CIDR notation range");
+                Error::new(ErrorType::Custom("Invalid configuration"))
+            })
+        })
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Ranges of a file, `#` starts a comment.
+fn read_ranges(path: &Path) -> Result<Vec<IpCidr>> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        tracing::error!("Failed to read CIDR ranges from {path:?}: {e}");
+        Error::new(ErrorType::Custom("Invalid configuration"))
+    })?;
+
+    parse_ranges(
+        content
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(ranges, _)| ranges)),
+    )
+}
+
+impl CidrFile {
+    fn open(path: PathBuf) -> Result<Self> {
+        let modified = modified(&path);
+        let ranges = read_ranges(&path)?;
+
+        Ok(Self {
+            path,
+            ranges: ArcSwap::from_pointee(ranges),
+            checked: Mutex::new((Instant::now(), modified)),
+        })
+    }
+
+    /// Reads the file again when it changed, keeping the previous ranges
+    /// when it cannot be read.
+    async fn refresh(&self) {
+        let last_modified = {
+            let mut checked = self.checked.lock().expect("cidr file state poisoned");
+            if checked.0.elapsed() < FILE_CHECK_INTERVAL {
+                return;
+            }
+            checked.0 = Instant::now();
+            checked.1
+        };
+
+        let path = self.path.clone();
+        let read = tokio::task::spawn_blocking(move || {
+            let modified = modified(&path);
+            if modified == last_modified {
+                return None;
+            }
+            Some((modified, read_ranges(&path)))
+        })
+        .await;
+
+        match read {
+            Ok(Some((modified, Ok(ranges)))) => {
+                tracing::info!(path = ?self.path, "Reloaded {} CIDR ranges", ranges.len());
+                self.ranges.store(Arc::new(ranges));
+                self.checked.lock().expect("cidr file state poisoned").1 = modified;
+            }
+            Ok(Some((_, Err(_)))) => {
+                tracing::warn!(path = ?self.path, "Keeping the previous CIDR ranges");
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("CIDR file refresh failed: {e}"),
+        }
+    }
 }
 
 impl CidrRangeFilter {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "addrs",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated IPv4/IPv6 addresses or CIDR ranges",
+        },
+        FilterArg {
+            name: "file",
+            required: false,
+            kind: ArgKind::String,
+            description: "File of addresses or CIDR ranges, one per line, reloaded when it changes",
+        },
+        FilterArg {
+            name: "mode",
+            required: false,
+            kind: ArgKind::String,
+            description: "'block' (default) rejects matching requests, 'allow' rejects all others",
+        },
+        FilterArg {
+            name: "hosts",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated requested hosts, '*.' or '.' in front for subdomains",
+        },
+    ];
+
     /// Create from the settings field
     pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
-        let mat = extract_val("addrs", &mut settings)?;
-
-        let addrs = mat.split(',');
-
-        let mut blocks = vec![];
-        for addr in addrs {
-            let addr = addr.trim();
-            match addr.parse::<IpCidr>() {
-                Ok(a) => {
-                    blocks.push(a);
-                }
-                Err(_) => {
-                    tracing::error!("Failed to parse '{addr}' as a valid CIDR notation range");
-                    return Err(Error::new(ErrorType::Custom("Invalid configuration")));
-                }
-            };
+        let ranges = match settings.remove("addrs") {
+            Some(addrs) => parse_ranges(addrs.split(','))?,
+            None => Vec::new(),
+        };
+
+        let file = settings
+            .remove("file")
+            .map(|path| CidrFile::open(path.into()))
+            .transpose()?;
+
+        let hosts = settings
+            .remove("hosts")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(HostRule::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        if ranges.is_empty() && file.is_none() && hosts.is_empty() {
+            tracing::error!("Expected CIDR ranges in 'addrs' or 'file', or rules in 'hosts'");
+            return Err(Error::new_str("Missing configuration field!"));
         }
 
-        Ok(Self { blocks })
+        let mode = match settings.remove("mode").as_deref() {
+            None | Some("block") => Mode::Block,
+            Some("allow") => Mode::Allow,
+            Some(other) => {
+                tracing::error!("Unknown mode '{other}', expected 'block' or 'allow'");
+                return Err(Error::new(ErrorType::Custom("Invalid configuration")));
+            }
+        };
+
+        ensure_empty(&settings)?;
+
+        Ok(Self {
+            mode,
+            ranges,
+            file,
+            hosts,
+        })
+    }
+
+    /// `ip` is `None` for clients on Unix sockets, only the host rules apply
+    /// to them.
+    fn rejects(&self, ip: Option<IpAddr>, host: Option<&str>) -> bool {
+        let in_ranges = |ip: IpAddr| {
+            self.ranges.iter().any(|range| range.contains(&ip))
+                || self
+                    .file
+                    .as_ref()
+                    .is_some_and(|file| file.ranges.load().iter().any(|range| range.contains(&ip)))
+        };
+
+        let listed = ip.is_some_and(in_ranges)
+            || host.is_some_and(|host| self.hosts.iter().any(|rule| rule.matches(host)));
+
+        match self.mode {
+            Mode::Block => listed,
+            Mode::Allow => !listed,
+        }
     }
 }
 
@@ -47,13 +276,20 @@ impl RequestFilterMod for CidrRangeFilter {
             session.downstream_session.respond_error(401).await?;
             return Ok(true);
         };
-        let SocketAddr::Inet(addr) = addr else {
+        let ip = addr
+            .as_inet()
+            .map(|addr| client_ip::resolve(addr.ip(), session.req_header()));
+        if ip.is_none() && self.hosts.is_empty() {
             // CIDR filters don't apply to UDS
             return Ok(false);
-        };
-        let ip_addr = addr.ip();
+        }
 
-        if self.blocks.iter().any(|b| b.contains(&ip_addr)) {
+        if let (Some(file), Some(_)) = (&self.file, ip) {
+            file.refresh().await;
+        }
+
+        let host = request_host(session.req_header());
+        if self.rejects(ip, host.as_deref()) {
             session.downstream_session.respond_error(401).await?;
             Ok(true)
         } else {
@@ -78,12 +314,12 @@ mod tests {
         let filter =
             CidrRangeFilter::from_settings(settings).expect("Should successfully create filter");
 
-        assert_eq!(filter.blocks.len(), 2);
+        assert_eq!(filter.ranges.len(), 2);
         assert!(filter
-            .blocks
+            .ranges
             .iter()
             .any(|b| b.to_string() == "192.168.0.0/16"));
-        assert!(filter.blocks.iter().any(|b| b.to_string() == "10.0.0.0/8"));
+        assert!(filter.ranges.iter().any(|b| b.to_string() == "10.0.0.0/8"));
     }
 
     #[test]
@@ -97,13 +333,13 @@ mod tests {
         let filter =
             CidrRangeFilter::from_settings(settings).expect("Should successfully create filter");
 
-        assert_eq!(filter.blocks.len(), 4);
+        assert_eq!(filter.ranges.len(), 4);
         assert!(filter
-            .blocks
+            .ranges
             .iter()
             .any(|b| b.to_string() == "2001:db8::/32"));
-        assert!(filter.blocks.iter().any(|b| b.to_string() == "::1"));
-        assert!(filter.blocks.iter().any(|b| b.to_string() == "1.1.1.1"));
+        assert!(filter.ranges.iter().any(|b| b.to_string() == "::1"));
+        assert!(filter.ranges.iter().any(|b| b.to_string() == "1.1.1.1"));
     }
 
     #[test]
@@ -133,4 +369,110 @@ mod tests {
         let err = result.err().unwrap();
         assert!(format!("{:?}", err).contains("Missing configuration"));
     }
+
+    fn cidr(settings: &[(&str, &str)]) -> Result<CidrRangeFilter> {
+        CidrRangeFilter::from_settings(
+            settings
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_modes() {
+        let inside: IpAddr = "10.1.2.3".parse().unwrap();
+        let outside: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let block = cidr(&[("addrs", "10.0.0.0/8")]).unwrap();
+        assert!(block.rejects(Some(inside), None));
+        assert!(!block.rejects(Some(outside), None));
+
+        let allow = cidr(&[("addrs", "10.0.0.0/8"), ("mode", "allow")]).unwrap();
+        assert!(!allow.rejects(Some(inside), None));
+        assert!(allow.rejects(Some(outside), None));
+
+        assert!(cidr(&[("addrs", "10.0.0.0/8"), ("mode", "deny")]).is_err());
+        assert!(cidr(&[("addrs", " , ")]).is_err());
+    }
+
+    #[test]
+    fn test_host_rules() {
+        let rules = [
+            "corp.example.com",
+            "*.corp.example.com",
+            ".Internal.Example.com.",
+        ]
+        .map(|rule| HostRule::parse(rule).unwrap());
+        let matching = |host| rules.iter().filter(|rule| rule.matches(host)).count();
+
+        assert_eq!(matching("corp.example.com"), 1);
+        assert_eq!(matching("wiki.corp.example.com"), 1);
+        assert_eq!(matching("a.wiki.corp.example.com"), 0);
+        assert_eq!(matching("internal.example.com"), 1);
+        assert_eq!(matching("a.b.internal.example.com"), 1);
+        assert_eq!(matching("notinternal.example.com"), 0);
+
+        assert!(HostRule::parse("*.").is_err());
+        assert!(HostRule::parse("bad_host.example").is_err());
+    }
+
+    #[test]
+    fn test_combined_policy() {
+        let office: IpAddr = "10.1.2.3".parse().unwrap();
+        let outside: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let allow = cidr(&[
+            ("addrs", "10.0.0.0/8"),
+            ("hosts", ".public.example.com"),
+            ("mode", "allow"),
+        ])
+        .unwrap();
+        assert!(!allow.rejects(Some(office), Some("admin.example.com")));
+        assert!(!allow.rejects(Some(outside), Some("www.public.example.com")));
+        assert!(allow.rejects(Some(outside), Some("admin.example.com")));
+        assert!(allow.rejects(Some(outside), None));
+        assert!(allow.rejects(None, Some("admin.example.com")));
+
+        let block = cidr(&[("hosts", "*.corp.example.com")]).unwrap();
+        assert!(block.rejects(Some(outside), Some("wiki.corp.example.com")));
+        assert!(!block.rejects(Some(office), Some("example.com")));
+        assert!(block.rejects(None, Some("wiki.corp.example.com")));
+    }
+
+    #[tokio::test]
+    async fn test_file_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("office.txt");
+        std::fs::write(&path, "# office\n10.0.0.0/8 # vpn\n\n2001:db8::/32\n").unwrap();
+
+        let filter = cidr(&[("file", path.to_str().unwrap()), ("mode", "allow")]).unwrap();
+        assert!(!filter.rejects(Some("10.1.2.3".parse().unwrap()), None));
+        assert!(!filter.rejects(Some("2001:db8::1".parse().unwrap()), None));
+        assert!(filter.rejects(Some("192.0.2.1".parse().unwrap()), None));
+
+        let file = filter.file.as_ref().unwrap();
+        let changed = |content: &str| {
+            std::fs::write(&path, content).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::now() + Duration::from_secs(60))
+                .unwrap();
+            file.checked.lock().unwrap().0 = Instant::now() - FILE_CHECK_INTERVAL;
+        };
+
+        changed("192.0.2.0/24\n");
+        file.refresh().await;
+        assert!(filter.rejects(Some("10.1.2.3".parse().unwrap()), None));
+        assert!(!filter.rejects(Some("192.0.2.1".parse().unwrap()), None));
+
+        // A broken file keeps the ranges read before.
+        changed("not a range\n");
+        file.refresh().await;
+        assert!(!filter.rejects(Some("192.0.2.1".parse().unwrap()), None));
+
+        assert!(cidr(&[("file", "/nonexistent/ranges.txt")]).is_err());
+    }
 }
@@ -0,0 +1,439 @@
+//! The `Check` call of Envoy's `envoy.service.auth.v3.Authorization` service,
+//! for authorization services at a `grpc://` or `grpcs://` URL.
+
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use http::{header::HOST, HeaderName, HeaderValue, StatusCode};
+use pingora::{
+    connectors::http::Connector, prelude::HttpPeer, upstreams::peer::ALPN, Error, ErrorType,
+};
+use pingora_http::RequestHeader;
+use reqwest::Url;
+
+use crate::proxy::{
+    grpc::{put_varint, read_varint},
+    plugins::mutations::{PluginAnswer, RequestMutations},
+};
+
+use super::Decision;
+
+const CHECK_PATH: &str = "/envoy.service.auth.v3.Authorization/Check";
+
+const CHECK_FAILED: ErrorType = ErrorType::Custom("ext-auth check failed");
+
+/// A protobuf field value, fixed size ones are skipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// The fields of a protobuf message, `None` when it is malformed.
+fn fields(mut message: &[u8]) -> Option<Vec<(u64, Value<'_>)>> {
+    let mut fields = Vec::new();
+
+    while !message.is_empty() {
+        let key = read_varint(&mut message)?;
+
+        let value = match key & 0x7 {
+            0 => Value::Varint(read_varint(&mut message)?),
+            2 => {
+                let len = read_varint(&mut message)? as usize;
+                let bytes = message.get(..len)?;
+                message = &message[len..];
+                Value::Bytes(bytes)
+            }
+            1 => {
+                message = message.get(8..)?;
+                continue;
+            }
+            5 => {
+                message = message.get(4..)?;
+                continue;
+            }
+            _ => return None,
+        };
+
+        fields.push((key >> 3, value));
+    }
+
+    Some(fields)
+}
+
+/// The last value of varint field `number`, absent fields hold `0`.
+fn varint(fields: &[(u64, Value<'_>)], number: u64) -> u64 {
+    fields
+        .iter()
+        .rev()
+        .find_map(|(n, value)| match value {
+            Value::Varint(value) if *n == number => Some(*value),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// Every value of length delimited field `number`.
+fn bytes<'a>(fields: &[(u64, Value<'a>)], number: u64) -> Vec<&'a [u8]> {
+    fields
+        .iter()
+        .filter_map(|(n, value)| match value {
+            Value::Bytes(bytes) if *n == number => Some(*bytes),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The embedded message of field `number`, empty when it is absent.
+fn message<'a>(fields: &[(u64, Value<'a>)], number: u64) -> Option<Vec<(u64, Value<'a>)>> {
+    match bytes(fields, number).last().copied() {
+        Some(message) => self::fields(message),
+        None => Some(Vec::new()),
+    }
+}
+
+/// Appends field `number` holding `bytes`, a string or an embedded message.
+fn put_field(buf: &mut BytesMut, number: u64, bytes: &[u8]) {
+    put_varint(buf, (number << 3) | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.put_slice(bytes);
+}
+
+/// Length-prefixed gRPC message of a `CheckRequest` describing `req`, with
+/// its `forward_headers` only.
+fn check_request(req: &RequestHeader, forward_headers: &[HeaderName]) -> Bytes {
+    // AttributeContext.HttpRequest
+    let mut http = BytesMut::new();
+    put_field(&mut http, 2, req.method.as_str().as_bytes());
+
+    for name in forward_headers {
+        let values = req
+            .headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()))
+            .collect::<Vec<_>>();
+
+        if values.is_empty() {
+            continue;
+        }
+
+        let mut entry = BytesMut::new();
+        put_field(&mut entry, 1, name.as_str().as_bytes());
+        put_field(&mut entry, 2, values.join(",").as_bytes());
+        put_field(&mut http, 3, &entry);
+    }
+
+    let path = req.uri.path_and_query().map_or("/", |path| path.as_str());
+    put_field(&mut http, 4, path.as_bytes());
+
+    if let Some(host) = req.headers.get(HOST) {
+        put_field(&mut http, 5, host.as_bytes());
+    }
+
+    // AttributeContext.Request, AttributeContext and CheckRequest
+    let mut request = BytesMut::new();
+    put_field(&mut request, 2, &http);
+    let mut attributes = BytesMut::new();
+    put_field(&mut attributes, 4, &request);
+    let mut check = BytesMut::new();
+    put_field(&mut check, 1, &attributes);
+
+    let mut frame = BytesMut::with_capacity(5 + check.len());
+    frame.put_u8(0);
+    frame.put_u32(check.len() as u32);
+    frame.put_slice(&check);
+
+    frame.freeze()
+}
+
+/// Name, value and whether to append of a `HeaderValueOption`.
+fn header_option(option: &[u8]) -> Option<(String, String, bool)> {
+    let option = fields(option)?;
+    let header = message(&option, 1)?;
+    let append = message(&option, 2)?;
+
+    let name = bytes(&header, 1).last().copied().unwrap_or_default();
+    let value = match bytes(&header, 2).last().copied() {
+        Some(value) => value,
+        None => bytes(&header, 3).last().copied().unwrap_or_default(),
+    };
+
+    Some((
+        String::from_utf8_lossy(name).into_owned(),
+        String::from_utf8_lossy(value).into_owned(),
+        varint(&append, 1) == 1,
+    ))
+}
+
+/// What a length-prefixed `CheckResponse` decides. The `upstream_headers`
+/// are dropped from allowed requests before its own changes.
+fn decision(frame: &[u8], upstream_headers: &[HeaderName]) -> Result<Decision, String> {
+    let malformed = || "malformed CheckResponse".to_string();
+
+    let (&compressed, rest) = frame.split_first().ok_or_else(malformed)?;
+    if compressed != 0 || rest.len() < 4 {
+        return Err(malformed());
+    }
+    let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+    let response = rest
+        .get(4..4 + len)
+        .and_then(fields)
+        .ok_or_else(malformed)?;
+
+    let status = message(&response, 1).ok_or_else(malformed)?;
+
+    if varint(&status, 1) != 0 {
+        let denied = message(&response, 2).ok_or_else(malformed)?;
+        let http_status = message(&denied, 1).ok_or_else(malformed)?;
+
+        // Envoy answers denials without a status with a `403`.
+        let code = u16::try_from(varint(&http_status, 1)).unwrap_or_default();
+        let code = StatusCode::from_u16(code)
+            .ok()
+            .filter(|code| code.as_u16() >= 200)
+            .unwrap_or(StatusCode::FORBIDDEN);
+
+        let mut headers = Vec::new();
+        for option in bytes(&denied, 2) {
+            let (name, value, _) = header_option(option).ok_or_else(malformed)?;
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|err| err.to_string())?;
+            let value = HeaderValue::from_str(&value).map_err(|err| err.to_string())?;
+            headers.push((name, value));
+        }
+
+        let body = bytes(&denied, 3).last().copied().unwrap_or_default();
+
+        return Ok(Decision::Deny(PluginAnswer::new(
+            code,
+            headers,
+            Bytes::copy_from_slice(body),
+        )));
+    }
+
+    let mut mutations = RequestMutations::default();
+    for name in upstream_headers {
+        mutations.remove_header(name.as_str())?;
+    }
+
+    let ok = message(&response, 3).ok_or_else(malformed)?;
+    for option in bytes(&ok, 2) {
+        match header_option(option).ok_or_else(malformed)? {
+            (name, value, true) => mutations.add_header(&name, &value)?,
+            (name, value, false) => mutations.set_header(&name, &value)?,
+        }
+    }
+    for name in bytes(&ok, 5) {
+        mutations.remove_header(&String::from_utf8_lossy(name))?;
+    }
+
+    Ok(Decision::Allow(mutations))
+}
+
+/// Asks an Envoy ext_authz gRPC service, over HTTP/2 with TLS for `grpcs://`
+/// and without for `grpc://`.
+pub(super) struct GrpcAuthz {
+    authority: String,
+    host: String,
+    tls: bool,
+    timeout: Duration,
+    connector: Connector,
+}
+
+impl GrpcAuthz {
+    /// `None` when the URL lacks a host or port.
+    pub(super) fn new(url: &Url, timeout: Duration) -> Option<Self> {
+        let host = url.host_str()?.to_string();
+        let port = url.port()?;
+
+        Some(Self {
+            authority: format!("{host}:{port}"),
+            host,
+            tls: url.scheme() == "grpcs",
+            timeout,
+            connector: Connector::new(None),
+        })
+    }
+
+    pub(super) async fn check(
+        &self,
+        req: &RequestHeader,
+        forward_headers: &[HeaderName],
+        upstream_headers: &[HeaderName],
+    ) -> Result<Decision, String> {
+        let body = check_request(req, forward_headers);
+
+        let frame = tokio::time::timeout(self.timeout, self.call(body))
+            .await
+            .map_err(|_| format!("no answer within {:?}", self.timeout))?
+            .map_err(|err| err.to_string())?;
+
+        decision(&frame, upstream_headers)
+    }
+
+    /// Sends the length-prefixed `body` and reads the message of the answer.
+    async fn call(&self, body: Bytes) -> pingora::Result<BytesMut> {
+        let addr = tokio::net::lookup_host(&self.authority)
+            .await
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| Error::explain(CHECK_FAILED, "no address for the service"))?;
+
+        let mut peer = HttpPeer::new(addr, self.tls, self.host.clone());
+        peer.options.alpn = ALPN::H2;
+
+        let (mut session, _) = self.connector.get_http_session(&peer).await?;
+
+        let mut req = RequestHeader::build("POST", CHECK_PATH.as_bytes(), None)?;
+        req.insert_header("host", &self.authority)?;
+        req.insert_header("content-type", "application/grpc")?;
+        req.insert_header("te", "trailers")?;
+        req.insert_header("content-length", body.len().to_string())?;
+
+        session.write_request_header(Box::new(req)).await?;
+        session.write_request_body(body, false).await?;
+        session.finish_request_body().await?;
+        session.read_response_header().await?;
+
+        let Some(response) = session.response_header() else {
+            return Error::e_explain(CHECK_FAILED, "no response");
+        };
+
+        if response.status != 200 {
+            return Error::e_explain(CHECK_FAILED, format!("answered {}", response.status));
+        }
+
+        // A trailers-only response carries the error in the headers.
+        if let Some(status) = response.headers.get("grpc-status") {
+            if status != "0" {
+                return Error::e_explain(CHECK_FAILED, format!("grpc-status {status:?}"));
+            }
+        }
+
+        let mut frame = BytesMut::new();
+        while let Some(chunk) = session.read_response_body().await? {
+            frame.extend_from_slice(&chunk);
+        }
+
+        self.connector
+            .release_http_session(session, &peer, None)
+            .await;
+
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A length-prefixed gRPC message holding `message`.
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    fn field(number: u64, bytes: &[u8]) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        put_field(&mut buf, number, bytes);
+        buf.to_vec()
+    }
+
+    fn option(name: &str, value: &str, append: bool) -> Vec<u8> {
+        let header = [field(1, name.as_bytes()), field(2, value.as_bytes())].concat();
+        let mut option = field(1, &header);
+        if append {
+            option.extend(field(2, &[0x08, 0x01]));
+        }
+        option
+    }
+
+    #[test]
+    fn test_check_request() {
+        let mut req = RequestHeader::build("POST", b"/orders?id=1", None).unwrap();
+        req.insert_header("host", "shop.example.com").unwrap();
+        req.insert_header("authorization", "Bearer good").unwrap();
+        req.insert_header("x-secret", "kept").unwrap();
+
+        let request = check_request(&req, &[http::header::AUTHORIZATION]);
+        assert_eq!(request[0], 0);
+        assert_eq!(
+            request.len(),
+            5 + u32::from_be_bytes(request[1..5].try_into().unwrap()) as usize
+        );
+
+        // CheckRequest.attributes.request.http
+        let check = fields(&request[5..]).unwrap();
+        let attributes = message(&check, 1).unwrap();
+        let context = message(&attributes, 4).unwrap();
+        let http = message(&context, 2).unwrap();
+
+        assert_eq!(bytes(&http, 2), [b"POST".as_slice()]);
+        assert_eq!(bytes(&http, 4), [b"/orders?id=1".as_slice()]);
+        assert_eq!(bytes(&http, 5), [b"shop.example.com".as_slice()]);
+
+        // Only the forwarded headers.
+        let headers = bytes(&http, 3);
+        assert_eq!(headers.len(), 1);
+        let entry = fields(headers[0]).unwrap();
+        assert_eq!(bytes(&entry, 1), [b"authorization".as_slice()]);
+        assert_eq!(bytes(&entry, 2), [b"Bearer good".as_slice()]);
+    }
+
+    #[test]
+    fn test_decision_allow() {
+        let ok = [
+            field(2, &option("x-user", "alice", false)),
+            field(2, &option("x-role", "admin", true)),
+            field(5, b"cookie"),
+        ]
+        .concat();
+        // An empty status is OK.
+        let response = [field(1, &[]), field(3, &ok)].concat();
+
+        let Decision::Allow(mutations) = decision(&frame(&response), &[]).unwrap() else {
+            panic!("expected the request to be allowed");
+        };
+
+        let mut upstream = RequestHeader::build("GET", b"/", None).unwrap();
+        upstream.insert_header("x-user", "forged").unwrap();
+        upstream.insert_header("x-role", "viewer").unwrap();
+        upstream.insert_header("cookie", "session=1").unwrap();
+        mutations.apply(&mut upstream).unwrap();
+
+        assert_eq!(upstream.headers["x-user"], "alice");
+        assert_eq!(upstream.headers.get_all("x-role").iter().count(), 2);
+        assert!(upstream.headers.get("cookie").is_none());
+    }
+
+    #[test]
+    fn test_decision_deny() {
+        // PERMISSION_DENIED, with a 401 and a body.
+        let denied = [
+            field(1, &[0x08, 0x91, 0x03]),
+            field(2, &option("www-authenticate", "Bearer", false)),
+            field(3, b"denied"),
+        ]
+        .concat();
+        let response = [field(1, &[0x08, 0x07]), field(2, &denied)].concat();
+
+        let Decision::Deny(answer) = decision(&frame(&response), &[]).unwrap() else {
+            panic!("expected the request to be denied");
+        };
+        let header = answer.header().unwrap();
+        assert_eq!(header.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(header.headers["www-authenticate"], "Bearer");
+
+        // Without a denied response the client gets a 403.
+        let response = field(1, &[0x08, 0x07]);
+        let Decision::Deny(answer) = decision(&frame(&response), &[]).unwrap() else {
+            panic!("expected the request to be denied");
+        };
+        assert_eq!(answer.header().unwrap().status, StatusCode::FORBIDDEN);
+
+        assert!(decision(&[], &[]).is_err());
+        assert!(decision(&frame(&[0x0a, 0x05]), &[]).is_err());
+    }
+}
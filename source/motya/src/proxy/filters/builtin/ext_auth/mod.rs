@@ -0,0 +1,473 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{
+    header::{CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING},
+    HeaderName, HeaderValue,
+};
+use pingora::{Error, Result};
+use pingora_http::RequestHeader;
+use pingora_proxy::Session;
+use reqwest::Url;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{
+        builtin::{
+            ext_auth::grpc::GrpcAuthz,
+            helpers::{ensure_empty, extract_val},
+        },
+        types::RequestFilterMod,
+    },
+    plugins::mutations::{PluginAnswer, RequestMutations},
+    MotyaContext,
+};
+
+mod grpc;
+
+/// Decisions kept at most, so distinct credentials cannot grow the cache
+/// without bound.
+const MAX_CACHED: usize = 10_000;
+
+/// Bodies of denials passed on to the client are cut off here.
+const MAX_DENY_BODY: usize = 64 * 1024;
+
+/// What the authorization service made of a request.
+#[derive(Debug, Clone, PartialEq)]
+enum Decision {
+    /// Proxy the request, with these changes.
+    Allow(RequestMutations),
+    /// Answer the request with the response of the service.
+    Deny(PluginAnswer),
+}
+
+/// Where the decisions come from.
+#[allow(clippy::large_enum_variant)]
+enum Service {
+    /// Answers a `GET` with a status, a `2xx` allows the request.
+    Http { client: reqwest::Client, url: Url },
+    /// Answers the `Check` call of Envoy's ext_authz gRPC API.
+    Grpc(GrpcAuthz),
+}
+
+/// Asks an authorization service whether to proxy a request, sending it the
+/// method, URI and `forward-headers` of the request. An HTTP service allows it
+/// with a `2xx` and has its `upstream-headers` copied onto the upstream
+/// request, any other status is sent to the client. A gRPC service allows it
+/// with an OK status and the header changes of its `ok_response`, or denies it
+/// with its `denied_response`.
+pub struct ExtAuth {
+    service: Service,
+    forward_headers: Vec<HeaderName>,
+    upstream_headers: Vec<HeaderName>,
+    cache_ttl: Option<Duration>,
+    cache: Mutex<HashMap<String, (Instant, Decision)>>,
+    failure_mode_allow: bool,
+}
+
+fn header_names(key: &str, list: &str) -> Result<Vec<HeaderName>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                tracing::error!("Invalid header '{name}' in '{key}'");
+                Error::new_str("Invalid ext-auth header")
+            })
+        })
+        .collect()
+}
+
+fn parse_int(key: &str, value: String) -> Result<u64> {
+    value.parse().map_err(|_| {
+        tracing::error!("'{key}' must be a non-negative integer, found '{value}'");
+        Error::new_str("Invalid ext-auth setting")
+    })
+}
+
+impl ExtAuth {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "url",
+            required: true,
+            kind: ArgKind::String,
+            description: "HTTP(S) URL of the authorization service, or grpc(s):// for an Envoy ext_authz one",
+        },
+        FilterArg {
+            name: "forward-headers",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated request headers sent to the service, defaults to 'authorization,cookie'",
+        },
+        FilterArg {
+            name: "upstream-headers",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated headers of an allowing response copied onto the upstream request",
+        },
+        FilterArg {
+            name: "timeout-ms",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "Time the service has to answer, defaults to 1000",
+        },
+        FilterArg {
+            name: "cache-secs",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "How long decisions are reused, defaults to 0 which never reuses them",
+        },
+        FilterArg {
+            name: "failure-mode-allow",
+            required: false,
+            kind: ArgKind::Bool,
+            description: "Proxy requests when the service fails instead of answering 503",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let url = extract_val("url", &mut settings)?;
+        let url = match Url::parse(&url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https" | "grpc" | "grpcs") => url,
+            _ => {
+                tracing::error!("'url' must be an HTTP, HTTPS, gRPC or gRPCS URL, found '{url}'");
+                return Err(Error::new_str("Invalid ext-auth url"));
+            }
+        };
+
+        let forward_headers = settings
+            .remove("forward-headers")
+            .unwrap_or_else(|| "authorization,cookie".to_string());
+        let forward_headers = header_names("forward-headers", &forward_headers)?;
+
+        let upstream_headers = settings.remove("upstream-headers").unwrap_or_default();
+        let upstream_headers = header_names("upstream-headers", &upstream_headers)?;
+
+        let timeout = match settings.remove("timeout-ms") {
+            Some(timeout) => parse_int("timeout-ms", timeout)?,
+            None => 1000,
+        };
+        if timeout == 0 {
+            tracing::error!("'timeout-ms' must be greater than 0");
+            return Err(Error::new_str("Invalid ext-auth setting"));
+        }
+
+        let cache_ttl = match settings.remove("cache-secs") {
+            Some(secs) => Some(parse_int("cache-secs", secs)?)
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            None => None,
+        };
+
+        let failure_mode_allow = match settings.remove("failure-mode-allow").as_deref() {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(other) => {
+                tracing::error!("'failure-mode-allow' must be true or false, found '{other}'");
+                return Err(Error::new_str("Invalid ext-auth setting"));
+            }
+        };
+
+        ensure_empty(&settings)?;
+
+        let timeout = Duration::from_millis(timeout);
+        let service = if matches!(url.scheme(), "grpc" | "grpcs") {
+            let Some(authz) = GrpcAuthz::new(&url, timeout) else {
+                tracing::error!("A gRPC 'url' needs a host and a port, found '{url}'");
+                return Err(Error::new_str("Invalid ext-auth url"));
+            };
+            Service::Grpc(authz)
+        } else {
+            let client = reqwest::Client::builder()
+                .timeout(timeout)
+                // A redirect is a denial to pass on, e.g. to a login page.
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .map_err(|err| {
+                    tracing::error!("Failed to create the ext-auth HTTP client: {err}");
+                    Error::new_str("Invalid ext-auth setting")
+                })?;
+            Service::Http { client, url }
+        };
+
+        Ok(Self {
+            service,
+            forward_headers,
+            upstream_headers,
+            cache_ttl,
+            cache: Mutex::new(HashMap::new()),
+            failure_mode_allow,
+        })
+    }
+
+    /// Everything the service gets to see, so equal keys get equal decisions.
+    fn cache_key(&self, req: &RequestHeader) -> String {
+        let host = req
+            .headers
+            .get(http::header::HOST)
+            .map(|host| String::from_utf8_lossy(host.as_bytes()))
+            .unwrap_or_default();
+
+        let mut key = format!("{} {host} {}", req.method, req.uri);
+        for name in &self.forward_headers {
+            for value in req.headers.get_all(name) {
+                key.push('\n');
+                key.push_str(name.as_str());
+                key.push(':');
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+        key
+    }
+
+    fn cached(&self, key: &str) -> Option<Decision> {
+        let cache = self.cache.lock().expect("ext-auth cache poisoned");
+        let (expires, decision) = cache.get(key)?;
+
+        (*expires > Instant::now()).then(|| decision.clone())
+    }
+
+    fn remember(&self, key: String, decision: &Decision, ttl: Duration) {
+        let now = Instant::now();
+        let mut cache = self.cache.lock().expect("ext-auth cache poisoned");
+
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, (expires, _)| *expires > now);
+        }
+        if cache.len() < MAX_CACHED {
+            cache.insert(key, (now + ttl, decision.clone()));
+        }
+    }
+
+    async fn decide(&self, req: &RequestHeader) -> Decision {
+        let key = self.cache_ttl.map(|_| self.cache_key(req));
+        if let Some(decision) = key.as_deref().and_then(|key| self.cached(key)) {
+            return decision;
+        }
+
+        match self.check(req).await {
+            Ok(decision) => {
+                if let (Some(key), Some(ttl)) = (key, self.cache_ttl) {
+                    self.remember(key, &decision, ttl);
+                }
+                decision
+            }
+            Err(err) if self.failure_mode_allow => {
+                tracing::warn!("Authorization service failed, allowing the request: {err}");
+                Decision::Allow(RequestMutations::default())
+            }
+            Err(err) => {
+                tracing::error!("Authorization service failed, refusing the request: {err}");
+                Decision::Deny(PluginAnswer::new(
+                    http::StatusCode::SERVICE_UNAVAILABLE,
+                    vec![],
+                    Bytes::new(),
+                ))
+            }
+        }
+    }
+
+    async fn check(&self, req: &RequestHeader) -> Result<Decision, String> {
+        let (client, url) = match &self.service {
+            Service::Http { client, url } => (client, url),
+            Service::Grpc(authz) => {
+                return authz
+                    .check(req, &self.forward_headers, &self.upstream_headers)
+                    .await
+            }
+        };
+
+        let mut builder = client
+            .get(url.clone())
+            .header("x-forwarded-method", req.method.as_str())
+            .header("x-forwarded-uri", req.uri.to_string());
+
+        if let Some(host) = req.headers.get(http::header::HOST) {
+            builder = builder.header("x-forwarded-host", host);
+        }
+        for name in &self.forward_headers {
+            for value in req.headers.get_all(name) {
+                builder = builder.header(name, value);
+            }
+        }
+
+        let mut response = builder.send().await.map_err(|err| err.to_string())?;
+        let status = response.status();
+
+        if status.is_success() {
+            let mut mutations = RequestMutations::default();
+            for name in &self.upstream_headers {
+                // Dropped even when the service sends none, so clients cannot
+                // supply the header themselves.
+                mutations.remove_header(name.as_str())?;
+                for value in response.headers().get_all(name) {
+                    let value = value.to_str().map_err(|err| err.to_string())?;
+                    mutations.add_header(name.as_str(), value)?;
+                }
+            }
+            return Ok(Decision::Allow(mutations));
+        }
+
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| ![CONTENT_LENGTH, TRANSFER_ENCODING, CONNECTION].contains(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<Vec<(HeaderName, HeaderValue)>>();
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+            let room = MAX_DENY_BODY - body.len();
+            body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            if body.len() == MAX_DENY_BODY {
+                break;
+            }
+        }
+
+        Ok(Decision::Deny(PluginAnswer::new(
+            status,
+            headers,
+            body.into(),
+        )))
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for ExtAuth {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        match self.decide(session.req_header()).await {
+            Decision::Allow(mutations) => {
+                mutations.store(ctx.extensions_mut());
+                Ok(false)
+            }
+            Decision::Deny(answer) => {
+                answer.respond(session).await?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{header, method},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn ext_auth(url: &str, extra: &[(&str, &str)]) -> Result<ExtAuth> {
+        let mut settings = BTreeMap::from([("url".to_string(), url.to_string())]);
+        for (key, value) in extra {
+            settings.insert(key.to_string(), value.to_string());
+        }
+        ExtAuth::from_settings(settings)
+    }
+
+    fn request(token: &str) -> RequestHeader {
+        let mut req = RequestHeader::build("POST", b"/orders?id=1", None).unwrap();
+        req.insert_header("authorization", token).unwrap();
+        req.insert_header("x-user", "forged").unwrap();
+        req
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let auth = ext_auth("http://auth.internal/check", &[]).unwrap();
+        assert_eq!(auth.forward_headers.len(), 2);
+        assert!(auth.cache_ttl.is_none());
+        assert!(!auth.failure_mode_allow);
+
+        assert!(matches!(
+            ext_auth("grpc://auth.internal:9000", &[]).unwrap().service,
+            Service::Grpc(_)
+        ));
+        assert!(ext_auth("grpc://auth.internal", &[]).is_err());
+        assert!(ext_auth("not a url", &[]).is_err());
+        assert!(ext_auth("http://auth.internal", &[("timeout-ms", "0")]).is_err());
+        assert!(ext_auth(
+            "http://auth.internal",
+            &[("upstream-headers", "bad header")]
+        )
+        .is_err());
+        assert!(ext_auth("http://auth.internal", &[("unknown", "1")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decide() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(header("authorization", "Bearer good"))
+            .and(header("x-forwarded-method", "POST"))
+            .and(header("x-forwarded-uri", "/orders?id=1"))
+            .respond_with(ResponseTemplate::new(200).insert_header("x-user", "alice"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(header("authorization", "Bearer bad"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .insert_header("www-authenticate", "Bearer")
+                    .set_body_string("denied"),
+            )
+            .mount(&server)
+            .await;
+
+        let auth = ext_auth(
+            &server.uri(),
+            &[("upstream-headers", "x-user"), ("cache-secs", "60")],
+        )
+        .unwrap();
+
+        let Decision::Allow(mutations) = auth.decide(&request("Bearer good")).await else {
+            panic!("expected the request to be allowed");
+        };
+        let mut upstream = request("Bearer good");
+        mutations.apply(&mut upstream).unwrap();
+        assert_eq!(upstream.headers["x-user"], "alice");
+
+        // Answered from the cache, the mock expects a single call.
+        assert!(matches!(
+            auth.decide(&request("Bearer good")).await,
+            Decision::Allow(_)
+        ));
+
+        let Decision::Deny(answer) = auth.decide(&request("Bearer bad")).await else {
+            panic!("expected the request to be denied");
+        };
+        let header = answer.header().unwrap();
+        assert_eq!(header.status, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(header.headers["www-authenticate"], "Bearer");
+        assert_eq!(header.headers[CONTENT_LENGTH], "6");
+    }
+
+    #[tokio::test]
+    async fn test_failure_mode() {
+        // Nothing listens on the discard port.
+        let url = "http://127.0.0.1:9/check";
+
+        let closed = ext_auth(url, &[]).unwrap();
+        let Decision::Deny(answer) = closed.decide(&request("Bearer good")).await else {
+            panic!("expected a failing service to refuse the request");
+        };
+        assert_eq!(
+            answer.header().unwrap().status,
+            http::StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let open = ext_auth(url, &[("failure-mode-allow", "true")]).unwrap();
+        assert_eq!(
+            open.decide(&request("Bearer good")).await,
+            Decision::Allow(RequestMutations::default())
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashSet};
+
+use async_trait::async_trait;
+use pingora::{Error, Result};
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::{
+    client_ip,
+    geoip::{self, Location},
+    proxy::{
+        filters::{builtin::helpers::ensure_empty, types::RequestFilterMod},
+        MotyaContext,
+    },
+};
+
+/// Answers with `status` by the country or network of the client, as the
+/// databases of `definitions.geoip` know them.
+pub struct GeoIpFilter {
+    countries: Countries,
+    deny_asns: HashSet<u32>,
+    status: u16,
+}
+
+enum Countries {
+    Any,
+    /// Clients of unknown countries are refused too.
+    Allow(HashSet<String>),
+    Deny(HashSet<String>),
+}
+
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn countries(value: String) -> Option<HashSet<String>> {
+    let countries: HashSet<_> = list(&value).map(str::to_ascii_uppercase).collect();
+    (!countries.is_empty()).then_some(countries)
+}
+
+impl GeoIpFilter {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "allow-countries",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated ISO country codes to accept, all others are refused",
+        },
+        FilterArg {
+            name: "deny-countries",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated ISO country codes to refuse",
+        },
+        FilterArg {
+            name: "deny-asns",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated autonomous system numbers to refuse",
+        },
+        FilterArg {
+            name: "status",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "Status of refused requests, defaults to 403",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let countries = match (
+            settings.remove("allow-countries").and_then(countries),
+            settings.remove("deny-countries").and_then(countries),
+        ) {
+            (None, None) => Countries::Any,
+            (Some(allow), None) => Countries::Allow(allow),
+            (None, Some(deny)) => Countries::Deny(deny),
+            (Some(_), Some(_)) => {
+                tracing::error!("'allow-countries' and 'deny-countries' exclude each other");
+                return Err(Error::new_str("Conflicting geoip country lists"));
+            }
+        };
+
+        let deny_asns = settings.remove("deny-asns").unwrap_or_default();
+        let deny_asns = list(&deny_asns)
+            .map(|asn| {
+                let number = asn.strip_prefix("AS").unwrap_or(asn);
+                number.parse::<u32>().map_err(|_| {
+                    tracing::error!("Invalid autonomous system number '{asn}'");
+                    Error::new_str("Invalid geoip ASN")
+                })
+            })
+            .collect::<Result<HashSet<_>>>()?;
+
+        if matches!(countries, Countries::Any) && deny_asns.is_empty() {
+            tracing::error!("Expected 'allow-countries', 'deny-countries' or 'deny-asns'");
+            return Err(Error::new_str("Missing geoip rules"));
+        }
+
+        let status = match settings.remove("status") {
+            None => 403,
+            Some(status) => match status.parse::<u16>() {
+                Ok(status) if (400..600).contains(&status) => status,
+                _ => {
+                    tracing::error!("Status must be a 4xx or 5xx code, found '{status}'");
+                    return Err(Error::new_str("Invalid geoip status"));
+                }
+            },
+        };
+
+        ensure_empty(&settings)?;
+
+        Ok(Self {
+            countries,
+            deny_asns,
+            status,
+        })
+    }
+
+    fn refuses(&self, location: &Location) -> bool {
+        let country = location.country.as_ref();
+
+        let by_country = match &self.countries {
+            Countries::Any => false,
+            Countries::Allow(allow) => !country.is_some_and(|country| allow.contains(country)),
+            Countries::Deny(deny) => country.is_some_and(|country| deny.contains(country)),
+        };
+
+        by_country
+            || location
+                .asn
+                .is_some_and(|asn| self.deny_asns.contains(&asn))
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for GeoIpFilter {
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+        // Clients on Unix sockets have no address to locate.
+        let Some(ip) = client_ip::of(session) else {
+            return Ok(false);
+        };
+
+        if !self.refuses(&geoip::locate(ip)) {
+            return Ok(false);
+        }
+
+        session
+            .downstream_session
+            .respond_error(self.status)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geoip(settings: &[(&str, &str)]) -> Result<GeoIpFilter> {
+        GeoIpFilter::from_settings(
+            settings
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    fn location(country: Option<&str>, asn: Option<u32>) -> Location {
+        Location {
+            country: country.map(str::to_string),
+            asn,
+        }
+    }
+
+    #[test]
+    fn test_refuses() {
+        let deny = geoip(&[("deny-countries", "ru, kp"), ("deny-asns", "AS64500,64501")]).unwrap();
+        assert!(deny.refuses(&location(Some("RU"), None)));
+        assert!(deny.refuses(&location(Some("DE"), Some(64501))));
+        assert!(!deny.refuses(&location(Some("DE"), Some(64502))));
+        assert!(!deny.refuses(&location(None, None)));
+
+        let allow = geoip(&[("allow-countries", "DE,FR")]).unwrap();
+        assert!(!allow.refuses(&location(Some("FR"), None)));
+        assert!(allow.refuses(&location(Some("US"), None)));
+        assert!(allow.refuses(&location(None, None)));
+    }
+
+    #[test]
+    fn test_from_settings() {
+        assert_eq!(geoip(&[("deny-asns", "64500")]).unwrap().status, 403);
+        assert!(geoip(&[]).is_err());
+        assert!(geoip(&[("deny-countries", " , ")]).is_err());
+        assert!(geoip(&[("allow-countries", "DE"), ("deny-countries", "RU")]).is_err());
+        assert!(geoip(&[("deny-asns", "ASN64500")]).is_err());
+        assert!(geoip(&[("deny-asns", "64500"), ("status", "200")]).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use http::HeaderName;
+use pingora::{Error, Result};
+use pingora_http::RequestHeader;
+use pingora_proxy::Session;
+use regex::Regex;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::{ensure_empty, extract_val},
+        types::RequestFilterMod,
+    },
+    MotyaContext,
+};
+
+const ARGS: &[FilterArg] = &[
+    FilterArg {
+        name: "header",
+        required: true,
+        kind: ArgKind::String,
+        description: "Name of the request header",
+    },
+    FilterArg {
+        name: "pattern",
+        required: false,
+        kind: ArgKind::Regex,
+        description: "Regex one of the values has to match, any value matches without it",
+    },
+    FilterArg {
+        name: "status",
+        required: false,
+        kind: ArgKind::Integer,
+        description: "Status of refused requests, defaults to 403",
+    },
+];
+
+/// A request header, and optionally a regex one of its values has to match.
+struct HeaderMatch {
+    header: HeaderName,
+    pattern: Option<Regex>,
+    status: u16,
+}
+
+impl HeaderMatch {
+    fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let header = extract_val("header", &mut settings)?;
+        let header = HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+            tracing::error!("Invalid header name '{header}'");
+            Error::new_str("Invalid header name")
+        })?;
+
+        let pattern = match settings.remove("pattern") {
+            None => None,
+            Some(pattern) => Some(Regex::new(&pattern).map_err(|e| {
+                tracing::error!("Bad pattern: '{pattern}': {e:?}");
+                Error::new_str("Error building regex")
+            })?),
+        };
+
+        let status = match settings.remove("status") {
+            None => 403,
+            Some(status) => match status.parse::<u16>() {
+                Ok(status) if (400..600).contains(&status) => status,
+                _ => {
+                    tracing::error!("Status must be a 4xx or 5xx code, found '{status}'");
+                    return Err(Error::new_str("Invalid header filter status"));
+                }
+            },
+        };
+
+        ensure_empty(&settings)?;
+
+        Ok(Self {
+            header,
+            pattern,
+            status,
+        })
+    }
+
+    fn matches(&self, req: &RequestHeader) -> bool {
+        let mut values = req.headers.get_all(&self.header).iter();
+
+        match &self.pattern {
+            None => values.next().is_some(),
+            // Values that are not UTF-8 never match.
+            Some(pattern) => {
+                values.any(|value| value.to_str().is_ok_and(|value| pattern.is_match(value)))
+            }
+        }
+    }
+
+    async fn refuse(&self, session: &mut Session) -> Result<bool> {
+        session
+            .downstream_session
+            .respond_error(self.status)
+            .await?;
+        Ok(true)
+    }
+}
+
+/// Answers with `status` unless the request carries `header`, with a value
+/// matching `pattern` when one is given.
+pub struct RequireHeader(HeaderMatch);
+
+impl RequireHeader {
+    pub const ARGS: &[FilterArg] = ARGS;
+
+    pub fn from_settings(settings: BTreeMap<String, String>) -> Result<Self> {
+        HeaderMatch::from_settings(settings).map(Self)
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for RequireHeader {
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+        if self.0.matches(session.req_header()) {
+            return Ok(false);
+        }
+
+        self.0.refuse(session).await
+    }
+}
+
+/// Answers with `status` when the request carries `header`, with a value
+/// matching `pattern` when one is given.
+pub struct DenyHeader(HeaderMatch);
+
+impl DenyHeader {
+    pub const ARGS: &[FilterArg] = ARGS;
+
+    pub fn from_settings(settings: BTreeMap<String, String>) -> Result<Self> {
+        HeaderMatch::from_settings(settings).map(Self)
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for DenyHeader {
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+        if !self.0.matches(session.req_header()) {
+            return Ok(false);
+        }
+
+        self.0.refuse(session).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_match(settings: &[(&str, &str)]) -> Result<HeaderMatch> {
+        HeaderMatch::from_settings(
+            settings
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    fn request(headers: &[(&str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        for (name, value) in headers {
+            req.append_header(name.to_string(), *value).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_matches() {
+        let presence = header_match(&[("header", "X-Internal")]).unwrap();
+        assert!(presence.matches(&request(&[("x-internal", "")])));
+        assert!(!presence.matches(&request(&[])));
+
+        let token = header_match(&[("header", "X-Internal"), ("pattern", "^s3cr3t$")]).unwrap();
+        assert!(token.matches(&request(&[
+            ("x-internal", "other"),
+            ("x-internal", "s3cr3t")
+        ])));
+        assert!(!token.matches(&request(&[("x-internal", "s3cr3t-not")])));
+        assert!(!token.matches(&request(&[])));
+    }
+
+    #[test]
+    fn test_from_settings() {
+        assert_eq!(
+            header_match(&[("header", "X-Internal")]).unwrap().status,
+            403
+        );
+        assert!(header_match(&[]).is_err());
+        assert!(header_match(&[("header", "bad header")]).is_err());
+        assert!(header_match(&[("header", "x-a"), ("pattern", "(")]).is_err());
+        assert!(header_match(&[("header", "x-a"), ("status", "302")]).is_err());
+    }
+}
@@ -1,5 +1,15 @@
+pub mod block_tls_fingerprint;
 pub mod cidr_range;
+pub mod ext_auth;
+pub mod geoip;
+pub mod header_gate;
 pub mod helpers;
+pub mod post_select;
+pub mod redirect;
 pub mod request;
+pub mod request_body;
+pub mod require_flag;
 pub mod response;
+pub mod response_body;
 pub mod simple_response;
+pub mod upgrade;
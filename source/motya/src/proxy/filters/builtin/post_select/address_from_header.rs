@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, net::SocketAddr, str::FromStr};
+
+use async_trait::async_trait;
+use cidr::IpCidr;
+use pingora::{prelude::HttpPeer, protocols::l4::socket::SocketAddr as PeerAddr, Error, Result};
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::{ensure_empty, extract_val},
+        types::PostSelectFilterMod,
+    },
+    MotyaContext,
+};
+
+/// Sends the request to the address in a request header instead of the chosen
+/// backend, as long as the address is in the allowed ranges. TLS settings of
+/// the backend are kept.
+pub struct AddressFromHeader {
+    header: String,
+    allow: Vec<IpCidr>,
+}
+
+impl AddressFromHeader {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "header",
+            required: true,
+            kind: ArgKind::String,
+            description: "Request header holding an 'IP:PORT' address",
+        },
+        FilterArg {
+            name: "allow",
+            required: true,
+            kind: ArgKind::String,
+            description: "Comma separated CIDR ranges the address must be in",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let header = extract_val("header", &mut settings)?;
+        let allow = extract_val("allow", &mut settings)?
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                IpCidr::from_str(range).map_err(|err| {
+                    tracing::error!("Bad CIDR range '{range}': {err}");
+                    Error::new_str("Bad CIDR range!")
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ensure_empty(&settings)?;
+
+        if allow.is_empty() {
+            tracing::error!("No 'allow' range for '{header}'");
+            return Err(Error::new_str("No allowed range!"));
+        }
+
+        Ok(Self { header, allow })
+    }
+
+    fn address(&self, value: &str) -> Option<SocketAddr> {
+        value
+            .trim()
+            .parse::<SocketAddr>()
+            .ok()
+            .filter(|addr| self.allow.iter().any(|range| range.contains(&addr.ip())))
+    }
+}
+
+#[async_trait]
+impl PostSelectFilterMod for AddressFromHeader {
+    async fn post_select_filter(
+        &self,
+        session: &mut Session,
+        peer: &mut HttpPeer,
+        _ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        let Some(value) = session
+            .req_header()
+            .headers
+            .get(self.header.as_str())
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Ok(());
+        };
+
+        match self.address(value) {
+            Some(addr) => peer._address = PeerAddr::Inet(addr),
+            None => tracing::debug!("Ignoring '{}', '{value}' is not allowed", self.header),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address() {
+        let filter = AddressFromHeader::from_settings(BTreeMap::from([
+            ("header".to_string(), "x-shard".to_string()),
+            (
+                "allow".to_string(),
+                "10.0.0.0/24, 192.168.1.7/32".to_string(),
+            ),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            filter.address(" 10.0.0.5:8080"),
+            Some("10.0.0.5:8080".parse().unwrap())
+        );
+        assert_eq!(filter.address("10.0.1.5:8080"), None);
+        assert_eq!(filter.address("192.168.1.7"), None);
+
+        let bad = BTreeMap::from([
+            ("header".to_string(), "x-shard".to_string()),
+            ("allow".to_string(), "10.0.0.0/33".to_string()),
+        ]);
+        assert!(AddressFromHeader::from_settings(bad).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use pingora::{prelude::HttpPeer, protocols::ALPN, Error, Result};
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::{ensure_empty, extract_val},
+        types::PostSelectFilterMod,
+    },
+    MotyaContext,
+};
+
+/// Overrides the HTTP versions offered to the chosen backend.
+pub struct Alpn {
+    alpn: ALPN,
+}
+
+impl Alpn {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "proto",
+        required: true,
+        kind: ArgKind::String,
+        description: "One of 'h1-only', 'h2-only' or 'h2-or-h1'",
+    }];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let proto = extract_val("proto", &mut settings)?;
+        ensure_empty(&settings)?;
+
+        let alpn = match proto.as_str() {
+            "h1-only" => ALPN::H1,
+            "h2-only" => ALPN::H2,
+            "h2-or-h1" => ALPN::H2H1,
+            other => {
+                tracing::error!("Unknown proto: '{other}'");
+                return Err(Error::new_str("Unknown proto!"));
+            }
+        };
+
+        Ok(Self { alpn })
+    }
+}
+
+#[async_trait]
+impl PostSelectFilterMod for Alpn {
+    async fn post_select_filter(
+        &self,
+        _session: &mut Session,
+        peer: &mut HttpPeer,
+        _ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        peer.options.alpn = self.alpn.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_settings() {
+        let proto = |proto: &str| {
+            Alpn::from_settings(BTreeMap::from([("proto".to_string(), proto.to_string())]))
+        };
+
+        assert!(matches!(proto("h2-only").unwrap().alpn, ALPN::H2));
+        assert!(proto("h3").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use pingora::{prelude::HttpPeer, Result};
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::{ensure_empty, extract_val},
+        types::PostSelectFilterMod,
+    },
+    MotyaContext,
+};
+
+/// Tells the upstream which backend the request was sent to, replacing the
+/// header on every attempt.
+pub struct BackendHeader {
+    key: String,
+}
+
+impl BackendHeader {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "key",
+        required: true,
+        kind: ArgKind::String,
+        description: "Header set to the address of the chosen backend",
+    }];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let key = extract_val("key", &mut settings)?;
+        ensure_empty(&settings)?;
+
+        Ok(Self { key })
+    }
+}
+
+#[async_trait]
+impl PostSelectFilterMod for BackendHeader {
+    async fn post_select_filter(
+        &self,
+        session: &mut Session,
+        peer: &mut HttpPeer,
+        _ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        session
+            .req_header_mut()
+            .insert_header(self.key.clone(), peer._address.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_settings() {
+        let settings = BTreeMap::from([("key".to_string(), "x-backend".to_string())]);
+        assert_eq!(
+            BackendHeader::from_settings(settings).unwrap().key,
+            "x-backend"
+        );
+
+        let settings = BTreeMap::from([
+            ("key".to_string(), "x-backend".to_string()),
+            ("value".to_string(), "nope".to_string()),
+        ]);
+        assert!(BackendHeader::from_settings(settings).is_err());
+    }
+}
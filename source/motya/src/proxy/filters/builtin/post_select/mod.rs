@@ -0,0 +1,4 @@
+pub mod address_from_header;
+pub mod alpn;
+pub mod backend_header;
+pub mod sni_from_header;
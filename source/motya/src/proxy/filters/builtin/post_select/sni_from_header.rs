@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use pingora::{prelude::HttpPeer, Error, Result};
+use pingora_proxy::Session;
+
+use motya_config::common_types::{
+    filter_schema::{ArgKind, FilterArg},
+    host_name::ascii_host,
+};
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::{ensure_empty, extract_val},
+        types::PostSelectFilterMod,
+    },
+    MotyaContext,
+};
+
+/// Takes the TLS SNI of the upstream connection from a request header, so one
+/// connector serves tenants with their own certificates.
+pub struct SniFromHeader {
+    header: String,
+    suffix: Option<String>,
+}
+
+impl SniFromHeader {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "header",
+            required: true,
+            kind: ArgKind::String,
+            description: "Request header holding the server name",
+        },
+        FilterArg {
+            name: "suffix",
+            required: false,
+            kind: ArgKind::String,
+            description: "Only names ending with this suffix are used, e.g. '.tenants.example.com'",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let header = extract_val("header", &mut settings)?;
+        let suffix = settings.remove("suffix").map(|suffix| {
+            let ascii = match suffix.strip_prefix('.') {
+                Some(domain) => ascii_host(domain).map(|domain| format!(".{domain}")),
+                None => ascii_host(&suffix),
+            };
+            ascii.unwrap_or_else(|| suffix.to_ascii_lowercase())
+        });
+        ensure_empty(&settings)?;
+
+        if suffix.as_deref() == Some("") {
+            tracing::error!("Empty 'suffix' of the SNI filter");
+            return Err(Error::new_str("Empty suffix!"));
+        }
+
+        Ok(Self { header, suffix })
+    }
+
+    /// The server name in `value` in punycode, `None` when it is not a plain
+    /// host name or lacks the suffix.
+    fn server_name(&self, value: &str) -> Option<String> {
+        let name = ascii_host(value)?;
+
+        let suffixed = self
+            .suffix
+            .as_deref()
+            .is_none_or(|suffix| name.len() > suffix.len() && name.ends_with(suffix));
+
+        suffixed.then_some(name)
+    }
+}
+
+#[async_trait]
+impl PostSelectFilterMod for SniFromHeader {
+    async fn post_select_filter(
+        &self,
+        session: &mut Session,
+        peer: &mut HttpPeer,
+        _ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        let Some(value) = session
+            .req_header()
+            .headers
+            .get(self.header.as_str())
+            .and_then(|value| std::str::from_utf8(value.as_bytes()).ok())
+        else {
+            return Ok(());
+        };
+
+        match self.server_name(value) {
+            Some(name) => peer.sni = name,
+            None => tracing::debug!("Ignoring '{}', '{value}' is not allowed", self.header),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_name() {
+        let filter = SniFromHeader::from_settings(BTreeMap::from([
+            ("header".to_string(), "x-tenant-host".to_string()),
+            ("suffix".to_string(), ".Tenants.example.com".to_string()),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            filter.server_name(" Acme.tenants.example.com "),
+            Some("acme.tenants.example.com".to_string())
+        );
+        assert_eq!(filter.server_name(".tenants.example.com"), None);
+        assert_eq!(filter.server_name("evil.example.com"), None);
+        assert_eq!(filter.server_name("a/b.tenants.example.com"), None);
+        assert_eq!(
+            filter.server_name("Bücher.tenants.example.com"),
+            Some("xn--bcher-kva.tenants.example.com".to_string())
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::header::{CONTENT_LENGTH, LOCATION};
+use pingora::{Error, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::{ensure_empty, extract_val},
+        types::RequestFilterMod,
+    },
+    virtual_host::request_host,
+    MotyaContext,
+};
+
+/// Answers with a redirect instead of proxying, e.g. from HTTP to HTTPS.
+/// `location` may reference `{scheme}`, `{host}` and `{path}`, the path
+/// including the query.
+pub struct Redirect {
+    status: u16,
+    location: String,
+}
+
+impl Redirect {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "location",
+            required: true,
+            kind: ArgKind::String,
+            description: "Target, may use {scheme}, {host} and {path}",
+        },
+        FilterArg {
+            name: "status",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "301, 302, 307 or 308, defaults to 301",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let location = extract_val("location", &mut settings)?;
+
+        let status = match settings.remove("status").as_deref() {
+            None => 301,
+            Some(status @ ("301" | "302" | "307" | "308")) => status.parse().unwrap(),
+            Some(other) => {
+                tracing::error!("Redirect status must be 301, 302, 307 or 308, found '{other}'");
+                return Err(Error::new_str("Invalid redirect status"));
+            }
+        };
+
+        ensure_empty(&settings)?;
+
+        Ok(Self { status, location })
+    }
+
+    /// A `301` to the same host and path over HTTPS.
+    pub fn to_https() -> Self {
+        Self {
+            status: 301,
+            location: "https://{host}{path}".into(),
+        }
+    }
+
+    /// `None` when the template needs a host the request does not carry.
+    fn location(&self, req: &RequestHeader, tls: bool) -> Option<String> {
+        let scheme = if tls { "https" } else { "http" };
+        let mut location = self.location.replace("{scheme}", scheme);
+
+        if location.contains("{host}") {
+            location = location.replace("{host}", &request_host(req)?);
+        }
+
+        let path = req.uri.path_and_query().map_or("/", |path| path.as_str());
+
+        Some(location.replace("{path}", path))
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for Redirect {
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+        let tls = session
+            .downstream_session
+            .digest()
+            .is_some_and(|digest| digest.ssl_digest.is_some());
+
+        let Some(location) = self.location(session.req_header(), tls) else {
+            session.downstream_session.respond_error(400).await?;
+            return Ok(true);
+        };
+
+        let mut response = ResponseHeader::build(self.status, Some(2))?;
+        response.insert_header(LOCATION, location)?;
+        response.insert_header(CONTENT_LENGTH, "0")?;
+
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(Bytes::new(), true)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::HOST;
+
+    use super::*;
+
+    fn redirect(location: &str, status: Option<&str>) -> Result<Redirect> {
+        let mut settings = BTreeMap::from([("location".to_string(), location.to_string())]);
+        if let Some(status) = status {
+            settings.insert("status".to_string(), status.to_string());
+        }
+        Redirect::from_settings(settings)
+    }
+
+    #[test]
+    fn test_location() {
+        let mut req = RequestHeader::build("GET", b"/shop/cart?id=7", None).unwrap();
+        req.insert_header(HOST, "Example.com:8080").unwrap();
+
+        let https = redirect("https://{host}{path}", Some("308")).unwrap();
+        assert_eq!(https.status, 308);
+        assert_eq!(
+            https.location(&req, false).as_deref(),
+            Some("https://example.com/shop/cart?id=7")
+        );
+
+        let canonical = redirect("{scheme}://www.example.com{path}", None).unwrap();
+        assert_eq!(canonical.status, 301);
+        assert_eq!(
+            canonical.location(&req, true).as_deref(),
+            Some("https://www.example.com/shop/cart?id=7")
+        );
+
+        let no_host = RequestHeader::build("GET", b"/", None).unwrap();
+        assert_eq!(https.location(&no_host, false), None);
+        assert_eq!(
+            canonical.location(&no_host, false).as_deref(),
+            Some("http://www.example.com/")
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        assert!(redirect("/", Some("302")).is_ok());
+        assert!(redirect("/", Some("200")).is_err());
+        assert!(redirect("/", Some("moved")).is_err());
+    }
+}
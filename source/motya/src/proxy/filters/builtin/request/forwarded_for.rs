@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use http::HeaderName;
+use pingora::{Error, Result};
+use pingora_http::RequestHeader;
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::{
+    client_ip,
+    proxy::{
+        filters::{builtin::helpers::ensure_empty, types::RequestModifyMod},
+        MotyaContext,
+    },
+};
+
+/// Tells the upstream the address of the client, as resolved through
+/// `system.trusted-proxies` and anonymized by `system.client-ip-privacy`. A
+/// value sent by the client is replaced.
+pub struct ForwardedFor {
+    header: HeaderName,
+}
+
+impl ForwardedFor {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "header",
+        required: false,
+        kind: ArgKind::String,
+        description: "Header carrying the client address, defaults to 'X-Forwarded-For'",
+    }];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let header = match settings.remove("header") {
+            None => HeaderName::from_static("x-forwarded-for"),
+            Some(header) => HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+                tracing::error!("Invalid header name '{header}'");
+                Error::new_str("Invalid forwarded-for header")
+            })?,
+        };
+
+        ensure_empty(&settings)?;
+
+        Ok(Self { header })
+    }
+}
+
+#[async_trait]
+impl RequestModifyMod for ForwardedFor {
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        header: &mut RequestHeader,
+        _ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        header.remove_header(&self.header);
+
+        // Clients on Unix sockets have no address to forward.
+        let ip = client_ip::of(session).map(client_ip::anonymize);
+
+        if let Some(ip) = ip {
+            header.insert_header(self.header.clone(), ip.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_settings() {
+        let filter = ForwardedFor::from_settings(BTreeMap::new()).unwrap();
+        assert_eq!(filter.header, "x-forwarded-for");
+
+        let settings = BTreeMap::from([("header".to_string(), "X-Real-IP".to_string())]);
+        let filter = ForwardedFor::from_settings(settings).unwrap();
+        assert_eq!(filter.header, "x-real-ip");
+
+        let settings = BTreeMap::from([("header".to_string(), "bad header".to_string())]);
+        assert!(ForwardedFor::from_settings(settings).is_err());
+    }
+}
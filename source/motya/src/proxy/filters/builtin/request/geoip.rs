@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use http::HeaderName;
+use pingora::{Error, Result};
+use pingora_http::RequestHeader;
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::{
+    client_ip,
+    geoip::{self, Location},
+    proxy::{
+        filters::{builtin::helpers::ensure_empty, types::RequestModifyMod},
+        MotyaContext,
+    },
+};
+
+/// Tells the upstream the country and network of the client, as the
+/// databases of `definitions.geoip` know them. Values sent by the client are
+/// removed, also when nothing is known of its address.
+pub struct GeoIpHeaders {
+    country: HeaderName,
+    asn: HeaderName,
+}
+
+fn header(
+    settings: &mut BTreeMap<String, String>,
+    key: &str,
+    default: &'static str,
+) -> Result<HeaderName> {
+    match settings.remove(key) {
+        None => Ok(HeaderName::from_static(default)),
+        Some(header) => HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+            tracing::error!("Invalid header name '{header}'");
+            Error::new_str("Invalid geoip header")
+        }),
+    }
+}
+
+impl GeoIpHeaders {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "country-header",
+            required: false,
+            kind: ArgKind::String,
+            description: "Header carrying the ISO country code, defaults to 'X-Geo-Country'",
+        },
+        FilterArg {
+            name: "asn-header",
+            required: false,
+            kind: ArgKind::String,
+            description: "Header carrying the autonomous system number, defaults to 'X-Geo-ASN'",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let country = header(&mut settings, "country-header", "x-geo-country")?;
+        let asn = header(&mut settings, "asn-header", "x-geo-asn")?;
+
+        ensure_empty(&settings)?;
+
+        Ok(Self { country, asn })
+    }
+
+    fn enrich(&self, header: &mut RequestHeader, location: Location) -> Result<()> {
+        header.remove_header(&self.country);
+        header.remove_header(&self.asn);
+
+        if let Some(country) = location.country {
+            header.insert_header(self.country.clone(), country)?;
+        }
+        if let Some(asn) = location.asn {
+            header.insert_header(self.asn.clone(), asn.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RequestModifyMod for GeoIpHeaders {
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        header: &mut RequestHeader,
+        _ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        // Clients on Unix sockets have no address to locate.
+        let location = client_ip::of(session)
+            .map(geoip::locate)
+            .unwrap_or_default();
+
+        self.enrich(header, location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enrich() {
+        let filter = GeoIpHeaders::from_settings(BTreeMap::new()).unwrap();
+
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("x-geo-country", "ZZ").unwrap();
+        req.insert_header("x-geo-asn", "1").unwrap();
+
+        let location = Location {
+            country: Some("DE".to_string()),
+            asn: None,
+        };
+        filter.enrich(&mut req, location).unwrap();
+
+        assert_eq!(req.headers["x-geo-country"], "DE");
+        assert!(req.headers.get("x-geo-asn").is_none());
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let settings = BTreeMap::from([("asn-header".to_string(), "X-Client-ASN".to_string())]);
+        let filter = GeoIpHeaders::from_settings(settings).unwrap();
+        assert_eq!(filter.country, "x-geo-country");
+        assert_eq!(filter.asn, "x-client-asn");
+
+        let settings = BTreeMap::from([("country-header".to_string(), "bad header".to_string())]);
+        assert!(GeoIpHeaders::from_settings(settings).is_err());
+    }
+}
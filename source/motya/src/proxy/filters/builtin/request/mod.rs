@@ -1,3 +1,5 @@
+pub mod forwarded_for;
+pub mod geoip;
 pub mod remove_headers;
 pub mod rewrite_path;
 pub mod strip_prefix;
@@ -6,6 +6,8 @@ use pingora_http::RequestHeader;
 use pingora_proxy::Session;
 use regex::Regex;
 
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
 use crate::proxy::{
     filters::{
         builtin::helpers::{ensure_empty, extract_val},
@@ -19,6 +21,13 @@ pub struct RemoveHeaderKeyRegex {
 }
 
 impl RemoveHeaderKeyRegex {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "pattern",
+        required: true,
+        kind: ArgKind::Regex,
+        description: "Regex matching the keys of the headers to remove",
+    }];
+
     pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
         let mat = extract_val("pattern", &mut settings)?;
 
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::str::FromStr;
 
@@ -8,23 +9,43 @@ use pingora_http::RequestHeader;
 use pingora_proxy::Session;
 use regex::Regex;
 
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
 use crate::proxy::{
     filters::{
         builtin::helpers::{ensure_empty, extract_val},
-        types::RequestModifyMod,
+        types::{PreRoutingFilterMod, RequestModifyMod},
     },
+    upstream_router::RouteCaptures,
     MotyaContext,
 };
 
 /// Filter: Rewrite Path Regex
 /// Replaces path based on regex pattern. Supports capture groups ($1, $2).
 /// Example: pattern="^/api/v1/(.*)", replace="/v2/$1"
+///
+/// The named groups of a regex route are available as `${name}`.
 pub struct RewritePathRegex {
     regex: Regex,
     replace: String,
 }
 
 impl RewritePathRegex {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "pattern",
+            required: true,
+            kind: ArgKind::Regex,
+            description: "Regex matched against the path",
+        },
+        FilterArg {
+            name: "replace",
+            required: true,
+            kind: ArgKind::String,
+            description: "Replacement, may reference capture groups",
+        },
+    ];
+
     pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
         let pattern = extract_val("pattern", &mut settings)?;
         let replace = extract_val("replace", &mut settings)?;
@@ -40,15 +61,35 @@ impl RewritePathRegex {
     }
 }
 
-#[async_trait]
-impl RequestModifyMod for RewritePathRegex {
-    async fn upstream_request_filter(
-        &self,
-        _session: &mut Session,
-        header: &mut RequestHeader,
-        _ctx: &mut MotyaContext,
-    ) -> Result<()> {
-        match rewrite_uri_path_regex(&header.uri, &self.regex, &self.replace) {
+impl RewritePathRegex {
+    /// `replace` with the `${name}` references to named groups of a regex
+    /// route filled in. Groups of the filter's own pattern take precedence.
+    fn replacement(&self, captures: Option<&RouteCaptures>) -> Cow<'_, str> {
+        let Some(RouteCaptures(captures)) = captures else {
+            return Cow::Borrowed(&self.replace);
+        };
+
+        let mut replace = self.replace.clone();
+
+        for (name, value) in captures {
+            if self
+                .regex
+                .capture_names()
+                .flatten()
+                .any(|own| own == name.as_str())
+            {
+                continue;
+            }
+            replace = replace.replace(&format!("${{{name}}}"), &value.replace('$', "$$"));
+        }
+
+        Cow::Owned(replace)
+    }
+
+    fn rewrite(&self, header: &mut RequestHeader, captures: Option<&RouteCaptures>) -> Result<()> {
+        let replace = self.replacement(captures);
+
+        match rewrite_uri_path_regex(&header.uri, &self.regex, &replace) {
             Ok(Some(new_uri)) => {
                 tracing::debug!("RewritePath: {} -> {}", header.uri.path(), new_uri.path());
                 header.set_uri(new_uri);
@@ -63,6 +104,31 @@ impl RequestModifyMod for RewritePathRegex {
     }
 }
 
+#[async_trait]
+impl RequestModifyMod for RewritePathRegex {
+    async fn upstream_request_filter(
+        &self,
+        _session: &mut Session,
+        header: &mut RequestHeader,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        self.rewrite(header, ctx.extensions().get::<RouteCaptures>())
+    }
+}
+
+/// Rewrites the downstream request, so the route is matched on the new path.
+#[async_trait]
+impl PreRoutingFilterMod for RewritePathRegex {
+    async fn pre_routing_filter(
+        &self,
+        session: &mut Session,
+        _ctx: &mut MotyaContext,
+    ) -> Result<bool> {
+        self.rewrite(session.req_header_mut(), None)?;
+        Ok(false)
+    }
+}
+
 fn rewrite_uri_path_regex(
     original_uri: &Uri,
     regex: &Regex,
@@ -170,6 +236,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_route_captures() {
+        let filter = RewritePathRegex::from_settings(BTreeMap::from([
+            ("pattern".to_string(), "^/api/(?<rest>.*)$".to_string()),
+            ("replace".to_string(), "/users/${id}/${rest}".to_string()),
+        ]))
+        .unwrap();
+
+        let captures = RouteCaptures(vec![
+            ("id".into(), "42".into()),
+            ("rest".into(), "ignored".into()),
+        ]);
+
+        let mut header = RequestHeader::build("GET", b"/api/posts", None).unwrap();
+        filter.rewrite(&mut header, Some(&captures)).unwrap();
+        assert_eq!(header.uri.path(), "/users/42/posts");
+    }
+
     #[test]
     fn test_rewrite_to_root() -> RewriteResult<()> {
         let original_uri = create_uri("/some/deep/path");
@@ -7,6 +7,8 @@ use pingora::{Error, Result};
 use pingora_http::RequestHeader;
 use pingora_proxy::Session;
 
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
 use crate::proxy::{
     filters::{
         builtin::helpers::{ensure_empty, extract_val},
@@ -20,6 +22,13 @@ pub struct StripPrefix {
 }
 
 impl StripPrefix {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "prefix",
+        required: true,
+        kind: ArgKind::String,
+        description: "Prefix removed from the path",
+    }];
+
     pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
         let prefix = extract_val("prefix", &mut settings)?;
         ensure_empty(&settings)?;
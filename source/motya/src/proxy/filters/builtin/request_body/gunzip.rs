@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, io::Write};
+
+use bytes::Bytes;
+use flate2::write::GzDecoder;
+use http::header::CONTENT_ENCODING;
+use pingora::{Error, ErrorType, Result};
+use pingora_http::RequestHeader;
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::filters::{
+    builtin::{
+        helpers::ensure_empty,
+        request_body::{max_bytes, BodyLimit},
+    },
+    types::{BodyStream, RequestBodyModifyMod},
+};
+
+/// Decompresses gzip request bodies for upstreams that only take plain ones.
+pub struct GunzipBody {
+    max_bytes: usize,
+}
+
+impl GunzipBody {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "max-bytes",
+        required: false,
+        kind: ArgKind::Integer,
+        description: "Largest decompressed body, defaults to 10 MiB",
+    }];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let max_bytes = max_bytes(&mut settings, 10 * 1024 * 1024)?;
+        ensure_empty(&settings)?;
+
+        Ok(Self { max_bytes })
+    }
+}
+
+impl RequestBodyModifyMod for GunzipBody {
+    fn request_body_start(
+        &self,
+        _session: &Session,
+        header: &mut RequestHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        let gzipped = header
+            .headers
+            .get(CONTENT_ENCODING)
+            .is_some_and(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"gzip"));
+
+        if !gzipped {
+            return None;
+        }
+
+        header.remove_header(&CONTENT_ENCODING);
+
+        Some(Box::new(GunzipStream {
+            decoder: GzDecoder::new(Vec::new()),
+            limit: BodyLimit::new(self.max_bytes),
+        }))
+    }
+}
+
+struct GunzipStream {
+    decoder: GzDecoder<Vec<u8>>,
+    limit: BodyLimit,
+}
+
+impl GunzipStream {
+    /// Moves what was decompressed so far to `out`.
+    fn drain(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        let decoded = self.decoder.get_mut();
+        self.limit.take(decoded.len())?;
+        out.append(decoded);
+        Ok(())
+    }
+}
+
+impl BodyStream for GunzipStream {
+    fn filter(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<Bytes> {
+        let invalid = |err| Error::because(ErrorType::HTTPStatus(400), "invalid gzip body", err);
+        let mut out = Vec::new();
+
+        // Small pieces keep a compression bomb from growing past the limit
+        // by much before it is refused.
+        for piece in chunk.chunks(8 * 1024) {
+            self.decoder.write_all(piece).map_err(invalid)?;
+            self.drain(&mut out)?;
+        }
+
+        if end_of_stream {
+            self.decoder.try_finish().map_err(invalid)?;
+            self.drain(&mut out)?;
+        }
+
+        Ok(out.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn stream(max_bytes: usize) -> GunzipStream {
+        GunzipStream {
+            decoder: GzDecoder::new(Vec::new()),
+            limit: BodyLimit::new(max_bytes),
+        }
+    }
+
+    #[test]
+    fn test_decompress_in_chunks() {
+        let compressed = gzip(b"{\"user\":\"alice\"}");
+        let (head, tail) = compressed.split_at(compressed.len() / 2);
+
+        let mut stream = stream(1024);
+        let mut body = Vec::new();
+        body.extend(stream.filter(Bytes::copy_from_slice(head), false).unwrap());
+        body.extend(stream.filter(Bytes::copy_from_slice(tail), false).unwrap());
+        body.extend(stream.filter(Bytes::new(), true).unwrap());
+
+        assert_eq!(body, b"{\"user\":\"alice\"}");
+    }
+
+    #[test]
+    fn test_limit() {
+        let compressed = gzip(&[0; 64 * 1024]);
+
+        let err = stream(1024).filter(compressed.into(), true).unwrap_err();
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(413));
+    }
+}
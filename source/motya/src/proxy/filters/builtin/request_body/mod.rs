@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+use pingora::{Error, ErrorType, Result};
+
+pub mod gunzip;
+pub mod strip_json_fields;
+
+/// Counts the bytes a request body filter produces or holds, refusing the
+/// request with `413 Payload Too Large` past `max`.
+pub struct BodyLimit {
+    max: usize,
+    seen: usize,
+}
+
+impl BodyLimit {
+    pub fn new(max: usize) -> Self {
+        Self { max, seen: 0 }
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<()> {
+        self.seen += len;
+
+        if self.seen > self.max {
+            return Err(Error::explain(
+                ErrorType::HTTPStatus(413),
+                "request body exceeds the filter's max-bytes",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the optional `max-bytes` setting, `default` when it is absent.
+pub fn max_bytes(settings: &mut BTreeMap<String, String>, default: usize) -> Result<usize> {
+    let Some(value) = settings.remove("max-bytes") else {
+        return Ok(default);
+    };
+
+    match value.parse::<usize>() {
+        Ok(max) if max > 0 => Ok(max),
+        _ => {
+            tracing::error!("Invalid max-bytes: '{value}'");
+            Err(Error::new_str("Invalid max-bytes!"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body_limit() {
+        let mut limit = BodyLimit::new(10);
+        assert!(limit.take(6).is_ok());
+        assert!(limit.take(4).is_ok());
+
+        let err = limit.take(1).unwrap_err();
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(413));
+    }
+}
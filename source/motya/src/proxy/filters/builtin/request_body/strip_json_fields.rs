@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use http::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use pingora::Result;
+use pingora_http::RequestHeader;
+use pingora_proxy::Session;
+use serde_json::Value;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::filters::{
+    builtin::{
+        helpers::{ensure_empty, extract_val},
+        request_body::{max_bytes, BodyLimit},
+    },
+    types::{BodyStream, RequestBodyModifyMod},
+};
+
+/// Removes top-level fields from JSON object request bodies, e.g. ones
+/// clients must not set themselves. The body is buffered, other bodies pass
+/// through untouched.
+pub struct StripJsonFields {
+    fields: Vec<String>,
+    max_bytes: usize,
+}
+
+impl StripJsonFields {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "fields",
+            required: true,
+            kind: ArgKind::String,
+            description: "Comma separated top-level fields to remove",
+        },
+        FilterArg {
+            name: "max-bytes",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "Largest body buffered for the rewrite, defaults to 1 MiB",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let fields = extract_val("fields", &mut settings)?
+            .split(',')
+            .map(|field| field.trim().to_string())
+            .filter(|field| !field.is_empty())
+            .collect();
+        let max_bytes = max_bytes(&mut settings, 1024 * 1024)?;
+        ensure_empty(&settings)?;
+
+        Ok(Self { fields, max_bytes })
+    }
+}
+
+impl RequestBodyModifyMod for StripJsonFields {
+    fn request_body_start(
+        &self,
+        _session: &Session,
+        header: &mut RequestHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        let json = header
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|essence| essence.trim().to_ascii_lowercase())
+            .is_some_and(|essence| essence == "application/json" || essence.ends_with("+json"));
+
+        if !json || header.headers.contains_key(CONTENT_ENCODING) {
+            return None;
+        }
+
+        Some(Box::new(StripJsonStream {
+            fields: self.fields.clone(),
+            buffer: Vec::new(),
+            limit: BodyLimit::new(self.max_bytes),
+        }))
+    }
+}
+
+struct StripJsonStream {
+    fields: Vec<String>,
+    buffer: Vec<u8>,
+    limit: BodyLimit,
+}
+
+impl BodyStream for StripJsonStream {
+    fn filter(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<Bytes> {
+        self.limit.take(chunk.len())?;
+        self.buffer.extend_from_slice(&chunk);
+
+        if !end_of_stream {
+            return Ok(Bytes::new());
+        }
+
+        let body = std::mem::take(&mut self.buffer);
+
+        // Anything but an object is for the upstream to reject.
+        let Ok(Value::Object(mut object)) = serde_json::from_slice::<Value>(&body) else {
+            return Ok(body.into());
+        };
+
+        for field in &self.fields {
+            object.remove(field);
+        }
+
+        Ok(serde_json::to_vec(&object).unwrap_or(body).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pingora::ErrorType;
+
+    use super::*;
+
+    fn stream(max_bytes: usize) -> StripJsonStream {
+        StripJsonStream {
+            fields: vec!["role".into(), "internal".into()],
+            buffer: Vec::new(),
+            limit: BodyLimit::new(max_bytes),
+        }
+    }
+
+    #[test]
+    fn test_strip_fields() {
+        let mut stream = stream(1024);
+
+        assert!(stream
+            .filter(Bytes::from(r#"{"user":"alice","ro"#), false)
+            .unwrap()
+            .is_empty());
+        let body = stream
+            .filter(Bytes::from(r#"le":"admin","nested":{"role":1}}"#), true)
+            .unwrap();
+
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"user": "alice", "nested": {"role": 1}})
+        );
+    }
+
+    #[test]
+    fn test_not_an_object() {
+        assert_eq!(
+            stream(1024).filter(Bytes::from("[1, 2]"), true).unwrap(),
+            "[1, 2]"
+        );
+    }
+
+    #[test]
+    fn test_limit() {
+        let err = stream(4)
+            .filter(Bytes::from("{\"a\":1}"), true)
+            .unwrap_err();
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(413));
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use pingora::{Error, Result};
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::{
+    flags::Flags,
+    proxy::{
+        filters::{
+            builtin::helpers::{ensure_empty, extract_val},
+            types::RequestFilterMod,
+        },
+        MotyaContext,
+    },
+};
+
+/// Answers with `status` unless the feature flag is set as required,
+/// `flag="NAME"` passes while it is on and `flag="!NAME"` while it is off.
+pub struct RequireFlag {
+    name: String,
+    enabled: bool,
+    status: u16,
+}
+
+impl RequireFlag {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "flag",
+            required: true,
+            kind: ArgKind::String,
+            description: "Flag that has to be on, or off with a leading '!'",
+        },
+        FilterArg {
+            name: "status",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "Status of refused requests, defaults to 404",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let flag = extract_val("flag", &mut settings)?;
+        let (name, enabled) = match flag.strip_prefix('!') {
+            Some(name) => (name.to_string(), false),
+            None => (flag, true),
+        };
+
+        if name.is_empty() {
+            tracing::error!("Missing flag name in 'flag'");
+            return Err(Error::new_str("Invalid flag"));
+        }
+
+        let status = match settings.remove("status") {
+            None => 404,
+            Some(status) => match status.parse::<u16>() {
+                Ok(status) if (400..600).contains(&status) => status,
+                _ => {
+                    tracing::error!("Flag status must be a 4xx or 5xx code, found '{status}'");
+                    return Err(Error::new_str("Invalid flag status"));
+                }
+            },
+        };
+
+        ensure_empty(&settings)?;
+
+        Ok(Self {
+            name,
+            enabled,
+            status,
+        })
+    }
+
+    fn passes(&self, flags: &Flags) -> bool {
+        flags.is_enabled(&self.name) == self.enabled
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for RequireFlag {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        if self.passes(ctx.flags()) {
+            return Ok(false);
+        }
+
+        session
+            .downstream_session
+            .respond_error(self.status)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn require(flag: &str, status: Option<&str>) -> Result<RequireFlag> {
+        let mut settings = BTreeMap::from([("flag".to_string(), flag.to_string())]);
+        if let Some(status) = status {
+            settings.insert("status".to_string(), status.to_string());
+        }
+        RequireFlag::from_settings(settings)
+    }
+
+    #[test]
+    fn test_passes() {
+        let flags = Flags::new(BTreeMap::from([("beta".to_string(), true)]));
+
+        assert!(require("beta", None).unwrap().passes(&flags));
+        assert!(!require("!beta", None).unwrap().passes(&flags));
+        // Undeclared flags are off.
+        assert!(!require("other", None).unwrap().passes(&flags));
+        assert!(require("!other", None).unwrap().passes(&flags));
+    }
+
+    #[test]
+    fn test_from_settings() {
+        assert_eq!(require("beta", None).unwrap().status, 404);
+        assert_eq!(require("beta", Some("503")).unwrap().status, 503);
+        assert!(require("beta", Some("200")).is_err());
+        assert!(require("!", None).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+
+use pingora::Result;
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::{
+    fingerprint,
+    proxy::{
+        filters::{builtin::helpers::ensure_empty, types::ResponseModifyMod},
+        MotyaContext,
+    },
+};
+
+const DEFAULT_HEADER: &str = "X-Config-Fingerprint";
+
+/// Tells clients which configuration answered, by its fingerprint.
+pub struct ConfigFingerprint {
+    header: String,
+}
+
+impl ConfigFingerprint {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "header",
+        required: false,
+        kind: ArgKind::String,
+        description: "Header name, defaults to X-Config-Fingerprint",
+    }];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let header = settings
+            .remove("header")
+            .unwrap_or_else(|| DEFAULT_HEADER.to_string());
+
+        ensure_empty(&settings)?;
+
+        Ok(Self { header })
+    }
+}
+
+impl ResponseModifyMod for ConfigFingerprint {
+    fn upstream_response_filter(
+        &self,
+        _session: &mut Session,
+        header: &mut ResponseHeader,
+        _ctx: &mut MotyaContext,
+    ) {
+        if let Some(fingerprint) = fingerprint::current() {
+            let _ = header.insert_header(self.header.clone(), fingerprint.as_str());
+        }
+    }
+}
@@ -1,2 +1,3 @@
+pub mod config_fingerprint;
 pub mod remove_header;
 pub mod upsert_header;
@@ -4,6 +4,8 @@ use pingora::Result;
 use pingora_http::ResponseHeader;
 use pingora_proxy::Session;
 
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
 use crate::proxy::{
     filters::{builtin::helpers::extract_val, types::ResponseModifyMod},
     MotyaContext,
@@ -15,6 +17,21 @@ pub struct UpsertHeader {
 }
 
 impl UpsertHeader {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "key",
+            required: true,
+            kind: ArgKind::String,
+            description: "Header name",
+        },
+        FilterArg {
+            name: "value",
+            required: true,
+            kind: ArgKind::String,
+            description: "Header value",
+        },
+    ];
+
     pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
         let key = extract_val("key", &mut settings)?;
         let value = extract_val("value", &mut settings)?;
@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, io::Write};
+
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use http::{
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, VARY},
+    Method, StatusCode,
+};
+use pingora::{Error, ErrorType, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::ensure_empty,
+        types::{BodyStream, ResponseBodyModifyMod},
+    },
+    negotiation::accepted_token,
+};
+
+/// Compresses responses of clients that accept gzip, unless the upstream
+/// already encoded them.
+pub struct GzipBody {
+    level: Compression,
+}
+
+impl GzipBody {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "level",
+        required: false,
+        kind: ArgKind::Integer,
+        description: "Compression level from 0 to 9, defaults to 6",
+    }];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let level = match settings.remove("level") {
+            None => Compression::default(),
+            Some(level) => match level.parse::<u32>() {
+                Ok(level) if level <= 9 => Compression::new(level),
+                _ => {
+                    tracing::error!("Invalid gzip level: '{level}'");
+                    return Err(Error::new_str("Invalid compression level!"));
+                }
+            },
+        };
+        ensure_empty(&settings)?;
+
+        Ok(Self { level })
+    }
+
+    fn applies(&self, session: &Session, header: &ResponseHeader) -> bool {
+        let request = session.req_header();
+
+        let accepted = request
+            .headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(accepted_token)
+            .any(|token| token == "gzip");
+
+        accepted
+            && request.method != Method::HEAD
+            && header.status != StatusCode::NO_CONTENT
+            && header.status != StatusCode::NOT_MODIFIED
+            && !header.headers.contains_key(CONTENT_ENCODING)
+    }
+}
+
+impl ResponseBodyModifyMod for GzipBody {
+    fn response_body_start(
+        &self,
+        session: &Session,
+        header: &mut ResponseHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        if !self.applies(session, header) {
+            return None;
+        }
+
+        header.insert_header(CONTENT_ENCODING, "gzip").ok()?;
+        header.append_header(VARY, "Accept-Encoding").ok()?;
+
+        Some(Box::new(GzipStream {
+            encoder: Some(GzEncoder::new(Vec::new(), self.level)),
+        }))
+    }
+}
+
+struct GzipStream {
+    /// `None` once the trailer was written.
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl BodyStream for GzipStream {
+    fn filter(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<Bytes> {
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(Bytes::new());
+        };
+
+        let failed = |err| Error::because(ErrorType::InternalError, "gzip failed", err);
+        encoder.write_all(&chunk).map_err(failed)?;
+
+        if end_of_stream {
+            let encoder = self.encoder.take().expect("checked above");
+            return encoder.finish().map(Bytes::from).map_err(failed);
+        }
+
+        Ok(std::mem::take(encoder.get_mut()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn test_from_settings() {
+        assert!(GzipBody::from_settings(BTreeMap::new()).is_ok());
+
+        let level = |level: &str| {
+            GzipBody::from_settings(BTreeMap::from([("level".to_string(), level.to_string())]))
+        };
+        assert_eq!(level("9").unwrap().level, Compression::best());
+        assert!(level("10").is_err());
+        assert!(level("fast").is_err());
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let mut stream = GzipStream {
+            encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+        };
+
+        let mut compressed = Vec::new();
+        for chunk in ["hello ", "streaming ", "world"] {
+            compressed.extend(stream.filter(Bytes::from(chunk), false).unwrap());
+        }
+        compressed.extend(stream.filter(Bytes::new(), true).unwrap());
+
+        let mut body = String::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "hello streaming world");
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use http::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE},
+    Method, StatusCode,
+};
+use pingora::{Error, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::filters::{
+    builtin::{
+        helpers::{ensure_empty, extract_val},
+        response_body::FixedBody,
+    },
+    types::{BodyStream, ResponseBodyModifyMod},
+};
+
+/// Sends upstream status codes on to the client as other codes, optionally
+/// with a fixed body in place of the upstream one.
+pub struct MapStatus {
+    map: BTreeMap<StatusCode, StatusCode>,
+    body: Option<Bytes>,
+    content_type: String,
+}
+
+impl MapStatus {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "map",
+            required: true,
+            kind: ArgKind::String,
+            description: "Comma separated 'FROM=TO' status codes, e.g. '404=200,500=503'",
+        },
+        FilterArg {
+            name: "body",
+            required: false,
+            kind: ArgKind::String,
+            description: "Body sent instead of the upstream one on mapped responses",
+        },
+        FilterArg {
+            name: "content-type",
+            required: false,
+            kind: ArgKind::String,
+            description: "Content type of 'body', defaults to application/json",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let map = parse_map(&extract_val("map", &mut settings)?)?;
+        let body = settings.remove("body").map(Bytes::from);
+        let content_type = settings
+            .remove("content-type")
+            .unwrap_or_else(|| "application/json".to_string());
+        ensure_empty(&settings)?;
+
+        Ok(Self {
+            map,
+            body,
+            content_type,
+        })
+    }
+
+    /// Maps the status of `header`, returning the body to send instead when
+    /// the response was mapped and there is one.
+    fn rewrite(&self, header: &mut ResponseHeader) -> Option<Bytes> {
+        let status = *self.map.get(&header.status)?;
+        header.set_status(status).ok()?;
+
+        let body = self.body.clone()?;
+        header
+            .insert_header(CONTENT_TYPE, self.content_type.as_str())
+            .ok()?;
+        header.remove_header(&CONTENT_ENCODING);
+
+        Some(body)
+    }
+}
+
+fn parse_map(value: &str) -> Result<BTreeMap<StatusCode, StatusCode>> {
+    let status = |code: &str| {
+        code.trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|code| (100..600).contains(code))
+            .and_then(|code| StatusCode::from_u16(code).ok())
+    };
+
+    let mut map = BTreeMap::new();
+
+    for pair in value.split(',') {
+        let Some((from, to)) = pair
+            .split_once('=')
+            .and_then(|(from, to)| status(from).zip(status(to)))
+        else {
+            tracing::error!("Invalid status mapping '{pair}', expected 'FROM=TO'");
+            return Err(Error::new_str("Invalid status map!"));
+        };
+
+        if map.insert(from, to).is_some() {
+            tracing::error!("Status {from} is mapped twice");
+            return Err(Error::new_str("Invalid status map!"));
+        }
+    }
+
+    Ok(map)
+}
+
+impl ResponseBodyModifyMod for MapStatus {
+    fn response_body_start(
+        &self,
+        session: &Session,
+        header: &mut ResponseHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        let body = self.rewrite(header)?;
+
+        if session.req_header().method == Method::HEAD {
+            return None;
+        }
+
+        Some(Box::new(FixedBody::new(body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_status(map: &str, body: Option<&str>) -> Result<MapStatus> {
+        let mut settings = BTreeMap::from([("map".to_string(), map.to_string())]);
+        if let Some(body) = body {
+            settings.insert("body".to_string(), body.to_string());
+        }
+        MapStatus::from_settings(settings)
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let filter = map_status("404=200, 500=503", None).unwrap();
+        assert_eq!(
+            filter.map,
+            BTreeMap::from([
+                (StatusCode::NOT_FOUND, StatusCode::OK),
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    StatusCode::SERVICE_UNAVAILABLE
+                ),
+            ])
+        );
+
+        for invalid in ["", "404", "404=", "404=abc", "404=600", "404=200,404=204"] {
+            assert!(map_status(invalid, None).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let filter = map_status("404=200,500=503", Some("{}")).unwrap();
+
+        let mut header = ResponseHeader::build(404, None).unwrap();
+        header.insert_header(CONTENT_TYPE, "text/html").unwrap();
+        header.insert_header(CONTENT_ENCODING, "gzip").unwrap();
+
+        assert_eq!(filter.rewrite(&mut header), Some(Bytes::from("{}")));
+        assert_eq!(header.status, StatusCode::OK);
+        assert_eq!(header.headers[CONTENT_TYPE], "application/json");
+        assert!(!header.headers.contains_key(CONTENT_ENCODING));
+
+        let mut header = ResponseHeader::build(502, None).unwrap();
+        assert_eq!(filter.rewrite(&mut header), None);
+        assert_eq!(header.status, StatusCode::BAD_GATEWAY);
+
+        // Without a body only the status changes.
+        let filter = map_status("500=503", None).unwrap();
+        let mut header = ResponseHeader::build(500, None).unwrap();
+        assert_eq!(filter.rewrite(&mut header), None);
+        assert_eq!(header.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,45 @@
+use bytes::Bytes;
+use pingora::Result;
+
+use crate::proxy::filters::types::BodyStream;
+
+pub mod gzip;
+pub mod map_status;
+pub mod regex_replace;
+pub mod replace;
+pub mod validate;
+
+/// Drops the upstream body and sends `body` at its end.
+pub struct FixedBody {
+    /// `None` once it was sent.
+    body: Option<Bytes>,
+}
+
+impl FixedBody {
+    pub fn new(body: Bytes) -> Self {
+        Self { body: Some(body) }
+    }
+}
+
+impl BodyStream for FixedBody {
+    fn filter(&mut self, _: Bytes, end_of_stream: bool) -> Result<Bytes> {
+        if !end_of_stream {
+            return Ok(Bytes::new());
+        }
+
+        Ok(self.body.take().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_body() {
+        let mut stream = FixedBody::new(Bytes::from("{}"));
+
+        assert_eq!(stream.filter(Bytes::from("not found"), false).unwrap(), "");
+        assert_eq!(stream.filter(Bytes::new(), true).unwrap(), "{}");
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use http::{header::CONTENT_ENCODING, Method};
+use pingora::{Error, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+use regex::bytes::Regex;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::filters::{
+    builtin::helpers::{ensure_empty, extract_val},
+    types::{BodyStream, ResponseBodyModifyMod},
+};
+
+/// Filter: Regex Body Replace
+/// Replaces every match of a regex in the response body, `$1` and `${name}`
+/// in the replacement refer to its groups. Matches may span chunks as long as
+/// they are at most `max-match-bytes` long. Encoded bodies are left alone.
+pub struct RegexReplaceBody {
+    regex: Regex,
+    replace: Bytes,
+    max_match: usize,
+}
+
+impl RegexReplaceBody {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "pattern",
+            required: true,
+            kind: ArgKind::Regex,
+            description: "Regex matched against the body",
+        },
+        FilterArg {
+            name: "replace",
+            required: true,
+            kind: ArgKind::String,
+            description: "Replacement, may reference capture groups",
+        },
+        FilterArg {
+            name: "max-match-bytes",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "Longest match found across chunks, defaults to 1024",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let pattern = extract_val("pattern", &mut settings)?;
+        let replace = extract_val("replace", &mut settings)?;
+        let max_match = match settings.remove("max-match-bytes") {
+            None => 1024,
+            Some(max) => match max.parse::<usize>() {
+                Ok(max) if max > 0 => max,
+                _ => {
+                    tracing::error!("Invalid max-match-bytes: '{max}'");
+                    return Err(Error::new_str("Invalid max-match-bytes!"));
+                }
+            },
+        };
+        ensure_empty(&settings)?;
+
+        let regex = Regex::new(&pattern).map_err(|e| {
+            tracing::error!("Bad regex pattern: '{pattern}': {e:?}");
+            Error::new_str("Error building regex for body replacement")
+        })?;
+
+        // An empty match would be replaced again at the start of every chunk.
+        if regex.is_match(b"") {
+            tracing::error!("Regex pattern '{pattern}' matches an empty body");
+            return Err(Error::new_str("Body replacement pattern matches nothing!"));
+        }
+
+        Ok(Self {
+            regex,
+            replace: replace.into(),
+            max_match,
+        })
+    }
+}
+
+impl ResponseBodyModifyMod for RegexReplaceBody {
+    fn response_body_start(
+        &self,
+        session: &Session,
+        header: &mut ResponseHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        if session.req_header().method == Method::HEAD
+            || header.headers.contains_key(CONTENT_ENCODING)
+        {
+            return None;
+        }
+
+        Some(Box::new(RegexReplaceStream {
+            regex: self.regex.clone(),
+            replace: self.replace.clone(),
+            max_match: self.max_match,
+            pending: Vec::new(),
+        }))
+    }
+}
+
+struct RegexReplaceStream {
+    regex: Regex,
+    replace: Bytes,
+    max_match: usize,
+    /// Last `max_match` bytes of the body seen so far, where a match may
+    /// still grow with the next chunk, and any match reaching into them.
+    pending: Vec<u8>,
+}
+
+impl BodyStream for RegexReplaceStream {
+    fn filter(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<Bytes> {
+        self.pending.extend_from_slice(&chunk);
+
+        // Matches ending before the last `max_match` bytes cannot change.
+        let settled = if end_of_stream {
+            self.pending.len()
+        } else {
+            self.pending.len().saturating_sub(self.max_match)
+        };
+
+        let mut out = Vec::with_capacity(self.pending.len());
+        let mut start = 0;
+        let mut flushed = None;
+
+        for captures in self.regex.captures_iter(&self.pending) {
+            let found = captures.get(0).expect("group 0 is the whole match");
+            if found.end() > settled {
+                flushed = Some(found.start());
+                break;
+            }
+
+            out.extend_from_slice(&self.pending[start..found.start()]);
+            captures.expand(&self.replace, &mut out);
+            start = found.end();
+        }
+
+        let flushed = flushed.unwrap_or(settled).max(start);
+
+        out.extend_from_slice(&self.pending[start..flushed]);
+        self.pending.drain(..flushed);
+
+        Ok(out.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(pattern: &str, replace: &str, max_match: usize) -> RegexReplaceStream {
+        RegexReplaceStream {
+            regex: Regex::new(pattern).unwrap(),
+            replace: Bytes::copy_from_slice(replace.as_bytes()),
+            max_match,
+            pending: Vec::new(),
+        }
+    }
+
+    fn run(stream: &mut RegexReplaceStream, chunks: &[&str]) -> String {
+        let mut out = Vec::new();
+        for chunk in chunks {
+            out.extend(
+                stream
+                    .filter(Bytes::copy_from_slice(chunk.as_bytes()), false)
+                    .unwrap(),
+            );
+        }
+        out.extend(stream.filter(Bytes::new(), true).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let settings = |pattern: &str| {
+            BTreeMap::from([
+                ("pattern".to_string(), pattern.to_string()),
+                ("replace".to_string(), "new".to_string()),
+            ])
+        };
+
+        assert!(RegexReplaceBody::from_settings(settings("old(er)?")).is_ok());
+        assert!(RegexReplaceBody::from_settings(settings("(")).is_err());
+        assert!(RegexReplaceBody::from_settings(settings("x*")).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_links_across_chunks() {
+        let mut stream = stream(
+            r#"href="http://old\.example\.com/([^"]*)""#,
+            r#"href="https://example.com/$1""#,
+            64,
+        );
+
+        assert_eq!(
+            run(
+                &mut stream,
+                &[
+                    r#"<a href="http://old.exa"#,
+                    r#"mple.com/docs">Docs</a> <a href="http://old.example.com/">"#,
+                    "Home</a>",
+                ]
+            ),
+            r#"<a href="https://example.com/docs">Docs</a> <a href="https://example.com/">Home</a>"#
+        );
+    }
+
+    #[test]
+    fn test_greedy_match_waits_for_more() {
+        let mut stream = stream("a+", "b", 8);
+
+        // The run of `a` may continue in the next chunk.
+        assert_eq!(stream.filter(Bytes::from("xaa"), false).unwrap(), "x");
+        assert_eq!(stream.filter(Bytes::from("aay"), false).unwrap(), "");
+        assert_eq!(stream.filter(Bytes::new(), true).unwrap(), "by");
+    }
+
+    #[test]
+    fn test_settled_bytes_are_flushed() {
+        let mut stream = stream("needle", "pin", 6);
+
+        assert_eq!(
+            stream
+                .filter(Bytes::from("a needle in the hay"), false)
+                .unwrap(),
+            "a pin in t"
+        );
+        assert_eq!(stream.filter(Bytes::new(), true).unwrap(), "he hay");
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use http::{header::CONTENT_ENCODING, Method};
+use pingora::{Error, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::filters::{
+    builtin::helpers::{ensure_empty, extract_val},
+    types::{BodyStream, ResponseBodyModifyMod},
+};
+
+/// Replaces every occurrence of a literal in the response body, also when it
+/// spans chunks. Encoded bodies are left alone.
+pub struct ReplaceBody {
+    find: Bytes,
+    replace: Bytes,
+}
+
+impl ReplaceBody {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "find",
+            required: true,
+            kind: ArgKind::String,
+            description: "Text to look for in the body",
+        },
+        FilterArg {
+            name: "replace",
+            required: true,
+            kind: ArgKind::String,
+            description: "Text written in its place",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let find = extract_val("find", &mut settings)?;
+        let replace = extract_val("replace", &mut settings)?;
+        ensure_empty(&settings)?;
+
+        if find.is_empty() {
+            tracing::error!("Empty 'find' of a body replacement");
+            return Err(Error::new_str("Nothing to find!"));
+        }
+
+        Ok(Self {
+            find: find.into(),
+            replace: replace.into(),
+        })
+    }
+}
+
+impl ResponseBodyModifyMod for ReplaceBody {
+    fn response_body_start(
+        &self,
+        session: &Session,
+        header: &mut ResponseHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        if session.req_header().method == Method::HEAD
+            || header.headers.contains_key(CONTENT_ENCODING)
+        {
+            return None;
+        }
+
+        Some(Box::new(ReplaceStream {
+            find: self.find.clone(),
+            replace: self.replace.clone(),
+            pending: Vec::new(),
+        }))
+    }
+}
+
+struct ReplaceStream {
+    find: Bytes,
+    replace: Bytes,
+    /// Tail of the body seen so far that is the start of a possible match.
+    pending: Vec<u8>,
+}
+
+impl BodyStream for ReplaceStream {
+    fn filter(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<Bytes> {
+        self.pending.extend_from_slice(&chunk);
+
+        let mut out = Vec::with_capacity(self.pending.len());
+        let mut start = 0;
+
+        while let Some(pos) = self.pending[start..]
+            .windows(self.find.len())
+            .position(|window| window == &self.find[..])
+        {
+            out.extend_from_slice(&self.pending[start..start + pos]);
+            out.extend_from_slice(&self.replace);
+            start += pos + self.find.len();
+        }
+
+        let rest = &self.pending[start..];
+        let held = if end_of_stream {
+            0
+        } else {
+            (1..self.find.len().min(rest.len() + 1))
+                .rev()
+                .find(|len| rest.ends_with(&self.find[..*len]))
+                .unwrap_or(0)
+        };
+        let flushed = self.pending.len() - held;
+
+        out.extend_from_slice(&self.pending[start..flushed]);
+        self.pending.drain(..flushed);
+
+        Ok(out.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(find: &str, replace: &str) -> ReplaceStream {
+        ReplaceStream {
+            find: Bytes::copy_from_slice(find.as_bytes()),
+            replace: Bytes::copy_from_slice(replace.as_bytes()),
+            pending: Vec::new(),
+        }
+    }
+
+    fn run(stream: &mut ReplaceStream, chunks: &[&str]) -> String {
+        let mut out = Vec::new();
+        for chunk in chunks {
+            out.extend(
+                stream
+                    .filter(Bytes::copy_from_slice(chunk.as_bytes()), false)
+                    .unwrap(),
+            );
+        }
+        out.extend(stream.filter(Bytes::new(), true).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let settings = |find: &str| {
+            BTreeMap::from([
+                ("find".to_string(), find.to_string()),
+                ("replace".to_string(), "new".to_string()),
+            ])
+        };
+
+        assert!(ReplaceBody::from_settings(settings("old")).is_ok());
+        assert!(ReplaceBody::from_settings(settings("")).is_err());
+    }
+
+    #[test]
+    fn test_replace_across_chunks() {
+        assert_eq!(
+            run(
+                &mut stream("world", "motya"),
+                &["hello wo", "rld, wor", "ld!"]
+            ),
+            "hello motya, motya!"
+        );
+        assert_eq!(run(&mut stream("aa", "b"), &["a", "aa", "a", "a"]), "bba");
+    }
+
+    #[test]
+    fn test_partial_match_flushed() {
+        let mut stream = stream("needle", "pin");
+
+        // The tail could still become a match.
+        assert_eq!(
+            stream.filter(Bytes::from("hay nee"), false).unwrap(),
+            "hay "
+        );
+        assert_eq!(stream.filter(Bytes::new(), true).unwrap(), "nee");
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use http::{
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+    HeaderName, Method,
+};
+use pingora::{Error, ErrorType, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+use regex::Regex;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::filters::{
+    builtin::{helpers::ensure_empty, response_body::FixedBody},
+    types::{BodyStream, ResponseBodyModifyMod},
+};
+
+/// What happens to a response that fails validation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnViolation {
+    /// Sent as it is.
+    Log,
+    /// Sent without its body.
+    Scrub,
+    /// Replaced with an empty `502 Bad Gateway`.
+    Reject,
+}
+
+/// Checks upstream responses against what the route expects of them, so a
+/// misbehaving or compromised origin cannot hand clients anything else.
+/// Violations are always logged.
+pub struct ValidateResponse {
+    content_type: Option<Regex>,
+    max_bytes: Option<usize>,
+    required_headers: Vec<HeaderName>,
+    on_violation: OnViolation,
+}
+
+impl ValidateResponse {
+    pub const ARGS: &[FilterArg] = &[
+        FilterArg {
+            name: "content-type",
+            required: false,
+            kind: ArgKind::Regex,
+            description: "Regex the Content-Type header has to match",
+        },
+        FilterArg {
+            name: "max-bytes",
+            required: false,
+            kind: ArgKind::Integer,
+            description: "Largest body accepted",
+        },
+        FilterArg {
+            name: "require-headers",
+            required: false,
+            kind: ArgKind::String,
+            description: "Comma separated headers every response has to carry",
+        },
+        FilterArg {
+            name: "on-violation",
+            required: false,
+            kind: ArgKind::String,
+            description: "'log', 'scrub' or 'reject' (the default)",
+        },
+    ];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let content_type = match settings.remove("content-type") {
+            None => None,
+            Some(pattern) => Some(Regex::new(&pattern).map_err(|e| {
+                tracing::error!("Bad content-type pattern: '{pattern}': {e:?}");
+                Error::new_str("Error building regex for response validation")
+            })?),
+        };
+
+        let max_bytes = match settings.remove("max-bytes") {
+            None => None,
+            Some(max) => match max.parse::<usize>() {
+                Ok(max) => Some(max),
+                _ => {
+                    tracing::error!("Invalid max-bytes: '{max}'");
+                    return Err(Error::new_str("Invalid max-bytes!"));
+                }
+            },
+        };
+
+        let required_headers = match settings.remove("require-headers") {
+            None => Vec::new(),
+            Some(headers) => headers
+                .split(',')
+                .map(|name| {
+                    HeaderName::try_from(name.trim()).map_err(|_| {
+                        tracing::error!("Invalid required header: '{name}'");
+                        Error::new_str("Invalid header name!")
+                    })
+                })
+                .collect::<Result<_>>()?,
+        };
+
+        let on_violation = match settings.remove("on-violation").as_deref() {
+            None | Some("reject") => OnViolation::Reject,
+            Some("scrub") => OnViolation::Scrub,
+            Some("log") => OnViolation::Log,
+            Some(other) => {
+                tracing::error!("Invalid on-violation: '{other}', expected log, scrub or reject");
+                return Err(Error::new_str("Invalid on-violation!"));
+            }
+        };
+
+        ensure_empty(&settings)?;
+
+        if content_type.is_none() && max_bytes.is_none() && required_headers.is_empty() {
+            tracing::error!("Response validation needs content-type, max-bytes or require-headers");
+            return Err(Error::new_str("Nothing to validate!"));
+        }
+
+        Ok(Self {
+            content_type,
+            max_bytes,
+            required_headers,
+            on_violation,
+        })
+    }
+
+    /// Why `header` fails the checks that need no body, if it does.
+    fn violation(&self, header: &ResponseHeader) -> Option<String> {
+        if let Some(pattern) = &self.content_type {
+            let content_type = header
+                .headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok());
+
+            match content_type {
+                Some(value) if pattern.is_match(value) => {}
+                Some(value) => return Some(format!("content type '{value}' is not expected")),
+                None => return Some("content type is missing".to_string()),
+            }
+        }
+
+        if let Some(name) = self
+            .required_headers
+            .iter()
+            .find(|name| !header.headers.contains_key(*name))
+        {
+            return Some(format!("header '{name}' is missing"));
+        }
+
+        let length = header
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+
+        match (self.max_bytes, length) {
+            (Some(max), Some(length)) if length > max => {
+                Some(format!("body of {length} bytes exceeds {max}"))
+            }
+            _ => None,
+        }
+    }
+
+    /// Applies [OnViolation] to a failed response, returning the stream
+    /// that drops its body.
+    fn quarantine(&self, header: &mut ResponseHeader) -> Option<Box<dyn BodyStream>> {
+        match self.on_violation {
+            OnViolation::Log => None,
+            OnViolation::Scrub => {
+                header.remove_header(&CONTENT_TYPE);
+                header.remove_header(&CONTENT_ENCODING);
+                Some(Box::new(FixedBody::new(Bytes::new())))
+            }
+            OnViolation::Reject => {
+                // Nothing of the origin's response reaches the client.
+                *header = ResponseHeader::build(502, None).ok()?;
+                Some(Box::new(FixedBody::new(Bytes::new())))
+            }
+        }
+    }
+}
+
+impl ResponseBodyModifyMod for ValidateResponse {
+    fn response_body_start(
+        &self,
+        session: &Session,
+        header: &mut ResponseHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        if let Some(reason) = self.violation(header) {
+            tracing::warn!(
+                "Upstream response to {} violates validation: {reason}",
+                session.req_header().uri.path()
+            );
+            return self.quarantine(header);
+        }
+
+        if session.req_header().method == Method::HEAD
+            || header.headers.contains_key(CONTENT_LENGTH)
+        {
+            return None;
+        }
+
+        // Bodies of unknown length are counted as they stream.
+        self.max_bytes.map(|max| {
+            Box::new(SizeLimit {
+                max,
+                seen: 0,
+                on_violation: self.on_violation,
+            }) as Box<dyn BodyStream>
+        })
+    }
+}
+
+/// Counts the body against `max-bytes`. The header is gone by the time it is
+/// exceeded, so a response past it is cut off unless violations are only
+/// logged.
+struct SizeLimit {
+    max: usize,
+    seen: usize,
+    on_violation: OnViolation,
+}
+
+impl BodyStream for SizeLimit {
+    fn filter(&mut self, chunk: Bytes, _: bool) -> Result<Bytes> {
+        let exceeded = self.seen <= self.max && self.seen + chunk.len() > self.max;
+        self.seen += chunk.len();
+
+        if !exceeded {
+            return Ok(chunk);
+        }
+
+        tracing::warn!(
+            "Upstream response violates validation: body exceeds {} bytes",
+            self.max
+        );
+
+        match self.on_violation {
+            OnViolation::Log => Ok(chunk),
+            OnViolation::Scrub | OnViolation::Reject => Err(Error::explain(
+                ErrorType::HTTPStatus(502),
+                "upstream response exceeds the validation's max-bytes",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(settings: &[(&str, &str)]) -> Result<ValidateResponse> {
+        ValidateResponse::from_settings(
+            settings
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    fn response(headers: &[(&'static str, &str)]) -> ResponseHeader {
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        for (name, value) in headers {
+            header.insert_header(*name, value.to_string()).unwrap();
+        }
+        header
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let filter = validate(&[("require-headers", "X-Request-Id, ETag")]).unwrap();
+        assert_eq!(filter.required_headers.len(), 2);
+        assert_eq!(filter.on_violation, OnViolation::Reject);
+
+        assert!(validate(&[]).is_err());
+        assert!(validate(&[("max-bytes", "-1")]).is_err());
+        assert!(validate(&[("content-type", "(")]).is_err());
+        assert!(validate(&[("max-bytes", "10"), ("on-violation", "drop")]).is_err());
+    }
+
+    #[test]
+    fn test_violation() {
+        let filter = validate(&[
+            ("content-type", "^application/json"),
+            ("max-bytes", "100"),
+            ("require-headers", "X-Request-Id"),
+        ])
+        .unwrap();
+
+        let valid = [
+            ("Content-Type", "application/json; charset=utf-8"),
+            ("X-Request-Id", "1"),
+            ("Content-Length", "100"),
+        ];
+        assert_eq!(filter.violation(&response(&valid)), None);
+
+        let cases = [
+            (
+                &[("Content-Type", "text/html"), ("X-Request-Id", "1")][..],
+                "not expected",
+            ),
+            (&[("X-Request-Id", "1")][..], "content type is missing"),
+            (
+                &[("Content-Type", "application/json")][..],
+                "'x-request-id' is missing",
+            ),
+            (
+                &[
+                    ("Content-Type", "application/json"),
+                    ("X-Request-Id", "1"),
+                    ("Content-Length", "101"),
+                ][..],
+                "exceeds 100",
+            ),
+        ];
+
+        for (headers, expected) in cases {
+            let reason = filter.violation(&response(headers)).unwrap();
+            assert!(reason.contains(expected), "{reason}");
+        }
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let headers = [("Content-Type", "text/html"), ("Set-Cookie", "a=b")];
+
+        let mut header = response(&headers);
+        let filter = validate(&[("max-bytes", "1"), ("on-violation", "log")]).unwrap();
+        assert!(filter.quarantine(&mut header).is_none());
+        assert_eq!(header.headers[CONTENT_TYPE], "text/html");
+
+        let mut header = response(&headers);
+        let filter = validate(&[("max-bytes", "1"), ("on-violation", "scrub")]).unwrap();
+        assert!(filter.quarantine(&mut header).is_some());
+        assert!(!header.headers.contains_key(CONTENT_TYPE));
+        assert!(header.headers.contains_key("Set-Cookie"));
+
+        let mut header = response(&headers);
+        let filter = validate(&[("max-bytes", "1")]).unwrap();
+        assert!(filter.quarantine(&mut header).is_some());
+        assert_eq!(header.status, 502);
+        assert!(header.headers.is_empty());
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut limit = SizeLimit {
+            max: 4,
+            seen: 0,
+            on_violation: OnViolation::Reject,
+        };
+        assert_eq!(limit.filter(Bytes::from("abcd"), false).unwrap(), "abcd");
+        let err = limit.filter(Bytes::from("e"), true).unwrap_err();
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(502));
+
+        let mut limit = SizeLimit {
+            max: 4,
+            seen: 0,
+            on_violation: OnViolation::Log,
+        };
+        assert_eq!(
+            limit.filter(Bytes::from("abcdef"), false).unwrap(),
+            "abcdef"
+        );
+        assert_eq!(limit.filter(Bytes::from("g"), true).unwrap(), "g");
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use pingora::Result;
+use pingora_proxy::Session;
+
+use motya_config::common_types::filter_schema::{ArgKind, FilterArg};
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::{ensure_empty, extract_val},
+        types::UpgradeFilterMod,
+    },
+    MotyaContext,
+};
+
+/// Refuses WebSocket handshakes whose `Origin` is not in the allowed list,
+/// browsers do not apply the same-origin policy to WebSockets.
+pub struct CheckOrigin {
+    origins: Vec<String>,
+}
+
+impl CheckOrigin {
+    pub const ARGS: &[FilterArg] = &[FilterArg {
+        name: "origins",
+        required: true,
+        kind: ArgKind::String,
+        description: "Comma separated origins allowed to upgrade",
+    }];
+
+    pub fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self> {
+        let origins = extract_val("origins", &mut settings)?
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_ascii_lowercase())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        ensure_empty(&settings)?;
+
+        Ok(Self { origins })
+    }
+
+    fn allows(&self, origin: Option<&str>) -> bool {
+        origin.is_some_and(|origin| {
+            let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+            self.origins.contains(&origin)
+        })
+    }
+}
+
+#[async_trait]
+impl UpgradeFilterMod for CheckOrigin {
+    async fn upgrade_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+        let origin = session
+            .req_header()
+            .headers
+            .get(http::header::ORIGIN)
+            .and_then(|origin| origin.to_str().ok());
+
+        if self.allows(origin) {
+            return Ok(false);
+        }
+
+        tracing::debug!("Refusing WebSocket upgrade from origin {origin:?}");
+        session.downstream_session.respond_error(403).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_origins() {
+        let filter = CheckOrigin::from_settings(BTreeMap::from([(
+            "origins".to_string(),
+            "https://app.example.com, https://Admin.example.com/".to_string(),
+        )]))
+        .unwrap();
+
+        assert!(filter.allows(Some("https://app.example.com")));
+        assert!(filter.allows(Some("https://admin.example.com")));
+        assert!(!filter.allows(Some("https://evil.example.com")));
+        assert!(!filter.allows(None));
+    }
+
+    #[test]
+    fn test_rejects_unknown_settings() {
+        let settings = BTreeMap::from([
+            ("origins".to_string(), "https://app.example.com".to_string()),
+            ("strict".to_string(), "true".to_string()),
+        ]);
+
+        assert!(CheckOrigin::from_settings(settings).is_err());
+    }
+}
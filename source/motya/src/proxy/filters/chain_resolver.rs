@@ -1,21 +1,34 @@
 use crate::proxy::{
     filters::{
         registry::{FilterInstance, FilterRegistry, RegistryFilterContainer},
-        types::{RequestFilterMod, RequestModifyMod, ResponseModifyMod},
+        types::{
+            PostSelectFilterMod, PreRoutingFilterMod, RequestBodyModifyMod, RequestFilterMod,
+            RequestModifyMod, ResponseBodyModifyMod, ResponseModifyMod, UpgradeFilterMod,
+        },
     },
     plugins::module::{FilterType, WasmInvoker},
 };
 use miette::{miette, Context, IntoDiagnostic, Result};
-use motya_config::common_types::{definitions::FilterChain, definitions_table::DefinitionsTable};
+use motya_config::common_types::{
+    definitions::{ConfiguredFilter, FilterChain},
+    definitions_table::DefinitionsTable,
+};
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 #[derive(Default)]
 pub struct RuntimeChain {
+    /// Name of the chain in the definitions table.
+    pub name: String,
+    pub pre_routing: Vec<Box<dyn PreRoutingFilterMod>>,
+    pub post_select: Vec<Box<dyn PostSelectFilterMod>>,
     pub actions: Vec<Box<dyn RequestFilterMod>>,
     pub req_mods: Vec<Box<dyn RequestModifyMod>>,
+    pub req_body_mods: Vec<Box<dyn RequestBodyModifyMod>>,
     pub res_mods: Vec<Box<dyn ResponseModifyMod>>,
+    pub res_body_mods: Vec<Box<dyn ResponseBodyModifyMod>>,
+    pub upgrades: Vec<Box<dyn UpgradeFilterMod>>,
 }
 
 #[derive(Clone, Default)]
@@ -57,6 +70,12 @@ impl ChainResolver {
         Ok(Self { table, registry })
     }
 
+    /// A resolver without the checks of [`ChainResolver::new`], chains with
+    /// unknown filters only fail when they are resolved.
+    pub fn unchecked(table: DefinitionsTable, registry: Arc<Mutex<FilterRegistry>>) -> Self {
+        Self { table, registry }
+    }
+
     pub async fn resolve(&self, chain_name: &str) -> Result<RuntimeChain> {
         let chain_cfg = self
             .table
@@ -68,7 +87,10 @@ impl ChainResolver {
     }
 
     async fn build_chain(&self, chain: &FilterChain, context_name: &str) -> Result<RuntimeChain> {
-        let mut runtime_chain = RuntimeChain::default();
+        let mut runtime_chain = RuntimeChain {
+            name: context_name.to_string(),
+            ..Default::default()
+        };
 
         for filter_cfg in &chain.filters {
             let settings: BTreeMap<String, String> = filter_cfg
@@ -77,8 +99,10 @@ impl ChainResolver {
                 .map(|(k, v)| (k.clone(), v.clone()))
                 .collect();
 
-            let registry = self.registry.lock().await;
-            let container = registry
+            let container = self
+                .registry
+                .lock()
+                .await
                 .build(&filter_cfg.name, settings.clone())
                 .into_diagnostic()
                 .wrap_err_with(|| {
@@ -88,12 +112,8 @@ impl ChainResolver {
                     )
                 })?;
 
-            match container {
-                RegistryFilterContainer::Builtin(builtin) => match builtin {
-                    FilterInstance::Action(f) => runtime_chain.actions.push(f),
-                    FilterInstance::Request(f) => runtime_chain.req_mods.push(f),
-                    FilterInstance::Response(f) => runtime_chain.res_mods.push(f),
-                },
+            let instance = match container {
+                RegistryFilterContainer::Builtin(builtin) => builtin,
                 RegistryFilterContainer::Plugin(plugin) => {
                     let (_plugin_name, filter_name) = filter_cfg
                         .name
@@ -110,15 +130,42 @@ impl ChainResolver {
 
                     let invoker = WasmInvoker::new(plugin, filter_name.to_string(), settings);
 
-                    match invoker.get_filter_type()? {
-                        FilterType::Filter => Box::new(invoker),
-                        FilterType::OnRequest => Box::new(invoker),
-                        FilterType::OnResponse => Box::new(invoker),
+                    // Instantiating may compile the plugin, which is CPU bound.
+                    let filter_type = {
+                        let invoker = invoker.clone();
+                        tokio::task::spawn_blocking(move || invoker.get_filter_type())
+                            .await
+                            .into_diagnostic()??
                     };
+
+                    match filter_type {
+                        FilterType::Filter => FilterInstance::Action(Box::new(invoker)),
+                        FilterType::OnRequest => FilterInstance::Request(Box::new(invoker)),
+                        FilterType::OnResponse => FilterInstance::Response(Box::new(invoker)),
+                    }
                 }
+            };
+
+            match scheduled(instance, filter_cfg) {
+                FilterInstance::Action(f) => runtime_chain.actions.push(f),
+                FilterInstance::Request(f) => runtime_chain.req_mods.push(f),
+                FilterInstance::RequestBody(f) => runtime_chain.req_body_mods.push(f),
+                FilterInstance::Response(f) => runtime_chain.res_mods.push(f),
+                FilterInstance::ResponseBody(f) => runtime_chain.res_body_mods.push(f),
+                FilterInstance::Upgrade(f) => runtime_chain.upgrades.push(f),
+                FilterInstance::PreRouting(f) => runtime_chain.pre_routing.push(f),
+                FilterInstance::PostSelect(f) => runtime_chain.post_select.push(f),
             }
         }
 
         Ok(runtime_chain)
     }
 }
+
+/// Wraps a filter with an `active-between` window, leaves the others alone.
+fn scheduled(instance: FilterInstance, filter_cfg: &ConfiguredFilter) -> FilterInstance {
+    match filter_cfg.active {
+        Some(window) => instance.active_between(window),
+        None => instance,
+    }
+}
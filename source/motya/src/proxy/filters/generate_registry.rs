@@ -1,33 +1,83 @@
 use crate::proxy::filters::builtin::{
+    block_tls_fingerprint::BlockTlsFingerprint,
     cidr_range::CidrRangeFilter,
+    ext_auth::ExtAuth,
+    geoip::GeoIpFilter,
+    header_gate::{DenyHeader, RequireHeader},
+    post_select::{
+        address_from_header::AddressFromHeader, alpn::Alpn, backend_header::BackendHeader,
+        sni_from_header::SniFromHeader,
+    },
+    redirect::Redirect,
     request::{
+        forwarded_for::ForwardedFor, geoip::GeoIpHeaders,
         remove_headers::RemoveHeaderKeyRegex as RequestRemoveHeaderKeyRegex,
         rewrite_path::RewritePathRegex, strip_prefix::StripPrefix,
         upsert_headers::UpsertHeader as RequestUpsertHeader,
     },
+    request_body::{gunzip::GunzipBody, strip_json_fields::StripJsonFields},
+    require_flag::RequireFlag,
     response::{
+        config_fingerprint::ConfigFingerprint,
         remove_header::RemoveHeaderKeyRegex as ResponseRemoveHeaderKeyRegex,
         upsert_header::UpsertHeader as ResponseUpsertHeader,
     },
+    response_body::{
+        gzip::GzipBody, map_status::MapStatus, regex_replace::RegexReplaceBody,
+        replace::ReplaceBody, validate::ValidateResponse,
+    },
+    upgrade::check_origin::CheckOrigin,
+};
+use crate::proxy::filters::registry::{
+    FilterInstance, FilterMetadata, FilterPhase, FilterRegistry, RegistryFilterContainer,
 };
-use crate::proxy::filters::registry::{FilterInstance, FilterRegistry, RegistryFilterContainer};
 use motya_config::common_types::definitions_table::DefinitionsTable;
 use motya_config::define_builtin_filters;
 
 macro_rules! impl_registry_loader {
     (
+        pre_routing: { $($pre_key:literal => $pre_type:ty),* $(,)? }
+
         actions: { $($act_key:literal => $act_type:ty),* $(,)? }
 
         requests: { $($req_key:literal => $req_type:ty),* $(,)? }
 
+        request_bodies: { $($req_body_key:literal => $req_body_type:ty),* $(,)? }
+
         responses: { $($res_key:literal => $res_type:ty),* $(,)? }
+
+        response_bodies: { $($body_key:literal => $body_type:ty),* $(,)? }
+
+        upgrades: { $($upg_key:literal => $upg_type:ty),* $(,)? }
+
+        post_select: { $($post_key:literal => $post_type:ty),* $(,)? }
     ) => {
         pub fn load_registry(definitions: &mut DefinitionsTable) -> FilterRegistry {
             let mut registry = FilterRegistry::new();
 
+            $(
+                let key = fqdn::fqdn!($pre_key);
+                definitions.insert_filter(key.clone());
+                definitions.insert_filter_schema(key.clone(), <$pre_type>::ARGS);
+                registry.describe(key.clone(), FilterMetadata {
+                    phase: FilterPhase::PreRouting,
+                    args: <$pre_type>::ARGS,
+                });
+
+                registry.register_factory(key, Box::new(|settings| {
+                    let item = <$pre_type>::from_settings(settings)?;
+                    Ok(RegistryFilterContainer::Builtin(FilterInstance::PreRouting(Box::new(item))))
+                }));
+            )*
+
             $(
                 let key = fqdn::fqdn!($act_key);
                 definitions.insert_filter(key.clone());
+                definitions.insert_filter_schema(key.clone(), <$act_type>::ARGS);
+                registry.describe(key.clone(), FilterMetadata {
+                    phase: FilterPhase::RequestFilters,
+                    args: <$act_type>::ARGS,
+                });
 
                 registry.register_factory(key, Box::new(|settings| {
                     let item = <$act_type>::from_settings(settings)?;
@@ -39,6 +89,11 @@ macro_rules! impl_registry_loader {
             $(
                 let key = fqdn::fqdn!($req_key);
                 definitions.insert_filter(key.clone());
+                definitions.insert_filter_schema(key.clone(), <$req_type>::ARGS);
+                registry.describe(key.clone(), FilterMetadata {
+                    phase: FilterPhase::UpstreamRequest,
+                    args: <$req_type>::ARGS,
+                });
 
                 registry.register_factory(key, Box::new(|settings| {
                     let item = <$req_type>::from_settings(settings)?;
@@ -46,9 +101,29 @@ macro_rules! impl_registry_loader {
                 }));
             )*
 
+            $(
+                let key = fqdn::fqdn!($req_body_key);
+                definitions.insert_filter(key.clone());
+                definitions.insert_filter_schema(key.clone(), <$req_body_type>::ARGS);
+                registry.describe(key.clone(), FilterMetadata {
+                    phase: FilterPhase::RequestBody,
+                    args: <$req_body_type>::ARGS,
+                });
+
+                registry.register_factory(key, Box::new(|settings| {
+                    let item = <$req_body_type>::from_settings(settings)?;
+                    Ok(RegistryFilterContainer::Builtin(FilterInstance::RequestBody(Box::new(item))))
+                }));
+            )*
+
             $(
                 let key = fqdn::fqdn!($res_key);
                 definitions.insert_filter(key.clone());
+                definitions.insert_filter_schema(key.clone(), <$res_type>::ARGS);
+                registry.describe(key.clone(), FilterMetadata {
+                    phase: FilterPhase::UpstreamResponse,
+                    args: <$res_type>::ARGS,
+                });
 
                 registry.register_factory(key, Box::new(|settings| {
                     let item = <$res_type>::from_settings(settings)?;
@@ -56,6 +131,51 @@ macro_rules! impl_registry_loader {
                 }));
             )*
 
+            $(
+                let key = fqdn::fqdn!($body_key);
+                definitions.insert_filter(key.clone());
+                definitions.insert_filter_schema(key.clone(), <$body_type>::ARGS);
+                registry.describe(key.clone(), FilterMetadata {
+                    phase: FilterPhase::ResponseBody,
+                    args: <$body_type>::ARGS,
+                });
+
+                registry.register_factory(key, Box::new(|settings| {
+                    let item = <$body_type>::from_settings(settings)?;
+                    Ok(RegistryFilterContainer::Builtin(FilterInstance::ResponseBody(Box::new(item))))
+                }));
+            )*
+
+            $(
+                let key = fqdn::fqdn!($upg_key);
+                definitions.insert_filter(key.clone());
+                definitions.insert_filter_schema(key.clone(), <$upg_type>::ARGS);
+                registry.describe(key.clone(), FilterMetadata {
+                    phase: FilterPhase::Upgrade,
+                    args: <$upg_type>::ARGS,
+                });
+
+                registry.register_factory(key, Box::new(|settings| {
+                    let item = <$upg_type>::from_settings(settings)?;
+                    Ok(RegistryFilterContainer::Builtin(FilterInstance::Upgrade(Box::new(item))))
+                }));
+            )*
+
+            $(
+                let key = fqdn::fqdn!($post_key);
+                definitions.insert_filter(key.clone());
+                definitions.insert_filter_schema(key.clone(), <$post_type>::ARGS);
+                registry.describe(key.clone(), FilterMetadata {
+                    phase: FilterPhase::PostSelect,
+                    args: <$post_type>::ARGS,
+                });
+
+                registry.register_factory(key, Box::new(|settings| {
+                    let item = <$post_type>::from_settings(settings)?;
+                    Ok(RegistryFilterContainer::Builtin(FilterInstance::PostSelect(Box::new(item))))
+                }));
+            )*
+
             registry
         }
     };
@@ -2,4 +2,5 @@ pub mod builtin;
 pub mod chain_resolver;
 pub mod generate_registry;
 pub mod registry;
+pub mod scheduled;
 pub mod types;
@@ -1,15 +1,26 @@
 use fqdn::FQDN;
+use motya_config::common_types::filter_schema::FilterArg;
 use pingora::{Error, ErrorType, Result};
 use std::collections::{BTreeMap, HashMap};
 
 use crate::proxy::{
-    plugins::module::WasmModule, RequestFilterMod, RequestModifyMod, ResponseModifyMod,
+    filters::types::{
+        PostSelectFilterMod, PreRoutingFilterMod, RequestBodyModifyMod, ResponseBodyModifyMod,
+        UpgradeFilterMod,
+    },
+    plugins::module::WasmModule,
+    RequestFilterMod, RequestModifyMod, ResponseModifyMod,
 };
 
 pub enum FilterInstance {
     Action(Box<dyn RequestFilterMod>),
     Request(Box<dyn RequestModifyMod>),
+    RequestBody(Box<dyn RequestBodyModifyMod>),
     Response(Box<dyn ResponseModifyMod>),
+    ResponseBody(Box<dyn ResponseBodyModifyMod>),
+    Upgrade(Box<dyn UpgradeFilterMod>),
+    PreRouting(Box<dyn PreRoutingFilterMod>),
+    PostSelect(Box<dyn PostSelectFilterMod>),
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -18,6 +29,41 @@ pub enum RegistryFilterContainer {
     Plugin(WasmModule),
 }
 
+/// Stage of request handling a filter runs in, named like the `path-control` sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterPhase {
+    PreRouting,
+    RequestFilters,
+    PostSelect,
+    UpstreamRequest,
+    RequestBody,
+    UpstreamResponse,
+    ResponseBody,
+    Upgrade,
+}
+
+impl FilterPhase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FilterPhase::PreRouting => "pre-routing",
+            FilterPhase::RequestFilters => "request-filters",
+            FilterPhase::PostSelect => "post-select",
+            FilterPhase::UpstreamRequest => "upstream-request",
+            FilterPhase::RequestBody => "request-body",
+            FilterPhase::UpstreamResponse => "upstream-response",
+            FilterPhase::ResponseBody => "response-body",
+            FilterPhase::Upgrade => "upgrade",
+        }
+    }
+}
+
+/// What `motya filters list` shows about a registered filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterMetadata {
+    pub phase: FilterPhase,
+    pub args: &'static [FilterArg],
+}
+
 type FiltersContainerFactoryFn =
     Box<dyn Fn(BTreeMap<String, String>) -> Result<RegistryFilterContainer> + Send + Sync>;
 
@@ -34,6 +80,7 @@ type FiltersContainerFactoryFn =
 #[derive(Default)]
 pub struct FilterRegistry {
     factories: HashMap<FQDN, FiltersContainerFactoryFn>,
+    metadata: HashMap<FQDN, FilterMetadata>,
 }
 
 impl FilterRegistry {
@@ -47,6 +94,39 @@ impl FilterRegistry {
         }
     }
 
+    /// Attaches the description of an already registered filter.
+    pub fn describe(&mut self, name: FQDN, metadata: FilterMetadata) {
+        self.metadata.insert(name, metadata);
+    }
+
+    /// Every described filter, sorted by name.
+    pub fn descriptions(&self) -> Vec<(&FQDN, &FilterMetadata)> {
+        let mut descriptions: Vec<_> = self.metadata.iter().collect();
+        descriptions.sort_by_key(|(name, _)| name.to_string());
+        descriptions
+    }
+
+    /// Human readable listing of [`Self::descriptions`].
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+
+        for (name, metadata) in self.descriptions() {
+            out.push_str(&format!("{name}\n    phase: {}\n", metadata.phase.as_str()));
+
+            for arg in metadata.args {
+                let need = if arg.required { "required" } else { "optional" };
+                out.push_str(&format!(
+                    "    {} ({need}, {}): {}\n",
+                    arg.name,
+                    arg.kind.as_str(),
+                    arg.description
+                ));
+            }
+        }
+
+        out
+    }
+
     pub fn build(
         &self,
         name: &FQDN,
@@ -76,7 +156,7 @@ mod tests {
     use crate::proxy::filters::chain_resolver::ChainResolver;
     use crate::proxy::filters::generate_registry::load_registry;
     use crate::proxy::filters::registry::{
-        FilterInstance, FilterRegistry, RegistryFilterContainer,
+        FilterInstance, FilterPhase, FilterRegistry, RegistryFilterContainer,
     };
     use crate::proxy::MotyaContext;
     use crate::proxy::{RequestFilterMod, RequestModifyMod};
@@ -174,6 +254,57 @@ mod tests {
             .contains(&FQDN::from_str("motya.request.remove-header").unwrap()));
     }
 
+    #[test]
+    fn test_builtin_filters_described() {
+        let mut definitions = DefinitionsTable::default();
+        let registry = load_registry(&mut definitions);
+        let descriptions = registry.descriptions();
+
+        assert_eq!(descriptions.len(), registry.factories.len());
+
+        let (_, rewrite) = descriptions
+            .iter()
+            .find(|(name, _)| **name == FQDN::from_str("motya.request.rewrite-path").unwrap())
+            .unwrap();
+        assert_eq!(rewrite.phase, FilterPhase::UpstreamRequest);
+        assert_eq!(
+            rewrite.args.iter().map(|arg| arg.name).collect::<Vec<_>>(),
+            ["pattern", "replace"]
+        );
+        assert_eq!(
+            definitions.get_filter_schema(&FQDN::from_str("motya.request.rewrite-path").unwrap()),
+            Some(rewrite.args)
+        );
+
+        let phase = |name: &str| {
+            descriptions
+                .iter()
+                .find(|(key, _)| **key == FQDN::from_str(name).unwrap())
+                .map(|(_, metadata)| metadata.phase)
+        };
+        assert_eq!(
+            phase("motya.pre-routing.rewrite-path"),
+            Some(FilterPhase::PreRouting)
+        );
+        assert_eq!(
+            phase("motya.post-select.backend-header"),
+            Some(FilterPhase::PostSelect)
+        );
+        assert_eq!(
+            phase("motya.request-body.gunzip"),
+            Some(FilterPhase::RequestBody)
+        );
+        assert_eq!(
+            phase("motya.response-body.gzip"),
+            Some(FilterPhase::ResponseBody)
+        );
+
+        let listing = registry.listing();
+        assert!(listing.contains("motya.filters.block-cidr-range\n    phase: request-filters\n"));
+        assert!(listing.contains("    origins (required, string): "));
+        assert!(listing.contains("    pattern (required, regex): "));
+    }
+
     #[tokio::test]
     async fn test_compile_success() {
         let mut definitions_table = DefinitionsTable::default();
@@ -191,10 +322,12 @@ mod tests {
             ConfiguredFilter {
                 name: FQDN::from_str("motya.sec.block").unwrap(),
                 args: HashMap::new(),
+                active: None,
             },
             ConfiguredFilter {
                 name: FQDN::from_str("motya.req.add_header").unwrap(),
                 args: header_args,
+                active: None,
             },
         ];
 
@@ -264,6 +397,7 @@ mod tests {
                 filters: vec![ConfiguredFilter {
                     name: FQDN::from_str("motya.always_fail").unwrap(),
                     args: HashMap::new(),
+                    active: None,
                 }],
             },
         );
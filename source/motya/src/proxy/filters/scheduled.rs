@@ -0,0 +1,158 @@
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use pingora::{prelude::HttpPeer, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+use motya_config::common_types::active_window::ActiveWindow;
+
+use crate::proxy::{
+    filters::{
+        registry::FilterInstance,
+        types::{
+            BodyStream, PostSelectFilterMod, PreRoutingFilterMod, RequestBodyModifyMod,
+            RequestFilterMod, RequestModifyMod, ResponseBodyModifyMod, ResponseModifyMod,
+            UpgradeFilterMod,
+        },
+    },
+    MotyaContext,
+};
+
+/// A filter with an `active-between` window, outside of it the filter is
+/// skipped as if it was not in the chain.
+pub struct Scheduled<F> {
+    window: ActiveWindow,
+    inner: F,
+}
+
+impl<F> Scheduled<F> {
+    fn active(&self) -> bool {
+        self.window.contains(SystemTime::now())
+    }
+}
+
+impl FilterInstance {
+    /// Runs the filter only within `window`.
+    pub fn active_between(self, window: ActiveWindow) -> Self {
+        fn wrap<F>(window: ActiveWindow, inner: F) -> Box<Scheduled<F>> {
+            Box::new(Scheduled { window, inner })
+        }
+
+        match self {
+            FilterInstance::Action(f) => FilterInstance::Action(wrap(window, f)),
+            FilterInstance::Request(f) => FilterInstance::Request(wrap(window, f)),
+            FilterInstance::RequestBody(f) => FilterInstance::RequestBody(wrap(window, f)),
+            FilterInstance::Response(f) => FilterInstance::Response(wrap(window, f)),
+            FilterInstance::ResponseBody(f) => FilterInstance::ResponseBody(wrap(window, f)),
+            FilterInstance::Upgrade(f) => FilterInstance::Upgrade(wrap(window, f)),
+            FilterInstance::PreRouting(f) => FilterInstance::PreRouting(wrap(window, f)),
+            FilterInstance::PostSelect(f) => FilterInstance::PostSelect(wrap(window, f)),
+        }
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for Scheduled<Box<dyn RequestFilterMod>> {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        if !self.active() {
+            return Ok(false);
+        }
+        self.inner.request_filter(session, ctx).await
+    }
+}
+
+#[async_trait]
+impl RequestModifyMod for Scheduled<Box<dyn RequestModifyMod>> {
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        header: &mut RequestHeader,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        if !self.active() {
+            return Ok(());
+        }
+        self.inner
+            .upstream_request_filter(session, header, ctx)
+            .await
+    }
+}
+
+impl RequestBodyModifyMod for Scheduled<Box<dyn RequestBodyModifyMod>> {
+    fn request_body_start(
+        &self,
+        session: &Session,
+        header: &mut RequestHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        if !self.active() {
+            return None;
+        }
+        self.inner.request_body_start(session, header)
+    }
+}
+
+impl ResponseModifyMod for Scheduled<Box<dyn ResponseModifyMod>> {
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        header: &mut ResponseHeader,
+        ctx: &mut MotyaContext,
+    ) {
+        if self.active() {
+            self.inner.upstream_response_filter(session, header, ctx);
+        }
+    }
+}
+
+impl ResponseBodyModifyMod for Scheduled<Box<dyn ResponseBodyModifyMod>> {
+    fn response_body_start(
+        &self,
+        session: &Session,
+        header: &mut ResponseHeader,
+    ) -> Option<Box<dyn BodyStream>> {
+        if !self.active() {
+            return None;
+        }
+        self.inner.response_body_start(session, header)
+    }
+}
+
+#[async_trait]
+impl UpgradeFilterMod for Scheduled<Box<dyn UpgradeFilterMod>> {
+    async fn upgrade_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        if !self.active() {
+            return Ok(false);
+        }
+        self.inner.upgrade_filter(session, ctx).await
+    }
+}
+
+#[async_trait]
+impl PreRoutingFilterMod for Scheduled<Box<dyn PreRoutingFilterMod>> {
+    async fn pre_routing_filter(
+        &self,
+        session: &mut Session,
+        ctx: &mut MotyaContext,
+    ) -> Result<bool> {
+        if !self.active() {
+            return Ok(false);
+        }
+        self.inner.pre_routing_filter(session, ctx).await
+    }
+}
+
+#[async_trait]
+impl PostSelectFilterMod for Scheduled<Box<dyn PostSelectFilterMod>> {
+    async fn post_select_filter(
+        &self,
+        session: &mut Session,
+        peer: &mut HttpPeer,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        if !self.active() {
+            return Ok(());
+        }
+        self.inner.post_select_filter(session, peer, ctx).await
+    }
+}
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
-use pingora::Result;
+use bytes::Bytes;
+use pingora::{prelude::HttpPeer, Result};
 use pingora_http::{RequestHeader, ResponseHeader};
 use pingora_proxy::Session;
 
@@ -17,6 +18,27 @@ pub trait ResponseModifyMod: Send + Sync {
     );
 }
 
+/// Rewrites the response body as it streams through
+/// [ProxyHttp::response_body_filter], next to [ResponseModifyMod].
+pub trait ResponseBodyModifyMod: Send + Sync {
+    /// Called with the upstream response header, returns the stream that
+    /// rewrites this response or `None` to leave the body alone. Header
+    /// changes the rewrite implies, e.g. `Content-Encoding`, go here.
+    fn response_body_start(
+        &self,
+        session: &Session,
+        header: &mut ResponseHeader,
+    ) -> Option<Box<dyn BodyStream>>;
+}
+
+/// Per-message state of a [ResponseBodyModifyMod] or [RequestBodyModifyMod].
+pub trait BodyStream: Send + Sync {
+    /// Takes the next chunk and returns what to send on, which may be empty
+    /// while the stream holds bytes back. Called once with `end_of_stream`
+    /// set, where anything held back must be flushed.
+    fn filter(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<Bytes>;
+}
+
 /// This is a single-serving trait for modifiers that provide actions for
 /// [ProxyHttp::upstream_request_filter] methods
 #[async_trait]
@@ -30,6 +52,26 @@ pub trait RequestModifyMod: Send + Sync {
     ) -> Result<()>;
 }
 
+/// Rewrites the request body as it streams upstream through
+/// [ProxyHttp::request_body_filter], next to [RequestModifyMod].
+pub trait RequestBodyModifyMod: Send + Sync {
+    /// Called with the upstream request header of every attempt, returns the
+    /// stream that rewrites this body or `None` to leave it alone.
+    fn request_body_start(
+        &self,
+        session: &Session,
+        header: &mut RequestHeader,
+    ) -> Option<Box<dyn BodyStream>>;
+}
+
+/// Inspects WebSocket upgrade handshakes, runs in [ProxyHttp::request_filter]
+/// before the actions of the chain.
+#[async_trait]
+pub trait UpgradeFilterMod: Send + Sync {
+    /// Returning `Ok(true)` means the handshake was answered and is not proxied.
+    async fn upgrade_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool>;
+}
+
 /// This is a single-serving trait for modifiers that provide actions for
 /// [ProxyHttp::request_filter] methods
 #[async_trait]
@@ -37,3 +79,28 @@ pub trait RequestFilterMod: Send + Sync {
     /// See [ProxyHttp::request_filter] for more details
     async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool>;
 }
+
+/// Runs in [ProxyHttp::request_filter] before the route is matched, so changes
+/// to the request path pick the route. The route is not known yet, so these
+/// filters apply to every request of the service.
+#[async_trait]
+pub trait PreRoutingFilterMod: Send + Sync {
+    /// Returning `Ok(true)` means the request was answered and is not proxied.
+    async fn pre_routing_filter(
+        &self,
+        session: &mut Session,
+        ctx: &mut MotyaContext,
+    ) -> Result<bool>;
+}
+
+/// Runs in [ProxyHttp::upstream_peer] once the backend is chosen and before
+/// connecting to it.
+#[async_trait]
+pub trait PostSelectFilterMod: Send + Sync {
+    async fn post_select_filter(
+        &self,
+        session: &mut Session,
+        peer: &mut HttpPeer,
+        ctx: &mut MotyaContext,
+    ) -> Result<()>;
+}
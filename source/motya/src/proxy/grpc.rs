@@ -0,0 +1,310 @@
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use pingora::{
+    connectors::http::Connector, prelude::HttpPeer, Error, ErrorSource, ErrorType, Result,
+};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_load_balancing::{health_check::HealthCheck, Backend};
+use pingora_proxy::{FailToProxy, Session};
+
+use crate::events::{self, Event};
+
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+/// `HealthCheckResponse.ServingStatus.SERVING`
+const SERVING: u64 = 1;
+
+/// gRPC status codes sent for requests that never reached an upstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrpcStatus {
+    DeadlineExceeded = 4,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+}
+
+pub fn is_grpc(req: &RequestHeader) -> bool {
+    req.headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/grpc"))
+}
+
+/// Maps a proxying error to the status a gRPC client expects.
+pub fn status_for(e: &Error) -> (GrpcStatus, &'static str) {
+    match e.etype() {
+        ErrorType::ConnectTimedout
+        | ErrorType::ReadTimedout
+        | ErrorType::WriteTimedout
+        | ErrorType::HTTPStatus(504) => (GrpcStatus::DeadlineExceeded, "upstream timed out"),
+        ErrorType::HTTPStatus(404) => (GrpcStatus::Unimplemented, "no route for this method"),
+        ErrorType::ConnectRefused
+        | ErrorType::ConnectNoRoute
+        | ErrorType::ConnectError
+        | ErrorType::TLSHandshakeFailure => (GrpcStatus::Unavailable, "upstream unavailable"),
+        _ if e.esource() == &ErrorSource::Upstream => {
+            (GrpcStatus::Unavailable, "upstream unavailable")
+        }
+        _ => (GrpcStatus::Internal, "proxy error"),
+    }
+}
+
+/// Answers a failed gRPC call with a trailers-only response carrying
+/// `grpc-status`, instead of an HTTP error page.
+pub async fn fail_to_proxy(session: &mut Session, e: &Error) -> FailToProxy {
+    let (status, message) = status_for(e);
+
+    let result = async {
+        let mut response = ResponseHeader::build(200, Some(3))?;
+        response.insert_header("content-type", "application/grpc")?;
+        response.insert_header("grpc-status", (status as u16).to_string())?;
+        response.insert_header("grpc-message", message)?;
+
+        session
+            .write_response_header(Box::new(response), true)
+            .await
+    }
+    .await;
+
+    if let Err(err) = result {
+        tracing::error!("Failed to send gRPC error to downstream: {err}");
+    }
+
+    FailToProxy {
+        error_code: 200,
+        can_reuse_downstream: false,
+    }
+}
+
+/// Length-prefixed gRPC message of a `HealthCheckRequest` for `service`.
+fn health_request(service: &str) -> Bytes {
+    let mut message = BytesMut::new();
+
+    if !service.is_empty() {
+        // field 1, length delimited
+        message.put_u8(0x0a);
+        put_varint(&mut message, service.len() as u64);
+        message.put_slice(service.as_bytes());
+    }
+
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    frame.put_u8(0);
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(&message);
+
+    frame.freeze()
+}
+
+/// `status` of the `HealthCheckResponse` in a length-prefixed gRPC message.
+fn serving_status(frame: &[u8]) -> Option<u64> {
+    let (&compressed, rest) = frame.split_first()?;
+    if compressed != 0 || rest.len() < 4 {
+        return None;
+    }
+
+    let len = u32::from_be_bytes(rest[..4].try_into().ok()?) as usize;
+    let mut message = rest.get(4..4 + len)?;
+    // An absent field holds the default, `UNKNOWN`.
+    let mut status = 0;
+
+    while !message.is_empty() {
+        let key = read_varint(&mut message)?;
+
+        match (key >> 3, key & 0x7) {
+            (1, 0) => status = read_varint(&mut message)?,
+            (_, 0) => {
+                read_varint(&mut message)?;
+            }
+            (_, 2) => {
+                let len = read_varint(&mut message)? as usize;
+                message = message.get(len..)?;
+            }
+            _ => return None,
+        }
+    }
+
+    Some(status)
+}
+
+pub(crate) fn put_varint(buf: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+pub(crate) fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// The standard gRPC health checking protocol, a backend stays healthy while
+/// it answers `SERVING`. Transitions are published as
+/// [`Event::BackendHealthChanged`] of the proxy `proxy`.
+pub struct GrpcHealthCheck {
+    service: String,
+    proxy: String,
+    connector: Connector,
+}
+
+impl GrpcHealthCheck {
+    pub fn new(service: String, proxy: String) -> Self {
+        Self {
+            service,
+            proxy,
+            connector: Connector::new(None),
+        }
+    }
+
+    fn unhealthy<T>(&self, reason: String) -> Result<T> {
+        Error::e_explain(ErrorType::Custom("gRPC health check failed"), reason)
+    }
+}
+
+#[async_trait]
+impl HealthCheck for GrpcHealthCheck {
+    async fn check(&self, target: &Backend) -> Result<()> {
+        let Some(peer) = target.ext.get::<HttpPeer>() else {
+            return self.unhealthy(format!("{} has no peer", target.addr));
+        };
+
+        let (mut session, _) = self.connector.get_http_session(peer).await?;
+
+        let body = health_request(&self.service);
+        let mut req = RequestHeader::build("POST", HEALTH_CHECK_PATH.as_bytes(), None)?;
+        req.insert_header("host", target.addr.to_string())?;
+        req.insert_header("content-type", "application/grpc")?;
+        req.insert_header("te", "trailers")?;
+        req.insert_header("content-length", body.len().to_string())?;
+
+        session.write_request_header(Box::new(req)).await?;
+        session.write_request_body(body, false).await?;
+        session.finish_request_body().await?;
+        session.read_response_header().await?;
+
+        let Some(response) = session.response_header() else {
+            return self.unhealthy("no response".into());
+        };
+
+        if response.status != 200 {
+            return self.unhealthy(format!("answered {}", response.status));
+        }
+
+        // A trailers-only response carries the error in the headers.
+        if let Some(status) = response.headers.get("grpc-status") {
+            if status != "0" {
+                return self.unhealthy(format!("grpc-status {status:?}"));
+            }
+        }
+
+        let mut frame = BytesMut::new();
+        while let Some(chunk) = session.read_response_body().await? {
+            frame.extend_from_slice(&chunk);
+        }
+
+        match serving_status(&frame) {
+            Some(SERVING) => Ok(()),
+            Some(status) => self.unhealthy(format!("serving status {status}")),
+            None => self.unhealthy("malformed HealthCheckResponse".into()),
+        }
+    }
+
+    fn health_threshold(&self, _success: bool) -> usize {
+        1
+    }
+
+    async fn health_status_change(&self, target: &Backend, healthy: bool) {
+        events::emit(Event::BackendHealthChanged {
+            service: self.proxy.clone(),
+            backend: target.addr.to_string(),
+            healthy,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_request() {
+        assert_eq!(&health_request("")[..], &[0, 0, 0, 0, 0]);
+        assert_eq!(
+            &health_request("shop.Cart")[..],
+            b"\x00\x00\x00\x00\x0b\x0a\x09shop.Cart"
+        );
+    }
+
+    #[test]
+    fn test_serving_status() {
+        assert_eq!(serving_status(&[0, 0, 0, 0, 2, 0x08, 0x01]), Some(SERVING));
+        assert_eq!(serving_status(&[0, 0, 0, 0, 2, 0x08, 0x02]), Some(2));
+        // Default value, the field is omitted.
+        assert_eq!(serving_status(&[0, 0, 0, 0, 0]), Some(0));
+        // Unknown length delimited field before the status.
+        assert_eq!(
+            serving_status(&[0, 0, 0, 0, 5, 0x12, 0x01, b'x', 0x08, 0x01]),
+            Some(SERVING)
+        );
+        assert_eq!(serving_status(&[0, 0, 0, 0, 2, 0x08]), None);
+        assert_eq!(serving_status(&[1, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_status_for() {
+        let refused = Error::new(ErrorType::ConnectRefused);
+        assert_eq!(status_for(&refused).0, GrpcStatus::Unavailable);
+
+        let timeout = Error::new(ErrorType::ReadTimedout);
+        assert_eq!(status_for(&timeout).0, GrpcStatus::DeadlineExceeded);
+
+        let no_route = Error::new(ErrorType::HTTPStatus(404));
+        assert_eq!(status_for(&no_route).0, GrpcStatus::Unimplemented);
+    }
+
+    #[test]
+    fn test_is_grpc() {
+        let mut req = RequestHeader::build("POST", b"/shop.Cart/Add", None).unwrap();
+        assert!(!is_grpc(&req));
+
+        req.insert_header("content-type", "application/grpc+proto")
+            .unwrap();
+        assert!(is_grpc(&req));
+    }
+
+    #[tokio::test]
+    async fn test_health_status_change() {
+        let mut rx = events::subscribe();
+        let check = GrpcHealthCheck::new(String::new(), "api".into());
+        let backend = Backend::new("10.0.0.1:50051").unwrap();
+
+        check.health_status_change(&backend, false).await;
+
+        loop {
+            let record = rx.recv().await.unwrap();
+            if let Event::BackendHealthChanged {
+                service,
+                backend,
+                healthy,
+            } = record.event
+            {
+                assert_eq!(service, "api");
+                assert_eq!(backend, "10.0.0.1:50051");
+                assert!(!healthy);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! `record-har` of a route: sampled requests written with their responses to
+//! HAR 1.2 files, which browser dev tools and replay tools load.
+
+use std::{
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::BytesMut;
+use http::{header::CONTENT_TYPE, header::HOST, HeaderMap};
+use pingora_http::{RequestHeader, ResponseHeader};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use motya_config::common_types::connectors::HarRecordingConfig;
+
+/// The `record-har` of a route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HarRecorder {
+    dir: PathBuf,
+    one_in: usize,
+    max_body_bytes: usize,
+}
+
+impl From<HarRecordingConfig> for HarRecorder {
+    fn from(value: HarRecordingConfig) -> Self {
+        Self {
+            dir: value.dir,
+            one_in: value.one_in,
+            max_body_bytes: value.max_body_bytes,
+        }
+    }
+}
+
+impl HarRecorder {
+    /// Starts recording the request if it is sampled.
+    pub fn start(&self, req: &RequestHeader, tls: bool) -> Option<HarCapture> {
+        if rand::random_range(0..self.one_in) != 0 {
+            return None;
+        }
+
+        Some(HarCapture {
+            dir: self.dir.clone(),
+            max_body_bytes: self.max_body_bytes,
+            started: SystemTime::now(),
+            clock: Instant::now(),
+            request: request(req, tls),
+            request_type: content_type(&req.headers).to_string(),
+            request_body: Body::default(),
+            response: None,
+            response_body: Body::default(),
+        })
+    }
+}
+
+/// The start of a body, and how long it was in full.
+#[derive(Debug, Default)]
+struct Body {
+    kept: BytesMut,
+    size: usize,
+    complete: bool,
+}
+
+impl Body {
+    fn push(&mut self, chunk: &[u8], end_of_stream: bool, max_bytes: usize) {
+        // A retried request sends its body again.
+        if self.complete {
+            return;
+        }
+
+        let room = max_bytes.saturating_sub(self.kept.len());
+        self.kept.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        self.size += chunk.len();
+        self.complete = end_of_stream;
+    }
+
+    /// The HAR `text` of the body, base64 when it is not UTF-8.
+    fn content(&self, mime_type: &str, max_bytes: usize) -> Value {
+        let mut content = json!({ "mimeType": mime_type, "size": self.size });
+
+        match std::str::from_utf8(&self.kept) {
+            Ok(text) => content["text"] = text.into(),
+            Err(_) => {
+                content["text"] = openssl::base64::encode_block(&self.kept).into();
+                content["encoding"] = "base64".into();
+            }
+        }
+
+        if self.size > self.kept.len() {
+            content["comment"] = format!("Cut at {max_bytes} of {} bytes", self.size).into();
+        }
+
+        content
+    }
+}
+
+/// A request being recorded, written to a file once it is done.
+#[derive(Debug)]
+pub struct HarCapture {
+    dir: PathBuf,
+    max_body_bytes: usize,
+    started: SystemTime,
+    clock: Instant,
+    request: Value,
+    request_type: String,
+    request_body: Body,
+    response: Option<ResponseHeader>,
+    response_body: Body,
+}
+
+impl HarCapture {
+    /// Records a piece of the request body as the client sent it.
+    pub fn request_body(&mut self, chunk: &[u8], end_of_stream: bool) {
+        self.request_body
+            .push(chunk, end_of_stream, self.max_body_bytes);
+    }
+
+    /// Records the response header as it goes downstream.
+    pub fn response(&mut self, header: &ResponseHeader) {
+        self.response = Some(header.clone());
+        self.response_body = Body::default();
+    }
+
+    /// Records a piece of the response body as it goes downstream.
+    pub fn response_body(&mut self, chunk: &[u8], end_of_stream: bool) {
+        self.response_body
+            .push(chunk, end_of_stream, self.max_body_bytes);
+    }
+
+    /// Writes the file in the background, with `written` as the response
+    /// when none went through the upstream phases, e.g. one a filter sent.
+    pub fn finish(mut self, written: Option<&ResponseHeader>) {
+        if self.response.is_none() {
+            self.response = written.cloned();
+        }
+
+        let Some(response) = self.response.as_ref() else {
+            // The client went away before it got an answer.
+            return;
+        };
+
+        let har = self.to_har(response);
+        let name = format!("{}-{}", millis(self.started), Uuid::new_v4());
+        let dir = self.dir;
+
+        tokio::spawn(async move {
+            let file = dir.join(format!("{name}.har"));
+            // Written aside first so readers of the directory see whole files.
+            let partial = dir.join(format!(".{name}.har.partial"));
+
+            let written = async {
+                tokio::fs::create_dir_all(&dir).await?;
+                tokio::fs::write(&partial, har.to_string()).await?;
+                tokio::fs::rename(&partial, &file).await
+            };
+
+            if let Err(e) = written.await {
+                tracing::warn!(?file, "Failed to write HAR recording: {e}");
+            }
+        });
+    }
+
+    fn to_har(&self, response: &ResponseHeader) -> Value {
+        let mut request = self.request.clone();
+        request["bodySize"] = self.request_body.size.into();
+        if self.request_body.size > 0 {
+            request["postData"] = self
+                .request_body
+                .content(&self.request_type, self.max_body_bytes);
+        }
+
+        let time = self.clock.elapsed().as_secs_f64() * 1000.0;
+
+        json!({
+            "log": {
+                "version": "1.2",
+                "creator": { "name": "motya", "version": env!("CARGO_PKG_VERSION") },
+                "entries": [{
+                    "startedDateTime": iso8601(self.started),
+                    "time": time,
+                    "request": request,
+                    "response": {
+                        "status": response.status.as_u16(),
+                        "statusText": response.status.canonical_reason().unwrap_or_default(),
+                        "httpVersion": format!("{:?}", response.version),
+                        "cookies": [],
+                        "headers": headers(&response.headers),
+                        "content": self
+                            .response_body
+                            .content(content_type(&response.headers), self.max_body_bytes),
+                        "redirectURL": "",
+                        "headersSize": -1,
+                        "bodySize": self.response_body.size,
+                    },
+                    "cache": {},
+                    "timings": { "send": 0, "wait": time, "receive": 0 },
+                }],
+            },
+        })
+    }
+}
+
+/// The request part of an entry, without its body.
+fn request(req: &RequestHeader, tls: bool) -> Value {
+    let scheme = if tls { "https" } else { "http" };
+    let host = req
+        .headers
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| req.uri.authority().map(|authority| authority.as_str()))
+        .unwrap_or_default();
+    let path = req.uri.path_and_query().map_or("/", |path| path.as_str());
+
+    let query: Vec<Value> = req
+        .uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            json!({ "name": name, "value": value })
+        })
+        .collect();
+
+    json!({
+        "method": req.method.as_str(),
+        "url": format!("{scheme}://{host}{path}"),
+        "httpVersion": format!("{:?}", req.version),
+        "cookies": [],
+        "headers": headers(&req.headers),
+        "queryString": query,
+        "headersSize": -1,
+        "bodySize": 0,
+    })
+}
+
+fn headers(headers: &HeaderMap) -> Value {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name.as_str(),
+                "value": String::from_utf8_lossy(value.as_bytes()),
+            })
+        })
+        .collect()
+}
+
+fn content_type(headers: &HeaderMap) -> &str {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+fn millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// `time` in UTC as `2024-03-01T12:00:00.000Z`.
+fn iso8601(time: SystemTime) -> String {
+    let millis = millis(time) as i64;
+    let (days, of_day) = (millis.div_euclid(86_400_000), millis.rem_euclid(86_400_000));
+
+    // Proleptic Gregorian date of a day count since 1970-01-01.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        of_day / 3_600_000,
+        of_day / 60_000 % 60,
+        of_day / 1000 % 60,
+        of_day % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn recorder(one_in: usize, max_body_bytes: usize) -> HarRecorder {
+        HarRecordingConfig {
+            dir: "har".into(),
+            one_in,
+            max_body_bytes,
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(iso8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            iso8601(UNIX_EPOCH + Duration::from_millis(1_709_294_400_123)),
+            "2024-03-01T12:00:00.123Z"
+        );
+        assert_eq!(
+            iso8601(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut req = RequestHeader::build("POST", b"/api/items?page=2&q", None).unwrap();
+        req.insert_header("host", "example.com").unwrap();
+        req.insert_header("content-type", "application/json")
+            .unwrap();
+
+        let mut capture = recorder(1, 8).start(&req, true).unwrap();
+        capture.request_body(b"{\"name\":", false);
+        capture.request_body(b"\"x\"}", true);
+        capture.request_body(b"{\"name\":\"x\"}", true);
+
+        let mut resp = ResponseHeader::build(201, None).unwrap();
+        resp.insert_header("content-type", "application/octet-stream")
+            .unwrap();
+        capture.response(&resp);
+        capture.response_body(&[0xff, 0x00], true);
+
+        let har = capture.to_har(capture.response.as_ref().unwrap());
+        let entry = &har["log"]["entries"][0];
+
+        assert_eq!(
+            entry["request"]["url"],
+            "https://example.com/api/items?page=2&q"
+        );
+        assert_eq!(
+            entry["request"]["queryString"],
+            json!([{ "name": "page", "value": "2" }, { "name": "q", "value": "" }])
+        );
+        assert_eq!(entry["request"]["bodySize"], 12);
+        assert_eq!(entry["request"]["postData"]["text"], "{\"name\":");
+        assert_eq!(entry["request"]["postData"]["mimeType"], "application/json");
+        assert!(entry["request"]["postData"]["comment"].is_string());
+
+        assert_eq!(entry["response"]["status"], 201);
+        assert_eq!(entry["response"]["statusText"], "Created");
+        assert_eq!(entry["response"]["content"]["encoding"], "base64");
+        assert_eq!(entry["response"]["content"]["text"], "/wA=");
+    }
+
+    #[test]
+    fn test_sampling() {
+        let req = RequestHeader::build("GET", b"/", None).unwrap();
+
+        assert!(recorder(1, 0).start(&req, false).is_some());
+
+        let sampled = (0..10_000)
+            .filter(|_| recorder(100, 0).start(&req, false).is_some())
+            .count();
+        assert!((20..300).contains(&sampled), "sampled {sampled}");
+    }
+}
@@ -0,0 +1,74 @@
+use pingora_http::RequestHeader;
+
+use crate::proxy::virtual_host::{host_matches, request_host};
+use motya_config::common_types::connectors::HostSniConfig;
+
+/// Derives the upstream SNI of a route from the host of each request.
+pub struct HostSni {
+    template: String,
+    allow: Vec<String>,
+}
+
+impl From<HostSniConfig> for HostSni {
+    fn from(config: HostSniConfig) -> Self {
+        Self {
+            template: config.template,
+            allow: config.allow,
+        }
+    }
+}
+
+impl HostSni {
+    /// SNI for `req`, `None` when its host is missing or not allowed.
+    pub fn sni(&self, req: &RequestHeader) -> Option<String> {
+        let host = request_host(req)?;
+
+        if !self
+            .allow
+            .iter()
+            .any(|pattern| host_matches(pattern, &host))
+        {
+            tracing::debug!("Host '{host}' is not allowed as the upstream SNI");
+            return None;
+        }
+
+        Some(self.template.replace("{host}", &host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::HOST;
+
+    use super::*;
+
+    fn request(host: &str) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(HOST, host).unwrap();
+        req
+    }
+
+    #[test]
+    fn test_sni() {
+        let host_sni = HostSni::from(HostSniConfig {
+            template: "{host}".into(),
+            allow: vec!["*.tenants.example.com".into(), "example.com".into()],
+        });
+
+        assert_eq!(
+            host_sni.sni(&request("Acme.tenants.example.com:8443")),
+            Some("acme.tenants.example.com".into())
+        );
+        assert_eq!(
+            host_sni.sni(&request("example.com")),
+            Some("example.com".into())
+        );
+        assert_eq!(host_sni.sni(&request("a.b.tenants.example.com")), None);
+        assert_eq!(host_sni.sni(&request("tenants.example.com")), None);
+        assert_eq!(host_sni.sni(&request("evil.com")), None);
+        assert_eq!(host_sni.sni(&request("[::1]:443")), None);
+
+        let no_host = RequestHeader::build("GET", b"/", None).unwrap();
+        assert_eq!(host_sni.sni(&no_host), None);
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::OnceLock;
+
+use pingora_http::RequestHeader;
+use pingora_proxy::Session;
+use prometheus::{register_histogram_vec, register_int_gauge_vec, HistogramVec, IntGaugeVec};
+
+use crate::metrics::Active;
+
+fn active_metric() -> &'static IntGaugeVec {
+    static METRIC: OnceLock<IntGaugeVec> = OnceLock::new();
+
+    METRIC.get_or_init(|| {
+        register_int_gauge_vec!(
+            "motya_listener_requests_active",
+            "Downstream requests being served, per listener address",
+            &["listener"]
+        )
+        .expect("metric should register once")
+    })
+}
+
+fn h2_headers_metric() -> &'static HistogramVec {
+    static METRIC: OnceLock<HistogramVec> = OnceLock::new();
+
+    METRIC.get_or_init(|| {
+        register_histogram_vec!(
+            "motya_listener_h2_header_list_bytes",
+            "Header list size of HTTP/2 requests as counted against 'h2-max-header-list-bytes', per listener address",
+            &["listener"],
+            vec![1024.0, 4096.0, 8192.0, 16384.0, 32768.0, 65536.0, 131072.0]
+        )
+        .expect("metric should register once")
+    })
+}
+
+fn listener(session: &Session) -> String {
+    session
+        .server_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default()
+}
+
+/// Counts the request of `session` as active on its listener until the guard
+/// is dropped.
+pub fn active(session: &Session) -> Active {
+    Active::new(active_metric().with_label_values(&[&listener(session)]))
+}
+
+/// Records how close an HTTP/2 request came to the header list limit of its
+/// listener. Requests over it were refused before reaching the proxy.
+pub fn record_h2_headers(session: &Session) {
+    if !session.is_http2() {
+        return;
+    }
+
+    h2_headers_metric()
+        .with_label_values(&[&listener(session)])
+        .observe(header_list_size(session.req_header()) as f64);
+}
+
+/// Size of the header list of `req` as HTTP/2 counts it, pseudo-headers
+/// included, with 32 bytes of overhead per field (RFC 9113, 6.5.2).
+fn header_list_size(req: &RequestHeader) -> usize {
+    const FIELD_OVERHEAD: usize = 32;
+
+    let pseudo = [
+        Some((":method", req.method.as_str())),
+        req.uri
+            .path_and_query()
+            .map(|path| (":path", path.as_str())),
+        req.uri.scheme_str().map(|scheme| (":scheme", scheme)),
+        req.uri
+            .authority()
+            .map(|authority| (":authority", authority.as_str())),
+    ];
+
+    let pseudo = pseudo
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| name.len() + value.len() + FIELD_OVERHEAD);
+    let fields = req
+        .headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + FIELD_OVERHEAD);
+
+    pseudo.chain(fields).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_list_size() {
+        let mut req = RequestHeader::build("GET", b"/a", None).unwrap();
+        // ":method" "GET" and ":path" "/a"
+        assert_eq!(header_list_size(&req), 10 + 32 + 7 + 32);
+
+        req.insert_header("x-id", "12345").unwrap();
+        assert_eq!(header_list_size(&req), 10 + 32 + 7 + 32 + 9 + 32);
+    }
+}
@@ -1,41 +1,87 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures_util::future::try_join_all;
-use http::uri::PathAndQuery;
+use http::{uri::PathAndQuery, Extensions, StatusCode};
 use pingora::{prelude::HttpPeer, server::Server, Result};
 use pingora_http::{RequestHeader, ResponseHeader};
-use pingora_proxy::{ProxyHttp, Session};
+use pingora_proxy::{FailToProxy, ProxyHttp, Session};
 use uuid::Uuid;
 
-use crate::proxy::{
-    context::{ContextInfo, SessionInfo},
-    filters::builtin::simple_response::SimpleResponse,
-    filters::{
-        chain_resolver::ChainResolver,
-        types::{RequestFilterMod, RequestModifyMod, ResponseModifyMod},
+use crate::{
+    alerting,
+    flags::{self, Flags},
+    maintenance::MaintenancePage,
+    metrics::Active,
+    proxy::{
+        body_limit::RequestBodyLimit,
+        buffering::ResponseBuffer,
+        cache::CacheFill,
+        context::{ContextInfo, SessionInfo},
+        error_pages::ErrorPages,
+        filters::builtin::{redirect::Redirect, simple_response::SimpleResponse},
+        filters::types::{BodyStream, RequestFilterMod, RequestModifyMod, ResponseModifyMod},
+        har::HarCapture,
+        plugins::mutations::RequestMutations,
+        populate_listeners::populate_listners,
+        primary::MethodSplit,
+        read_your_writes::ReadYourWrites,
+        retry::Failure,
+        timeouts::RouteTimeouts,
+        upstream_factory::UpstreamFactory,
+        upstream_router::{UpstreamContext, UpstreamContextTrait, UpstreamRouter},
+        virtual_host::{Dispatch, VirtualHost, VirtualHosts},
     },
-    populate_listeners::populate_listners,
-    upstream_factory::UpstreamFactory,
-    upstream_router::{UpstreamContext, UpstreamRouter},
+    runtime_metrics::RuntimeTracker,
 };
 use motya_config::{
     common_types::{
-        connectors::{UpstreamConfig, UpstreamContextConfig},
-        listeners::Listeners,
+        connectors::UpstreamConfig,
+        listeners::{ListenerConfig, Listeners},
     },
     internal::ProxyConfig,
 };
 
+pub mod acme;
+pub mod backend_metrics;
+pub mod backend_override;
 pub mod balancer;
+pub mod body_limit;
+pub mod buffering;
+pub mod cache;
+pub mod compression;
 pub mod context;
+pub mod error_pages;
 pub mod filters;
+pub mod grpc;
+pub mod har;
+pub mod host_sni;
+pub mod listener_metrics;
+pub mod negotiation;
 pub mod plugins;
+pub mod pool_warm;
 pub mod populate_listeners;
+pub mod primary;
+pub mod read_your_writes;
+pub mod retry;
+pub mod shaping;
+pub mod timeouts;
+pub mod tls_fingerprint;
+pub mod traffic_split;
 pub mod upstream_factory;
 pub mod upstream_router;
+pub mod virtual_host;
+pub mod warm_up;
 pub mod watcher;
+pub mod websocket;
+pub mod when_down;
 
 // pub struct RateLimiters {
 //     request_filter_stage_multi: Vec<MultiRaterInstance>,
@@ -46,72 +92,267 @@ pub type SharedProxyState = Arc<ArcSwap<UpstreamRouter<UpstreamContext>>>;
 
 pub struct MotyaProxyService {
     // pub rate_limiters: RateLimiters,
-    pub state: SharedProxyState,
+    pub hosts: VirtualHosts,
+    /// Set on `redirect-to-https` listeners, answering every request with a redirect.
+    pub redirect: Option<Redirect>,
+    /// `max-request-body-bytes` of the listeners, for routes without their own.
+    pub max_request_body: Option<usize>,
+    /// Registers the runtime of the service for the runtime metrics.
+    pub runtime: RuntimeTracker,
 }
 
 /// Create a proxy service, with the type parameters chosen based on the config file
 pub async fn motya_proxy_service(
     conf: ProxyConfig,
-    chain_resolver: ChainResolver,
+    factory: UpstreamFactory,
     server: &Server,
-) -> miette::Result<(Box<dyn pingora::services::Service>, SharedProxyState)> {
-    let factory = UpstreamFactory::new(chain_resolver);
+) -> miette::Result<(Vec<Box<dyn pingora::services::Service>>, SharedProxyState)> {
+    let host = virtual_host(&conf, factory).await?;
+    let shared_state = host.state.clone();
+
+    let services = shared_proxy_services(std::slice::from_ref(&conf), &[host], server);
+
+    Ok((services, shared_state))
+}
+
+/// Builds the routes of a proxy service, to be served with
+/// [MotyaProxyService::from_hosts] on the listeners it shares.
+pub async fn virtual_host(
+    conf: &ProxyConfig,
+    upstream_factory: UpstreamFactory,
+) -> miette::Result<VirtualHost> {
+    let upstream_ctx = try_join_all(
+        conf.connectors
+            .upstreams
+            .iter()
+            .cloned()
+            .map(|cfg| upstream_factory.create_context(cfg)),
+    )
+    .await?;
+
+    let router = UpstreamRouter::build(upstream_ctx)
+        .expect("Paths must be valid after parsing the configuration");
+
+    // let mut request_filter_stage_multi = vec![];
+    // let mut request_filter_stage_single = vec![];
+
+    // for rule in rate_limiting.rules.clone() {
+    //     match rule {
+    //         AllRateConfig::Single { kind, config } => {
+    //             let rater = SingleInstance::new(config, kind);
+    //             request_filter_stage_single.push(rater);
+    //         }
+    //         AllRateConfig::Multi { kind, config } => {
+    //             let rater = MultiRaterInstance::new(config, kind);
+    //             request_filter_stage_multi.push(rater);
+    //         }
+    //     }
+    // }
+
+    Ok(VirtualHost {
+        hosts: conf.hosts.clone(),
+        state: Arc::new(ArcSwap::from_pointee(router)),
+        warm_up: conf.warm_up.as_ref().map(|_| warm_up::gate(&conf.name)),
+        error_pages: conf
+            .error_pages
+            .as_ref()
+            .map(ErrorPages::load)
+            .transpose()?
+            .map(Arc::new),
+        maintenance: Arc::new(MaintenancePage::load(&conf.name, &conf.maintenance)?),
+    })
+}
+
+/// Serves `hosts`, built from `proxies` in the same order, with one service
+/// per set of listeners the same proxy services share.
+pub fn shared_proxy_services(
+    proxies: &[ProxyConfig],
+    hosts: &[VirtualHost],
+    server: &Server,
+) -> Vec<Box<dyn pingora::services::Service>> {
+    let mut listeners: BTreeMap<String, (Vec<usize>, &ListenerConfig)> = BTreeMap::new();
+
+    for (idx, proxy) in proxies.iter().enumerate() {
+        for listener in &proxy.listeners.list_cfgs {
+            listeners
+                .entry(listener.source.address())
+                .or_insert_with(|| (Vec::new(), listener))
+                .0
+                .push(idx);
+        }
+    }
+
+    // Sharing services agree on the listener settings, checked by the compiler.
+    // The settings handled per request split the listeners of a service.
+    let mut groups: Vec<(Vec<usize>, Vec<ListenerConfig>)> = Vec::new();
+
+    for (services, listener) in listeners.into_values() {
+        let alike = |other: &ListenerConfig| {
+            other.unmatched_host == listener.unmatched_host
+                && other.redirect_to_https == listener.redirect_to_https
+                && other.max_request_body_bytes == listener.max_request_body_bytes
+                && other.h2_limits == listener.h2_limits
+        };
 
-    MotyaProxyService::from_basic_conf(conf.connectors.upstreams, &conf.listeners, factory, server)
-        .await
+        match groups
+            .iter_mut()
+            .find(|(other, list_cfgs)| *other == services && alike(&list_cfgs[0]))
+        {
+            Some((_, list_cfgs)) => list_cfgs.push(listener.clone()),
+            None => groups.push((services, vec![listener.clone()])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(services, list_cfgs)| {
+            let hosts = services.iter().map(|&idx| hosts[idx].clone()).collect();
+
+            MotyaProxyService::from_hosts(hosts, &Listeners { list_cfgs }, server)
+        })
+        .collect()
 }
 
 impl MotyaProxyService {
-    /// Create a new [MotyaProxyService] from the given [ProxyConfig]
-    pub async fn from_basic_conf(
-        upstream_configs: Vec<UpstreamContextConfig>,
+    /// Create a new [MotyaProxyService] serving `hosts` on `listeners`, which
+    /// agree on `unmatched-host`, `redirect-to-https`, `max-request-body-bytes`
+    /// and the `h2-*` limits
+    pub fn from_hosts(
+        hosts: Vec<VirtualHost>,
         listeners: &Listeners,
-        upstream_factory: UpstreamFactory,
         server: &Server,
-    ) -> miette::Result<(Box<dyn pingora::services::Service>, SharedProxyState)> {
-        let upstream_ctx = try_join_all(
-            upstream_configs
-                .into_iter()
-                .map(|cfg| upstream_factory.create_context(cfg)),
-        )
-        .await?;
-
-        let router = UpstreamRouter::build(upstream_ctx)
-            .expect("Paths must be valid after parsing the configuration");
-
-        // let mut request_filter_stage_multi = vec![];
-        // let mut request_filter_stage_single = vec![];
-
-        // for rule in rate_limiting.rules.clone() {
-        //     match rule {
-        //         AllRateConfig::Single { kind, config } => {
-        //             let rater = SingleInstance::new(config, kind);
-        //             request_filter_stage_single.push(rater);
-        //         }
-        //         AllRateConfig::Multi { kind, config } => {
-        //             let rater = MultiRaterInstance::new(config, kind);
-        //             request_filter_stage_multi.push(rater);
-        //         }
-        //     }
-        // }
-
-        let shared_state = Arc::new(ArcSwap::from_pointee(router));
+    ) -> Box<dyn pingora::services::Service> {
+        let first = listeners.list_cfgs.first();
+
+        let service = Self {
+            hosts: VirtualHosts::new(hosts, first.and_then(|l| l.unmatched_host)),
+            redirect: first
+                .is_some_and(|l| l.redirect_to_https)
+                .then(Redirect::to_https),
+            max_request_body: first.and_then(|l| l.max_request_body_bytes),
+            runtime: RuntimeTracker::new(first.map(|l| l.source.address()).unwrap_or_default()),
+        };
+
         let mut my_proxy = pingora_proxy::http_proxy_service_with_name(
             &server.configuration,
-            Self {
-                state: shared_state.clone(),
-            },
+            service,
             "motya-proxy",
         );
 
         populate_listners(listeners, &mut my_proxy);
 
-        Ok((Box::new(my_proxy), shared_state))
+        Box::new(my_proxy)
     }
 }
 
 pub struct MotyaContext {
     router: Arc<UpstreamRouter<UpstreamContext>>,
+    info: ContextInfo,
+    /// Route of an open WebSocket connection, for the connection counters.
+    websocket: Option<String>,
+    /// Arrival of the request, the start of a route's `total` timeout.
+    started: Instant,
+    /// Upstream attempts retried so far.
+    retries: usize,
+    /// Pause before the next attempt, set when a retry was decided.
+    backoff: Option<Duration>,
+    /// Backend of the current attempt until its outcome is known.
+    backend: Option<SocketAddr>,
+    /// When the backend of the current attempt was picked.
+    attempt_started: Instant,
+    /// Body rewrites started for the request of the current attempt.
+    req_body_streams: Vec<Box<dyn BodyStream>>,
+    /// Bytes of the request body still accepted, set on routes with a limit.
+    req_body_limit: Option<RequestBodyLimit>,
+    /// Body rewrites started for the response, applied in chain order.
+    res_body_streams: Vec<Box<dyn BodyStream>>,
+    /// Holds the response body back on routes with `buffering`.
+    res_buffer: Option<ResponseBuffer>,
+    /// The response body read so far, on routes with a `cache` missing it.
+    cache_fill: Option<CacheFill>,
+    /// The request and response so far, on routes with `record-har`.
+    har: Option<HarCapture>,
+    /// Typed values filters hand to later filters of the request.
+    extensions: Extensions,
+    /// Feature flags as they were when the request arrived.
+    flags: Arc<Flags>,
+    /// `error-pages` of the service the request went to.
+    error_pages: Option<Arc<ErrorPages>>,
+    /// Counts the request as active on its listener while it lasts.
+    active: Option<Active>,
+    /// Counts the current attempt as in flight on its backend.
+    backend_in_flight: Option<Active>,
+}
+
+impl MotyaContext {
+    /// Values stored by earlier filters, one per type, e.g. the claims of a
+    /// verified token for a later authorization filter.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Feature flags of the `flags` section, the same for the whole request
+    /// even when the admin API toggles one meanwhile.
+    pub fn flags(&self) -> &Flags {
+        &self.flags
+    }
+
+    /// Reports the outcome of the current attempt, the status it was answered
+    /// with if any, to the backend metrics and the circuit breaker of the
+    /// route, if it has one.
+    fn record_outcome(&mut self, req: &RequestHeader, status: Option<StatusCode>) {
+        self.backend_in_flight = None;
+
+        let Some(addr) = self.backend.take() else {
+            return;
+        };
+        let Some(upstream_ctx) = self
+            .router
+            .get_upstream(req.uri.path(), &req.method, &self.flags)
+        else {
+            return;
+        };
+
+        backend_metrics::record(
+            upstream_ctx.get_prefix_path().path(),
+            addr,
+            status,
+            self.attempt_started.elapsed(),
+        );
+
+        if let Some(breaker) = upstream_ctx
+            .balancer
+            .as_ref()
+            .and_then(|balancer| balancer.breaker.as_ref())
+        {
+            let success = status.is_some_and(|status| !status.is_server_error());
+            breaker.record(&addr, success);
+        }
+    }
+}
+
+/// Passes a body chunk through `streams` in order.
+fn filter_body(
+    streams: &mut [Box<dyn BodyStream>],
+    body: &mut Option<Bytes>,
+    end_of_stream: bool,
+) -> Result<()> {
+    if streams.is_empty() {
+        return Ok(());
+    }
+
+    let mut chunk = body.take().unwrap_or_default();
+    for stream in streams {
+        chunk = stream.filter(chunk, end_of_stream)?;
+    }
+    // An empty chunk would read as the end of a chunked body.
+    *body = (!chunk.is_empty()).then_some(chunk);
+
+    Ok(())
 }
 
 #[async_trait]
@@ -119,9 +360,28 @@ impl ProxyHttp for MotyaProxyService {
     type CTX = MotyaContext;
 
     fn new_ctx(&self) -> Self::CTX {
-        let router = self.state.load();
+        let host = self.hosts.first();
+        let router = host.state.load();
         MotyaContext {
             router: router.clone(),
+            info: ContextInfo::default(),
+            websocket: None,
+            started: Instant::now(),
+            retries: 0,
+            backoff: None,
+            backend: None,
+            attempt_started: Instant::now(),
+            req_body_streams: Vec::new(),
+            req_body_limit: None,
+            res_body_streams: Vec::new(),
+            res_buffer: None,
+            cache_fill: None,
+            har: None,
+            extensions: Extensions::new(),
+            flags: flags::snapshot(),
+            error_pages: host.error_pages.clone(),
+            active: None,
+            backend_in_flight: None,
         }
     }
 
@@ -130,10 +390,139 @@ impl ProxyHttp for MotyaProxyService {
     where
         Self::CTX: Send + Sync,
     {
+        self.runtime.track();
+        ctx.active = Some(listener_metrics::active(session));
+        listener_metrics::record_h2_headers(session);
+        if let Some(fingerprint) = tls_fingerprint::of(session) {
+            ctx.extensions_mut().insert(fingerprint);
+        }
+
+        if acme::challenges::answer(session).await? {
+            return Ok(true);
+        }
+
+        if let Some(redirect) = &self.redirect {
+            return redirect.request_filter(session, ctx).await;
+        }
+
+        let host = match self.hosts.dispatch(session.req_header()) {
+            Dispatch::Service(host) => host,
+            Dispatch::Misdirected => {
+                let mut response = ResponseHeader::build(421, Some(1))?;
+                response.insert_header("Content-Length", "0")?;
+
+                session
+                    .downstream_session
+                    .write_response_header(Box::new(response))
+                    .await?;
+                session
+                    .downstream_session
+                    .write_response_body(Bytes::new(), true)
+                    .await?;
+                return Ok(true);
+            }
+            Dispatch::Close => {
+                session.downstream_session.set_keepalive(None);
+                session.downstream_session.shutdown().await;
+                return Ok(true);
+            }
+        };
+
+        // Requests start on the first service, the others are picked by host.
+        if !std::ptr::eq(host, self.hosts.first()) {
+            ctx.router = host.state.load_full();
+            ctx.error_pages = host.error_pages.clone();
+        }
+
+        // Requests opening upstream connections for `pool-min-idle` go
+        // straight to their backend.
+        if pool_warm::warming_backend(session.req_header()).is_some() {
+            return Ok(false);
+        }
+
+        if host.maintenance.is_on() {
+            return host.maintenance.respond(session).await;
+        }
+
+        if let Some(gate) = &host.warm_up {
+            if !gate.admits(session.req_header()) {
+                let mut response = ResponseHeader::build(503, Some(2))?;
+                response.insert_header("Retry-After", "1")?;
+                response.insert_header("Content-Length", "0")?;
+
+                session
+                    .downstream_session
+                    .write_response_header(Box::new(response))
+                    .await?;
+                session
+                    .downstream_session
+                    .write_response_body(Bytes::new(), true)
+                    .await?;
+                return Ok(true);
+            }
+        }
+
         let router = ctx.router.clone();
+
+        for chain in router.pre_routing_chains() {
+            for filter in &chain.pre_routing {
+                if filter.pre_routing_filter(session, ctx).await? {
+                    return Ok(true);
+                }
+            }
+        }
+
         let path = session.req_header().uri.path();
+        let method = session.req_header().method.clone();
+
+        if let Some(captures) = router.route_captures(path, &method, &ctx.flags) {
+            ctx.extensions_mut().insert(captures);
+        }
+
+        if let Some(upstream_ctx) = router.get_upstream(path, &method, &ctx.flags) {
+            if let Some(max) = upstream_ctx.max_request_body.or(self.max_request_body) {
+                let limit = RequestBodyLimit::new(max);
+                limit.check_header(session.req_header())?;
+                ctx.req_body_limit = Some(limit);
+            }
+
+            if let Some(recorder) = &upstream_ctx.record_har {
+                let tls = session
+                    .downstream_session
+                    .digest()
+                    .is_some_and(|digest| digest.ssl_digest.is_some());
+                ctx.har = recorder.start(session.req_header(), tls);
+            }
+
+            if let Some(shaper) = &upstream_ctx.shaping {
+                tokio::time::sleep(shaper.delay()).await;
+            }
+
+            if let Some(retry) = &upstream_ctx.retry {
+                retry.record_request();
+                // Keeps the request body around for another attempt.
+                session.enable_retry_buffering();
+            }
+
+            if websocket::is_upgrade(session.req_header()) {
+                let route = upstream_ctx.get_prefix_path().path();
+
+                if !upstream_ctx.allows_websocket() {
+                    websocket::refused(route);
+                    session.downstream_session.respond_error(403).await?;
+                    return Ok(true);
+                }
+
+                for chain in &upstream_ctx.chains {
+                    for filter in &chain.upgrades {
+                        if filter.upgrade_filter(session, ctx).await? {
+                            websocket::refused(route);
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
 
-        if let Some(upstream_ctx) = router.get_upstream_by_path(path) {
             // let multis = self
             //     .rate_limiters
             //     .request_filter_stage_multi
@@ -180,12 +569,35 @@ impl ProxyHttp for MotyaProxyService {
                 }
             }
 
+            if let Some(route_cache) = &upstream_ctx.cache {
+                if let Some(cached) = route_cache.lookup(session.req_header()).await {
+                    cache::respond(session, cached).await?;
+                    return Ok(true);
+                }
+            }
+
             if let UpstreamConfig::Static(response) = upstream_ctx.upstream.clone() {
                 let _ = std::convert::Into::<SimpleResponse>::into(response)
                     .request_filter(session, ctx)
                     .await?;
                 return Ok(true);
             }
+        } else if let Some(allowed) = router.allowed_methods(path, &ctx.flags) {
+            let allowed: Vec<_> = allowed.iter().map(|method| method.as_str()).collect();
+
+            let mut response = ResponseHeader::build(405, Some(2))?;
+            response.insert_header("Allow", allowed.join(", "))?;
+            response.insert_header("Content-Length", "0")?;
+
+            session
+                .downstream_session
+                .write_response_header(Box::new(response))
+                .await?;
+            session
+                .downstream_session
+                .write_response_body(Bytes::new(), true)
+                .await?;
+            return Ok(true);
         }
 
         Ok(false)
@@ -199,8 +611,12 @@ impl ProxyHttp for MotyaProxyService {
     ) -> Result<Box<HttpPeer>> {
         static DEFAULT: PathAndQuery = PathAndQuery::from_static("/");
 
+        if let Some(backoff) = ctx.backoff.take() {
+            tokio::time::sleep(backoff).await;
+        }
+
         match ctx.router.pick_peer(
-            &mut ContextInfo {},
+            &mut ctx.info,
             &mut SessionInfo {
                 headers: session.req_header(),
                 client_addr: session.client_addr(),
@@ -210,9 +626,64 @@ impl ProxyHttp for MotyaProxyService {
                     .path_and_query()
                     .unwrap_or(&DEFAULT),
             },
+            &ctx.flags,
         ) {
-            Ok(Some(peer)) => Ok(Box::new(peer)),
+            Ok(Some(mut peer)) => {
+                let router = ctx.router.clone();
+                let path = session.req_header().uri.path();
+                let upstream_ctx =
+                    router.get_upstream(path, &session.req_header().method, &ctx.flags);
+
+                if let Some(timeouts) =
+                    upstream_ctx.and_then(|upstream_ctx| upstream_ctx.timeouts.as_ref())
+                {
+                    timeouts.apply(&mut peer, ctx.started)?;
+                }
+
+                if let Some(buffering) =
+                    upstream_ctx.and_then(|upstream_ctx| upstream_ctx.buffering.as_ref())
+                {
+                    buffering.apply(&mut peer);
+                }
+
+                if let Some(host_sni) =
+                    upstream_ctx.and_then(|upstream_ctx| upstream_ctx.host_sni.as_ref())
+                {
+                    peer.sni = host_sni.sni(session.req_header()).ok_or_else(|| {
+                        pingora::Error::explain(
+                            pingora::ErrorType::HTTPStatus(421),
+                            "Host is not allowed as the upstream SNI",
+                        )
+                    })?;
+                }
+
+                ctx.backend = peer._address.as_inet().copied();
+                ctx.attempt_started = Instant::now();
+
+                for chain in upstream_ctx
+                    .iter()
+                    .flat_map(|upstream_ctx| &upstream_ctx.chains)
+                {
+                    for filter in &chain.post_select {
+                        filter.post_select_filter(session, &mut peer, ctx).await?;
+                    }
+                }
+
+                // Filters may have moved the request to another address.
+                ctx.backend = peer._address.as_inet().copied();
+                ctx.backend_in_flight = ctx.backend.map(backend_metrics::in_flight);
+
+                Ok(Box::new(peer))
+            }
             Ok(None) => Err(pingora::Error::new(pingora::ErrorType::HTTPStatus(404))),
+            // Answered by `fail_to_proxy`, with the `when-down` response if any.
+            Err(err) if err.etype() == &when_down::ALL_BACKENDS_DOWN => {
+                tracing::warn!(
+                    "No backend available for {}",
+                    session.req_header().uri.path()
+                );
+                Err(err)
+            }
             Err(err) => {
                 let id = Uuid::new_v4();
                 tracing::error!("[{id}] error on pick_peer. err: {err}");
@@ -222,6 +693,119 @@ impl ProxyHttp for MotyaProxyService {
         }
     }
 
+    /// Retries reads of a `split-by-method` route on a primary when the replica
+    /// cannot be reached, and connection failures of routes with a `retry` policy.
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<pingora::Error>,
+    ) -> Box<pingora::Error> {
+        let router = ctx.router.clone();
+        let path = session.req_header().uri.path();
+        ctx.record_outcome(session.req_header(), None);
+
+        let Some(upstream_ctx) =
+            router.get_upstream(path, &session.req_header().method, &ctx.flags)
+        else {
+            return e;
+        };
+        let method = &session.req_header().method;
+
+        if upstream_ctx.split_by_method.is_some()
+            && !ctx.info.replica_failed
+            && MethodSplit::is_replica_read(method)
+        {
+            ctx.info.replica_failed = true;
+            e.set_retry(true);
+            return e;
+        }
+
+        if let Some(retry) = &upstream_ctx.retry {
+            let route = upstream_ctx.get_prefix_path().path();
+
+            if retry.should_retry(route, method, ctx.retries, Failure::Connect) {
+                ctx.retries += 1;
+                ctx.backoff = Some(retry.backoff(ctx.retries));
+                e.set_retry(true);
+            }
+        }
+
+        e
+    }
+
+    /// Answers gRPC calls on `proto="grpc"` routes with a `grpc-status`,
+    /// routes without an available backend with their `when-down` response and
+    /// timeouts of routes with `timeouts` with their `504`, everything else
+    /// gets the service's `error-pages` page or the default one.
+    async fn fail_to_proxy(
+        &self,
+        session: &mut Session,
+        e: &pingora::Error,
+        ctx: &mut Self::CTX,
+    ) -> FailToProxy
+    where
+        Self::CTX: Send + Sync,
+    {
+        let router = ctx.router.clone();
+        let path = session.req_header().uri.path();
+        let upstream_ctx = router.get_upstream(path, &session.req_header().method, &ctx.flags);
+
+        if upstream_ctx.is_some_and(|upstream_ctx| upstream_ctx.is_grpc())
+            && grpc::is_grpc(session.req_header())
+        {
+            return grpc::fail_to_proxy(session, e).await;
+        }
+
+        if e.etype() == &when_down::ALL_BACKENDS_DOWN {
+            if let Some(when_down) =
+                upstream_ctx.and_then(|upstream_ctx| upstream_ctx.when_down.as_ref())
+            {
+                return when_down.respond(session).await;
+            }
+        }
+
+        if let Some(timeouts) = upstream_ctx.and_then(|upstream_ctx| upstream_ctx.timeouts.as_ref())
+        {
+            if RouteTimeouts::is_timeout(e) {
+                return timeouts.respond(session).await;
+            }
+        }
+
+        // Same as the default implementation.
+        let code = match e.etype() {
+            pingora::ErrorType::HTTPStatus(code) => *code,
+            _ => match e.esource() {
+                pingora::ErrorSource::Upstream => 502,
+                pingora::ErrorSource::Downstream => match e.etype() {
+                    pingora::ErrorType::WriteError
+                    | pingora::ErrorType::ReadError
+                    | pingora::ErrorType::ConnectionClosed => 0,
+                    _ => 400,
+                },
+                pingora::ErrorSource::Internal | pingora::ErrorSource::Unset => 500,
+            },
+        };
+
+        let error_pages = ctx.error_pages.clone();
+        let answered = match &error_pages {
+            Some(error_pages) if code > 0 => error_pages.respond(session, code).await,
+            _ => false,
+        };
+
+        if code > 0 && !answered {
+            session.respond_error(code).await.unwrap_or_else(|e| {
+                tracing::error!("failed to send error response to downstream: {e}");
+            });
+        }
+
+        FailToProxy {
+            error_code: code,
+            can_reuse_downstream: false,
+        }
+    }
+
     /// Handle the "upstream request filter" phase, where we can choose to make
     /// modifications to the request, prior to it being passed along to the
     /// upstream.
@@ -235,20 +819,86 @@ impl ProxyHttp for MotyaProxyService {
         header: &mut RequestHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
+        // The token is only meant for this process.
+        header.remove_header(pool_warm::POOL_WARM_HEADER);
+
         let router = ctx.router.clone();
         let path = session.req_header().uri.path();
 
-        if let Some(upstream_ctx) = router.get_upstream_by_path(path) {
+        // Requests opening upstream connections for `pool-min-idle` reach the
+        // backend for its `pool-warm-path`, untouched by the route filters.
+        if pool_warm::warming_backend(session.req_header()).is_some() {
+            if let Some(warmer) = router
+                .get_upstream(path, &session.req_header().method, &ctx.flags)
+                .and_then(|upstream| upstream.get_pool_warmer())
+            {
+                header.set_uri(warmer.upstream_uri());
+            }
+            return Ok(());
+        }
+
+        // Changes `filter` plugins and `ext-auth` made to the request.
+        if let Some(mutations) = ctx.extensions().get::<RequestMutations>() {
+            mutations.apply(header)?;
+        }
+
+        if let Some(upstream_ctx) =
+            router.get_upstream(path, &session.req_header().method, &ctx.flags)
+        {
             for chain in &upstream_ctx.chains {
                 for filter in &chain.req_mods {
                     filter.upstream_request_filter(session, header, ctx).await?;
                 }
             }
+
+            // Retries send the body again, through fresh streams.
+            ctx.req_body_streams.clear();
+
+            for chain in &upstream_ctx.chains {
+                for filter in &chain.req_body_mods {
+                    if let Some(stream) = filter.request_body_start(session, header) {
+                        ctx.req_body_streams.push(stream);
+                    }
+                }
+            }
+
+            // The rewritten body has a length of its own. HTTP/2 frames it
+            // without one and refuses `Transfer-Encoding`, an HTTP/1.1 backend
+            // would read a body without either as empty.
+            if !ctx.req_body_streams.is_empty() {
+                header.remove_header(&http::header::CONTENT_LENGTH);
+                if header.version == http::Version::HTTP_11 {
+                    header.insert_header(http::header::TRANSFER_ENCODING, "chunked")?;
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Counts the request body against its limit and runs its rewrites
+    /// before it is sent upstream.
+    async fn request_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        if let (Some(limit), Some(chunk)) = (&mut ctx.req_body_limit, body.as_ref()) {
+            limit.take(chunk.len())?;
+        }
+
+        if let Some(har) = &mut ctx.har {
+            har.request_body(body.as_deref().unwrap_or_default(), end_of_stream);
+        }
+
+        filter_body(&mut ctx.req_body_streams, body, end_of_stream)
+    }
+
     /// Handle the "upstream response filter" phase, where we can choose to make
     /// modifications to the response, prior to it being passed along downstream
     ///
@@ -260,16 +910,298 @@ impl ProxyHttp for MotyaProxyService {
         upstream_response: &mut ResponseHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
+        static DEFAULT: PathAndQuery = PathAndQuery::from_static("/");
+
         let router = ctx.router.clone();
         let path = session.req_header().uri.path();
+        let backend = ctx.backend;
+        ctx.record_outcome(session.req_header(), Some(upstream_response.status));
+
+        if let Some(upstream_ctx) =
+            router.get_upstream(path, &session.req_header().method, &ctx.flags)
+        {
+            if let Some(retry) = &upstream_ctx.retry {
+                let status = upstream_response.status;
+                let route = upstream_ctx.get_prefix_path().path();
+
+                // Nothing was sent downstream yet, so the response can still
+                // be dropped in favor of another attempt.
+                if status.is_server_error()
+                    && !session.retry_buffer_truncated()
+                    && retry.should_retry(
+                        route,
+                        &session.req_header().method,
+                        ctx.retries,
+                        Failure::ServerError,
+                    )
+                {
+                    ctx.retries += 1;
+                    ctx.backoff = Some(retry.backoff(ctx.retries));
+
+                    let mut e = pingora::Error::explain(
+                        pingora::ErrorType::HTTPStatus(status.as_u16()),
+                        "upstream answered with a server error",
+                    );
+                    e.set_retry(true);
+                    return Err(e);
+                }
+            }
+
+            if upstream_response.status == http::StatusCode::SWITCHING_PROTOCOLS
+                && websocket::is_upgrade(session.req_header())
+            {
+                let route = upstream_ctx.get_prefix_path().path().to_string();
+                websocket::opened(&route);
+                ctx.websocket = Some(route);
+            }
+
+            if let Some(rw) = &upstream_ctx.read_your_writes {
+                if ReadYourWrites::is_write(&session.req_header().method)
+                    && upstream_response.status.as_u16() < 400
+                {
+                    let cookie = rw.pin_cookie(upstream_ctx.get_prefix_path().path());
+                    upstream_response.append_header("Set-Cookie", cookie)?;
+                }
+            }
+
+            if let Some((sticky, backend)) = upstream_ctx
+                .balancer
+                .as_ref()
+                .and_then(|balancer| balancer.sticky.as_ref())
+                .zip(backend)
+            {
+                let info = SessionInfo {
+                    headers: session.req_header(),
+                    client_addr: session.client_addr(),
+                    path: session
+                        .req_header()
+                        .uri
+                        .path_and_query()
+                        .unwrap_or(&DEFAULT),
+                };
+
+                if let Some(cookie) =
+                    sticky.set_cookie(&info, backend, upstream_ctx.get_prefix_path().path())
+                {
+                    upstream_response.append_header("Set-Cookie", cookie)?;
+                }
+            }
 
-        if let Some(upstream_ctx) = router.get_upstream_by_path(path) {
             for chain in &upstream_ctx.chains {
                 for filter in &chain.res_mods {
                     filter.upstream_response_filter(session, upstream_response, ctx);
                 }
             }
+
+            // A response dropped for a retry may have started streams already.
+            ctx.res_body_streams.clear();
+
+            for chain in &upstream_ctx.chains {
+                for filter in &chain.res_body_mods {
+                    if let Some(stream) = filter.response_body_start(session, upstream_response) {
+                        ctx.res_body_streams.push(stream);
+                    }
+                }
+            }
+
+            // Compresses what the chains made of the body.
+            if let Some(compression) = &upstream_ctx.compression {
+                if let Some(stream) = compression.start(session.req_header(), upstream_response)? {
+                    ctx.res_body_streams.push(stream);
+                }
+            }
+
+            // The rewritten body has a length of its own. HTTP/2 frames it
+            // without one and refuses `Transfer-Encoding`, HTTP/1.0 reads it
+            // until the connection closes.
+            if !ctx.res_body_streams.is_empty() {
+                upstream_response.remove_header(&http::header::CONTENT_LENGTH);
+                if session.req_header().version == http::Version::HTTP_11 {
+                    upstream_response.insert_header(http::header::TRANSFER_ENCODING, "chunked")?;
+                } else {
+                    upstream_response.remove_header(&http::header::TRANSFER_ENCODING);
+                }
+            }
+
+            // Caches the body as the client gets it.
+            ctx.cache_fill = upstream_ctx
+                .cache
+                .as_ref()
+                .and_then(|cache| cache.start(session.req_header(), upstream_response));
+
+            ctx.res_buffer = upstream_ctx
+                .buffering
+                .as_ref()
+                .and_then(|buffering| buffering.start(upstream_response));
+
+            if let Some(har) = &mut ctx.har {
+                har.response(upstream_response);
+            }
         }
         Ok(())
     }
+
+    /// Runs the body rewrites of the response, stores it on routes with a
+    /// `cache`, holds it back on routes with `buffering`, throttles the body
+    /// of shaped routes to their configured bandwidth and cuts off responses
+    /// that outlive the route's `total` timeout.
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Duration>> {
+        let path = session.req_header().uri.path();
+        let upstream_ctx = ctx
+            .router
+            .get_upstream(path, &session.req_header().method, &ctx.flags);
+
+        if let Some(timeouts) = upstream_ctx.and_then(|upstream_ctx| upstream_ctx.timeouts.as_ref())
+        {
+            timeouts.check(ctx.started)?;
+        }
+
+        filter_body(&mut ctx.res_body_streams, body, end_of_stream)?;
+
+        let chunk = body.as_deref().unwrap_or_default();
+        if ctx
+            .cache_fill
+            .as_mut()
+            .is_some_and(|fill| !fill.push(chunk))
+        {
+            ctx.cache_fill = None;
+        }
+        if end_of_stream {
+            if let Some((fill, cache)) = ctx
+                .cache_fill
+                .take()
+                .zip(upstream_ctx.and_then(|upstream_ctx| upstream_ctx.cache.as_ref()))
+            {
+                cache.store(fill);
+            }
+        }
+
+        if let Some(buffer) = &mut ctx.res_buffer {
+            buffer.filter(body, end_of_stream);
+        }
+
+        if let Some(har) = &mut ctx.har {
+            har.response_body(body.as_deref().unwrap_or_default(), end_of_stream);
+        }
+
+        let delay = upstream_ctx
+            .and_then(|upstream_ctx| upstream_ctx.shaping.as_ref())
+            .zip(body.as_ref())
+            .and_then(|(shaper, chunk)| shaper.chunk_delay(chunk.len()));
+
+        Ok(delay)
+    }
+
+    /// Feeds the outcome of every request to the alerting error rate, and
+    /// attempts that failed without a response to the backend metrics and the
+    /// circuit breaker. Writes the `record-har` recording of the request.
+    async fn logging(&self, session: &mut Session, e: Option<&pingora::Error>, ctx: &mut Self::CTX)
+    where
+        Self::CTX: Send + Sync,
+    {
+        if e.is_some_and(|e| e.esource() == &pingora::ErrorSource::Upstream) {
+            ctx.record_outcome(session.req_header(), None);
+        }
+
+        if let Some(route) = ctx.websocket.take() {
+            websocket::closed(&route);
+        }
+
+        if let Some(har) = ctx.har.take() {
+            har.finish(session.response_written());
+        }
+
+        alerting::record_response(session.response_written().map(|resp| resp.status.as_u16()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Claims(&'static str);
+
+    /// Stands for an authentication filter handing its claims on.
+    struct Authenticate;
+
+    #[async_trait]
+    impl RequestFilterMod for Authenticate {
+        async fn request_filter(&self, _s: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+            ctx.extensions_mut().insert(Claims("alice"));
+            Ok(false)
+        }
+    }
+
+    fn service() -> MotyaProxyService {
+        let host = VirtualHost {
+            hosts: vec![],
+            state: Arc::new(ArcSwap::from_pointee(
+                UpstreamRouter::build(vec![]).unwrap(),
+            )),
+            warm_up: None,
+            error_pages: None,
+            maintenance: Arc::new(MaintenancePage::load("test", &Default::default()).unwrap()),
+        };
+
+        MotyaProxyService {
+            hosts: VirtualHosts::new(vec![host], None),
+            redirect: None,
+            max_request_body: None,
+            runtime: RuntimeTracker::new("test"),
+        }
+    }
+
+    /// A session that read a request, with the client end of its connection.
+    async fn session() -> (Session, DuplexStream) {
+        let (mut client, server) = tokio::io::duplex(1024);
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut session = Session::new_h1(Box::new(server));
+        assert!(session.read_request().await.unwrap());
+        (session, client)
+    }
+
+    #[test]
+    fn test_extensions() {
+        let mut ctx = service().new_ctx();
+        assert_eq!(ctx.extensions().get::<Claims>(), None);
+
+        ctx.extensions_mut().insert(Claims("alice"));
+        assert_eq!(ctx.extensions().get::<Claims>(), Some(&Claims("alice")));
+        // One value per type, other types are not found.
+        assert_eq!(ctx.extensions().get::<&'static str>(), None);
+        assert_eq!(ctx.extensions().get::<String>(), None);
+
+        let previous = ctx.extensions_mut().insert(Claims("bob"));
+        assert_eq!(previous, Some(Claims("alice")));
+        assert_eq!(ctx.extensions().get::<Claims>(), Some(&Claims("bob")));
+    }
+
+    #[tokio::test]
+    async fn test_extensions_across_phases() {
+        let service = service();
+        let (mut session, _client) = session().await;
+        let mut ctx = service.new_ctx();
+
+        let handled = Authenticate
+            .request_filter(&mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert!(!handled);
+
+        service.logging(&mut session, None, &mut ctx).await;
+        assert_eq!(ctx.extensions().get::<Claims>(), Some(&Claims("alice")));
+    }
 }
@@ -0,0 +1,36 @@
+//! Content negotiation helpers shared by the cache, compression and the body
+//! filters.
+
+/// Lowercased token of a list element like `gzip;q=0.8`, or `None` when the
+/// element is empty or explicitly refused with `q=0`.
+pub fn accepted_token(element: &str) -> Option<String> {
+    let mut parts = element.split(';');
+    let token = parts.next()?.trim().to_ascii_lowercase();
+
+    if token.is_empty() {
+        return None;
+    }
+
+    let refused = parts.any(|param| {
+        param
+            .trim()
+            .strip_prefix("q=")
+            .and_then(|q| q.parse::<f32>().ok())
+            .is_some_and(|q| q == 0.0)
+    });
+
+    (!refused).then_some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepted_token() {
+        assert_eq!(accepted_token(" GZip ").as_deref(), Some("gzip"));
+        assert_eq!(accepted_token("br;q=0.5").as_deref(), Some("br"));
+        assert_eq!(accepted_token("br; q=0"), None);
+        assert_eq!(accepted_token(" ;q=1"), None);
+    }
+}
@@ -0,0 +1,156 @@
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use wasmtime_wasi::{sockets::SocketAddrUse, DirPerms, FilePerms, WasiCtx};
+
+use motya_config::common_types::definitions::WasiCapabilities;
+
+use crate::proxy::plugins::http_client::HttpClient;
+
+/// WASI grants of a plugin, with its `allow-host` entries resolved.
+#[derive(Debug, Clone, Default)]
+pub struct PluginCapabilities {
+    config: WasiCapabilities,
+    allowed_addrs: Arc<HashSet<SocketAddr>>,
+    http_client: Option<HttpClient>,
+}
+
+impl PluginCapabilities {
+    /// Resolves the allowed hosts once, so later DNS changes need a reload.
+    pub async fn resolve(config: WasiCapabilities) -> Result<Self> {
+        let mut allowed_addrs = HashSet::new();
+
+        for host in &config.allowed_hosts {
+            let addrs = tokio::net::lookup_host(host)
+                .await
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to resolve allowed host '{host}'"))?;
+            allowed_addrs.extend(addrs);
+        }
+
+        let http_client = config
+            .http_client
+            .clone()
+            .map(|limits| HttpClient::new(limits, &config.allowed_hosts))
+            .transpose()?;
+
+        let capabilities = Self {
+            config,
+            allowed_addrs: Arc::new(allowed_addrs),
+            http_client,
+        };
+
+        // Reports missing directories at startup rather than on a request.
+        capabilities.wasi_ctx()?;
+
+        Ok(capabilities)
+    }
+
+    /// Most bytes of the request body the plugin may read.
+    pub fn request_body(&self) -> Option<usize> {
+        self.config.request_body
+    }
+
+    pub fn http_client(&self) -> Option<&HttpClient> {
+        self.http_client.as_ref()
+    }
+
+    /// A WASI context granting exactly the declared capabilities, `None` when
+    /// nothing is granted and the default locked down context fits.
+    pub fn wasi_ctx(&self) -> Result<Option<WasiCtx>> {
+        let WasiCapabilities {
+            dirs,
+            env,
+            allowed_hosts,
+            request_body: _,
+            http_client: _,
+        } = &self.config;
+
+        if dirs.is_empty() && env.is_empty() && allowed_hosts.is_empty() {
+            return Ok(None);
+        }
+
+        let mut builder = WasiCtx::builder();
+
+        for dir in dirs {
+            let (dir_perms, file_perms) = if dir.read_only {
+                (DirPerms::READ, FilePerms::READ)
+            } else {
+                (DirPerms::all(), FilePerms::all())
+            };
+
+            builder
+                .preopened_dir(&dir.host, &dir.guest, dir_perms, file_perms)
+                .map_err(|err| miette!("Failed to open {:?} for the plugin: {err}", dir.host))?;
+        }
+
+        for (key, value) in env {
+            builder.env(key, value);
+        }
+
+        if !self.allowed_addrs.is_empty() {
+            let allowed = self.allowed_addrs.clone();
+
+            builder.allow_ip_name_lookup(true);
+            builder.socket_addr_check(move |addr, addr_use| {
+                let granted =
+                    matches!(addr_use, SocketAddrUse::TcpConnect) && allowed.contains(&addr);
+                Box::pin(async move { granted })
+            });
+        }
+
+        Ok(Some(builder.build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::definitions::PreopenedDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let capabilities = PluginCapabilities::resolve(WasiCapabilities {
+            allowed_hosts: vec!["127.0.0.1:8080".into()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(capabilities
+            .allowed_addrs
+            .contains(&"127.0.0.1:8080".parse().unwrap()));
+        assert!(capabilities.wasi_ctx().unwrap().is_some());
+
+        let nothing = PluginCapabilities::resolve(WasiCapabilities::default())
+            .await
+            .unwrap();
+        assert!(nothing.wasi_ctx().unwrap().is_none());
+
+        // Reading the request body needs no WASI.
+        let body = PluginCapabilities::resolve(WasiCapabilities {
+            request_body: Some(1024),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(body.request_body(), Some(1024));
+        assert!(body.wasi_ctx().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_missing_dir() {
+        let result = PluginCapabilities::resolve(WasiCapabilities {
+            dirs: vec![PreopenedDir {
+                host: "/nonexistent/motya-plugin-data".into(),
+                guest: "/data".into(),
+                read_only: true,
+            }],
+            ..Default::default()
+        })
+        .await;
+
+        assert!(result.is_err());
+    }
+}
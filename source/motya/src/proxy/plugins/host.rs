@@ -1,11 +1,37 @@
-use wasmtime::component::{Linker, LinkerInstance};
+use pingora_http::{RequestHeader, ResponseHeader};
+use wasmtime::{
+    component::{Linker, LinkerInstance},
+    StoreLimits,
+};
 use wasmtime_wasi::WasiView;
 use wasmtime_wasi_io::IoView;
 
-use crate::proxy::plugins::{module::TraitModuleState, store::ModuleState};
+use crate::proxy::plugins::{
+    capabilities::PluginCapabilities,
+    g::motya::proxy::http_client::{HttpRequest, HttpResponse},
+    http_client::HttpClient,
+    module::TraitModuleState,
+    mutations::RequestMutations,
+    store::{ModuleState, SessionCtx},
+};
 
 pub trait HostFunctions {
     fn get_path(&self) -> String;
+    fn read_body(&mut self, max: u32) -> Option<Vec<u8>>;
+    /// The upstream response, `None` outside of the response phase.
+    fn response(&mut self) -> Option<&mut ResponseHeader>;
+    fn request_header(&self) -> Option<&RequestHeader>;
+    /// Changes of the request, `None` outside of the request phases.
+    fn mutations(&mut self) -> Option<&mut RequestMutations>;
+    /// `None` unless the plugin was granted `http-client`.
+    fn http_client(&self) -> Option<&HttpClient>;
+    /// Called once on a new instance of the plugin.
+    fn grant(&mut self, capabilities: &PluginCapabilities);
+    /// Hands the session of a call to a pooled instance, `None` once it
+    /// returns, giving back the one bound before.
+    fn bind(&mut self, session: Option<SessionCtx>) -> Option<SessionCtx>;
+    /// Memory bounds of the instance, from the plugin's `limits`.
+    fn limits(&mut self) -> &mut StoreLimits;
 }
 
 pub struct PluginHost;
@@ -18,6 +44,9 @@ impl PluginHost {
 
         Self::register_logger(linker.root().instance("motya:proxy/logger")?)?;
         Self::register_context(linker.root().instance("motya:proxy/context")?)?;
+        Self::register_response(linker.root().instance("motya:proxy/response")?)?;
+        Self::register_request(linker.root().instance("motya:proxy/request")?)?;
+        Self::register_http_client(linker.root().instance("motya:proxy/http-client")?)?;
 
         Ok(())
     }
@@ -29,6 +58,150 @@ impl PluginHost {
             Ok((ctx.data().get_path(),))
         })?;
 
+        logger.func_wrap(
+            "read-body",
+            |mut ctx, (max,): (u32,)| -> wasmtime::Result<(Option<Vec<u8>>,)> {
+                Ok((ctx.data_mut().read_body(max),))
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn register_response<T: TraitModuleState>(
+        mut response: LinkerInstance<'_, T>,
+    ) -> wasmtime::Result<()> {
+        fn header<T: HostFunctions>(state: &mut T) -> wasmtime::Result<&mut ResponseHeader> {
+            state
+                .response()
+                .ok_or_else(|| wasmtime::Error::msg("the response is only available on-response"))
+        }
+
+        response.func_wrap(
+            "get-status",
+            |mut ctx, (): ()| -> wasmtime::Result<(u16,)> {
+                Ok((header(ctx.data_mut())?.status.as_u16(),))
+            },
+        )?;
+
+        response.func_wrap(
+            "set-status",
+            |mut ctx, (status,): (u16,)| -> wasmtime::Result<(Result<(), String>,)> {
+                let result = header(ctx.data_mut())?
+                    .set_status(status)
+                    .map_err(|err| err.to_string());
+                Ok((result,))
+            },
+        )?;
+
+        response.func_wrap(
+            "get-header",
+            |mut ctx, (name,): (String,)| -> wasmtime::Result<(Option<String>,)> {
+                let value = header(ctx.data_mut())?
+                    .headers
+                    .get(&name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from);
+                Ok((value,))
+            },
+        )?;
+
+        response.func_wrap(
+            "set-header",
+            |mut ctx, (name, value): (String, String)| -> wasmtime::Result<(Result<(), String>,)> {
+                let result = header(ctx.data_mut())?
+                    .insert_header(name, value)
+                    .map_err(|err| err.to_string());
+                Ok((result,))
+            },
+        )?;
+
+        response.func_wrap("remove-header", |mut ctx, (name,): (String,)| {
+            header(ctx.data_mut())?.remove_header(&name);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    fn register_request<T: TraitModuleState>(
+        mut request: LinkerInstance<'_, T>,
+    ) -> wasmtime::Result<()> {
+        fn mutations<T: HostFunctions>(state: &mut T) -> wasmtime::Result<&mut RequestMutations> {
+            state.mutations().ok_or_else(|| {
+                wasmtime::Error::msg("the request is only available to filter and on-request")
+            })
+        }
+
+        request.func_wrap(
+            "get-header",
+            |ctx, (name,): (String,)| -> wasmtime::Result<(Option<String>,)> {
+                let value = ctx
+                    .data()
+                    .request_header()
+                    .and_then(|header| header.headers.get(&name))
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from);
+                Ok((value,))
+            },
+        )?;
+
+        request.func_wrap(
+            "set-header",
+            |mut ctx, (name, value): (String, String)| -> wasmtime::Result<(Result<(), String>,)> {
+                Ok((mutations(ctx.data_mut())?.set_header(&name, &value),))
+            },
+        )?;
+
+        request.func_wrap(
+            "add-header",
+            |mut ctx, (name, value): (String, String)| -> wasmtime::Result<(Result<(), String>,)> {
+                Ok((mutations(ctx.data_mut())?.add_header(&name, &value),))
+            },
+        )?;
+
+        request.func_wrap(
+            "remove-header",
+            |mut ctx, (name,): (String,)| -> wasmtime::Result<(Result<(), String>,)> {
+                Ok((mutations(ctx.data_mut())?.remove_header(&name),))
+            },
+        )?;
+
+        request.func_wrap(
+            "set-path",
+            |mut ctx, (path,): (String,)| -> wasmtime::Result<(Result<(), String>,)> {
+                Ok((mutations(ctx.data_mut())?.set_path(&path),))
+            },
+        )?;
+
+        request.func_wrap(
+            "respond",
+            |mut ctx,
+             (status, headers, body): (u16, Vec<(String, String)>, Vec<u8>)|
+             -> wasmtime::Result<(Result<(), String>,)> {
+                Ok((mutations(ctx.data_mut())?.respond(status, headers, body),))
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn register_http_client<T: TraitModuleState>(
+        mut http: LinkerInstance<'_, T>,
+    ) -> wasmtime::Result<()> {
+        http.func_wrap(
+            "send",
+            |ctx,
+             (request,): (HttpRequest,)|
+             -> wasmtime::Result<(Result<HttpResponse, String>,)> {
+                let response = match ctx.data().http_client() {
+                    Some(client) => client.send(request),
+                    None => Err("the plugin was not granted 'http-client'".to_string()),
+                };
+                Ok((response,))
+            },
+        )?;
+
         Ok(())
     }
 
@@ -63,4 +236,111 @@ impl HostFunctions for ModuleState {
             panic!("invariant violated: session was null on filter phase");
         }
     }
+
+    fn read_body(&mut self, max: u32) -> Option<Vec<u8>> {
+        let body = self.session.as_mut()?.body.as_mut()?;
+        if body.is_empty() {
+            return None;
+        }
+
+        let len = body.len().min(max.max(1) as usize);
+        Some(body.split_to(len).to_vec())
+    }
+
+    fn response(&mut self) -> Option<&mut ResponseHeader> {
+        let mut header = self.session.as_ref()?.res_header?;
+        // SAFETY: the header outlives the call into the plugin that uses it.
+        Some(unsafe { header.as_mut() })
+    }
+
+    fn request_header(&self) -> Option<&RequestHeader> {
+        let header = self.session.as_ref()?.req_header?;
+        // SAFETY: the header outlives the call into the plugin that uses it.
+        Some(unsafe { header.as_ref() })
+    }
+
+    fn mutations(&mut self) -> Option<&mut RequestMutations> {
+        self.session.as_mut()?.mutations.as_mut()
+    }
+
+    fn http_client(&self) -> Option<&HttpClient> {
+        self.http_client.as_ref()
+    }
+
+    fn grant(&mut self, capabilities: &PluginCapabilities) {
+        self.http_client = capabilities.http_client().cloned();
+    }
+
+    fn bind(&mut self, session: Option<SessionCtx>) -> Option<SessionCtx> {
+        std::mem::replace(&mut self.session, session)
+    }
+
+    fn limits(&mut self) -> &mut StoreLimits {
+        &mut self.limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::NonNull;
+
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn state(body: Option<&'static [u8]>) -> ModuleState {
+        ModuleState {
+            session: Some(SessionCtx {
+                _session: NonNull::dangling(),
+                req_header: None,
+                res_header: None,
+                body: body.map(Bytes::from_static),
+                mutations: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_read_body() {
+        let mut body = state(Some(b"hello world"));
+        assert_eq!(body.read_body(5), Some(b"hello".to_vec()));
+        assert_eq!(body.read_body(100), Some(b" world".to_vec()));
+        assert_eq!(body.read_body(100), None);
+
+        // Plugins without `request-body` see no body.
+        assert_eq!(state(None).read_body(100), None);
+    }
+
+    #[test]
+    fn test_response() {
+        let mut header = ResponseHeader::build(200, None).unwrap();
+
+        let mut request = state(None);
+        assert!(request.response().is_none());
+
+        let mut response = state(None);
+        response.session.as_mut().unwrap().res_header = Some(NonNull::from(&mut header));
+        response.response().unwrap().set_status(503).unwrap();
+
+        assert_eq!(header.status, 503);
+    }
+
+    #[test]
+    fn test_mutations() {
+        let mut response = state(None);
+        assert!(response.mutations().is_none());
+
+        let mut request = state(None);
+        request.session.as_mut().unwrap().mutations = Some(RequestMutations::default());
+        request
+            .mutations()
+            .unwrap()
+            .set_header("x-user", "alice")
+            .unwrap();
+
+        // The changes come back with the session once the call returns.
+        let session = request.bind(None).unwrap();
+        assert!(!session.mutations.unwrap().is_empty());
+    }
 }
@@ -0,0 +1,191 @@
+use std::{collections::HashSet, sync::Arc};
+
+use miette::{miette, Result};
+use reqwest::{Method, Url};
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    sync::Semaphore,
+};
+
+use motya_config::common_types::definitions::HttpClientLimits;
+
+use crate::proxy::plugins::g::motya::proxy::http_client::{HttpRequest, HttpResponse};
+
+/// Outbound HTTP calls of a plugin, to its `allow-host` entries only and
+/// within its `http-client` limits.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    allowed_hosts: Arc<HashSet<String>>,
+    slots: Arc<Semaphore>,
+    limits: HttpClientLimits,
+}
+
+impl HttpClient {
+    pub fn new(limits: HttpClientLimits, allowed_hosts: &[String]) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(limits.timeout)
+            // A redirect could leave the allowed hosts.
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|err| miette!("Failed to create the plugin HTTP client: {err}"))?;
+
+        Ok(Self {
+            client,
+            allowed_hosts: Arc::new(allowed_hosts.iter().cloned().collect()),
+            slots: Arc::new(Semaphore::new(limits.max_concurrent)),
+            limits,
+        })
+    }
+
+    /// Makes a call for a plugin. Plugins run synchronously, so the worker
+    /// thread blocks until the call is done.
+    pub fn send(&self, request: HttpRequest) -> Result<HttpResponse, String> {
+        let url = self.check(&request.url)?;
+
+        let handle = Handle::try_current().map_err(|err| err.to_string())?;
+        if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+            return Err("HTTP calls need a multi-threaded runtime".to_string());
+        }
+
+        tokio::task::block_in_place(|| {
+            handle.block_on(async {
+                tokio::time::timeout(self.limits.timeout, self.call(url, request))
+                    .await
+                    .unwrap_or_else(|_| Err("HTTP call timed out".to_string()))
+            })
+        })
+    }
+
+    /// The URL of a call, if it goes to an allowed host.
+    fn check(&self, url: &str) -> Result<Url, String> {
+        let url = Url::parse(url).map_err(|err| format!("Invalid URL '{url}': {err}"))?;
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Unsupported scheme '{}'", url.scheme()));
+        }
+
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or_default();
+        let authority = format!("{host}:{port}");
+
+        if !self.allowed_hosts.contains(&authority) {
+            return Err(format!("'{authority}' is not an allowed host"));
+        }
+
+        Ok(url)
+    }
+
+    async fn call(&self, url: Url, request: HttpRequest) -> Result<HttpResponse, String> {
+        let _slot = self.slots.acquire().await.map_err(|err| err.to_string())?;
+
+        let method = Method::from_bytes(request.method.as_bytes())
+            .map_err(|_| format!("Invalid method '{}'", request.method))?;
+
+        let mut builder = self.client.request(method, url);
+        for (name, value) in request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        let mut response = builder.send().await.map_err(|err| err.to_string())?;
+
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+            if body.len() + chunk.len() > self.limits.max_response_bytes {
+                return Err(format!(
+                    "Response exceeds {} bytes",
+                    self.limits.max_response_bytes
+                ));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn client(host: &str, max_response_bytes: usize) -> HttpClient {
+        let limits = HttpClientLimits {
+            timeout: Duration::from_secs(5),
+            max_concurrent: 2,
+            max_response_bytes,
+        };
+        HttpClient::new(limits, &[host.to_string()]).unwrap()
+    }
+
+    fn get(url: String) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url,
+            headers: vec![("X-Plugin".to_string(), "auth".to_string())],
+            body: None,
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let client = client("auth.example.com:443", 1024);
+
+        assert!(client.check("https://auth.example.com/verify").is_ok());
+        assert!(client.check("http://auth.example.com/verify").is_err());
+        assert!(client.check("https://other.example.com/").is_err());
+        assert!(client.check("ftp://auth.example.com:443/").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_send() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/verify"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("allowed"))
+            .mount(&server)
+            .await;
+
+        let host = server.address().to_string();
+
+        let response = client(&host, 1024)
+            .send(get(format!("{}/verify", server.uri())))
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"allowed");
+
+        let err = client(&host, 4)
+            .send(get(format!("{}/verify", server.uri())))
+            .unwrap_err();
+        assert!(err.contains("exceeds 4 bytes"), "{err}");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_send_current_thread() {
+        let err = client("127.0.0.1:80", 1024)
+            .send(get("http://127.0.0.1/".to_string()))
+            .unwrap_err();
+        assert!(err.contains("multi-threaded"), "{err}");
+    }
+}
@@ -0,0 +1,74 @@
+use std::{sync::Once, thread, time::Duration};
+
+use wasmtime::{Engine, Store, StoreLimits, StoreLimitsBuilder};
+
+use motya_config::common_types::definitions::PluginLimits;
+
+/// The epoch advances this often, the resolution of `max-ms`.
+const EPOCH_TICK: Duration = Duration::from_millis(1);
+
+/// Deadline of calls without `max-ms`, never reached.
+const NO_DEADLINE: u64 = u64::MAX / 2;
+
+/// Starts advancing the epoch of `engine`, once per process. It starts on the
+/// first call rather than with the engine, so the thread lives in the process
+/// serving requests after daemonizing.
+fn start_ticker(engine: &Engine) {
+    static STARTED: Once = Once::new();
+
+    STARTED.call_once(|| {
+        let engine = engine.clone();
+        thread::Builder::new()
+            .name("motya-wasm-epoch".to_string())
+            .spawn(move || loop {
+                thread::sleep(EPOCH_TICK);
+                engine.increment_epoch();
+            })
+            .expect("Failed to start the Wasm epoch thread");
+    });
+}
+
+/// Epoch ticks a call may take.
+fn deadline(limits: &PluginLimits) -> u64 {
+    limits.max_time.map_or(NO_DEADLINE, |max| {
+        (max.as_millis() / EPOCH_TICK.as_millis()).max(1) as u64
+    })
+}
+
+/// Memory bounds of a new instance. Growing past them traps, instead of
+/// leaving the plugin to handle a failed allocation.
+pub fn store_limits(limits: &PluginLimits) -> StoreLimits {
+    match limits.max_memory {
+        Some(max) => StoreLimitsBuilder::new()
+            .memory_size(max)
+            .trap_on_grow_failure(true)
+            .build(),
+        None => StoreLimits::default(),
+    }
+}
+
+/// Gives the next call into `store` its `max-ms`, instantiation included.
+pub fn arm<T>(store: &mut Store<T>, limits: &PluginLimits) {
+    if limits.max_time.is_some() {
+        start_ticker(store.engine());
+    }
+
+    store.set_epoch_deadline(deadline(limits));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadline() {
+        let limits = |max_time| PluginLimits {
+            max_time,
+            ..Default::default()
+        };
+
+        assert_eq!(deadline(&limits(Some(Duration::from_millis(5)))), 5);
+        assert_eq!(deadline(&limits(Some(Duration::from_micros(10)))), 1);
+        assert_eq!(deadline(&limits(None)), NO_DEADLINE);
+    }
+}
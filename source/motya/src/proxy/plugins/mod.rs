@@ -1,5 +1,10 @@
+pub mod capabilities;
 pub mod g;
 pub mod host;
+pub mod http_client;
+pub mod limits;
 pub mod loader;
 pub mod module;
+pub mod mutations;
+pub mod pool;
 pub mod store;
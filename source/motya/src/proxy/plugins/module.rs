@@ -1,6 +1,7 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, ptr::NonNull, sync::Arc, time::Instant};
 
 use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
 use miette::miette;
 use pingora_http::{RequestHeader, ResponseHeader};
 use pingora_proxy::Session;
@@ -11,16 +12,27 @@ use wasmtime::{
 use wasmtime_wasi::WasiView;
 use wasmtime_wasi_io::IoView;
 
-use crate::proxy::{
-    filters::types::{RequestFilterMod, RequestModifyMod, ResponseModifyMod},
-    plugins::{
-        g::{self, exports::motya::proxy::filter_factory::GuestFilterInstance},
-        host::HostFunctions,
-        store::{ModuleState, SessionCtx, WasmArtifact},
+use motya_config::common_types::definitions::FailurePolicy;
+
+use crate::{
+    proxy::{
+        filters::types::{RequestFilterMod, RequestModifyMod, ResponseModifyMod},
+        plugins::{
+            g::{self, exports::motya::proxy::filter_factory::GuestFilterInstance},
+            host::HostFunctions,
+            limits,
+            mutations::RequestMutations,
+            pool::{self, Checkout, InstancePool},
+            store::{ModuleState, SessionCtx, WasmArtifact},
+        },
+        MotyaContext,
     },
-    MotyaContext,
+    runtime_metrics,
 };
 
+const TRAPPED: &str = "Wasm runtime trap/error";
+const INSTANTIATION_FAILED: &str = "Failed to instantiate module";
+
 pub trait TraitModuleState: WasiView + IoView + HostFunctions + Default + 'static {}
 
 impl<T> TraitModuleState for T where T: WasiView + IoView + HostFunctions + Default + 'static {}
@@ -28,6 +40,8 @@ impl<T> TraitModuleState for T where T: WasiView + IoView + HostFunctions + Defa
 pub struct WasmModule<T: 'static = ModuleState> {
     artifact: WasmArtifact,
     linker: Linker<T>,
+    /// Instances kept per filter of the module.
+    pool_size: usize,
 }
 
 impl<T> Clone for WasmModule<T> {
@@ -35,24 +49,43 @@ impl<T> Clone for WasmModule<T> {
         Self {
             artifact: self.artifact.clone(),
             linker: self.linker.clone(),
+            pool_size: self.pool_size,
         }
     }
 }
 
 impl<T: TraitModuleState> WasmModule<T> {
     pub fn new(artifact: WasmArtifact, linker: Linker<T>) -> Self {
-        Self { artifact, linker }
+        Self {
+            artifact,
+            linker,
+            pool_size: 1,
+        }
+    }
+
+    pub fn with_pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = pool_size;
+        self
     }
 
     pub fn pick(
         &self,
         name: &str,
         cfg: &BTreeMap<String, String>,
-        state: T,
+        mut state: T,
     ) -> miette::Result<Option<WasmFilterState<T>>> {
+        if let Some(ctx) = self.artifact.capabilities.wasi_ctx()? {
+            *state.ctx().ctx = ctx;
+        }
+        state.grant(&self.artifact.capabilities);
+        *state.limits() = limits::store_limits(&self.artifact.limits);
+
         let mut store = Store::new(&self.artifact.engine, state);
+        store.limiter(|state| state.limits());
+        limits::arm(&mut store, &self.artifact.limits);
 
-        let instance = g::App::instantiate(&mut store, &self.artifact.component, &self.linker)
+        let component = self.artifact.component().map_err(|err| miette!("{err}"))?;
+        let instance = g::App::instantiate(&mut store, component, &self.linker)
             .map_err(|err| miette!("{err}"))?;
 
         if let Some((resource, self_type)) = instance
@@ -77,13 +110,32 @@ impl<T: TraitModuleState> WasmModule<T> {
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FilterType {
     Filter,
     OnRequest,
     OnResponse,
 }
 
+impl FilterType {
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterType::Filter => "filter",
+            FilterType::OnRequest => "on-request",
+            FilterType::OnResponse => "on-response",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "filter" => Some(FilterType::Filter),
+            "on-request" => Some(FilterType::OnRequest),
+            "on-response" => Some(FilterType::OnResponse),
+            _ => None,
+        }
+    }
+}
+
 pub struct WasmFilterState<T: 'static> {
     pub store: Store<T>,
     pub instance: g::App,
@@ -95,6 +147,8 @@ pub struct WasmInvoker<T: 'static = ModuleState> {
     pub module: WasmModule<T>,
     pub filter_name: String,
     pub config: BTreeMap<String, String>,
+    /// Instances of the filter, shared by clones of the invoker.
+    pool: Arc<InstancePool<T>>,
 }
 
 impl<T> Clone for WasmInvoker<T> {
@@ -103,6 +157,7 @@ impl<T> Clone for WasmInvoker<T> {
             module: self.module.clone(),
             filter_name: self.filter_name.clone(),
             config: self.config.clone(),
+            pool: self.pool.clone(),
         }
     }
 }
@@ -114,25 +169,74 @@ impl<T: TraitModuleState> WasmInvoker<T> {
         config: BTreeMap<String, String>,
     ) -> Self {
         Self {
+            pool: Arc::new(InstancePool::new(module.pool_size)),
             config,
             filter_name,
             module,
         }
     }
 
+    /// The type of the filter, pre-instantiating its pool once the plugin is
+    /// compiled.
+    ///
+    /// A type remembered by the artifact is returned without creating an
+    /// instance, so a lazily compiled plugin stays uncompiled. Otherwise an
+    /// instance is created, compiling the plugin if needed: this blocks, and
+    /// is meant for `spawn_blocking`.
     pub fn get_filter_type(&self) -> miette::Result<FilterType> {
-        let state = T::default();
+        let artifact = &self.module.artifact;
+
+        let self_type = match artifact.filter_type(&self.filter_name) {
+            Some(self_type) if !artifact.is_compiled() => return Ok(self_type),
+            Some(self_type) => self_type,
+            None => {
+                let filter_state = self.instantiate()?;
+                let self_type = filter_state.self_type;
+                self.pool.put(filter_state);
+                artifact.remember_filter_type(&self.filter_name, self_type);
+                self_type
+            }
+        };
 
-        //TODO: generate types instead of dry-run
-        let filter_state = self
-            .module
-            .pick(&self.filter_name, &self.config, state)?
-            .ok_or_else(|| miette!("Invariant violated: filter instance not found"))?;
+        while self.pool.idle() < self.pool.size() {
+            self.pool.put(self.instantiate()?);
+        }
 
-        Ok(filter_state.self_type)
+        Ok(self_type)
     }
 
-    fn execute<F, R>(&self, state: T, func: F) -> pingora::Result<R>
+    fn instantiate(&self) -> miette::Result<WasmFilterState<T>> {
+        self.module
+            .pick(&self.filter_name, &self.config, T::default())?
+            .ok_or_else(|| miette!("Invariant violated: filter instance not found"))
+    }
+
+    /// An idle instance of the filter, or a new one when all are busy.
+    fn checkout(&self) -> pingora::Result<WasmFilterState<T>> {
+        let started = Instant::now();
+
+        let (filter_state, checkout) = match self.pool.take() {
+            Some(filter_state) => (filter_state, Checkout::Pooled),
+            None => (
+                self.instantiate()
+                    .map_err(|e| Self::make_err(INSTANTIATION_FAILED, e))?,
+                Checkout::Created,
+            ),
+        };
+
+        let name = format!("{}.{}", self.module.artifact._name, self.filter_name);
+        pool::record_wait(&name, checkout, started.elapsed());
+
+        Ok(filter_state)
+    }
+
+    /// Calls into an instance with `session` bound, returning the changes the
+    /// call made to the request along with its result.
+    fn execute<F, R>(
+        &self,
+        session: Option<SessionCtx>,
+        func: F,
+    ) -> pingora::Result<(R, RequestMutations)>
     where
         F: FnOnce(
             &GuestFilterInstance,
@@ -140,33 +244,62 @@ impl<T: TraitModuleState> WasmInvoker<T> {
             ResourceAny,
         ) -> wasmtime::Result<std::result::Result<R, String>>,
     {
-        let mut filter_state = self
-            .module
-            .pick(&self.filter_name, &self.config, state)
-            .map_err(|e| Self::make_err("Failed to instantiate module", e))?
-            .ok_or_else(|| Self::make_err("Invariant violated: filter instance not found", ""))?;
+        // Plugins run synchronously on the worker thread of the request.
+        let _blocking = runtime_metrics::blocking("wasm-plugin");
+        let mut filter_state = self.checkout()?;
 
         let factory = filter_state.instance.motya_proxy_filter_factory();
         let filter = factory.filter_instance();
 
-        let wasm_result = func(&filter, &mut filter_state.store, filter_state.resource)
-            .map_err(|e| Self::make_err("Wasm runtime trap/error", e))?;
+        limits::arm(&mut filter_state.store, &self.module.artifact.limits);
+        filter_state.store.data_mut().bind(session);
+        let result = func(&filter, &mut filter_state.store, filter_state.resource);
+        // The session does not outlive the call.
+        let session = filter_state.store.data_mut().bind(None);
+
+        // A trapped instance is dropped instead of returned to the pool.
+        let wasm_result = result.map_err(|e| Self::make_err(TRAPPED, e))?;
+        self.pool.put(filter_state);
 
-        wasm_result.map_err(|e| Self::make_err("Filter execution error", e))
+        let mutations = session
+            .and_then(|session| session.mutations)
+            .unwrap_or_default();
+
+        wasm_result
+            .map(|result| (result, mutations))
+            .map_err(|e| Self::make_err("Filter execution error", e))
     }
 
-    fn on_request(&self, state: T) -> pingora::Result<()> {
-        self.execute(state, |f, s, r| f.call_on_request(s, r))
+    /// Passes the request on past a plugin that failed, rather than failing
+    /// it, when the plugin's `on-failure` is `open`.
+    fn fail_open<R>(&self, err: pingora::BError, passed: R) -> pingora::Result<R> {
+        let failed = matches!(
+            err.etype(),
+            pingora::ErrorType::Custom(TRAPPED | INSTANTIATION_FAILED)
+        );
+
+        if failed && self.module.artifact.limits.on_failure == FailurePolicy::Open {
+            tracing::warn!("Plugin filter '{}' failed open: {err}", self.filter_name);
+            return Ok(passed);
+        }
+
+        Err(err)
     }
 
-    #[allow(unused)]
-    fn filter(&self, state: T) -> pingora::Result<bool> {
-        self.execute(state, |f, s, r| f.call_filter(s, r))
+    fn on_request(&self, session: Option<SessionCtx>) -> pingora::Result<RequestMutations> {
+        self.execute(session, |f, s, r| f.call_on_request(s, r))
+            .map(|((), mutations)| mutations)
+            .or_else(|err| self.fail_open(err, RequestMutations::default()))
     }
 
-    #[allow(unused)]
-    fn on_response(&self, state: T) -> pingora::Result<()> {
-        self.execute(state, |f, s, r| f.call_on_response(s, r))
+    fn filter(&self, session: Option<SessionCtx>) -> pingora::Result<(bool, RequestMutations)> {
+        self.execute(session, |f, s, r| f.call_filter(s, r))
+            .or_else(|err| self.fail_open(err, (false, RequestMutations::default())))
+    }
+
+    fn on_response(&self, session: Option<SessionCtx>) -> pingora::Result<()> {
+        self.execute(session, |f, s, r| f.call_on_response(s, r))
+            .map(|((), _)| ())
     }
 
     fn make_err(msg: &'static str, context: impl std::fmt::Display) -> pingora::BError {
@@ -174,23 +307,61 @@ impl<T: TraitModuleState> WasmInvoker<T> {
     }
 }
 
+/// Reads the whole request body for a plugin, it is sent upstream from the
+/// retry buffer afterwards.
+async fn read_request_body(session: &mut Session, max: usize) -> pingora::Result<Bytes> {
+    session.enable_retry_buffering();
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = session.downstream_session.read_request_body().await? {
+        if body.len() + chunk.len() > max {
+            return Err(pingora::Error::explain(
+                pingora::ErrorType::HTTPStatus(413),
+                "request body exceeds the plugin's max-bytes",
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body.freeze())
+}
+
+/// A `filter` plugin refuses the request with `403` by returning `true`, or
+/// answers it with a response of its own. Its other changes are kept for the
+/// request sent upstream, so they do not affect routing.
 #[async_trait]
 impl RequestFilterMod for WasmInvoker {
     async fn request_filter(
         &self,
         session: &mut Session,
-        _: &mut MotyaContext,
+        ctx: &mut MotyaContext,
     ) -> pingora::Result<bool> {
+        let body = match self.module.artifact.capabilities.request_body() {
+            Some(max) => Some(read_request_body(session, max).await?),
+            None => None,
+        };
+
         let session_state = SessionCtx {
-            req_header: None,
-            _res_headers: None,
+            req_header: Some(NonNull::from(session.req_header())),
+            res_header: None,
+            body,
+            mutations: Some(RequestMutations::answerable()),
             _session: session.into(),
         };
 
-        let _state = ModuleState {
-            session: Some(session_state),
-            ..Default::default()
-        };
+        let (refused, mut mutations) = self.filter(Some(session_state))?;
+
+        if let Some(answer) = mutations.take_answer() {
+            answer.respond(session).await?;
+            return Ok(true);
+        }
+
+        if refused {
+            session.downstream_session.respond_error(403).await?;
+            return Ok(true);
+        }
+
+        mutations.store(ctx.extensions_mut());
 
         Ok(false)
     }
@@ -205,15 +376,17 @@ impl ResponseModifyMod for WasmInvoker {
         _: &mut MotyaContext,
     ) {
         let session_state = SessionCtx {
-            req_header: None,
-            _res_headers: Some(header.into()),
+            req_header: Some(NonNull::from(session.req_header())),
+            res_header: Some(header.into()),
+            body: None,
+            mutations: None,
             _session: session.into(),
         };
 
-        let _state = ModuleState {
-            session: Some(session_state),
-            ..Default::default()
-        };
+        // A failing plugin does not fail the response.
+        if let Err(err) = self.on_response(Some(session_state)) {
+            tracing::error!("Plugin filter '{}' failed: {err}", self.filter_name);
+        }
     }
 }
 
@@ -227,16 +400,13 @@ impl RequestModifyMod for WasmInvoker {
     ) -> pingora::Result<()> {
         let session_state = SessionCtx {
             req_header: Some(header.into()),
-            _res_headers: None,
+            res_header: None,
+            body: None,
+            mutations: Some(RequestMutations::default()),
             _session: session.into(),
         };
 
-        let state = ModuleState {
-            session: Some(session_state),
-            ..Default::default()
-        };
-
-        self.on_request(state)
+        self.on_request(Some(session_state))?.apply(header)
     }
 }
 
@@ -256,16 +426,24 @@ mod tests {
     use std::str::FromStr;
 
     use fqdn::FQDN;
-    use wasmtime::Engine;
+    use wasmtime::{Engine, StoreLimits};
     use wasmtime_wasi::{ResourceTable, WasiCtx, WasiCtxView};
 
-    use crate::proxy::plugins::store::WasmPluginStore;
-    use motya_config::common_types::definitions::PluginSource;
+    use crate::proxy::plugins::{
+        capabilities::PluginCapabilities,
+        http_client::HttpClient,
+        store::{WasmArtifact, WasmPluginStore},
+    };
+    use motya_config::common_types::{
+        definitions::{PluginLimits, PluginSource, WasiCapabilities},
+        system_data::WasmConfig,
+    };
 
     #[derive(Default)]
     pub struct MockState {
         pub ctx: WasiCtx,
         pub table: ResourceTable,
+        pub limits: StoreLimits,
     }
 
     impl WasiView for MockState {
@@ -287,6 +465,36 @@ mod tests {
         fn get_path(&self) -> String {
             "/hubabuba".to_string()
         }
+
+        fn read_body(&mut self, _: u32) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn response(&mut self) -> Option<&mut ResponseHeader> {
+            None
+        }
+
+        fn request_header(&self) -> Option<&RequestHeader> {
+            None
+        }
+
+        fn mutations(&mut self) -> Option<&mut RequestMutations> {
+            None
+        }
+
+        fn http_client(&self) -> Option<&HttpClient> {
+            None
+        }
+
+        fn grant(&mut self, _: &PluginCapabilities) {}
+
+        fn bind(&mut self, _: Option<SessionCtx>) -> Option<SessionCtx> {
+            None
+        }
+
+        fn limits(&mut self) -> &mut StoreLimits {
+            &mut self.limits
+        }
     }
 
     use super::*;
@@ -296,7 +504,10 @@ mod tests {
             FQDN::from_str("example").unwrap(),
             //request_filter.wasm from examples/wasm-module
             &PluginSource::File("./assets/request_filter.wasm".into()),
+            WasiCapabilities::default(),
+            PluginLimits::default(),
             &Engine::default(),
+            &WasmConfig::default(),
         )
         .await
         .unwrap();
@@ -304,41 +515,144 @@ mod tests {
         let filter_name = "my_filter".to_string();
 
         {
-            let module = WasmPluginStore::create_module(&artifact).unwrap();
+            let module = WasmPluginStore::create_module::<MockState>(&artifact).unwrap();
 
             let config = BTreeMap::from([("forbidden".to_string(), "hubabuba".to_string())]);
 
-            let state = MockState::default();
-
             let invoker = WasmInvoker::new(module, filter_name.clone(), config);
 
-            assert!(invoker.filter(state).unwrap());
+            assert!(invoker.filter(None).unwrap().0);
         }
 
         {
-            let module = WasmPluginStore::create_module(&artifact).unwrap();
+            let module = WasmPluginStore::create_module::<MockState>(&artifact).unwrap();
 
             let config = BTreeMap::from([("forbidden".to_string(), "not hubabuba".to_string())]);
 
-            let state = MockState::default();
-
             let invoker = WasmInvoker::new(module, filter_name.clone(), config);
 
-            assert!(!invoker.filter(state).unwrap());
+            assert!(!invoker.filter(None).unwrap().0);
         }
 
         let filter_name = "response_logger".to_string();
 
         {
-            let module = WasmPluginStore::create_module(&artifact).unwrap();
+            let module = WasmPluginStore::create_module::<MockState>(&artifact).unwrap();
 
             let config = BTreeMap::from([("forbidden".to_string(), "not hubabuba".to_string())]);
 
-            let state = MockState::default();
-
             let invoker = WasmInvoker::new(module, filter_name.clone(), config);
 
-            invoker.on_response(state).unwrap();
+            invoker.on_response(None).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pool() {
+        let artifact = WasmPluginStore::create_artifact(
+            FQDN::from_str("example").unwrap(),
+            &PluginSource::File("./assets/request_filter.wasm".into()),
+            WasiCapabilities::default(),
+            PluginLimits::default(),
+            &Engine::default(),
+            &WasmConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        let module = WasmPluginStore::create_module::<MockState>(&artifact)
+            .unwrap()
+            .with_pool_size(2);
+        let config = BTreeMap::from([("forbidden".to_string(), "hubabuba".to_string())]);
+        let invoker = WasmInvoker::new(module, "my_filter".to_string(), config);
+
+        assert!(invoker.get_filter_type().unwrap() == FilterType::Filter);
+        assert_eq!(invoker.pool.idle(), 2);
+
+        // Calls take an instance and give it back.
+        assert!(invoker.filter(None).unwrap().0);
+        assert_eq!(invoker.pool.idle(), 2);
+
+        // Instances created while the pool is drained do not grow it.
+        let taken = [invoker.pool.take().unwrap(), invoker.pool.take().unwrap()];
+        assert!(invoker.filter(None).unwrap().0);
+        assert_eq!(invoker.pool.idle(), 1);
+
+        for filter_state in taken {
+            invoker.pool.put(filter_state);
         }
+        assert_eq!(invoker.pool.idle(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_filter_type_is_remembered() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = WasmConfig {
+            precompiled_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let artifact = || async {
+            WasmPluginStore::create_artifact(
+                FQDN::from_str("example").unwrap(),
+                &PluginSource::File("./assets/request_filter.wasm".into()),
+                WasiCapabilities::default(),
+                PluginLimits::default(),
+                &Engine::default(),
+                &config,
+            )
+            .await
+            .unwrap()
+        };
+        let invoker = |artifact: &WasmArtifact| {
+            let module = WasmPluginStore::create_module::<MockState>(artifact).unwrap();
+            WasmInvoker::new(module, "my_filter".to_string(), BTreeMap::new())
+        };
+
+        let first = artifact().await;
+        assert_eq!(first.filter_type("my_filter"), None);
+        assert_eq!(
+            invoker(&first).get_filter_type().unwrap(),
+            FilterType::Filter
+        );
+        assert!(first.is_compiled());
+
+        // A later start knows the type without compiling the plugin.
+        let second = artifact().await;
+        let invoker = invoker(&second);
+        assert_eq!(invoker.get_filter_type().unwrap(), FilterType::Filter);
+        assert!(!second.is_compiled());
+        assert_eq!(invoker.pool.idle(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let invoker = |on_failure| async move {
+            let limits = PluginLimits {
+                max_memory: Some(64 * 1024),
+                max_time: None,
+                on_failure,
+            };
+            let artifact = WasmPluginStore::create_artifact(
+                FQDN::from_str("example").unwrap(),
+                &PluginSource::File("./assets/request_filter.wasm".into()),
+                WasiCapabilities::default(),
+                limits,
+                &Engine::default(),
+                &WasmConfig::default(),
+            )
+            .await
+            .unwrap();
+
+            let module = WasmPluginStore::create_module::<MockState>(&artifact).unwrap();
+            let config = BTreeMap::from([("forbidden".to_string(), "hubabuba".to_string())]);
+            WasmInvoker::new(module, "my_filter".to_string(), config)
+        };
+
+        // The module needs more memory than a single page to start at all.
+        let closed = invoker(FailurePolicy::Closed).await;
+        assert!(closed.filter(None).is_err());
+
+        let open = invoker(FailurePolicy::Open).await;
+        assert!(!open.filter(None).unwrap().0);
     }
 }
@@ -0,0 +1,271 @@
+use bytes::Bytes;
+use http::{
+    header::{HeaderName, HeaderValue, CONTENT_LENGTH},
+    uri::PathAndQuery,
+    Extensions, StatusCode, Uri,
+};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+/// A change of a request header, checked when the plugin asked for it.
+#[derive(Debug, Clone, PartialEq)]
+enum HeaderChange {
+    Set(HeaderName, HeaderValue),
+    Add(HeaderName, HeaderValue),
+    Remove(HeaderName),
+}
+
+/// A response a `filter` plugin answers the request with instead of the
+/// upstream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginAnswer {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+}
+
+impl PluginAnswer {
+    pub fn new(status: StatusCode, headers: Vec<(HeaderName, HeaderValue)>, body: Bytes) -> Self {
+        Self {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub fn header(&self) -> pingora::Result<ResponseHeader> {
+        let mut header = ResponseHeader::build(self.status, Some(self.headers.len() + 1))?;
+        for (name, value) in &self.headers {
+            header.append_header(name.clone(), value.clone())?;
+        }
+        header.insert_header(CONTENT_LENGTH, self.body.len().to_string())?;
+
+        Ok(header)
+    }
+
+    pub async fn respond(&self, session: &mut Session) -> pingora::Result<()> {
+        session
+            .write_response_header(Box::new(self.header()?), false)
+            .await?;
+        session
+            .write_response_body(Some(self.body.clone()), true)
+            .await
+    }
+}
+
+/// What a plugin made of the request during a call through the `request`
+/// interface, applied by the proxy once the call returned.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestMutations {
+    headers: Vec<HeaderChange>,
+    path: Option<PathAndQuery>,
+    answer: Option<PluginAnswer>,
+    /// Only `filter` calls run early enough to answer the request.
+    answerable: bool,
+}
+
+fn header_name(name: &str) -> Result<HeaderName, String> {
+    HeaderName::from_bytes(name.as_bytes()).map_err(|err| format!("invalid header '{name}': {err}"))
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|err| format!("invalid header value: {err}"))
+}
+
+impl RequestMutations {
+    /// Mutations of a `filter` call, which may answer the request.
+    pub fn answerable() -> Self {
+        Self {
+            answerable: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.path.is_none() && self.answer.is_none()
+    }
+
+    pub fn set_header(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let change = HeaderChange::Set(header_name(name)?, header_value(value)?);
+        self.headers.push(change);
+        Ok(())
+    }
+
+    pub fn add_header(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let change = HeaderChange::Add(header_name(name)?, header_value(value)?);
+        self.headers.push(change);
+        Ok(())
+    }
+
+    pub fn remove_header(&mut self, name: &str) -> Result<(), String> {
+        self.headers.push(HeaderChange::Remove(header_name(name)?));
+        Ok(())
+    }
+
+    /// Rewrites the path, keeping the query unless `path` has its own.
+    pub fn set_path(&mut self, path: &str) -> Result<(), String> {
+        if !path.starts_with('/') {
+            return Err(format!("path '{path}' must start with '/'"));
+        }
+
+        let path = path
+            .parse::<PathAndQuery>()
+            .map_err(|err| format!("invalid path '{path}': {err}"))?;
+        self.path = Some(path);
+        Ok(())
+    }
+
+    pub fn respond(
+        &mut self,
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> Result<(), String> {
+        if !self.answerable {
+            return Err("only 'filter' plugins can answer the request".to_string());
+        }
+
+        let status = StatusCode::from_u16(status)
+            .ok()
+            .filter(|status| !status.is_informational())
+            .ok_or_else(|| format!("invalid status {status}"))?;
+        let headers = headers
+            .iter()
+            .map(|(name, value)| Ok((header_name(name)?, header_value(value)?)))
+            .collect::<Result<_, String>>()?;
+
+        self.answer = Some(PluginAnswer {
+            status,
+            headers,
+            body: body.into(),
+        });
+        Ok(())
+    }
+
+    pub fn take_answer(&mut self) -> Option<PluginAnswer> {
+        self.answer.take()
+    }
+
+    /// Adds the changes of a later call after those of this one.
+    pub fn extend(&mut self, later: RequestMutations) {
+        self.headers.extend(later.headers);
+        if later.path.is_some() {
+            self.path = later.path;
+        }
+    }
+
+    /// Keeps the changes for the upstream request, after those of earlier
+    /// filters.
+    pub fn store(self, extensions: &mut Extensions) {
+        if self.is_empty() {
+            return;
+        }
+
+        match extensions.get_mut::<RequestMutations>() {
+            Some(earlier) => earlier.extend(self),
+            None => {
+                extensions.insert(self);
+            }
+        }
+    }
+
+    pub fn apply(&self, header: &mut RequestHeader) -> pingora::Result<()> {
+        for change in &self.headers {
+            match change {
+                HeaderChange::Set(name, value) => {
+                    header.insert_header(name.clone(), value.clone())?;
+                }
+                HeaderChange::Add(name, value) => {
+                    header.append_header(name.clone(), value.clone())?;
+                }
+                HeaderChange::Remove(name) => {
+                    header.remove_header(name);
+                }
+            }
+        }
+
+        if let Some(path) = &self.path {
+            header.set_uri(rewrite_path(&header.uri, path)?);
+        }
+
+        Ok(())
+    }
+}
+
+fn rewrite_path(uri: &Uri, path: &PathAndQuery) -> pingora::Result<Uri> {
+    let path_and_query = match (path.query(), uri.query()) {
+        (None, Some(query)) => format!("{}?{query}", path.path())
+            .parse()
+            .map_err(|_| pingora::Error::new_str("Failed to keep the query of a rewritten path"))?,
+        _ => path.clone(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query);
+
+    Uri::from_parts(parts).map_err(|_| pingora::Error::new_str("Failed to build rewritten URI"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let mut mutations = RequestMutations::default();
+        mutations.set_header("x-user", "alice").unwrap();
+        mutations.add_header("x-tag", "b").unwrap();
+        mutations.remove_header("cookie").unwrap();
+        mutations.set_path("/v2/items").unwrap();
+
+        let mut header = RequestHeader::build("GET", b"/v1/items?page=2", None).unwrap();
+        header.insert_header("x-user", "mallory").unwrap();
+        header.insert_header("x-tag", "a").unwrap();
+        header.insert_header("cookie", "session=1").unwrap();
+
+        mutations.apply(&mut header).unwrap();
+
+        assert_eq!(header.headers["x-user"], "alice");
+        assert_eq!(header.headers.get_all("x-tag").iter().count(), 2);
+        assert!(header.headers.get("cookie").is_none());
+        assert_eq!(header.uri, "/v2/items?page=2");
+
+        let mut mutations = RequestMutations::default();
+        mutations.set_path("/search?q=new").unwrap();
+        mutations.apply(&mut header).unwrap();
+        assert_eq!(header.uri, "/search?q=new");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut mutations = RequestMutations::default();
+
+        assert!(mutations.set_header("bad header", "x").is_err());
+        assert!(mutations.set_header("x-ok", "line\nbreak").is_err());
+        assert!(mutations.set_path("relative").is_err());
+        assert!(mutations.is_empty());
+    }
+
+    #[test]
+    fn test_respond() {
+        let mut mutations = RequestMutations::default();
+        assert!(mutations.respond(401, vec![], vec![]).is_err());
+
+        let mut mutations = RequestMutations::answerable();
+        assert!(mutations.respond(100, vec![], vec![]).is_err());
+
+        mutations
+            .respond(
+                401,
+                vec![("www-authenticate".to_string(), "Bearer".to_string())],
+                b"denied".to_vec(),
+            )
+            .unwrap();
+
+        let answer = mutations.take_answer().unwrap();
+        let header = answer.header().unwrap();
+        assert_eq!(header.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(header.headers["www-authenticate"], "Bearer");
+        assert_eq!(header.headers[CONTENT_LENGTH], "6");
+    }
+}
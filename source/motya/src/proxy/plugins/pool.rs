@@ -0,0 +1,83 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use prometheus::{register_histogram_vec, HistogramVec};
+
+use crate::proxy::plugins::module::WasmFilterState;
+
+fn wait_metric() -> &'static HistogramVec {
+    static METRIC: OnceLock<HistogramVec> = OnceLock::new();
+
+    METRIC.get_or_init(|| {
+        register_histogram_vec!(
+            "motya_plugin_instance_wait_seconds",
+            "Time a plugin call waited for an instance, by whether it was pooled or created",
+            &["filter", "outcome"],
+            vec![0.00001, 0.0001, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5]
+        )
+        .expect("metric should register once")
+    })
+}
+
+/// Where the instance of a call came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Checkout {
+    Pooled,
+    /// Every pooled instance was busy, so one was instantiated for the call.
+    Created,
+}
+
+impl Checkout {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Pooled => "pooled",
+            Self::Created => "created",
+        }
+    }
+}
+
+/// Instances of a plugin filter kept between calls. Each call takes one for
+/// itself, so concurrent requests only share the lock around the idle list.
+pub struct InstancePool<T: 'static> {
+    idle: Mutex<Vec<WasmFilterState<T>>>,
+    size: usize,
+}
+
+impl<T> InstancePool<T> {
+    pub fn new(size: usize) -> Self {
+        Self {
+            idle: Mutex::new(Vec::with_capacity(size)),
+            size: size.max(1),
+        }
+    }
+
+    /// Instances the pool keeps at most.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn idle(&self) -> usize {
+        self.idle.lock().expect("instance pool poisoned").len()
+    }
+
+    pub fn take(&self) -> Option<WasmFilterState<T>> {
+        self.idle.lock().expect("instance pool poisoned").pop()
+    }
+
+    /// Returns an instance after a call, it is dropped when the pool is full.
+    pub fn put(&self, instance: WasmFilterState<T>) {
+        let mut idle = self.idle.lock().expect("instance pool poisoned");
+        if idle.len() < self.size {
+            idle.push(instance);
+        }
+    }
+}
+
+/// Records how long a call of `filter` waited for its instance.
+pub fn record_wait(filter: &str, checkout: Checkout, waited: Duration) {
+    wait_metric()
+        .with_label_values(&[filter, checkout.as_str()])
+        .observe(waited.as_secs_f64());
+}
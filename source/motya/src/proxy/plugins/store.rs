@@ -1,32 +1,159 @@
+use bytes::Bytes;
 use fqdn::FQDN;
-use futures_util::future::join_all;
 use miette::{miette, Context, Result};
 use pingora_http::{RequestHeader, ResponseHeader};
 use pingora_proxy::Session;
-use std::{collections::HashMap, ptr::NonNull, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+    sync::{Arc, Mutex, OnceLock},
+};
 use wasmtime::{
     component::{Component, Linker},
-    Engine,
+    Cache, Engine, StoreLimits,
 };
 use wasmtime_wasi::{ResourceTable, WasiCtx, WasiCtxView, WasiView};
 use wasmtime_wasi_io::IoView;
 
-use crate::proxy::{
-    filters::registry::{FilterRegistry, RegistryFilterContainer},
-    plugins::{
-        host::PluginHost,
-        module::{TraitModuleState, WasmModule},
+use crate::{
+    proxy::{
+        filters::registry::{FilterRegistry, RegistryFilterContainer},
+        plugins::{
+            capabilities::PluginCapabilities,
+            host::PluginHost,
+            http_client::HttpClient,
+            module::{FilterType, TraitModuleState, WasmModule},
+            mutations::RequestMutations,
+        },
     },
+    startup::{init_all, OnFailure},
+};
+use motya_config::common_types::{
+    definitions::{PluginLimits, PluginSource, WasiCapabilities},
+    definitions_table::DefinitionsTable,
+    system_data::{WasmCompilation, WasmConfig},
 };
-use motya_config::common_types::{definitions::PluginSource, definitions_table::DefinitionsTable};
 
 use super::loader::PluginLoader;
 
 #[derive(Clone)]
 pub struct WasmArtifact {
     pub _name: FQDN,
-    pub component: Component,
     pub engine: Engine,
+    bytes: Arc<[u8]>,
+    /// Compiled on first use, shared by every clone.
+    component: Arc<OnceLock<Result<Component, String>>>,
+    /// Serialized component kept across restarts.
+    precompiled: Option<PathBuf>,
+    /// Types of the filters instantiated so far, by filter name.
+    filter_types: Arc<Mutex<HashMap<String, FilterType>>>,
+    pub capabilities: PluginCapabilities,
+    pub limits: PluginLimits,
+}
+
+impl WasmArtifact {
+    /// The compiled component, compiling it if this is the first use.
+    pub fn component(&self) -> wasmtime::Result<&Component> {
+        self.component
+            .get_or_init(|| self.compile())
+            .as_ref()
+            .map_err(|err| wasmtime::Error::msg(err.clone()))
+    }
+
+    fn compile(&self) -> Result<Component, String> {
+        if let Some(path) = self.precompiled.as_deref().filter(|path| path.exists()) {
+            // SAFETY: the precompiled directory is written by Motya only and
+            // trusted like the plugin sources themselves.
+            match unsafe { Component::deserialize_file(&self.engine, path) } {
+                Ok(component) => {
+                    tracing::info!("Plugin '{}' loaded from {:?}", self._name, path);
+                    return Ok(component);
+                }
+                Err(err) => tracing::warn!("Ignoring precompiled plugin {:?}: {err}", path),
+            }
+        }
+
+        tracing::debug!(
+            "Compiling plugin '{}' ({} bytes)...",
+            self._name,
+            self.bytes.len()
+        );
+
+        let component =
+            Component::from_binary(&self.engine, &self.bytes).map_err(|err| format!("{err}"))?;
+
+        tracing::info!("Plugin '{}' compiled successfully", self._name);
+
+        if let Some(path) = &self.precompiled {
+            if let Err(err) = Self::store_precompiled(&component, path) {
+                tracing::warn!("Failed to store precompiled plugin {:?}: {err}", path);
+            }
+        }
+
+        Ok(component)
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        self.component.get().is_some_and(Result::is_ok)
+    }
+
+    /// The type of `filter` if it was instantiated before, in this run or, with
+    /// a `precompiled-dir`, in an earlier one.
+    pub fn filter_type(&self, filter: &str) -> Option<FilterType> {
+        self.filter_types
+            .lock()
+            .expect("filter types poisoned")
+            .get(filter)
+            .copied()
+    }
+
+    /// Remembers the type of `filter`, next to the precompiled component if any.
+    pub fn remember_filter_type(&self, filter: &str, filter_type: FilterType) {
+        let mut filter_types = self.filter_types.lock().expect("filter types poisoned");
+        filter_types.insert(filter.to_string(), filter_type);
+
+        let Some(path) = self.precompiled.as_deref().map(filter_types_path) else {
+            return;
+        };
+        let lines: String = filter_types
+            .iter()
+            .map(|(filter, filter_type)| format!("{filter} {}\n", filter_type.name()))
+            .collect();
+
+        if let Err(err) = std::fs::write(&path, lines) {
+            tracing::warn!("Failed to store the filter types {:?}: {err}", path);
+        }
+    }
+
+    fn store_precompiled(component: &Component, path: &Path) -> wasmtime::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, component.serialize()?)?;
+        Ok(())
+    }
+}
+
+/// Where the filter types of a precompiled component are kept.
+fn filter_types_path(precompiled: &Path) -> PathBuf {
+    precompiled.with_extension("types")
+}
+
+/// Filter types stored by [`WasmArtifact::remember_filter_type`], malformed
+/// lines are skipped.
+async fn read_filter_types(precompiled: &Path) -> HashMap<String, FilterType> {
+    let Ok(lines) = tokio::fs::read_to_string(filter_types_path(precompiled)).await else {
+        return HashMap::new();
+    };
+
+    lines
+        .lines()
+        .filter_map(|line| {
+            let (filter, filter_type) = line.split_once(' ')?;
+            Some((filter.to_string(), FilterType::from_name(filter_type)?))
+        })
+        .collect()
 }
 
 pub struct WasmPluginStore {
@@ -38,28 +165,57 @@ impl WasmPluginStore {
     ///
     /// Note that this method only prepares the modules. The filter names defined
     /// in the configuration are registered later via [`WasmPluginStore::register_into`].
-    pub async fn compile(table: &DefinitionsTable) -> Result<Self> {
-        let engine = Engine::default();
-
-        let futures = table.get_plugins().iter().map(|(name, def)| {
+    /// Plugins that fail to load are left out with [`OnFailure::Skip`].
+    pub async fn compile(
+        table: &DefinitionsTable,
+        config: &WasmConfig,
+        on_failure: OnFailure,
+    ) -> Result<Self> {
+        let epochs = table
+            .get_plugins()
+            .values()
+            .any(|def| def.limits.max_time.is_some());
+        let engine = Self::create_engine(config, epochs)?;
+
+        let artifacts = init_all("plugins", table.get_plugins(), on_failure, |(name, def)| {
             let engine = engine.clone();
             let name = name.clone();
             let source = def.source.clone();
-
-            async move {
-                let artifact =
-                    WasmPluginStore::create_artifact(name.clone(), &source, &engine).await?;
-                Ok::<_, miette::Report>((name, Arc::new(artifact)))
-            }
-        });
-
-        let results = join_all(futures).await;
-
-        let mut artifacts = HashMap::new();
-        for res in results {
-            let (name, artifact) = res?;
-            artifacts.insert(name, artifact);
-        }
+            let capabilities = def.capabilities.clone();
+            let limits = def.limits.clone();
+            let label = name.to_string();
+
+            let init = async move {
+                let artifact = Arc::new(
+                    WasmPluginStore::create_artifact(
+                        name.clone(),
+                        &source,
+                        capabilities,
+                        limits,
+                        &engine,
+                        config,
+                    )
+                    .await?,
+                );
+
+                if config.compilation == WasmCompilation::Eager {
+                    // Compiling is CPU bound, plugins are compiled on
+                    // separate threads.
+                    let compiled = artifact.clone();
+                    tokio::task::spawn_blocking(move || compiled.component().map(|_| ()))
+                        .await
+                        .map_err(|err| miette!("{err}"))?
+                        .map_err(|err| miette!("{err}"))?;
+                }
+
+                Ok::<_, miette::Report>((name, artifact))
+            };
+
+            (label, init)
+        })
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
 
         tracing::info!(
             "WasmPluginFactory initialized with {} plugins",
@@ -70,8 +226,9 @@ impl WasmPluginStore {
     }
 
     /// Iterates over the definitions `table` to find filter definitions and
-    /// registers them into the provided `registry`.
-    pub fn register_into(&self, registry: &mut FilterRegistry) {
+    /// registers them into the provided `registry`, keeping `pool_size`
+    /// instances of every filter they are used in.
+    pub fn register_into(&self, registry: &mut FilterRegistry, pool_size: usize) {
         for (name, artifact) in &self.artifacts {
             let artifact_ref = artifact.clone();
 
@@ -84,16 +241,39 @@ impl WasmPluginStore {
                             .more_context(format!("artifact name: '{name}'. error: {e}"))
                     })?;
 
-                    Ok(RegistryFilterContainer::Plugin(module))
+                    Ok(RegistryFilterContainer::Plugin(
+                        module.with_pool_size(pool_size),
+                    ))
                 }),
             );
         }
     }
 
+    /// `epochs` turns on the interruption `max-ms` needs, it slows down every
+    /// plugin a little so it is off unless one has the limit.
+    fn create_engine(config: &WasmConfig, epochs: bool) -> Result<Engine> {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.epoch_interruption(epochs);
+
+        if config.cache {
+            match Cache::from_file(None) {
+                Ok(cache) => {
+                    engine_config.cache(Some(cache));
+                }
+                Err(err) => tracing::warn!("Wasm compilation cache is disabled: {err}"),
+            }
+        }
+
+        Engine::new(&engine_config).map_err(|err| miette!("{err}"))
+    }
+
     pub async fn create_artifact(
         name: FQDN,
         source: &PluginSource,
+        capabilities: WasiCapabilities,
+        limits: PluginLimits,
         engine: &Engine,
+        config: &WasmConfig,
     ) -> Result<WasmArtifact> {
         tracing::debug!("Preparing plugin '{}'...", name);
 
@@ -105,16 +285,30 @@ impl WasmPluginStore {
             .await
             .wrap_err_with(|| format!("Download failed for plugin '{}'", name))?;
 
-        tracing::debug!("Compiling plugin '{}' ({} bytes)...", name, bytes.len());
+        // Keyed by the source too, so a changed plugin is never served stale.
+        let precompiled = config
+            .precompiled_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{name}-{:08x}.cwasm", crc32fast::hash(&bytes))));
 
-        let component = Component::from_binary(engine, &bytes).map_err(|err| miette!("{err}"))?;
+        let filter_types = match &precompiled {
+            Some(path) => read_filter_types(path).await,
+            None => HashMap::new(),
+        };
 
-        tracing::info!("Plugin '{}' loaded and compiled successfully", name);
+        let capabilities = PluginCapabilities::resolve(capabilities)
+            .await
+            .wrap_err_with(|| format!("Invalid capabilities of plugin '{}'", name))?;
 
         Ok(WasmArtifact {
             _name: name,
-            component,
             engine: engine.clone(),
+            bytes: bytes.into(),
+            component: Default::default(),
+            precompiled,
+            filter_types: Arc::new(Mutex::new(filter_types)),
+            capabilities,
+            limits,
         })
     }
 
@@ -134,6 +328,8 @@ pub struct ModuleState {
     pub ctx: WasiCtx,
     pub table: ResourceTable,
     pub session: Option<SessionCtx>,
+    pub http_client: Option<HttpClient>,
+    pub limits: StoreLimits,
 }
 
 unsafe impl Send for ModuleState {}
@@ -142,7 +338,11 @@ unsafe impl Sync for ModuleState {}
 pub struct SessionCtx {
     pub _session: NonNull<Session>,
     pub req_header: Option<NonNull<RequestHeader>>,
-    pub _res_headers: Option<NonNull<ResponseHeader>>,
+    pub res_header: Option<NonNull<ResponseHeader>>,
+    /// The part of the request body the plugin has not read yet.
+    pub body: Option<Bytes>,
+    /// Changes of the request, `None` outside of the request phases.
+    pub mutations: Option<RequestMutations>,
 }
 
 impl WasiView for ModuleState {
@@ -180,6 +380,8 @@ mod tests {
             PluginDefinition {
                 name: FQDN::from_str(plugin_name).unwrap(),
                 source,
+                capabilities: Default::default(),
+                limits: Default::default(),
             },
         );
 
@@ -205,7 +407,7 @@ mod tests {
         let url = format!("{}/filter.wasm", mock_server.uri());
         let table = create_rules_table("test-plugin", PluginSource::Url(url));
 
-        let factory = WasmPluginStore::compile(&table)
+        let factory = WasmPluginStore::compile(&table, &WasmConfig::default(), OnFailure::Abort)
             .await
             .expect("Factory initialization failed");
 
@@ -232,7 +434,8 @@ mod tests {
         let url = format!("{}/missing.wasm", mock_server.uri());
         let table = create_rules_table("missing-plugin", PluginSource::Url(url));
 
-        let result = WasmPluginStore::compile(&table).await;
+        let result =
+            WasmPluginStore::compile(&table, &WasmConfig::default(), OnFailure::Abort).await;
 
         assert!(result.is_err());
         let err = result.err().unwrap();
@@ -242,6 +445,25 @@ mod tests {
             .contains("Availability check failed for plugin 'missing-plugin'"));
     }
 
+    #[tokio::test]
+    async fn test_factory_skips_failed_plugins() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+
+        let url = format!("{}/missing.wasm", mock_server.uri());
+        let table = create_rules_table("missing-plugin", PluginSource::Url(url));
+
+        let store = WasmPluginStore::compile(&table, &WasmConfig::default(), OnFailure::Skip)
+            .await
+            .unwrap();
+
+        assert!(store.artifacts.is_empty());
+    }
+
     #[tokio::test]
     async fn test_factory_compilation_error_invalid_bytes() {
         let mock_server = MockServer::start().await;
@@ -260,7 +482,8 @@ mod tests {
         let url = format!("{}/bad.wasm", mock_server.uri());
         let table = create_rules_table("bad-plugin", PluginSource::Url(url));
 
-        let result = WasmPluginStore::compile(&table).await;
+        let result =
+            WasmPluginStore::compile(&table, &WasmConfig::default(), OnFailure::Abort).await;
 
         assert!(result.is_err());
         let err = result.err().unwrap();
@@ -296,6 +519,8 @@ mod tests {
             PluginDefinition {
                 name: FQDN::from_str("remote").unwrap(),
                 source: PluginSource::Url(url),
+                capabilities: Default::default(),
+                limits: Default::default(),
             },
         );
 
@@ -304,13 +529,15 @@ mod tests {
             PluginDefinition {
                 name: FQDN::from_str("local").unwrap(),
                 source: PluginSource::File(file_path),
+                capabilities: Default::default(),
+                limits: Default::default(),
             },
         );
 
         let table =
             DefinitionsTable::new(HashSet::new(), HashMap::new(), plugins, Default::default());
 
-        let factory = WasmPluginStore::compile(&table)
+        let factory = WasmPluginStore::compile(&table, &WasmConfig::default(), OnFailure::Abort)
             .await
             .expect("Should load mixed sources");
 
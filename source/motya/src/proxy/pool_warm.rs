@@ -0,0 +1,228 @@
+//! `pool-min-idle`: connections to the backends of a route are opened ahead of
+//! traffic by requests the service sends through its own listener, which leave
+//! them idle in the upstream connection pool. The pool belongs to the proxy and
+//! only takes connections back after a request, so the backends receive `HEAD`
+//! requests for the configured `pool-warm-path`.
+
+use std::{collections::HashMap, net::SocketAddr, sync::OnceLock, time::Duration};
+
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use http::{header::HOST, uri::PathAndQuery, Uri};
+use pingora::{
+    prelude::HttpPeer,
+    server::ShutdownWatch,
+    services::background::{background_service, BackgroundService},
+};
+use pingora_http::RequestHeader;
+use uuid::Uuid;
+
+use motya_config::{common_types::connectors::UpstreamConfig, internal::ProxyConfig};
+
+use crate::proxy::{
+    upstream_router::UpstreamContextTrait,
+    warm_up::{base_url, warm_up_host},
+    SharedProxyState,
+};
+
+/// Header of the requests opening connections, holding the token of this
+/// process and the backend to connect to.
+pub const POOL_WARM_HEADER: &str = "x-motya-pool-warm";
+
+/// How often the pools are topped up again, as backends close idle connections.
+const REFRESH: Duration = Duration::from_secs(15);
+
+fn token() -> &'static str {
+    static TOKEN: OnceLock<String> = OnceLock::new();
+    TOKEN.get_or_init(|| Uuid::new_v4().to_string())
+}
+
+/// The backend a request opening connections is meant for, `None` for every
+/// other request.
+pub fn warming_backend(req: &RequestHeader) -> Option<SocketAddr> {
+    let value = req.headers.get(POOL_WARM_HEADER)?.to_str().ok()?;
+    let (claimed, backend) = value.split_once(' ')?;
+
+    if claimed != token() {
+        return None;
+    }
+
+    backend.parse().ok()
+}
+
+/// The backends of a route with `pool-min-idle`.
+pub struct PoolWarmer {
+    min_idle: usize,
+    path: PathAndQuery,
+    peers: HashMap<SocketAddr, HttpPeer>,
+}
+
+impl PoolWarmer {
+    pub fn new(min_idle: usize, path: PathAndQuery, peers: HashMap<SocketAddr, HttpPeer>) -> Self {
+        Self {
+            min_idle,
+            path,
+            peers,
+        }
+    }
+
+    /// The peer a request opening connections asks for.
+    pub fn pick(&self, req: &RequestHeader) -> Option<HttpPeer> {
+        self.peers.get(&warming_backend(req)?).cloned()
+    }
+
+    /// The URI the backends receive the requests opening connections for,
+    /// in place of the route path they are routed by.
+    pub fn upstream_uri(&self) -> Uri {
+        Uri::from(self.path.clone())
+    }
+}
+
+/// Sends the requests opening connections for the routes of a service, and
+/// repeats them every [REFRESH].
+pub struct PoolWarmService {
+    service: String,
+    base: Option<String>,
+    host: Option<String>,
+    state: SharedProxyState,
+    client: reqwest::Client,
+}
+
+impl PoolWarmService {
+    async fn warm(&self, base: &str) {
+        let router = self.state.load_full();
+        let mut requests = Vec::new();
+
+        for upstream in &router.upstreams {
+            let Some(warmer) = &upstream.pool_warmer else {
+                continue;
+            };
+            let url = format!("{base}{}", upstream.get_prefix_path().path());
+
+            for backend in warmer.peers.keys() {
+                // Recovered backends are filled up again on the next round.
+                if upstream
+                    .balancer
+                    .as_ref()
+                    .is_some_and(|balancer| !balancer.is_available(backend))
+                {
+                    continue;
+                }
+
+                // Sent at once, so each one needs a connection of its own.
+                for _ in 0..warmer.min_idle {
+                    requests.push(self.send(url.clone(), *backend));
+                }
+            }
+        }
+
+        join_all(requests).await;
+    }
+
+    async fn send(&self, url: String, backend: SocketAddr) {
+        let mut builder = self
+            .client
+            .head(url)
+            .header(POOL_WARM_HEADER, format!("{} {backend}", token()));
+
+        if let Some(host) = &self.host {
+            builder = builder.header(HOST, host);
+        }
+
+        if let Err(err) = builder.send().await {
+            tracing::debug!(
+                "Opening a connection of {} to {backend} failed: {err}",
+                self.service
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for PoolWarmService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let Some(base) = &self.base else {
+            tracing::warn!(
+                "Service {} has no TCP listener to open connections through, ignoring 'pool-min-idle'",
+                self.service
+            );
+            return;
+        };
+
+        let mut refresh = tokio::time::interval(REFRESH);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = refresh.tick() => self.warm(base).await,
+            }
+        }
+    }
+}
+
+/// Whether a route of `proxy` has `pool-min-idle`.
+pub fn wants_pool_warm(proxy: &ProxyConfig) -> bool {
+    proxy
+        .connectors
+        .upstreams
+        .iter()
+        .any(|upstream| match &upstream.upstream {
+            UpstreamConfig::Service(peer) => peer.pool_min_idle > 0,
+            UpstreamConfig::MultiServer(multi) => multi.pool_min_idle > 0,
+            UpstreamConfig::Static(_) => false,
+        })
+}
+
+pub fn pool_warm_service(
+    proxy: &ProxyConfig,
+    state: SharedProxyState,
+) -> Box<dyn pingora::services::Service> {
+    Box::new(background_service(
+        "motya-pool-warm",
+        PoolWarmService {
+            service: proxy.name.clone(),
+            base: base_url(proxy),
+            host: warm_up_host(proxy),
+            state,
+            client: reqwest::Client::builder()
+                // The listener certificate is issued for the public name.
+                .danger_accept_invalid_certs(true)
+                .build()
+                .expect("pool warm client must build"),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(value: &str) -> RequestHeader {
+        let mut req = RequestHeader::build("HEAD", b"/", None).unwrap();
+        req.insert_header(POOL_WARM_HEADER, value).unwrap();
+        req
+    }
+
+    #[test]
+    fn test_pick() {
+        let addr: SocketAddr = "10.0.0.5:8080".parse().unwrap();
+        let warmer = PoolWarmer::new(
+            2,
+            PathAndQuery::from_static("/healthz"),
+            HashMap::from([(addr, HttpPeer::new(addr, false, String::new()))]),
+        );
+
+        let peer = warmer.pick(&request(&format!("{} 10.0.0.5:8080", token())));
+        assert_eq!(peer.unwrap()._address.to_string(), "10.0.0.5:8080");
+        assert_eq!(warmer.upstream_uri(), "/healthz");
+
+        // Only backends of the route, and only with the token of this process.
+        assert!(warmer
+            .pick(&request(&format!("{} 10.0.0.6:8080", token())))
+            .is_none());
+        assert!(warmer.pick(&request("guessed 10.0.0.5:8080")).is_none());
+        assert!(warmer
+            .pick(&RequestHeader::build("HEAD", b"/", None).unwrap())
+            .is_none());
+    }
+}
@@ -1,11 +1,45 @@
-use pingora::listeners::tls::TlsSettings;
+use pingora::{
+    apps::HttpServerOptions, listeners::tls::TlsSettings, protocols::http::v2::server::H2Options,
+};
+use pingora_proxy::HttpProxy;
 
-use motya_config::common_types::listeners::{ListenerKind, Listeners};
+use crate::proxy::{acme, tls_fingerprint};
+use motya_config::common_types::listeners::{H2Limits, ListenerKind, Listeners, TlsConfig};
 
-pub fn populate_listners<T>(
+/// Fails when a TLS listener of `listeners` cannot load its certificate and
+/// key, before adding the listeners would stop the whole server.
+pub fn check_tls_files(listeners: &Listeners) -> miette::Result<()> {
+    for list_cfg in &listeners.list_cfgs {
+        if let ListenerKind::Tcp {
+            addr,
+            tls:
+                Some(TlsConfig::Files {
+                    cert_path,
+                    key_path,
+                }),
+            ..
+        } = &list_cfg.source
+        {
+            let (Some(cert), Some(key)) = (cert_path.to_str(), key_path.to_str()) else {
+                return Err(miette::miette!("TLS files of {addr} are not UTF-8 paths"));
+            };
+
+            TlsSettings::intermediate(cert, key).map_err(|e| {
+                miette::miette!("Failed to load the TLS certificate of {addr}: {e}")
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn populate_listners<SV>(
     listeners: &Listeners,
-    service: &mut pingora::services::listening::Service<T>,
+    service: &mut pingora::services::listening::Service<HttpProxy<SV>>,
 ) {
+    let mut h2c_enabled = false;
+    let mut h2_limits: Option<H2Limits> = None;
+
     for list_cfg in listeners.list_cfgs.iter() {
         // NOTE: See https://github.com/cloudflare/pingora/issues/182 for tracking "paths aren't
         // always UTF-8 strings".
@@ -17,18 +51,33 @@ pub fn populate_listners<T>(
                 addr,
                 tls: Some(tls_cfg),
                 offer_h2,
+                ..
             } => {
-                let cert_path = tls_cfg
-                    .cert_path
-                    .to_str()
-                    .expect("cert path should be utf8");
-                let key_path = tls_cfg.key_path.to_str().expect("key path should be utf8");
-
-                // TODO: Make conditional!
-                let mut settings = TlsSettings::intermediate(cert_path, key_path)
-                    .expect("adding TLS listener shouldn't fail");
+                let fingerprint = list_cfg.tls_fingerprint;
+                let mut settings = match tls_cfg {
+                    TlsConfig::Files {
+                        cert_path,
+                        key_path,
+                    } => {
+                        let cert_path = cert_path.to_str().expect("cert path should be utf8");
+                        let key_path = key_path.to_str().expect("key path should be utf8");
+                        // TODO: Make conditional!
+                        TlsSettings::intermediate(cert_path, key_path)
+                            .expect("adding TLS listener shouldn't fail")
+                    }
+                    TlsConfig::Acme(acme_cfg) => {
+                        let resolver =
+                            acme::resolver(acme_cfg).expect("setting up ACME shouldn't fail");
+                        TlsSettings::with_callbacks(Box::new(resolver))
+                            .expect("adding TLS listener shouldn't fail")
+                    }
+                };
+                if fingerprint {
+                    tls_fingerprint::enable(&mut settings);
+                }
                 if *offer_h2 {
                     settings.enable_h2();
+                    h2_limits = Some(strictest(h2_limits, list_cfg.h2_limits));
                 }
 
                 service.add_tls_with_settings(addr, None, settings);
@@ -37,10 +86,15 @@ pub fn populate_listners<T>(
                 addr,
                 tls: None,
                 offer_h2,
+                h2c,
             } => {
                 if *offer_h2 {
                     panic!("Unsupported configuration: {addr:?} configured without TLS, but H2 enabled which requires TLS");
                 }
+                h2c_enabled |= *h2c;
+                if *h2c {
+                    h2_limits = Some(strictest(h2_limits, list_cfg.h2_limits));
+                }
                 service.add_tcp(addr);
             }
             ListenerKind::Uds(path) => {
@@ -49,4 +103,49 @@ pub fn populate_listners<T>(
             }
         }
     }
+
+    // Pingora enables h2c for the whole service, so once one listener asks for
+    // it every cleartext listener of the service accepts prior-knowledge HTTP/2.
+    if h2c_enabled {
+        if let Some(app) = service.app_logic_mut() {
+            let mut options = HttpServerOptions::default();
+            options.h2c = true;
+            app.server_options = Some(options);
+        }
+    }
+
+    // Like h2c, the HTTP/2 settings are per service. Services sharing listeners
+    // agree on them, file servers get the strictest of their listeners.
+    if let (Some(limits), Some(app)) = (h2_limits, service.app_logic_mut()) {
+        app.h2_options = Some(h2_options(limits));
+    }
+}
+
+fn strictest(current: Option<H2Limits>, limits: H2Limits) -> H2Limits {
+    let Some(current) = current else {
+        return limits;
+    };
+
+    H2Limits {
+        max_header_list_bytes: current
+            .max_header_list_bytes
+            .min(limits.max_header_list_bytes),
+        max_concurrent_streams: current
+            .max_concurrent_streams
+            .min(limits.max_concurrent_streams),
+        max_pending_reset_streams: current
+            .max_pending_reset_streams
+            .min(limits.max_pending_reset_streams),
+    }
+}
+
+/// HTTP/2 settings of a service. Oversized header lists are refused with
+/// `431`, and their CONTINUATION frames are capped along with them.
+fn h2_options(limits: H2Limits) -> H2Options {
+    let mut options = H2Options::new();
+    options
+        .max_header_list_size(limits.max_header_list_bytes)
+        .max_concurrent_streams(limits.max_concurrent_streams)
+        .max_pending_accept_reset_streams(limits.max_pending_reset_streams);
+    options
 }
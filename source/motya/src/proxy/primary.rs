@@ -0,0 +1,81 @@
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use http::Method;
+use pingora::prelude::HttpPeer;
+
+use motya_config::common_types::connectors::MethodSplitConfig;
+
+/// Primary servers of a replicated upstream, picked round-robin.
+pub struct PrimaryGroup {
+    peers: Vec<HttpPeer>,
+    next: AtomicUsize,
+}
+
+impl PrimaryGroup {
+    /// `peer` builds the primary peers, like the ones of the replicas.
+    pub fn new(addrs: &[SocketAddr], peer: impl Fn(&SocketAddr) -> HttpPeer) -> Self {
+        Self {
+            peers: addrs.iter().map(peer).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn pick(&self) -> HttpPeer {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.peers.len();
+        self.peers[idx].clone()
+    }
+}
+
+/// Serves `GET` and `HEAD` from the replicas and everything else from the
+/// primary servers.
+pub struct MethodSplit {
+    primary: PrimaryGroup,
+}
+
+impl MethodSplit {
+    pub fn new(config: MethodSplitConfig, peer: impl Fn(&SocketAddr) -> HttpPeer) -> Self {
+        Self {
+            primary: PrimaryGroup::new(&config.primary, peer),
+        }
+    }
+
+    pub fn is_replica_read(method: &Method) -> bool {
+        matches!(*method, Method::GET | Method::HEAD)
+    }
+
+    pub fn pick_primary(&self) -> HttpPeer {
+        self.primary.pick()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_robin() {
+        let addrs = [
+            "10.0.0.1:80".parse().unwrap(),
+            "10.0.0.2:80".parse().unwrap(),
+        ];
+        let group = PrimaryGroup::new(&addrs, |addr| HttpPeer::new(addr, false, String::new()));
+
+        let picked: Vec<String> = (0..4).map(|_| group.pick()._address.to_string()).collect();
+
+        assert_eq!(
+            picked,
+            ["10.0.0.1:80", "10.0.0.2:80", "10.0.0.1:80", "10.0.0.2:80"]
+        );
+    }
+
+    #[test]
+    fn test_replica_reads() {
+        assert!(MethodSplit::is_replica_read(&Method::GET));
+        assert!(MethodSplit::is_replica_read(&Method::HEAD));
+        assert!(!MethodSplit::is_replica_read(&Method::OPTIONS));
+        assert!(!MethodSplit::is_replica_read(&Method::POST));
+    }
+}
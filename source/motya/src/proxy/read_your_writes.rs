@@ -0,0 +1,129 @@
+use std::{net::SocketAddr, time::Duration};
+
+use http::Method;
+use pingora::prelude::HttpPeer;
+
+use crate::proxy::{balancer::key_selector::KeySourceContext, primary::PrimaryGroup};
+use motya_config::common_types::connectors::ReadYourWritesConfig;
+
+/// Pins writes, and the reads of clients that wrote recently, to the primary
+/// servers of an upstream.
+pub struct ReadYourWrites {
+    primary: PrimaryGroup,
+    ttl: Duration,
+    cookie: String,
+    header: Option<String>,
+}
+
+impl ReadYourWrites {
+    pub fn new(config: ReadYourWritesConfig, peer: impl Fn(&SocketAddr) -> HttpPeer) -> Self {
+        Self {
+            primary: PrimaryGroup::new(&config.primary, peer),
+            ttl: config.ttl,
+            cookie: config.cookie,
+            header: config.header,
+        }
+    }
+
+    pub fn is_write(method: &Method) -> bool {
+        matches!(
+            *method,
+            Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+        )
+    }
+
+    /// Whether the request must be served by a primary server.
+    pub fn wants_primary<C: KeySourceContext>(&self, method: &Method, ctx: &C) -> bool {
+        Self::is_write(method)
+            || ctx.get_cookie(&self.cookie).is_some()
+            || self
+                .header
+                .as_deref()
+                .is_some_and(|header| ctx.get_header(header).is_some())
+    }
+
+    pub fn pick_primary(&self) -> HttpPeer {
+        self.primary.pick()
+    }
+
+    /// `Set-Cookie` value pinning the client for `ttl`, scoped to `path`.
+    pub fn pin_cookie(&self, path: &str) -> String {
+        format!(
+            "{}=1; Max-Age={}; Path={}; HttpOnly",
+            self.cookie,
+            self.ttl.as_secs(),
+            path
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::IpAddr};
+
+    use http::uri::PathAndQuery;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockContext {
+        headers: HashMap<String, String>,
+        cookies: HashMap<String, String>,
+    }
+
+    impl KeySourceContext for MockContext {
+        fn get_header(&self, name: &str) -> Option<&str> {
+            self.headers.get(name).map(|s| s.as_str())
+        }
+        fn get_cookie(&self, name: &str) -> Option<&str> {
+            self.cookies.get(name).map(|s| s.as_str())
+        }
+        fn get_ip(&self) -> Option<IpAddr> {
+            None
+        }
+        fn get_path(&self) -> &PathAndQuery {
+            static PATH: PathAndQuery = PathAndQuery::from_static("/");
+            &PATH
+        }
+    }
+
+    fn read_your_writes() -> ReadYourWrites {
+        ReadYourWrites::new(
+            ReadYourWritesConfig {
+                primary: vec![
+                    "10.0.0.1:80".parse().unwrap(),
+                    "10.0.0.2:80".parse().unwrap(),
+                ],
+                ttl: Duration::from_secs(5),
+                cookie: "motya-primary".into(),
+                header: Some("x-read-primary".into()),
+            },
+            |addr| HttpPeer::new(addr, false, String::new()),
+        )
+    }
+
+    #[test]
+    fn test_wants_primary() {
+        let rw = read_your_writes();
+        let mut ctx = MockContext::default();
+
+        assert!(!rw.wants_primary(&Method::GET, &ctx));
+        assert!(rw.wants_primary(&Method::POST, &ctx));
+        assert!(rw.wants_primary(&Method::DELETE, &ctx));
+
+        ctx.cookies.insert("motya-primary".into(), "1".into());
+        assert!(rw.wants_primary(&Method::GET, &ctx));
+
+        ctx.cookies.clear();
+        ctx.headers.insert("x-read-primary".into(), "1".into());
+        assert!(rw.wants_primary(&Method::GET, &ctx));
+    }
+
+    #[test]
+    fn test_pin_cookie() {
+        assert_eq!(
+            read_your_writes().pin_cookie("/api"),
+            "motya-primary=1; Max-Age=5; Path=/api; HttpOnly"
+        );
+    }
+}
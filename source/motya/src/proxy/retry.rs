@@ -0,0 +1,219 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use http::Method;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+
+use motya_config::common_types::connectors::RetryConfig;
+
+/// Length of the window the retry budget is counted over.
+const BUDGET_WINDOW: Duration = Duration::from_secs(10);
+
+/// Retries a window always allows, so quiet routes can still retry.
+const MIN_RETRIES: usize = 10;
+
+fn retries_metric() -> &'static IntCounterVec {
+    static METRIC: OnceLock<IntCounterVec> = OnceLock::new();
+
+    METRIC.get_or_init(|| {
+        register_int_counter_vec!(
+            "motya_upstream_retries_total",
+            "Failed upstream attempts, by whether they were retried",
+            &["route", "outcome"]
+        )
+        .expect("metric should register once")
+    })
+}
+
+/// Why an upstream attempt failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Connect,
+    ServerError,
+}
+
+/// Caps retries to a share of the route's requests, so a failing upstream is
+/// not hit with a multiple of its usual traffic.
+struct RetryBudget {
+    percent: usize,
+    window: Mutex<BudgetWindow>,
+}
+
+struct BudgetWindow {
+    started: Instant,
+    requests: usize,
+    retries: usize,
+}
+
+impl RetryBudget {
+    fn new(percent: usize) -> Self {
+        Self {
+            percent,
+            window: Mutex::new(BudgetWindow {
+                started: Instant::now(),
+                requests: 0,
+                retries: 0,
+            }),
+        }
+    }
+
+    fn window(&self) -> std::sync::MutexGuard<'_, BudgetWindow> {
+        let mut window = self.window.lock().expect("retry budget poisoned");
+
+        if window.started.elapsed() >= BUDGET_WINDOW {
+            *window = BudgetWindow {
+                started: Instant::now(),
+                requests: 0,
+                retries: 0,
+            };
+        }
+
+        window
+    }
+
+    fn record_request(&self) {
+        self.window().requests += 1;
+    }
+
+    fn try_withdraw(&self) -> bool {
+        let mut window = self.window();
+        let allowed = (window.requests * self.percent / 100).max(MIN_RETRIES);
+
+        if window.retries < allowed {
+            window.retries += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The `retry` policy of a route.
+pub struct RetryPolicy {
+    attempts: usize,
+    backoff: Duration,
+    on_server_error: bool,
+    on_connect_error: bool,
+    methods: Vec<Method>,
+    budget: RetryBudget,
+}
+
+impl From<RetryConfig> for RetryPolicy {
+    fn from(value: RetryConfig) -> Self {
+        Self {
+            attempts: value.attempts,
+            backoff: value.backoff,
+            on_server_error: value.on_server_error,
+            on_connect_error: value.on_connect_error,
+            methods: value.methods,
+            budget: RetryBudget::new(value.budget_percent),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Counts the first attempt of a request towards the budget.
+    pub fn record_request(&self) {
+        self.budget.record_request();
+    }
+
+    /// Whether another attempt follows the failure of a request that was
+    /// already retried `retries` times.
+    pub fn should_retry(
+        &self,
+        route: &str,
+        method: &Method,
+        retries: usize,
+        failure: Failure,
+    ) -> bool {
+        let wanted = match failure {
+            Failure::Connect => self.on_connect_error,
+            Failure::ServerError => self.on_server_error,
+        };
+
+        if !wanted || retries + 1 >= self.attempts || !self.methods.contains(method) {
+            return false;
+        }
+
+        let retried = self.budget.try_withdraw();
+        let outcome = if retried {
+            "retried"
+        } else {
+            "budget-exhausted"
+        };
+        retries_metric().with_label_values(&[route, outcome]).inc();
+
+        retried
+    }
+
+    /// Pause before retry number `retry`, doubling from `backoff`.
+    pub fn backoff(&self, retry: usize) -> Duration {
+        let exponent = retry.saturating_sub(1).min(16) as u32;
+        self.backoff.saturating_mul(1 << exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(budget_percent: usize) -> RetryPolicy {
+        RetryPolicy::from(RetryConfig {
+            attempts: 3,
+            backoff: Duration::from_millis(50),
+            on_server_error: true,
+            on_connect_error: false,
+            methods: vec![Method::GET],
+            budget_percent,
+        })
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let policy = policy(100);
+
+        assert!(policy.should_retry("/", &Method::GET, 0, Failure::ServerError));
+        assert!(policy.should_retry("/", &Method::GET, 1, Failure::ServerError));
+        // Third attempt was the last one.
+        assert!(!policy.should_retry("/", &Method::GET, 2, Failure::ServerError));
+
+        assert!(!policy.should_retry("/", &Method::POST, 0, Failure::ServerError));
+        assert!(!policy.should_retry("/", &Method::GET, 0, Failure::Connect));
+    }
+
+    #[test]
+    fn test_budget() {
+        let policy = policy(10);
+
+        for _ in 0..200 {
+            policy.record_request();
+        }
+
+        // 10% of 200 requests.
+        let retried = (0..50)
+            .filter(|_| policy.should_retry("/", &Method::GET, 0, Failure::ServerError))
+            .count();
+        assert_eq!(retried, 20);
+    }
+
+    #[test]
+    fn test_budget_minimum() {
+        let policy = policy(10);
+
+        let retried = (0..50)
+            .filter(|_| policy.should_retry("/", &Method::GET, 0, Failure::ServerError))
+            .count();
+        assert_eq!(retried, MIN_RETRIES);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy(20);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(50));
+        assert_eq!(policy.backoff(2), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use motya_config::common_types::connectors::ShapingConfig;
+
+/// Artificial latency and bandwidth limit applied to a route.
+///
+/// Meant for staging environments that need to reproduce slow networks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrafficShaper {
+    bytes_per_sec: Option<u64>,
+    latency: Duration,
+    jitter: Duration,
+}
+
+impl From<ShapingConfig> for TrafficShaper {
+    fn from(value: ShapingConfig) -> Self {
+        Self {
+            bytes_per_sec: value.bytes_per_sec,
+            latency: value.latency,
+            jitter: value.jitter,
+        }
+    }
+}
+
+impl TrafficShaper {
+    /// Delay added before a request is handled: the fixed latency plus a
+    /// uniformly distributed share of the jitter.
+    pub fn delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.latency;
+        }
+
+        let jitter_ms = self.jitter.as_millis() as u64;
+        self.latency + Duration::from_millis(rand::random_range(0..=jitter_ms))
+    }
+
+    /// Pause needed after sending `len` bytes to stay within the bandwidth limit.
+    pub fn chunk_delay(&self, len: usize) -> Option<Duration> {
+        let rate = self.bytes_per_sec?;
+
+        if len == 0 {
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(len as f64 / rate as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shaper(bytes_per_sec: Option<u64>, latency_ms: u64, jitter_ms: u64) -> TrafficShaper {
+        ShapingConfig {
+            bytes_per_sec,
+            latency: Duration::from_millis(latency_ms),
+            jitter: Duration::from_millis(jitter_ms),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_delay_within_jitter_bounds() {
+        assert_eq!(shaper(None, 200, 0).delay(), Duration::from_millis(200));
+
+        let jittery = shaper(None, 100, 50);
+        for _ in 0..100 {
+            let delay = jittery.delay();
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(150));
+        }
+    }
+
+    #[test]
+    fn test_chunk_delay() {
+        let limited = shaper(Some(1024), 0, 0);
+
+        assert_eq!(limited.chunk_delay(2048), Some(Duration::from_secs(2)));
+        assert_eq!(limited.chunk_delay(512), Some(Duration::from_millis(500)));
+        assert_eq!(limited.chunk_delay(0), None);
+        assert_eq!(shaper(None, 10, 0).chunk_delay(4096), None);
+    }
+}
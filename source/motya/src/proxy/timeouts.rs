@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use pingora::{prelude::HttpPeer, Error, ErrorType, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::{FailToProxy, Session};
+
+use motya_config::common_types::connectors::TimeoutsConfig;
+
+/// Limits on how long a route may wait for its upstream.
+#[derive(Debug, Clone)]
+pub struct RouteTimeouts {
+    connect: Option<Duration>,
+    read: Option<Duration>,
+    write: Option<Duration>,
+    total: Option<Duration>,
+    body: Bytes,
+}
+
+impl From<TimeoutsConfig> for RouteTimeouts {
+    fn from(value: TimeoutsConfig) -> Self {
+        Self {
+            connect: value.connect,
+            read: value.read,
+            write: value.write,
+            total: value.total,
+            body: Bytes::from(value.body),
+        }
+    }
+}
+
+impl RouteTimeouts {
+    /// Time left of the `total` budget of a request that arrived at `started`,
+    /// `Err` once it is spent.
+    fn remaining(&self, started: Instant) -> Result<Option<Duration>> {
+        let Some(total) = self.total else {
+            return Ok(None);
+        };
+
+        match total.checked_sub(started.elapsed()) {
+            Some(left) if !left.is_zero() => Ok(Some(left)),
+            _ => Error::e_explain(ErrorType::HTTPStatus(504), "total timeout exceeded"),
+        }
+    }
+
+    /// Sets the limits on `peer`, none of them reaching past the `total` budget.
+    pub fn apply(&self, peer: &mut HttpPeer, started: Instant) -> Result<()> {
+        let remaining = self.remaining(started)?;
+
+        let cap = |limit: Option<Duration>, current: Option<Duration>| match (limit, remaining) {
+            (Some(limit), Some(remaining)) => Some(limit.min(remaining)),
+            (limit, remaining) => limit.or(remaining).or(current),
+        };
+
+        let options = &mut peer.options;
+        options.connection_timeout = cap(self.connect, options.connection_timeout);
+        options.total_connection_timeout = cap(self.connect, options.total_connection_timeout);
+        options.read_timeout = cap(self.read, options.read_timeout);
+        options.write_timeout = cap(self.write, options.write_timeout);
+
+        Ok(())
+    }
+
+    /// Fails a response that is still streaming once the `total` budget is spent.
+    pub fn check(&self, started: Instant) -> Result<()> {
+        self.remaining(started)
+            .map(drop)
+            .map_err(|_| Error::explain(ErrorType::ReadTimedout, "total timeout exceeded"))
+    }
+
+    pub fn is_timeout(e: &Error) -> bool {
+        matches!(
+            e.etype(),
+            ErrorType::ConnectTimedout
+                | ErrorType::ReadTimedout
+                | ErrorType::WriteTimedout
+                | ErrorType::HTTPStatus(504)
+        )
+    }
+
+    /// Answers with `504` and the configured body, unless the response
+    /// already started.
+    pub async fn respond(&self, session: &mut Session) -> FailToProxy {
+        if session.response_written().is_none() {
+            let result = async {
+                let mut response = ResponseHeader::build(504, Some(2))?;
+                response.insert_header("Content-Type", "text/plain")?;
+                response.insert_header("Content-Length", self.body.len().to_string())?;
+
+                session
+                    .write_response_header(Box::new(response), false)
+                    .await?;
+                session
+                    .write_response_body(Some(self.body.clone()), true)
+                    .await
+            }
+            .await;
+
+            if let Err(err) = result {
+                tracing::error!("Failed to send timeout response to downstream: {err}");
+            }
+        }
+
+        FailToProxy {
+            error_code: 504,
+            can_reuse_downstream: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeouts(total: Option<Duration>) -> RouteTimeouts {
+        RouteTimeouts::from(TimeoutsConfig {
+            connect: Some(Duration::from_millis(500)),
+            read: None,
+            write: Some(Duration::from_secs(60)),
+            total,
+            body: "Gateway Timeout".into(),
+        })
+    }
+
+    fn peer() -> HttpPeer {
+        HttpPeer::new("10.0.0.1:80", false, String::new())
+    }
+
+    #[test]
+    fn test_apply_without_total() {
+        let mut peer = peer();
+        timeouts(None).apply(&mut peer, Instant::now()).unwrap();
+
+        assert_eq!(
+            peer.options.connection_timeout,
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(peer.options.read_timeout, None);
+        assert_eq!(peer.options.write_timeout, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_apply_caps_by_total() {
+        let mut peer = peer();
+        timeouts(Some(Duration::from_secs(5)))
+            .apply(&mut peer, Instant::now())
+            .unwrap();
+
+        let read = peer.options.read_timeout.unwrap();
+        let write = peer.options.write_timeout.unwrap();
+
+        assert!(read <= Duration::from_secs(5) && read > Duration::from_secs(4));
+        assert!(write <= Duration::from_secs(5) && write > Duration::from_secs(4));
+        assert_eq!(
+            peer.options.connection_timeout,
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn test_spent_budget() {
+        let timeouts = timeouts(Some(Duration::from_millis(10)));
+        let started = Instant::now() - Duration::from_millis(20);
+
+        let err = timeouts.apply(&mut peer(), started).unwrap_err();
+        assert!(RouteTimeouts::is_timeout(&err));
+        assert!(timeouts.check(started).is_err());
+        assert!(timeouts.check(Instant::now()).is_ok());
+    }
+}
@@ -0,0 +1,343 @@
+//! JA3 and JA4 fingerprints of TLS client hellos, for listeners with
+//! `tls-fingerprint`. The client hello is read while the handshake runs and
+//! the fingerprint is kept with the TLS session of the connection.
+//!
+//! Pingora only hands out the TLS session of HTTP/1 connections, so requests
+//! over HTTP/2 carry no fingerprint.
+
+use std::{
+    os::raw::c_int,
+    ptr,
+    sync::{Arc, OnceLock},
+};
+
+use foreign_types::ForeignTypeRef;
+use openssl::ex_data::Index;
+use pingora::{
+    listeners::tls::TlsSettings,
+    tls::{
+        hash::{hash, MessageDigest},
+        ssl::{ClientHelloResponse, Ssl, SslRef},
+    },
+};
+use pingora_proxy::Session;
+use sha2::{Digest, Sha256};
+
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// Fingerprints of the client hello of a connection, stored in the context
+/// extensions of its requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFingerprint {
+    /// MD5 of the JA3 string, in hex.
+    pub ja3: String,
+    pub ja4: String,
+}
+
+/// What the fingerprints are made of.
+#[derive(Debug, Default)]
+struct ClientHello {
+    legacy_version: u16,
+    ciphers: Vec<u16>,
+    /// In the order the client sent them.
+    extensions: Vec<u16>,
+    groups: Vec<u16>,
+    point_formats: Vec<u8>,
+    signature_algorithms: Vec<u16>,
+    supported_versions: Vec<u16>,
+    first_alpn: Option<Vec<u8>>,
+}
+
+/// GREASE values (RFC 8701) are random per connection and left out.
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn u16_list(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .filter(|value| !is_grease(*value))
+        .collect()
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn joined<T: ToString>(values: &[T], separator: &str) -> String {
+    values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// First 12 hex digits of the SHA-256 of `input`, zeros for nothing.
+fn truncated_sha256(input: &str) -> String {
+    if input.is_empty() {
+        return "000000000000".to_string();
+    }
+
+    hex_encode(&Sha256::digest(input.as_bytes()))[..12].to_string()
+}
+
+impl ClientHello {
+    /// `VERSION,CIPHERS,EXTENSIONS,GROUPS,POINT_FORMATS` in decimal.
+    fn ja3_string(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.legacy_version,
+            joined(&self.ciphers, "-"),
+            joined(&self.extensions, "-"),
+            joined(&self.groups, "-"),
+            joined(&self.point_formats, "-"),
+        )
+    }
+
+    fn ja4(&self) -> String {
+        let version = self
+            .supported_versions
+            .iter()
+            .max()
+            .copied()
+            .unwrap_or(self.legacy_version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            _ => "00",
+        };
+
+        let sni = if self.extensions.contains(&EXT_SERVER_NAME) {
+            'd'
+        } else {
+            'i'
+        };
+
+        let alpn = match self.first_alpn.as_deref() {
+            Some(alpn) if !alpn.is_empty() => {
+                let (first, last) = (alpn[0], alpn[alpn.len() - 1]);
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                    format!("{}{}", first as char, last as char)
+                } else {
+                    // First and last digit of the value in hex instead.
+                    let hex = hex_encode(&[first, last]);
+                    format!("{}{}", &hex[..1], &hex[3..])
+                }
+            }
+            _ => "00".to_string(),
+        };
+
+        let hex_sorted = |values: &[u16]| {
+            let mut values = values
+                .iter()
+                .map(|value| format!("{value:04x}"))
+                .collect::<Vec<_>>();
+            values.sort();
+            values.join(",")
+        };
+
+        let ciphers = hex_sorted(&self.ciphers);
+
+        let extensions = self
+            .extensions
+            .iter()
+            .copied()
+            .filter(|ext| ![EXT_SERVER_NAME, EXT_ALPN].contains(ext))
+            .collect::<Vec<_>>();
+        let mut extensions = hex_sorted(&extensions);
+        if !extensions.is_empty() && !self.signature_algorithms.is_empty() {
+            let algorithms = self
+                .signature_algorithms
+                .iter()
+                .map(|alg| format!("{alg:04x}"))
+                .collect::<Vec<_>>();
+            extensions = format!("{extensions}_{}", algorithms.join(","));
+        }
+
+        format!(
+            "t{version}{sni}{:02}{:02}{alpn}_{}_{}",
+            self.ciphers.len().min(99),
+            self.extensions.len().min(99),
+            truncated_sha256(&ciphers),
+            truncated_sha256(&extensions),
+        )
+    }
+
+    fn fingerprint(&self) -> TlsFingerprint {
+        let ja3 = hash(MessageDigest::md5(), self.ja3_string().as_bytes())
+            .map(|digest| hex_encode(&digest))
+            .unwrap_or_default();
+
+        TlsFingerprint {
+            ja3,
+            ja4: self.ja4(),
+        }
+    }
+
+    /// Reads the client hello, only possible from the client hello callback.
+    fn read(ssl: &SslRef) -> Self {
+        let ciphers = ssl.client_hello_ciphers().map(u16_list).unwrap_or_default();
+
+        let mut hello = ClientHello {
+            ciphers,
+            ..Default::default()
+        };
+
+        // SAFETY: called from the client hello callback of `ssl`, the
+        // extension list is freed once copied.
+        unsafe {
+            hello.legacy_version =
+                openssl_sys::SSL_client_hello_get0_legacy_version(ssl.as_ptr()) as u16;
+
+            let mut present: *mut c_int = ptr::null_mut();
+            let mut len = 0;
+            let found = openssl_sys::SSL_client_hello_get1_extensions_present(
+                ssl.as_ptr(),
+                &mut present,
+                &mut len,
+            );
+            if found == 1 && !present.is_null() {
+                hello.extensions = std::slice::from_raw_parts(present, len)
+                    .iter()
+                    .map(|ext| *ext as u16)
+                    .filter(|ext| !is_grease(*ext))
+                    .collect();
+                openssl_sys::OPENSSL_free(present.cast());
+            }
+
+            // Extensions start with the length of their list.
+            if let Some(data) = client_hello_ext(ssl, EXT_SUPPORTED_GROUPS) {
+                hello.groups = u16_list(data.get(2..).unwrap_or_default());
+            }
+            if let Some(data) = client_hello_ext(ssl, EXT_POINT_FORMATS) {
+                hello.point_formats = data.get(1..).unwrap_or_default().to_vec();
+            }
+            if let Some(data) = client_hello_ext(ssl, EXT_SIGNATURE_ALGORITHMS) {
+                hello.signature_algorithms = u16_list(data.get(2..).unwrap_or_default());
+            }
+            if let Some(data) = client_hello_ext(ssl, EXT_SUPPORTED_VERSIONS) {
+                hello.supported_versions = u16_list(data.get(1..).unwrap_or_default());
+            }
+            if let Some(data) = client_hello_ext(ssl, EXT_ALPN) {
+                let len = data.get(2).copied().unwrap_or_default() as usize;
+                hello.first_alpn = data.get(3..3 + len).map(<[u8]>::to_vec);
+            }
+        }
+
+        hello
+    }
+}
+
+/// The data of an extension of the client hello, which outlives the
+/// callback it is read from.
+///
+/// # Safety
+///
+/// Only valid from the client hello callback of `ssl`.
+unsafe fn client_hello_ext(ssl: &SslRef, ext_type: u16) -> Option<&[u8]> {
+    let mut out = ptr::null();
+    let mut len = 0;
+    let found = openssl_sys::SSL_client_hello_get0_ext(
+        ssl.as_ptr(),
+        u32::from(ext_type),
+        &mut out,
+        &mut len,
+    );
+
+    (found == 1 && !out.is_null()).then(|| std::slice::from_raw_parts(out, len))
+}
+
+fn fingerprint_index() -> Index<Ssl, Arc<TlsFingerprint>> {
+    static INDEX: OnceLock<Index<Ssl, Arc<TlsFingerprint>>> = OnceLock::new();
+
+    *INDEX.get_or_init(|| Ssl::new_ex_index().expect("TLS ex data index should be available"))
+}
+
+/// Fingerprints the client hellos of a listener.
+pub fn enable(settings: &mut TlsSettings) {
+    settings.set_client_hello_callback(|ssl, _alert| {
+        let fingerprint = ClientHello::read(ssl).fingerprint();
+        tracing::debug!(
+            ja3 = %fingerprint.ja3,
+            ja4 = %fingerprint.ja4,
+            "TLS client hello fingerprinted"
+        );
+        ssl.set_ex_data(fingerprint_index(), Arc::new(fingerprint));
+        Ok(ClientHelloResponse::SUCCESS)
+    });
+}
+
+/// The fingerprint of the connection of a request, if its listener takes
+/// them and the request came over HTTP/1.
+pub fn of(session: &Session) -> Option<TlsFingerprint> {
+    let ssl = session.downstream_session.stream()?.get_ssl()?;
+
+    ssl.ex_data(fingerprint_index())
+        .map(|fingerprint| (**fingerprint).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The Chrome client hello of the JA4 specification.
+    fn chrome() -> ClientHello {
+        ClientHello {
+            legacy_version: 0x0303,
+            ciphers: vec![
+                0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013,
+                0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+            ],
+            extensions: vec![
+                0x001b, 0x0000, 0x0033, 0x0010, 0x4469, 0x0017, 0x002d, 0x000d, 0x0005, 0x0023,
+                0x0012, 0x002b, 0xff01, 0x000b, 0x000a, 0x0015,
+            ],
+            groups: vec![0x001d, 0x0017, 0x0018],
+            point_formats: vec![0],
+            signature_algorithms: vec![
+                0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601,
+            ],
+            supported_versions: vec![0x0304, 0x0303],
+            first_alpn: Some(b"h2".to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_ja4() {
+        assert_eq!(chrome().ja4(), "t13d1516h2_8daaf6152771_e5627efa2ab1");
+
+        let bare = ClientHello {
+            legacy_version: 0x0303,
+            ..Default::default()
+        };
+        assert_eq!(bare.ja4(), "t12i000000_000000000000_000000000000");
+    }
+
+    #[test]
+    fn test_ja3() {
+        let hello = chrome();
+        assert_eq!(
+            hello.ja3_string(),
+            "771,4865-4866-4867-49195-49199-49196-49200-52393-52392-49171-49172-156-157-47-53,\
+             27-0-51-16-17513-23-45-13-5-35-18-43-65281-11-10-21,29-23-24,0"
+        );
+        assert_eq!(hello.fingerprint().ja3.len(), 32);
+    }
+
+    #[test]
+    fn test_grease() {
+        assert!(is_grease(0x0a0a));
+        assert!(is_grease(0xfafa));
+        assert!(!is_grease(0x0a1a));
+        assert_eq!(u16_list(&[0x2a, 0x2a, 0x13, 0x01]), vec![0x1301]);
+    }
+}
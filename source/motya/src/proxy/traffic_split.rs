@@ -0,0 +1,197 @@
+use std::{
+    hash::Hasher,
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use pingora::prelude::HttpPeer;
+
+use motya_config::common_types::connectors::TrafficSplitConfig;
+
+use crate::proxy::{balancer::key_selector::KeySourceContext, primary::PrimaryGroup};
+
+/// Buckets the requests are spread over, the weights are percentages.
+const BUCKETS: usize = 100;
+
+/// Coprime with [BUCKETS], so every run of 100 requests visits each bucket
+/// once and the groups are interleaved rather than served in turns.
+const STRIDE: usize = 37;
+
+struct Group {
+    name: String,
+    /// Exclusive end of the bucket range, a drained group has an empty one.
+    end: usize,
+    servers: PrimaryGroup,
+}
+
+/// Shares the requests of a route between groups of servers, each group
+/// picking its servers round-robin.
+pub struct TrafficSplit {
+    groups: Vec<Group>,
+    sticky: bool,
+    override_header: Option<String>,
+    next: AtomicUsize,
+}
+
+impl TrafficSplit {
+    pub fn new(config: TrafficSplitConfig, peer: impl Fn(&SocketAddr) -> HttpPeer) -> Self {
+        let mut end = 0;
+        let groups = config
+            .groups
+            .iter()
+            .map(|group| {
+                end += group.weight;
+                Group {
+                    name: group.name.clone(),
+                    end,
+                    servers: PrimaryGroup::new(&group.servers, &peer),
+                }
+            })
+            .collect();
+
+        Self {
+            groups,
+            sticky: config.sticky,
+            override_header: config.override_header,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// The group named by the override header wins over the weights, drained
+    /// groups included. A `sticky` split keeps a client on one group, clients
+    /// on Unix sockets have no address and are spread like the others.
+    pub fn pick<C: KeySourceContext>(&self, ctx: &C) -> HttpPeer {
+        if let Some(group) = self.forced(ctx) {
+            return group.servers.pick();
+        }
+
+        let bucket = match ctx.get_ip() {
+            Some(ip) if self.sticky => {
+                let mut hasher = fnv::FnvHasher::default();
+                hasher.write(ip.to_string().as_bytes());
+                hasher.finish() as usize % BUCKETS
+            }
+            _ => self.next.fetch_add(1, Ordering::Relaxed) * STRIDE % BUCKETS,
+        };
+
+        self.groups
+            .iter()
+            .find(|group| bucket < group.end)
+            .map(|group| group.servers.pick())
+            .expect("split weights should add up to 100")
+    }
+
+    fn forced<C: KeySourceContext>(&self, ctx: &C) -> Option<&Group> {
+        let header = self.override_header.as_deref()?;
+        let name = ctx.get_header(header)?.trim();
+
+        let group = self.groups.iter().find(|group| group.name == name);
+        if group.is_none() {
+            tracing::debug!("Ignoring '{header}', '{name}' is not a split group");
+        }
+
+        group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use http::uri::PathAndQuery;
+    use motya_config::common_types::connectors::SplitGroupConfig;
+
+    use super::*;
+
+    struct MockContext {
+        ip: Option<IpAddr>,
+        canary: Option<&'static str>,
+    }
+
+    impl KeySourceContext for MockContext {
+        fn get_header(&self, name: &str) -> Option<&str> {
+            assert_eq!(name, "x-canary");
+            self.canary
+        }
+        fn get_cookie(&self, _: &str) -> Option<&str> {
+            None
+        }
+        fn get_ip(&self) -> Option<IpAddr> {
+            self.ip
+        }
+        fn get_path(&self) -> &PathAndQuery {
+            static PATH: PathAndQuery = PathAndQuery::from_static("/");
+            &PATH
+        }
+    }
+
+    fn ctx(ip: &str, canary: Option<&'static str>) -> MockContext {
+        MockContext {
+            ip: Some(ip.parse().unwrap()),
+            canary,
+        }
+    }
+
+    fn split(sticky: bool) -> TrafficSplit {
+        let group = |name: &str, weight, server: &str| SplitGroupConfig {
+            name: name.to_string(),
+            weight,
+            servers: vec![server.parse().unwrap()],
+        };
+
+        TrafficSplit::new(
+            TrafficSplitConfig {
+                groups: vec![
+                    group("stable", 95, "10.0.0.1:80"),
+                    group("canary", 5, "10.0.0.2:80"),
+                    group("drained", 0, "10.0.0.3:80"),
+                ],
+                sticky,
+                override_header: Some("x-canary".to_string()),
+            },
+            |addr| HttpPeer::new(addr, false, String::new()),
+        )
+    }
+
+    #[test]
+    fn test_weights() {
+        let split = split(false);
+
+        let canary = (0..1000)
+            .map(|_| split.pick(&ctx("192.0.2.7", None))._address.to_string())
+            .filter(|addr| addr == "10.0.0.2:80")
+            .count();
+
+        assert_eq!(canary, 50);
+    }
+
+    #[test]
+    fn test_sticky() {
+        let split = split(true);
+        let first = split.pick(&ctx("192.0.2.7", None))._address.to_string();
+
+        for _ in 0..100 {
+            let peer = split.pick(&ctx("192.0.2.7", None));
+            assert_eq!(peer._address.to_string(), first);
+        }
+    }
+
+    #[test]
+    fn test_override_header() {
+        let split = split(true);
+
+        let forced = [
+            ("stable", "10.0.0.1:80"),
+            ("canary", "10.0.0.2:80"),
+            (" drained ", "10.0.0.3:80"),
+        ];
+
+        for (group, addr) in forced {
+            let peer = split.pick(&ctx("192.0.2.7", Some(group)));
+            assert_eq!(peer._address.to_string(), addr);
+        }
+
+        // Unknown groups are split by the weights as usual.
+        assert!(split.forced(&ctx("192.0.2.7", Some("always"))).is_none());
+    }
+}
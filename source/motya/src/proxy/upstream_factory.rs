@@ -1,12 +1,14 @@
-use std::collections::BTreeSet;
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+};
 
-use futures_util::FutureExt;
 use miette::{miette, Result};
-use pingora::prelude::HttpPeer;
 use pingora_load_balancing::{
-    discovery,
     prelude::RoundRobin,
-    selection::{consistent::KetamaHashing, FNVHash, Random},
+    selection::{BackendIter, BackendSelection, FNVHash, Random},
     Backend, Backends, LoadBalancer,
 };
 
@@ -15,37 +17,184 @@ use motya_config::{
         connectors::{MultiServerUpstreamConfig, UpstreamConfig, UpstreamContextConfig},
         definitions::Modificator,
     },
-    internal::{SelectionKind, UpstreamOptions},
+    internal::{HealthCheckKind, SelectionKind, UpstreamOptions},
 };
 
 use crate::proxy::{
-    balancer::key_selector::{Balancer, BalancerType, KeySelector},
+    backend_override::BackendOverride,
+    balancer::{
+        affinity_keys::AffinityKeyStore,
+        circuit_breaker::CircuitBreaker,
+        discovery::{DiscoveryInput, DiscoveryRegistry, DISCOVERY_REFRESH},
+        key_selector::{Balancer, BalancerType, KeySelector},
+        ring::KetamaBalancer,
+        sticky::CookieSticky,
+    },
+    buffering::ResponseBuffering,
+    cache::RouteCache,
+    compression::ResponseCompression,
     filters::chain_resolver::ChainResolver,
-    upstream_router::UpstreamContext,
+    grpc::GrpcHealthCheck,
+    har::HarRecorder,
+    host_sni::HostSni,
+    pool_warm::PoolWarmer,
+    primary::MethodSplit,
+    read_your_writes::ReadYourWrites,
+    retry::RetryPolicy,
+    shaping::TrafficShaper,
+    timeouts::RouteTimeouts,
+    traffic_split::TrafficSplit,
+    upstream_router::{http_peer, UpstreamContext, UpstreamContextTrait},
+    when_down::WhenDown,
 };
 
 #[derive(Clone)]
 pub struct UpstreamFactory {
     resolver: ChainResolver,
+    affinity_keys: Option<Arc<AffinityKeyStore>>,
+    discovery: Arc<DiscoveryRegistry>,
+    state_dir: Option<PathBuf>,
+    service: String,
 }
 
 impl UpstreamFactory {
     pub fn new(resolver: ChainResolver) -> Self {
-        Self { resolver }
+        Self {
+            resolver,
+            affinity_keys: None,
+            discovery: Arc::new(DiscoveryRegistry::default()),
+            state_dir: None,
+            service: String::new(),
+        }
+    }
+
+    /// Keys signing the cookies of `CookieSticky` routes, opened from `system.state-dir`.
+    pub fn with_affinity_keys(mut self, affinity_keys: Option<Arc<AffinityKeyStore>>) -> Self {
+        self.affinity_keys = affinity_keys;
+        self
+    }
+
+    /// Discovery backends the `discovery "NAME"` of the routes picks from.
+    pub fn with_discovery(mut self, discovery: Arc<DiscoveryRegistry>) -> Self {
+        self.discovery = discovery;
+        self
+    }
+
+    /// `system.state-dir`, holding the compression dictionaries of the routes.
+    pub fn with_state_dir(mut self, state_dir: Option<PathBuf>) -> Self {
+        self.state_dir = state_dir;
+        self
+    }
+
+    /// Name of the proxy service the routes belong to, reported by their health checks.
+    pub fn with_service(mut self, service: impl Into<String>) -> Self {
+        self.service = service.into();
+        self
     }
 
     pub async fn create_context(&self, config: UpstreamContextConfig) -> Result<UpstreamContext> {
+        let when_down = config
+            .lb_options
+            .as_ref()
+            .and_then(|lb_options| lb_options.when_down.clone())
+            .map(WhenDown::from);
+
         let balancer = match &config.upstream {
             UpstreamConfig::Static(_) | UpstreamConfig::Service(_) => None,
             UpstreamConfig::MultiServer(m) => {
                 if let Some(lb_options) = config.lb_options {
-                    setup_balancer(lb_options, m)?
+                    setup_balancer(
+                        lb_options,
+                        m,
+                        &self.service,
+                        self.affinity_keys.as_ref(),
+                        &self.discovery,
+                    )
+                    .await?
                 } else {
                     None
                 }
             }
         };
 
+        let (read_your_writes, split_by_method, traffic_split, backend_override) =
+            match &config.upstream {
+                UpstreamConfig::MultiServer(m) => {
+                    let peer = |addr: &SocketAddr| http_peer(addr, m.tls_sni.as_deref(), &m.alpn);
+
+                    let backend_override = m.backend_override.clone().map(|rule| {
+                        let primary = m.read_your_writes.iter().flat_map(|rw| &rw.primary);
+                        let split = m.split_by_method.iter().flat_map(|split| &split.primary);
+
+                        let peers: HashMap<_, _> = m
+                            .servers
+                            .iter()
+                            .map(|s| &s.address)
+                            .chain(primary)
+                            .chain(split)
+                            .map(|addr| (*addr, peer(addr)))
+                            .collect();
+
+                        BackendOverride::new(rule, peers)
+                    });
+
+                    (
+                        m.read_your_writes
+                            .clone()
+                            .map(|rw| ReadYourWrites::new(rw, peer)),
+                        m.split_by_method
+                            .clone()
+                            .map(|split| MethodSplit::new(split, peer)),
+                        m.split.clone().map(|split| TrafficSplit::new(split, peer)),
+                        backend_override,
+                    )
+                }
+                _ => (None, None, None, None),
+            };
+
+        let host_sni = match &config.upstream {
+            UpstreamConfig::Service(s) => s.host_sni.clone(),
+            UpstreamConfig::MultiServer(m) => m.host_sni.clone(),
+            UpstreamConfig::Static(_) => None,
+        };
+
+        let pool_warmer = match &config.upstream {
+            UpstreamConfig::Service(s) if s.pool_min_idle > 0 => {
+                let peer = http_peer(&s.peer_address, s.tls.then_some(s.sni.as_str()), &s.alpn);
+                s.pool_warm_path.clone().map(|path| {
+                    PoolWarmer::new(
+                        s.pool_min_idle,
+                        path,
+                        HashMap::from([(s.peer_address, peer)]),
+                    )
+                })
+            }
+            UpstreamConfig::MultiServer(m) if m.pool_min_idle > 0 => {
+                let peer = |addr: &SocketAddr| http_peer(addr, m.tls_sni.as_deref(), &m.alpn);
+                let peers = m
+                    .servers
+                    .iter()
+                    .map(|s| (s.address, peer(&s.address)))
+                    .collect();
+                m.pool_warm_path
+                    .clone()
+                    .map(|path| PoolWarmer::new(m.pool_min_idle, path, peers))
+            }
+            _ => None,
+        };
+
+        let compression = match config.compression {
+            Some(compression) => {
+                Some(ResponseCompression::open(compression, self.state_dir.as_deref()).await?)
+            }
+            None => None,
+        };
+
+        let cache = match config.cache {
+            Some(cache) => Some(RouteCache::open(cache).await?),
+            None => None,
+        };
+
         let mut chains = Vec::new();
 
         for modificator in config.chains {
@@ -61,15 +210,44 @@ impl UpstreamFactory {
             balancer,
             upstream: config.upstream,
             chains,
+            shaping: config.shaping.map(TrafficShaper::from),
+            timeouts: config.timeouts.map(RouteTimeouts::from),
+            retry: config.retry.map(RetryPolicy::from),
+            compression,
+            max_request_body: config.max_request_body_bytes,
+            buffering: config.buffering.map(ResponseBuffering::from),
+            record_har: config.record_har.map(HarRecorder::from),
+            cache,
+            read_your_writes,
+            split_by_method,
+            traffic_split,
+            backend_override,
+            methods: config.methods,
+            flag: config.flag,
+            active: config.active,
+            host_sni: host_sni.map(HostSni::from),
+            pool_warmer,
+            when_down,
         };
 
+        if let Some(shaper) = &ctx.shaping {
+            tracing::warn!(
+                "Traffic shaping is enabled for '{}': {:?}",
+                ctx.get_prefix_path(),
+                shaper
+            );
+        }
+
         Ok(ctx)
     }
 }
 
-fn setup_balancer(
+async fn setup_balancer(
     lb_options: UpstreamOptions,
     m: &MultiServerUpstreamConfig,
+    service: &str,
+    affinity_keys: Option<&Arc<AffinityKeyStore>>,
+    registry: &DiscoveryRegistry,
 ) -> Result<Option<Balancer>, miette::Error> {
     let addrs = m.servers.iter().map(|s| (&s.address, s.weight));
     let mut backends = addrs
@@ -80,40 +258,69 @@ fn setup_balancer(
         })
         .collect::<Vec<_>>();
     for (backend, (addr, _)) in backends.iter_mut().zip(addrs) {
+        //sni is https only
+        //https://github.com/cloudflare/pingora/blob/main/docs/user_guide/peer.md
         assert!(backend
             .ext
-            .insert(HttpPeer::new(
-                addr,
-                //sni is https only
-                //https://github.com/cloudflare/pingora/blob/main/docs/user_guide/peer.md
-                m.tls_sni.is_some(),
-                m.tls_sni.clone().unwrap_or("".to_string())
-            ))
+            .insert(http_peer(addr, m.tls_sni.as_deref(), &m.alpn))
             .is_none());
     }
-    let disco = discovery::Static::new(BTreeSet::from_iter(backends));
+    let route = m.prefix_path.path();
+    let discovery = registry
+        .build(
+            &lb_options.discovery,
+            DiscoveryInput {
+                route,
+                servers: BTreeSet::from_iter(backends.clone()),
+                settings: &lb_options.discovery.settings,
+            },
+            m.tls_sni.as_deref(),
+            &m.alpn,
+        )
+        .map_err(|err| miette!("Failed to set up discovery of '{route}': {err}"))?;
+    let dynamic = !lb_options.discovery.is_static();
+    let health = &lb_options.health_checks;
     let balancer_type = match lb_options.selection {
-        SelectionKind::FvnHash => {
-            BalancerType::FNVHash(LoadBalancer::<FNVHash>::from_backends(Backends::new(disco)))
-        }
-        SelectionKind::RoundRobin => BalancerType::RoundRobin(
-            LoadBalancer::<RoundRobin>::from_backends(Backends::new(disco)),
-        ),
-        SelectionKind::Random => {
-            BalancerType::Random(LoadBalancer::<Random>::from_backends(Backends::new(disco)))
-        }
-        SelectionKind::KetamaHashing => BalancerType::KetamaHashing(
-            LoadBalancer::<KetamaHashing>::from_backends(Backends::new(disco)),
-        ),
+        SelectionKind::FvnHash => BalancerType::FNVHash(with_health_check(
+            LoadBalancer::<FNVHash>::from_backends(Backends::new(discovery)),
+            health,
+            service,
+        )),
+        SelectionKind::RoundRobin => BalancerType::RoundRobin(with_health_check(
+            LoadBalancer::<RoundRobin>::from_backends(Backends::new(discovery)),
+            health,
+            service,
+        )),
+        SelectionKind::Random => BalancerType::Random(with_health_check(
+            LoadBalancer::<Random>::from_backends(Backends::new(discovery)),
+            health,
+            service,
+        )),
+        SelectionKind::KetamaHashing => BalancerType::KetamaHashing(KetamaBalancer::new(
+            backends
+                .iter()
+                .cloned()
+                .zip(m.servers.iter().map(|s| s.vnodes)),
+        )),
     };
     match &balancer_type {
-        BalancerType::FNVHash(b) => b.update().now_or_never(),
-        BalancerType::KetamaHashing(b) => b.update().now_or_never(),
-        BalancerType::Random(b) => b.update().now_or_never(),
-        BalancerType::RoundRobin(b) => b.update().now_or_never(),
+        BalancerType::FNVHash(b) => with_discovery(b, dynamic).await,
+        BalancerType::Random(b) => with_discovery(b, dynamic).await,
+        BalancerType::RoundRobin(b) => with_discovery(b, dynamic).await,
+        // The ring is built from the static backends directly.
+        BalancerType::KetamaHashing(_) => Ok(()),
     }
-    .expect("static should not block")
-    .expect("static should not error");
+    .map_err(|err| miette!("Discovery of '{route}' failed: {err}"))?;
+
+    let sticky = match (lb_options.sticky, affinity_keys) {
+        (Some(config), Some(keys)) => Some(CookieSticky::new(config, keys.clone())),
+        (Some(_), None) => {
+            return Err(miette!(
+                "'CookieSticky' selection needs the affinity keys of 'system.state-dir'"
+            ))
+        }
+        (None, _) => None,
+    };
 
     Ok(Some(Balancer {
         selector: lb_options
@@ -122,5 +329,92 @@ fn setup_balancer(
             .transpose()
             .map_err(|err| miette!("{err}"))?,
         balancer_type,
+        breaker: lb_options.circuit_breaker.map(|config| {
+            CircuitBreaker::new(
+                config,
+                m.prefix_path.path().to_string(),
+                m.servers.iter().map(|s| s.address),
+            )
+        }),
+        sticky,
     }))
 }
+
+/// Runs the first discovery of `lb`, a `dynamic` one is repeated every
+/// [DISCOVERY_REFRESH] until a reload dropped it.
+async fn with_discovery<S>(lb: &Arc<LoadBalancer<S>>, dynamic: bool) -> pingora::Result<()>
+where
+    S: BackendSelection + Send + Sync + 'static,
+    S::Iter: BackendIter,
+{
+    lb.update().await?;
+
+    if !dynamic {
+        return Ok(());
+    }
+
+    let weak = Arc::downgrade(lb);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(DISCOVERY_REFRESH);
+        // The first tick completes at once, the update above covers it.
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let Some(lb) = weak.upgrade() else {
+                return;
+            };
+            if let Err(err) = lb.update().await {
+                tracing::warn!("Discovery failed, keeping the current backends: {err}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Starts the health checks of `lb` for the proxy `service`, they stop once a
+/// reload dropped it.
+fn with_health_check<S>(
+    mut lb: LoadBalancer<S>,
+    health: &HealthCheckKind,
+    service: &str,
+) -> Arc<LoadBalancer<S>>
+where
+    S: BackendSelection + Send + Sync + 'static,
+    S::Iter: BackendIter,
+{
+    let HealthCheckKind::Grpc {
+        service: grpc_service,
+        interval,
+    } = health
+    else {
+        return Arc::new(lb);
+    };
+
+    lb.set_health_check(Box::new(GrpcHealthCheck::new(
+        grpc_service.clone(),
+        service.to_string(),
+    )));
+
+    let lb = Arc::new(lb);
+    let weak = Arc::downgrade(&lb);
+    let interval = *interval;
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            let Some(lb) = weak.upgrade() else {
+                return;
+            };
+            lb.backends().run_health_check(true).await;
+        }
+    });
+
+    lb
+}
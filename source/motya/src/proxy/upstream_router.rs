@@ -1,18 +1,59 @@
-use http::uri::PathAndQuery;
-use matchit::{InsertError, Router};
-use pingora::{prelude::HttpPeer, ErrorType};
+use std::{net::SocketAddr, time::SystemTime};
 
-use crate::proxy::{
-    balancer::key_selector::Balancer,
-    context::{ContextInfo, SessionInfo},
-    filters::chain_resolver::RuntimeChain,
+use http::{uri::PathAndQuery, Method};
+use matchit::{InsertError, Router};
+use pingora::{prelude::HttpPeer, protocols::ALPN as PeerAlpn};
+
+use crate::{
+    flags::Flags,
+    proxy::{
+        backend_override::BackendOverride,
+        balancer::key_selector::Balancer,
+        buffering::ResponseBuffering,
+        cache::RouteCache,
+        compression::ResponseCompression,
+        context::{ContextInfo, SessionInfo},
+        filters::chain_resolver::RuntimeChain,
+        har::HarRecorder,
+        host_sni::HostSni,
+        pool_warm::PoolWarmer,
+        primary::MethodSplit,
+        read_your_writes::ReadYourWrites,
+        retry::RetryPolicy,
+        shaping::TrafficShaper,
+        timeouts::RouteTimeouts,
+        traffic_split::TrafficSplit,
+        when_down::{all_backends_down, WhenDown},
+    },
+};
+use motya_config::common_types::{
+    active_window::ActiveWindow,
+    connectors::{FlagCondition, RouteMatcher, RoutePattern, UpstreamConfig, ALPN},
 };
-use motya_config::common_types::connectors::{RouteMatcher, UpstreamConfig};
 
 pub struct UpstreamContext {
     pub upstream: UpstreamConfig,
     pub chains: Vec<RuntimeChain>,
     pub balancer: Option<Balancer>,
+    pub shaping: Option<TrafficShaper>,
+    pub timeouts: Option<RouteTimeouts>,
+    pub retry: Option<RetryPolicy>,
+    pub compression: Option<ResponseCompression>,
+    /// `max-request-body-bytes` of the route, overriding the listener's.
+    pub max_request_body: Option<usize>,
+    pub buffering: Option<ResponseBuffering>,
+    pub record_har: Option<HarRecorder>,
+    pub cache: Option<RouteCache>,
+    pub read_your_writes: Option<ReadYourWrites>,
+    pub split_by_method: Option<MethodSplit>,
+    pub traffic_split: Option<TrafficSplit>,
+    pub backend_override: Option<BackendOverride>,
+    pub methods: Option<Vec<Method>>,
+    pub flag: Option<FlagCondition>,
+    pub active: Option<ActiveWindow>,
+    pub host_sni: Option<HostSni>,
+    pub pool_warmer: Option<PoolWarmer>,
+    pub when_down: Option<WhenDown>,
 }
 
 pub trait UpstreamContextTrait {
@@ -20,23 +61,68 @@ pub trait UpstreamContextTrait {
     fn get_route_type(&self) -> RouteMatcher;
     fn get_balancer(&self) -> Option<&Balancer>;
     fn get_peer(&self) -> Option<HttpPeer>;
+    fn get_read_your_writes(&self) -> Option<&ReadYourWrites> {
+        None
+    }
+    fn get_method_split(&self) -> Option<&MethodSplit> {
+        None
+    }
+    fn get_traffic_split(&self) -> Option<&TrafficSplit> {
+        None
+    }
+    fn get_backend_override(&self) -> Option<&BackendOverride> {
+        None
+    }
+    fn get_pool_warmer(&self) -> Option<&PoolWarmer> {
+        None
+    }
+    fn is_grpc(&self) -> bool {
+        false
+    }
+    /// Methods the route accepts, any when `None`.
+    fn get_methods(&self) -> Option<&[Method]> {
+        None
+    }
+    /// Feature flag the route is gated on, always on when `None`.
+    fn get_flag(&self) -> Option<&FlagCondition> {
+        None
+    }
+    /// Time window the route is active in, always when `None`.
+    fn get_active_window(&self) -> Option<&ActiveWindow> {
+        None
+    }
 }
 
 pub struct UpstreamRouter<TUpstream: UpstreamContextTrait> {
-    pub router: Router<TUpstream>,
+    /// Maps a route path to the indexes of its routes in `upstreams`, several
+    /// when the routes take different methods.
+    pub router: Router<Vec<usize>>,
+    /// Regex routes in declaration order, with their index in `upstreams`.
+    pub regexes: Vec<(RoutePattern, usize)>,
+    pub upstreams: Vec<TUpstream>,
 }
 
+/// Named groups of the regex route a request matched, kept in the context
+/// extensions for the filters of the request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteCaptures(pub Vec<(String, String)>);
+
 impl<TUpstream: UpstreamContextTrait> UpstreamRouter<TUpstream> {
     pub fn build(paths: Vec<TUpstream>) -> Result<Self, InsertError> {
-        let mut router = Router::new();
+        let mut routes: Vec<(String, Vec<usize>)> = Vec::new();
+        let mut regexes = Vec::new();
+
+        let mut add =
+            |path: String, idx: usize| match routes.iter_mut().find(|(route, _)| *route == path) {
+                Some((_, indexes)) => indexes.push(idx),
+                None => routes.push((path, vec![idx])),
+            };
 
-        for item in paths {
+        for (idx, item) in paths.iter().enumerate() {
             let raw_path = item.get_prefix_path().path().to_string();
 
             match item.get_route_type() {
-                RouteMatcher::Exact => {
-                    router.insert(raw_path, item)?;
-                }
+                RouteMatcher::Exact => add(raw_path, idx),
                 RouteMatcher::Prefix => {
                     let clean_path = raw_path.trim_end_matches('/');
 
@@ -46,29 +132,83 @@ impl<TUpstream: UpstreamContextTrait> UpstreamRouter<TUpstream> {
                         format!("{}/{{*catch_all}}", clean_path)
                     };
 
-                    router.insert(wildcard_path, item)?;
+                    add(wildcard_path, idx);
+
+                    // gRPC calls use `/package.Service/Method` as the path, so a
+                    // method route has to match without anything after it.
+                    if item.is_grpc() && !clean_path.is_empty() {
+                        add(clean_path.to_string(), idx);
+                    }
                 }
+                RouteMatcher::Regex(pattern) => regexes.push((pattern, idx)),
             }
         }
 
-        Ok(Self { router })
+        let mut router = Router::new();
+
+        for (path, indexes) in routes {
+            router.insert(path, indexes)?;
+        }
+
+        Ok(Self {
+            router,
+            regexes,
+            upstreams: paths,
+        })
     }
 
     pub fn pick_peer(
         &self,
-        _: &mut ContextInfo,
+        ctx: &mut ContextInfo,
         session: &mut SessionInfo,
+        flags: &Flags,
     ) -> Result<Option<HttpPeer>, pingora::BError> {
-        let Some(upstream) = self.get_upstream_by_path(session.path.path()) else {
+        let Some(upstream) = self.get_upstream(session.path.path(), &session.headers.method, flags)
+        else {
             return Ok(None);
         };
 
+        if let Some(peer) = upstream
+            .get_pool_warmer()
+            .and_then(|warmer| warmer.pick(session.headers))
+        {
+            return Ok(Some(peer));
+        }
+
+        if let Some(peer) = upstream
+            .get_backend_override()
+            .and_then(|rule| rule.pick(session))
+        {
+            return Ok(Some(peer));
+        }
+
+        if let Some(rw) = upstream.get_read_your_writes() {
+            if rw.wants_primary(&session.headers.method, session) {
+                return Ok(Some(rw.pick_primary()));
+            }
+        }
+
+        let split = upstream.get_method_split();
+
+        if let Some(split) = split {
+            if ctx.replica_failed || !MethodSplit::is_replica_read(&session.headers.method) {
+                return Ok(Some(split.pick_primary()));
+            }
+        }
+
+        if let Some(traffic) = upstream.get_traffic_split() {
+            return Ok(Some(traffic.pick(session)));
+        }
+
         if let Some(balancer) = upstream.get_balancer() {
-            let backend = balancer.select_backend(session);
+            let backend = balancer.select_backend(session, &mut ctx.key_buffer);
 
-            let backend = backend.ok_or_else(|| {
-                pingora::Error::explain(ErrorType::HTTPStatus(500), "Unable to determine backend")
-            })?;
+            // No healthy replica left, the primary serves the read.
+            if let (None, Some(split)) = (&backend, split) {
+                return Ok(Some(split.pick_primary()));
+            }
+
+            let backend = backend.ok_or_else(all_backends_down)?;
 
             Ok(Some(
                 backend
@@ -85,11 +225,183 @@ impl<TUpstream: UpstreamContextTrait> UpstreamRouter<TUpstream> {
         }
     }
 
-    pub fn get_upstream_by_path(&self, path: &str) -> Option<&TUpstream> {
-        self.router.at(path).ok().map(|v| v.value)
+    pub fn get_upstream(&self, path: &str, method: &Method, flags: &Flags) -> Option<&TUpstream> {
+        self.route_index(path, method, flags)
+            .map(|idx| &self.upstreams[idx])
+    }
+
+    fn accepts(&self, idx: usize, method: &Method, flags: &Flags) -> bool {
+        self.enabled(idx, flags)
+            && self.upstreams[idx]
+                .get_methods()
+                .is_none_or(|methods| methods.contains(method))
+    }
+
+    /// Whether the flag gating the route `idx`, if any, is as it requires
+    /// and the route is within its `active-between` window.
+    fn enabled(&self, idx: usize, flags: &Flags) -> bool {
+        let upstream = &self.upstreams[idx];
+
+        upstream
+            .get_flag()
+            .is_none_or(|flag| flags.is_enabled(&flag.name) == flag.enabled)
+            && upstream
+                .get_active_window()
+                .is_none_or(|window| window.contains(SystemTime::now()))
+    }
+
+    fn matched(&self, path: &str) -> &[usize] {
+        self.router
+            .at(path)
+            .map(|v| v.value.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Whether the prefix route `idx` covers `path`, like its wildcard does.
+    fn prefix_covers(&self, idx: usize, path: &str) -> bool {
+        let upstream = &self.upstreams[idx];
+
+        if upstream.get_route_type() != RouteMatcher::Prefix {
+            return false;
+        }
+
+        let prefix = upstream.get_prefix_path().path().trim_end_matches('/');
+
+        path.strip_prefix(prefix).is_some_and(|rest| {
+            rest.starts_with('/') || (rest.is_empty() && upstream.is_grpc() && !prefix.is_empty())
+        })
+    }
+
+    /// Exact routes win over regex routes, which win over prefix routes.
+    /// Routes that do not take `method` or are gated off by `flags` are skipped.
+    fn route_index(&self, path: &str, method: &Method, flags: &Flags) -> Option<usize> {
+        let matched = self.matched(path);
+
+        if let Some(idx) = matched.iter().copied().find(|idx| {
+            self.upstreams[*idx].get_route_type() == RouteMatcher::Exact
+                && self.accepts(*idx, method, flags)
+        }) {
+            return Some(idx);
+        }
+
+        let regex = self
+            .regexes
+            .iter()
+            .find(|(pattern, idx)| {
+                pattern.regex().is_match(path) && self.accepts(*idx, method, flags)
+            })
+            .map(|(_, idx)| *idx);
+
+        regex
+            .or_else(|| {
+                matched
+                    .iter()
+                    .copied()
+                    .find(|idx| self.accepts(*idx, method, flags))
+            })
+            .or_else(|| {
+                // The most specific prefix may take other methods only, a
+                // shorter one can still match.
+                (0..self.upstreams.len())
+                    .filter(|idx| {
+                        self.prefix_covers(*idx, path) && self.accepts(*idx, method, flags)
+                    })
+                    .max_by_key(|idx| self.upstreams[*idx].get_prefix_path().path().len())
+            })
+    }
+
+    /// Methods taken by the routes of `path`, for the `Allow` header when none
+    /// of them takes the request method. `None` when no route has the path.
+    pub fn allowed_methods(&self, path: &str, flags: &Flags) -> Option<Vec<Method>> {
+        let regexes = self
+            .regexes
+            .iter()
+            .filter(|(pattern, _)| pattern.regex().is_match(path))
+            .map(|(_, idx)| *idx);
+        let prefixes = (0..self.upstreams.len()).filter(|idx| self.prefix_covers(*idx, path));
+
+        let mut routes = self
+            .matched(path)
+            .iter()
+            .copied()
+            .chain(regexes)
+            .chain(prefixes)
+            .filter(|idx| self.enabled(*idx, flags))
+            .peekable();
+        routes.peek()?;
+
+        let mut allowed: Vec<Method> = Vec::new();
+
+        for method in routes.flat_map(|idx| self.upstreams[idx].get_methods().unwrap_or_default()) {
+            if !allowed.contains(method) {
+                allowed.push(method.clone());
+            }
+        }
+
+        Some(allowed)
+    }
+
+    /// Named groups of the regex route of `path`, `None` for other routes or
+    /// when the pattern has no named group.
+    pub fn route_captures(
+        &self,
+        path: &str,
+        method: &Method,
+        flags: &Flags,
+    ) -> Option<RouteCaptures> {
+        let idx = self.route_index(path, method, flags)?;
+        let RouteMatcher::Regex(pattern) = self.upstreams[idx].get_route_type() else {
+            return None;
+        };
+
+        let regex = pattern.regex();
+        let captures = regex.captures(path)?;
+        let named: Vec<_> = regex
+            .capture_names()
+            .flatten()
+            .filter_map(|name| Some((name.to_string(), captures.name(name)?.as_str().to_string())))
+            .collect();
+
+        (!named.is_empty()).then_some(RouteCaptures(named))
+    }
+}
+
+impl UpstreamContext {
+    pub fn allows_websocket(&self) -> bool {
+        match &self.upstream {
+            UpstreamConfig::Service(s) => s.allow_websocket,
+            UpstreamConfig::MultiServer(m) => m.allow_websocket,
+            UpstreamConfig::Static(_) => true,
+        }
     }
 }
 
+impl UpstreamRouter<UpstreamContext> {
+    /// Chains that have `pre-routing` filters. They are only allowed at the
+    /// top of `connectors`, which every route inherits, so the first route
+    /// holds all of them.
+    pub fn pre_routing_chains(&self) -> impl Iterator<Item = &RuntimeChain> {
+        self.upstreams
+            .first()
+            .into_iter()
+            .flat_map(|upstream| &upstream.chains)
+            .filter(|chain| !chain.pre_routing.is_empty())
+    }
+}
+
+/// Builds a peer speaking `alpn`, an `H2` peer without TLS uses h2c.
+pub fn http_peer(addr: &SocketAddr, tls_sni: Option<&str>, alpn: &ALPN) -> HttpPeer {
+    let mut peer = HttpPeer::new(addr, tls_sni.is_some(), tls_sni.unwrap_or("").to_string());
+
+    peer.options.alpn = match alpn {
+        ALPN::H1 => PeerAlpn::H1,
+        ALPN::H2 => PeerAlpn::H2,
+        ALPN::H2H1 => PeerAlpn::H2H1,
+    };
+
+    peer
+}
+
 impl UpstreamContextTrait for UpstreamContext {
     fn get_prefix_path(&self) -> &PathAndQuery {
         match &self.upstream {
@@ -103,11 +415,51 @@ impl UpstreamContextTrait for UpstreamContext {
         self.balancer.as_ref()
     }
 
+    fn get_read_your_writes(&self) -> Option<&ReadYourWrites> {
+        self.read_your_writes.as_ref()
+    }
+
+    fn get_method_split(&self) -> Option<&MethodSplit> {
+        self.split_by_method.as_ref()
+    }
+
+    fn get_traffic_split(&self) -> Option<&TrafficSplit> {
+        self.traffic_split.as_ref()
+    }
+
+    fn get_backend_override(&self) -> Option<&BackendOverride> {
+        self.backend_override.as_ref()
+    }
+
+    fn get_pool_warmer(&self) -> Option<&PoolWarmer> {
+        self.pool_warmer.as_ref()
+    }
+
+    fn get_methods(&self) -> Option<&[Method]> {
+        self.methods.as_deref()
+    }
+
+    fn get_flag(&self) -> Option<&FlagCondition> {
+        self.flag.as_ref()
+    }
+
+    fn get_active_window(&self) -> Option<&ActiveWindow> {
+        self.active.as_ref()
+    }
+
+    fn is_grpc(&self) -> bool {
+        match &self.upstream {
+            UpstreamConfig::Service(s) => s.grpc,
+            UpstreamConfig::MultiServer(m) => m.grpc,
+            UpstreamConfig::Static(_) => false,
+        }
+    }
+
     fn get_route_type(&self) -> RouteMatcher {
         match &self.upstream {
-            UpstreamConfig::Service(peer_options) => peer_options.matcher,
+            UpstreamConfig::Service(peer_options) => peer_options.matcher.clone(),
             UpstreamConfig::Static(_) => RouteMatcher::Exact,
-            UpstreamConfig::MultiServer(m) => m.matcher,
+            UpstreamConfig::MultiServer(m) => m.matcher.clone(),
         }
     }
 
@@ -116,9 +468,11 @@ impl UpstreamContextTrait for UpstreamContext {
     // MultiServer - processing is delegated to the load balancer.
     fn get_peer(&self) -> Option<HttpPeer> {
         match &self.upstream {
-            UpstreamConfig::Service(s) => {
-                Some(HttpPeer::new(s.peer_address, false, "".to_string()))
-            }
+            UpstreamConfig::Service(s) => Some(http_peer(
+                &s.peer_address,
+                s.tls.then_some(s.sni.as_str()),
+                &s.alpn,
+            )),
             _ => None,
         }
     }
@@ -126,12 +480,17 @@ impl UpstreamContextTrait for UpstreamContext {
 
 #[cfg(test)]
 pub mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
     use super::*;
 
     pub struct MockUpstreamContext {
         pub prefix: PathAndQuery,
         pub matcher: RouteMatcher,
         pub peer: HttpPeer,
+        pub methods: Option<Vec<Method>>,
+        pub flag: Option<FlagCondition>,
+        pub active: Option<ActiveWindow>,
     }
 
     impl UpstreamContextTrait for MockUpstreamContext {
@@ -140,7 +499,7 @@ pub mod tests {
         }
 
         fn get_route_type(&self) -> RouteMatcher {
-            self.matcher
+            self.matcher.clone()
         }
 
         fn get_balancer(&self) -> Option<&Balancer> {
@@ -149,6 +508,18 @@ pub mod tests {
         fn get_peer(&self) -> Option<HttpPeer> {
             Some(self.peer.clone())
         }
+
+        fn get_methods(&self) -> Option<&[Method]> {
+            self.methods.as_deref()
+        }
+
+        fn get_flag(&self) -> Option<&FlagCondition> {
+            self.flag.as_ref()
+        }
+
+        fn get_active_window(&self) -> Option<&ActiveWindow> {
+            self.active.as_ref()
+        }
     }
 
     fn mock_context(path: &str, matcher: RouteMatcher) -> MockUpstreamContext {
@@ -156,11 +527,15 @@ pub mod tests {
             prefix: path.parse().unwrap(),
             matcher,
             peer: HttpPeer::new("0.0.0.0:0", false, "".to_string()),
+            methods: None,
+            flag: None,
+            active: None,
         }
     }
 
     #[test]
     pub fn test_router_configuration_modes() {
+        let flags = Flags::default();
         let paths = vec![
             mock_context("/health", RouteMatcher::Exact),
             mock_context("/api", RouteMatcher::Prefix),
@@ -170,31 +545,224 @@ pub mod tests {
         let router = UpstreamRouter::build(paths).expect("Router build failed");
 
         // --- Test Strict ---
-        let elem = router.get_upstream_by_path("/health").unwrap();
+        let elem = router
+            .get_upstream("/health", &Method::GET, &flags)
+            .unwrap();
         assert_eq!(elem.get_prefix_path(), "/health");
 
-        let elem = router.get_upstream_by_path("/health/foo");
+        let elem = router.get_upstream("/health/foo", &Method::GET, &flags);
 
         assert_eq!(elem.unwrap().get_prefix_path(), "/");
 
         // --- Test Prefix ---
-        let elem = router.get_upstream_by_path("/api/users").unwrap();
+        let elem = router
+            .get_upstream("/api/users", &Method::GET, &flags)
+            .unwrap();
         assert_eq!(elem.get_prefix_path(), "/api");
 
-        let elem = router.get_upstream_by_path("/api").unwrap();
+        let elem = router.get_upstream("/api", &Method::GET, &flags).unwrap();
         assert_eq!(elem.get_prefix_path(), "/");
 
         // --- Test Fallback (Root) ---
-        let elem = router.get_upstream_by_path("/random/stuff").unwrap();
+        let elem = router
+            .get_upstream("/random/stuff", &Method::GET, &flags)
+            .unwrap();
         assert_eq!(elem.get_prefix_path(), "/");
     }
 
+    #[test]
+    fn test_regex_routes() {
+        let flags = Flags::default();
+        let regex = |pattern: &str| RouteMatcher::Regex(RoutePattern::new(pattern).unwrap());
+
+        let paths = vec![
+            mock_context("/api/v1/users", RouteMatcher::Exact),
+            mock_context("/api", RouteMatcher::Prefix),
+            mock_context("/api", regex(r"^/api/v(?<version>[0-9]+)/users")),
+            mock_context("/", regex(r"^/api/v[0-9]+/")),
+        ];
+
+        let router = UpstreamRouter::build(paths).expect("Router build failed");
+        let index = |path: &str| router.route_index(path, &Method::GET, &flags).unwrap();
+
+        assert_eq!(index("/api/v1/users"), 0);
+        // The first matching pattern wins over the later one and the prefix.
+        assert_eq!(index("/api/v2/users/7"), 2);
+        assert_eq!(index("/api/v2/orders"), 3);
+        assert_eq!(index("/api/health"), 1);
+
+        assert_eq!(
+            router.route_captures("/api/v2/users/7", &Method::GET, &flags),
+            Some(RouteCaptures(vec![("version".into(), "2".into())]))
+        );
+        assert_eq!(
+            router.route_captures("/api/v2/orders", &Method::GET, &flags),
+            None
+        );
+        assert_eq!(
+            router.route_captures("/api/health", &Method::GET, &flags),
+            None
+        );
+    }
+
+    #[test]
+    fn test_method_routes() {
+        let flags = Flags::default();
+        let only = |path: &str, matcher: RouteMatcher, methods: &[Method]| MockUpstreamContext {
+            methods: Some(methods.to_vec()),
+            ..mock_context(path, matcher)
+        };
+
+        let paths = vec![
+            only("/api", RouteMatcher::Prefix, &[Method::POST, Method::PUT]),
+            mock_context("/api", RouteMatcher::Prefix),
+            only("/admin", RouteMatcher::Prefix, &[Method::GET]),
+            only("/health", RouteMatcher::Exact, &[Method::GET, Method::HEAD]),
+            mock_context("/", RouteMatcher::Prefix),
+        ];
+
+        let router = UpstreamRouter::build(paths).expect("Router build failed");
+        let index = |path: &str, method: Method| router.route_index(path, &method, &flags);
+
+        assert_eq!(index("/api/users", Method::POST), Some(0));
+        assert_eq!(index("/api/users", Method::GET), Some(1));
+        assert_eq!(index("/health", Method::HEAD), Some(3));
+        // A shorter prefix takes what the more specific routes refuse.
+        assert_eq!(index("/admin/users", Method::DELETE), Some(4));
+        assert_eq!(index("/health", Method::POST), Some(4));
+
+        let router =
+            UpstreamRouter::build(vec![only("/api", RouteMatcher::Prefix, &[Method::GET])])
+                .expect("Router build failed");
+
+        assert_eq!(
+            router.route_index("/api/users", &Method::DELETE, &flags),
+            None
+        );
+        assert_eq!(
+            router.allowed_methods("/api/users", &flags),
+            Some(vec![Method::GET])
+        );
+        assert_eq!(router.allowed_methods("/other", &flags), None);
+    }
+
+    #[test]
+    fn test_flag_routes() {
+        let gated = |path: &str, enabled: bool| MockUpstreamContext {
+            flag: Some(FlagCondition {
+                name: "new-router".to_string(),
+                enabled,
+            }),
+            ..mock_context(path, RouteMatcher::Prefix)
+        };
+
+        let router = UpstreamRouter::build(vec![
+            gated("/api", true),
+            gated("/api", false),
+            gated("/beta", true),
+        ])
+        .expect("Router build failed");
+
+        let on = Flags::new(BTreeMap::from([("new-router".to_string(), true)]));
+        let off = Flags::new(BTreeMap::from([("new-router".to_string(), false)]));
+
+        assert_eq!(router.route_index("/api/users", &Method::GET, &on), Some(0));
+        assert_eq!(
+            router.route_index("/api/users", &Method::GET, &off),
+            Some(1)
+        );
+        // An undeclared flag counts as off.
+        assert_eq!(
+            router.route_index("/api/users", &Method::GET, &Flags::default()),
+            Some(1)
+        );
+
+        assert_eq!(router.route_index("/beta/x", &Method::GET, &off), None);
+        assert_eq!(router.allowed_methods("/beta/x", &off), None);
+    }
+
+    #[test]
+    fn test_active_window_routes() {
+        let flags = Flags::default();
+        let now = SystemTime::now();
+        let window = |start: SystemTime, end: SystemTime| MockUpstreamContext {
+            active: Some(ActiveWindow { start, end }),
+            ..mock_context("/", RouteMatcher::Prefix)
+        };
+        let hour = Duration::from_secs(3600);
+
+        let router = UpstreamRouter::build(vec![
+            window(now - 2 * hour, now - hour),
+            window(now - hour, now + hour),
+            mock_context("/", RouteMatcher::Prefix),
+        ])
+        .expect("Router build failed");
+
+        // The expired window is skipped, the current one takes the request.
+        assert_eq!(router.route_index("/sale", &Method::GET, &flags), Some(1));
+
+        let router = UpstreamRouter::build(vec![
+            window(now + hour, now + 2 * hour),
+            mock_context("/", RouteMatcher::Prefix),
+        ])
+        .expect("Router build failed");
+
+        assert_eq!(router.route_index("/sale", &Method::GET, &flags), Some(1));
+    }
+
     #[test]
     fn test_manual_wildcard_override() {
+        let flags = Flags::default();
         let paths = vec![mock_context("/custom/{*foo}", RouteMatcher::Exact)];
         let router = UpstreamRouter::build(paths).expect("Router build failed");
 
-        let elem = router.get_upstream_by_path("/custom/bar").unwrap();
+        let elem = router
+            .get_upstream("/custom/bar", &Method::GET, &flags)
+            .unwrap();
         assert_eq!(elem.get_prefix_path(), "/custom/{*foo}");
     }
+
+    #[test]
+    fn test_grpc_method_routes() {
+        let flags = Flags::default();
+        struct GrpcContext(MockUpstreamContext);
+
+        impl UpstreamContextTrait for GrpcContext {
+            fn get_prefix_path(&self) -> &PathAndQuery {
+                self.0.get_prefix_path()
+            }
+            fn get_route_type(&self) -> RouteMatcher {
+                self.0.get_route_type()
+            }
+            fn get_balancer(&self) -> Option<&Balancer> {
+                None
+            }
+            fn get_peer(&self) -> Option<HttpPeer> {
+                self.0.get_peer()
+            }
+            fn is_grpc(&self) -> bool {
+                true
+            }
+        }
+
+        let router = UpstreamRouter::build(vec![
+            GrpcContext(mock_context("/shop.Cart/Checkout", RouteMatcher::Prefix)),
+            GrpcContext(mock_context("/shop.Cart", RouteMatcher::Prefix)),
+        ])
+        .expect("Router build failed");
+
+        let elem = router
+            .get_upstream("/shop.Cart/Checkout", &Method::GET, &flags)
+            .unwrap();
+        assert_eq!(elem.get_prefix_path(), "/shop.Cart/Checkout");
+
+        let elem = router
+            .get_upstream("/shop.Cart/Add", &Method::GET, &flags)
+            .unwrap();
+        assert_eq!(elem.get_prefix_path(), "/shop.Cart");
+
+        assert!(router
+            .get_upstream("/shop.CartV2/Add", &Method::GET, &flags)
+            .is_none());
+    }
 }
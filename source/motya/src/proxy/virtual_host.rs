@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use http::header::HOST;
+use pingora_http::RequestHeader;
+
+use crate::{
+    maintenance::MaintenancePage,
+    proxy::{error_pages::ErrorPages, warm_up::WarmUpGate, SharedProxyState},
+};
+use motya_config::common_types::{host_name::ascii_host, listeners::UnmatchedHost};
+
+/// A proxy service as seen by the listeners it is served on.
+#[derive(Clone)]
+pub struct VirtualHost {
+    /// Host patterns the service answers, any host when empty.
+    pub hosts: Vec<String>,
+    pub state: SharedProxyState,
+    /// Set while the service has a `warm-up` block.
+    pub warm_up: Option<Arc<WarmUpGate>>,
+    /// Set while the service has an `error-pages` directory.
+    pub error_pages: Option<Arc<ErrorPages>>,
+    pub maintenance: Arc<MaintenancePage>,
+}
+
+/// Where a request on a shared listener goes.
+pub enum Dispatch<'a> {
+    Service(&'a VirtualHost),
+    Misdirected,
+    Close,
+}
+
+/// The services sharing a set of listeners.
+pub struct VirtualHosts {
+    services: Vec<VirtualHost>,
+    unmatched: UnmatchedHost,
+}
+
+impl VirtualHosts {
+    pub fn new(services: Vec<VirtualHost>, unmatched: Option<UnmatchedHost>) -> Self {
+        let has_default = services.iter().any(|service| service.hosts.is_empty());
+        let unmatched = unmatched.unwrap_or(if has_default {
+            UnmatchedHost::DefaultService
+        } else {
+            UnmatchedHost::Misdirected
+        });
+
+        Self {
+            services,
+            unmatched,
+        }
+    }
+
+    /// The service a request starts with before its host is looked at.
+    pub fn first(&self) -> &VirtualHost {
+        &self.services[0]
+    }
+
+    pub fn dispatch(&self, req: &RequestHeader) -> Dispatch<'_> {
+        if let [only] = &self.services[..] {
+            if only.hosts.is_empty() {
+                return Dispatch::Service(only);
+            }
+        }
+
+        let matched = request_host(req).and_then(|host| {
+            self.services.iter().find(|service| {
+                service
+                    .hosts
+                    .iter()
+                    .any(|pattern| host_matches(pattern, &host))
+            })
+        });
+
+        if let Some(service) = matched {
+            return Dispatch::Service(service);
+        }
+
+        match self.unmatched {
+            UnmatchedHost::DefaultService => self
+                .services
+                .iter()
+                .find(|service| service.hosts.is_empty())
+                .map_or(Dispatch::Misdirected, Dispatch::Service),
+            UnmatchedHost::Misdirected => Dispatch::Misdirected,
+            UnmatchedHost::Close => Dispatch::Close,
+        }
+    }
+}
+
+/// Host of `req` without its port, lowercased and with Unicode labels in
+/// punycode like the configured hosts. `None` when it is missing or not a
+/// host name.
+pub(crate) fn request_host(req: &RequestHeader) -> Option<String> {
+    let host = req
+        .headers
+        .get(HOST)
+        .and_then(|value| std::str::from_utf8(value.as_bytes()).ok())
+        .or_else(|| req.uri.host())?;
+
+    // The port is not part of the name, bracketed IPv6 hosts are refused.
+    ascii_host(host.split(':').next()?)
+}
+
+/// `*.example.com` matches exactly one label in front of `example.com`.
+pub(crate) fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .and_then(|rest| rest.strip_suffix('.'))
+            .is_some_and(|label| !label.is_empty() && !label.contains('.')),
+        None => host == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arc_swap::ArcSwap;
+
+    use super::*;
+    use crate::proxy::upstream_router::UpstreamRouter;
+
+    fn service(hosts: &[&str]) -> VirtualHost {
+        VirtualHost {
+            hosts: hosts.iter().map(|host| host.to_string()).collect(),
+            state: Arc::new(ArcSwap::from_pointee(
+                UpstreamRouter::build(vec![]).unwrap(),
+            )),
+            warm_up: None,
+            error_pages: None,
+            maintenance: Arc::new(MaintenancePage::load("test", &Default::default()).unwrap()),
+        }
+    }
+
+    /// The first host of the picked service, or what happens instead.
+    fn dispatch(hosts: &VirtualHosts, host: &str) -> String {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(HOST, host).unwrap();
+
+        match hosts.dispatch(&req) {
+            Dispatch::Service(service) => service.hosts.first().cloned().unwrap_or_default(),
+            Dispatch::Misdirected => "421".into(),
+            Dispatch::Close => "close".into(),
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let shop = || service(&["shop.example.com", "*.shop.example.com"]);
+
+        let hosts = VirtualHosts::new(vec![shop(), service(&[])], None);
+        assert_eq!(
+            dispatch(&hosts, "EU.shop.example.com:8080"),
+            "shop.example.com"
+        );
+        assert_eq!(dispatch(&hosts, "blog.example.com"), "");
+
+        let hosts = VirtualHosts::new(vec![service(&["xn--bcher-kva.example"])], None);
+        assert_eq!(dispatch(&hosts, "Bücher.example"), "xn--bcher-kva.example");
+
+        let hosts = VirtualHosts::new(vec![shop()], None);
+        assert_eq!(dispatch(&hosts, "blog.example.com"), "421");
+
+        let hosts = VirtualHosts::new(vec![shop(), service(&[])], Some(UnmatchedHost::Misdirected));
+        assert_eq!(dispatch(&hosts, "blog.example.com"), "421");
+
+        let hosts = VirtualHosts::new(vec![shop()], Some(UnmatchedHost::Close));
+        assert_eq!(dispatch(&hosts, "a.b.shop.example.com"), "close");
+    }
+}
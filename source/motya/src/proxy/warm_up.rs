@@ -0,0 +1,354 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use http::header::HOST;
+use pingora::{
+    server::ShutdownWatch,
+    services::background::{background_service, BackgroundService},
+};
+use pingora_http::RequestHeader;
+use uuid::Uuid;
+
+use motya_config::{
+    common_types::{listeners::ListenerKind, warm_up::WarmUpConfig},
+    internal::ProxyConfig,
+};
+
+/// Header carrying the token that lets warm-up requests through the gate.
+pub const WARM_UP_HEADER: &str = "x-motya-warm-up";
+
+/// Delay between attempts while the listener is not accepting yet.
+const CONNECT_RETRY: Duration = Duration::from_millis(100);
+
+static GATES: OnceLock<Mutex<BTreeMap<String, Arc<WarmUpGate>>>> = OnceLock::new();
+
+fn gates() -> &'static Mutex<BTreeMap<String, Arc<WarmUpGate>>> {
+    GATES.get_or_init(Default::default)
+}
+
+/// Holds back downstream traffic of a service until its warm-up finished.
+pub struct WarmUpGate {
+    token: String,
+    ready: AtomicBool,
+}
+
+impl WarmUpGate {
+    fn new() -> Self {
+        Self {
+            token: Uuid::new_v4().to_string(),
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    /// Whether the request may be served, either because the service is ready
+    /// or because it is one of the warm-up requests.
+    pub fn admits(&self, req: &RequestHeader) -> bool {
+        self.is_ready()
+            || req
+                .headers
+                .get(WARM_UP_HEADER)
+                .is_some_and(|token| token.as_bytes() == self.token.as_bytes())
+    }
+}
+
+/// The gate of `service`, shared by the proxy and its warm-up task.
+pub fn gate(service: &str) -> Arc<WarmUpGate> {
+    gates()
+        .lock()
+        .expect("warm-up gates poisoned")
+        .entry(service.to_string())
+        .or_insert_with(|| Arc::new(WarmUpGate::new()))
+        .clone()
+}
+
+/// Services that are still warming up.
+pub fn pending() -> Vec<String> {
+    gates()
+        .lock()
+        .expect("warm-up gates poisoned")
+        .iter()
+        .filter(|(_, gate)| !gate.is_ready())
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Base URL the warm-up requests are sent to, the first TCP listener of the
+/// service that does not redirect. Wildcard addresses are reached through loopback.
+pub(crate) fn base_url(config: &ProxyConfig) -> Option<String> {
+    config
+        .listeners
+        .list_cfgs
+        .iter()
+        .filter(|listener| !listener.redirect_to_https)
+        .find_map(|listener| match &listener.source {
+            ListenerKind::Tcp { addr, tls, .. } => {
+                let scheme = if tls.is_some() { "https" } else { "http" };
+
+                let addr = match addr.parse::<SocketAddr>() {
+                    Ok(mut sock) if sock.ip().is_unspecified() => {
+                        sock.set_ip(match sock.ip() {
+                            IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                            IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                        });
+                        sock.to_string()
+                    }
+                    _ => addr.clone(),
+                };
+
+                Some(format!("{scheme}://{addr}"))
+            }
+            ListenerKind::Uds(_) => None,
+        })
+}
+
+/// Host the warm-up requests claim, so a shared listener hands them to the
+/// service. Wildcards get a `warm-up` label.
+pub(crate) fn warm_up_host(config: &ProxyConfig) -> Option<String> {
+    let host = config.hosts.first()?;
+
+    Some(match host.strip_prefix("*.") {
+        Some(domain) => format!("warm-up.{domain}"),
+        None => host.clone(),
+    })
+}
+
+/// Sends the `warm-up` requests of a service through its own listener and
+/// opens its gate once they completed or the timeout elapsed.
+pub struct WarmUpService {
+    service: String,
+    config: WarmUpConfig,
+    base: Option<String>,
+    host: Option<String>,
+    gate: Arc<WarmUpGate>,
+    client: reqwest::Client,
+}
+
+impl WarmUpService {
+    pub fn new(proxy: &ProxyConfig, config: WarmUpConfig) -> Self {
+        Self {
+            service: proxy.name.clone(),
+            base: base_url(proxy),
+            host: warm_up_host(proxy),
+            gate: gate(&proxy.name),
+            config,
+            client: reqwest::Client::builder()
+                // The listener certificate is issued for the public name.
+                .danger_accept_invalid_certs(true)
+                .build()
+                .expect("warm-up client must build"),
+        }
+    }
+
+    /// Returns how many of the requests failed.
+    async fn run(&self, base: &str) -> usize {
+        let mut failed = 0;
+
+        for request in &self.config.requests {
+            let url = format!("{base}{}", request.path);
+
+            for _ in 0..request.repeat {
+                let response = loop {
+                    let mut builder = self
+                        .client
+                        .request(request.method.clone(), &url)
+                        .header(WARM_UP_HEADER, &self.gate.token);
+
+                    if let Some(host) = &self.host {
+                        builder = builder.header(HOST, host);
+                    }
+
+                    let result = builder.send().await;
+
+                    match result {
+                        Err(err) if err.is_connect() => tokio::time::sleep(CONNECT_RETRY).await,
+                        result => break result,
+                    }
+                };
+
+                match response {
+                    Ok(response) if !response.status().is_server_error() => {
+                        // Drain the body so the connection is reused.
+                        let _ = response.bytes().await;
+                    }
+                    Ok(response) => {
+                        failed += 1;
+                        tracing::warn!(
+                            "Warm-up request {} {url} answered {}",
+                            request.method,
+                            response.status()
+                        );
+                    }
+                    Err(err) => {
+                        failed += 1;
+                        tracing::warn!("Warm-up request {} {url} failed: {err}", request.method);
+                    }
+                }
+            }
+        }
+
+        failed
+    }
+}
+
+#[async_trait]
+impl BackgroundService for WarmUpService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let Some(base) = &self.base else {
+            tracing::warn!(
+                "Service {} has no TCP listener to warm up through, skipping warm-up",
+                self.service
+            );
+            self.gate.mark_ready();
+            return;
+        };
+
+        tracing::info!("Warming up service {}", self.service);
+
+        tokio::select! {
+            _ = shutdown.changed() => return,
+            result = tokio::time::timeout(self.config.timeout, self.run(base)) => match result {
+                Ok(0) => tracing::info!("Service {} warmed up", self.service),
+                Ok(failed) => tracing::warn!(
+                    "Service {} warmed up, {failed} request(s) failed",
+                    self.service
+                ),
+                Err(_) => tracing::warn!(
+                    "Warm-up of service {} timed out after {:?}",
+                    self.service,
+                    self.config.timeout
+                ),
+            },
+        }
+
+        self.gate.mark_ready();
+    }
+}
+
+pub fn warm_up_service(
+    proxy: &ProxyConfig,
+    config: WarmUpConfig,
+) -> Box<dyn pingora::services::Service> {
+    Box::new(background_service(
+        "motya-warm-up",
+        WarmUpService::new(proxy, config),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::{
+        connectors::Connectors,
+        listeners::{H2Limits, ListenerConfig, Listeners},
+    };
+
+    use super::*;
+
+    fn proxy(listeners: Vec<ListenerKind>) -> ProxyConfig {
+        ProxyConfig {
+            name: "api".into(),
+            listeners: Listeners {
+                list_cfgs: listeners
+                    .into_iter()
+                    .map(|source| ListenerConfig {
+                        source,
+                        unmatched_host: None,
+                        redirect_to_https: false,
+                        max_request_body_bytes: None,
+                        h2_limits: H2Limits::default(),
+                        tls_fingerprint: false,
+                    })
+                    .collect(),
+            },
+            hosts: Vec::new(),
+            connectors: Connectors {
+                upstreams: vec![],
+                anonymous_definitions: Default::default(),
+            },
+            warm_up: None,
+            error_pages: None,
+            labels: Default::default(),
+            maintenance: Default::default(),
+        }
+    }
+
+    fn tcp(addr: &str) -> ListenerKind {
+        ListenerKind::Tcp {
+            addr: addr.into(),
+            tls: None,
+            offer_h2: false,
+            h2c: false,
+        }
+    }
+
+    #[test]
+    fn test_base_url() {
+        assert_eq!(
+            base_url(&proxy(vec![tcp("0.0.0.0:8080")])).as_deref(),
+            Some("http://127.0.0.1:8080")
+        );
+        assert_eq!(
+            base_url(&proxy(vec![tcp("[::]:8080")])).as_deref(),
+            Some("http://[::1]:8080")
+        );
+        assert_eq!(
+            base_url(&proxy(vec![
+                ListenerKind::Uds("/tmp/motya.sock".into()),
+                tcp("10.0.0.1:80")
+            ]))
+            .as_deref(),
+            Some("http://10.0.0.1:80")
+        );
+        assert_eq!(
+            base_url(&proxy(vec![ListenerKind::Uds("/tmp/motya.sock".into())])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_warm_up_host() {
+        let mut config = proxy(vec![tcp("0.0.0.0:8080")]);
+        assert_eq!(warm_up_host(&config), None);
+
+        config.hosts = vec!["*.shop.example.com".into(), "shop.example.com".into()];
+        assert_eq!(
+            warm_up_host(&config).as_deref(),
+            Some("warm-up.shop.example.com")
+        );
+    }
+
+    #[test]
+    fn test_gate_admits_warm_up_requests() {
+        let gate = gate("test-gate");
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+
+        assert!(!gate.admits(&req));
+        assert!(pending().contains(&"test-gate".to_string()));
+
+        req.insert_header(WARM_UP_HEADER, "guess").unwrap();
+        assert!(!gate.admits(&req));
+
+        req.insert_header(WARM_UP_HEADER, gate.token.as_str())
+            .unwrap();
+        assert!(gate.admits(&req));
+
+        gate.mark_ready();
+        assert!(gate.admits(&RequestHeader::build("GET", b"/", None).unwrap()));
+        assert!(!pending().contains(&"test-gate".to_string()));
+    }
+}
@@ -1,15 +1,26 @@
 use std::{
-    collections::HashMap, convert::Infallible, marker::PhantomData, path::PathBuf, time::Duration,
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::Duration,
 };
 
 use futures_util::future::try_join_all;
 use miette::IntoDiagnostic;
-use notify::{Event, RecursiveMode, Watcher};
-use tokio::sync::mpsc;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, Notify};
 
 use crate::{
+    alerting, client_ip, events, fingerprint, flags,
     fs_adapter::TokioFs,
+    geoip, maintenance,
     proxy::{upstream_factory::UpstreamFactory, upstream_router::UpstreamRouter, SharedProxyState},
+    service_labels,
 };
 use motya_config::{
     common_types::definitions_table::DefinitionsTable,
@@ -19,6 +30,25 @@ use motya_config::{
     loader::{ConfigLoader, FileConfigLoaderProvider},
 };
 
+/// Set once a watcher runs, only it can reload on request.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+fn reload_requests() -> &'static Notify {
+    static REQUESTS: OnceLock<Notify> = OnceLock::new();
+    REQUESTS.get_or_init(Notify::new)
+}
+
+/// Makes the running watcher reload the configuration as if a file changed,
+/// `false` when no watcher runs, e.g. with the `http` provider.
+pub fn request_reload() -> bool {
+    if !RUNNING.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    reload_requests().notify_one();
+    true
+}
+
 pub struct ConfigWatcher<
     Cs: ConfigSource = FileCollector<TokioFs>,
     TConfigLoader: FileConfigLoaderProvider + Clone = ConfigLoader<Cs>,
@@ -29,9 +59,22 @@ pub struct ConfigWatcher<
     watch_entry_path: PathBuf,
     upstream_factory: UpstreamFactory,
     config_loader: TConfigLoader,
+    watch_files: bool,
     phantom: PhantomData<Cs>,
 }
 
+#[derive(Default)]
+struct WatchedSources {
+    files: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl WatchedSources {
+    fn is_affected_by(&self, event: &Event) -> bool {
+        event.paths.iter().any(|path| self.files.contains(path))
+    }
+}
+
 impl<Cs: ConfigSource, T: FileConfigLoaderProvider + Clone> ConfigWatcher<Cs, T> {
     pub fn new(
         config: Config,
@@ -47,15 +90,44 @@ impl<Cs: ConfigSource, T: FileConfigLoaderProvider + Clone> ConfigWatcher<Cs, T>
             upstream_factory,
             config_loader,
             active_proxies: HashMap::default(),
+            watch_files: true,
             phantom: PhantomData,
         }
     }
 
+    /// Whether [`ConfigWatcher::watch`] follows the files, it only reloads on
+    /// request otherwise.
+    pub fn with_file_watching(mut self, watch_files: bool) -> Self {
+        self.watch_files = watch_files;
+        self
+    }
+
     pub fn insert_proxy_state(&mut self, name: String, state: SharedProxyState) {
         self.active_proxies.insert(name, state);
     }
 
+    /// Watches every file of the configuration (the entry point and all of its
+    /// includes) and reloads the configuration when any of them changes.
+    ///
+    /// Directories are watched instead of the files themselves, so editors that
+    /// save by replacing the file are still picked up. The set of watched files is
+    /// refreshed after every reload, following added or removed includes.
+    /// Reloads asked for with [`request_reload`] are taken either way.
     pub async fn watch(&mut self) -> Result<Infallible, Box<dyn std::error::Error + Send + Sync>> {
+        if !self.watch_files {
+            tracing::info!("Config file watching is disabled, reloading on request only");
+            RUNNING.store(true, Ordering::Relaxed);
+
+            loop {
+                reload_requests().notified().await;
+                tracing::info!("Reload requested through the admin API");
+
+                if let Err(err) = self.reload().await {
+                    tracing::error!("fail on reload: {err}");
+                }
+            }
+        }
+
         tracing::info!("Starting watcher on: {:?}", &self.watch_entry_path);
 
         let (tx, mut rx) = mpsc::channel(100);
@@ -68,22 +140,70 @@ impl<Cs: ConfigSource, T: FileConfigLoaderProvider + Clone> ConfigWatcher<Cs, T>
             }
         })?;
 
-        watcher.watch(&self.watch_entry_path, RecursiveMode::Recursive)?;
+        let mut sources = WatchedSources::default();
+        self.sync_sources(&mut watcher, &mut sources).await?;
+
+        RUNNING.store(true, Ordering::Relaxed);
 
         loop {
-            if let Some(_event) = rx.recv().await {
-                tokio::time::sleep(Duration::from_millis(100)).await;
+            tokio::select! {
+                Some(event) = rx.recv() => {
+                    if !sources.is_affected_by(&event) {
+                        continue;
+                    }
 
-                while rx.try_recv().is_ok() {}
+                    tokio::time::sleep(Duration::from_millis(100)).await;
 
-                match self.reload().await {
-                    Ok(_) => {}
-                    Err(err) => tracing::error!("fail on reload: {err}"),
+                    while rx.try_recv().is_ok() {}
                 }
+                () = reload_requests().notified() => {
+                    tracing::info!("Reload requested through the admin API");
+                }
+            }
+
+            match self.reload().await {
+                Ok(_) => {}
+                Err(err) => tracing::error!("fail on reload: {err}"),
+            }
+
+            if let Err(err) = self.sync_sources(&mut watcher, &mut sources).await {
+                tracing::warn!("Failed to refresh watched config files: {err}");
             }
         }
     }
 
+    async fn sync_sources(
+        &self,
+        watcher: &mut RecommendedWatcher,
+        sources: &mut WatchedSources,
+    ) -> miette::Result<()> {
+        let mut files = HashSet::new();
+        for path in Cs::default().sources(self.watch_entry_path.clone()).await? {
+            files.insert(tokio::fs::canonicalize(&path).await.unwrap_or(path));
+        }
+
+        let dirs: HashSet<PathBuf> = files
+            .iter()
+            .filter_map(|file| file.parent().map(Path::to_path_buf))
+            .collect();
+
+        for dir in dirs.difference(&sources.dirs) {
+            tracing::debug!("Watching config directory: {:?}", dir);
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .into_diagnostic()?;
+        }
+
+        for dir in sources.dirs.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+
+        sources.files = files;
+        sources.dirs = dirs;
+
+        Ok(())
+    }
+
     async fn reload(&mut self) -> miette::Result<()> {
         tracing::info!("Reloading configuration...");
 
@@ -95,62 +215,121 @@ impl<Cs: ConfigSource, T: FileConfigLoaderProvider + Clone> ConfigWatcher<Cs, T>
             .load_entry_point(Some(self.watch_entry_path.clone()), &mut new_definitions)
             .await
         {
-            Ok(Some(cfg)) => {
-                self.table = new_definitions;
-
-                let old_proxies: HashMap<&String, &ProxyConfig> = self
-                    .config
-                    .basic_proxies
-                    .iter()
-                    .map(|p| (&p.name, p))
-                    .collect();
-
-                let new_proxies: HashMap<&String, &ProxyConfig> =
-                    cfg.basic_proxies.iter().map(|p| (&p.name, p)).collect();
-
-                for (name, new) in new_proxies.iter() {
-                    if let Some(old) = old_proxies.get(name) {
-                        if old.connectors != new.connectors {
-                            if let Some(active_config) = self.active_proxies.get(*name) {
-                                println!("Connectors changed for proxy '{}'", new.name);
-                                let upstreams = try_join_all(
-                                    new.connectors
-                                        .upstreams
-                                        .clone()
-                                        .into_iter()
-                                        .map(|cfg| self.upstream_factory.create_context(cfg))
-                                        .collect::<Vec<_>>(),
-                                )
-                                .await?;
-
-                                let router = UpstreamRouter::build(upstreams).into_diagnostic()?;
-
-                                active_config.swap(router.into());
-                            }
-                            // logic...
-                        }
-                    } else {
-                        // println!("New proxy detected: '{}'", new.name);
-                    }
-                }
-            }
+            Ok(Some(cfg)) => self.apply(cfg, new_definitions).await?,
             Ok(None) => {
                 tracing::warn!("Failed to load config: invariant violated: path not exist. Keeping old configuration.");
             }
             Err(e) => {
                 tracing::warn!("Failed to reload config: {}. Keeping old configuration.", e);
+                events::emit(events::Event::ConfigRejected {
+                    reason: e.to_string(),
+                });
             }
         }
 
         Ok(())
     }
+
+    /// Swaps the routers of every service whose connectors differ in `cfg`.
+    ///
+    /// All affected routers are built before any of them is swapped, so on error
+    /// every service stays on the previous configuration.
+    pub async fn apply(
+        &mut self,
+        cfg: Config,
+        new_definitions: DefinitionsTable,
+    ) -> miette::Result<()> {
+        let reject = |err: &miette::Report| {
+            events::emit(events::Event::ConfigRejected {
+                reason: err.to_string(),
+            })
+        };
+
+        let databases = geoip::Databases::open(new_definitions.get_geoip()).inspect_err(reject)?;
+        let changed = self.swap_routers(&cfg).await.inspect_err(reject)?;
+
+        fingerprint::apply(cfg.fingerprint.as_deref());
+        flags::apply(&cfg.flags);
+        service_labels::apply(&cfg.basic_proxies);
+        maintenance::apply(&cfg.basic_proxies);
+        alerting::apply(&cfg.basic_proxies);
+        client_ip::apply(cfg.client_ip_privacy, cfg.trusted_proxies.clone());
+        geoip::apply(databases);
+
+        self.table = new_definitions;
+        self.config = cfg;
+
+        events::emit(events::Event::ConfigReloaded { services: changed });
+
+        Ok(())
+    }
+
+    /// Returns the names of the proxies whose router was swapped.
+    async fn swap_routers(&self, cfg: &Config) -> miette::Result<Vec<String>> {
+        let old_proxies: HashMap<&String, &ProxyConfig> = self
+            .config
+            .basic_proxies
+            .iter()
+            .map(|p| (&p.name, p))
+            .collect();
+
+        let mut swaps = Vec::new();
+
+        for new in &cfg.basic_proxies {
+            let Some(old) = old_proxies.get(&new.name) else {
+                tracing::warn!(
+                    "New proxy '{}' requires a restart to start listening",
+                    new.name
+                );
+                continue;
+            };
+
+            if old.hosts != new.hosts {
+                tracing::warn!(
+                    "Hosts of proxy '{}' changed, this requires a restart",
+                    new.name
+                );
+            }
+
+            if old.connectors == new.connectors {
+                continue;
+            }
+
+            if let Some(active_config) = self.active_proxies.get(&new.name) {
+                tracing::info!("Connectors changed for proxy '{}'", new.name);
+                let factory = self.upstream_factory.clone().with_service(&new.name);
+                let upstreams = try_join_all(
+                    new.connectors
+                        .upstreams
+                        .clone()
+                        .into_iter()
+                        .map(|cfg| factory.create_context(cfg))
+                        .collect::<Vec<_>>(),
+                )
+                .await?;
+
+                let router = UpstreamRouter::build(upstreams).into_diagnostic()?;
+
+                swaps.push((&new.name, active_config, router));
+            }
+        }
+
+        let mut changed = Vec::with_capacity(swaps.len());
+
+        for (name, active_config, router) in swaps {
+            active_config.swap(router.into());
+            changed.push(name.clone());
+        }
+
+        Ok(changed)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
-    use http::{uri::PathAndQuery, StatusCode};
+    use http::{uri::PathAndQuery, Method, StatusCode};
     use miette::Result;
     use tempfile::env::temp_dir;
     use tokio::sync::Mutex;
@@ -196,11 +375,23 @@ mod tests {
         let new_proxy_config = Config {
             basic_proxies: vec![ProxyConfig {
                 listeners: Listeners { list_cfgs: vec![] },
+                hosts: Vec::new(),
                 connectors: Connectors {
                     anonymous_definitions: Default::default(),
                     upstreams: vec![UpstreamContextConfig {
                         chains: vec![],
                         lb_options: Default::default(),
+                        shaping: None,
+                        timeouts: None,
+                        retry: None,
+                        compression: None,
+                        max_request_body_bytes: None,
+                        buffering: None,
+                        record_har: None,
+                        cache: None,
+                        methods: None,
+                        flag: None,
+                        active: None,
                         upstream: UpstreamConfig::Static(SimpleResponseConfig {
                             http_code: StatusCode::OK,
                             response_body: "ver 1".to_string(),
@@ -209,6 +400,10 @@ mod tests {
                     }],
                 },
                 name: "Test".to_string(),
+                warm_up: None,
+                error_pages: None,
+                labels: Default::default(),
+                maintenance: Default::default(),
             }],
             ..Config::default()
         };
@@ -248,7 +443,9 @@ mod tests {
         watcher.reload().await.expect("Reload failed");
 
         let router = tracked_router.load();
-        let first_version = router.get_upstream_by_path("/").unwrap();
+        let first_version = router
+            .get_upstream("/", &Method::GET, &flags::Flags::default())
+            .unwrap();
         let UpstreamConfig::Static(response) = &first_version.upstream else {
             unreachable!()
         };
@@ -270,7 +467,9 @@ mod tests {
         watcher.reload().await.expect("Reload failed");
 
         let router = tracked_router.load();
-        let second_version = router.get_upstream_by_path("/").unwrap();
+        let second_version = router
+            .get_upstream("/", &Method::GET, &flags::Flags::default())
+            .unwrap();
         let UpstreamConfig::Static(response) = &second_version.upstream else {
             unreachable!()
         };
@@ -0,0 +1,263 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header::AUTHORIZATION, Method, StatusCode};
+use pingora::{server::Server, upstreams::peer::HttpPeer, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::{ProxyHttp, Session};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use crate::{
+    events::{self, Event},
+    proxy::watcher::file_watcher::ConfigWatcher,
+};
+use motya_config::{
+    common_types::{definitions_table::DefinitionsTable, system_data::HttpProviderConfig},
+    loader::load_document,
+};
+
+/// Upper bound for a pushed configuration document.
+const MAX_DOCUMENT_SIZE: usize = 4 * 1024 * 1024;
+
+const PUSHED_DOCUMENT_NAME: &str = "pushed.kdl";
+
+/// Location of the last successfully applied pushed config for `entry_path`.
+///
+/// `/etc/motya/entry.kdl` is persisted as `/etc/motya/entry.last-good.kdl`.
+pub fn last_good_path(entry_path: &Path) -> PathBuf {
+    entry_path.with_extension("last-good.kdl")
+}
+
+/// Admin listener of the `http` config provider.
+///
+/// Accepts `POST` requests carrying a whole KDL document at the configured path,
+/// validates it with the regular parser and applies it through the [`ConfigWatcher`].
+/// With a `token`, requests without it as their bearer token are refused.
+pub struct ConfigPushService {
+    path: String,
+    persist_path: Option<PathBuf>,
+    token: Option<String>,
+    watcher: Arc<Mutex<ConfigWatcher>>,
+}
+
+pub fn config_push_service(
+    provider: &HttpProviderConfig,
+    entry_path: &Path,
+    watcher: ConfigWatcher,
+    server: &Server,
+) -> Box<dyn pingora::services::Service> {
+    let push = ConfigPushService {
+        path: provider.path.path().to_string(),
+        persist_path: provider.persist.then(|| last_good_path(entry_path)),
+        token: provider.token.clone(),
+        watcher: Arc::new(Mutex::new(watcher)),
+    };
+
+    let mut service = pingora_proxy::http_proxy_service_with_name(
+        &server.configuration,
+        push,
+        "motya-config-push",
+    );
+
+    service.add_tcp(&provider.address.to_string());
+
+    Box::new(service)
+}
+
+impl ConfigPushService {
+    async fn handle(&self, session: &mut Session) -> (StatusCode, String) {
+        let req = session.req_header();
+
+        if req.uri.path() != self.path {
+            return (StatusCode::NOT_FOUND, "not found".to_string());
+        }
+
+        if !self.authorized(req) {
+            return (StatusCode::UNAUTHORIZED, "unauthorized".to_string());
+        }
+
+        if req.method != Method::POST {
+            return (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "only POST is supported".to_string(),
+            );
+        }
+
+        let mut body = Vec::new();
+        loop {
+            match session.downstream_session.read_request_body().await {
+                Ok(Some(chunk)) => {
+                    if body.len() + chunk.len() > MAX_DOCUMENT_SIZE {
+                        return (
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            format!("config document exceeds {MAX_DOCUMENT_SIZE} bytes"),
+                        );
+                    }
+                    body.extend_from_slice(&chunk);
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        format!("failed to read body: {err}"),
+                    )
+                }
+            }
+        }
+
+        let Ok(source) = String::from_utf8(body) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                "config document must be valid UTF-8".to_string(),
+            );
+        };
+
+        let mut definitions = DefinitionsTable::new_with_global();
+
+        let config = match load_document(&source, PUSHED_DOCUMENT_NAME, &mut definitions) {
+            Ok(config) => config,
+            Err(err) => {
+                tracing::warn!("Rejected pushed config: {err}");
+                events::emit(Event::ConfigRejected {
+                    reason: err.to_string(),
+                });
+                return (StatusCode::UNPROCESSABLE_ENTITY, report_to_string(&err));
+            }
+        };
+
+        if let Err(err) = self.watcher.lock().await.apply(config, definitions).await {
+            tracing::warn!("Failed to apply pushed config: {err}");
+            return (StatusCode::UNPROCESSABLE_ENTITY, report_to_string(&err));
+        }
+
+        tracing::info!("Applied pushed config");
+
+        if let Some(path) = &self.persist_path {
+            if let Err(err) = persist(path, &source).await {
+                tracing::error!("Failed to persist pushed config to {:?}: {err}", path);
+                return (StatusCode::OK, format!("applied, but not persisted: {err}"));
+            }
+        }
+
+        (StatusCode::OK, "applied".to_string())
+    }
+
+    /// Whether `req` carries the configured bearer token, if there is one.
+    fn authorized(&self, req: &RequestHeader) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+
+        req.headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Compares without returning at the first difference, so the time taken
+/// does not tell how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn report_to_string(err: &miette::Report) -> String {
+    match err.help() {
+        Some(help) => format!("{err}\n{help}"),
+        None => err.to_string(),
+    }
+}
+
+/// Replaces `path` with `source` so that a crash leaves either the old or the
+/// new document, both on disk before the push is answered.
+async fn persist(path: &Path, source: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.write_all(source.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    tokio::fs::rename(&tmp, path).await?;
+
+    // The rename itself is only durable once the directory is synced.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+#[async_trait]
+impl ProxyHttp for ConfigPushService {
+    type CTX = ();
+
+    fn new_ctx(&self) -> Self::CTX {}
+
+    async fn upstream_peer(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Self::CTX,
+    ) -> Result<Box<HttpPeer>> {
+        // Every request is answered in `request_filter`.
+        Err(pingora::Error::new_str("Request Failed"))
+    }
+
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut Self::CTX) -> Result<bool> {
+        let (status, body) = self.handle(session).await;
+
+        let mut response = ResponseHeader::build(status, Some(2))?;
+        response.insert_header("Content-Type", "text/plain; charset=utf-8")?;
+        response.insert_header("Content-Length", body.len().to_string())?;
+
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(Bytes::from(body), true)
+            .await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entry.last-good.kdl");
+
+        persist(&path, "services {}").await.unwrap();
+        persist(&path, "services { A {} }").await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "services { A {} }");
+        assert!(!dir.path().join("entry.last-good.kdl.tmp").exists());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(!constant_time_eq(b"s3cret", b"s3creT"));
+        assert!(!constant_time_eq(b"s3cre", b"s3cret"));
+        assert!(!constant_time_eq(b"", b"s3cret"));
+    }
+
+    #[test]
+    fn test_last_good_path() {
+        assert_eq!(
+            last_good_path(Path::new("/etc/motya/entry.kdl")),
+            PathBuf::from("/etc/motya/entry.last-good.kdl")
+        );
+    }
+}
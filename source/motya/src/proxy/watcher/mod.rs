@@ -1,2 +1,3 @@
 mod diffs;
 pub mod file_watcher;
+pub mod http_provider;
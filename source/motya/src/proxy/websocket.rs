@@ -0,0 +1,115 @@
+use std::sync::OnceLock;
+
+use pingora_http::RequestHeader;
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+
+struct WebSocketMetrics {
+    active: IntGaugeVec,
+    upgraded: IntCounterVec,
+    refused: IntCounterVec,
+}
+
+fn metrics() -> &'static WebSocketMetrics {
+    static METRICS: OnceLock<WebSocketMetrics> = OnceLock::new();
+
+    METRICS.get_or_init(|| WebSocketMetrics {
+        active: register_int_gauge_vec!(
+            "motya_websocket_connections_active",
+            "WebSocket connections currently open",
+            &["route"]
+        )
+        .expect("metric should register once"),
+        upgraded: register_int_counter_vec!(
+            "motya_websocket_connections_total",
+            "WebSocket handshakes accepted by the upstream",
+            &["route"]
+        )
+        .expect("metric should register once"),
+        refused: register_int_counter_vec!(
+            "motya_websocket_refused_total",
+            "WebSocket handshakes refused by the proxy",
+            &["route"]
+        )
+        .expect("metric should register once"),
+    })
+}
+
+/// Whether the request asks to switch to the WebSocket protocol.
+pub fn is_upgrade(req: &RequestHeader) -> bool {
+    let has_token = |name: http::header::HeaderName, token: &str| {
+        req.headers.get_all(name).iter().any(|value| {
+            value.to_str().is_ok_and(|value| {
+                value
+                    .split(',')
+                    .any(|part| part.trim().eq_ignore_ascii_case(token))
+            })
+        })
+    };
+
+    has_token(http::header::UPGRADE, "websocket") && has_token(http::header::CONNECTION, "upgrade")
+}
+
+pub fn opened(route: &str) {
+    let metrics = metrics();
+    metrics.active.with_label_values(&[route]).inc();
+    metrics.upgraded.with_label_values(&[route]).inc();
+}
+
+pub fn closed(route: &str) {
+    metrics().active.with_label_values(&[route]).dec();
+}
+
+pub fn refused(route: &str) {
+    metrics().refused.with_label_values(&[route]).inc();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/ws", None).unwrap();
+        for (name, value) in headers {
+            req.append_header(name.to_string(), *value).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_detects_upgrade() {
+        assert!(is_upgrade(&request(&[
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade")
+        ])));
+        assert!(is_upgrade(&request(&[
+            ("Upgrade", "WebSocket"),
+            ("Connection", "keep-alive, Upgrade")
+        ])));
+
+        assert!(!is_upgrade(&request(&[("Upgrade", "websocket")])));
+        assert!(!is_upgrade(&request(&[
+            ("Upgrade", "h2c"),
+            ("Connection", "Upgrade")
+        ])));
+    }
+
+    #[test]
+    fn test_counters() {
+        opened("/test-counters");
+        opened("/test-counters");
+        closed("/test-counters");
+
+        let metrics = metrics();
+        assert_eq!(
+            metrics.active.with_label_values(&["/test-counters"]).get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .upgraded
+                .with_label_values(&["/test-counters"])
+                .get(),
+            2
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::StatusCode;
+use pingora::{Error, ErrorType, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::{FailToProxy, Session};
+
+use motya_config::internal::WhenDownConfig;
+
+/// Error of a balanced route that has no backend left to pick.
+pub const ALL_BACKENDS_DOWN: ErrorType = ErrorType::Custom("All backends are down");
+
+pub fn all_backends_down() -> Box<Error> {
+    Error::explain(ALL_BACKENDS_DOWN, "Unable to determine backend")
+}
+
+/// The `when-down` response of a route.
+#[derive(Debug, Clone)]
+pub struct WhenDown {
+    status: StatusCode,
+    body: Bytes,
+    retry_after: Option<Duration>,
+}
+
+impl From<WhenDownConfig> for WhenDown {
+    fn from(value: WhenDownConfig) -> Self {
+        Self {
+            status: value.status,
+            body: Bytes::from(value.body),
+            retry_after: value.retry_after,
+        }
+    }
+}
+
+impl WhenDown {
+    fn header(&self) -> Result<ResponseHeader> {
+        let mut response = ResponseHeader::build(self.status, Some(3))?;
+        response.insert_header("Content-Type", "text/plain")?;
+        response.insert_header("Content-Length", self.body.len().to_string())?;
+
+        if let Some(retry_after) = self.retry_after {
+            response.insert_header("Retry-After", retry_after.as_secs().to_string())?;
+        }
+
+        Ok(response)
+    }
+
+    /// Answers with the configured response, unless the response already
+    /// started.
+    pub async fn respond(&self, session: &mut Session) -> FailToProxy {
+        if session.response_written().is_none() {
+            let result = async {
+                session
+                    .write_response_header(Box::new(self.header()?), false)
+                    .await?;
+                session
+                    .write_response_body(Some(self.body.clone()), true)
+                    .await
+            }
+            .await;
+
+            if let Err(err) = result {
+                tracing::error!("Failed to send 'when-down' response to downstream: {err}");
+            }
+        }
+
+        FailToProxy {
+            error_code: self.status.as_u16(),
+            can_reuse_downstream: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let when_down = WhenDown::from(WhenDownConfig {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            body: "down for maintenance".into(),
+            retry_after: Some(Duration::from_secs(30)),
+        });
+
+        let header = when_down.header().unwrap();
+        assert_eq!(header.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(header.headers["Content-Length"], "20");
+        assert_eq!(header.headers["Retry-After"], "30");
+
+        let header = WhenDown::from(WhenDownConfig {
+            status: StatusCode::OK,
+            body: String::new(),
+            retry_after: None,
+        })
+        .header()
+        .unwrap();
+        assert_eq!(header.status, StatusCode::OK);
+        assert!(!header.headers.contains_key("Retry-After"));
+
+        assert_eq!(all_backends_down().etype(), &ALL_BACKENDS_DOWN);
+    }
+}
@@ -0,0 +1,425 @@
+//! `motya replay`: requests of HAR recordings, e.g. those of `record-har`,
+//! sent again to a target server, in their recorded timing or at a steady rate.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+use miette::IntoDiagnostic;
+use reqwest::{Method, Url};
+use serde_json::Value;
+
+use crate::self_test::percentile;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers that belong to the recorded connection, not to the request.
+const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "te",
+    "upgrade",
+];
+
+/// A request of a recording.
+#[derive(Debug, Clone, PartialEq)]
+struct Recorded {
+    /// When it was sent, in milliseconds since the Unix epoch.
+    started: i64,
+    method: Method,
+    /// Path and query.
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+pub async fn run(har: &[PathBuf], target: &str, max_rate: Option<f64>) -> miette::Result<()> {
+    let target = Url::parse(target)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or_else(|| miette::miette!("'{target}' is not an http or https URL"))?;
+
+    if max_rate.is_some_and(|rate| rate.is_nan() || rate <= 0.0) {
+        return Err(miette::miette!("--max-rate must be greater than 0"));
+    }
+
+    let mut requests = Vec::new();
+    for path in har_files(har)? {
+        requests.extend(load(&path)?);
+    }
+    requests.sort_by_key(|request| request.started);
+
+    if requests.is_empty() {
+        return Err(miette::miette!("The recordings hold no requests"));
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .into_diagnostic()?;
+    let base = target.as_str().trim_end_matches('/').to_string();
+
+    tracing::info!("Replaying {} requests to {base}", requests.len());
+
+    let start = Instant::now();
+    let schedule = schedule(&requests, max_rate);
+
+    let sent = requests.into_iter().zip(schedule).map(|(request, at)| {
+        let client = client.clone();
+        let url = format!("{base}{}", request.path);
+
+        async move {
+            tokio::time::sleep_until((start + at).into()).await;
+
+            let mut builder = client.request(request.method, url);
+            for (name, value) in request.headers {
+                builder = builder.header(name, value);
+            }
+
+            let sent = Instant::now();
+            let response = match builder.body(request.body).send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    response.bytes().await.map(|_| status)
+                }
+                Err(err) => Err(err),
+            };
+
+            (sent.elapsed(), response.map(|status| status.as_u16()))
+        }
+    });
+
+    // Requests are spawned so a slow answer does not hold back the next ones.
+    let sent: Vec<_> = sent.map(tokio::spawn).collect();
+
+    let mut report = Report::default();
+    for (latency, status) in join_all(sent).await.into_iter().flatten() {
+        match status {
+            Ok(status) => {
+                report.latencies.push(latency);
+                *report.statuses.entry(status).or_default() += 1;
+            }
+            Err(_) => report.failures += 1,
+        }
+    }
+    report.elapsed = start.elapsed();
+
+    println!("{}", report.summary());
+
+    Ok(())
+}
+
+/// `paths`, with directories replaced by the `.har` files in them.
+fn har_files(paths: &[PathBuf]) -> miette::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut in_dir = std::fs::read_dir(path)
+            .into_diagnostic()?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .into_diagnostic()?;
+        // Files still being written by `record-har` end in `.partial`.
+        in_dir.retain(|file| file.extension().is_some_and(|ext| ext == "har"));
+        in_dir.sort();
+
+        files.extend(in_dir);
+    }
+
+    Ok(files)
+}
+
+fn load(path: &Path) -> miette::Result<Vec<Recorded>> {
+    let har: Value = std::fs::read(path)
+        .into_diagnostic()
+        .and_then(|content| serde_json::from_slice(&content).into_diagnostic())
+        .map_err(|e| miette::miette!("Failed to read HAR file {path:?}: {e}"))?;
+
+    let Some(entries) = har["log"]["entries"].as_array() else {
+        return Err(miette::miette!("{path:?} has no 'log.entries'"));
+    };
+
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            recorded(entry).map_err(|e| miette::miette!("Entry {i} of {path:?}: {e}"))
+        })
+        .collect()
+}
+
+fn recorded(entry: &Value) -> Result<Recorded, String> {
+    let started = entry["startedDateTime"]
+        .as_str()
+        .and_then(parse_iso8601)
+        .ok_or("'startedDateTime' is not an ISO 8601 date")?;
+
+    let request = &entry["request"];
+
+    let method = request["method"]
+        .as_str()
+        .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+        .ok_or("'method' is missing or invalid")?;
+
+    let url = request["url"]
+        .as_str()
+        .and_then(|url| Url::parse(url).ok())
+        .ok_or("'url' is missing or invalid")?;
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+
+    let headers = request["headers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|header| Some((header["name"].as_str()?, header["value"].as_str()?)))
+        // HTTP/2 recordings of browsers list pseudo headers like `:authority`.
+        .filter(|(name, _)| {
+            !name.starts_with(':') && !SKIPPED_HEADERS.contains(&&*name.to_ascii_lowercase())
+        })
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+    let post_data = &request["postData"];
+    let text = post_data["text"].as_str().unwrap_or_default();
+    let body = if post_data["encoding"] == "base64" {
+        openssl::base64::decode_block(text).map_err(|_| "'postData' is not valid base64")?
+    } else {
+        text.as_bytes().to_vec()
+    };
+
+    Ok(Recorded {
+        started,
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+/// When each of `requests`, sorted by their start, is sent from the start of
+/// the replay.
+fn schedule(requests: &[Recorded], max_rate: Option<f64>) -> Vec<Duration> {
+    let first = requests.first().map_or(0, |request| request.started);
+
+    requests
+        .iter()
+        .enumerate()
+        .map(|(i, request)| match max_rate {
+            Some(rate) => Duration::from_secs_f64(i as f64 / rate),
+            None => Duration::from_millis((request.started - first) as u64),
+        })
+        .collect()
+}
+
+/// Milliseconds since the Unix epoch of a date like `2024-03-01T12:00:00.123Z`
+/// or `2024-03-01T14:00:00+02:00`.
+fn parse_iso8601(date: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = date.get(range)?;
+        digits.bytes().all(|b| b.is_ascii_digit()).then_some(())?;
+        digits.parse().ok()
+    };
+
+    if date.get(4..5)? != "-" || date.get(7..8)? != "-" || date.get(13..14)? != ":" {
+        return None;
+    }
+    if !matches!(date.get(10..11)?, "T" | "t" | " ") || date.get(16..17)? != ":" {
+        return None;
+    }
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let mut rest = &date[19..];
+
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        // Only milliseconds are kept of finer fractions.
+        let kept = &fraction[..digits.min(3)];
+        millis = kept.parse::<i64>().ok()? * 10_i64.pow(3 - kept.len() as u32);
+        rest = &fraction[digits..];
+    }
+
+    let offset_minutes = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = rest[1..].split_once(':')?;
+            if hours.len() != 2 || minutes.len() != 2 {
+                return None;
+            }
+            sign * (hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?)
+        }
+    };
+
+    // Days since 1970-01-01 of the proleptic Gregorian date.
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let shifted_month = (month + 9) % 12;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset_minutes * 60;
+    Some(seconds * 1000 + millis)
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    /// One per answered request.
+    latencies: Vec<Duration>,
+    /// Answered requests by status.
+    statuses: BTreeMap<u16, usize>,
+    /// Not answered at all.
+    failures: usize,
+    elapsed: Duration,
+}
+
+impl Report {
+    fn summary(mut self) -> String {
+        self.latencies.sort_unstable();
+
+        let statuses = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{status}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!(
+            "{requests} requests replayed in {elapsed:.2?}, {failures} failed\n  \
+             statuses {statuses}\n  \
+             latency p50 {p50:.2?}, p90 {p90:.2?}, p99 {p99:.2?}, max {max:.2?}",
+            requests = self.latencies.len() + self.failures,
+            elapsed = self.elapsed,
+            failures = self.failures,
+            p50 = percentile(&self.latencies, 0.5),
+            p90 = percentile(&self.latencies, 0.9),
+            p99 = percentile(&self.latencies, 0.99),
+            max = percentile(&self.latencies, 1.0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_iso8601() {
+        assert_eq!(parse_iso8601("1970-01-01T00:00:00.000Z"), Some(0));
+        assert_eq!(
+            parse_iso8601("2024-03-01T12:00:00.123Z"),
+            Some(1_709_294_400_123)
+        );
+        assert_eq!(
+            parse_iso8601("2024-03-01T14:00:00.1234567+02:00"),
+            Some(1_709_294_400_123)
+        );
+        assert_eq!(parse_iso8601("2000-02-29T00:00:00Z"), Some(951_782_400_000));
+        assert_eq!(parse_iso8601("2024-03-01 12:00:00"), None);
+        assert_eq!(parse_iso8601("2024-13-01T12:00:00Z"), None);
+        assert_eq!(parse_iso8601("yesterday"), None);
+    }
+
+    #[test]
+    fn test_recorded() {
+        let entry = json!({
+            "startedDateTime": "2024-03-01T12:00:00.123Z",
+            "request": {
+                "method": "POST",
+                "url": "https://example.com/api/items?page=2",
+                "headers": [
+                    { "name": ":authority", "value": "example.com" },
+                    { "name": "Host", "value": "example.com" },
+                    { "name": "content-type", "value": "application/octet-stream" },
+                    { "name": "Content-Length", "value": "2" },
+                ],
+                "postData": { "mimeType": "application/octet-stream", "text": "/wA=", "encoding": "base64" },
+            },
+        });
+
+        let request = recorded(&entry).unwrap();
+        assert_eq!(request.started, 1_709_294_400_123);
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(request.path, "/api/items?page=2");
+        assert_eq!(
+            request.headers,
+            vec![(
+                "content-type".to_string(),
+                "application/octet-stream".to_string()
+            )]
+        );
+        assert_eq!(request.body, vec![0xff, 0x00]);
+
+        assert!(recorded(&json!({ "request": {} })).is_err());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let at = |started| Recorded {
+            started,
+            method: Method::GET,
+            path: "/".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        let requests = [at(1_000), at(1_250), at(3_000)];
+
+        assert_eq!(
+            schedule(&requests, None),
+            [0, 250, 2000].map(Duration::from_millis)
+        );
+        assert_eq!(
+            schedule(&requests, Some(10.0)),
+            [0, 100, 200].map(Duration::from_millis)
+        );
+    }
+
+    #[test]
+    fn test_har_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["2-b.har", "1-a.har", ".3-c.har.partial", "notes.txt"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let single = PathBuf::from("other.har");
+
+        assert_eq!(
+            har_files(&[dir.path().to_path_buf(), single.clone()]).unwrap(),
+            vec![
+                dir.path().join("1-a.har"),
+                dir.path().join("2-b.har"),
+                single
+            ]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Metrics of the Tokio runtimes serving requests, per worker thread, so a
+//! single saturated worker shows up instead of being averaged away.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, Once, OnceLock},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use pingora::{
+    server::ShutdownWatch,
+    services::background::{background_service, BackgroundService},
+};
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_gauge_vec, GaugeVec, HistogramVec,
+    IntGaugeVec,
+};
+use tokio::runtime::Handle;
+
+/// How often the runtimes are sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Blocking a worker thread for longer than this is logged.
+const BLOCKING_WARN: Duration = Duration::from_millis(100);
+
+struct RuntimeMetrics {
+    workers: IntGaugeVec,
+    alive_tasks: IntGaugeVec,
+    queued_tasks: IntGaugeVec,
+    utilization: GaugeVec,
+    blocking: HistogramVec,
+}
+
+fn metrics() -> &'static RuntimeMetrics {
+    static METRICS: OnceLock<RuntimeMetrics> = OnceLock::new();
+
+    METRICS.get_or_init(|| RuntimeMetrics {
+        workers: register_int_gauge_vec!(
+            "motya_runtime_workers",
+            "Worker threads of the runtime serving a set of listeners",
+            &["runtime"]
+        )
+        .expect("metric should register once"),
+        alive_tasks: register_int_gauge_vec!(
+            "motya_runtime_alive_tasks",
+            "Tasks alive on the runtime, connections being served among them",
+            &["runtime"]
+        )
+        .expect("metric should register once"),
+        queued_tasks: register_int_gauge_vec!(
+            "motya_runtime_queued_tasks",
+            "Tasks waiting in the global queue of the runtime for a free worker",
+            &["runtime"]
+        )
+        .expect("metric should register once"),
+        utilization: register_gauge_vec!(
+            "motya_runtime_worker_utilization",
+            "Share of the last sampling interval a worker thread spent busy, from 0 to 1",
+            &["runtime", "worker"]
+        )
+        .expect("metric should register once"),
+        blocking: register_histogram_vec!(
+            "motya_runtime_blocking_seconds",
+            "Time a worker thread was blocked by a synchronous operation",
+            &["operation"],
+            vec![0.0001, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0]
+        )
+        .expect("metric should register once"),
+    })
+}
+
+/// Runtimes registered by [RuntimeTracker::track], by name.
+fn runtimes() -> &'static Mutex<Vec<(String, Handle)>> {
+    static RUNTIMES: OnceLock<Mutex<Vec<(String, Handle)>>> = OnceLock::new();
+
+    RUNTIMES.get_or_init(Default::default)
+}
+
+/// Registers the runtime a service runs on. Pingora creates the runtimes of
+/// services itself, so the service registers it from its first request.
+pub struct RuntimeTracker {
+    name: String,
+    registered: Once,
+}
+
+impl RuntimeTracker {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            registered: Once::new(),
+        }
+    }
+
+    pub fn track(&self) {
+        self.registered.call_once(|| {
+            if let Ok(handle) = Handle::try_current() {
+                runtimes()
+                    .lock()
+                    .expect("runtime registry poisoned")
+                    .push((self.name.clone(), handle));
+            }
+        });
+    }
+}
+
+/// Times a synchronous operation blocking the worker thread until dropped.
+pub struct Blocking {
+    operation: &'static str,
+    started: Instant,
+}
+
+pub fn blocking(operation: &'static str) -> Blocking {
+    Blocking {
+        operation,
+        started: Instant::now(),
+    }
+}
+
+impl Drop for Blocking {
+    fn drop(&mut self) {
+        let blocked = self.started.elapsed();
+
+        metrics()
+            .blocking
+            .with_label_values(&[self.operation])
+            .observe(blocked.as_secs_f64());
+
+        if blocked > BLOCKING_WARN {
+            tracing::warn!(
+                "{} blocked a worker thread for {} ms",
+                self.operation,
+                blocked.as_millis()
+            );
+        }
+    }
+}
+
+/// Busy time of every worker at the previous sample.
+#[derive(Default)]
+struct Sampler {
+    busy: HashMap<(String, usize), Duration>,
+    sampled_at: Option<Instant>,
+}
+
+impl Sampler {
+    fn sample(&mut self, runtimes: &[(String, Handle)]) {
+        let now = Instant::now();
+        let elapsed = self.sampled_at.map(|at| now - at);
+        self.sampled_at = Some(now);
+
+        let metrics = metrics();
+
+        for (name, handle) in runtimes {
+            let runtime = handle.metrics();
+
+            metrics
+                .workers
+                .with_label_values(&[name])
+                .set(runtime.num_workers() as i64);
+            metrics
+                .alive_tasks
+                .with_label_values(&[name])
+                .set(runtime.num_alive_tasks() as i64);
+            metrics
+                .queued_tasks
+                .with_label_values(&[name])
+                .set(runtime.global_queue_depth() as i64);
+
+            for worker in 0..runtime.num_workers() {
+                let busy = runtime.worker_total_busy_duration(worker);
+                let previous = self.busy.insert((name.clone(), worker), busy);
+
+                if let (Some(previous), Some(elapsed)) = (previous, elapsed) {
+                    metrics
+                        .utilization
+                        .with_label_values(&[name, &worker.to_string()])
+                        .set(utilization(busy.saturating_sub(previous), elapsed));
+                }
+            }
+        }
+    }
+}
+
+/// Share of `elapsed` spent `busy`, capped as the two are not read at once.
+fn utilization(busy: Duration, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+
+    (busy.as_secs_f64() / elapsed.as_secs_f64()).min(1.0)
+}
+
+struct RuntimeSampling;
+
+#[async_trait]
+impl BackgroundService for RuntimeSampling {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut sampler = Sampler::default();
+        let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = ticker.tick() => {
+                    let runtimes = runtimes().lock().expect("runtime registry poisoned").clone();
+                    sampler.sample(&runtimes);
+                }
+            }
+        }
+    }
+}
+
+pub fn runtime_metrics_service() -> Box<dyn pingora::services::Service> {
+    Box::new(background_service("motya-runtime-metrics", RuntimeSampling))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utilization() {
+        let second = Duration::from_secs(1);
+
+        assert_eq!(utilization(Duration::from_millis(250), second), 0.25);
+        assert_eq!(utilization(Duration::from_millis(1200), second), 1.0);
+        assert_eq!(utilization(second, Duration::ZERO), 0.0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sample() {
+        let runtimes = vec![("test-runtime".to_string(), Handle::current())];
+
+        let mut sampler = Sampler::default();
+        sampler.sample(&runtimes);
+        sampler.sample(&runtimes);
+
+        let metrics = metrics();
+        assert_eq!(
+            metrics.workers.with_label_values(&["test-runtime"]).get(),
+            2
+        );
+
+        let utilization = metrics
+            .utilization
+            .with_label_values(&["test-runtime", "0"])
+            .get();
+        assert!((0.0..=1.0).contains(&utilization));
+    }
+}
@@ -0,0 +1,173 @@
+//! `motya schema`: the configuration nodes and the builtin filters as JSON,
+//! for editors and external validators.
+
+use serde_json::{json, Map, Value};
+
+use motya_config::{
+    common_types::definitions_table::DefinitionsTable,
+    kdl::{
+        parser::utils::PrimitiveType,
+        schema::{Node, SECTIONS},
+    },
+};
+
+use crate::proxy::filters::generate_registry::load_registry;
+
+/// The whole schema, pretty printed.
+pub fn render() -> String {
+    let mut out = serde_json::to_string_pretty(&schema()).expect("schema always serializes");
+    out.push('\n');
+    out
+}
+
+fn schema() -> Value {
+    let mut ancestors = Vec::new();
+    let sections: Vec<_> = SECTIONS
+        .iter()
+        .map(|section| describe(section, &mut ancestors))
+        .collect();
+
+    let registry = load_registry(&mut DefinitionsTable::default());
+    let filters: Vec<_> = registry
+        .descriptions()
+        .into_iter()
+        .map(|(name, metadata)| {
+            let args: Vec<_> = metadata
+                .args
+                .iter()
+                .map(|arg| {
+                    json!({
+                        "name": arg.name,
+                        "kind": arg.kind.as_str(),
+                        "required": arg.required,
+                        "description": arg.description,
+                    })
+                })
+                .collect();
+
+            json!({
+                "name": name.to_string(),
+                "phase": metadata.phase.as_str(),
+                "args": args,
+            })
+        })
+        .collect();
+
+    json!({ "sections": sections, "filters": filters })
+}
+
+/// A node that repeats one of its ancestors, like a `section` in a `section`,
+/// is a `ref` to the dotted path of that ancestor.
+fn describe(node: &'static Node, ancestors: &mut Vec<&'static Node>) -> Value {
+    if let Some(depth) = ancestors.iter().position(|a| std::ptr::eq(*a, node)) {
+        let path: Vec<_> = ancestors[..=depth].iter().map(|a| a.name).collect();
+        return json!({ "name": node.name, "ref": path.join(".") });
+    }
+
+    let mut out = Map::new();
+    out.insert("name".into(), node.name.into());
+    out.insert("occurs".into(), node.occurs.as_str().into());
+    out.insert("description".into(), node.description.into());
+
+    let args: Vec<_> = node
+        .args
+        .iter()
+        .map(|arg| {
+            let value = json!({ "name": arg.name, "kind": kind(arg.kind) });
+            with_values(value, arg.values)
+        })
+        .collect();
+    out.insert("args".into(), args.into());
+
+    let props: Vec<_> = node
+        .props
+        .iter()
+        .map(|prop| {
+            let value = json!({
+                "name": prop.name,
+                "kind": kind(prop.kind),
+                "required": prop.required,
+            });
+            with_values(value, prop.values)
+        })
+        .collect();
+    out.insert("props".into(), props.into());
+
+    ancestors.push(node);
+    let children: Vec<_> = node
+        .children
+        .iter()
+        .map(|child| describe(child, ancestors))
+        .collect();
+    ancestors.pop();
+    out.insert("children".into(), children.into());
+
+    Value::Object(out)
+}
+
+fn with_values(mut value: Value, values: &[&str]) -> Value {
+    if !values.is_empty() {
+        value["values"] = values.into();
+    }
+    value
+}
+
+fn kind(kind: PrimitiveType) -> &'static str {
+    match kind {
+        PrimitiveType::String => "string",
+        PrimitiveType::Integer => "integer",
+        PrimitiveType::Float => "float",
+        PrimitiveType::Bool => "bool",
+        PrimitiveType::Null => "null",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn child<'a>(node: &'a Value, name: &str) -> &'a Value {
+        node["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|child| child["name"] == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema: Value = serde_json::from_str(&render()).unwrap();
+
+        let sections: Vec<_> = schema["sections"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|section| section["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            sections,
+            ["system", "includes", "flags", "definitions", "services"]
+        );
+
+        let connectors = child(child(&schema["sections"][4], "*"), "connectors");
+        let section = child(connectors, "section");
+        assert_eq!(section["occurs"], "repeated");
+        assert_eq!(
+            child(section, "section")["ref"],
+            "services.*.connectors.section"
+        );
+
+        let proxy = child(section, "proxy");
+        let proto = proxy["props"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|prop| prop["name"] == "proto")
+            .unwrap();
+        assert_eq!(proto["kind"], "string");
+        assert!(proto["values"].as_array().unwrap().contains(&json!("grpc")));
+
+        assert!(!schema["filters"].as_array().unwrap().is_empty());
+    }
+}
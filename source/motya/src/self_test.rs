@@ -0,0 +1,367 @@
+//! `motya self-test`: every proxy service of the configuration is served on a
+//! loopback port of its own, in front of a built-in echo upstream, and driven
+//! with synthetic traffic.
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use futures_util::future::join_all;
+use http::{header::CONTENT_LENGTH, StatusCode};
+use miette::IntoDiagnostic;
+use pingora::{server::Server, services::Service, upstreams::peer::HttpPeer, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::{ProxyHttp, Session};
+
+use crate::app_context::AppContext;
+use motya_config::{
+    cli::cli_struct::{Cli, TrafficPattern},
+    common_types::{
+        connectors::{UpstreamConfig, UpstreamServer, ALPN},
+        listeners::{H2Limits, ListenerConfig, ListenerKind},
+    },
+    internal::Config,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often an idle client checks whether the pattern needs it.
+const IDLE_POLL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub pattern: TrafficPattern,
+    /// Length of the run for each service.
+    pub duration: Duration,
+    pub concurrency: usize,
+    pub path: String,
+}
+
+/// A proxy service moved to a loopback port.
+struct Target {
+    name: String,
+    address: SocketAddr,
+}
+
+pub async fn run(cli_args: Cli, plan: Plan) -> miette::Result<()> {
+    let echo = loopback_address()?;
+
+    let mut ctx = AppContext::bootstrap(cli_args).await?;
+    let targets = isolate(ctx.config_mut(), echo)?;
+
+    if targets.is_empty() {
+        return Err(miette::miette!("The configuration has no proxy services"));
+    }
+
+    let mut services = ctx.build_services().await?;
+    let (mut server, _) = ctx.ready();
+    services.push(echo_service(echo, &server));
+
+    server.bootstrap();
+    server.add_services(services);
+    std::thread::spawn(move || server.run_forever());
+
+    for target in &targets {
+        wait_listening(target.address).await?;
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .into_diagnostic()?;
+
+    for target in &targets {
+        tracing::info!("Self-testing {} on {}", target.name, target.address);
+
+        let url = format!("http://{}{}", target.address, plan.path);
+        let report = drive(&client, &url, &plan).await;
+
+        println!("{}", report.summary(&target.name, &plan));
+    }
+
+    Ok(())
+}
+
+/// Moves every proxy service to a loopback port of its own in front of `echo`,
+/// and drops everything else that would listen or reach out.
+fn isolate(config: &mut Config, echo: SocketAddr) -> miette::Result<Vec<Target>> {
+    config.file_servers.clear();
+    config.provider = None;
+    config.events = None;
+    config.admin = None;
+    config.alerts = None;
+
+    let mut targets = Vec::with_capacity(config.basic_proxies.len());
+
+    for proxy in &mut config.basic_proxies {
+        let address = loopback_address()?;
+
+        proxy.listeners.list_cfgs = vec![ListenerConfig {
+            source: ListenerKind::Tcp {
+                addr: address.to_string(),
+                tls: None,
+                offer_h2: false,
+                h2c: false,
+            },
+            unmatched_host: None,
+            redirect_to_https: false,
+            max_request_body_bytes: None,
+            h2_limits: H2Limits::default(),
+            tls_fingerprint: false,
+        }];
+        // The listener is not shared, so the service takes any host.
+        proxy.hosts.clear();
+
+        for upstream in &mut proxy.connectors.upstreams {
+            point_at(&mut upstream.upstream, echo);
+        }
+
+        targets.push(Target {
+            name: proxy.name.clone(),
+            address,
+        });
+    }
+
+    Ok(targets)
+}
+
+fn point_at(upstream: &mut UpstreamConfig, echo: SocketAddr) {
+    match upstream {
+        UpstreamConfig::Service(peer) => {
+            peer.peer_address = echo;
+            peer.tls = false;
+            peer.host_sni = None;
+            peer.alpn = ALPN::H1;
+            peer.grpc = false;
+        }
+        UpstreamConfig::MultiServer(multi) => {
+            multi.servers = vec![UpstreamServer {
+                address: echo,
+                weight: 1,
+                vnodes: None,
+            }];
+            multi.read_your_writes = None;
+            multi.split_by_method = None;
+            multi.backend_override = None;
+            multi.tls_sni = None;
+            multi.host_sni = None;
+            multi.alpn = ALPN::H1;
+            multi.grpc = false;
+        }
+        UpstreamConfig::Static(_) => {}
+    }
+}
+
+/// A free loopback port, released again so a service can bind it.
+fn loopback_address() -> miette::Result<SocketAddr> {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .into_diagnostic()
+}
+
+async fn wait_listening(address: SocketAddr) -> miette::Result<()> {
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(address).await.is_ok() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(miette::miette!("Nothing started listening on {address}"))
+}
+
+/// How many of `peak` clients send requests `progress` (`0.0..1.0`) into a run.
+fn clients(pattern: TrafficPattern, peak: usize, progress: f64) -> usize {
+    let clients = match pattern {
+        TrafficPattern::Ramp => (peak as f64 * progress).ceil() as usize,
+        TrafficPattern::Spike if (1.0 / 3.0..2.0 / 3.0).contains(&progress) => peak,
+        TrafficPattern::Spike => peak / 10,
+        TrafficPattern::Soak => peak,
+    };
+
+    clients.clamp(1, peak)
+}
+
+async fn drive(client: &reqwest::Client, url: &str, plan: &Plan) -> Report {
+    let start = Instant::now();
+
+    let workers = (0..plan.concurrency).map(|id| {
+        let client = client.clone();
+        let url = url.to_string();
+        let plan = plan.clone();
+
+        tokio::spawn(async move {
+            let mut report = Report::default();
+
+            loop {
+                let elapsed = start.elapsed();
+                if elapsed >= plan.duration {
+                    return report;
+                }
+
+                let progress = elapsed.as_secs_f64() / plan.duration.as_secs_f64();
+                if id >= clients(plan.pattern, plan.concurrency, progress) {
+                    tokio::time::sleep(IDLE_POLL).await;
+                    continue;
+                }
+
+                let sent = Instant::now();
+                let status = match client.get(&url).send().await {
+                    Ok(response) => {
+                        let status = response.status();
+                        response.bytes().await.map(|_| status)
+                    }
+                    Err(err) => Err(err),
+                };
+
+                match status {
+                    Ok(status) => {
+                        report.latencies.push(sent.elapsed());
+                        report.server_errors += usize::from(status.is_server_error());
+                    }
+                    Err(_) => report.failures += 1,
+                }
+            }
+        })
+    });
+
+    let mut report = Report::default();
+    for client in join_all(workers).await.into_iter().flatten() {
+        report.latencies.extend(client.latencies);
+        report.server_errors += client.server_errors;
+        report.failures += client.failures;
+    }
+    report.elapsed = start.elapsed();
+
+    report
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    /// One per answered request.
+    latencies: Vec<Duration>,
+    /// Answered with a `5xx`.
+    server_errors: usize,
+    /// Not answered at all.
+    failures: usize,
+    elapsed: Duration,
+}
+
+impl Report {
+    fn summary(mut self, name: &str, plan: &Plan) -> String {
+        self.latencies.sort_unstable();
+
+        let requests = self.latencies.len() + self.failures;
+        let rate = requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON);
+
+        format!(
+            "{name}: {pattern} up to {concurrency} clients for {duration:?}\n  \
+             {requests} requests ({rate:.1}/s), {server_errors} answered 5xx, {failures} failed\n  \
+             latency p50 {p50:.2?}, p90 {p90:.2?}, p99 {p99:.2?}, max {max:.2?}",
+            pattern = format!("{:?}", plan.pattern).to_lowercase(),
+            concurrency = plan.concurrency,
+            duration = plan.duration,
+            server_errors = self.server_errors,
+            failures = self.failures,
+            p50 = percentile(&self.latencies, 0.5),
+            p90 = percentile(&self.latencies, 0.9),
+            p99 = percentile(&self.latencies, 0.99),
+            max = percentile(&self.latencies, 1.0),
+        )
+    }
+}
+
+/// Nearest-rank percentile of `sorted`, zero when it is empty.
+pub(crate) fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (sorted.len() as f64 * p).ceil() as usize;
+    sorted
+        .get(rank.clamp(1, sorted.len().max(1)) - 1)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// The upstream of every proxy service during a self-test, it answers with
+/// the request body.
+struct EchoUpstream;
+
+fn echo_service(address: SocketAddr, server: &Server) -> Box<dyn Service> {
+    let mut service = pingora_proxy::http_proxy_service_with_name(
+        &server.configuration,
+        EchoUpstream,
+        "motya-self-test-echo",
+    );
+
+    service.add_tcp(&address.to_string());
+
+    Box::new(service)
+}
+
+#[async_trait]
+impl ProxyHttp for EchoUpstream {
+    type CTX = ();
+
+    fn new_ctx(&self) -> Self::CTX {}
+
+    async fn upstream_peer(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Self::CTX,
+    ) -> Result<Box<HttpPeer>> {
+        // Every request is answered in `request_filter`.
+        Err(pingora::Error::new_str("Request Failed"))
+    }
+
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut Self::CTX) -> Result<bool> {
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.downstream_session.read_request_body().await? {
+            body.extend_from_slice(&chunk);
+        }
+
+        let mut response = ResponseHeader::build(StatusCode::OK, Some(1))?;
+        response.insert_header(CONTENT_LENGTH, body.len().to_string())?;
+
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(body.freeze(), true)
+            .await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clients() {
+        assert_eq!(clients(TrafficPattern::Ramp, 40, 0.0), 1);
+        assert_eq!(clients(TrafficPattern::Ramp, 40, 0.5), 20);
+        assert_eq!(clients(TrafficPattern::Ramp, 40, 0.99), 40);
+
+        assert_eq!(clients(TrafficPattern::Spike, 40, 0.2), 4);
+        assert_eq!(clients(TrafficPattern::Spike, 40, 0.5), 40);
+        assert_eq!(clients(TrafficPattern::Spike, 40, 0.8), 4);
+        assert_eq!(clients(TrafficPattern::Spike, 5, 0.1), 1);
+
+        assert_eq!(clients(TrafficPattern::Soak, 40, 0.1), 40);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&sorted, 0.5), Duration::from_millis(50));
+        assert_eq!(percentile(&sorted, 0.99), Duration::from_millis(99));
+        assert_eq!(percentile(&sorted, 1.0), Duration::from_millis(100));
+        assert_eq!(percentile(&sorted, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+    }
+}
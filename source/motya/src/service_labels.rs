@@ -0,0 +1,80 @@
+//! `labels` of the proxy services, so dashboards can group them by owner or
+//! environment without parsing their names.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, OnceLock},
+};
+
+use arc_swap::ArcSwapOption;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+
+use motya_config::internal::ProxyConfig;
+
+type Labels = BTreeMap<String, BTreeMap<String, String>>;
+
+static CURRENT: ArcSwapOption<Labels> = ArcSwapOption::const_empty();
+
+fn info() -> &'static IntGaugeVec {
+    static INFO: OnceLock<IntGaugeVec> = OnceLock::new();
+
+    INFO.get_or_init(|| {
+        register_int_gauge_vec!(
+            "motya_service_labels",
+            "Always 1, one series per label of every proxy service",
+            &["service", "label", "value"]
+        )
+        .expect("metric should register once")
+    })
+}
+
+/// Sets the labels of a configuration that was just loaded or reloaded.
+pub fn apply(proxies: &[ProxyConfig]) {
+    let labels: Labels = proxies
+        .iter()
+        .map(|proxy| (proxy.name.clone(), proxy.labels.clone()))
+        .collect();
+
+    let info = info();
+    info.reset();
+    for (service, labels) in &labels {
+        for (label, value) in labels {
+            info.with_label_values(&[service.as_str(), label.as_str(), value.as_str()])
+                .set(1);
+        }
+    }
+
+    CURRENT.store(Some(Arc::new(labels)));
+}
+
+/// The labels of every proxy service, services without any included.
+pub fn snapshot() -> Arc<Labels> {
+    CURRENT.load_full().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::cli::builder::CliConfigBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let mut proxy = CliConfigBuilder::build_hello(8080, "hi".to_string())
+            .unwrap()
+            .basic_proxies
+            .remove(0);
+        proxy.name = "test-labels".to_string();
+        proxy.labels = BTreeMap::from([("team".to_string(), "payments".to_string())]);
+
+        apply(&[proxy]);
+
+        assert_eq!(snapshot()["test-labels"]["team"], "payments");
+        assert_eq!(
+            info()
+                .with_label_values(&["test-labels", "team", "payments"])
+                .get(),
+            1
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Services and plugins set up while the server starts, several at a time.
+
+use std::{future::Future, num::NonZeroUsize, thread};
+
+use futures_util::{stream, StreamExt};
+
+/// Items set up at once, one per core.
+fn parallelism() -> usize {
+    thread::available_parallelism().map_or(4, NonZeroUsize::get)
+}
+
+/// What happens when some items fail to initialize.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnFailure {
+    /// Nothing starts, the error lists every item that failed.
+    Abort,
+    /// The items are logged and left out, the others start.
+    Skip,
+}
+
+impl OnFailure {
+    /// `Skip` with `--allow-partial-start`, `Abort` otherwise.
+    pub fn new(allow_partial_start: bool) -> Self {
+        if allow_partial_start {
+            Self::Skip
+        } else {
+            Self::Abort
+        }
+    }
+}
+
+/// Runs the future `init` makes of every item, a bounded number at a time,
+/// and returns their outputs in the order of `items`. Failed items are named
+/// as `init` named them, all of them and not only the first.
+pub async fn init_all<I, T, F, Fut>(
+    what: &str,
+    items: I,
+    on_failure: OnFailure,
+    init: F,
+) -> miette::Result<Vec<T>>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> (String, Fut),
+    Fut: Future<Output = miette::Result<T>>,
+{
+    let results = stream::iter(items.into_iter().map(init))
+        .map(|(name, init)| async move { (name, init.await) })
+        .buffered(parallelism())
+        .collect::<Vec<_>>()
+        .await;
+
+    collect(what, results, on_failure)
+}
+
+fn collect<T>(
+    what: &str,
+    results: Vec<(String, miette::Result<T>)>,
+    on_failure: OnFailure,
+) -> miette::Result<Vec<T>> {
+    let total = results.len();
+    let mut outputs = Vec::with_capacity(total);
+    let mut failures = Vec::new();
+
+    for (name, result) in results {
+        match result {
+            Ok(output) => outputs.push(output),
+            Err(e) => {
+                let causes = e.chain().map(ToString::to_string).collect::<Vec<_>>();
+                failures.push(format!("  {name}: {}", causes.join(": ")));
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(outputs);
+    }
+
+    let report = format!(
+        "{} of {total} {what} failed to initialize:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+
+    match on_failure {
+        OnFailure::Abort => Err(miette::miette!("{report}")),
+        OnFailure::Skip => {
+            tracing::error!("{report}\nStarting without them, as partial start is allowed");
+            Ok(outputs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_init_all() {
+        // Later items finishing first keep their place.
+        let outputs = init_all("items", [30, 10, 20], OnFailure::Abort, |delay| {
+            (format!("item {delay}"), async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                Ok(delay)
+            })
+        })
+        .await
+        .unwrap();
+        assert_eq!(outputs, [30, 10, 20]);
+
+        let services = ["api", "web", "static"];
+        let init = |name: &'static str| {
+            (name.to_string(), async move {
+                match name {
+                    "static" => Ok(name),
+                    _ => Err(miette::miette!("address in use")),
+                }
+            })
+        };
+
+        let err = init_all("services", services, OnFailure::Abort, init)
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(
+            err.starts_with("2 of 3 services failed to initialize"),
+            "{err}"
+        );
+        assert!(err.contains("  api: address in use"), "{err}");
+        assert!(err.contains("  web: address in use"), "{err}");
+        assert!(!err.contains("static"), "{err}");
+
+        let started = init_all("services", services, OnFailure::Skip, init)
+            .await
+            .unwrap();
+        assert_eq!(started, ["static"]);
+    }
+}
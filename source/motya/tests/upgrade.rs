@@ -0,0 +1,222 @@
+//! Zero-downtime upgrade: a second `motya --upgrade` takes the listeners of a
+//! daemonized instance over while requests keep coming in.
+#![cfg(target_os = "linux")]
+
+use std::{
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use tempfile::TempDir;
+
+/// Instances started by one test, they share the pid file, the upgrade socket
+/// and the listener.
+struct Harness {
+    dir: TempDir,
+    port: u16,
+    daemons: Vec<Pid>,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        Self {
+            dir: tempfile::tempdir().unwrap(),
+            port,
+            daemons: Vec::new(),
+        }
+    }
+
+    fn pid_file(&self) -> PathBuf {
+        self.dir.path().join("motya.pid")
+    }
+
+    fn upgrade_socket(&self) -> PathBuf {
+        self.dir.path().join("upgrade.sock")
+    }
+
+    fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.port)
+    }
+
+    /// Writes a configuration answering every request with `body`.
+    fn config(&self, body: &str) -> PathBuf {
+        let path = self.dir.path().join(format!("{body}.kdl"));
+
+        let config = format!(
+            r#"
+            system {{
+                threads-per-service 2
+                daemonize #true
+                pid-file "{pid_file}"
+                upgrade-socket "{upgrade_socket}"
+                shutdown grace-period-secs=1 timeout-secs=1
+            }}
+            services {{
+                Upgrade {{
+                    listeners {{ "127.0.0.1:{port}" }}
+                    connectors {{ return code=200 response="{body}" }}
+                }}
+            }}
+            "#,
+            pid_file = self.pid_file().display(),
+            upgrade_socket = self.upgrade_socket().display(),
+            port = self.port,
+        );
+
+        std::fs::write(&path, config).unwrap();
+        path
+    }
+
+    fn motya(&self, config: &Path) -> tokio::process::Command {
+        let mut command = std::process::Command::new(assert_cmd::cargo::cargo_bin!("motya"));
+        command.arg("--config-entry").arg(config);
+        command.into()
+    }
+
+    /// Waits for the pid file to name a daemon other than `previous`.
+    async fn daemon(&mut self, previous: Option<Pid>) -> Pid {
+        for _ in 0..100 {
+            let pid = std::fs::read_to_string(self.pid_file())
+                .ok()
+                .and_then(|pid| pid.trim().parse().ok())
+                .map(Pid::from_raw);
+
+            if let Some(pid) = pid.filter(|pid| Some(*pid) != previous) {
+                self.daemons.push(pid);
+                return pid;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        panic!("No new daemon wrote {}", self.pid_file().display());
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        for pid in self.daemons.iter().filter(|pid| is_running(**pid)) {
+            let _ = kill(*pid, Signal::SIGTERM);
+        }
+    }
+}
+
+/// Daemons are reparented, so an exited one may linger as a zombie.
+fn is_running(pid: Pid) -> bool {
+    std::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .ok()
+        .and_then(|stat| {
+            let (_, state) = stat.rsplit_once(") ")?;
+            state.chars().next()
+        })
+        .is_some_and(|state| state != 'Z')
+}
+
+async fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    for _ in 0..200 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    panic!("Timed out waiting for {what}");
+}
+
+/// Sends requests, each on a new connection, until `stop` is set. Returns the
+/// answered bodies and the failures.
+async fn load(url: String, stop: Arc<AtomicBool>) -> (Vec<String>, Vec<String>) {
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap();
+
+    let mut bodies = Vec::new();
+    let mut failures = Vec::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        let response = match client.get(&url).send().await {
+            Ok(response) if response.status().is_success() => response.text().await,
+            Ok(response) => {
+                failures.push(response.status().to_string());
+                continue;
+            }
+            Err(err) => Err(err),
+        };
+
+        match response {
+            Ok(body) => bodies.push(body),
+            Err(err) => failures.push(err.to_string()),
+        }
+    }
+
+    (bodies, failures)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_upgrade_drops_no_requests() {
+    let mut harness = Harness::new();
+
+    let started = harness
+        .motya(&harness.config("old"))
+        .status()
+        .await
+        .unwrap();
+    assert!(started.success(), "The first instance did not daemonize");
+
+    let old = harness.daemon(None).await;
+    let url = harness.url();
+    wait_until("the first instance to listen", || {
+        std::net::TcpStream::connect(("127.0.0.1", harness.port)).is_ok()
+    })
+    .await;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let load = tokio::spawn(load(url, stop.clone()));
+
+    // The new instance waits on the upgrade socket until the old one sends
+    // its listeners, which it does on SIGQUIT.
+    let mut new = harness
+        .motya(&harness.config("new"))
+        .arg("--upgrade")
+        .spawn()
+        .unwrap();
+
+    let upgrade_socket = harness.upgrade_socket();
+    wait_until("the upgrade socket", || upgrade_socket.exists()).await;
+    kill(old, Signal::SIGQUIT).unwrap();
+
+    let upgraded = new.wait().await.unwrap();
+    assert!(upgraded.success(), "The new instance did not daemonize");
+    harness.daemon(Some(old)).await;
+
+    wait_until("the old instance to exit", || !is_running(old)).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    stop.store(true, Ordering::Relaxed);
+    let (bodies, failures) = load.await.unwrap();
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} requests failed: {failures:?}",
+        failures.len(),
+        failures.len() + bodies.len()
+    );
+    assert!(bodies.iter().any(|body| body == "old"));
+    assert_eq!(bodies.last().map(String::as_str), Some("new"));
+}
@@ -0,0 +1,145 @@
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use motya::alerting::{self, alerting_service};
+    use motya::app_context::{pingora_opt, pingora_server_conf};
+    use motya::fs_adapter::TokioFs;
+    use motya::proxy::acme::client::self_signed;
+    use motya::proxy::filters::chain_resolver::ChainResolver;
+    use motya::proxy::filters::generate_registry::load_registry;
+    use motya::proxy::motya_proxy_service;
+    use motya::proxy::upstream_factory::UpstreamFactory;
+    use motya_config::common_types::definitions_table::DefinitionsTable;
+    use motya_config::internal::Config;
+    use motya_config::kdl::fs_loader::FileCollector;
+    use motya_config::loader::{ConfigLoader, FileConfigLoaderProvider};
+    use pingora::server::Server;
+    use tokio::time::timeout;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const CONFIG: &str = r#"
+        system {
+            alerts {
+                webhook "__WEBHOOK__"
+                pool-healthy-below percent=50
+                cert-expiring days=14
+            }
+        }
+        services {
+            Api {
+                listeners {
+                    "127.0.0.1:__PORT__" cert-path="__CERT__" key-path="__KEY__"
+                }
+                connectors {
+                    load-balance {
+                        selection "RoundRobin"
+                        health-check "Grpc" interval-secs=1
+                    }
+                    proxy {
+                        server "127.0.0.1:__BACKEND__"
+                    }
+                }
+            }
+        }
+    "#;
+
+    fn get_free_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_listener_certs_and_health_checks_raise_alerts() {
+        let webhook = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&webhook)
+            .await;
+
+        // Expires in a day, well within the 14 days of the rule.
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = self_signed(&["example.com".to_string()]).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        // Nothing listens there, so the only backend fails its health check.
+        let config = CONFIG
+            .replace("__WEBHOOK__", &format!("{}/hook", webhook.uri()))
+            .replace("__PORT__", &get_free_port().to_string())
+            .replace("__BACKEND__", &get_free_port().to_string())
+            .replace("__CERT__", cert_path.to_str().unwrap())
+            .replace("__KEY__", key_path.to_str().unwrap());
+        let config_path = dir.path().join("motya.kdl");
+        std::fs::write(&config_path, config).unwrap();
+
+        let mut definitions_table = DefinitionsTable::default();
+        let registry = load_registry(&mut definitions_table);
+        let config = ConfigLoader::new(FileCollector::<TokioFs>::default())
+            .load_entry_point(Some(config_path), &mut definitions_table)
+            .await
+            .unwrap()
+            .unwrap();
+        let resolver = ChainResolver::new(definitions_table, Arc::new(registry.into()))
+            .await
+            .unwrap();
+
+        alerting::apply(&config.basic_proxies);
+
+        let conf = Config::default();
+        let mut alerting_server =
+            Server::new_with_opt_and_conf(pingora_opt(&conf), pingora_server_conf(&conf));
+        alerting_server.bootstrap();
+        alerting_server.add_services(vec![alerting_service(config.alerts.clone().unwrap())]);
+        thread::spawn(move || alerting_server.run_forever());
+
+        // The health check only reports the transition, so alerting has to
+        // listen before it runs.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let proxy = config.basic_proxies[0].clone();
+        let factory = UpstreamFactory::new(resolver).with_service(&proxy.name);
+        let mut app_server =
+            Server::new_with_opt_and_conf(pingora_opt(&conf), pingora_server_conf(&conf));
+        let (proxy_services, _) = motya_proxy_service(proxy, factory, &app_server)
+            .await
+            .unwrap();
+        app_server.bootstrap();
+        app_server.add_services(proxy_services);
+        thread::spawn(move || app_server.run_forever());
+
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let bodies: Vec<String> = webhook
+                    .received_requests()
+                    .await
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|req| String::from_utf8_lossy(&req.body).into_owned())
+                    .collect();
+
+                let fired = |rule: &str, subject: &str| {
+                    bodies.iter().any(|body| {
+                        body.contains(&format!(r#""alert":"{rule}""#))
+                            && body.contains(&format!(r#""subject":"{subject}""#))
+                    })
+                };
+                if fired("cert-expiring", cert_path.to_str().unwrap())
+                    && fired("pool-healthy-below", "Api")
+                {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("Alerts were not delivered");
+    }
+}
@@ -39,6 +39,7 @@ mod tests {
             upgrade: false,
             pidfile: None,
             upgrade_socket: None,
+            allow_partial_start: false,
             command: Some(Commands::Hello {
                 port,
                 text: expected_text.to_string(),
@@ -87,6 +88,7 @@ mod tests {
             upgrade: false,
             pidfile: None,
             upgrade_socket: None,
+            allow_partial_start: false,
             command: Some(Commands::Serve {
                 port,
                 map: vec![
@@ -153,6 +155,7 @@ mod tests {
             upgrade: false,
             pidfile: None,
             upgrade_socket: None,
+            allow_partial_start: false,
             command: Some(Commands::Serve {
                 port: proxy_port,
                 map: vec![
@@ -141,7 +141,7 @@ mod tests {
         let resolver = ChainResolver::new(definitions.clone(), registry)
             .await
             .unwrap();
-        let factory = UpstreamFactory::new(resolver.clone());
+        let factory = UpstreamFactory::new(resolver);
 
         // Start the real Pingora server in background
         let mut app_server =
@@ -149,11 +149,12 @@ mod tests {
         app_server.bootstrap();
 
         let proxy_config = config.basic_proxies[0].clone();
-        let (service, shared_state) = motya_proxy_service(proxy_config, resolver, &app_server)
-            .await
-            .unwrap();
+        let (services, shared_state) =
+            motya_proxy_service(proxy_config, factory.clone(), &app_server)
+                .await
+                .unwrap();
 
-        app_server.add_services(vec![service]);
+        app_server.add_services(services);
         thread::spawn(move || {
             app_server.run_forever();
         });
@@ -237,4 +238,97 @@ mod tests {
         assert_eq!(get_response_body(port, "/4").await, Some("OK4".to_string()));
         assert_eq!(get_response_body(port, "/5").await, Some("OK5".to_string()));
     }
+
+    #[tokio::test]
+    async fn test_config_watcher_reloads_on_included_file_change() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let entry_path = dir.path().join("entry.kdl");
+        let services_path = dir.path().join("services.kdl");
+        let port = get_free_port();
+
+        let entry = r#"
+            includes {
+                "./services.kdl"
+            }
+            system {
+                providers {
+                    files watch=#true
+                }
+            }
+        "#;
+
+        let services = r#"
+            services {
+                IncludedService {
+                    listeners { "127.0.0.1:__PORT__" }
+                    connectors {
+                        return code=200 response="__BODY__"
+                    }
+                }
+            }
+        "#;
+
+        let write_services = |body: &str| {
+            let mut file = File::create(&services_path).unwrap();
+            let content = services
+                .replace("__PORT__", &port.to_string())
+                .replace("__BODY__", body);
+            file.write_all(content.as_bytes()).unwrap();
+            file.sync_all().unwrap();
+        };
+
+        std::fs::write(&entry_path, entry).unwrap();
+        write_services("before");
+
+        let mut definitions = DefinitionsTable::default();
+        let loader = ConfigLoader::new(FileCollector::<TokioFs>::default());
+
+        let config = loader
+            .clone()
+            .load_entry_point(Some(entry_path.clone()), &mut definitions)
+            .await
+            .expect("Failed to load initial config")
+            .expect("Config should be present");
+
+        let registry = Arc::new(Mutex::new(FilterRegistry::default()));
+        let resolver = ChainResolver::new(definitions.clone(), registry)
+            .await
+            .unwrap();
+        let factory = UpstreamFactory::new(resolver);
+
+        let mut app_server =
+            Server::new_with_opt_and_conf(pingora_opt(&config), pingora_server_conf(&config));
+        app_server.bootstrap();
+
+        let proxy_config = config.basic_proxies[0].clone();
+        let (services, shared_state) =
+            motya_proxy_service(proxy_config, factory.clone(), &app_server)
+                .await
+                .unwrap();
+
+        app_server.add_services(services);
+        thread::spawn(move || {
+            app_server.run_forever();
+        });
+
+        let mut watcher: ConfigWatcher<
+            FileCollector<TokioFs>,
+            ConfigLoader<FileCollector<TokioFs>>,
+        > = ConfigWatcher::new(config.clone(), definitions, entry_path, factory, loader);
+
+        watcher.insert_proxy_state("IncludedService".to_string(), shared_state);
+
+        tokio::spawn(async move {
+            let Err(e) = watcher.watch().await;
+            panic!("Watcher failed: {}", e);
+        });
+
+        wait_for_route_update(port, "/", Some("before")).await;
+
+        // Give the watcher a moment to register the include directory.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        write_services("after");
+
+        wait_for_route_update(port, "/", Some("after")).await;
+    }
 }
@@ -0,0 +1,85 @@
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use motya::app_context::{pingora_opt, pingora_server_conf};
+    use motya::events::{emit, sse::event_stream_service, Event};
+    use motya_config::common_types::system_data::EventsConfig;
+    use motya_config::internal::Config;
+    use pingora::server::Server;
+    use reqwest::{Client, StatusCode};
+    use tokio::time::timeout;
+
+    fn get_free_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_delivers_emitted_events() {
+        let port = get_free_port();
+        let config = Config::default();
+
+        let mut app_server =
+            Server::new_with_opt_and_conf(pingora_opt(&config), pingora_server_conf(&config));
+        app_server.bootstrap();
+
+        let events = EventsConfig {
+            address: format!("127.0.0.1:{port}").parse().unwrap(),
+            path: "/events".parse().unwrap(),
+        };
+        app_server.add_services(vec![event_stream_service(&events, &app_server)]);
+        thread::spawn(move || {
+            app_server.run_forever();
+        });
+
+        let client = Client::new();
+        let url = format!("http://127.0.0.1:{port}/events");
+
+        let mut resp = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(resp) = client.get(&url).send().await {
+                    return resp;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("Event stream did not come up");
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+
+        emit(Event::ConfigReloaded {
+            services: vec!["Api".to_string()],
+        });
+
+        let received = timeout(Duration::from_secs(5), async {
+            let mut buf = String::new();
+            while let Some(chunk) = resp.chunk().await.unwrap() {
+                buf.push_str(&String::from_utf8_lossy(&chunk));
+                if buf.contains("\n\n") {
+                    break;
+                }
+            }
+            buf
+        })
+        .await
+        .expect("No event received");
+
+        assert!(received.starts_with("event: config-reloaded\n"));
+        assert!(received.contains(r#""services":["Api"]"#));
+
+        let not_found = client
+            .get(format!("http://127.0.0.1:{port}/other"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(not_found.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,183 @@
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use motya::app_context::{pingora_opt, pingora_server_conf};
+    use motya::fs_adapter::TokioFs;
+    use motya::proxy::filters::{chain_resolver::ChainResolver, registry::FilterRegistry};
+    use motya::proxy::motya_proxy_service;
+    use motya::proxy::upstream_factory::UpstreamFactory;
+    use motya::proxy::watcher::file_watcher::ConfigWatcher;
+    use motya::proxy::watcher::http_provider::{config_push_service, last_good_path};
+    use motya_config::common_types::definitions_table::DefinitionsTable;
+    use motya_config::common_types::system_data::ConfigProvider;
+    use motya_config::kdl::fs_loader::FileCollector;
+    use motya_config::loader::{ConfigLoader, FileConfigLoaderProvider};
+    use pingora::server::Server;
+    use reqwest::{Client, StatusCode};
+    use tempfile::tempdir;
+    use tokio::sync::Mutex;
+    use tokio::time::timeout;
+
+    fn get_free_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    fn config(proxy_port: u16, admin_port: u16, body: &str) -> String {
+        r#"
+            system {
+                providers {
+                    http address="127.0.0.1:__ADMIN__" path="/config" persist=#true token="s3cret"
+                }
+            }
+            services {
+                PushedService {
+                    listeners { "127.0.0.1:__PORT__" }
+                    connectors {
+                        return code=200 response="__BODY__"
+                    }
+                }
+            }
+        "#
+        .replace("__ADMIN__", &admin_port.to_string())
+        .replace("__PORT__", &proxy_port.to_string())
+        .replace("__BODY__", body)
+    }
+
+    async fn wait_for_body(port: u16, expected: &str) {
+        let client = Client::new();
+        let url = format!("http://127.0.0.1:{port}/");
+
+        let check = async {
+            loop {
+                if let Ok(resp) = client.get(&url).send().await {
+                    if resp.text().await.ok().as_deref() == Some(expected) {
+                        return;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+
+        if timeout(Duration::from_secs(5), check).await.is_err() {
+            panic!("Timeout waiting for body {expected:?} on port {port}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_provider_applies_and_persists_pushed_config() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let entry_path = dir.path().join("entry.kdl");
+        let proxy_port = get_free_port();
+        let admin_port = get_free_port();
+
+        std::fs::write(&entry_path, config(proxy_port, admin_port, "initial")).unwrap();
+
+        let mut definitions = DefinitionsTable::default();
+        let loader = ConfigLoader::new(FileCollector::<TokioFs>::default());
+
+        let config_loaded = loader
+            .clone()
+            .load_entry_point(Some(entry_path.clone()), &mut definitions)
+            .await
+            .expect("Failed to load initial config")
+            .expect("Config should be present");
+
+        let Some(ConfigProvider::Http(provider)) = config_loaded.provider.clone() else {
+            panic!("http provider expected");
+        };
+
+        let registry = Arc::new(Mutex::new(FilterRegistry::default()));
+        let resolver = ChainResolver::new(definitions.clone(), registry)
+            .await
+            .unwrap();
+        let factory = UpstreamFactory::new(resolver);
+
+        let mut app_server = Server::new_with_opt_and_conf(
+            pingora_opt(&config_loaded),
+            pingora_server_conf(&config_loaded),
+        );
+        app_server.bootstrap();
+
+        let proxy_config = config_loaded.basic_proxies[0].clone();
+        let (mut services, shared_state) =
+            motya_proxy_service(proxy_config, factory.clone(), &app_server)
+                .await
+                .unwrap();
+
+        let mut watcher: ConfigWatcher = ConfigWatcher::new(
+            config_loaded.clone(),
+            definitions,
+            entry_path.clone(),
+            factory,
+            loader,
+        );
+        watcher.insert_proxy_state("PushedService".to_string(), shared_state);
+
+        let push = config_push_service(&provider, &entry_path, watcher, &app_server);
+
+        services.push(push);
+        app_server.add_services(services);
+        thread::spawn(move || {
+            app_server.run_forever();
+        });
+
+        wait_for_body(proxy_port, "initial").await;
+
+        let client = Client::new();
+        let admin_url = format!("http://127.0.0.1:{admin_port}/config");
+
+        let unauthorized = client
+            .post(&admin_url)
+            .bearer_auth("guess")
+            .body(config(proxy_port, admin_port, "forged"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unauthorized.status(), StatusCode::UNAUTHORIZED);
+
+        let anonymous = client
+            .post(&admin_url)
+            .body(config(proxy_port, admin_port, "forged"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+
+        let rejected = client
+            .post(&admin_url)
+            .bearer_auth("s3cret")
+            .body("services { broken")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let wrong_method = client
+            .get(&admin_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(wrong_method.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let pushed = config(proxy_port, admin_port, "pushed");
+        let accepted = client
+            .post(&admin_url)
+            .bearer_auth("s3cret")
+            .body(pushed.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(accepted.status(), StatusCode::OK);
+
+        wait_for_body(proxy_port, "pushed").await;
+
+        let persisted = std::fs::read_to_string(last_good_path(&entry_path)).unwrap();
+        assert_eq!(persisted, pushed);
+    }
+}
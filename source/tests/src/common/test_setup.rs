@@ -10,6 +10,7 @@ use motya::{
     proxy::{
         filters::{chain_resolver::ChainResolver, generate_registry::load_registry},
         motya_proxy_service,
+        upstream_factory::UpstreamFactory,
     },
 };
 use pingora::{prelude::HttpPeer, server::Server};
@@ -21,7 +22,7 @@ use motya_config::{
         connectors::{Connectors, HttpPeerConfig, UpstreamConfig, UpstreamContextConfig, ALPN},
         definitions::{ConfiguredFilter, FilterChain, Modificator, NamedFilterChain},
         definitions_table::DefinitionsTable,
-        listeners::{ListenerConfig, ListenerKind, Listeners},
+        listeners::{H2Limits, ListenerConfig, ListenerKind, Listeners},
     },
     internal::{Config, ProxyConfig},
 };
@@ -42,6 +43,7 @@ pub async fn setup_check_cidr() -> thread::JoinHandle<()> {
         filters: vec![ConfiguredFilter {
             name: fqdn!("motya.filters.block-cidr-range"),
             args: HashMap::from([("addrs".to_string(), "127.0.0.0/8".to_string())]),
+            active: None,
         }],
     };
 
@@ -56,9 +58,21 @@ pub async fn setup_check_cidr() -> thread::JoinHandle<()> {
     let proxy_addr = "127.0.0.1:8081";
 
     let proxy = ProxyConfig {
+        hosts: Vec::new(),
         connectors: Connectors {
             upstreams: vec![UpstreamContextConfig {
                 lb_options: Default::default(),
+                shaping: None,
+                timeouts: None,
+                retry: None,
+                compression: None,
+                max_request_body_bytes: None,
+                buffering: None,
+                record_har: None,
+                cache: None,
+                methods: None,
+                flag: None,
+                active: None,
                 chains: vec![Modificator::Chain(NamedFilterChain {
                     name: "block-noob".to_string(),
                     chain: chain.clone(),
@@ -67,10 +81,15 @@ pub async fn setup_check_cidr() -> thread::JoinHandle<()> {
                     peer_address: *mock_server.address(),
                     alpn: ALPN::H1,
                     sni: String::new(),
+                    host_sni: None,
                     tls: false,
                     prefix_path: PathAndQuery::from_static("/"),
                     target_path: PathAndQuery::from_static("/"),
                     matcher: Default::default(),
+                    allow_websocket: true,
+                    grpc: false,
+                    pool_min_idle: 0,
+                    pool_warm_path: None,
                 }),
             }],
             anonymous_definitions: Default::default(),
@@ -80,22 +99,33 @@ pub async fn setup_check_cidr() -> thread::JoinHandle<()> {
                 source: ListenerKind::Tcp {
                     addr: proxy_addr.to_string(),
                     offer_h2: false,
+                    h2c: false,
                     tls: None,
                 },
+                unmatched_host: None,
+                redirect_to_https: false,
+                max_request_body_bytes: None,
+                h2_limits: H2Limits::default(),
+                tls_fingerprint: false,
             }],
         },
         name: "TestServer".to_string(),
+        warm_up: None,
+        error_pages: None,
+        labels: Default::default(),
+        maintenance: Default::default(),
     };
 
     let mut app_server =
         Server::new_with_opt_and_conf(pingora_opt(&config), pingora_server_conf(&config));
 
-    let (proxy_service, _) = motya_proxy_service(proxy, resolver, &app_server)
-        .await
-        .unwrap();
+    let (proxy_services, _) =
+        motya_proxy_service(proxy, UpstreamFactory::new(resolver), &app_server)
+            .await
+            .unwrap();
 
     app_server.bootstrap();
-    app_server.add_services(vec![proxy_service]);
+    app_server.add_services(proxy_services);
 
     let (tx, rx) = mpsc::channel();
 
@@ -126,6 +156,7 @@ pub async fn setup_check_cidr_accept() -> thread::JoinHandle<()> {
         filters: vec![ConfiguredFilter {
             name: fqdn!("motya.filters.block-cidr-range"),
             args: HashMap::from([("addrs".to_string(), "10.0.0.0/8".to_string())]),
+            active: None,
         }],
     };
 
@@ -140,9 +171,21 @@ pub async fn setup_check_cidr_accept() -> thread::JoinHandle<()> {
     let proxy_addr = "127.0.0.1:8082";
 
     let proxy = ProxyConfig {
+        hosts: Vec::new(),
         connectors: Connectors {
             upstreams: vec![UpstreamContextConfig {
                 lb_options: Default::default(),
+                shaping: None,
+                timeouts: None,
+                retry: None,
+                compression: None,
+                max_request_body_bytes: None,
+                buffering: None,
+                record_har: None,
+                cache: None,
+                methods: None,
+                flag: None,
+                active: None,
                 chains: vec![Modificator::Chain(NamedFilterChain {
                     name: "block-noob".to_string(),
                     chain: chain.clone(),
@@ -151,10 +194,15 @@ pub async fn setup_check_cidr_accept() -> thread::JoinHandle<()> {
                     peer_address: *mock_server.address(),
                     alpn: ALPN::H1,
                     sni: String::new(),
+                    host_sni: None,
                     tls: false,
                     prefix_path: PathAndQuery::from_static("/"),
                     target_path: PathAndQuery::from_static("/"),
                     matcher: Default::default(),
+                    allow_websocket: true,
+                    grpc: false,
+                    pool_min_idle: 0,
+                    pool_warm_path: None,
                 }),
             }],
             anonymous_definitions: Default::default(),
@@ -164,22 +212,33 @@ pub async fn setup_check_cidr_accept() -> thread::JoinHandle<()> {
                 source: ListenerKind::Tcp {
                     addr: proxy_addr.to_string(),
                     offer_h2: false,
+                    h2c: false,
                     tls: None,
                 },
+                unmatched_host: None,
+                redirect_to_https: false,
+                max_request_body_bytes: None,
+                h2_limits: H2Limits::default(),
+                tls_fingerprint: false,
             }],
         },
         name: "TestServer".to_string(),
+        warm_up: None,
+        error_pages: None,
+        labels: Default::default(),
+        maintenance: Default::default(),
     };
 
     let mut app_server =
         Server::new_with_opt_and_conf(pingora_opt(&config), pingora_server_conf(&config));
 
-    let (proxy_service, _) = motya_proxy_service(proxy, resolver, &app_server)
-        .await
-        .unwrap();
+    let (proxy_services, _) =
+        motya_proxy_service(proxy, UpstreamFactory::new(resolver), &app_server)
+            .await
+            .unwrap();
 
     app_server.bootstrap();
-    app_server.add_services(vec![proxy_service]);
+    app_server.add_services(proxy_services);
 
     let (tx, rx) = mpsc::channel();
 
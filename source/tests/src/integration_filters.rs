@@ -3,6 +3,7 @@ use motya::fs_adapter::TokioFs;
 use motya::proxy::filters::chain_resolver::ChainResolver;
 use motya::proxy::filters::generate_registry::load_registry;
 use motya::proxy::motya_proxy_service;
+use motya::proxy::upstream_factory::UpstreamFactory;
 use motya_config::common_types::definitions_table::DefinitionsTable;
 use motya_config::internal::Config;
 use motya_config::kdl::fs_loader::FileCollector;
@@ -91,11 +92,12 @@ async fn start_server_from_config_path(config_path: &std::path::Path) -> thread:
 
     let mut app_server =
         Server::new_with_opt_and_conf(pingora_opt(&conf), pingora_server_conf(&conf));
-    let (proxy_service, _) = motya_proxy_service(proxy, resolver, &app_server)
-        .await
-        .unwrap();
+    let (proxy_services, _) =
+        motya_proxy_service(proxy, UpstreamFactory::new(resolver), &app_server)
+            .await
+            .unwrap();
     app_server.bootstrap();
-    app_server.add_services(vec![proxy_service]);
+    app_server.add_services(proxy_services);
 
     let (tx, rx) = mpsc::channel();
     let handle = thread::spawn(move || {
@@ -156,3 +158,208 @@ async fn test_routes_apply_different_filters_internal_file() {
 
     handle.thread().unpark();
 }
+
+const PHASES_CONFIG: &str = r#"
+    system { }
+    definitions {
+        modifiers {
+            chain-filters "phases" {
+                filter name="motya.pre-routing.rewrite-path" pattern="^/legacy/(.*)" replace="/service-a/$1"
+                filter name="motya.post-select.backend-header" key="X-Backend"
+            }
+        }
+    }
+
+    services {
+        TestService {
+            connectors {
+                use-chain "phases"
+                section "/service-a" as="prefix" {
+                    proxy "__SERVICE_A__"
+                }
+            }
+            listeners {
+                "127.0.0.1:__PORT__"
+            }
+        }
+    }
+"#;
+
+#[tokio::test]
+async fn test_pre_routing_and_post_select_phases() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/service-a/users"))
+        .and(header(
+            "X-Backend",
+            mock_server.address().to_string().as_str(),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Rerouted"))
+        .mount(&mock_server)
+        .await;
+
+    let proxy_port = get_free_port();
+
+    let config_content = PHASES_CONFIG
+        .replace("__SERVICE_A__", &mock_server.uri())
+        .replace("__PORT__", &proxy_port.to_string());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp file");
+    write!(config_file, "{}", config_content).expect("Failed to write config");
+
+    let handle = start_server_from_config_path(config_file.path()).await;
+
+    // No route matches `/legacy` until the pre-routing rewrite ran.
+    let url = format!("http://127.0.0.1:{}/legacy/users", proxy_port);
+    wait_for_proxy_start(&url).await;
+
+    let resp = Client::new()
+        .get(&url)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await.unwrap(), "Rerouted");
+
+    handle.thread().unpark();
+}
+
+const BODY_CONFIG: &str = r#"
+    system { }
+    definitions {
+        modifiers {
+            chain-filters "body" {
+                filter name="motya.response-body.replace" find="internal.local" replace="example.com"
+            }
+        }
+    }
+
+    services {
+        TestService {
+            connectors {
+                section "/service-a" as="prefix" {
+                    use-chain "body"
+                    proxy "__SERVICE_A__"
+                }
+            }
+            listeners {
+                "127.0.0.1:__PORT__"
+            }
+        }
+    }
+"#;
+
+#[tokio::test]
+async fn test_response_body_replace() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/service-a/links"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string("see http://internal.local/a and http://internal.local/b"),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let proxy_port = get_free_port();
+
+    let config_content = BODY_CONFIG
+        .replace("__SERVICE_A__", &mock_server.uri())
+        .replace("__PORT__", &proxy_port.to_string());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp file");
+    write!(config_file, "{}", config_content).expect("Failed to write config");
+
+    let handle = start_server_from_config_path(config_file.path()).await;
+
+    let url = format!("http://127.0.0.1:{}/service-a/links", proxy_port);
+    wait_for_proxy_start(&url).await;
+
+    let resp = Client::new()
+        .get(&url)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.text().await.unwrap(),
+        "see http://example.com/a and http://example.com/b"
+    );
+
+    handle.thread().unpark();
+}
+
+const CACHE_CONFIG: &str = r#"
+    system { }
+    services {
+        TestService {
+            connectors {
+                section "/assets" as="prefix" {
+                    cache max-size-mb=1 ttl-secs=60
+                    proxy "__SERVICE_A__"
+                }
+                section "/health" {
+                    proxy "__SERVICE_A__"
+                }
+            }
+            listeners {
+                "127.0.0.1:__PORT__"
+            }
+        }
+    }
+"#;
+
+#[tokio::test]
+async fn test_cache_hit() {
+    let mock_server = MockServer::start().await;
+
+    // The later requests are answered from the cache.
+    Mock::given(method("GET"))
+        .and(path("/assets/app.js"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("ETag", "\"v1\"")
+                .set_body_string("console.log(1)"),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let proxy_port = get_free_port();
+
+    let config_content = CACHE_CONFIG
+        .replace("__SERVICE_A__", &mock_server.uri())
+        .replace("__PORT__", &proxy_port.to_string());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp file");
+    write!(config_file, "{}", config_content).expect("Failed to write config");
+
+    let handle = start_server_from_config_path(config_file.path()).await;
+
+    wait_for_proxy_start(&format!("http://127.0.0.1:{}/health", proxy_port)).await;
+
+    let url = format!("http://127.0.0.1:{}/assets/app.js", proxy_port);
+    let client = Client::new();
+
+    for _ in 0..2 {
+        let resp = client.get(&url).send().await.expect("Request failed");
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.text().await.unwrap(), "console.log(1)");
+    }
+
+    // The cached validators answer conditional requests.
+    let resp = client
+        .get(&url)
+        .header("If-None-Match", "\"v1\"")
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(resp.status(), 304);
+    assert_eq!(resp.headers()["ETag"], "\"v1\"");
+
+    mock_server.verify().await;
+
+    handle.thread().unpark();
+}
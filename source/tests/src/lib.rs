@@ -1,7 +1,10 @@
 #![cfg(test)]
+mod check_alerting;
 mod check_cidr;
 mod check_cli_serve_and_hello;
 mod check_diff_filewatcher;
+mod check_events;
+mod check_http_provider;
 mod common;
 mod integration_filters;
 mod load_balancer;
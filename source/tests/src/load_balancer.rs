@@ -31,6 +31,31 @@ services {
 }
 "#;
 
+const STICKY_CONFIG_TEMPLATE: &str = r#"
+system {
+    state-dir "__STATE_DIR__"
+}
+services {
+    StickyTest {
+        listeners {
+            "127.0.0.1:__PROXY_PORT__"
+        }
+        connectors {
+            section "/" {
+                load-balance {
+                    selection "CookieSticky" cookie="river_affinity" ttl-secs=3600
+                }
+                proxy {
+                    server "__BACKEND_1__"
+                    server "__BACKEND_2__"
+                    server "__BACKEND_3__"
+                }
+            }
+        }
+    }
+}
+"#;
+
 fn get_free_port() -> u16 {
     let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind to random port");
     let port = listener.local_addr().unwrap().port();
@@ -51,6 +76,43 @@ async fn wait_for_proxy(url: &str) {
     panic!("Proxy did not start at {} within timeout", url);
 }
 
+/// Runs the proxy in the background, the returned file has to outlive it.
+async fn start_proxy(config_content: &str) -> NamedTempFile {
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp config file");
+    write!(config_file, "{}", config_content).expect("Failed to write config content");
+    let config_path = config_file.path().to_path_buf();
+
+    let cli = Cli {
+        validate_configs: false,
+        threads_per_service: None,
+        config_entry: Some(config_path),
+        daemonize: false,
+        upgrade: false,
+        pidfile: None,
+        upgrade_socket: None,
+        allow_partial_start: false,
+        command: None,
+    };
+
+    let mut app_ctx = AppContext::bootstrap(cli)
+        .await
+        .expect("Failed to bootstrap AppContext");
+    let services = app_ctx
+        .build_services()
+        .await
+        .expect("Failed to build services");
+
+    let (mut server, _watcher) = app_ctx.ready();
+    server.add_services(services);
+    server.bootstrap();
+
+    thread::spawn(move || {
+        server.run_forever();
+    });
+
+    config_file
+}
+
 #[tokio::test]
 async fn test_load_balancer_round_robin_distribution() {
     let backend1 = MockServer::start().await;
@@ -87,36 +149,7 @@ async fn test_load_balancer_round_robin_distribution() {
         .replace("__BACKEND_2__", &b2_str)
         .replace("__BACKEND_3__", &b3_str);
 
-    let mut config_file = NamedTempFile::new().expect("Failed to create temp config file");
-    write!(config_file, "{}", config_content).expect("Failed to write config content");
-    let config_path = config_file.path().to_path_buf();
-
-    let cli = Cli {
-        validate_configs: false,
-        threads_per_service: None,
-        config_entry: Some(config_path),
-        daemonize: false,
-        upgrade: false,
-        pidfile: None,
-        upgrade_socket: None,
-        command: None,
-    };
-
-    let mut app_ctx = AppContext::bootstrap(cli)
-        .await
-        .expect("Failed to bootstrap AppContext");
-    let services = app_ctx
-        .build_services()
-        .await
-        .expect("Failed to build services");
-
-    let (mut server, _watcher) = app_ctx.ready();
-    server.add_services(services);
-    server.bootstrap();
-
-    thread::spawn(move || {
-        server.run_forever();
-    });
+    let _config_file = start_proxy(&config_content).await;
 
     let proxy_url = format!("http://127.0.0.1:{}", proxy_port);
     wait_for_proxy(&proxy_url).await;
@@ -150,3 +183,88 @@ async fn test_load_balancer_round_robin_distribution() {
     assert_eq!(count2, 10, "Backend 2 received wrong amount of requests");
     assert_eq!(count3, 10, "Backend 3 received wrong amount of requests");
 }
+
+#[tokio::test]
+async fn test_cookie_sticky_pins_client() {
+    let backends = [
+        MockServer::start().await,
+        MockServer::start().await,
+        MockServer::start().await,
+    ];
+
+    for backend in &backends {
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .mount(backend)
+            .await;
+    }
+
+    let proxy_port = get_free_port();
+    let state_dir = tempfile::tempdir().expect("Failed to create state dir");
+
+    let mut config_content = STICKY_CONFIG_TEMPLATE
+        .replace("__STATE_DIR__", state_dir.path().to_str().unwrap())
+        .replace("__PROXY_PORT__", &proxy_port.to_string());
+    for (i, backend) in backends.iter().enumerate() {
+        config_content = config_content.replace(
+            &format!("__BACKEND_{}__", i + 1),
+            &backend.address().to_string(),
+        );
+    }
+
+    let _config_file = start_proxy(&config_content).await;
+
+    let proxy_url = format!("http://127.0.0.1:{}", proxy_port);
+    wait_for_proxy(&proxy_url).await;
+
+    let client = Client::new();
+
+    let first = client
+        .get(&proxy_url)
+        .header("X-Sticky-Req", "true")
+        .send()
+        .await
+        .expect("Failed to send request");
+    let set_cookie = first
+        .headers()
+        .get("set-cookie")
+        .expect("First response should set the affinity cookie")
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(set_cookie.starts_with("river_affinity="));
+    assert!(set_cookie.contains("Max-Age=3600"));
+
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+    for _ in 0..9 {
+        let response = client
+            .get(&proxy_url)
+            .header("X-Sticky-Req", "true")
+            .header("Cookie", &cookie)
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        // Already pinned, the cookie is not issued again.
+        assert!(response.headers().get("set-cookie").is_none());
+    }
+
+    let mut counts = Vec::new();
+    for backend in &backends {
+        let requests = backend.received_requests().await.unwrap();
+        counts.push(
+            requests
+                .iter()
+                .filter(|r| r.headers.contains_key("x-sticky-req"))
+                .count(),
+        );
+    }
+    counts.sort();
+
+    assert_eq!(
+        counts,
+        [0, 0, 10],
+        "Every request should reach the pinned backend"
+    );
+}
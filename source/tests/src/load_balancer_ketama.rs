@@ -136,6 +136,7 @@ async fn test_ketama_hashing_with_transforms() {
         upgrade: false,
         pidfile: None,
         upgrade_socket: None,
+        allow_partial_start: false,
         command: None,
     };
 